@@ -0,0 +1,36 @@
+//! Build script, exporting build metadata to the agent.
+//!
+//! Packagers building outside of a git checkout can provide the source
+//! revision via the `ZINCATI_GIT_SHA` environment variable.
+
+use std::process::Command;
+
+fn main() {
+    println!("cargo:rerun-if-env-changed=ZINCATI_GIT_SHA");
+    println!("cargo:rerun-if-changed=.git/HEAD");
+
+    let git_sha = std::env::var("ZINCATI_GIT_SHA")
+        .ok()
+        .or_else(|| command_output("git", &["rev-parse", "--short=12", "HEAD"]))
+        .unwrap_or_else(|| "unknown".to_string());
+    println!("cargo:rustc-env=ZINCATI_GIT_SHA={}", git_sha);
+
+    let rustc = std::env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    let rustc_version =
+        command_output(&rustc, &["--version"]).unwrap_or_else(|| "unknown".to_string());
+    println!("cargo:rustc-env=ZINCATI_RUSTC_VERSION={}", rustc_version);
+}
+
+/// Run a command, returning its trimmed output on success.
+fn command_output(cmd: &str, args: &[&str]) -> Option<String> {
+    let out = Command::new(cmd).args(args).output().ok()?;
+    if !out.status.success() {
+        return None;
+    }
+    let stdout = String::from_utf8(out.stdout).ok()?;
+    let trimmed = stdout.trim();
+    if trimmed.is_empty() {
+        return None;
+    }
+    Some(trimmed.to_string())
+}
@@ -0,0 +1,178 @@
+//! Blackout periods for updates finalization.
+//!
+//! Blackout periods are date ranges (e.g. holiday freezes) configured by
+//! administrators, during which staged updates are never finalized,
+//! regardless of update strategy.
+
+use crate::config::inputs;
+use anyhow::{Context, Result};
+use chrono::{DateTime, NaiveDate, TimeZone, Utc};
+use fn_error_context::context;
+use serde::Serialize;
+
+/// Configured periods during which finalization is denied.
+#[derive(Clone, Debug, Serialize)]
+pub struct BlackoutPeriods {
+    /// Blackout periods, in configuration order.
+    periods: Vec<BlackoutPeriod>,
+}
+
+/// A single blackout period.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct BlackoutPeriod {
+    /// Start of the period.
+    start: DateTime<Utc>,
+    /// End of the period (excluded).
+    end: DateTime<Utc>,
+    /// Free-form reason, for humans.
+    reason: String,
+}
+
+impl BlackoutPeriods {
+    /// Process blackout periods configuration.
+    ///
+    /// This returns `None` if no periods are configured.
+    pub fn with_config(cfg: inputs::BlackoutInput) -> Result<Option<Self>> {
+        Self::with_config_since(cfg, Utc::now())
+    }
+
+    /// Process blackout periods configuration, ignoring periods ended before `now`.
+    ///
+    /// This returns `None` if no periods are configured.
+    #[context("failed to validate blackout periods configuration")]
+    pub fn with_config_since(
+        cfg: inputs::BlackoutInput,
+        now: DateTime<Utc>,
+    ) -> Result<Option<Self>> {
+        if cfg.periods.is_empty() {
+            return Ok(None);
+        }
+
+        let mut periods = Vec::with_capacity(cfg.periods.len());
+        for entry in cfg.periods {
+            let period = BlackoutPeriod::with_config(entry)?;
+            if period.end <= now {
+                log::debug!("ignoring past {}", period.describe());
+                continue;
+            }
+            periods.push(period);
+        }
+
+        Ok(Some(Self { periods }))
+    }
+
+    /// Return the blackout period containing `datetime`, if any.
+    pub fn active(&self, datetime: &DateTime<Utc>) -> Option<&BlackoutPeriod> {
+        self.periods
+            .iter()
+            .find(|p| p.start <= *datetime && *datetime < p.end)
+    }
+}
+
+impl BlackoutPeriod {
+    /// Build a blackout period from its configuration entry.
+    fn with_config(cfg: inputs::BlackoutPeriodInput) -> Result<Self> {
+        let start = parse_bound(&cfg.start, false)?;
+        let end = parse_bound(&cfg.end, true)?;
+        if end <= start {
+            anyhow::bail!(
+                "blackout period end '{}' must be later than its start '{}'",
+                cfg.end,
+                cfg.start
+            );
+        }
+
+        let period = Self {
+            start,
+            end,
+            reason: cfg.reason.trim().to_string(),
+        };
+        Ok(period)
+    }
+
+    /// Return the end of this period.
+    pub fn end(&self) -> DateTime<Utc> {
+        self.end
+    }
+
+    /// Return a human-readable description of this period.
+    pub fn describe(&self) -> String {
+        let mut desc = format!(
+            "blackout period until {}",
+            self.end.format("%a %Y-%m-%d %H:%M:%S %Z")
+        );
+        if !self.reason.is_empty() {
+            desc.push_str(&format!(" ({})", self.reason));
+        }
+        desc
+    }
+}
+
+/// Parse a period bound, either a RFC 3339 timestamp or a `YYYY-MM-DD` date (UTC).
+///
+/// Dates are inclusive, thus a date as period end covers the whole day.
+fn parse_bound(input: &str, is_end: bool) -> Result<DateTime<Utc>> {
+    let input = input.trim();
+    if let Ok(date) = NaiveDate::parse_from_str(input, "%Y-%m-%d") {
+        let day = if is_end { date.succ_opt() } else { Some(date) }
+            .with_context(|| format!("invalid blackout period bound '{}'", input))?;
+        let midnight = day.and_hms_opt(0, 0, 0).expect("valid midnight");
+        return Ok(Utc.from_utc_datetime(&midnight));
+    }
+    let datetime = DateTime::parse_from_rfc3339(input)
+        .with_context(|| format!("invalid blackout period bound '{}'", input))?;
+    Ok(datetime.with_timezone(&Utc))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn period(start: &str, end: &str) -> inputs::BlackoutPeriodInput {
+        inputs::BlackoutPeriodInput {
+            start: start.to_string(),
+            end: end.to_string(),
+            reason: "holiday freeze".to_string(),
+        }
+    }
+
+    #[test]
+    fn blackout_periods() {
+        let unset = BlackoutPeriods::with_config(inputs::BlackoutInput::default()).unwrap();
+        assert!(unset.is_none());
+
+        let invalid = vec![
+            period("2099-12-24", "2099-12-23"),
+            period("2099-12-24T10:00:00Z", "2099-12-24T10:00:00Z"),
+            period("tomorrow", "2099-12-24"),
+        ];
+        for entry in invalid {
+            let cfg = inputs::BlackoutInput {
+                periods: vec![entry],
+            };
+            BlackoutPeriods::with_config(cfg).unwrap_err();
+        }
+
+        let cfg = inputs::BlackoutInput {
+            periods: vec![
+                period("2000-01-01", "2000-01-02"),
+                period("2099-12-24", "2099-12-26"),
+                period("2099-06-01T08:00:00+02:00", "2099-06-01T18:00:00+02:00"),
+            ],
+        };
+        let blackout = BlackoutPeriods::with_config(cfg).unwrap().unwrap();
+        assert_eq!(blackout.periods.len(), 2);
+
+        let holidays = Utc.with_ymd_and_hms(2099, 12, 26, 23, 59, 0).unwrap();
+        let active = blackout.active(&holidays).unwrap();
+        assert!(active.describe().ends_with("(holiday freeze)"));
+        let after = Utc.with_ymd_and_hms(2099, 12, 27, 0, 0, 0).unwrap();
+        assert!(blackout.active(&after).is_none());
+
+        let working_hours = Utc.with_ymd_and_hms(2099, 6, 1, 6, 0, 0).unwrap();
+        assert!(blackout.active(&working_hours).is_some());
+        let evening = Utc.with_ymd_and_hms(2099, 6, 1, 16, 0, 0).unwrap();
+        assert!(blackout.active(&evening).is_none());
+    }
+}
@@ -6,6 +6,7 @@
 
 // TODO(lucab): eventually move to its own "cincinnati client library" crate
 
+use crate::network::NetworkSettings;
 use anyhow::{Context, Result};
 use futures::prelude::*;
 use reqwest::Method;
@@ -20,18 +21,26 @@ const DEFAULT_HTTP_COMPLETION_TIMEOUT: Duration = Duration::from_secs(30 * 60);
 /// Cincinnati graph API path endpoint (v1).
 static V1_GRAPH_PATH: &str = "v1/graph";
 
+/// HTTP header carrying the request correlation ID.
+pub static REQUEST_ID_HEADER: &str = "X-Request-ID";
+
 /// Cincinnati JSON protocol: node object.
 #[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
 pub struct Node {
+    /// Release version.
     pub version: String,
+    /// Release payload (OSTree commit checksum).
     pub payload: String,
+    /// Release metadata.
     pub metadata: HashMap<String, String>,
 }
 
 /// Cincinnati JSON protocol: graph object.
 #[derive(Debug, Deserialize, PartialEq, Eq)]
 pub struct Graph {
+    /// Release nodes.
     pub nodes: Vec<Node>,
+    /// Update edges, as `(from, to)` indices into `nodes`.
     pub edges: Vec<(u64, u64)>,
 }
 
@@ -39,9 +48,9 @@ pub struct Graph {
 #[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
 pub struct GraphJsonError {
     /// Machine-friendly brief error kind.
-    pub(crate) kind: String,
+    pub kind: String,
     /// Human-friendly detailed error explanation.
-    pub(crate) value: String,
+    pub value: String,
 }
 
 /// Error related to the Cincinnati service.
@@ -123,9 +132,16 @@ pub struct Client {
 
 impl Client {
     /// Fetch an update-graph from Cincinnati.
-    pub fn fetch_graph(&self) -> impl Future<Output = Result<Graph, CincinnatiError>> {
+    ///
+    /// The request carries the given correlation ID, so that it can be
+    /// matched with server-side logs.
+    pub fn fetch_graph(
+        &self,
+        request_id: &str,
+    ) -> impl Future<Output = Result<Graph, CincinnatiError>> {
         let req = self
             .new_request(Method::GET, V1_GRAPH_PATH)
+            .map(|req| req.header(REQUEST_ID_HEADER, request_id))
             .map_err(|e| CincinnatiError::FailedRequest(e.to_string()));
 
         futures::future::ready(req)
@@ -171,6 +187,23 @@ impl Client {
     }
 }
 
+/// Generate a new (random, version 4) UUID to correlate a request with
+/// server-side logs.
+pub fn new_request_id() -> String {
+    let mut bytes: [u8; 16] = rand::random();
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[0..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..32]
+    )
+}
+
 /// Client builder.
 #[derive(Clone, Debug)]
 pub struct ClientBuilder {
@@ -178,6 +211,8 @@ pub struct ClientBuilder {
     api_base: String,
     /// Asynchronous reqwest client (custom).
     hclient: Option<reqwest::Client>,
+    /// Outbound network settings, for the default reqwest client.
+    network: NetworkSettings,
     /// Client parameters (custom).
     query_params: Option<HashMap<String, String>>,
 }
@@ -191,10 +226,18 @@ impl ClientBuilder {
         Self {
             api_base: api_base.into(),
             hclient: None,
+            network: NetworkSettings::default(),
             query_params: None,
         }
     }
 
+    /// Set the outbound network settings to use.
+    pub fn network(self, network: NetworkSettings) -> Self {
+        let mut builder = self;
+        builder.network = network;
+        builder
+    }
+
     /// Set (or reset) the query parameters to use.
     pub fn query_params(self, params: Option<HashMap<String, String>>) -> Self {
         let mut builder = self;
@@ -206,14 +249,13 @@ impl ClientBuilder {
     pub fn build(self) -> Result<Client> {
         let hclient = match self.hclient {
             Some(client) => client,
-            None => reqwest::ClientBuilder::new()
+            None => self
+                .network
+                .configure(reqwest::ClientBuilder::new())?
                 .timeout(DEFAULT_HTTP_COMPLETION_TIMEOUT)
                 .build()?,
         };
-        let query_params = match self.query_params {
-            Some(params) => params,
-            None => HashMap::new(),
-        };
+        let query_params = self.query_params.unwrap_or_default();
 
         let api_base = reqwest::Url::parse(&self.api_base)
             .context(format!("failed to parse '{}'", &self.api_base))?;
@@ -289,4 +331,16 @@ mod tests {
             "client-side error: failed to decode graph: error decoding response body: missing field `nodes` at line 1 column 2";
         assert_eq!(&msg, expected_msg);
     }
+
+    #[test]
+    fn test_new_request_id() {
+        let id = new_request_id();
+        assert_eq!(id.len(), 36);
+        let groups: Vec<&str> = id.split('-').collect();
+        let lengths: Vec<usize> = groups.iter().map(|g| g.len()).collect();
+        assert_eq!(lengths, vec![8, 4, 4, 4, 12]);
+        assert!(groups[2].starts_with('4'));
+        assert!(matches!(&groups[3][..1], "8" | "9" | "a" | "b"));
+        assert_ne!(id, new_request_id());
+    }
 }
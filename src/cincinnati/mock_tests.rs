@@ -1,5 +1,6 @@
 use crate::cincinnati::*;
 use crate::identity::Identity;
+use crate::network::NetworkSettings;
 use mockito::{self, Matcher};
 use std::collections::BTreeSet;
 use tokio::runtime as rt;
@@ -9,7 +10,8 @@ fn test_empty_graph() {
     let empty_graph = r#"{ "nodes": [], "edges": [] }"#;
     let m_graph = mockito::mock("GET", Matcher::Regex(r"^/v1/graph?.+$".to_string()))
         .match_header("accept", Matcher::Regex("application/json".to_string()))
-        .with_body(&empty_graph)
+        .match_header("x-request-id", "f81d4fae-7dec-41d0-a765-00a0c91e6bf6")
+        .with_body(empty_graph)
         .with_status(200)
         .create();
 
@@ -17,8 +19,16 @@ fn test_empty_graph() {
     let id = Identity::mock_default();
     let client = Cincinnati {
         base_url: mockito::server_url(),
+        network: NetworkSettings::default(),
+        skip_versions: BTreeSet::new(),
+        policy: TargetPolicy::default(),
     };
-    let update = runtime.block_on(client.next_update(&id, BTreeSet::new(), false));
+    let update = runtime.block_on(client.next_update(
+        &id,
+        BTreeSet::new(),
+        false,
+        "f81d4fae-7dec-41d0-a765-00a0c91e6bf6",
+    ));
     m_graph.assert();
 
     assert!(update.unwrap().is_none());
@@ -2,23 +2,32 @@
 
 // Cincinnati client.
 mod client;
-pub use client::{CincinnatiError, Node};
+pub use client::{CincinnatiError, Graph, Node};
+
+// Target selection policies.
+mod policy;
+pub use policy::{RankingPolicy, TargetPolicy};
 
 #[cfg(test)]
 mod mock_tests;
 
 use crate::config::inputs;
 use crate::identity::Identity;
+use crate::network::NetworkSettings;
 use crate::rpm_ostree::Release;
+use crate::update_source::UpdateSource;
 use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
 use fn_error_context::context;
 use futures::prelude::*;
 use futures::TryFutureExt;
-use prometheus::{IntCounter, IntCounterVec, IntGauge};
+use prometheus::{HistogramVec, IntCounter, IntCounterVec, IntGauge};
 use serde::Serialize;
-use std::collections::BTreeSet;
+use std::collections::{BTreeSet, HashMap};
 use std::pin::Pin;
 use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Mutex;
+use std::time::Instant;
 
 /// Metadata key for payload scheme.
 pub static AGE_INDEX_KEY: &str = "org.fedoraproject.coreos.releases.age_index";
@@ -32,6 +41,27 @@ pub static DEADEND_KEY: &str = "org.fedoraproject.coreos.updates.deadend";
 /// Metadata key for dead-end reason.
 pub static DEADEND_REASON_KEY: &str = "org.fedoraproject.coreos.updates.deadend_reason";
 
+/// Metadata key for release timestamp (RFC 3339).
+pub static RELEASE_TIMESTAMP_KEY: &str = "org.fedoraproject.coreos.releases.timestamp";
+
+/// Metadata key for security severity of a release (e.g. `important`).
+pub static SEVERITY_KEY: &str = "org.fedoraproject.coreos.releases.severity";
+
+/// Metadata key for release-notes URL.
+pub static RELEASE_NOTES_KEY: &str = "org.fedoraproject.coreos.releases.notes_url";
+
+/// Metadata key for errata IDs (comma-separated).
+pub static ERRATA_KEY: &str = "org.fedoraproject.coreos.releases.errata";
+
+/// Metadata key for the rollout wave assigned to this node.
+pub static WAVE_KEY: &str = "org.fedoraproject.coreos.updates.wave";
+
+/// Metadata key for the earliest start time (RFC 3339) of the assigned rollout wave.
+pub static WAVE_START_KEY: &str = "org.fedoraproject.coreos.updates.wave_start";
+
+/// Client parameter advertising support for rollout waves.
+pub static WAVES_PARAM: &str = "rollout_waves";
+
 /// Metadata value for "checksum" payload scheme.
 pub static CHECKSUM_SCHEME: &str = "checksum";
 
@@ -52,6 +82,14 @@ lazy_static::lazy_static! {
         "zincati_cincinnati_ignored_update_targets",
         "Number of ignored targets among update targets found."
     ).unwrap();
+    static ref UPDATE_TARGETS_SKIPPED: IntGauge = register_int_gauge!(
+        "zincati_cincinnati_skipped_update_targets",
+        "Number of update targets found but deny-listed by configuration."
+    ).unwrap();
+    static ref UPDATE_TARGETS_TOO_RECENT: IntGauge = register_int_gauge!(
+        "zincati_cincinnati_too_recent_update_targets",
+        "Number of update targets found but younger than the minimum release age."
+    ).unwrap();
     static ref UPDATE_CHECKS: IntCounter = register_int_counter!(opts!(
         "zincati_cincinnati_update_checks_total",
         "Total number of checks for updates to the upstream Cincinnati server."
@@ -61,15 +99,29 @@ lazy_static::lazy_static! {
         "Total number of errors while checking for updates.",
         &["kind"]
     ).unwrap();
+    static ref UPDATE_CHECKS_DURATION: HistogramVec = register_histogram_vec!(
+        "zincati_cincinnati_update_check_duration_seconds",
+        "Duration of checks for updates, including fetching the update graph, by result.",
+        &["result"],
+        vec![0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0]
+    ).unwrap();
     static ref DEADEND_STATE : DeadEndState = DeadEndState::default();
+    static ref LAST_REQUEST_ID: Mutex<Option<String>> = Mutex::new(None);
+    static ref FIRST_SEEN: Mutex<HashMap<String, DateTime<Utc>>> = Mutex::new(HashMap::new());
 }
 
 /// For tracking a dead-end release.
-pub struct DeadEndState(AtomicU8);
+pub(crate) struct DeadEndState {
+    state: AtomicU8,
+    reason: Mutex<Option<String>>,
+}
 
 impl Default for DeadEndState {
     fn default() -> Self {
-        Self(AtomicU8::new(DeadEndState::UNKNOWN))
+        Self {
+            state: AtomicU8::new(DeadEndState::UNKNOWN),
+            reason: Mutex::new(None),
+        }
     }
 }
 
@@ -80,20 +132,49 @@ impl DeadEndState {
 
     /// Return whether this is in a known dead-end state.
     pub fn is_deadend(&self) -> bool {
-        self.0.load(Ordering::SeqCst) == Self::TRUE
+        self.state.load(Ordering::SeqCst) == Self::TRUE
     }
 
     /// Return whether this is in a known NOT dead-end state.
     pub fn is_no_deadend(&self) -> bool {
-        self.0.load(Ordering::SeqCst) == Self::FALSE
+        self.state.load(Ordering::SeqCst) == Self::FALSE
     }
 
     pub fn set_deadend(&self) {
-        self.0.store(Self::TRUE, Ordering::SeqCst);
+        self.state.store(Self::TRUE, Ordering::SeqCst);
     }
 
     pub fn set_no_deadend(&self) {
-        self.0.store(Self::FALSE, Ordering::SeqCst);
+        self.state.store(Self::FALSE, Ordering::SeqCst);
+    }
+
+    /// Return the dead-end reason, if booted release is known to be a dead-end.
+    pub fn reason(&self) -> Option<String> {
+        self.reason.lock().ok().and_then(|r| r.clone())
+    }
+
+    /// Record (or clear) the dead-end reason.
+    fn set_reason(&self, reason: Option<String>) {
+        if let Ok(mut r) = self.reason.lock() {
+            *r = reason;
+        }
+    }
+}
+
+/// Return the reason why the booted release is a dead-end, if it is known to be one.
+pub fn deadend_reason() -> Option<String> {
+    DEADEND_STATE.reason()
+}
+
+/// Return the correlation ID of the last request to the Cincinnati server, if any.
+pub fn last_request_id() -> Option<String> {
+    LAST_REQUEST_ID.lock().ok().and_then(|id| id.clone())
+}
+
+/// Record the correlation ID of the latest request to the Cincinnati server.
+fn record_request_id(request_id: &str) {
+    if let Ok(mut id) = LAST_REQUEST_ID.lock() {
+        *id = Some(request_id.to_string());
     }
 }
 
@@ -102,12 +183,26 @@ impl DeadEndState {
 pub struct Cincinnati {
     /// Service base URL.
     pub base_url: String,
+    /// Outbound network settings.
+    pub network: NetworkSettings,
+    /// Release versions never to be selected as update targets.
+    pub skip_versions: BTreeSet<String>,
+    /// Policy for selecting among multiple update targets.
+    pub policy: TargetPolicy,
 }
 
 impl Cincinnati {
+    /// Source label/name.
+    pub const LABEL: &'static str = "cincinnati";
+
     /// Process Cincinnati configuration.
     #[context("failed to validate cincinnati configuration")]
-    pub(crate) fn with_config(cfg: inputs::CincinnatiInput, id: &Identity) -> Result<Self> {
+    pub fn with_config(
+        cfg: inputs::CincinnatiInput,
+        id: &Identity,
+        network: &NetworkSettings,
+        skip_versions: &[String],
+    ) -> Result<Self> {
         if cfg.base_url.is_empty() {
             anyhow::bail!("empty Cincinnati base URL");
         }
@@ -121,60 +216,238 @@ impl Cincinnati {
             cfg.base_url
         };
         log::info!("Cincinnati service: {}", &base_url);
+        let policy = TargetPolicy::parse(&cfg.target_policy)?;
 
-        let c = Self { base_url };
+        let c = Self {
+            base_url,
+            network: network.clone(),
+            skip_versions: skip_versions.iter().cloned().collect(),
+            policy,
+        };
         Ok(c)
     }
 
-    /// Fetch next update-hint from Cincinnati.
-    pub(crate) fn fetch_update_hint(
-        &self,
-        id: &Identity,
-        deployments: BTreeSet<Release>,
-        allow_downgrade: bool,
-    ) -> Pin<Box<dyn Future<Output = Option<Release>>>> {
-        UPDATE_CHECKS.inc();
-        log::trace!("checking upstream Cincinnati server for updates");
-
-        let update = self
-            .next_update(id, deployments, allow_downgrade)
-            .unwrap_or_else(|e| {
-                UPDATE_CHECKS_ERRORS
-                    .with_label_values(&[&e.error_kind()])
-                    .inc();
-                log::error!("failed to check Cincinnati for updates: {}", e);
-                None
-            });
-        Box::pin(update)
-    }
-
     /// Get the next update.
     fn next_update(
         &self,
         id: &Identity,
         deployments: BTreeSet<Release>,
         allow_downgrade: bool,
+        request_id: &str,
     ) -> Pin<Box<dyn Future<Output = Result<Option<Release>, CincinnatiError>>>> {
         let booted = id.current_os.clone();
-        let params = id.cincinnati_params();
+        let min_age = id.min_release_age();
+        let skip_versions = self.skip_versions.clone();
+        let policy = self.policy;
+        let mut params = id.cincinnati_params();
+        params.insert(WAVES_PARAM.to_string(), "1".to_string());
         let client = client::ClientBuilder::new(self.base_url.to_string())
+            .network(self.network.clone())
             .query_params(Some(params))
             .build()
             .map_err(|e| CincinnatiError::FailedClientBuilder(e.to_string()));
+        let request_id = request_id.to_string();
 
         let next = futures::future::ready(client)
-            .and_then(|c| c.fetch_graph())
+            .and_then(move |c| c.fetch_graph(&request_id))
             .and_then(move |graph| async move {
-                find_update(graph, booted, deployments, allow_downgrade)
+                find_update(
+                    graph,
+                    booted,
+                    deployments,
+                    allow_downgrade,
+                    min_age,
+                    &skip_versions,
+                    policy,
+                )
             });
         Box::pin(next)
     }
+
+    /// Fetch the update graph, as seen by this node.
+    ///
+    /// If `eager` is set, the graph is requested as seen by the most eager
+    /// node (i.e. with zero rollout wariness) instead.
+    /// Unlike update checks, this does not record any metrics.
+    pub fn fetch_graph(
+        &self,
+        id: &Identity,
+        eager: bool,
+    ) -> Pin<Box<dyn Future<Output = Result<Graph, CincinnatiError>>>> {
+        let mut params = id.cincinnati_params();
+        params.insert(WAVES_PARAM.to_string(), "1".to_string());
+        if eager {
+            params.insert("rollout_wariness".to_string(), format!("{:.06}", 0.0));
+        }
+        let client = client::ClientBuilder::new(self.base_url.to_string())
+            .network(self.network.clone())
+            .query_params(Some(params))
+            .build()
+            .map_err(|e| CincinnatiError::FailedClientBuilder(e.to_string()));
+        let request_id = client::new_request_id();
+
+        let graph = futures::future::ready(client).and_then(move |c| c.fetch_graph(&request_id));
+        Box::pin(graph)
+    }
+}
+
+/// Read-only evaluation of an update graph, from the booted release.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct GraphInspection {
+    /// Whether the booted release is part of the graph.
+    pub booted_in_graph: bool,
+    /// Reason for the booted release being a dead-end, if so.
+    pub deadend_reason: Option<String>,
+    /// Next update target, if any.
+    pub target: Option<Release>,
+    /// Downgrade target rejected by configuration, if any.
+    pub rejected_downgrade: Option<Release>,
+    /// Newest target ignored for being younger than the minimum release age, if any.
+    pub too_recent: Option<Release>,
+    /// Newest target ignored for being deny-listed by configuration, if any.
+    pub skipped: Option<Release>,
+}
+
+/// Inspect the graph for updates reachable from the booted release.
+///
+/// This follows the same logic as `find_update`, without side-effects
+/// (metrics, dead-end state and MOTD). Release age is only known from
+/// node metadata here, as first-seen times are tracked by the agent.
+pub fn inspect_graph(
+    graph: &Graph,
+    booted_depl: &Release,
+    local_depls: BTreeSet<Release>,
+    allow_downgrade: bool,
+    min_age: Option<chrono::Duration>,
+    skip_versions: &BTreeSet<String>,
+    policy: TargetPolicy,
+) -> Result<GraphInspection, CincinnatiError> {
+    let now = Utc::now();
+    let mut inspection = GraphInspection::default();
+    let (cur_position, cur_node) = match graph
+        .nodes
+        .iter()
+        .enumerate()
+        .find(|(_, node)| is_same_checksum(node, &booted_depl.checksum))
+    {
+        Some(current) => current,
+        None => return Ok(inspection),
+    };
+    inspection.booted_in_graph = true;
+    inspection.deadend_reason = evaluate_deadend(cur_node);
+    let cur_release = Release::from_cincinnati(cur_node.clone())
+        .map_err(|e| CincinnatiError::FailedNodeParsing(e.to_string()))?;
+
+    let local_releases = find_local_releases(graph, local_depls);
+    let mut updates = BTreeSet::new();
+    for (_, dst) in graph
+        .edges
+        .iter()
+        .filter(|(src, _)| *src == cur_position as u64)
+    {
+        let node = match graph.nodes.get(*dst as usize) {
+            Some(n) => n.clone(),
+            None => {
+                let msg = format!("target node '{}' not present in graph", dst);
+                return Err(CincinnatiError::FailedNodeLookup(msg));
+            }
+        };
+        let too_recent = is_too_recent(&node, min_age, &now, false);
+        let release = Release::from_cincinnati(node)
+            .map_err(|e| CincinnatiError::FailedNodeParsing(e.to_string()))?;
+        if skip_versions.contains(&release.version) {
+            if inspection.skipped.as_ref() < Some(&release) {
+                inspection.skipped = Some(release);
+            }
+            continue;
+        }
+        if too_recent {
+            if inspection.too_recent.as_ref() < Some(&release) {
+                inspection.too_recent = Some(release);
+            }
+            continue;
+        }
+        updates.insert(release);
+    }
+
+    let candidates: Vec<Release> = updates.difference(&local_releases).cloned().collect();
+    let next = match policy.ranking().select(&cur_release, &candidates, &now) {
+        Some(rel) => rel.clone(),
+        None => return Ok(inspection),
+    };
+    if next <= cur_release && !allow_downgrade {
+        inspection.rejected_downgrade = Some(next);
+    } else {
+        inspection.target = Some(next);
+    }
+    Ok(inspection)
+}
+
+impl UpdateSource for Cincinnati {
+    fn label(&self) -> &'static str {
+        Self::LABEL
+    }
+
+    /// Fetch next update-hint from Cincinnati.
+    fn fetch_update_hint(
+        &self,
+        id: &Identity,
+        deployments: BTreeSet<Release>,
+        allow_downgrade: bool,
+    ) -> Pin<Box<dyn Future<Output = Option<Release>>>> {
+        UPDATE_CHECKS.inc();
+        let request_id = client::new_request_id();
+        record_request_id(&request_id);
+        log::trace!(
+            "checking upstream Cincinnati server for updates, request ID {}",
+            request_id
+        );
+
+        let start = Instant::now();
+        let update = self
+            .next_update(id, deployments, allow_downgrade, &request_id)
+            .map(move |res| {
+                let result = if res.is_ok() { "success" } else { "failure" };
+                UPDATE_CHECKS_DURATION
+                    .with_label_values(&[result])
+                    .observe(start.elapsed().as_secs_f64());
+                res
+            })
+            .map(move |res| match res {
+                Ok(release) => {
+                    let target = release
+                        .as_ref()
+                        .map(|r| r.version.as_str())
+                        .unwrap_or("none");
+                    log::debug!(
+                        "Cincinnati request {} succeeded, update target: {}",
+                        request_id,
+                        target
+                    );
+                    release
+                }
+                Err(e) => {
+                    UPDATE_CHECKS_ERRORS
+                        .with_label_values(&[&e.error_kind()])
+                        .inc();
+                    log::error!(
+                        "failed to check Cincinnati for updates (request ID {}): {}",
+                        request_id,
+                        e
+                    );
+                    None
+                }
+            });
+        Box::pin(update)
+    }
 }
 
 /// Evaluate and record whether booted OS is a dead-end release, and
 /// log that information in a MOTD file.
 fn refresh_deadend_status(node: &Node) -> Result<()> {
-    match evaluate_deadend(node) {
+    let deadend_reason = evaluate_deadend(node);
+    DEADEND_STATE.set_reason(deadend_reason.clone());
+    match deadend_reason {
         Some(reason) => {
             BOOTED_DEADEND.set(1);
             if !DEADEND_STATE.is_deadend() {
@@ -210,11 +483,17 @@ fn refresh_deadend_status(node: &Node) -> Result<()> {
 }
 
 /// Walk the graph, looking for an update reachable from the given digest.
-fn find_update(
+///
+/// Targets in `skip_versions` or younger than `min_age` (if any) are ignored,
+/// and `policy` selects among the remaining ones.
+pub(crate) fn find_update(
     graph: client::Graph,
     booted_depl: Release,
     local_depls: BTreeSet<Release>,
     allow_downgrade: bool,
+    min_age: Option<chrono::Duration>,
+    skip_versions: &BTreeSet<String>,
+    policy: TargetPolicy,
 ) -> Result<Option<Release>, CincinnatiError> {
     GRAPH_NODES.set(graph.nodes.len() as i64);
     GRAPH_EDGES.set(graph.edges.len() as i64);
@@ -238,17 +517,10 @@ fn find_update(
     let cur_release = Release::from_cincinnati(cur_node.clone())
         .map_err(|e| CincinnatiError::FailedNodeParsing(e.to_string()))?;
 
-    if let Err(e) = refresh_deadend_status(&cur_node) {
+    // Evaluate and record whether booted OS is a dead-end release.
+    if let Err(e) = refresh_deadend_status(cur_node) {
         log::warn!("failed to refresh dead-end status: {}", e);
     }
-    // Evaluate and record whether booted OS is a dead-end release.
-    // TODO(lucab): consider exposing this information in more places
-    // (e.g. logs, motd, env/json file in a well-known location).
-    let is_deadend = match evaluate_deadend(&cur_node) {
-        Some(_) => 1,
-        None => 0,
-    };
-    BOOTED_DEADEND.set(is_deadend);
 
     // Try to find all local deployments in the graph too.
     let local_releases = find_local_releases(&graph, local_depls);
@@ -265,7 +537,10 @@ fn find_update(
             }
         })
         .collect();
+    let now = Utc::now();
     let mut updates = BTreeSet::new();
+    let mut skipped = 0;
+    let mut too_recent = 0;
     for pos in targets {
         let node = match graph.nodes.get(pos) {
             Some(n) => n.clone(),
@@ -274,13 +549,31 @@ fn find_update(
                 return Err(CincinnatiError::FailedNodeLookup(msg));
             }
         };
+        if skip_versions.contains(&node.version) {
+            log::info!(
+                "skipping update target '{}', deny-listed by configuration",
+                node.version
+            );
+            skipped += 1;
+            continue;
+        }
+        if is_too_recent(&node, min_age, &now, true) {
+            log::debug!(
+                "ignoring update target '{}', younger than minimum release age",
+                node.version
+            );
+            too_recent += 1;
+            continue;
+        }
         let release = Release::from_cincinnati(node)
             .map_err(|e| CincinnatiError::FailedNodeParsing(e.to_string()))?;
         updates.insert(release);
     }
+    UPDATE_TARGETS_SKIPPED.set(skipped);
+    UPDATE_TARGETS_TOO_RECENT.set(too_recent);
 
     // Exclude target already deployed locally in the past.
-    let new_updates = updates.difference(&local_releases);
+    let new_updates: Vec<Release> = updates.difference(&local_releases).cloned().collect();
 
     // Log that we will avoid updating to already deployed releases.
     let prev_deployed_excluded = updates.intersection(&local_releases).count();
@@ -293,9 +586,9 @@ fn find_update(
     }
     UPDATE_TARGETS_IGNORED.set(prev_deployed_excluded as i64);
 
-    // Pick highest available updates target (based on age-index).
-    let next = match new_updates.last().cloned() {
-        Some(rel) => rel,
+    // Pick an update target, as ranked by the configured policy.
+    let next = match policy.ranking().select(&cur_release, &new_updates, &now) {
+        Some(rel) => rel.clone(),
         None => return Ok(None),
     };
 
@@ -311,6 +604,49 @@ fn find_update(
     Ok(Some(next))
 }
 
+/// Return whether a release node is younger than `min_age`.
+///
+/// Release time is read from node metadata. As a fallback, if `track_first_seen`
+/// is set, the time at which the agent first saw the node is used instead.
+/// Releases with unknown age are not considered too recent.
+fn is_too_recent(
+    node: &Node,
+    min_age: Option<chrono::Duration>,
+    now: &DateTime<Utc>,
+    track_first_seen: bool,
+) -> bool {
+    let min_age = match min_age {
+        Some(age) => age,
+        None => return false,
+    };
+
+    let released = match node.metadata.get(RELEASE_TIMESTAMP_KEY) {
+        Some(timestamp) => match DateTime::parse_from_rfc3339(timestamp) {
+            Ok(t) => Some(t.with_timezone(&Utc)),
+            Err(e) => {
+                log::warn!(
+                    "invalid release timestamp '{}' for '{}': {}",
+                    timestamp,
+                    node.version,
+                    e
+                );
+                None
+            }
+        },
+        None => None,
+    };
+    let released = match released {
+        Some(t) => t,
+        None if track_first_seen => match FIRST_SEEN.lock() {
+            Ok(mut seen) => *seen.entry(node.payload.clone()).or_insert(*now),
+            Err(_) => return false,
+        },
+        None => return false,
+    };
+
+    now.signed_duration_since(released) < min_age
+}
+
 /// Try to match a set of (local) deployments to their graph entries.
 fn find_local_releases(graph: &client::Graph, depls: BTreeSet<Release>) -> BTreeSet<Release> {
     use std::collections::HashSet;
@@ -427,4 +763,256 @@ mod tests {
         let common: Node = serde_json::from_str(common_json).unwrap();
         assert_eq!(evaluate_deadend(&common), None);
     }
+
+    #[test]
+    fn graph_inspection() {
+        let graph_json = r#"
+{
+  "nodes": [
+    {
+      "version": "30.20190716.1",
+      "metadata": {
+        "org.fedoraproject.coreos.releases.age_index": "0",
+        "org.fedoraproject.coreos.scheme": "checksum",
+        "org.fedoraproject.coreos.updates.deadend": "true",
+        "org.fedoraproject.coreos.updates.deadend_reason": "bad release"
+      },
+      "payload": "sha-booted"
+    },
+    {
+      "version": "30.20190725.0",
+      "metadata": {
+        "org.fedoraproject.coreos.releases.age_index": "1",
+        "org.fedoraproject.coreos.scheme": "checksum"
+      },
+      "payload": "sha-next"
+    }
+  ],
+  "edges": [ [0, 1] ]
+}
+"#;
+        let graph: Graph = serde_json::from_str(graph_json).unwrap();
+        let booted = Release {
+            version: "30.20190716.1".to_string(),
+            checksum: "sha-booted".to_string(),
+            age_index: None,
+            advisory: None,
+            wave: None,
+        };
+        let inspection = inspect_graph(
+            &graph,
+            &booted,
+            BTreeSet::new(),
+            false,
+            None,
+            &BTreeSet::new(),
+            TargetPolicy::default(),
+        )
+        .unwrap();
+        assert!(inspection.booted_in_graph);
+        assert_eq!(inspection.deadend_reason, Some("bad release".to_string()));
+        assert_eq!(
+            inspection.target.map(|r| r.version),
+            Some("30.20190725.0".to_string())
+        );
+
+        // Targets deployed in the past are ignored.
+        let next = Release::from_cincinnati(graph.nodes[1].clone()).unwrap();
+        let deployments = maplit::btreeset![next];
+        let inspection = inspect_graph(
+            &graph,
+            &booted,
+            deployments,
+            false,
+            None,
+            &BTreeSet::new(),
+            TargetPolicy::default(),
+        )
+        .unwrap();
+        assert_eq!(inspection.target, None);
+
+        let unknown = Release {
+            version: "0.0.0".to_string(),
+            checksum: "sha-unknown".to_string(),
+            age_index: None,
+            advisory: None,
+            wave: None,
+        };
+        let inspection = inspect_graph(
+            &graph,
+            &unknown,
+            BTreeSet::new(),
+            false,
+            None,
+            &BTreeSet::new(),
+            TargetPolicy::default(),
+        )
+        .unwrap();
+        assert_eq!(inspection, GraphInspection::default());
+    }
+
+    #[test]
+    fn release_min_age() {
+        let now = Utc::now();
+        let min_age = Some(chrono::Duration::hours(72));
+        let node = |payload: &str, age_hours: Option<i64>| {
+            let mut metadata = HashMap::new();
+            metadata.insert(SCHEME_KEY.to_string(), CHECKSUM_SCHEME.to_string());
+            if let Some(hours) = age_hours {
+                let released = now - chrono::Duration::hours(hours);
+                metadata.insert(RELEASE_TIMESTAMP_KEY.to_string(), released.to_rfc3339());
+            }
+            Node {
+                version: payload.to_string(),
+                payload: payload.to_string(),
+                metadata,
+            }
+        };
+
+        assert!(!is_too_recent(&node("recent", Some(1)), None, &now, true));
+        assert!(is_too_recent(&node("recent", Some(1)), min_age, &now, true));
+        assert!(!is_too_recent(&node("old", Some(96)), min_age, &now, true));
+
+        // Without metadata, age is counted from first sighting (if tracked).
+        let unknown = node("sha-unknown-age", None);
+        assert!(!is_too_recent(&unknown, min_age, &now, false));
+        assert!(is_too_recent(&unknown, min_age, &now, true));
+        let later = now + chrono::Duration::hours(73);
+        assert!(!is_too_recent(&unknown, min_age, &later, true));
+
+        let mut graph = Graph {
+            nodes: vec![node("booted", Some(200)), node("recent", Some(1))],
+            edges: vec![(0, 1)],
+        };
+        for (pos, n) in graph.nodes.iter_mut().enumerate() {
+            n.metadata
+                .insert(AGE_INDEX_KEY.to_string(), pos.to_string());
+        }
+        let booted = Release::from_cincinnati(graph.nodes[0].clone()).unwrap();
+        let inspection = inspect_graph(
+            &graph,
+            &booted,
+            BTreeSet::new(),
+            false,
+            min_age,
+            &BTreeSet::new(),
+            TargetPolicy::default(),
+        )
+        .unwrap();
+        assert_eq!(inspection.target, None);
+        assert_eq!(
+            inspection.too_recent.map(|r| r.version),
+            Some("recent".to_string())
+        );
+    }
+
+    #[test]
+    fn release_skip_versions() {
+        let node = |version: &str, age_index: u64| Node {
+            version: version.to_string(),
+            payload: format!("sha-{}", version),
+            metadata: maplit::hashmap! {
+                SCHEME_KEY.to_string() => CHECKSUM_SCHEME.to_string(),
+                AGE_INDEX_KEY.to_string() => age_index.to_string(),
+            },
+        };
+        let graph = Graph {
+            nodes: vec![node("booted", 0), node("good", 1), node("bad", 2)],
+            edges: vec![(0, 1), (0, 2)],
+        };
+        let booted = Release::from_cincinnati(graph.nodes[0].clone()).unwrap();
+
+        let inspection = inspect_graph(
+            &graph,
+            &booted,
+            BTreeSet::new(),
+            false,
+            None,
+            &BTreeSet::new(),
+            TargetPolicy::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            inspection.target.map(|r| r.version),
+            Some("bad".to_string())
+        );
+        assert_eq!(inspection.skipped, None);
+
+        let skip_versions = maplit::btreeset!["bad".to_string()];
+        let inspection = inspect_graph(
+            &graph,
+            &booted,
+            BTreeSet::new(),
+            false,
+            None,
+            &skip_versions,
+            TargetPolicy::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            inspection.target.map(|r| r.version),
+            Some("good".to_string())
+        );
+        assert_eq!(
+            inspection.skipped.map(|r| r.version),
+            Some("bad".to_string())
+        );
+    }
+
+    #[test]
+    fn release_target_policy() {
+        let node = |version: &str, age_index: u64| Node {
+            version: version.to_string(),
+            payload: format!("sha-{}", version),
+            metadata: maplit::hashmap! {
+                SCHEME_KEY.to_string() => CHECKSUM_SCHEME.to_string(),
+                AGE_INDEX_KEY.to_string() => age_index.to_string(),
+            },
+        };
+        let graph = Graph {
+            nodes: vec![node("booted", 1), node("next", 2), node("newest", 3)],
+            edges: vec![(0, 1), (0, 2)],
+        };
+        let booted = Release::from_cincinnati(graph.nodes[0].clone()).unwrap();
+
+        let expected = vec![
+            (TargetPolicy::Newest, "newest"),
+            (TargetPolicy::NextHop, "next"),
+            (TargetPolicy::LowestRisk, "next"),
+        ];
+        for (policy, version) in expected {
+            let inspection = inspect_graph(
+                &graph,
+                &booted,
+                BTreeSet::new(),
+                false,
+                None,
+                &BTreeSet::new(),
+                policy,
+            )
+            .unwrap();
+            assert_eq!(
+                inspection.target.map(|r| r.version),
+                Some(version.to_string())
+            );
+        }
+    }
+
+    #[test]
+    fn deadend_state_reason() {
+        let state = DeadEndState::default();
+        assert!(!state.is_deadend());
+        assert!(!state.is_no_deadend());
+        assert_eq!(state.reason(), None);
+
+        state.set_reason(Some("foo".to_string()));
+        state.set_deadend();
+        assert!(state.is_deadend());
+        assert_eq!(state.reason(), Some("foo".to_string()));
+
+        state.set_reason(None);
+        state.set_no_deadend();
+        assert!(state.is_no_deadend());
+        assert_eq!(state.reason(), None);
+    }
 }
@@ -0,0 +1,197 @@
+//! Policies for selecting an update target.
+//!
+//! The graph can offer multiple valid update targets from the booted
+//! release. A policy selects one of them, after targets which are deny-listed,
+//! too recent, or deployed locally in the past have been filtered out.
+
+use crate::rpm_ostree::Release;
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+/// Ranking of update targets, picking one of them.
+pub trait RankingPolicy {
+    /// Select a target among `candidates` (non-empty, sorted by age-index),
+    /// for a node running `current`.
+    fn select<'a>(
+        &self,
+        current: &Release,
+        candidates: &'a [Release],
+        now: &DateTime<Utc>,
+    ) -> Option<&'a Release>;
+}
+
+/// Select the newest target.
+#[derive(Clone, Copy, Debug)]
+pub struct Newest;
+
+impl RankingPolicy for Newest {
+    fn select<'a>(
+        &self,
+        _current: &Release,
+        candidates: &'a [Release],
+        _now: &DateTime<Utc>,
+    ) -> Option<&'a Release> {
+        candidates.last()
+    }
+}
+
+/// Select the oldest target newer than the current release, i.e. the
+/// smallest step forward.
+#[derive(Clone, Copy, Debug)]
+pub struct NextHop;
+
+impl RankingPolicy for NextHop {
+    fn select<'a>(
+        &self,
+        current: &Release,
+        candidates: &'a [Release],
+        now: &DateTime<Utc>,
+    ) -> Option<&'a Release> {
+        candidates
+            .iter()
+            .find(|rel| *rel > current)
+            .or_else(|| Newest.select(current, candidates, now))
+    }
+}
+
+/// Select the oldest target newer than the current release whose rollout
+/// is already open to this node, falling back to the next hop.
+#[derive(Clone, Copy, Debug)]
+pub struct LowestRisk;
+
+impl RankingPolicy for LowestRisk {
+    fn select<'a>(
+        &self,
+        current: &Release,
+        candidates: &'a [Release],
+        now: &DateTime<Utc>,
+    ) -> Option<&'a Release> {
+        candidates
+            .iter()
+            .filter(|rel| *rel > current)
+            .find(|rel| rel.wave.as_ref().map(|w| w.start <= *now).unwrap_or(true))
+            .or_else(|| NextHop.select(current, candidates, now))
+    }
+}
+
+/// Configured policy for selecting an update target.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum TargetPolicy {
+    /// Newest target (default).
+    #[default]
+    Newest,
+    /// Smallest step forward.
+    NextHop,
+    /// Smallest step forward already open for rollout.
+    LowestRisk,
+}
+
+impl TargetPolicy {
+    /// Parse a policy label (empty for the default one).
+    pub fn parse(label: &str) -> Result<Self> {
+        let policy = match label.trim() {
+            "" | "newest" => TargetPolicy::Newest,
+            "next-hop" => TargetPolicy::NextHop,
+            "lowest-risk" => TargetPolicy::LowestRisk,
+            x => anyhow::bail!(
+                "unknown target policy '{}', expected one of: newest, next-hop, lowest-risk",
+                x
+            ),
+        };
+        Ok(policy)
+    }
+
+    /// Return the ranking implementing this policy.
+    pub fn ranking(&self) -> &'static dyn RankingPolicy {
+        match self {
+            TargetPolicy::Newest => &Newest,
+            TargetPolicy::NextHop => &NextHop,
+            TargetPolicy::LowestRisk => &LowestRisk,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rpm_ostree::RolloutWave;
+
+    fn release(age_index: u64, wave_start: Option<DateTime<Utc>>) -> Release {
+        Release {
+            version: format!("v{}", age_index),
+            checksum: format!("c{}", age_index),
+            age_index: Some(age_index),
+            advisory: None,
+            wave: wave_start.map(|start| RolloutWave {
+                name: "canary".to_string(),
+                start,
+            }),
+        }
+    }
+
+    fn select(policy: TargetPolicy, current: &Release, candidates: &[Release]) -> String {
+        let now = Utc::now();
+        let target = policy.ranking().select(current, candidates, &now);
+        target.map(|r| r.version.clone()).unwrap_or_default()
+    }
+
+    #[test]
+    fn policy_parse() {
+        assert_eq!(TargetPolicy::parse("").unwrap(), TargetPolicy::Newest);
+        assert_eq!(
+            TargetPolicy::parse(" next-hop ").unwrap(),
+            TargetPolicy::NextHop
+        );
+        assert_eq!(
+            TargetPolicy::parse("lowest-risk").unwrap(),
+            TargetPolicy::LowestRisk
+        );
+        for invalid in &["oldest", "Newest", "next_hop"] {
+            TargetPolicy::parse(invalid).unwrap_err();
+        }
+    }
+
+    #[test]
+    fn policy_newest() {
+        let current = release(2, None);
+        let candidates = vec![release(1, None), release(3, None), release(4, None)];
+        assert_eq!(select(TargetPolicy::Newest, &current, &candidates), "v4");
+        assert_eq!(select(TargetPolicy::Newest, &current, &[]), "");
+    }
+
+    #[test]
+    fn policy_next_hop() {
+        let current = release(2, None);
+        let candidates = vec![release(1, None), release(3, None), release(4, None)];
+        assert_eq!(select(TargetPolicy::NextHop, &current, &candidates), "v3");
+
+        // Only downgrades, left to the caller to accept or reject.
+        let candidates = vec![release(0, None), release(1, None)];
+        assert_eq!(select(TargetPolicy::NextHop, &current, &candidates), "v1");
+    }
+
+    #[test]
+    fn policy_lowest_risk() {
+        let current = release(2, None);
+        let future = Utc::now() + chrono::Duration::hours(1);
+        let past = Utc::now() - chrono::Duration::hours(1);
+        let candidates = vec![
+            release(3, Some(future)),
+            release(4, Some(past)),
+            release(5, None),
+        ];
+        assert_eq!(
+            select(TargetPolicy::LowestRisk, &current, &candidates),
+            "v4"
+        );
+
+        // All rollouts pending, same as next hop.
+        let candidates = vec![release(3, Some(future)), release(4, Some(future))];
+        assert_eq!(
+            select(TargetPolicy::LowestRisk, &current, &candidates),
+            "v3"
+        );
+    }
+}
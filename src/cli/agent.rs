@@ -1,23 +1,39 @@
 //! Logic for the `agent` subcommand.
 
 use super::ensure_user;
-use crate::{config, dbus, metrics, rpm_ostree, update_agent};
-use actix::Actor;
+#[cfg(feature = "dbus")]
+use crate::dbus;
+#[cfg(feature = "metrics")]
+use crate::metrics;
+use crate::{config, logging, network_watch, rpm_ostree, self_test, update_agent, watchdog};
+use actix::{Actor, Addr};
 use anyhow::{Context, Result};
+use futures::future::{self, Either};
 use log::{info, trace};
-use prometheus::IntGauge;
+use prometheus::{IntGauge, IntGaugeVec};
+use std::time::Duration;
 use structopt::clap::{crate_name, crate_version};
+use tokio::signal::unix::{signal, SignalKind};
 
 lazy_static::lazy_static! {
     static ref PROCESS_START_TIME: IntGauge = register_int_gauge!(opts!(
         "process_start_time_seconds",
         "Start time of the process since unix epoch in seconds."
     )).unwrap();
+    static ref BUILD_INFO: IntGaugeVec = register_int_gauge_vec!(
+        "zincati_build_info",
+        "Information about the agent build",
+        &["version", "git_sha", "rustc"]
+    ).unwrap();
 }
 
+/// Maximum time for a graceful shutdown, before stopping the actor system anyway.
+const SHUTDOWN_TIMEOUT_SECS: u64 = 60;
+
 /// Agent subcommand entry-point.
 pub(crate) fn run_agent() -> Result<()> {
     ensure_user("zincati", "update agent not running as `zincati` user")?;
+    logging::restore_persisted();
     info!(
         "starting update agent ({} {})",
         crate_name!(),
@@ -26,32 +42,110 @@ pub(crate) fn run_agent() -> Result<()> {
 
     let settings = config::Settings::assemble()?;
     settings.refresh_metrics();
+    rpm_ostree::check_update_policy(settings.reconcile_rpm_ostree_policy);
     info!(
         "agent running on node '{}', in update group '{}'",
         settings.identity.node_uuid.lower_hex(),
         settings.identity.group
     );
 
-    // Expose process start timestamp.
+    // Expose process start timestamp and build details.
     let start_time = chrono::Utc::now();
     PROCESS_START_TIME.set(start_time.timestamp());
+    BUILD_INFO
+        .with_label_values(&[
+            crate_version!(),
+            env!("ZINCATI_GIT_SHA"),
+            env!("ZINCATI_RUSTC_VERSION"),
+        ])
+        .set(1);
 
     trace!("creating actor system");
     let sys = actix::System::new();
 
     sys.block_on(async {
-        trace!("creating metrics service");
-        let _metrics_addr = metrics::MetricsService::bind_socket()?.start();
+        #[cfg(feature = "metrics")]
+        {
+            trace!("creating metrics service");
+            let _metrics_addr = metrics::MetricsService::bind_socket()?.start();
+            if let Some(telemetry) = &settings.telemetry {
+                trace!("creating metrics exporter over TCP");
+                metrics::TcpExporter::bind(telemetry)?.spawn()?;
+            }
+        }
+
+        if let Some(interval) = settings.self_test_interval {
+            trace!("creating self-test prober");
+            let metrics_tcp = settings.telemetry.as_ref().map(|t| t.listen_address);
+            self_test::SelfTest::new(interval, metrics_tcp).spawn()?;
+        }
 
         trace!("creating rpm-ostree client");
-        let rpm_ostree_addr = rpm_ostree::RpmOstreeClient::start(1);
+        let rpm_ostree_client = rpm_ostree::RpmOstreeClient::new(
+            settings.rpm_ostree_backend,
+            settings.rpm_ostree_timeouts,
+        );
+        let rpm_ostree_addr =
+            rpm_ostree::OperationQueue::start(rpm_ostree_client, settings.rpm_ostree_max_queued);
 
         trace!("creating update agent");
+        let check_on_network_up = settings.check_on_network_up;
+        let queue_addr = rpm_ostree_addr.clone();
         let agent = update_agent::UpdateAgent::with_config(settings, rpm_ostree_addr);
         let agent_addr = agent.start();
 
-        trace!("creating D-Bus service");
-        let _dbus_service_addr = dbus::DBusService::start(1, agent_addr);
+        if let Some(watchdog) = watchdog::Watchdog::new(agent_addr.clone()) {
+            trace!("creating service watchdog keep-alive");
+            watchdog.spawn();
+        }
+
+        if check_on_network_up {
+            trace!("creating network connectivity watcher");
+            network_watch::NetworkWatch::new(agent_addr.clone()).spawn()?;
+        }
+
+        trace!("creating shutdown handler");
+        let mut terminations =
+            signal(SignalKind::terminate()).context("failed to set up SIGTERM handler")?;
+        let mut interrupts =
+            signal(SignalKind::interrupt()).context("failed to set up SIGINT handler")?;
+        let shutdown_addr = agent_addr.clone();
+        actix::spawn(async move {
+            future::select(Box::pin(terminations.recv()), Box::pin(interrupts.recv())).await;
+            info!("received termination signal, shutting down");
+            let timeout = actix::clock::sleep(Duration::from_secs(SHUTDOWN_TIMEOUT_SECS));
+            let shutdown = shutdown(queue_addr, shutdown_addr);
+            if let Either::Right(_) = future::select(Box::pin(shutdown), Box::pin(timeout)).await {
+                log::warn!(
+                    "graceful shutdown timed out after {} seconds",
+                    SHUTDOWN_TIMEOUT_SECS
+                );
+            }
+            actix::System::current().stop();
+        });
+
+        trace!("creating configuration reload handler");
+        let mut hangups =
+            signal(SignalKind::hangup()).context("failed to set up SIGHUP handler")?;
+        let reload_addr = agent_addr.clone();
+        actix::spawn(async move {
+            while hangups.recv().await.is_some() {
+                info!("received SIGHUP, reloading configuration");
+                match reload_addr.send(update_agent::Reload {}).await {
+                    Ok(Ok(())) => {}
+                    Ok(Err(e)) => log::error!("{:#}", e),
+                    Err(e) => log::error!("failed to send reload request to agent: {}", e),
+                }
+            }
+        });
+
+        #[cfg(feature = "dbus")]
+        {
+            trace!("creating D-Bus service");
+            let _dbus_service_addr = dbus::DBusService::start(1, agent_addr);
+        }
+        #[cfg(not(feature = "dbus"))]
+        let _ = agent_addr;
 
         Ok::<(), anyhow::Error>(())
     })?;
@@ -61,3 +155,21 @@ pub(crate) fn run_agent() -> Result<()> {
 
     Ok(())
 }
+
+/// Gracefully shut down the update agent.
+///
+/// In-flight rpm-ostree transactions are aborted first, as the agent only
+/// processes the shutdown request once its current refresh tick completes.
+async fn shutdown(
+    queue_addr: Addr<rpm_ostree::OperationQueue>,
+    agent_addr: Addr<update_agent::UpdateAgent>,
+) {
+    if let Err(e) = queue_addr.send(rpm_ostree::ShutdownQueue {}).await {
+        log::error!("failed to send shutdown request to rpm-ostree queue: {}", e);
+    }
+    match agent_addr.send(update_agent::Shutdown {}).await {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => log::error!("{:#}", e),
+        Err(e) => log::error!("failed to send shutdown request to agent: {}", e),
+    }
+}
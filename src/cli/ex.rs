@@ -1,6 +1,9 @@
 //! Logic for the ex subcommand.
 
 use super::ensure_user;
+use super::simulate::SimulateFleetOpts;
+use super::status::timestamp;
+use crate::update_agent::{AgentStatus, PlannedAction, UpdateCheck};
 use anyhow::Result;
 use fn_error_context::context;
 use structopt::StructOpt;
@@ -15,15 +18,49 @@ pub enum Cmd {
         #[structopt(long)]
         talkative: bool,
     },
+    /// Get the projected next actions of the update agent.
+    #[structopt(name = "get-plan")]
+    GetPlan,
     /// Get last refresh time of update agent actor's state.
     #[structopt(name = "last-refresh-time")]
     LastRefreshTime,
+    /// Schedule a one-time finalization at the given UTC timestamp
+    /// (seconds since epoch), if an update is staged by then.
+    #[structopt(name = "schedule-finalize")]
+    ScheduleFinalize { timestamp: i64 },
+    /// Cancel the one-time scheduled finalization.
+    #[structopt(name = "cancel-scheduled-finalize")]
+    CancelScheduledFinalize,
+    /// Forbid reboots between the given UTC timestamps (seconds since epoch).
+    #[structopt(name = "set-temporary-blackout")]
+    SetTemporaryBlackout {
+        start: i64,
+        end: i64,
+        /// Reason for the blackout, shown in the agent status.
+        #[structopt(long, default_value = "")]
+        reason: String,
+    },
+    /// Lift the temporary blackout.
+    #[structopt(name = "clear-temporary-blackout")]
+    ClearTemporaryBlackout,
+    /// Get the UTC timestamp of the one-time scheduled finalization (0 if unset).
+    #[structopt(name = "scheduled-finalize-time")]
+    ScheduledFinalizeTime,
+    /// Simulate an update rollout across a fleet, and estimate
+    /// time-to-full-rollout (does not require a running agent).
+    #[structopt(name = "simulate-fleet")]
+    SimulateFleet(SimulateFleetOpts),
 }
 
 impl Cmd {
     /// `ex` subcommand entry point.
     #[context("failed to run `ex` subcommand")]
     pub(crate) fn run(self) -> Result<()> {
+        // Offline commands, not talking to the agent.
+        if let Cmd::SimulateFleet(opts) = self {
+            return opts.run();
+        }
+
         ensure_user(
             "root",
             "ex subcommand must be run as `root` user, \
@@ -36,10 +73,45 @@ impl Cmd {
                 println!("{}", proxy.moo(talkative)?);
                 Ok(())
             }
+            Cmd::GetPlan => {
+                for step in proxy.get_plan()? {
+                    let at = match step.estimated_time {
+                        0 => "unknown".to_string(),
+                        t => timestamp(t),
+                    };
+                    let mut line = format!("{}: {}", step.action, at);
+                    if !step.conditions.is_empty() {
+                        line.push_str(&format!(" ({})", step.conditions));
+                    }
+                    println!("{}", line);
+                }
+                Ok(())
+            }
             Cmd::LastRefreshTime => {
                 println!("{}", proxy.last_refresh_time()?);
                 Ok(())
             }
+            Cmd::ScheduleFinalize { timestamp } => {
+                proxy.schedule_finalize(timestamp)?;
+                Ok(())
+            }
+            Cmd::CancelScheduledFinalize => {
+                println!("{}", proxy.cancel_scheduled_finalize()?);
+                Ok(())
+            }
+            Cmd::SetTemporaryBlackout { start, end, reason } => {
+                proxy.set_temporary_blackout(start, end, &reason)?;
+                Ok(())
+            }
+            Cmd::ClearTemporaryBlackout => {
+                println!("{}", proxy.clear_temporary_blackout()?);
+                Ok(())
+            }
+            Cmd::ScheduledFinalizeTime => {
+                println!("{}", proxy.scheduled_finalize_time()?);
+                Ok(())
+            }
+            Cmd::SimulateFleet(_) => unreachable!("offline command"),
         }
     }
 }
@@ -49,10 +121,41 @@ impl Cmd {
     default_service = "org.coreos.zincati",
     default_path = "/org/coreos/zincati"
 )]
-trait Experimental {
+pub(super) trait Experimental {
+    /// CancelScheduledFinalize method
+    fn cancel_scheduled_finalize(&self) -> zbus::Result<bool>;
+
+    /// CheckUpdate method
+    fn check_update(&self) -> zbus::Result<UpdateCheck>;
+
+    /// ClearTemporaryBlackout method
+    fn clear_temporary_blackout(&self) -> zbus::Result<bool>;
+
+    /// GetFullStatus method
+    fn get_full_status(&self) -> zbus::Result<AgentStatus>;
+
+    /// GetPlan method
+    fn get_plan(&self) -> zbus::Result<Vec<PlannedAction>>;
+
+    /// Hold method
+    fn hold(&self) -> zbus::Result<String>;
+
     /// LastRefreshTime method
     fn last_refresh_time(&self) -> zbus::Result<i64>;
 
     /// Moo method
     fn moo(&self, talkative: bool) -> zbus::Result<String>;
+
+    /// ScheduleFinalize method
+    fn schedule_finalize(&self, timestamp: i64) -> zbus::Result<()>;
+
+    /// Unhold method
+    fn unhold(&self) -> zbus::Result<bool>;
+
+    /// SetTemporaryBlackout method
+    fn set_temporary_blackout(&self, start: i64, end: i64, reason: &str) -> zbus::Result<()>;
+
+    /// ScheduledFinalizeTime property
+    #[dbus_proxy(property)]
+    fn scheduled_finalize_time(&self) -> zbus::Result<i64>;
 }
@@ -0,0 +1,38 @@
+//! Logic for the `history` subcommand.
+
+use crate::update_agent::history::{self, HISTORY_PATH};
+use anyhow::Result;
+use structopt::StructOpt;
+
+/// Options for the `history` subcommand.
+#[derive(Debug, StructOpt)]
+pub struct HistoryOpts {
+    /// Print history entries as JSON.
+    #[structopt(long)]
+    json: bool,
+    /// Only print the last N entries.
+    #[structopt(long, short = "n")]
+    last: Option<usize>,
+}
+
+impl HistoryOpts {
+    /// `history` subcommand entry point.
+    pub(crate) fn run(self) -> Result<()> {
+        let mut entries = history::load(HISTORY_PATH)?;
+        if let Some(last) = self.last {
+            let skipped = entries.len().saturating_sub(last);
+            entries.drain(..skipped);
+        }
+
+        if self.json {
+            println!("{}", serde_json::to_string_pretty(&entries)?);
+        } else if entries.is_empty() {
+            println!("no update history recorded");
+        } else {
+            for entry in entries {
+                println!("{}", entry.describe());
+            }
+        }
+        Ok(())
+    }
+}
@@ -3,6 +3,12 @@
 mod agent;
 mod deadend;
 mod ex;
+mod history;
+mod simulate;
+mod status;
+mod update;
+mod validate;
+mod why;
 
 use anyhow::Result;
 use log::LevelFilter;
@@ -33,13 +39,23 @@ impl CliOptions {
         }
     }
 
-    /// Dispatch CLI subcommand.
-    pub(crate) fn run(self) -> Result<()> {
+    /// Dispatch CLI subcommand, returning the process exit code.
+    pub(crate) fn run(self) -> Result<i32> {
         match self.cmd {
             CliCommand::Agent => agent::run_agent(),
+            CliCommand::CheckUpdate => return update::check_update(),
             CliCommand::DeadendMotd(cmd) => cmd.run(),
             CliCommand::Ex(cmd) => cmd.run(),
-        }
+            CliCommand::Finalize => return update::finalize(),
+            CliCommand::History(opts) => opts.run(),
+            CliCommand::Hold => update::hold(),
+            CliCommand::Simulate(opts) => opts.run(),
+            CliCommand::Status(opts) => opts.run(),
+            CliCommand::Unhold => update::unhold(),
+            CliCommand::ValidateConfig => validate::validate_config(),
+            CliCommand::Why => return why::why(),
+        }?;
+        Ok(libc::EXIT_SUCCESS)
     }
 }
 
@@ -49,12 +65,35 @@ impl CliOptions {
 pub(crate) enum CliCommand {
     /// Long-running agent for auto-updates.
     Agent,
+    /// Check whether an update is available (exit code 100 if so).
+    CheckUpdate,
     /// Set or unset deadend MOTD state.
     #[structopt(setting = AppSettings::Hidden)]
     DeadendMotd(deadend::Cmd),
     /// Print update agent state's last refresh time.
     #[structopt(setting = AppSettings::Hidden)]
     Ex(ex::Cmd),
+    /// Finalize the staged update as soon as possible, overriding the
+    /// update strategy (exit code 2 if no update is staged).
+    Finalize,
+    /// Show the history of updates on this node (checks finding an update,
+    /// stagings, postponements, finalizations and failures).
+    History(history::HistoryOpts),
+    /// Hold the node on its booted release: updates are still checked for
+    /// and reported, but not applied until `unhold`.
+    Hold,
+    /// Replay the configured update strategy over a date range, printing
+    /// when reboots would have been allowed (does not require a running agent).
+    Simulate(simulate::SimulateOpts),
+    /// Show update agent status.
+    Status(status::StatusOpts),
+    /// Release the hold on the booted release.
+    Unhold,
+    /// Validate configuration fragments and print the effective configuration.
+    ValidateConfig,
+    /// Explain why an update is (not) happening, by checking every gate once
+    /// in read-only mode (exit code 2 if a gate fails).
+    Why,
 }
 
 /// Return Error with msg if not run by user.
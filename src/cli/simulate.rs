@@ -0,0 +1,226 @@
+//! Logic for the `simulate` and `ex simulate-fleet` subcommands.
+
+use crate::blackout::BlackoutPeriods;
+use crate::config::inputs;
+use crate::config::inputs::DEFAULT_STEADY_INTERVAL_SECS;
+use crate::simulate::{human_replay_summary, FinalizePolicy, FleetSimulation, StrategyReplay};
+use crate::strategy::{StrategyImmediate, StrategyPeriodic, FLEET_LOCK_LABEL};
+use anyhow::{Context, Result};
+use chrono::{DateTime, NaiveDate, TimeZone, Utc};
+use fn_error_context::context;
+use structopt::StructOpt;
+
+/// Options for fleet rollout simulation.
+#[derive(Debug, StructOpt)]
+pub struct SimulateFleetOpts {
+    /// Number of nodes in the fleet.
+    #[structopt(long, default_value = "100")]
+    nodes: u32,
+    /// Update strategy (immediate, periodic, fleet_lock).
+    #[structopt(long, default_value = "immediate")]
+    strategy: String,
+    /// Number of concurrent reboot slots (fleet_lock strategy).
+    #[structopt(long, default_value = "1")]
+    slots: u32,
+    /// Maintenance window, as "<days> <HH:MM> <length_minutes>" (periodic
+    /// strategy), e.g. "Sat,Sun 23:00 120". Can be repeated.
+    #[structopt(long = "window", number_of_values = 1)]
+    windows: Vec<String>,
+    /// Time zone for maintenance windows (periodic strategy).
+    #[structopt(long, default_value = "UTC")]
+    time_zone: String,
+    /// Duration of the server-side rollout, in hours.
+    #[structopt(long, default_value = "24")]
+    rollout_hours: u64,
+    /// Fixed rollout wariness for all nodes (default: random per node).
+    #[structopt(long)]
+    wariness: Option<f64>,
+    /// Pausing interval between update checks in steady state, in seconds.
+    #[structopt(long, default_value = "300")]
+    steady_interval_secs: u64,
+    /// Average time to download and stage an update, in minutes.
+    #[structopt(long, default_value = "10")]
+    stage_minutes: u64,
+    /// Average time to reboot and report steady, in minutes.
+    #[structopt(long, default_value = "5")]
+    reboot_minutes: u64,
+    /// Number of simulation runs.
+    #[structopt(long, default_value = "100")]
+    runs: u32,
+    /// Rollout start time, in RFC 3339 format (default: now).
+    #[structopt(long)]
+    start: Option<DateTime<Utc>>,
+    /// Seed for the random generator, for reproducible results.
+    #[structopt(long)]
+    seed: Option<u64>,
+}
+
+impl SimulateFleetOpts {
+    /// `ex simulate-fleet` subcommand entry point.
+    #[context("failed to run fleet simulation")]
+    pub(crate) fn run(self) -> Result<()> {
+        let policy = self.policy()?;
+        let simulation = FleetSimulation {
+            nodes: self.nodes,
+            runs: self.runs,
+            policy,
+            start: self.start.unwrap_or_else(Utc::now),
+            rollout_secs: self.rollout_hours.saturating_mul(3600),
+            wariness: self.wariness,
+            steady_interval_secs: self.steady_interval_secs,
+            refresh_period_secs: DEFAULT_STEADY_INTERVAL_SECS,
+            stage_secs: self.stage_minutes.saturating_mul(60),
+            reboot_secs: self.reboot_minutes.saturating_mul(60),
+            seed: self.seed,
+        };
+        let report = simulation.run()?;
+
+        println!(
+            "Simulated rollout to {} nodes, strategy: {}",
+            self.nodes,
+            self.human_strategy()
+        );
+        print!("{}", report.human_summary());
+        Ok(())
+    }
+
+    /// Build the finalization policy for the selected strategy.
+    fn policy(&self) -> Result<FinalizePolicy> {
+        let policy = match self.strategy.as_str() {
+            StrategyImmediate::LABEL => FinalizePolicy::Immediate,
+            FLEET_LOCK_LABEL => FinalizePolicy::FleetLock { slots: self.slots },
+            StrategyPeriodic::LABEL => {
+                let strategy = StrategyPeriodic::new(self.periodic_input()?)?;
+                FinalizePolicy::Periodic(strategy)
+            }
+            x => anyhow::bail!("unsupported strategy '{}'", x),
+        };
+        Ok(policy)
+    }
+
+    /// Assemble periodic strategy config from `--window` entries.
+    fn periodic_input(&self) -> Result<inputs::UpdateInput> {
+        let mut intervals = vec![];
+        for win in &self.windows {
+            let fields: Vec<_> = win.split_whitespace().collect();
+            if fields.len() != 3 {
+                anyhow::bail!("invalid window '{}'", win);
+            }
+            let length_minutes = fields[2]
+                .parse()
+                .with_context(|| format!("invalid window length '{}'", fields[2]))?;
+            for day in fields[0].split(',') {
+                let interval = inputs::PeriodicIntervalInput {
+                    start_day: day.to_string(),
+                    start_time: fields[1].to_string(),
+                    length_minutes,
+                    time_zone: None,
+                };
+                intervals.push(interval);
+            }
+        }
+
+        let input = inputs::UpdateInput {
+            strategy: StrategyPeriodic::LABEL.to_string(),
+            periodic: inputs::PeriodicInput {
+                intervals,
+                time_zone: self.time_zone.clone(),
+                window_jitter_minutes: 0,
+            },
+            ..Default::default()
+        };
+        Ok(input)
+    }
+
+    /// Return the selected strategy, in human terms.
+    fn human_strategy(&self) -> String {
+        match self.strategy.as_str() {
+            FLEET_LOCK_LABEL => format!("{} ({} slots)", self.strategy, self.slots),
+            StrategyPeriodic::LABEL => format!(
+                "{} ({}; {})",
+                self.strategy,
+                self.windows.join(", "),
+                self.time_zone
+            ),
+            _ => self.strategy.clone(),
+        }
+    }
+}
+
+/// Options for strategy replay.
+#[derive(Debug, StructOpt)]
+pub struct SimulateOpts {
+    /// Directory of configuration fragments (default: system locations).
+    #[structopt(long = "config")]
+    config_dir: Option<String>,
+    /// Start of the replayed range, as RFC 3339 timestamp or `YYYY-MM-DD` date (UTC).
+    #[structopt(long)]
+    from: String,
+    /// End of the replayed range (excluded), as RFC 3339 timestamp or
+    /// `YYYY-MM-DD` date (UTC).
+    #[structopt(long)]
+    to: String,
+}
+
+impl SimulateOpts {
+    /// `simulate` subcommand entry point.
+    #[context("failed to simulate update strategy")]
+    pub(crate) fn run(self) -> Result<()> {
+        let cfg = match &self.config_dir {
+            Some(dir) => {
+                inputs::ConfigInput::read_configs(vec![dir.clone()], "", vec!["toml".to_string()])?
+            }
+            None => crate::config::read_inputs()?,
+        };
+        let from = parse_datetime(&self.from)?;
+        let to = parse_datetime(&self.to)?;
+
+        let blackout = BlackoutPeriods::with_config_since(cfg.updates.blackout.clone(), from)?;
+        let strategy = cfg.updates.strategy.clone();
+        let policy = match strategy.as_str() {
+            "" | StrategyImmediate::LABEL => FinalizePolicy::Immediate,
+            FLEET_LOCK_LABEL => FinalizePolicy::FleetLock { slots: 1 },
+            StrategyPeriodic::LABEL => {
+                let periodic = StrategyPeriodic::new(cfg.updates)?;
+                FinalizePolicy::Periodic(periodic)
+            }
+            x => anyhow::bail!("unsupported strategy '{}'", x),
+        };
+
+        let replay = StrategyReplay {
+            policy,
+            blackout,
+            from,
+            to,
+        };
+        let spans = replay.run()?;
+
+        let mut label = match strategy.as_str() {
+            "" => StrategyImmediate::LABEL.to_string(),
+            x => x.to_string(),
+        };
+        if strategy == FLEET_LOCK_LABEL {
+            label.push_str(" (stubbed, lock always granted)");
+        }
+        println!("Strategy: {}", label);
+        println!(
+            "Reboots allowed between {} and {}:",
+            from.format("%a %Y-%m-%d %H:%M %Z"),
+            to.format("%a %Y-%m-%d %H:%M %Z")
+        );
+        print!("{}", human_replay_summary(&spans));
+        Ok(())
+    }
+}
+
+/// Parse a RFC 3339 timestamp or a `YYYY-MM-DD` date (midnight, UTC).
+fn parse_datetime(input: &str) -> Result<DateTime<Utc>> {
+    let input = input.trim();
+    if let Ok(date) = NaiveDate::parse_from_str(input, "%Y-%m-%d") {
+        let midnight = date.and_hms_opt(0, 0, 0).expect("valid midnight");
+        return Ok(Utc.from_utc_datetime(&midnight));
+    }
+    let datetime = DateTime::parse_from_rfc3339(input)
+        .with_context(|| format!("invalid date or timestamp '{}'", input))?;
+    Ok(datetime.with_timezone(&Utc))
+}
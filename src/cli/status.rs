@@ -0,0 +1,207 @@
+//! Logic for the `status` subcommand.
+
+use super::ensure_user;
+use super::ex::ExperimentalProxy;
+use crate::update_agent::AgentStatus;
+use anyhow::Result;
+use chrono::{TimeZone, Utc};
+use fn_error_context::context;
+use structopt::StructOpt;
+
+/// Options for the `status` subcommand.
+#[derive(Debug, StructOpt)]
+pub struct StatusOpts {
+    /// Print status as JSON.
+    #[structopt(long)]
+    json: bool,
+}
+
+impl StatusOpts {
+    /// `status` subcommand entry point.
+    #[context("failed to query update agent status")]
+    pub(crate) fn run(self) -> Result<()> {
+        ensure_user("root", "status subcommand must be run as `root` user")?;
+        let connection = zbus::Connection::new_system()?;
+        let proxy = ExperimentalProxy::new(&connection)?;
+        let status = proxy.get_full_status()?;
+
+        if self.json {
+            println!("{}", serde_json::to_string_pretty(&status)?);
+        } else {
+            print!("{}", render(&status));
+        }
+        Ok(())
+    }
+}
+
+/// Render agent status in human-readable form.
+fn render(status: &AgentStatus) -> String {
+    let mut rows = vec![
+        ("State", status.state.clone()),
+        (
+            "Booted",
+            release(&status.booted_version, &status.booted_checksum),
+        ),
+        (
+            "Target",
+            release(&status.target_version, &status.target_checksum),
+        ),
+        ("Update source", status.update_source.clone()),
+        ("Strategy", status.strategy.clone()),
+        ("Last check", timestamp(status.last_refresh_time)),
+        ("Next check", next_check(status)),
+        ("Last state change", timestamp(status.state_change_time)),
+        (
+            "Finalization",
+            or_none(&status.last_finalize_verdict).to_string(),
+        ),
+        (
+            "Postponements left",
+            status.postponements_remaining.to_string(),
+        ),
+    ];
+    if !status.inhibitors.is_empty() {
+        rows.push(("Inhibited by", status.inhibitors.join(", ")));
+    }
+    if !status.target_severity.is_empty() {
+        rows.push(("Severity", status.target_severity.clone()));
+    }
+    if !status.target_release_notes.is_empty() {
+        rows.push(("Release notes", status.target_release_notes.clone()));
+    }
+    if !status.target_errata.is_empty() {
+        rows.push(("Errata", status.target_errata.join(", ")));
+    }
+    if !status.fixed_cves.is_empty() {
+        let cves = format!(
+            "{} ({})",
+            status.fixed_cves.len(),
+            status.fixed_cves.join(", ")
+        );
+        rows.push(("Fixed CVEs", cves));
+    }
+    if status.downgrade {
+        rows.push(("Downgrade", "yes".to_string()));
+    }
+    if status.scheduled_finalize_time != 0 {
+        rows.push((
+            "Scheduled reboot",
+            timestamp(status.scheduled_finalize_time),
+        ));
+    }
+    if status.estimated_reboot_time != 0 {
+        rows.push(("Estimated reboot", timestamp(status.estimated_reboot_time)));
+    }
+    if status.blackout_end_time != 0 {
+        let mut blackout = format!("until {}", timestamp(status.blackout_end_time));
+        if !status.blackout_reason.is_empty() {
+            blackout.push_str(&format!(" ({})", status.blackout_reason));
+        }
+        rows.push(("Reboot blackout", blackout));
+    }
+    if !status.last_error.is_empty() {
+        rows.push((
+            "Last error",
+            format!(
+                "{}: {}",
+                timestamp(status.last_error_time),
+                status.last_error
+            ),
+        ));
+    }
+
+    let width = rows.iter().map(|(k, _)| k.len()).max().unwrap_or(0) + 1;
+    rows.into_iter()
+        .map(|(k, v)| format!("{:width$} {}\n", format!("{}:", k), v, width = width))
+        .collect()
+}
+
+/// Render a release, from its version and checksum.
+fn release(version: &str, checksum: &str) -> String {
+    match (version, checksum) {
+        ("", "") => "none".to_string(),
+        (v, "") => v.to_string(),
+        (v, c) => format!("{} ({})", or_none(v), c),
+    }
+}
+
+/// Render a UTC timestamp (seconds since epoch), with `0` meaning unset.
+pub(super) fn timestamp(ts: i64) -> String {
+    match Utc.timestamp_opt(ts, 0).single() {
+        Some(dt) if ts != 0 => dt.format("%a %Y-%m-%d %H:%M:%S %Z").to_string(),
+        _ => "never".to_string(),
+    }
+}
+
+/// Render the time of the next refresh tick, with its reason.
+fn next_check(status: &AgentStatus) -> String {
+    if status.next_refresh_time == 0 {
+        return "not scheduled".to_string();
+    }
+    let mut next = timestamp(status.next_refresh_time);
+    if !status.next_refresh_reason.is_empty() {
+        next.push_str(&format!(" ({})", status.next_refresh_reason));
+    }
+    next
+}
+
+/// Render an empty value as `none`.
+fn or_none(value: &str) -> &str {
+    match value {
+        "" => "none",
+        v => v,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let status = AgentStatus {
+            state: "UpdateStaged".to_string(),
+            booted_version: "34.20210626.3.1".to_string(),
+            booted_checksum: "aaaa".to_string(),
+            target_version: "34.20210711.3.0".to_string(),
+            target_checksum: "bbbb".to_string(),
+            update_source: "cincinnati".to_string(),
+            strategy: "immediate".to_string(),
+            last_finalize_verdict: "user-sessions".to_string(),
+            last_refresh_time: 1_626_000_000,
+            next_refresh_time: 1_626_000_300,
+            next_refresh_reason: "UpdateStaged state, jitter 12s".to_string(),
+            postponements_remaining: 3,
+            last_error: "failed to stage".to_string(),
+            last_error_time: 1_625_999_000,
+            target_severity: "important".to_string(),
+            fixed_cves: vec!["CVE-2021-33909".to_string(), "CVE-2021-33910".to_string()],
+            ..Default::default()
+        };
+        let out = render(&status);
+        assert!(
+            out.contains("State:              UpdateStaged\n"),
+            "{}",
+            out
+        );
+        assert!(out.contains("Target:             34.20210711.3.0 (bbbb)\n"));
+        assert!(out.contains("Last check:         Sun 2021-07-11 10:40:00 UTC\n"));
+        assert!(out.contains(
+            "Next check:         Sun 2021-07-11 10:45:00 UTC (UpdateStaged state, jitter 12s)\n"
+        ));
+        assert!(out.contains("Last state change:  never\n"));
+        assert!(out.contains("Finalization:       user-sessions\n"));
+        assert!(out.contains("Postponements left: 3\n"));
+        assert!(out.contains("Sun 2021-07-11 10:23:20 UTC: failed to stage\n"));
+        assert!(out.contains("Severity:           important\n"));
+        assert!(out.contains("Fixed CVEs:         2 (CVE-2021-33909, CVE-2021-33910)\n"));
+        assert!(!out.contains("Release notes"));
+        assert!(!out.contains("Reboot blackout"));
+
+        let idle = AgentStatus::default();
+        let out = render(&idle);
+        assert!(out.contains("Target:             none\n"), "{}", out);
+        assert!(out.contains("Next check:         not scheduled\n"));
+        assert!(!out.contains("Last error"));
+    }
+}
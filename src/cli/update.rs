@@ -0,0 +1,112 @@
+//! Logic for the `check-update`, `finalize`, `hold` and `unhold` subcommands.
+//!
+//! These are thin wrappers around the agent D-Bus interface, with exit
+//! codes meant for scripts.
+
+use super::ensure_user;
+use super::ex::ExperimentalProxy;
+use super::status::timestamp;
+use anyhow::Result;
+use fn_error_context::context;
+
+/// Exit code for `check-update`, when an update is available.
+const EXIT_UPDATE_AVAILABLE: i32 = 100;
+
+/// Exit code for `finalize`, when no update is staged.
+const EXIT_NO_UPDATE_STAGED: i32 = 2;
+
+/// Delay before finalizing, when requested via `finalize` (in seconds).
+const FINALIZE_DELAY_SECS: i64 = 1;
+
+/// `check-update` subcommand entry point.
+#[context("failed to check for updates")]
+pub(crate) fn check_update() -> Result<i32> {
+    ensure_user("root", "check-update subcommand must be run as `root` user")?;
+    let connection = zbus::Connection::new_system()?;
+    let proxy = ExperimentalProxy::new(&connection)?;
+    let update = proxy.check_update()?;
+    if update.version.is_empty() {
+        println!(
+            "no update available (last check: {})",
+            timestamp(update.last_refresh_time)
+        );
+        let status = proxy.get_full_status()?;
+        if !status.inhibitors.is_empty() {
+            println!("auto-updates not running: {}", status.inhibitors.join(", "));
+        }
+        return Ok(libc::EXIT_SUCCESS);
+    }
+
+    println!("update available: {} ({})", update.version, update.state);
+    if !update.severity.is_empty() {
+        println!("severity: {}", update.severity);
+    }
+    if !update.release_notes.is_empty() {
+        println!("release notes: {}", update.release_notes);
+    }
+    if !update.errata.is_empty() {
+        println!("errata: {}", update.errata.join(", "));
+    }
+    Ok(EXIT_UPDATE_AVAILABLE)
+}
+
+/// `finalize` subcommand entry point.
+#[context("failed to request update finalization")]
+pub(crate) fn finalize() -> Result<i32> {
+    ensure_user("root", "finalize subcommand must be run as `root` user")?;
+    let connection = zbus::Connection::new_system()?;
+    let proxy = ExperimentalProxy::new(&connection)?;
+    let status = proxy.get_full_status()?;
+    match status.state.as_str() {
+        "UpdateStaged" => {}
+        "UpdateFinalized" | "EndState" => {
+            println!(
+                "update {} already finalized, waiting for reboot",
+                status.target_version
+            );
+            return Ok(libc::EXIT_SUCCESS);
+        }
+        _ => {
+            println!("no update staged");
+            return Ok(EXIT_NO_UPDATE_STAGED);
+        }
+    }
+
+    // Finalization is requested as a one-time scheduled finalization, which
+    // overrides the update strategy but is still subject to other checks.
+    let finalize_at = chrono::Utc::now().timestamp() + FINALIZE_DELAY_SECS;
+    proxy.schedule_finalize(finalize_at)?;
+    println!(
+        "finalization of update {} requested, reboot expected on the next agent refresh",
+        status.target_version
+    );
+    Ok(libc::EXIT_SUCCESS)
+}
+
+/// `hold` subcommand entry point.
+#[context("failed to hold updates")]
+pub(crate) fn hold() -> Result<()> {
+    ensure_user("root", "hold subcommand must be run as `root` user")?;
+    let connection = zbus::Connection::new_system()?;
+    let proxy = ExperimentalProxy::new(&connection)?;
+    let version = proxy.hold()?;
+    println!(
+        "node held on {}, updates will be reported but not applied",
+        version
+    );
+    Ok(())
+}
+
+/// `unhold` subcommand entry point.
+#[context("failed to release update hold")]
+pub(crate) fn unhold() -> Result<()> {
+    ensure_user("root", "unhold subcommand must be run as `root` user")?;
+    let connection = zbus::Connection::new_system()?;
+    let proxy = ExperimentalProxy::new(&connection)?;
+    if proxy.unhold()? {
+        println!("update hold released");
+    } else {
+        println!("no update hold in place");
+    }
+    Ok(())
+}
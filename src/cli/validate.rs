@@ -0,0 +1,69 @@
+//! Logic for the `validate-config` subcommand.
+
+use crate::config::{self, provenance::Provenance, Settings};
+use anyhow::{Context, Result};
+use fn_error_context::context;
+
+/// `validate-config` subcommand entry point.
+///
+/// This reads all configuration fragments, prints the effective merged
+/// configuration, and validates it without starting the agent.
+#[context("configuration is not valid")]
+pub(crate) fn validate_config() -> Result<()> {
+    let (cfg, provenance) = config::read_inputs_with_provenance()?;
+
+    print_fragments(&provenance);
+    let effective = toml::Value::try_from(&cfg)
+        .and_then(|v| toml::to_string(&v))
+        .context("failed to render effective configuration")?;
+    println!("\nEffective configuration:\n{}", effective);
+    print_sources(&provenance);
+
+    Settings::validate(cfg)?;
+    println!("\nconfiguration is valid");
+    Ok(())
+}
+
+/// Print configuration fragments in merge order, warning about unknown keys.
+fn print_fragments(provenance: &Provenance) {
+    if provenance.fragments.is_empty() {
+        println!("No configuration fragments found, using defaults.");
+        return;
+    }
+
+    println!("Configuration fragments (in merge order):");
+    for frag in &provenance.fragments {
+        println!("  {}", frag.path.display());
+    }
+    for frag in &provenance.fragments {
+        for key in &frag.unknown {
+            eprintln!(
+                "warning: unknown key '{}' in '{}', ignored",
+                key,
+                frag.path.display()
+            );
+        }
+    }
+}
+
+/// Print where each key set by fragments comes from.
+fn print_sources(provenance: &Provenance) {
+    let sources = provenance.sources();
+    if sources.is_empty() {
+        return;
+    }
+
+    println!("Keys set by fragments:");
+    for (key, paths) in sources {
+        let (last, overridden) = match paths.split_last() {
+            Some(split) => split,
+            None => continue,
+        };
+        let mut line = format!("  {} = {}", key, last.display());
+        if !overridden.is_empty() {
+            let earlier: Vec<_> = overridden.iter().map(|p| p.display().to_string()).collect();
+            line.push_str(&format!(" (overrides {})", earlier.join(", ")));
+        }
+        println!("{}", line);
+    }
+}
@@ -0,0 +1,408 @@
+//! Logic for the `why` subcommand.
+//!
+//! This runs the update decision pipeline once, in read-only mode (no
+//! deployments, locks or dead-end MOTD changes), and reports every gate an
+//! update has to pass, pinpointing the first failing one.
+
+use crate::cincinnati::{self, Cincinnati, CincinnatiError};
+use crate::config::{self, Settings};
+use crate::rpm_ostree::{self, Release};
+use crate::strategy::{UpdateStrategy, FLEET_LOCK_LABEL};
+use crate::update_agent::{
+    cmdline_inhibits_updates, TemporaryBlackout, UpdateHold, KERNEL_CMDLINE_PATH,
+    TEMPORARY_BLACKOUT_PATH, UPDATE_HOLD_PATH,
+};
+use anyhow::Result;
+use fn_error_context::context;
+use std::collections::BTreeSet;
+
+/// Exit code for `why`, when a gate fails.
+const EXIT_GATE_FAILED: i32 = 2;
+
+/// Outcome of a gate.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Outcome {
+    /// The gate lets updates through.
+    Pass,
+    /// The gate blocks updates.
+    Fail,
+    /// The gate cannot be evaluated in read-only mode.
+    Unknown,
+    /// The gate was not evaluated, as an earlier one failed.
+    Skipped,
+}
+
+impl Outcome {
+    /// Return a short label for this outcome.
+    fn label(self) -> &'static str {
+        match self {
+            Outcome::Pass => "pass",
+            Outcome::Fail => "FAIL",
+            Outcome::Unknown => "????",
+            Outcome::Skipped => "skip",
+        }
+    }
+}
+
+/// A step of the update decision pipeline.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Gate {
+    /// Gate name, in human terms.
+    name: &'static str,
+    /// Gate outcome.
+    outcome: Outcome,
+    /// Details about the outcome, in human terms.
+    details: String,
+}
+
+impl Gate {
+    fn new(name: &'static str, outcome: Outcome, details: impl Into<String>) -> Self {
+        Self {
+            name,
+            outcome,
+            details: details.into(),
+        }
+    }
+}
+
+/// Inputs for gates evaluation, gathered from the host.
+#[derive(Debug)]
+struct Inputs {
+    /// Validated agent settings.
+    settings: Settings,
+    /// Cincinnati client, if that is the update source.
+    cincinnati: Option<Cincinnati>,
+    /// Persistent (i.e. finalized) local deployments.
+    deployments: BTreeSet<Release>,
+    /// Whether the kernel command-line inhibits auto-updates.
+    kernel_inhibited: bool,
+    /// Temporary blackout, if any.
+    temporary_blackout: Option<TemporaryBlackout>,
+    /// Hold on the booted release, if any.
+    hold: Option<UpdateHold>,
+}
+
+/// `why` subcommand entry point.
+#[context("failed to analyze update decisions")]
+pub(crate) fn why() -> Result<i32> {
+    let cfg = config::read_inputs()?;
+    let cincinnati_cfg = cfg.cincinnati.clone();
+    let source_label = cfg.updates.source.clone();
+    let skip_versions = cfg.updates.skip_versions.clone();
+    let settings = Settings::validate(cfg)?;
+    let cincinnati = match source_label.as_str() {
+        Cincinnati::LABEL | "" => Some(Cincinnati::with_config(
+            cincinnati_cfg,
+            &settings.identity,
+            &settings.network,
+            &skip_versions,
+        )?),
+        _ => None,
+    };
+    let status = rpm_ostree::invoke_cli_status(false)?;
+    let deployments = rpm_ostree::parse_local_deployments(&status, true);
+    let kernel_inhibited = std::fs::read_to_string(KERNEL_CMDLINE_PATH)
+        .map(|cmdline| cmdline_inhibits_updates(&cmdline))
+        .unwrap_or(false);
+    let temporary_blackout = TemporaryBlackout::load(TEMPORARY_BLACKOUT_PATH).unwrap_or_else(|e| {
+        log::warn!("{:#}", e);
+        None
+    });
+    let hold = UpdateHold::load(UPDATE_HOLD_PATH).unwrap_or_else(|e| {
+        log::warn!("{:#}", e);
+        None
+    });
+
+    let inputs = Inputs {
+        settings,
+        cincinnati,
+        deployments,
+        kernel_inhibited,
+        temporary_blackout,
+        hold,
+    };
+    let gates = actix::System::new().block_on(evaluate(inputs));
+
+    print!("{}", render(&gates));
+    println!("\n{}", summary(&gates));
+    if gates.iter().any(|g| g.outcome == Outcome::Fail) {
+        return Ok(EXIT_GATE_FAILED);
+    }
+    Ok(libc::EXIT_SUCCESS)
+}
+
+/// Evaluate all gates, in pipeline order.
+///
+/// Gates after a failing one are skipped.
+async fn evaluate(inputs: Inputs) -> Vec<Gate> {
+    let settings = &inputs.settings;
+    let booted = settings.identity.current_os.clone();
+    let mut gates = vec![Gate::new(
+        "booted release",
+        Outcome::Pass,
+        booted.version.clone(),
+    )];
+
+    match &inputs.cincinnati {
+        Some(c) => {
+            gates.extend(graph_gates(c, settings, &booted, inputs.deployments.clone()).await);
+        }
+        None => {
+            let target = settings
+                .source
+                .fetch_update_hint(
+                    &settings.identity,
+                    inputs.deployments.clone(),
+                    settings.allow_downgrade,
+                )
+                .await;
+            let source = settings.source.label();
+            let details = format!("update source '{}' has no graph", source);
+            gates.push(Gate::new("graph reachable", Outcome::Unknown, details));
+            gates.push(candidate_gate(target.as_ref(), source));
+        }
+    }
+
+    gates.push(strategy_gate(&settings.strategy));
+    gates.push(inhibitors_gate(&inputs));
+
+    // Only the first failure is meaningful, later gates are not reached.
+    if let Some(pos) = gates.iter().position(|g| g.outcome == Outcome::Fail) {
+        for gate in gates.iter_mut().skip(pos + 1) {
+            gate.outcome = Outcome::Skipped;
+        }
+    }
+    gates
+}
+
+/// Evaluate Cincinnati graph gates: graph reachable, candidate found,
+/// not dead-end and wariness passed.
+async fn graph_gates(
+    cincinnati: &Cincinnati,
+    settings: &Settings,
+    booted: &Release,
+    deployments: BTreeSet<Release>,
+) -> Vec<Gate> {
+    let eager = match fetch_and_inspect(cincinnati, settings, booted, &deployments, true).await {
+        Ok(inspection) => inspection,
+        Err(e) => {
+            let details = format!("{} ({})", e, cincinnati.base_url);
+            return vec![Gate::new("graph reachable", Outcome::Fail, details)];
+        }
+    };
+
+    let mut gates = vec![Gate::new(
+        "graph reachable",
+        Outcome::Pass,
+        cincinnati.base_url.clone(),
+    )];
+    if !eager.booted_in_graph {
+        let details = format!("booted release {} not found in graph", booted.version);
+        gates.push(Gate::new("candidate found", Outcome::Fail, details));
+        return gates;
+    }
+    if let Some(rejected) = &eager.rejected_downgrade {
+        let details = format!(
+            "only candidate {} is a downgrade, not allowed by configuration",
+            rejected.version
+        );
+        gates.push(Gate::new("candidate found", Outcome::Fail, details));
+        return gates;
+    }
+    if let (None, Some(skipped)) = (&eager.target, &eager.skipped) {
+        let details = format!("{} is deny-listed by configuration", skipped.version);
+        gates.push(Gate::new("candidate found", Outcome::Fail, details));
+        return gates;
+    }
+    if let (None, Some(recent)) = (&eager.target, &eager.too_recent) {
+        let details = format!(
+            "{} is younger than the minimum release age ({} hours)",
+            recent.version,
+            settings.identity.min_release_age_hours.unwrap_or_default()
+        );
+        gates.push(Gate::new("candidate found", Outcome::Fail, details));
+        return gates;
+    }
+    gates.push(candidate_gate(eager.target.as_ref(), Cincinnati::LABEL));
+    match &eager.deadend_reason {
+        Some(reason) => gates.push(Gate::new("not dead-end", Outcome::Fail, reason.clone())),
+        None => gates.push(Gate::new("not dead-end", Outcome::Pass, "")),
+    };
+
+    // The eager graph ignores phased rollouts; the graph as seen by this
+    // node tells whether the candidate rollout reached its wariness.
+    let wariness = settings
+        .identity
+        .rollout_wariness
+        .map(|rw| format!("wariness {:.06}", rw))
+        .unwrap_or_else(|| "server-side wariness".to_string());
+    let gate = match fetch_and_inspect(cincinnati, settings, booted, &deployments, false).await {
+        Ok(inspection) => match (&eager.target, inspection.target) {
+            (Some(_), Some(target)) => Gate::new(
+                "wariness passed",
+                Outcome::Pass,
+                format!("{} rolled out at {}", target.version, wariness),
+            ),
+            (Some(candidate), None) => Gate::new(
+                "wariness passed",
+                Outcome::Fail,
+                format!("{} not yet rolled out at {}", candidate.version, wariness),
+            ),
+            (None, _) => Gate::new("wariness passed", Outcome::Skipped, ""),
+        },
+        Err(e) => Gate::new("wariness passed", Outcome::Fail, e.to_string()),
+    };
+    gates.push(gate);
+    gates
+}
+
+/// Fetch the graph (either eager or as seen by this node) and inspect it.
+async fn fetch_and_inspect(
+    cincinnati: &Cincinnati,
+    settings: &Settings,
+    booted: &Release,
+    deployments: &BTreeSet<Release>,
+    eager: bool,
+) -> Result<cincinnati::GraphInspection, CincinnatiError> {
+    let graph = cincinnati.fetch_graph(&settings.identity, eager).await?;
+    cincinnati::inspect_graph(
+        &graph,
+        booted,
+        deployments.clone(),
+        settings.allow_downgrade,
+        settings.identity.min_release_age(),
+        &cincinnati.skip_versions,
+        cincinnati.policy,
+    )
+}
+
+/// Evaluate whether an update candidate was found.
+fn candidate_gate(target: Option<&Release>, source: &str) -> Gate {
+    match target {
+        Some(release) => Gate::new("candidate found", Outcome::Pass, release.version.clone()),
+        None => Gate::new(
+            "candidate found",
+            Outcome::Fail,
+            format!("no update available from {}", source),
+        ),
+    }
+}
+
+/// Evaluate whether the update strategy allows finalization now.
+fn strategy_gate(strategy: &UpdateStrategy) -> Gate {
+    let name = "strategy allows";
+    if strategy.configuration_label() == FLEET_LOCK_LABEL {
+        let details = "reboot slots are not requested from the lock manager in read-only mode";
+        return Gate::new(name, Outcome::Unknown, details);
+    }
+    match strategy.remaining_to_window(&chrono::Utc::now()) {
+        Some(remaining) if remaining <= chrono::Duration::zero() => {
+            Gate::new(name, Outcome::Pass, strategy.human_description())
+        }
+        Some(_) => Gate::new(name, Outcome::Fail, strategy.human_description()),
+        None => Gate::new(name, Outcome::Fail, "no reachable finalization windows"),
+    }
+}
+
+/// Evaluate whether anything inhibits auto-updates or reboots.
+fn inhibitors_gate(inputs: &Inputs) -> Gate {
+    let now = chrono::Utc::now();
+    let mut inhibitors = vec![];
+    if !inputs.settings.enabled {
+        inhibitors.push("auto-updates disabled by configuration".to_string());
+    }
+    if inputs.kernel_inhibited {
+        inhibitors.push("auto-updates inhibited via kernel argument".to_string());
+    }
+    if let Some(hold) = &inputs.hold {
+        inhibitors.push(hold.describe());
+    }
+    if let Some(blackout) = &inputs.temporary_blackout {
+        if blackout.is_active(&now) {
+            inhibitors.push(blackout.describe());
+        }
+    }
+    if let Some(period) = inputs
+        .settings
+        .blackout
+        .as_ref()
+        .and_then(|b| b.active(&now))
+    {
+        inhibitors.push(period.describe());
+    }
+
+    if inhibitors.is_empty() {
+        Gate::new("inhibitors clear", Outcome::Pass, "")
+    } else {
+        Gate::new("inhibitors clear", Outcome::Fail, inhibitors.join("; "))
+    }
+}
+
+/// Summarize gates outcomes, pinpointing the first failing gate.
+fn summary(gates: &[Gate]) -> String {
+    if let Some(failed) = gates.iter().find(|g| g.outcome == Outcome::Fail) {
+        return format!("first failing gate: {}", failed.name);
+    }
+    let unknown: Vec<_> = gates
+        .iter()
+        .filter(|g| g.outcome == Outcome::Unknown)
+        .map(|g| g.name)
+        .collect();
+    if unknown.is_empty() {
+        "all gates passed".to_string()
+    } else {
+        format!("no failing gates, not evaluated: {}", unknown.join(", "))
+    }
+}
+
+/// Render gates as a tree, in pipeline order.
+fn render(gates: &[Gate]) -> String {
+    let mut out = String::new();
+    for (depth, gate) in gates.iter().enumerate() {
+        let indent = "   ".repeat(depth);
+        let branch = if depth == 0 { "" } else { "└─ " };
+        out.push_str(&format!(
+            "{}{}[{}] {}",
+            indent,
+            branch,
+            gate.outcome.label(),
+            gate.name
+        ));
+        if !gate.details.is_empty() {
+            out.push_str(&format!(": {}", gate.details));
+        }
+        out.push('\n');
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let gates = vec![
+            Gate::new("booted release", Outcome::Pass, "34.20210626.3.1"),
+            Gate::new("graph reachable", Outcome::Pass, "https://example.com/"),
+            Gate::new("candidate found", Outcome::Fail, "no update available"),
+            Gate::new("not dead-end", Outcome::Skipped, ""),
+        ];
+        let expected = "[pass] booted release: 34.20210626.3.1
+   └─ [pass] graph reachable: https://example.com/
+      └─ [FAIL] candidate found: no update available
+         └─ [skip] not dead-end
+";
+        assert_eq!(render(&gates), expected);
+        assert_eq!(summary(&gates), "first failing gate: candidate found");
+
+        let gates = vec![
+            Gate::new("candidate found", Outcome::Pass, "34.20210711.3.0"),
+            Gate::new("strategy allows", Outcome::Unknown, ""),
+        ];
+        assert_eq!(
+            summary(&gates),
+            "no failing gates, not evaluated: strategy allows"
+        );
+    }
+}
@@ -1,98 +1,397 @@
 //! TOML configuration fragments.
 
 use ordered_float::NotNan;
-use serde::Deserialize;
-use std::collections::BTreeSet;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
 use std::num::NonZeroU64;
 
 /// Top-level configuration stanza.
-#[derive(Debug, Deserialize, PartialEq, Eq)]
-pub(crate) struct ConfigFragment {
+#[derive(Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct ConfigFragment {
     /// Agent configuration.
-    pub(crate) agent: Option<AgentFragment>,
+    pub agent: Option<AgentFragment>,
     /// Cincinnati client configuration.
-    pub(crate) cincinnati: Option<CincinnatiFragment>,
+    pub cincinnati: Option<CincinnatiFragment>,
     /// Agent identity.
-    pub(crate) identity: Option<IdentityFragment>,
+    pub identity: Option<IdentityFragment>,
+    /// User-facing messages.
+    pub messages: Option<MessagesFragment>,
+    /// Outbound network configuration.
+    pub network: Option<NetworkFragment>,
+    /// Metrics exporter configuration.
+    pub telemetry: Option<TelemetryFragment>,
     /// Update strategy configuration.
-    pub(crate) updates: Option<UpdateFragment>,
+    pub updates: Option<UpdateFragment>,
 }
 
 /// Config fragment for agent settings.
-#[derive(Debug, Deserialize, PartialEq, Eq)]
-pub(crate) struct AgentFragment {
+#[derive(Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct AgentFragment {
+    /// Whether to disable rpm-ostree automatic updates if they conflict (default: false).
+    pub reconcile_rpm_ostree_policy: Option<bool>,
+    /// Backend used to interact with rpm-ostree (default: cli).
+    pub rpm_ostree_backend: Option<String>,
+    /// Maximum number of queued rpm-ostree operations (default: 8).
+    pub rpm_ostree_max_queued: Option<NonZeroU64>,
     /// Timing settings for the agent.
-    pub(crate) timing: Option<AgentTiming>,
+    pub timing: Option<AgentTiming>,
 }
 
 /// Config fragment for agent timing.
-#[derive(Debug, Deserialize, PartialEq, Eq)]
-pub(crate) struct AgentTiming {
+#[derive(Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct AgentTiming {
     /// Pausing interval between updates checks in steady mode, in seconds (default: 300).
-    pub(crate) steady_interval_secs: Option<NonZeroU64>,
+    pub steady_interval_secs: Option<NonZeroU64>,
+    /// Interval between self-tests of control surfaces, in seconds (default: 600, 0 to disable).
+    pub self_test_interval_secs: Option<u64>,
+    /// Timeout for staging, downloading and rebasing via rpm-ostree, in seconds (default: 3600).
+    pub rpm_ostree_stage_timeout_secs: Option<NonZeroU64>,
+    /// Timeout for finalizing via rpm-ostree, in seconds (default: 600).
+    pub rpm_ostree_finalize_timeout_secs: Option<NonZeroU64>,
+    /// Timeout for rpm-ostree status queries, in seconds (default: 300).
+    pub rpm_ostree_status_timeout_secs: Option<NonZeroU64>,
+    /// Maximum delay before retrying a failed deploy, in seconds (default: 3600).
+    pub deploy_retry_max_delay_secs: Option<NonZeroU64>,
+    /// Maximum random delay before the first update check after start, in seconds (default: 0).
+    pub first_check_splay_secs: Option<u64>,
 }
 
-// Config fragment for agent identity.
-#[derive(Debug, Deserialize, PartialEq, Eq)]
-pub(crate) struct IdentityFragment {
+/// Config fragment for agent identity.
+#[derive(Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct IdentityFragment {
     /// Update group for this agent (default: 'default')
-    pub(crate) group: Option<String>,
+    pub group: Option<String>,
     /// Update group for this agent (default: derived from machine-id)
-    pub(crate) node_uuid: Option<String>,
+    pub node_uuid: Option<String>,
     /// Update group for this agent (default: derived server-side)
-    pub(crate) rollout_wariness: Option<NotNan<f64>>,
+    pub rollout_wariness: Option<NotNan<f64>>,
+    /// Minimum age of releases to consider them as update targets, in hours (default: none)
+    pub min_release_age_hours: Option<NonZeroU64>,
+    /// Base architecture override, for testing (default: detected from booted deployment)
+    pub basearch: Option<String>,
+    /// Identity providers, in order (default: config)
+    pub providers: Option<Vec<String>>,
+    /// Custom identity parameters (default: none)
+    pub parameters: Option<BTreeMap<String, String>>,
+    /// Extra client parameters forwarded to Cincinnati (default: none)
+    pub extra_params: Option<BTreeMap<String, String>>,
 }
 
 /// Config fragment for Cincinnati client.
-#[derive(Debug, Deserialize, PartialEq, Eq)]
-pub(crate) struct CincinnatiFragment {
+#[derive(Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct CincinnatiFragment {
     /// Base URL to upstream cincinnati server.
-    pub(crate) base_url: Option<String>,
+    pub base_url: Option<String>,
+    /// Policy for selecting among multiple update targets (default: newest).
+    pub target_policy: Option<String>,
+}
+
+/// Config fragment for user-facing messages.
+#[derive(Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct MessagesFragment {
+    /// Template for reboot warnings.
+    pub reboot_warning: Option<String>,
+    /// Template for finalization postponement notices.
+    pub postponement_notice: Option<String>,
+    /// Template for the dead-end release MOTD.
+    pub deadend_motd: Option<String>,
+}
+
+/// Config fragment for outbound network settings.
+#[derive(Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct NetworkFragment {
+    /// Proxy URL for HTTP connections.
+    pub http_proxy: Option<String>,
+    /// Proxy URL for HTTPS connections.
+    pub https_proxy: Option<String>,
+    /// Hosts and domains which bypass proxies.
+    pub no_proxy: Option<Vec<String>>,
+    /// Whether to defer fetching updates on metered connections (default: false).
+    pub respect_metered: Option<bool>,
+    /// TLS settings for HTTPS connections.
+    pub tls: Option<NetworkTls>,
+}
+
+/// Config fragment for TLS settings.
+#[derive(Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct NetworkTls {
+    /// Path to additional CA certificates to trust (PEM bundle).
+    pub ca_bundle: Option<String>,
+    /// Path to client certificate (PEM).
+    pub client_cert: Option<String>,
+    /// Path to client private key (PEM).
+    pub client_key: Option<String>,
+}
+
+/// Config fragment for the metrics exporter over TCP.
+#[derive(Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct TelemetryFragment {
+    /// Address to listen on for metrics scrapes (default: none, TCP exporter disabled).
+    pub listen_address: Option<String>,
+    /// Path to a file with basic auth credentials (`user:password`).
+    pub basic_auth_file: Option<String>,
+    /// Path to server certificate (PEM).
+    pub tls_cert: Option<String>,
+    /// Path to server private key (PEM).
+    pub tls_key: Option<String>,
+    /// Path to CA certificates for authenticating clients (PEM bundle).
+    pub tls_client_ca: Option<String>,
 }
 
 /// Config fragment for update logic.
-#[derive(Debug, Deserialize, PartialEq, Eq)]
-pub(crate) struct UpdateFragment {
+#[derive(Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct UpdateFragment {
     /// Whether to enable automatic downgrades.
-    pub(crate) allow_downgrade: Option<bool>,
+    pub allow_downgrade: Option<bool>,
     /// Whether to enable auto-updates logic.
-    pub(crate) enabled: Option<bool>,
+    pub enabled: Option<bool>,
+    /// Whether to block updates when packages are layered on the booted deployment (default: false).
+    pub block_on_layered: Option<bool>,
+    /// Periods during which finalization is always denied.
+    pub blackout: Option<UpdateBlackout>,
+    /// Whether to check for updates as soon as the network comes online (default: false).
+    pub check_on_network_up: Option<bool>,
+    /// Healthy days on a new release before cleaning up the rollback deployment (default: 0, never).
+    pub cleanup_rollback_after_days: Option<u64>,
+    /// Connectivity gate for finalization.
+    pub connectivity_gate: Option<UpdateConnectivityGate>,
+    /// Windows for downloading updates (default: any time).
+    pub download: Option<UpdatePeriodic>,
+    /// Whether to defer staging until close to a finalization window (default: false).
+    pub fetch_only_window: Option<bool>,
+    /// Health checks to pass before finalization (`unit:<name>` or `command:<program>`).
+    pub finalize_health_checks: Option<Vec<String>>,
+    /// Maximum number of finalization postponements due to active user sessions (default: 10).
+    pub max_postponements: Option<u8>,
+    /// Delay between finalization postponements, in minutes (default: 1).
+    pub postponement_delay_minutes: Option<NonZeroU64>,
+    /// Hooks to run after booting into a finalized update (`command:<program>` or HTTP(S) URL).
+    pub post_boot_hooks: Option<Vec<String>>,
+    /// Countdown before rebooting into a finalized update, in minutes (default: 0, disabled).
+    pub reboot_countdown_minutes: Option<u64>,
+    /// Lock file shared with other reboot managers (default: none).
+    pub reboot_lock_path: Option<String>,
+    /// Whether to require an explicit approval before finalization (default: false).
+    pub require_reboot_approval: Option<bool>,
+    /// Release versions never to be selected as update targets (default: none).
+    pub skip_versions: Option<Vec<String>>,
+    /// Update source (default: cincinnati).
+    pub source: Option<String>,
+    /// Consecutive failures to report steady state before polling for updates
+    /// in degraded mode (default: 0, never).
+    pub steady_report_max_failures: Option<u32>,
     /// Update strategy (default: immediate).
-    pub(crate) strategy: Option<String>,
+    pub strategy: Option<String>,
+    /// OSTree remote to check for the target release before fetching it (default: none).
+    pub verify_remote: Option<String>,
+    /// Whether to verify the signature of the target release on `verify_remote` (default: false).
+    pub verify_signature: Option<bool>,
+    /// Free disk space checks before staging.
+    pub disk_space: Option<UpdateDiskSpace>,
+    /// Barriers against downgrading too far back.
+    pub downgrade_barrier: Option<UpdateDowngradeBarrier>,
     /// `fleet_lock` strategy config.
-    pub(crate) fleet_lock: Option<UpdateFleetLock>,
+    pub fleet_lock: Option<UpdateFleetLock>,
+    /// Reboots scheduled via systemd-logind.
+    pub logind_reboot: Option<UpdateLogindReboot>,
+    /// `ostree-remote` source config.
+    pub ostree_remote: Option<UpdateOstreeRemote>,
+    /// Reporting of update outcomes.
+    pub outcome_report: Option<UpdateOutcomeReport>,
     /// `periodic` strategy config.
-    pub(crate) periodic: Option<UpdatePeriodic>,
+    pub periodic: Option<UpdatePeriodic>,
+    /// Services stopped before finalization.
+    pub quiesce: Option<UpdateQuiesce>,
+    /// `static-graph` source config.
+    pub static_graph: Option<UpdateStaticGraph>,
+    /// Webhook for agent events.
+    pub webhook: Option<UpdateWebhook>,
+    /// Switching to other update streams.
+    pub stream_switch: Option<UpdateStreamSwitch>,
+    /// Overriding the update strategy for urgent releases.
+    pub urgency: Option<UpdateUrgency>,
+}
+
+/// Config fragment for finalization blackout periods.
+#[derive(Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct UpdateBlackout {
+    /// A blackout period.
+    pub period: Option<Vec<UpdateBlackoutPeriod>>,
+}
+
+/// Config fragment for a `blackout.period` entry.
+#[derive(Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct UpdateBlackoutPeriod {
+    /// Start of the period (RFC 3339 timestamp or `YYYY-MM-DD` date).
+    pub start: String,
+    /// End of the period (RFC 3339 timestamp, or inclusive `YYYY-MM-DD` date).
+    pub end: String,
+    /// Reason for the blackout, for humans.
+    pub reason: Option<String>,
+}
+
+/// Config fragment for the finalization connectivity gate.
+#[derive(Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct UpdateConnectivityGate {
+    /// Target to probe before finalization (`icmp://`, `tcp://` or `http(s)://`).
+    pub probe: Option<String>,
+    /// Timeout for a single probe, in seconds (default: 10).
+    pub timeout_secs: Option<NonZeroU64>,
+}
+
+/// Config fragment for free disk space checks before staging.
+#[derive(Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct UpdateDiskSpace {
+    /// Minimum free space on `/boot`, in MiB (default: 0, unchecked).
+    pub boot_min_free_mib: Option<u64>,
+    /// Command to reclaim disk space when below a threshold (default: none).
+    pub cleanup_command: Option<String>,
+    /// Minimum free space on the OSTree sysroot, in MiB (default: 0, unchecked).
+    pub sysroot_min_free_mib: Option<u64>,
+}
+
+/// Config fragment for barriers against downgrading too far back.
+#[derive(Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct UpdateDowngradeBarrier {
+    /// Minimum version of update targets (default: none).
+    pub min_version: Option<String>,
+    /// Maximum age of a staged commit relative to the booted one, in days (default: none).
+    pub max_age_days: Option<u64>,
+}
+
+/// Config fragment for reboots scheduled via systemd-logind.
+#[derive(Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct UpdateLogindReboot {
+    /// Whether to schedule reboots via logind (default: false).
+    pub enabled: Option<bool>,
+    /// Lead time between scheduling and reboot, in minutes (default: 10).
+    pub lead_time_minutes: Option<NonZeroU64>,
 }
 
 /// Config fragment for `fleet_lock` update strategy.
-#[derive(Debug, Deserialize, PartialEq, Eq)]
-pub(crate) struct UpdateFleetLock {
+#[derive(Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct UpdateFleetLock {
     /// Base URL for the remote semaphore manager.
-    pub(crate) base_url: Option<String>,
+    pub base_url: Option<String>,
+    /// Whether to confirm successful updates before unlocking (default: false).
+    pub confirm_updates: Option<bool>,
+    /// Whether to report a node health summary when unlocking (default: true).
+    pub report_health: Option<bool>,
+    /// Path to a bearer token for authentication (default: none).
+    pub token_path: Option<String>,
+    /// Path to a TLS client certificate (PEM), overriding the `network.tls` one.
+    pub client_cert: Option<String>,
+    /// Path to a TLS client private key (PEM), overriding the `network.tls` one.
+    pub client_key: Option<String>,
+    /// Reboot groups to lock, all at once (default: the node group).
+    pub groups: Option<Vec<String>>,
+}
+
+/// Config fragment for `ostree-remote` update source.
+#[derive(Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct UpdateOstreeRemote {
+    /// OSTree refspec to poll for updates (`remote:ref`).
+    pub refspec: Option<String>,
+}
+
+/// Config fragment for reporting of update outcomes.
+#[derive(Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct UpdateOutcomeReport {
+    /// URL of the reporting endpoint (default: no reporting).
+    pub url: Option<String>,
+    /// Maximum number of reports per request (default: 20).
+    pub batch_size: Option<NonZeroU64>,
+    /// Maximum number of queued reports (default: 100).
+    pub max_queued: Option<NonZeroU64>,
+    /// Maximum age of queued reports, in hours (default: 168).
+    pub max_age_hours: Option<u64>,
+}
+
+/// Config fragment for services stopped before finalization.
+#[derive(Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct UpdateQuiesce {
+    /// A unit to stop.
+    pub unit: Option<Vec<UpdateQuiesceUnit>>,
+}
+
+/// Config fragment for a `quiesce.unit` entry.
+#[derive(Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct UpdateQuiesceUnit {
+    /// Unit name.
+    pub name: String,
+    /// Whether to start the unit again if finalization is aborted (default: false).
+    pub restart_on_abort: Option<bool>,
+    /// Timeout for stopping the unit, in seconds (default: 90).
+    pub timeout_secs: Option<NonZeroU64>,
+}
+
+/// Config fragment for `static-graph` update source.
+#[derive(Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct UpdateStaticGraph {
+    /// Absolute path to the update graph (JSON).
+    pub path: Option<String>,
+}
+
+/// Config fragment for switching to other update streams.
+#[derive(Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct UpdateStreamSwitch {
+    /// Template for the OSTree refspec of a stream (`remote:ref`, with `${stream}`).
+    pub refspec: Option<String>,
+    /// Streams which can be switched to (default: any).
+    pub allowed_streams: Option<Vec<String>>,
+}
+
+/// Config fragment for overriding the update strategy for urgent releases.
+#[derive(Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct UpdateUrgency {
+    /// Whether to override the update strategy for urgent releases (default: false).
+    pub enabled: Option<bool>,
+    /// Minimum severity for a release to be urgent (default: critical).
+    pub min_severity: Option<String>,
+    /// Delay before overriding the update strategy, in minutes (default: 60).
+    pub grace_period_minutes: Option<u64>,
 }
 
 /// Config fragment for `periodic` update strategy.
-#[derive(Debug, Deserialize, PartialEq, Eq)]
-pub(crate) struct UpdatePeriodic {
+#[derive(Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct UpdatePeriodic {
     /// A weekly window.
-    pub(crate) window: Option<Vec<UpdatePeriodicWindow>>,
-    /// A time zone in the IANA Time Zone Database (https://www.iana.org/time-zones)
+    pub window: Option<Vec<UpdatePeriodicWindow>>,
+    /// A time zone in the IANA Time Zone Database (<https://www.iana.org/time-zones>)
     /// or "localtime". If unset, UTC is used.
     ///
     /// Examples: `America/Toronto`, `Europe/Rome`
-    pub(crate) time_zone: Option<String>,
+    pub time_zone: Option<String>,
+    /// Maximum delay after a window opens before considering it open, in minutes (default: 0).
+    pub window_jitter_minutes: Option<u32>,
+}
+
+/// Config fragment for the agent events webhook.
+#[derive(Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct UpdateWebhook {
+    /// Endpoint receiving agent events (default: none, webhook disabled).
+    pub url: Option<String>,
+    /// Maximum number of events queued while the endpoint is unreachable (default: 1000).
+    pub max_queued: Option<NonZeroU64>,
+    /// Maximum age of queued events, in hours (default: 168, zero for unbounded).
+    pub max_age_hours: Option<u64>,
 }
 
 /// Config fragment for a `periodic.window` entry.
-#[derive(Debug, Deserialize, PartialEq, Eq)]
-pub(crate) struct UpdatePeriodicWindow {
-    /// Weekdays (English names).
-    pub(crate) days: BTreeSet<String>,
+#[derive(Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct UpdatePeriodicWindow {
+    /// Weekdays (English names), or monthly weekdays (e.g. `first Sun`, `last Fri`).
+    pub days: Option<BTreeSet<String>>,
+    /// Days of the month.
+    pub days_of_month: Option<BTreeSet<u32>>,
+    /// Calendar dates (`YYYY-MM-DD`).
+    pub dates: Option<BTreeSet<String>>,
     /// Start time (`hh:mm` 24h format).
-    pub(crate) start_time: String,
+    pub start_time: String,
     /// Window length in minutes.
-    pub(crate) length_minutes: u32,
+    pub length_minutes: u32,
+    /// Time zone for this window, overriding the `periodic` one.
+    pub time_zone: Option<String>,
 }
 
 #[cfg(test)]
@@ -111,39 +410,206 @@ mod tests {
 
         let expected = ConfigFragment {
             agent: Some(AgentFragment {
+                reconcile_rpm_ostree_policy: Some(true),
+                rpm_ostree_backend: Some("dbus".to_string()),
+                rpm_ostree_max_queued: Some(NonZeroU64::new(4).unwrap()),
                 timing: Some(AgentTiming {
                     steady_interval_secs: Some(NonZeroU64::new(35).unwrap()),
+                    self_test_interval_secs: Some(120),
+                    rpm_ostree_stage_timeout_secs: Some(NonZeroU64::new(1800).unwrap()),
+                    rpm_ostree_finalize_timeout_secs: Some(NonZeroU64::new(300).unwrap()),
+                    rpm_ostree_status_timeout_secs: Some(NonZeroU64::new(60).unwrap()),
+                    deploy_retry_max_delay_secs: Some(NonZeroU64::new(21600).unwrap()),
+                    first_check_splay_secs: Some(900),
                 }),
             }),
             cincinnati: Some(CincinnatiFragment {
                 base_url: Some("http://cincinnati.example.com:80/".to_string()),
+                target_policy: Some("next-hop".to_string()),
             }),
             identity: Some(IdentityFragment {
                 group: Some("workers".to_string()),
                 node_uuid: Some("27e3ac02af3946af995c9940e18b0cce".to_string()),
                 rollout_wariness: Some(NotNan::new(0.5).unwrap()),
+                min_release_age_hours: Some(NonZeroU64::new(72).unwrap()),
+                basearch: Some("aarch64".to_string()),
+                providers: Some(vec!["afterburn".to_string(), "config".to_string()]),
+                parameters: Some(
+                    vec![("rack".to_string(), "r12".to_string())]
+                        .into_iter()
+                        .collect(),
+                ),
+                extra_params: Some(
+                    vec![
+                        ("datacenter".to_string(), "${zone}".to_string()),
+                        ("hw_model".to_string(), "r740".to_string()),
+                    ]
+                    .into_iter()
+                    .collect(),
+                ),
+            }),
+            messages: Some(MessagesFragment {
+                reboot_warning: Some("Rebooting into ${version} in ${delay}.".to_string()),
+                postponement_notice: None,
+                deadend_motd: None,
+            }),
+            network: Some(NetworkFragment {
+                http_proxy: Some("http://proxy.example.com:3128/".to_string()),
+                https_proxy: Some("http://proxy.example.com:3128/".to_string()),
+                no_proxy: Some(vec![
+                    "localhost".to_string(),
+                    ".internal.example.com".to_string(),
+                ]),
+                respect_metered: Some(true),
+                tls: Some(NetworkTls {
+                    ca_bundle: Some("/etc/pki/zincati/ca-bundle.pem".to_string()),
+                    client_cert: Some("/etc/pki/zincati/client.crt".to_string()),
+                    client_key: Some("/etc/pki/zincati/client.key".to_string()),
+                }),
+            }),
+            telemetry: Some(TelemetryFragment {
+                listen_address: Some("0.0.0.0:9101".to_string()),
+                basic_auth_file: Some("/etc/zincati/metrics-auth".to_string()),
+                tls_cert: Some("/etc/pki/zincati/metrics.crt".to_string()),
+                tls_key: Some("/etc/pki/zincati/metrics.key".to_string()),
+                tls_client_ca: Some("/etc/pki/zincati/metrics-clients.pem".to_string()),
             }),
             updates: Some(UpdateFragment {
                 allow_downgrade: Some(true),
                 enabled: Some(false),
+                block_on_layered: Some(true),
+                blackout: Some(UpdateBlackout {
+                    period: Some(vec![UpdateBlackoutPeriod {
+                        start: "2021-12-20".to_string(),
+                        end: "2022-01-02".to_string(),
+                        reason: Some("holiday freeze".to_string()),
+                    }]),
+                }),
+                check_on_network_up: Some(true),
+                cleanup_rollback_after_days: Some(14),
+                connectivity_gate: Some(UpdateConnectivityGate {
+                    probe: Some("tcp://bastion.example.com:22".to_string()),
+                    timeout_secs: Some(NonZeroU64::new(5).unwrap()),
+                }),
+                download: Some(UpdatePeriodic {
+                    window: Some(vec![UpdatePeriodicWindow {
+                        days: Some(btreeset!["Mon".to_string(), "Tue".to_string()]),
+                        days_of_month: None,
+                        dates: None,
+                        start_time: "20:00".to_string(),
+                        length_minutes: 600,
+                        time_zone: None,
+                    }]),
+                    time_zone: Some("UTC".to_string()),
+                    window_jitter_minutes: None,
+                }),
+                fetch_only_window: Some(true),
+                finalize_health_checks: Some(vec![
+                    "unit:etcd-member.service".to_string(),
+                    "command:/usr/local/bin/node-healthy".to_string(),
+                ]),
+                max_postponements: Some(5),
+                postponement_delay_minutes: Some(NonZeroU64::new(3).unwrap()),
+                post_boot_hooks: Some(vec![
+                    "command:/usr/local/bin/enlist-node".to_string(),
+                    "https://cmdb.example.com/api/nodes/enlist".to_string(),
+                ]),
+                reboot_countdown_minutes: Some(5),
+                reboot_lock_path: Some("/run/reboot.lock".to_string()),
+                require_reboot_approval: Some(true),
+                skip_versions: Some(vec!["36.20220505.3.2".to_string()]),
+                source: Some("cincinnati".to_string()),
+                steady_report_max_failures: Some(6),
                 strategy: Some("fleet_lock".to_string()),
+                verify_remote: Some("fedora".to_string()),
+                verify_signature: Some(true),
+                disk_space: Some(UpdateDiskSpace {
+                    boot_min_free_mib: Some(100),
+                    cleanup_command: Some("/usr/bin/rpm-ostree cleanup -r".to_string()),
+                    sysroot_min_free_mib: Some(2048),
+                }),
+                downgrade_barrier: Some(UpdateDowngradeBarrier {
+                    min_version: Some("36.20220505.3.2".to_string()),
+                    max_age_days: Some(90),
+                }),
                 fleet_lock: Some(UpdateFleetLock {
                     base_url: Some("http://fleet-lock.example.com:8080/".to_string()),
+                    confirm_updates: Some(true),
+                    report_health: Some(false),
+                    token_path: Some("credential:fleet-lock-token".to_string()),
+                    client_cert: Some("/etc/pki/zincati/fleet-lock.crt".to_string()),
+                    client_key: Some("/etc/pki/zincati/fleet-lock.key".to_string()),
+                    groups: Some(vec!["storage".to_string(), "rack-12".to_string()]),
+                }),
+                logind_reboot: Some(UpdateLogindReboot {
+                    enabled: Some(true),
+                    lead_time_minutes: Some(NonZeroU64::new(15).unwrap()),
+                }),
+                ostree_remote: Some(UpdateOstreeRemote {
+                    refspec: Some("mirror:fedora/x86_64/coreos/stable".to_string()),
+                }),
+                outcome_report: Some(UpdateOutcomeReport {
+                    url: Some("https://fleet.example.com/v1/outcomes".to_string()),
+                    batch_size: Some(NonZeroU64::new(10).unwrap()),
+                    max_queued: Some(NonZeroU64::new(50).unwrap()),
+                    max_age_hours: Some(72),
                 }),
                 periodic: Some(UpdatePeriodic {
                     window: Some(vec![
                         UpdatePeriodicWindow {
-                            days: btreeset!("Sat".to_string(), "Sun".to_string()),
+                            days: Some(btreeset!("Sat".to_string(), "Sun".to_string())),
+                            days_of_month: None,
+                            dates: None,
                             start_time: "23:00".to_string(),
                             length_minutes: 120,
+                            time_zone: None,
                         },
                         UpdatePeriodicWindow {
-                            days: btreeset!("Wed".to_string()),
+                            days: Some(btreeset!("Wed".to_string())),
+                            days_of_month: None,
+                            dates: None,
                             start_time: "23:30".to_string(),
                             length_minutes: 25,
+                            time_zone: Some("Europe/Rome".to_string()),
+                        },
+                        UpdatePeriodicWindow {
+                            days: Some(btreeset!("first Sun".to_string())),
+                            days_of_month: Some(btreeset!(15)),
+                            dates: Some(btreeset!("2021-12-24".to_string())),
+                            start_time: "02:00".to_string(),
+                            length_minutes: 120,
+                            time_zone: None,
                         },
                     ]),
                     time_zone: Some("localtime".to_string()),
+                    window_jitter_minutes: Some(10),
+                }),
+                quiesce: Some(UpdateQuiesce {
+                    unit: Some(vec![
+                        UpdateQuiesceUnit {
+                            name: "postgresql.service".to_string(),
+                            restart_on_abort: Some(true),
+                            timeout_secs: Some(NonZeroU64::new(300).unwrap()),
+                        },
+                        UpdateQuiesceUnit {
+                            name: "etcd-member.service".to_string(),
+                            restart_on_abort: None,
+                            timeout_secs: None,
+                        },
+                    ]),
+                }),
+                static_graph: Some(UpdateStaticGraph {
+                    path: Some("/etc/zincati/graph.json".to_string()),
+                }),
+                webhook: None,
+                stream_switch: Some(UpdateStreamSwitch {
+                    refspec: Some("fedora:fedora/${basearch}/coreos/${stream}".to_string()),
+                    allowed_streams: Some(vec!["stable".to_string(), "testing".to_string()]),
+                }),
+                urgency: Some(UpdateUrgency {
+                    enabled: Some(true),
+                    min_severity: Some("important".to_string()),
+                    grace_period_minutes: Some(30),
                 }),
             }),
         };
@@ -1,34 +1,106 @@
 use crate::config::fragments;
-use crate::update_agent::DEFAULT_STEADY_INTERVAL_SECS;
+use crate::config::provenance::Provenance;
+use crate::connectivity::DEFAULT_PROBE_TIMEOUT_SECS;
+use crate::quiesce::DEFAULT_STOP_TIMEOUT_SECS;
 use anyhow::{Context, Result};
 use fn_error_context::context;
 use log::trace;
 use ordered_float::NotNan;
 use serde::Serialize;
+use std::collections::BTreeMap;
 use std::num::NonZeroU64;
 
+/// Default refresh interval for steady state (in seconds).
+pub const DEFAULT_STEADY_INTERVAL_SECS: u64 = 300; // 5 minutes.
+
+/// Default interval between self-tests of control surfaces (in seconds).
+pub const DEFAULT_SELF_TEST_INTERVAL_SECS: u64 = 600; // 10 minutes.
+
+/// Default lead time for reboots scheduled via logind (in minutes).
+pub const DEFAULT_LOGIND_LEAD_TIME_MINUTES: u64 = 10;
+
+/// Default maximum number of queued webhook events.
+pub const DEFAULT_WEBHOOK_MAX_QUEUED: u64 = 1000;
+
+/// Default maximum age of queued webhook events (in hours).
+pub const DEFAULT_WEBHOOK_MAX_AGE_HOURS: u64 = 168; // 1 week.
+/// Default delay before overriding the update strategy for urgent releases (in minutes).
+pub const DEFAULT_URGENCY_GRACE_PERIOD_MINUTES: u64 = 60;
+
+/// Default maximum number of update outcome reports per request.
+pub const DEFAULT_OUTCOME_REPORT_BATCH_SIZE: u64 = 20;
+
+/// Default maximum number of queued update outcome reports.
+pub const DEFAULT_OUTCOME_REPORT_MAX_QUEUED: u64 = 100;
+
+/// Default maximum age of queued update outcome reports (in hours).
+pub const DEFAULT_OUTCOME_REPORT_MAX_AGE_HOURS: u64 = 168; // 1 week.
+
+/// Default maximum number of finalization postponements due to active user sessions.
+pub const DEFAULT_MAX_POSTPONEMENTS: u8 = 10;
+
+/// Default delay between finalization postponements (in minutes).
+pub const DEFAULT_POSTPONEMENT_DELAY_MINUTES: u64 = 1;
+
+/// Default maximum number of queued rpm-ostree operations.
+pub const DEFAULT_RPM_OSTREE_MAX_QUEUED: u64 = 8;
+
+/// Default timeout for staging, downloading and rebasing via rpm-ostree (in seconds).
+pub const DEFAULT_RPM_OSTREE_STAGE_TIMEOUT_SECS: u64 = 3600; // 1 hour.
+
+/// Default timeout for finalizing via rpm-ostree (in seconds).
+pub const DEFAULT_RPM_OSTREE_FINALIZE_TIMEOUT_SECS: u64 = 600; // 10 minutes.
+
+/// Default timeout for rpm-ostree status queries (in seconds).
+pub const DEFAULT_RPM_OSTREE_STATUS_TIMEOUT_SECS: u64 = 300; // 5 minutes.
+
+/// Default maximum delay before retrying a failed deploy (in seconds).
+pub const DEFAULT_DEPLOY_RETRY_MAX_DELAY_SECS: u64 = 3600; // 1 hour.
+
 /// Runtime configuration holding environmental inputs.
 #[derive(Debug, Serialize)]
-pub(crate) struct ConfigInput {
-    pub(crate) agent: AgentInput,
-    pub(crate) cincinnati: CincinnatiInput,
-    pub(crate) updates: UpdateInput,
-    pub(crate) identity: IdentityInput,
+pub struct ConfigInput {
+    /// Agent configuration.
+    pub agent: AgentInput,
+    /// Cincinnati client configuration.
+    pub cincinnati: CincinnatiInput,
+    /// Update strategy configuration.
+    pub updates: UpdateInput,
+    /// Agent identity.
+    pub identity: IdentityInput,
+    /// User-facing messages.
+    pub messages: MessagesInput,
+    /// Outbound network configuration.
+    pub network: NetworkInput,
+    /// Metrics exporter configuration.
+    pub telemetry: TelemetryInput,
 }
 
 impl ConfigInput {
     /// Read config fragments and merge them into a single config.
-    #[context("failed to read and merge config fragments")]
-    pub(crate) fn read_configs(
+    pub fn read_configs(
         dirs: Vec<String>,
         common_path: &str,
         extensions: Vec<String>,
     ) -> Result<Self> {
+        let (cfg, _) = Self::read_configs_with_provenance(dirs, common_path, extensions)?;
+        Ok(cfg)
+    }
+
+    /// Read config fragments and merge them into a single config, also
+    /// recording which keys are set by each fragment.
+    #[context("failed to read and merge config fragments")]
+    pub fn read_configs_with_provenance(
+        dirs: Vec<String>,
+        common_path: &str,
+        extensions: Vec<String>,
+    ) -> Result<(Self, Provenance)> {
         use std::io::Read;
 
         let scanner = liboverdrop::FragmentScanner::new(dirs, common_path, true, extensions);
 
         let mut fragments = Vec::new();
+        let mut provenance = Provenance::default();
         for (_, fpath) in scanner.scan() {
             trace!("reading config fragment '{}'", fpath.display());
 
@@ -39,22 +111,44 @@ impl ConfigInput {
             bufrd
                 .read_to_end(&mut content)
                 .with_context(|| format!("failed to read content of '{}'", fpath.display()))?;
-            let frag: fragments::ConfigFragment =
-                toml::from_slice(&content).context("failed to parse TOML")?;
+            let frag: fragments::ConfigFragment = toml::from_slice(&content)
+                .with_context(|| format!("failed to parse TOML fragment '{}'", fpath.display()))?;
+            let raw: toml::Value = toml::from_slice(&content)
+                .with_context(|| format!("failed to parse TOML fragment '{}'", fpath.display()))?;
+            provenance.record(&fpath, &raw, &frag)?;
 
             fragments.push(frag);
         }
 
         let cfg = Self::merge_fragments(fragments);
-        Ok(cfg)
+        Ok((cfg, provenance))
+    }
+
+    /// Compute a stable hash of this configuration.
+    ///
+    /// This is meant to detect configuration drift across nodes, thus it only
+    /// depends on effective values (not on how they are split into fragments).
+    /// The hash is truncated to 48 bits, so that it can be exactly represented
+    /// as a metric value.
+    pub fn config_hash(&self) -> Result<i64> {
+        let content = serde_json::to_vec(self).context("failed to serialize configuration")?;
+        let digest = openssl::sha::sha256(&content);
+        let hash = digest
+            .iter()
+            .take(6)
+            .fold(0i64, |acc, byte| (acc << 8) | i64::from(*byte));
+        Ok(hash)
     }
 
     /// Merge multiple fragments into a single configuration.
-    pub(crate) fn merge_fragments(fragments: Vec<fragments::ConfigFragment>) -> Self {
+    pub fn merge_fragments(fragments: Vec<fragments::ConfigFragment>) -> Self {
         let mut agents = vec![];
         let mut cincinnatis = vec![];
         let mut updates = vec![];
         let mut identities = vec![];
+        let mut messages = vec![];
+        let mut networks = vec![];
+        let mut telemetries = vec![];
 
         for snip in fragments {
             if let Some(a) = snip.agent {
@@ -69,6 +163,15 @@ impl ConfigInput {
             if let Some(i) = snip.identity {
                 identities.push(i);
             }
+            if let Some(m) = snip.messages {
+                messages.push(m);
+            }
+            if let Some(n) = snip.network {
+                networks.push(n);
+            }
+            if let Some(t) = snip.telemetry {
+                telemetries.push(t);
+            }
         }
 
         Self {
@@ -76,28 +179,93 @@ impl ConfigInput {
             cincinnati: CincinnatiInput::from_fragments(cincinnatis),
             updates: UpdateInput::from_fragments(updates),
             identity: IdentityInput::from_fragments(identities),
+            messages: MessagesInput::from_fragments(messages),
+            network: NetworkInput::from_fragments(networks),
+            telemetry: TelemetryInput::from_fragments(telemetries),
         }
     }
 }
 
 /// Config for the agent.
 #[derive(Debug, Serialize)]
-pub(crate) struct AgentInput {
-    pub(crate) steady_interval_secs: NonZeroU64,
+pub struct AgentInput {
+    /// Whether to disable rpm-ostree automatic updates if they conflict.
+    pub reconcile_rpm_ostree_policy: bool,
+    /// Backend used to interact with rpm-ostree.
+    pub rpm_ostree_backend: String,
+    /// Maximum number of queued rpm-ostree operations.
+    pub rpm_ostree_max_queued: NonZeroU64,
+    /// Pausing interval between updates checks in steady mode, in seconds.
+    pub steady_interval_secs: NonZeroU64,
+    /// Interval between self-tests of control surfaces, in seconds (0 if disabled).
+    pub self_test_interval_secs: u64,
+    /// Timeout for staging, downloading and rebasing via rpm-ostree, in seconds.
+    pub rpm_ostree_stage_timeout_secs: NonZeroU64,
+    /// Timeout for finalizing via rpm-ostree, in seconds.
+    pub rpm_ostree_finalize_timeout_secs: NonZeroU64,
+    /// Timeout for rpm-ostree status queries, in seconds.
+    pub rpm_ostree_status_timeout_secs: NonZeroU64,
+    /// Maximum delay before retrying a failed deploy, in seconds.
+    pub deploy_retry_max_delay_secs: NonZeroU64,
+    /// Maximum random delay before the first update check, in seconds (0 if disabled).
+    pub first_check_splay_secs: u64,
 }
 
 impl AgentInput {
     fn from_fragments(fragments: Vec<fragments::AgentFragment>) -> Self {
         let mut cfg = Self {
+            reconcile_rpm_ostree_policy: false,
+            rpm_ostree_backend: String::new(),
+            rpm_ostree_max_queued: NonZeroU64::new(DEFAULT_RPM_OSTREE_MAX_QUEUED)
+                .expect("non-zero queue size"),
             steady_interval_secs: NonZeroU64::new(DEFAULT_STEADY_INTERVAL_SECS)
                 .expect("non-zero interval"),
+            self_test_interval_secs: DEFAULT_SELF_TEST_INTERVAL_SECS,
+            rpm_ostree_stage_timeout_secs: NonZeroU64::new(DEFAULT_RPM_OSTREE_STAGE_TIMEOUT_SECS)
+                .expect("non-zero timeout"),
+            rpm_ostree_finalize_timeout_secs: NonZeroU64::new(
+                DEFAULT_RPM_OSTREE_FINALIZE_TIMEOUT_SECS,
+            )
+            .expect("non-zero timeout"),
+            rpm_ostree_status_timeout_secs: NonZeroU64::new(DEFAULT_RPM_OSTREE_STATUS_TIMEOUT_SECS)
+                .expect("non-zero timeout"),
+            deploy_retry_max_delay_secs: NonZeroU64::new(DEFAULT_DEPLOY_RETRY_MAX_DELAY_SECS)
+                .expect("non-zero delay"),
+            first_check_splay_secs: 0,
         };
 
         for snip in fragments {
+            if let Some(r) = snip.reconcile_rpm_ostree_policy {
+                cfg.reconcile_rpm_ostree_policy = r;
+            }
+            if let Some(b) = snip.rpm_ostree_backend {
+                cfg.rpm_ostree_backend = b;
+            }
+            if let Some(q) = snip.rpm_ostree_max_queued {
+                cfg.rpm_ostree_max_queued = q;
+            }
             if let Some(timing) = snip.timing {
                 if let Some(s) = timing.steady_interval_secs {
                     cfg.steady_interval_secs = s;
                 }
+                if let Some(s) = timing.self_test_interval_secs {
+                    cfg.self_test_interval_secs = s;
+                }
+                if let Some(t) = timing.rpm_ostree_stage_timeout_secs {
+                    cfg.rpm_ostree_stage_timeout_secs = t;
+                }
+                if let Some(t) = timing.rpm_ostree_finalize_timeout_secs {
+                    cfg.rpm_ostree_finalize_timeout_secs = t;
+                }
+                if let Some(t) = timing.rpm_ostree_status_timeout_secs {
+                    cfg.rpm_ostree_status_timeout_secs = t;
+                }
+                if let Some(d) = timing.deploy_retry_max_delay_secs {
+                    cfg.deploy_retry_max_delay_secs = d;
+                }
+                if let Some(s) = timing.first_check_splay_secs {
+                    cfg.first_check_splay_secs = s;
+                }
             }
         }
 
@@ -105,33 +273,54 @@ impl AgentInput {
     }
 }
 
+/// Config for Cincinnati client.
 #[derive(Clone, Debug, Serialize)]
-pub(crate) struct CincinnatiInput {
+pub struct CincinnatiInput {
     /// Base URL (template) for the Cincinnati service.
-    pub(crate) base_url: String,
+    pub base_url: String,
+    /// Policy for selecting among multiple update targets (empty for default).
+    pub target_policy: String,
 }
 
 impl CincinnatiInput {
     fn from_fragments(fragments: Vec<fragments::CincinnatiFragment>) -> Self {
         let mut cfg = Self {
             base_url: String::new(),
+            target_policy: String::new(),
         };
 
         for snip in fragments {
             if let Some(u) = snip.base_url {
                 cfg.base_url = u;
             }
+            if let Some(p) = snip.target_policy {
+                cfg.target_policy = p;
+            }
         }
 
         cfg
     }
 }
 
+/// Config for agent identity.
 #[derive(Debug, Serialize)]
-pub(crate) struct IdentityInput {
-    pub(crate) group: String,
-    pub(crate) node_uuid: String,
-    pub(crate) rollout_wariness: Option<NotNan<f64>>,
+pub struct IdentityInput {
+    /// Update group.
+    pub group: String,
+    /// Node UUID (empty to derive it from machine-id).
+    pub node_uuid: String,
+    /// Rollout wariness (unset to derive it server-side).
+    pub rollout_wariness: Option<NotNan<f64>>,
+    /// Minimum release age for update targets, in hours (unset for no minimum).
+    pub min_release_age_hours: Option<NonZeroU64>,
+    /// Base architecture override (unset to detect it from the booted deployment).
+    pub basearch: Option<String>,
+    /// Identity providers, in order (empty for configuration only).
+    pub providers: Vec<String>,
+    /// Custom identity parameters.
+    pub parameters: BTreeMap<String, String>,
+    /// Extra client parameters for Cincinnati, possibly templated.
+    pub extra_params: BTreeMap<String, String>,
 }
 
 impl IdentityInput {
@@ -140,6 +329,11 @@ impl IdentityInput {
             group: String::new(),
             node_uuid: String::new(),
             rollout_wariness: None,
+            min_release_age_hours: None,
+            basearch: None,
+            providers: vec![],
+            parameters: BTreeMap::new(),
+            extra_params: BTreeMap::new(),
         };
 
         for snip in fragments {
@@ -152,6 +346,155 @@ impl IdentityInput {
             if let Some(rw) = snip.rollout_wariness {
                 cfg.rollout_wariness = Some(rw);
             }
+            if let Some(age) = snip.min_release_age_hours {
+                cfg.min_release_age_hours = Some(age);
+            }
+            if let Some(arch) = snip.basearch {
+                cfg.basearch = Some(arch);
+            }
+            if let Some(p) = snip.providers {
+                cfg.providers = p;
+            }
+            if let Some(params) = snip.parameters {
+                cfg.parameters.extend(params);
+            }
+            if let Some(params) = snip.extra_params {
+                cfg.extra_params.extend(params);
+            }
+        }
+
+        cfg
+    }
+}
+
+/// Config for user-facing messages.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct MessagesInput {
+    /// Template for reboot warnings (empty for default).
+    pub reboot_warning: String,
+    /// Template for finalization postponement notices (empty for default).
+    pub postponement_notice: String,
+    /// Template for the dead-end release MOTD (empty for default).
+    pub deadend_motd: String,
+}
+
+impl MessagesInput {
+    fn from_fragments(fragments: Vec<fragments::MessagesFragment>) -> Self {
+        let mut cfg = Self::default();
+
+        for snip in fragments {
+            if let Some(rw) = snip.reboot_warning {
+                cfg.reboot_warning = rw;
+            }
+            if let Some(pn) = snip.postponement_notice {
+                cfg.postponement_notice = pn;
+            }
+            if let Some(dm) = snip.deadend_motd {
+                cfg.deadend_motd = dm;
+            }
+        }
+
+        cfg
+    }
+}
+
+/// Config for outbound network settings.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct NetworkInput {
+    /// Proxy URL for HTTP connections.
+    #[serde(skip)]
+    pub http_proxy: Option<String>,
+    /// Proxy URL for HTTPS connections.
+    #[serde(skip)]
+    pub https_proxy: Option<String>,
+    /// Hosts and domains which bypass proxies.
+    pub no_proxy: Vec<String>,
+    /// Whether to defer fetching updates on metered connections.
+    pub respect_metered: bool,
+    /// TLS settings for HTTPS connections.
+    pub tls: TlsInput,
+}
+
+/// Config for TLS settings.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct TlsInput {
+    /// Path to additional CA certificates (PEM bundle).
+    pub ca_bundle: Option<String>,
+    /// Path to client certificate (PEM).
+    pub client_cert: Option<String>,
+    /// Path to client private key (PEM).
+    pub client_key: Option<String>,
+}
+
+impl NetworkInput {
+    fn from_fragments(fragments: Vec<fragments::NetworkFragment>) -> Self {
+        let mut cfg = Self::default();
+
+        for snip in fragments {
+            if let Some(p) = snip.http_proxy {
+                cfg.http_proxy = Some(p);
+            }
+            if let Some(p) = snip.https_proxy {
+                cfg.https_proxy = Some(p);
+            }
+            if let Some(np) = snip.no_proxy {
+                cfg.no_proxy = np;
+            }
+            if let Some(metered) = snip.respect_metered {
+                cfg.respect_metered = metered;
+            }
+            if let Some(tls) = snip.tls {
+                if let Some(ca) = tls.ca_bundle {
+                    cfg.tls.ca_bundle = Some(ca);
+                }
+                if let Some(cert) = tls.client_cert {
+                    cfg.tls.client_cert = Some(cert);
+                }
+                if let Some(key) = tls.client_key {
+                    cfg.tls.client_key = Some(key);
+                }
+            }
+        }
+
+        cfg
+    }
+}
+
+/// Config for the metrics exporter over TCP.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct TelemetryInput {
+    /// Address to listen on for metrics scrapes (empty if disabled).
+    pub listen_address: String,
+    /// Path to a file with basic auth credentials (empty if unset).
+    pub basic_auth_file: String,
+    /// Path to server certificate (empty if unset).
+    pub tls_cert: String,
+    /// Path to server private key (empty if unset).
+    pub tls_key: String,
+    /// Path to CA certificates for authenticating clients (empty if unset).
+    pub tls_client_ca: String,
+}
+
+impl TelemetryInput {
+    fn from_fragments(fragments: Vec<fragments::TelemetryFragment>) -> Self {
+        let mut cfg = Self::default();
+
+        for snip in fragments {
+            if let Some(la) = snip.listen_address {
+                cfg.listen_address = la;
+            }
+            if let Some(ba) = snip.basic_auth_file {
+                cfg.basic_auth_file = ba;
+            }
+            if let Some(cert) = snip.tls_cert {
+                cfg.tls_cert = cert;
+            }
+            if let Some(key) = snip.tls_key {
+                cfg.tls_key = key;
+            }
+            if let Some(ca) = snip.tls_client_ca {
+                cfg.tls_client_ca = ca;
+            }
         }
 
         cfg
@@ -160,56 +503,455 @@ impl IdentityInput {
 
 /// Config for update logic.
 #[derive(Debug, Serialize)]
-pub(crate) struct UpdateInput {
+pub struct UpdateInput {
     /// Whether to enable automatic downgrades.
-    pub(crate) allow_downgrade: bool,
+    pub allow_downgrade: bool,
     /// Whether to enable auto-updates logic.
-    pub(crate) enabled: bool,
+    pub enabled: bool,
+    /// Whether to block updates when packages are layered on the booted deployment.
+    pub block_on_layered: bool,
+    /// Periods during which finalization is always denied.
+    pub blackout: BlackoutInput,
+    /// Whether to check for updates as soon as the network comes online.
+    pub check_on_network_up: bool,
+    /// Healthy days on a new release before cleaning up the rollback
+    /// deployment (0 for never).
+    pub cleanup_rollback_after_days: u64,
+    /// Connectivity gate for finalization.
+    pub connectivity_gate: ConnectivityGateInput,
+    /// Windows for downloading updates (empty for any time).
+    pub download: PeriodicInput,
+    /// Whether to defer staging until close to a finalization window.
+    pub fetch_only_window: bool,
+    /// Health checks to pass before finalization.
+    pub finalize_health_checks: Vec<String>,
+    /// Maximum number of finalization postponements due to active user sessions.
+    pub max_postponements: u8,
+    /// Delay between finalization postponements (in minutes).
+    pub postponement_delay_minutes: NonZeroU64,
+    /// Hooks to run after booting into a finalized update.
+    pub post_boot_hooks: Vec<String>,
+    /// Countdown before rebooting into a finalized update, in minutes (zero if disabled).
+    pub reboot_countdown_minutes: u64,
+    /// Lock file shared with other reboot managers (empty if unset).
+    pub reboot_lock_path: String,
+    /// Whether to require an explicit approval before finalization.
+    pub require_reboot_approval: bool,
+    /// Release versions never to be selected as update targets.
+    pub skip_versions: Vec<String>,
+    /// Update source.
+    pub source: String,
+    /// Consecutive failures to report steady state before polling for updates
+    /// in degraded mode (0 for never).
+    pub steady_report_max_failures: u32,
     /// Update strategy.
-    pub(crate) strategy: String,
+    pub strategy: String,
+    /// OSTree remote to check for the target release before fetching it (empty if unset).
+    pub verify_remote: String,
+    /// Whether to verify the signature of the target release on `verify_remote`.
+    pub verify_signature: bool,
+    /// Free disk space checks before staging.
+    pub disk_space: DiskSpaceInput,
+    /// Barriers against downgrading too far back.
+    pub downgrade_barrier: DowngradeBarrierInput,
     /// `fleet_lock` strategy config.
-    pub(crate) fleet_lock: FleetLockInput,
+    pub fleet_lock: FleetLockInput,
+    /// Reboots scheduled via systemd-logind.
+    pub logind_reboot: LogindRebootInput,
+    /// `ostree-remote` source config.
+    pub ostree_remote: OstreeRemoteInput,
+    /// Reporting of update outcomes.
+    pub outcome_report: OutcomeReportInput,
     /// `periodic` strategy config.
-    pub(crate) periodic: PeriodicInput,
+    pub periodic: PeriodicInput,
+    /// Services stopped before finalization.
+    pub quiesce: QuiesceInput,
+    /// `static-graph` source config.
+    pub static_graph: StaticGraphInput,
+    /// Webhook for agent events.
+    pub webhook: WebhookInput,
+    /// Switching to other update streams.
+    pub stream_switch: StreamSwitchInput,
+    /// Overriding the update strategy for urgent releases.
+    pub urgency: UrgencyInput,
+}
+
+impl Default for UpdateInput {
+    fn default() -> Self {
+        Self {
+            allow_downgrade: false,
+            enabled: true,
+            block_on_layered: false,
+            blackout: BlackoutInput::default(),
+            check_on_network_up: false,
+            cleanup_rollback_after_days: 0,
+            connectivity_gate: ConnectivityGateInput::default(),
+            download: PeriodicInput::default(),
+            fetch_only_window: false,
+            finalize_health_checks: vec![],
+            max_postponements: DEFAULT_MAX_POSTPONEMENTS,
+            postponement_delay_minutes: NonZeroU64::new(DEFAULT_POSTPONEMENT_DELAY_MINUTES)
+                .expect("non-zero postponement delay"),
+            post_boot_hooks: vec![],
+            reboot_countdown_minutes: 0,
+            reboot_lock_path: String::new(),
+            require_reboot_approval: false,
+            skip_versions: vec![],
+            source: String::new(),
+            steady_report_max_failures: 0,
+            strategy: String::new(),
+            verify_remote: String::new(),
+            verify_signature: false,
+            disk_space: DiskSpaceInput::default(),
+            downgrade_barrier: DowngradeBarrierInput::default(),
+            fleet_lock: FleetLockInput {
+                base_url: String::new(),
+                confirm_updates: false,
+                report_health: true,
+                token_path: String::new(),
+                client_cert: String::new(),
+                client_key: String::new(),
+                groups: vec![],
+            },
+            logind_reboot: LogindRebootInput::default(),
+            ostree_remote: OstreeRemoteInput::default(),
+            outcome_report: OutcomeReportInput::default(),
+            periodic: PeriodicInput::default(),
+            quiesce: QuiesceInput::default(),
+            static_graph: StaticGraphInput::default(),
+            webhook: WebhookInput::default(),
+            stream_switch: StreamSwitchInput::default(),
+            urgency: UrgencyInput::default(),
+        }
+    }
+}
+
+/// Config for finalization blackout periods.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct BlackoutInput {
+    /// Set of blackout periods.
+    pub periods: Vec<BlackoutPeriodInput>,
+}
+
+/// A finalization blackout period.
+#[derive(Clone, Debug, Serialize)]
+pub struct BlackoutPeriodInput {
+    /// Start of the period.
+    pub start: String,
+    /// End of the period.
+    pub end: String,
+    /// Reason for the blackout (empty if unset).
+    pub reason: String,
+}
+
+/// Config for services stopped before finalization.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct QuiesceInput {
+    /// Units to stop, in order.
+    pub units: Vec<QuiesceUnitInput>,
+}
+
+/// A unit stopped before finalization.
+#[derive(Clone, Debug, Serialize)]
+pub struct QuiesceUnitInput {
+    /// Unit name.
+    pub name: String,
+    /// Whether to start the unit again if finalization is aborted.
+    pub restart_on_abort: bool,
+    /// Timeout for stopping the unit, in seconds.
+    pub timeout_secs: NonZeroU64,
+}
+
+/// Config for the finalization connectivity gate.
+#[derive(Clone, Debug, Serialize)]
+pub struct ConnectivityGateInput {
+    /// Target to probe (empty to disable the gate).
+    pub probe: String,
+    /// Timeout for a single probe, in seconds.
+    pub timeout_secs: NonZeroU64,
+}
+
+impl Default for ConnectivityGateInput {
+    fn default() -> Self {
+        Self {
+            probe: String::new(),
+            timeout_secs: NonZeroU64::new(DEFAULT_PROBE_TIMEOUT_SECS).expect("non-zero timeout"),
+        }
+    }
 }
 
 /// Config for "fleet_lock" strategy.
 #[derive(Clone, Debug, Serialize)]
-pub(crate) struct FleetLockInput {
+pub struct FleetLockInput {
     /// Base URL (template) for the FleetLock service.
-    pub(crate) base_url: String,
+    pub base_url: String,
+    /// Whether to confirm successful updates before unlocking.
+    pub confirm_updates: bool,
+    /// Whether to report a node health summary when unlocking.
+    pub report_health: bool,
+    /// Path to a bearer token for authentication (empty if unset).
+    pub token_path: String,
+    /// Path to a TLS client certificate (empty if unset).
+    pub client_cert: String,
+    /// Path to a TLS client private key (empty if unset).
+    pub client_key: String,
+    /// Reboot groups to lock, all at once (empty for the node group).
+    pub groups: Vec<String>,
+}
+
+/// Config for reboots scheduled via systemd-logind.
+#[derive(Clone, Debug, Serialize)]
+pub struct LogindRebootInput {
+    /// Whether to schedule reboots via logind.
+    pub enabled: bool,
+    /// Lead time between scheduling and reboot, in minutes.
+    pub lead_time_minutes: NonZeroU64,
+}
+
+impl Default for LogindRebootInput {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            lead_time_minutes: NonZeroU64::new(DEFAULT_LOGIND_LEAD_TIME_MINUTES)
+                .expect("non-zero lead time"),
+        }
+    }
+}
+
+/// Config for "ostree-remote" update source.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct OstreeRemoteInput {
+    /// OSTree refspec to poll (`remote:ref`).
+    pub refspec: String,
+}
+
+/// Config for reporting of update outcomes.
+#[derive(Clone, Debug, Serialize)]
+pub struct OutcomeReportInput {
+    /// URL of the reporting endpoint (empty if unset).
+    pub url: String,
+    /// Maximum number of reports per request.
+    pub batch_size: NonZeroU64,
+    /// Maximum number of queued reports.
+    pub max_queued: NonZeroU64,
+    /// Maximum age of queued reports, in hours (zero if unbounded).
+    pub max_age_hours: u64,
+}
+
+impl Default for OutcomeReportInput {
+    fn default() -> Self {
+        Self {
+            url: String::new(),
+            batch_size: NonZeroU64::new(DEFAULT_OUTCOME_REPORT_BATCH_SIZE)
+                .expect("non-zero batch size"),
+            max_queued: NonZeroU64::new(DEFAULT_OUTCOME_REPORT_MAX_QUEUED)
+                .expect("non-zero queue size"),
+            max_age_hours: DEFAULT_OUTCOME_REPORT_MAX_AGE_HOURS,
+        }
+    }
+}
+
+/// Config for "static-graph" update source.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct StaticGraphInput {
+    /// Absolute path to the update graph (JSON).
+    pub path: String,
+}
+
+/// Config for the agent events webhook.
+#[derive(Clone, Debug, Serialize)]
+pub struct WebhookInput {
+    /// Endpoint receiving agent events (empty if disabled).
+    pub url: String,
+    /// Maximum number of queued events.
+    pub max_queued: NonZeroU64,
+    /// Maximum age of queued events, in hours (zero if unbounded).
+    pub max_age_hours: u64,
+}
+
+impl Default for WebhookInput {
+    fn default() -> Self {
+        Self {
+            url: String::new(),
+            max_queued: NonZeroU64::new(DEFAULT_WEBHOOK_MAX_QUEUED).expect("non-zero queue size"),
+            max_age_hours: DEFAULT_WEBHOOK_MAX_AGE_HOURS,
+        }
+    }
+}
+
+/// Config for switching to other update streams.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct StreamSwitchInput {
+    /// Template for the OSTree refspec of a stream (empty if unset).
+    pub refspec: String,
+    /// Streams which can be switched to (empty for any).
+    pub allowed_streams: Vec<String>,
+}
+
+/// Config for free disk space checks before staging.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct DiskSpaceInput {
+    /// Minimum free space on `/boot`, in MiB (zero if unchecked).
+    pub boot_min_free_mib: u64,
+    /// Command to reclaim disk space, with arguments (empty if unset).
+    pub cleanup_command: String,
+    /// Minimum free space on the OSTree sysroot, in MiB (zero if unchecked).
+    pub sysroot_min_free_mib: u64,
+}
+
+/// Config for barriers against downgrading too far back.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct DowngradeBarrierInput {
+    /// Minimum version of update targets (empty if unset).
+    pub min_version: String,
+    /// Maximum age of a staged commit relative to the booted one, in days (zero if unset).
+    pub max_age_days: u64,
+}
+
+/// Config for overriding the update strategy for urgent releases.
+#[derive(Clone, Debug, Serialize)]
+pub struct UrgencyInput {
+    /// Whether to override the update strategy for urgent releases.
+    pub enabled: bool,
+    /// Minimum severity for a release to be urgent.
+    pub min_severity: String,
+    /// Delay before overriding the update strategy, in minutes.
+    pub grace_period_minutes: u64,
+}
+
+impl Default for UrgencyInput {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            min_severity: "critical".to_string(),
+            grace_period_minutes: DEFAULT_URGENCY_GRACE_PERIOD_MINUTES,
+        }
+    }
 }
 
 /// Config for "periodic" strategy.
 #[derive(Clone, Debug, Serialize)]
-pub(crate) struct PeriodicInput {
+pub struct PeriodicInput {
     /// Set of updates windows.
-    pub(crate) intervals: Vec<PeriodicIntervalInput>,
+    pub intervals: Vec<PeriodicIntervalInput>,
     /// A time zone in the IANA Time Zone Database or "localtime".
     /// Defaults to "UTC".
-    pub(crate) time_zone: String,
+    pub time_zone: String,
+    /// Maximum delay after a window opens before considering it open (in minutes).
+    pub window_jitter_minutes: u32,
+}
+
+impl Default for PeriodicInput {
+    fn default() -> Self {
+        Self {
+            intervals: vec![],
+            time_zone: "UTC".to_string(),
+            window_jitter_minutes: 0,
+        }
+    }
+}
+
+impl PeriodicInput {
+    /// Merge a `periodic`-like fragment into this config.
+    fn merge_fragment(&mut self, fragment: fragments::UpdatePeriodic) {
+        if let Some(tz) = fragment.time_zone {
+            self.time_zone = tz;
+        }
+        if let Some(jitter) = fragment.window_jitter_minutes {
+            self.window_jitter_minutes = jitter;
+        }
+        if let Some(win) = fragment.window {
+            for entry in win {
+                // Monthly and date-based windows are told apart from weekly
+                // ones by their day format.
+                let days_of_month = entry
+                    .days_of_month
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|day| format!("day {}", day));
+                let days = entry
+                    .days
+                    .unwrap_or_default()
+                    .into_iter()
+                    .chain(days_of_month)
+                    .chain(entry.dates.unwrap_or_default());
+                for day in days {
+                    let interval = PeriodicIntervalInput {
+                        start_day: day,
+                        start_time: entry.start_time.clone(),
+                        length_minutes: entry.length_minutes,
+                        time_zone: entry.time_zone.clone(),
+                    };
+                    self.intervals.push(interval);
+                }
+            }
+        }
+    }
 }
 
 /// Update window for a "periodic" interval.
 #[derive(Clone, Debug, Serialize)]
-pub(crate) struct PeriodicIntervalInput {
-    pub(crate) start_day: String,
-    pub(crate) start_time: String,
-    pub(crate) length_minutes: u32,
+pub struct PeriodicIntervalInput {
+    /// Day on which the window starts (weekday, `day <N>`, `<ordinal> <weekday>`
+    /// or `YYYY-MM-DD`).
+    pub start_day: String,
+    /// Time of day at which the window starts (`HH:MM`).
+    pub start_time: String,
+    /// Window length, in minutes.
+    pub length_minutes: u32,
+    /// Time zone for this window, if different from the `periodic` one.
+    pub time_zone: Option<String>,
 }
 
 impl UpdateInput {
     fn from_fragments(fragments: Vec<fragments::UpdateFragment>) -> Self {
         let mut allow_downgrade = false;
         let mut enabled = true;
+        let mut block_on_layered = false;
+        let mut blackout = BlackoutInput::default();
+        let mut check_on_network_up = false;
+        let mut cleanup_rollback_after_days = 0;
+        let mut connectivity_gate = ConnectivityGateInput::default();
+        let mut download = PeriodicInput::default();
+        let mut fetch_only_window = false;
+        let mut finalize_health_checks = vec![];
+        let mut max_postponements = DEFAULT_MAX_POSTPONEMENTS;
+        let mut postponement_delay_minutes = NonZeroU64::new(DEFAULT_POSTPONEMENT_DELAY_MINUTES)
+            .expect("non-zero postponement delay");
+        let mut post_boot_hooks = vec![];
+        let mut reboot_countdown_minutes = 0;
+        let mut reboot_lock_path = String::new();
+        let mut require_reboot_approval = false;
+        let mut skip_versions = vec![];
+        let mut source = String::new();
+        let mut steady_report_max_failures = 0;
         let mut strategy = String::new();
+        let mut verify_remote = String::new();
+        let mut verify_signature = false;
         let mut fleet_lock = FleetLockInput {
             base_url: String::new(),
+            confirm_updates: false,
+            report_health: true,
+            token_path: String::new(),
+            client_cert: String::new(),
+            client_key: String::new(),
+            groups: vec![],
         };
-        let mut periodic = PeriodicInput {
-            intervals: vec![],
-            time_zone: "UTC".to_string(),
+        let mut logind_reboot = LogindRebootInput::default();
+        let mut ostree_remote = OstreeRemoteInput {
+            refspec: String::new(),
         };
+        let mut outcome_report = OutcomeReportInput::default();
+        let mut periodic = PeriodicInput::default();
+        let mut quiesce = QuiesceInput::default();
+        let mut static_graph = StaticGraphInput {
+            path: String::new(),
+        };
+        let mut webhook = WebhookInput::default();
+        let mut stream_switch = StreamSwitchInput::default();
+        let mut urgency = UrgencyInput::default();
+        let mut disk_space = DiskSpaceInput::default();
+        let mut downgrade_barrier = DowngradeBarrierInput::default();
 
         for snip in fragments {
             if let Some(a) = snip.allow_downgrade {
@@ -218,39 +960,284 @@ impl UpdateInput {
             if let Some(e) = snip.enabled {
                 enabled = e;
             }
+            if let Some(b) = snip.block_on_layered {
+                block_on_layered = b;
+            }
+            if let Some(f) = snip.fetch_only_window {
+                fetch_only_window = f;
+            }
+            if let Some(hc) = snip.finalize_health_checks {
+                finalize_health_checks = hc;
+            }
+            if let Some(m) = snip.max_postponements {
+                max_postponements = m;
+            }
+            if let Some(d) = snip.postponement_delay_minutes {
+                postponement_delay_minutes = d;
+            }
+            if let Some(h) = snip.post_boot_hooks {
+                post_boot_hooks = h;
+            }
+            if let Some(c) = snip.reboot_countdown_minutes {
+                reboot_countdown_minutes = c;
+            }
+            if let Some(p) = snip.reboot_lock_path {
+                reboot_lock_path = p;
+            }
+            if let Some(r) = snip.require_reboot_approval {
+                require_reboot_approval = r;
+            }
+            if let Some(s) = snip.skip_versions {
+                skip_versions = s;
+            }
+            if let Some(periods) = snip.blackout.and_then(|b| b.period) {
+                for p in periods {
+                    blackout.periods.push(BlackoutPeriodInput {
+                        start: p.start,
+                        end: p.end,
+                        reason: p.reason.unwrap_or_default(),
+                    });
+                }
+            }
+            if let Some(units) = snip.quiesce.and_then(|q| q.unit) {
+                for u in units {
+                    quiesce.units.push(QuiesceUnitInput {
+                        name: u.name,
+                        restart_on_abort: u.restart_on_abort.unwrap_or(false),
+                        timeout_secs: u.timeout_secs.unwrap_or_else(|| {
+                            NonZeroU64::new(DEFAULT_STOP_TIMEOUT_SECS).expect("non-zero timeout")
+                        }),
+                    });
+                }
+            }
+            if let Some(c) = snip.check_on_network_up {
+                check_on_network_up = c;
+            }
+            if let Some(d) = snip.cleanup_rollback_after_days {
+                cleanup_rollback_after_days = d;
+            }
+            if let Some(cg) = snip.connectivity_gate {
+                if let Some(p) = cg.probe {
+                    connectivity_gate.probe = p;
+                }
+                if let Some(t) = cg.timeout_secs {
+                    connectivity_gate.timeout_secs = t;
+                }
+            }
+            if let Some(s) = snip.source {
+                source = s;
+            }
+            if let Some(m) = snip.steady_report_max_failures {
+                steady_report_max_failures = m;
+            }
             if let Some(s) = snip.strategy {
                 strategy = s;
             }
+            if let Some(r) = snip.verify_remote {
+                verify_remote = r;
+            }
+            if let Some(v) = snip.verify_signature {
+                verify_signature = v;
+            }
             if let Some(fl) = snip.fleet_lock {
                 if let Some(b) = fl.base_url {
                     fleet_lock.base_url = b;
                 }
+                if let Some(c) = fl.confirm_updates {
+                    fleet_lock.confirm_updates = c;
+                }
+                if let Some(r) = fl.report_health {
+                    fleet_lock.report_health = r;
+                }
+                if let Some(t) = fl.token_path {
+                    fleet_lock.token_path = t;
+                }
+                if let Some(c) = fl.client_cert {
+                    fleet_lock.client_cert = c;
+                }
+                if let Some(k) = fl.client_key {
+                    fleet_lock.client_key = k;
+                }
+                if let Some(g) = fl.groups {
+                    fleet_lock.groups = g;
+                }
             }
-            if let Some(w) = snip.periodic {
-                if let Some(tz) = w.time_zone {
-                    periodic.time_zone = tz;
-                }
-                if let Some(win) = w.window {
-                    for entry in win {
-                        for day in entry.days {
-                            let interval = PeriodicIntervalInput {
-                                start_day: day,
-                                start_time: entry.start_time.clone(),
-                                length_minutes: entry.length_minutes,
-                            };
-                            periodic.intervals.push(interval);
-                        }
-                    }
+            if let Some(lr) = snip.logind_reboot {
+                if let Some(e) = lr.enabled {
+                    logind_reboot.enabled = e;
+                }
+                if let Some(l) = lr.lead_time_minutes {
+                    logind_reboot.lead_time_minutes = l;
+                }
+            }
+            if let Some(or) = snip.ostree_remote {
+                if let Some(r) = or.refspec {
+                    ostree_remote.refspec = r;
+                }
+            }
+            if let Some(or) = snip.outcome_report {
+                if let Some(u) = or.url {
+                    outcome_report.url = u;
+                }
+                if let Some(b) = or.batch_size {
+                    outcome_report.batch_size = b;
+                }
+                if let Some(q) = or.max_queued {
+                    outcome_report.max_queued = q;
+                }
+                if let Some(a) = or.max_age_hours {
+                    outcome_report.max_age_hours = a;
                 }
             }
+            if let Some(sg) = snip.static_graph {
+                if let Some(p) = sg.path {
+                    static_graph.path = p;
+                }
+            }
+            if let Some(wh) = snip.webhook {
+                if let Some(u) = wh.url {
+                    webhook.url = u;
+                }
+                if let Some(m) = wh.max_queued {
+                    webhook.max_queued = m;
+                }
+                if let Some(a) = wh.max_age_hours {
+                    webhook.max_age_hours = a;
+                }
+            }
+            if let Some(ss) = snip.stream_switch {
+                if let Some(r) = ss.refspec {
+                    stream_switch.refspec = r;
+                }
+                if let Some(a) = ss.allowed_streams {
+                    stream_switch.allowed_streams = a;
+                }
+            }
+            if let Some(d) = snip.disk_space {
+                if let Some(b) = d.boot_min_free_mib {
+                    disk_space.boot_min_free_mib = b;
+                }
+                if let Some(c) = d.cleanup_command {
+                    disk_space.cleanup_command = c;
+                }
+                if let Some(s) = d.sysroot_min_free_mib {
+                    disk_space.sysroot_min_free_mib = s;
+                }
+            }
+            if let Some(b) = snip.downgrade_barrier {
+                if let Some(v) = b.min_version {
+                    downgrade_barrier.min_version = v;
+                }
+                if let Some(d) = b.max_age_days {
+                    downgrade_barrier.max_age_days = d;
+                }
+            }
+            if let Some(u) = snip.urgency {
+                if let Some(e) = u.enabled {
+                    urgency.enabled = e;
+                }
+                if let Some(s) = u.min_severity {
+                    urgency.min_severity = s;
+                }
+                if let Some(g) = u.grace_period_minutes {
+                    urgency.grace_period_minutes = g;
+                }
+            }
+            if let Some(d) = snip.download {
+                download.merge_fragment(d);
+            }
+            if let Some(w) = snip.periodic {
+                periodic.merge_fragment(w);
+            }
         }
 
         Self {
             allow_downgrade,
             enabled,
+            block_on_layered,
+            blackout,
+            check_on_network_up,
+            cleanup_rollback_after_days,
+            connectivity_gate,
+            download,
+            fetch_only_window,
+            finalize_health_checks,
+            max_postponements,
+            postponement_delay_minutes,
+            post_boot_hooks,
+            reboot_countdown_minutes,
+            reboot_lock_path,
+            require_reboot_approval,
+            skip_versions,
+            source,
+            steady_report_max_failures,
             strategy,
+            verify_remote,
+            verify_signature,
+            disk_space,
+            downgrade_barrier,
             fleet_lock,
+            logind_reboot,
+            ostree_remote,
+            outcome_report,
             periodic,
+            quiesce,
+            static_graph,
+            webhook,
+            stream_switch,
+            urgency,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(content: &str) -> fragments::ConfigFragment {
+        toml::from_str(content).unwrap()
+    }
+
+    #[test]
+    fn config_hash_effective_values() {
+        let single = vec![parse(
+            r#"
+[identity]
+group = "workers"
+[updates]
+strategy = "periodic"
+"#,
+        )];
+        let split = vec![
+            parse(
+                r#"
+[identity]
+group = "default"
+[updates]
+strategy = "periodic"
+"#,
+            ),
+            parse(
+                r#"
+[identity]
+group = "workers"
+"#,
+            ),
+        ];
+        let other = vec![parse(
+            r#"
+[identity]
+group = "canaries"
+[updates]
+strategy = "periodic"
+"#,
+        )];
+
+        let hash = ConfigInput::merge_fragments(single).config_hash().unwrap();
+        assert!((0..(1 << 48)).contains(&hash));
+        let split_hash = ConfigInput::merge_fragments(split).config_hash().unwrap();
+        assert_eq!(hash, split_hash);
+        let other_hash = ConfigInput::merge_fragments(other).config_hash().unwrap();
+        assert_ne!(hash, other_hash);
+    }
+}
@@ -6,80 +6,448 @@
 //!  * Settings: validated settings for the agent.
 
 /// TOML structures.
-pub(crate) mod fragments;
+pub mod fragments;
 
 /// Configuration fragments.
-pub(crate) mod inputs;
+pub mod inputs;
 
-use crate::cincinnati::Cincinnati;
+/// Provenance of configuration keys.
+pub mod provenance;
+
+use crate::blackout::BlackoutPeriods;
+use crate::connectivity::ConnectivityGate;
+use crate::disk_space::DiskSpaceCheck;
+use crate::downgrade::DowngradeBarrier;
+use crate::download::DownloadSchedule;
+use crate::health_checks::HealthChecks;
 use crate::identity::Identity;
+use crate::messages::MessageTemplates;
+use crate::network::NetworkSettings;
+use crate::outcome_report::OutcomeReporter;
+use crate::post_boot::PostBootHooks;
+use crate::quiesce::ServiceQuiesce;
+use crate::rpm_ostree::{Backend, Timeouts};
 use crate::strategy::UpdateStrategy;
-use crate::update_agent;
-use anyhow::Result;
+use crate::stream_switch::StreamSwitch;
+use crate::telemetry::TelemetrySettings;
+use crate::update_source::{self, UpdateSource};
+use crate::urgency::UrgencyOverride;
+use crate::webhook::Webhook;
+use anyhow::{Context, Result};
 use fn_error_context::context;
+use prometheus::{IntGauge, IntGaugeVec};
 use serde::Serialize;
-use std::num::NonZeroU64;
+use std::convert::TryFrom;
+use std::num::{NonZeroU32, NonZeroU64};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 use structopt::clap::crate_name;
 
+/// Maximum countdown before rebooting into a finalized update (in minutes).
+const MAX_REBOOT_COUNTDOWN_MINUTES: u64 = 24 * 60; // 1 day.
+/// Maximum random delay before the first update check (in seconds).
+const MAX_FIRST_CHECK_SPLAY_SECS: u64 = 24 * 60 * 60; // 1 day.
+
+/// Maximum healthy time before cleaning up the rollback deployment (in days).
+const MAX_CLEANUP_ROLLBACK_AFTER_DAYS: u64 = 3650; // 10 years.
+
+lazy_static::lazy_static! {
+    static ref ALLOW_DOWNGRADE: IntGauge = register_int_gauge!(opts!(
+        "zincati_update_agent_updates_allow_downgrade",
+        "Whether downgrades via auto-updates logic are allowed."
+    )).unwrap();
+    static ref CONFIG_HASH: IntGauge = register_int_gauge!(opts!(
+        "zincati_config_hash",
+        "Hash of the effective (merged) configuration."
+    )).unwrap();
+    static ref CONFIG_INFO: IntGaugeVec = register_int_gauge_vec!(
+        "zincati_config_info",
+        "Information about the effective (merged) configuration.",
+        &["strategy", "stream", "rollout_wariness", "allow_downgrade"]
+    ).unwrap();
+    static ref UPDATES_ENABLED: IntGauge = register_int_gauge!(opts!(
+        "zincati_update_agent_updates_enabled",
+        "Whether auto-updates logic is enabled."
+    )).unwrap();
+}
+
 /// Runtime configuration for the agent.
 ///
 /// It holds validated agent configuration.
 #[derive(Debug, Serialize)]
-pub(crate) struct Settings {
+pub struct Settings {
     /// Whether to enable automatic downgrades.
-    pub(crate) allow_downgrade: bool,
+    pub allow_downgrade: bool,
     /// Whether to enable auto-updates logic.
-    pub(crate) enabled: bool,
+    pub enabled: bool,
+    /// Whether to block updates when packages are layered on the booted deployment.
+    pub block_on_layered: bool,
+    /// Periods during which finalization is always denied, if any.
+    pub blackout: Option<BlackoutPeriods>,
+    /// Whether to check for updates as soon as the network comes online.
+    pub check_on_network_up: bool,
+    /// Healthy time on a new release before cleaning up the rollback
+    /// deployment, if enabled.
+    pub cleanup_rollback_after: Option<Duration>,
+    /// Connectivity gate for finalization, if any.
+    pub connectivity_gate: Option<ConnectivityGate>,
+    /// Maximum delay before retrying a failed deploy.
+    pub deploy_retry_max_delay: Duration,
+    /// Free disk space checks before staging, if any.
+    pub disk_space: Option<DiskSpaceCheck>,
+    /// Barriers against downgrading too far back, if any.
+    pub downgrade_barrier: Option<DowngradeBarrier>,
+    /// Windows for downloading updates, if any.
+    pub download_schedule: Option<DownloadSchedule>,
+    /// Whether to defer staging until close to a finalization window.
+    pub fetch_only_window: bool,
+    /// Maximum random delay before the first update check, if enabled.
+    pub first_check_splay: Option<Duration>,
+    /// Lead time for reboots scheduled via logind, if enabled.
+    pub logind_reboot_lead: Option<Duration>,
+    /// Health checks before finalization, if any.
+    pub health_checks: Option<HealthChecks>,
+    /// Maximum number of finalization postponements due to active user sessions.
+    pub max_postponements: u8,
+    /// Reporting of update outcomes, if configured.
+    pub outcome_report: Option<OutcomeReporter>,
+    /// Delay between finalization postponements.
+    pub postponement_delay: Duration,
+    /// Hooks to run after booting into a finalized update, if any.
+    pub post_boot_hooks: Option<PostBootHooks>,
+    /// Services stopped before finalization, if any.
+    pub quiesce: Option<ServiceQuiesce>,
+    /// Countdown before rebooting into a finalized update, if enabled.
+    pub reboot_countdown: Option<Duration>,
+    /// Lock file shared with other reboot managers, if any.
+    pub reboot_lock_path: Option<PathBuf>,
+    /// Whether to require an explicit approval before finalization.
+    pub require_reboot_approval: bool,
+    /// Whether to disable rpm-ostree automatic updates if they conflict.
+    pub reconcile_rpm_ostree_policy: bool,
+    /// Backend used to interact with rpm-ostree.
+    pub rpm_ostree_backend: Backend,
+    /// Maximum number of queued rpm-ostree operations.
+    pub rpm_ostree_max_queued: usize,
+    /// Timeouts for rpm-ostree operations.
+    pub rpm_ostree_timeouts: Timeouts,
     /// Agent timing, steady state refresh period.
-    pub(crate) steady_interval_secs: NonZeroU64,
-    /// Cincinnati configuration.
-    pub(crate) cincinnati: Cincinnati,
+    pub steady_interval_secs: NonZeroU64,
+    /// Consecutive failures to report steady state before polling for updates
+    /// in degraded mode, if any.
+    pub steady_report_max_failures: Option<NonZeroU32>,
+    /// Interval between self-tests of control surfaces, if enabled.
+    pub self_test_interval: Option<Duration>,
+    /// Update source (e.g. Cincinnati) configuration.
+    pub source: Box<dyn UpdateSource>,
     /// Agent configuration.
-    pub(crate) identity: Identity,
+    pub identity: Identity,
+    /// Templates for user-facing messages.
+    pub messages: MessageTemplates,
+    /// Outbound network configuration.
+    pub network: NetworkSettings,
     /// Agent update strategy.
-    pub(crate) strategy: UpdateStrategy,
+    pub strategy: UpdateStrategy,
+    /// Switching to other update streams, if configured.
+    pub stream_switch: Option<StreamSwitch>,
+    /// Override of the update strategy for urgent releases, if enabled.
+    pub urgency: Option<UrgencyOverride>,
+    /// OSTree remote to check for the target release before fetching it, if any.
+    pub verify_remote: Option<String>,
+    /// Whether to verify the signature of the target release on `verify_remote`.
+    pub verify_signature: bool,
+    /// Metrics exporter over TCP, if enabled.
+    pub telemetry: Option<TelemetrySettings>,
+    /// Webhook for agent events, if any.
+    pub webhook: Option<Webhook>,
+    /// Hash of the effective configuration inputs.
+    pub config_hash: i64,
+    /// Effective configuration inputs, as JSON.
+    #[serde(skip)]
+    pub effective_config: String,
 }
 
 impl Settings {
     /// Assemble runtime settings.
     #[context("failed to assemble configuration settings")]
-    pub(crate) fn assemble() -> Result<Self> {
-        let prefixes = vec![
-            "/usr/lib/".to_string(),
-            "/run/".to_string(),
-            "/etc/".to_string(),
-        ];
-        let common_path = format!("{}/config.d/", crate_name!());
-        let extensions = vec!["toml".to_string()];
-        let cfg = inputs::ConfigInput::read_configs(prefixes, &common_path, extensions)?;
+    pub fn assemble() -> Result<Self> {
+        let cfg = read_inputs()?;
         Self::validate(cfg)
     }
 
     /// Refresh settings-related metrics values.
-    pub(crate) fn refresh_metrics(&self) {
+    pub fn refresh_metrics(&self) {
         // TODO(lucab): consider adding more metrics here (e.g. steady interval).
-        update_agent::UPDATES_ENABLED.set(i64::from(self.enabled));
-        update_agent::ALLOW_DOWNGRADE.set(i64::from(self.allow_downgrade));
+        CONFIG_HASH.set(self.config_hash);
+        let wariness = self
+            .identity
+            .rollout_wariness
+            .map(|rw| format!("{:.06}", rw))
+            .unwrap_or_default();
+        CONFIG_INFO.reset();
+        CONFIG_INFO
+            .with_label_values(&[
+                self.strategy.configuration_label(),
+                &self.identity.stream,
+                &wariness,
+                &self.allow_downgrade.to_string(),
+            ])
+            .set(1);
+        UPDATES_ENABLED.set(i64::from(self.enabled));
+        ALLOW_DOWNGRADE.set(i64::from(self.allow_downgrade));
 
+        update_source::refresh_metrics(self.source.as_ref());
         self.strategy.refresh_metrics();
     }
 
     /// Validate config and return a valid agent settings.
-    fn validate(cfg: inputs::ConfigInput) -> Result<Self> {
+    pub fn validate(cfg: inputs::ConfigInput) -> Result<Self> {
+        let config_hash = cfg.config_hash()?;
+        let effective_config =
+            serde_json::to_string(&cfg).context("failed to serialize configuration")?;
         let allow_downgrade = cfg.updates.allow_downgrade;
         let enabled = cfg.updates.enabled;
+        let block_on_layered = cfg.updates.block_on_layered;
+        let check_on_network_up = cfg.updates.check_on_network_up;
+        let fetch_only_window = cfg.updates.fetch_only_window;
+        let max_postponements = cfg.updates.max_postponements;
+        let require_reboot_approval = cfg.updates.require_reboot_approval;
+        // Reboots can only be approved over D-Bus.
+        #[cfg(not(feature = "dbus"))]
+        if require_reboot_approval {
+            anyhow::bail!("reboot approval not supported, agent built without `dbus` feature");
+        }
+        let steady_report_max_failures = NonZeroU32::new(cfg.updates.steady_report_max_failures);
+        let reboot_lock_path = match cfg.updates.reboot_lock_path.as_str() {
+            "" => None,
+            p if Path::new(p).is_absolute() => Some(PathBuf::from(p)),
+            p => anyhow::bail!("reboot lock path '{}' is not absolute", p),
+        };
+        let verify_remote = match cfg.updates.verify_remote.trim() {
+            "" => None,
+            r if r.contains(|c: char| c == ':' || c == '/' || c.is_whitespace()) => {
+                anyhow::bail!("invalid OSTree remote name '{}'", r)
+            }
+            r => Some(r.to_string()),
+        };
+        let verify_signature = cfg.updates.verify_signature;
+        if verify_signature && verify_remote.is_none() {
+            anyhow::bail!(
+                "signature verification requires an OSTree remote to check (verify_remote)"
+            );
+        }
+        let postponement_delay = {
+            let minutes = cfg.updates.postponement_delay_minutes.get();
+            Duration::from_secs(minutes.saturating_mul(60))
+        };
+        let logind_reboot_lead = if cfg.updates.logind_reboot.enabled {
+            let minutes = cfg.updates.logind_reboot.lead_time_minutes.get();
+            Some(Duration::from_secs(minutes.saturating_mul(60)))
+        } else {
+            None
+        };
+        let reboot_countdown = match cfg.updates.reboot_countdown_minutes {
+            0 => None,
+            minutes => {
+                anyhow::ensure!(
+                    minutes <= MAX_REBOOT_COUNTDOWN_MINUTES,
+                    "reboot countdown longer than {} minutes",
+                    MAX_REBOOT_COUNTDOWN_MINUTES
+                );
+                anyhow::ensure!(
+                    logind_reboot_lead.is_none(),
+                    "reboot countdown conflicts with reboots scheduled via logind"
+                );
+                Some(Duration::from_secs(minutes.saturating_mul(60)))
+            }
+        };
+        let reconcile_rpm_ostree_policy = cfg.agent.reconcile_rpm_ostree_policy;
+        let rpm_ostree_backend = Backend::with_config(&cfg.agent.rpm_ostree_backend)?;
+        let rpm_ostree_max_queued =
+            usize::try_from(cfg.agent.rpm_ostree_max_queued.get()).unwrap_or(usize::MAX);
+        let rpm_ostree_timeouts = Timeouts {
+            stage: Duration::from_secs(cfg.agent.rpm_ostree_stage_timeout_secs.get()),
+            finalize: Duration::from_secs(cfg.agent.rpm_ostree_finalize_timeout_secs.get()),
+            status: Duration::from_secs(cfg.agent.rpm_ostree_status_timeout_secs.get()),
+        };
         let steady_interval_secs = cfg.agent.steady_interval_secs;
+        let deploy_retry_max_delay =
+            Duration::from_secs(cfg.agent.deploy_retry_max_delay_secs.get());
+        let first_check_splay = match cfg.agent.first_check_splay_secs {
+            0 => None,
+            secs if secs > MAX_FIRST_CHECK_SPLAY_SECS => anyhow::bail!(
+                "first check splay of {} seconds is longer than {} seconds",
+                secs,
+                MAX_FIRST_CHECK_SPLAY_SECS
+            ),
+            secs => Some(Duration::from_secs(secs)),
+        };
+        let cleanup_rollback_after = match cfg.updates.cleanup_rollback_after_days {
+            0 => None,
+            days if days > MAX_CLEANUP_ROLLBACK_AFTER_DAYS => anyhow::bail!(
+                "rollback cleanup after {} days is later than {} days",
+                days,
+                MAX_CLEANUP_ROLLBACK_AFTER_DAYS
+            ),
+            days => Some(Duration::from_secs(days * 24 * 60 * 60)),
+        };
+        let self_test_interval = match cfg.agent.self_test_interval_secs {
+            0 => None,
+            secs => Some(Duration::from_secs(secs)),
+        };
         let identity = Identity::with_config(cfg.identity)?;
-        let strategy = UpdateStrategy::with_config(cfg.updates, &identity)?;
-        let cincinnati = Cincinnati::with_config(cfg.cincinnati, &identity)?;
+        let messages = MessageTemplates::with_config(cfg.messages)?;
+        let network = NetworkSettings::with_config(cfg.network)?;
+        let blackout = BlackoutPeriods::with_config(cfg.updates.blackout.clone())?;
+        let connectivity_gate =
+            ConnectivityGate::with_config(cfg.updates.connectivity_gate.clone(), &network)?;
+        let disk_space = DiskSpaceCheck::with_config(cfg.updates.disk_space.clone())?;
+        let downgrade_barrier =
+            DowngradeBarrier::with_config(cfg.updates.downgrade_barrier.clone())?;
+        let download_schedule = DownloadSchedule::with_config(cfg.updates.download.clone())?;
+        let health_checks = HealthChecks::with_config(cfg.updates.finalize_health_checks.clone())?;
+        let webhook = Webhook::with_config(cfg.updates.webhook.clone(), &identity, &network)?;
+        let outcome_report =
+            OutcomeReporter::with_config(cfg.updates.outcome_report.clone(), &network)?;
+        let post_boot_hooks =
+            PostBootHooks::with_config(cfg.updates.post_boot_hooks.clone(), &network)?;
+        let quiesce = ServiceQuiesce::with_config(cfg.updates.quiesce.clone())?;
+        let source = update_source::with_config(cfg.cincinnati, &cfg.updates, &identity, &network)?;
+        let stream_switch =
+            StreamSwitch::with_config(cfg.updates.stream_switch.clone(), &identity)?;
+        let urgency = UrgencyOverride::with_config(cfg.updates.urgency.clone())?;
+        let strategy = UpdateStrategy::with_config(cfg.updates, &identity, &network)?;
+        let telemetry = TelemetrySettings::with_config(cfg.telemetry)?;
+        #[cfg(not(feature = "metrics"))]
+        if telemetry.is_some() {
+            anyhow::bail!("metrics exporter not supported, agent built without `metrics` feature");
+        }
 
         Ok(Self {
             allow_downgrade,
             enabled,
+            block_on_layered,
+            blackout,
+            check_on_network_up,
+            cleanup_rollback_after,
+            connectivity_gate,
+            deploy_retry_max_delay,
+            disk_space,
+            downgrade_barrier,
+            download_schedule,
+            fetch_only_window,
+            first_check_splay,
+            health_checks,
+            logind_reboot_lead,
+            max_postponements,
+            outcome_report,
+            postponement_delay,
+            post_boot_hooks,
+            quiesce,
+            reboot_countdown,
+            reboot_lock_path,
+            require_reboot_approval,
+            reconcile_rpm_ostree_policy,
+            rpm_ostree_backend,
+            rpm_ostree_max_queued,
+            rpm_ostree_timeouts,
             steady_interval_secs,
-            cincinnati,
+            steady_report_max_failures,
+            self_test_interval,
+            source,
             identity,
+            messages,
+            network,
             strategy,
+            stream_switch,
+            urgency,
+            verify_remote,
+            verify_signature,
+            telemetry,
+            webhook,
+            config_hash,
+            effective_config,
         })
     }
+
+    /// Build mock settings, for tests.
+    ///
+    /// These use the mock identity and default values, with a local
+    /// Cincinnati service.
+    #[cfg(any(test, feature = "e2e-tests"))]
+    pub fn mock_default() -> Self {
+        let mut cfg = inputs::ConfigInput::merge_fragments(vec![]);
+        cfg.cincinnati.base_url = "http://localhost".to_string();
+        let identity = Identity::mock_default();
+        let network = NetworkSettings::default();
+        let source =
+            update_source::with_config(cfg.cincinnati, &cfg.updates, &identity, &network).unwrap();
+        let max_postponements = cfg.updates.max_postponements;
+        let strategy = UpdateStrategy::with_config(cfg.updates, &identity, &network).unwrap();
+
+        Self {
+            allow_downgrade: false,
+            enabled: true,
+            block_on_layered: false,
+            blackout: None,
+            check_on_network_up: false,
+            cleanup_rollback_after: None,
+            connectivity_gate: None,
+            deploy_retry_max_delay: Duration::from_secs(3600),
+            disk_space: None,
+            downgrade_barrier: None,
+            download_schedule: None,
+            fetch_only_window: false,
+            first_check_splay: None,
+            health_checks: None,
+            logind_reboot_lead: None,
+            max_postponements,
+            outcome_report: None,
+            postponement_delay: Duration::from_secs(60),
+            post_boot_hooks: None,
+            quiesce: None,
+            reboot_countdown: None,
+            reboot_lock_path: None,
+            require_reboot_approval: false,
+            reconcile_rpm_ostree_policy: false,
+            rpm_ostree_backend: Backend::Cli,
+            rpm_ostree_max_queued: 8,
+            rpm_ostree_timeouts: Timeouts::default(),
+            steady_interval_secs: NonZeroU64::new(3600).unwrap(),
+            steady_report_max_failures: None,
+            self_test_interval: None,
+            source,
+            identity,
+            messages: MessageTemplates::default(),
+            network,
+            strategy,
+            stream_switch: None,
+            urgency: None,
+            verify_remote: None,
+            verify_signature: false,
+            telemetry: None,
+            webhook: None,
+            config_hash: 0,
+            effective_config: String::new(),
+        }
+    }
+}
+
+/// Read and merge configuration fragments from all system locations.
+pub fn read_inputs() -> Result<inputs::ConfigInput> {
+    let (cfg, _) = read_inputs_with_provenance()?;
+    Ok(cfg)
+}
+
+/// Read and merge configuration fragments from all system locations,
+/// also recording which keys are set by each fragment.
+pub fn read_inputs_with_provenance() -> Result<(inputs::ConfigInput, provenance::Provenance)> {
+    let prefixes = vec![
+        "/usr/lib/".to_string(),
+        "/run/".to_string(),
+        "/etc/".to_string(),
+    ];
+    let common_path = format!("{}/config.d/", crate_name!());
+    let extensions = vec!["toml".to_string()];
+    inputs::ConfigInput::read_configs_with_provenance(prefixes, &common_path, extensions)
 }
@@ -0,0 +1,131 @@
+//! Provenance of configuration keys.
+//!
+//! Configuration is merged from fragments across several directories, thus
+//! it is not always obvious where an effective value comes from. This
+//! records, for each fragment, the keys it sets and the keys it sets but
+//! are unknown (thus ignored).
+
+use crate::config::fragments::ConfigFragment;
+use anyhow::{Context, Result};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// Keys set by a single configuration fragment.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FragmentKeys {
+    /// Path to the fragment.
+    pub path: PathBuf,
+    /// Known keys set by the fragment, in dotted notation.
+    pub keys: Vec<String>,
+    /// Unknown keys set by the fragment, in dotted notation.
+    pub unknown: Vec<String>,
+}
+
+/// Provenance of configuration keys, across all fragments.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Provenance {
+    /// Keys set by each fragment, in merge order.
+    pub fragments: Vec<FragmentKeys>,
+}
+
+impl Provenance {
+    /// Record the keys set by a fragment, given its raw and parsed content.
+    pub(crate) fn record(
+        &mut self,
+        path: &Path,
+        raw: &toml::Value,
+        parsed: &ConfigFragment,
+    ) -> Result<()> {
+        let known = toml::Value::try_from(parsed).context("failed to serialize fragment")?;
+        let mut known_keys = vec![];
+        flatten("", &known, &mut known_keys);
+        let mut raw_keys = vec![];
+        flatten("", raw, &mut raw_keys);
+
+        let (keys, unknown) = raw_keys.into_iter().partition(|k| known_keys.contains(k));
+        self.fragments.push(FragmentKeys {
+            path: path.to_path_buf(),
+            keys,
+            unknown,
+        });
+        Ok(())
+    }
+
+    /// Return all keys set by fragments, each with the fragments setting
+    /// it (in merge order, thus the last one wins).
+    pub fn sources(&self) -> BTreeMap<&str, Vec<&Path>> {
+        let mut sources: BTreeMap<&str, Vec<&Path>> = BTreeMap::new();
+        for frag in &self.fragments {
+            for key in &frag.keys {
+                sources.entry(key).or_default().push(&frag.path);
+            }
+        }
+        sources
+    }
+}
+
+/// Collect the dotted paths of all leaf values in `value`.
+///
+/// Arrays (including arrays of tables) are leaves, as they are replaced
+/// as a whole when merging fragments.
+fn flatten(prefix: &str, value: &toml::Value, out: &mut Vec<String>) {
+    match value {
+        toml::Value::Table(table) => {
+            for (k, v) in table {
+                let key = match prefix {
+                    "" => k.clone(),
+                    p => format!("{}.{}", p, k),
+                };
+                flatten(&key, v, out);
+            }
+        }
+        _ => out.push(prefix.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_provenance() {
+        let first = r#"
+[updates]
+strategy = "periodic"
+enabled = true
+
+[[updates.periodic.window]]
+days = [ "Sat" ]
+start_time = "23:00"
+length_minutes = 60
+"#;
+        let second = r#"
+[updates]
+enabled = false
+stratgy = "immediate"
+"#;
+        let mut provenance = Provenance::default();
+        for (path, content) in &[("/usr/lib/10.toml", first), ("/etc/90.toml", second)] {
+            let raw: toml::Value = toml::from_str(content).unwrap();
+            let parsed: ConfigFragment = toml::from_str(content).unwrap();
+            provenance.record(Path::new(path), &raw, &parsed).unwrap();
+        }
+
+        assert!(provenance.fragments[0].unknown.is_empty());
+        assert_eq!(provenance.fragments[1].unknown, vec!["updates.stratgy"]);
+
+        let sources = provenance.sources();
+        assert_eq!(
+            sources.keys().copied().collect::<Vec<_>>(),
+            vec![
+                "updates.enabled",
+                "updates.periodic.window",
+                "updates.strategy"
+            ]
+        );
+        assert_eq!(
+            sources["updates.enabled"],
+            vec![Path::new("/usr/lib/10.toml"), Path::new("/etc/90.toml")]
+        );
+    }
+}
@@ -0,0 +1,283 @@
+//! Connectivity gate for updates finalization.
+//!
+//! Nodes at remote sites are often only reachable through a VPN or a
+//! management tunnel. Finalizing an update reboots the node, and if the
+//! reboot goes wrong nobody can reach the box unless such out-of-band
+//! access is working. The gate probes a configured target and holds
+//! finalization back until it is reachable.
+
+use crate::config::inputs;
+use crate::network::NetworkSettings;
+use anyhow::{Context, Result};
+use fn_error_context::context;
+use futures::prelude::*;
+use prometheus::{IntCounter, IntGauge};
+use reqwest::Url;
+use serde::Serialize;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::pin::Pin;
+use std::time::Duration;
+
+/// Default timeout for a single probe (in seconds).
+pub const DEFAULT_PROBE_TIMEOUT_SECS: u64 = 10;
+
+lazy_static::lazy_static! {
+    static ref PROBES: IntCounter = register_int_counter!(opts!(
+        "zincati_connectivity_gate_probes_total",
+        "Total number of connectivity probes before finalization."
+    )).unwrap();
+    static ref PROBE_FAILURES: IntCounter = register_int_counter!(opts!(
+        "zincati_connectivity_gate_probe_failures_total",
+        "Total number of failed connectivity probes before finalization."
+    )).unwrap();
+    static ref REACHABLE: IntGauge = register_int_gauge!(opts!(
+        "zincati_connectivity_gate_reachable",
+        "Whether the connectivity probe target was reachable on last check."
+    )).unwrap();
+}
+
+/// Target to probe for connectivity.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum Probe {
+    /// ICMP echo request to a host.
+    Icmp {
+        /// Target host.
+        host: String,
+    },
+    /// TCP connection to a host and port.
+    Tcp {
+        /// Target host.
+        host: String,
+        /// Target port.
+        port: u16,
+    },
+    /// HTTP(S) request, expecting a successful status code.
+    Http {
+        /// Target URL.
+        url: Url,
+    },
+}
+
+impl Probe {
+    /// Parse a probe target, in `icmp://host`, `tcp://host:port` or
+    /// `http(s)://host/path` format.
+    fn parse(input: &str) -> Result<Self> {
+        let url = Url::parse(input.trim()).context("failed to parse probe target")?;
+        let host = url
+            .host_str()
+            .filter(|h| !h.is_empty())
+            .with_context(|| format!("missing host in probe target '{}'", input))?
+            .to_string();
+
+        let probe = match url.scheme() {
+            "icmp" => Probe::Icmp { host },
+            "tcp" => {
+                let port = url
+                    .port()
+                    .with_context(|| format!("missing port in probe target '{}'", input))?;
+                Probe::Tcp { host, port }
+            }
+            "http" | "https" => Probe::Http { url },
+            s => anyhow::bail!("unsupported probe scheme '{}'", s),
+        };
+        Ok(probe)
+    }
+}
+
+/// Connectivity gate, checked before finalizing an update.
+#[derive(Clone, Debug, Serialize)]
+pub struct ConnectivityGate {
+    /// Target to probe.
+    pub probe: Probe,
+    /// Timeout for a single probe.
+    pub timeout: Duration,
+    /// HTTP client, for HTTP(S) probes.
+    #[serde(skip)]
+    hclient: reqwest::Client,
+}
+
+impl ConnectivityGate {
+    /// Process connectivity gate configuration.
+    ///
+    /// This returns `None` if no probe target is configured.
+    #[context("failed to validate connectivity gate configuration")]
+    pub fn with_config(
+        cfg: inputs::ConnectivityGateInput,
+        network: &NetworkSettings,
+    ) -> Result<Option<Self>> {
+        if cfg.probe.trim().is_empty() {
+            return Ok(None);
+        }
+
+        let probe = Probe::parse(&cfg.probe)?;
+        let timeout = Duration::from_secs(cfg.timeout_secs.get());
+        let hclient = network
+            .configure(reqwest::ClientBuilder::new())?
+            .timeout(timeout)
+            .build()?;
+        log::info!("finalization gated on connectivity to '{}'", cfg.probe);
+
+        let gate = Self {
+            probe,
+            timeout,
+            hclient,
+        };
+        Ok(Some(gate))
+    }
+
+    /// Check whether the probe target is reachable.
+    ///
+    /// Errors are logged and recorded, and result in a closed gate.
+    pub fn is_open(&self) -> Pin<Box<dyn Future<Output = bool>>> {
+        PROBES.inc();
+        let check: Pin<Box<dyn Future<Output = Result<()>>>> = match self.probe.clone() {
+            Probe::Http { url } => Box::pin(probe_http(self.hclient.clone(), url)),
+            probe => {
+                let timeout = self.timeout;
+                // The blocking task is only spawned once polled, within a runtime.
+                Box::pin(async move {
+                    tokio::task::spawn_blocking(move || match probe {
+                        Probe::Icmp { host } => probe_icmp(&host, timeout),
+                        Probe::Tcp { host, port } => probe_tcp(&host, port, timeout),
+                        Probe::Http { .. } => unreachable!(),
+                    })
+                    .await
+                    .context("failed to join connectivity probe")?
+                })
+            }
+        };
+
+        let gate = check.map(|res| match res {
+            Ok(_) => {
+                REACHABLE.set(1);
+                true
+            }
+            Err(e) => {
+                PROBE_FAILURES.inc();
+                REACHABLE.set(0);
+                log::warn!("connectivity gate closed: {:#}", e);
+                false
+            }
+        });
+        Box::pin(gate)
+    }
+}
+
+/// Probe a host via a single ICMP echo request, using `ping`.
+#[context("failed to ping '{}'", host)]
+fn probe_icmp(host: &str, timeout: Duration) -> Result<()> {
+    let out = std::process::Command::new("ping")
+        .arg("-c")
+        .arg("1")
+        .arg("-W")
+        .arg(timeout.as_secs().to_string())
+        .arg(host)
+        .output()
+        .context("failed to run 'ping' binary")?;
+
+    if !out.status.success() {
+        anyhow::bail!("no echo reply received");
+    }
+    Ok(())
+}
+
+/// Probe a host by opening a TCP connection to the given port.
+#[context("failed to connect to '{}:{}'", host, port)]
+fn probe_tcp(host: &str, port: u16, timeout: Duration) -> Result<()> {
+    let addrs = (host, port)
+        .to_socket_addrs()
+        .context("failed to resolve host")?;
+
+    let mut last_err = None;
+    for addr in addrs {
+        match TcpStream::connect_timeout(&addr, timeout) {
+            Ok(_) => return Ok(()),
+            Err(e) => last_err = Some(e),
+        }
+    }
+    match last_err {
+        Some(e) => Err(e.into()),
+        None => anyhow::bail!("no addresses resolved"),
+    }
+}
+
+/// Probe a URL, expecting a successful HTTP status code.
+async fn probe_http(hclient: reqwest::Client, url: Url) -> Result<()> {
+    let resp = hclient
+        .get(url.clone())
+        .send()
+        .await
+        .with_context(|| format!("failed to query '{}'", url))?;
+    resp.error_for_status()
+        .with_context(|| format!("failed to query '{}'", url))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::num::NonZeroU64;
+    use tokio::runtime as rt;
+
+    #[test]
+    fn parse_probe() {
+        assert_eq!(
+            Probe::parse("icmp://10.0.0.1").unwrap(),
+            Probe::Icmp {
+                host: "10.0.0.1".to_string()
+            }
+        );
+        assert_eq!(
+            Probe::parse("tcp://bastion.example.com:22").unwrap(),
+            Probe::Tcp {
+                host: "bastion.example.com".to_string(),
+                port: 22,
+            }
+        );
+        assert_eq!(
+            Probe::parse("https://mgmt.example.com/health").unwrap(),
+            Probe::Http {
+                url: Url::parse("https://mgmt.example.com/health").unwrap()
+            }
+        );
+
+        Probe::parse("tcp://bastion.example.com").unwrap_err();
+        Probe::parse("udp://bastion.example.com:53").unwrap_err();
+        Probe::parse("bastion.example.com").unwrap_err();
+    }
+
+    #[test]
+    fn gate_config() {
+        let network = NetworkSettings::default();
+        let unset = inputs::ConnectivityGateInput::default();
+        let gate = ConnectivityGate::with_config(unset, &network).unwrap();
+        assert!(gate.is_none());
+
+        let invalid = inputs::ConnectivityGateInput {
+            probe: "tcp://".to_string(),
+            ..Default::default()
+        };
+        ConnectivityGate::with_config(invalid, &network).unwrap_err();
+    }
+
+    #[test]
+    fn tcp_gate() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let cfg = inputs::ConnectivityGateInput {
+            probe: format!("tcp://127.0.0.1:{}", port),
+            timeout_secs: NonZeroU64::new(1).unwrap(),
+        };
+        let gate = ConnectivityGate::with_config(cfg, &NetworkSettings::default())
+            .unwrap()
+            .unwrap();
+
+        let runtime = rt::Runtime::new().unwrap();
+        assert!(runtime.block_on(gate.is_open()));
+
+        drop(listener);
+        assert!(!runtime.block_on(gate.is_open()));
+    }
+}
@@ -0,0 +1,316 @@
+//! Calendar-windows for events on specific days (monthly or one-off).
+//!
+//! This contains helper logic to handle intervals of time which do not recur
+//! every week:
+//!  * `DayRule`: a rule selecting days (e.g. first Sunday of the month).
+//!  * `DatedWindow`: a continuous interval starting on days selected by a rule.
+//!  * `DatedCalendar`: a set of such intervals.
+//!
+//! All computations happen on wall-clock (naive local) time.
+
+use crate::weekly::utils;
+use anyhow::{anyhow, bail, Result};
+use chrono::{DateTime, Datelike, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Timelike};
+use fn_error_context::context;
+use serde::Serialize;
+use std::time::Duration;
+
+/// How far ahead to look for the next window start, in days.
+const MAX_LOOKAHEAD_DAYS: i64 = 4 * 366;
+
+/// A rule selecting the days on which a window starts.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub enum DayRule {
+    /// Day of the month (months without such a day are skipped).
+    DayOfMonth(u32),
+    /// N-th weekday of the month (1 to 5), or last one (-1).
+    WeekdayOfMonth(i8, chrono::Weekday),
+    /// A specific calendar date.
+    Date(NaiveDate),
+}
+
+impl DayRule {
+    /// Parse a rule, in the form `day <N>`, `<ordinal> <weekday>` or `YYYY-MM-DD`.
+    ///
+    /// ## Example
+    ///
+    /// ```rust
+    /// # use zincati_core::dated::DayRule;
+    /// let rule = DayRule::parse("first Sun").unwrap();
+    /// assert_eq!(rule, DayRule::WeekdayOfMonth(1, chrono::Weekday::Sun));
+    /// ```
+    pub fn parse(input: &str) -> Result<Self> {
+        let invalid = || {
+            anyhow!(
+                "invalid day '{}': expected `day <N>`, `<ordinal> <weekday>` or `YYYY-MM-DD`",
+                input
+            )
+        };
+
+        if let Ok(date) = NaiveDate::parse_from_str(input, "%Y-%m-%d") {
+            return Ok(DayRule::Date(date));
+        }
+        let (first, second) = input.trim().split_once(' ').ok_or_else(invalid)?;
+        if first == "day" {
+            let day: u32 = second.trim().parse().map_err(|_| invalid())?;
+            if day == 0 || day > 31 {
+                bail!("invalid day of month: {}", day);
+            }
+            return Ok(DayRule::DayOfMonth(day));
+        }
+        let nth = match first.to_lowercase().as_str() {
+            "first" => 1,
+            "second" => 2,
+            "third" => 3,
+            "fourth" => 4,
+            "fifth" => 5,
+            "last" => -1,
+            _ => return Err(invalid()),
+        };
+        let weekday = utils::weekday_from_string(second.trim())?;
+        Ok(DayRule::WeekdayOfMonth(nth, weekday))
+    }
+
+    /// Return whether this rule selects `date`.
+    pub fn matches(&self, date: NaiveDate) -> bool {
+        match self {
+            DayRule::DayOfMonth(day) => date.day() == *day,
+            DayRule::WeekdayOfMonth(nth, weekday) => {
+                if date.weekday() != *weekday {
+                    return false;
+                }
+                if *nth < 0 {
+                    let next_week = date + chrono::Duration::days(7);
+                    return next_week.month() != date.month();
+                }
+                let week_of_month = date.day0() / 7 + 1;
+                week_of_month == u32::from(*nth as u8)
+            }
+            DayRule::Date(d) => date == *d,
+        }
+    }
+}
+
+/// A continuous interval, starting at a given time on days selected by a rule.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct DatedWindow {
+    /// Days on which the window starts.
+    rule: DayRule,
+    /// Start time, on selected days.
+    start: NaiveTime,
+    /// Window length, in minutes.
+    length_minutes: u32,
+}
+
+impl DatedWindow {
+    /// Create a window starting at `start_hour:start_minute` on days selected by `rule`.
+    #[context("failed to parse dated window")]
+    pub fn new(rule: DayRule, start_hour: u8, start_minute: u8, length: Duration) -> Result<Self> {
+        utils::check_duration(&length)?;
+        let start = NaiveTime::from_hms_opt(u32::from(start_hour), u32::from(start_minute), 0)
+            .ok_or_else(|| anyhow!("invalid start time: {}:{}", start_hour, start_minute))?;
+        // SAFETY: length is checked to be at most a week.
+        let length_minutes = (length.as_secs() / 60) as u32;
+
+        Ok(Self {
+            rule,
+            start,
+            length_minutes,
+        })
+    }
+
+    /// Return the start of this window on `date`, if the rule selects it.
+    fn start_on(&self, date: NaiveDate) -> Option<NaiveDateTime> {
+        if self.rule.matches(date) {
+            Some(date.and_time(self.start))
+        } else {
+            None
+        }
+    }
+
+    /// Return whether a window starting at `start` contains `timepoint`.
+    ///
+    /// As with weekly windows, the end minute is included.
+    fn contains(&self, start: NaiveDateTime, timepoint: NaiveDateTime) -> bool {
+        let end = start + chrono::Duration::minutes(i64::from(self.length_minutes));
+        start <= timepoint && timepoint <= end
+    }
+}
+
+/// Calendar for time-windows on specific days.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct DatedCalendar {
+    /// A set of (possibly overlapping) windows.
+    windows: Vec<DatedWindow>,
+}
+
+impl DatedCalendar {
+    /// Create a calendar from a vector of dated windows.
+    pub fn new(windows: Vec<DatedWindow>) -> Self {
+        Self { windows }
+    }
+
+    /// Return true if the calendar contains no time-windows.
+    pub fn is_empty(&self) -> bool {
+        self.windows.is_empty()
+    }
+
+    /// Return the minutes elapsed since the start of the window containing
+    /// the given datetime.
+    ///
+    /// In case of overlapping windows, the earliest start is considered.
+    /// This returns `None` if the datetime is not in any window.
+    pub fn minutes_since_window_start(&self, datetime: &DateTime<impl TimeZone>) -> Option<u32> {
+        let timepoint = wall_clock_minute(datetime);
+        // Windows are at most a week long, so they can only have started
+        // in the last 8 days.
+        let earliest_start = (0..=8)
+            .map(|days| timepoint.date() - chrono::Duration::days(days))
+            .flat_map(|date| {
+                self.windows.iter().filter_map(move |w| {
+                    let start = w.start_on(date)?;
+                    if w.contains(start, timepoint) {
+                        Some(start)
+                    } else {
+                        None
+                    }
+                })
+            })
+            .min()?;
+
+        let elapsed = timepoint
+            .signed_duration_since(earliest_start)
+            .num_minutes();
+        Some(elapsed.max(0) as u32)
+    }
+
+    /// Return the start of the next window after the given datetime, in
+    /// wall-clock time.
+    ///
+    /// This returns `None` if no windows start in the next few years.
+    pub fn next_window_start(&self, datetime: &DateTime<impl TimeZone>) -> Option<NaiveDateTime> {
+        let timepoint = wall_clock_minute(datetime);
+        (0..=MAX_LOOKAHEAD_DAYS)
+            .map(|days| timepoint.date() + chrono::Duration::days(days))
+            .find_map(|date| {
+                self.windows
+                    .iter()
+                    .filter_map(|w| w.start_on(date))
+                    .filter(|start| *start >= timepoint)
+                    .min()
+            })
+    }
+
+    /// Return the duration remaining till the next window containing the given datetime.
+    ///
+    /// This returns a zero duration if the datetime is within a window, and
+    /// `None` if no windows are reachable.
+    pub fn remaining_to_datetime(
+        &self,
+        datetime: &DateTime<impl TimeZone>,
+    ) -> Option<chrono::Duration> {
+        if self.minutes_since_window_start(datetime).is_some() {
+            return Some(chrono::Duration::zero());
+        }
+        let next = self.next_window_start(datetime)?;
+        Some(next.signed_duration_since(wall_clock_minute(datetime)))
+    }
+}
+
+/// Return the wall-clock time of `datetime`, truncated to the minute.
+fn wall_clock_minute(datetime: &DateTime<impl TimeZone>) -> NaiveDateTime {
+    let local = datetime.naive_local();
+    local
+        .date()
+        .and_hms_opt(local.hour(), local.minute(), 0)
+        .expect("valid wall-clock time")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Utc, Weekday};
+
+    #[test]
+    fn parse_day_rule() {
+        let cases = vec![
+            ("day 1", DayRule::DayOfMonth(1)),
+            ("day 31", DayRule::DayOfMonth(31)),
+            ("first Sun", DayRule::WeekdayOfMonth(1, Weekday::Sun)),
+            ("last friday", DayRule::WeekdayOfMonth(-1, Weekday::Fri)),
+            (
+                "2021-12-24",
+                DayRule::Date(NaiveDate::from_ymd_opt(2021, 12, 24).unwrap()),
+            ),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(DayRule::parse(input).unwrap(), expected, "{}", input);
+        }
+
+        let invalid = vec!["", "Sun", "day 0", "day 32", "sixth Sun", "2021-02-30"];
+        for input in invalid {
+            DayRule::parse(input).unwrap_err();
+        }
+    }
+
+    #[test]
+    fn day_rule_matches() {
+        // 2021-08-01 and 2021-08-29 are the first and last Sundays of the month.
+        let first_sunday = DayRule::WeekdayOfMonth(1, Weekday::Sun);
+        assert!(first_sunday.matches(NaiveDate::from_ymd_opt(2021, 8, 1).unwrap()));
+        assert!(!first_sunday.matches(NaiveDate::from_ymd_opt(2021, 8, 8).unwrap()));
+        let last_sunday = DayRule::WeekdayOfMonth(-1, Weekday::Sun);
+        assert!(last_sunday.matches(NaiveDate::from_ymd_opt(2021, 8, 29).unwrap()));
+        assert!(!last_sunday.matches(NaiveDate::from_ymd_opt(2021, 8, 22).unwrap()));
+
+        let day31 = DayRule::DayOfMonth(31);
+        assert!(day31.matches(NaiveDate::from_ymd_opt(2021, 8, 31).unwrap()));
+        assert!(!day31.matches(NaiveDate::from_ymd_opt(2021, 9, 30).unwrap()));
+    }
+
+    #[test]
+    fn calendar_first_sunday() {
+        let length = utils::check_minutes(120).unwrap();
+        let rule = DayRule::WeekdayOfMonth(1, Weekday::Sun);
+        let window = DatedWindow::new(rule, 2, 0, length).unwrap();
+        let calendar = DatedCalendar::new(vec![window]);
+
+        let inside = Utc.with_ymd_and_hms(2021, 8, 1, 3, 0, 0).unwrap();
+        assert_eq!(calendar.minutes_since_window_start(&inside), Some(60));
+        assert_eq!(
+            calendar.remaining_to_datetime(&inside),
+            Some(chrono::Duration::zero())
+        );
+
+        let end = Utc.with_ymd_and_hms(2021, 8, 1, 4, 0, 30).unwrap();
+        assert_eq!(calendar.minutes_since_window_start(&end), Some(120));
+
+        // Next window is on 2021-09-05.
+        let after = Utc.with_ymd_and_hms(2021, 8, 1, 4, 1, 0).unwrap();
+        assert_eq!(calendar.minutes_since_window_start(&after), None);
+        let next = NaiveDate::from_ymd_opt(2021, 9, 5)
+            .unwrap()
+            .and_hms_opt(2, 0, 0)
+            .unwrap();
+        assert_eq!(calendar.next_window_start(&after), Some(next));
+        assert_eq!(
+            calendar.remaining_to_datetime(&after),
+            Some(next.signed_duration_since(after.naive_utc()))
+        );
+    }
+
+    #[test]
+    fn calendar_past_date() {
+        let length = utils::check_minutes(60).unwrap();
+        let rule = DayRule::parse("2021-12-24").unwrap();
+        let window = DatedWindow::new(rule, 23, 30, length).unwrap();
+        let calendar = DatedCalendar::new(vec![window]);
+
+        // Window crosses midnight.
+        let inside = Utc.with_ymd_and_hms(2021, 12, 25, 0, 10, 0).unwrap();
+        assert_eq!(calendar.minutes_since_window_start(&inside), Some(40));
+
+        let after = Utc.with_ymd_and_hms(2021, 12, 26, 0, 0, 0).unwrap();
+        assert_eq!(calendar.remaining_to_datetime(&after), None);
+    }
+}
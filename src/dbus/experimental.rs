@@ -1,16 +1,81 @@
 //! Experimental interface.
 
-use crate::update_agent::{LastRefresh, UpdateAgent};
+use super::polkit;
+use crate::cincinnati;
+use crate::rpm_ostree;
+use crate::update_agent::{
+    AgentEvent, AgentStatus, ApprovePendingReboot, CancelPendingReboot, CancelScheduledFinalize,
+    ClearTemporaryBlackout, GetConfig, GetPlan, GetStatus, HoldUpdates, LastRefresh, NextRefresh,
+    PlannedAction, ReleaseHold, Reload, ScheduleFinalize, ScheduledFinalizeTime,
+    SetTemporaryBlackout, SetUpdateGroup, SwitchStream, TargetRelease, UpdateAgent, UpdateCheck,
+};
+use actix::prelude::*;
 use actix::Addr;
 use futures::prelude::*;
 use tokio::runtime::Runtime;
-use zbus::{dbus_interface, fdo};
+use zbus::{dbus_interface, fdo, MessageHeader};
+
+/// Object path of the experimental interface.
+const OBJECT_PATH: &str = "/org/coreos/zincati";
+
+/// Name of the experimental interface.
+const INTERFACE_NAME: &str = "org.coreos.zincati.Experimental";
+
+/// Emit the signal for an agent event, on the experimental interface.
+///
+/// Signals are broadcast, as listeners (e.g. desktop notification daemons)
+/// are not known in advance.
+pub(crate) fn emit_signal(connection: &zbus::Connection, event: &AgentEvent) {
+    let result = match event {
+        AgentEvent::UpdateStaged { version } => connection.emit_signal(
+            None,
+            OBJECT_PATH,
+            INTERFACE_NAME,
+            "UpdateStaged",
+            &(version.as_str(),),
+        ),
+        AgentEvent::RebootScheduled { timestamp } => connection.emit_signal(
+            None,
+            OBJECT_PATH,
+            INTERFACE_NAME,
+            "RebootScheduled",
+            &(*timestamp,),
+        ),
+    };
+    if let Err(e) = result {
+        log::warn!("failed to emit D-Bus signal for {:?}: {}", event, e);
+    }
+}
 
 /// Experimental interface for testing.
 pub(crate) struct Experimental {
     pub(crate) agent_addr: Addr<UpdateAgent>,
 }
 
+impl Experimental {
+    /// Send a message to the update agent actor, blocking until it replies.
+    fn send_to_agent<M>(&self, msg: M, method: &str) -> fdo::Result<M::Result>
+    where
+        M: Message + Send + 'static,
+        M::Result: Send,
+        UpdateAgent: Handler<M>,
+    {
+        let reply_fut = self.agent_addr.send(msg).map_err(|e| {
+            let err_msg = format!("failed to send request to agent actor: {}", e);
+            log::error!("{} D-Bus method call: {}", method, err_msg);
+            fdo::Error::Failed(err_msg)
+        });
+
+        Runtime::new()
+            .map_err(|e| {
+                let err_msg = format!("failed to create runtime to execute future: {}", e);
+                log::error!("{}", err_msg);
+                fdo::Error::Failed(err_msg)
+            })
+            .and_then(|runtime| runtime.block_on(reply_fut))
+    }
+}
+
 #[dbus_interface(name = "org.coreos.zincati.Experimental")]
 impl Experimental {
     /// Just a test method.
@@ -24,19 +89,191 @@ impl Experimental {
 
     /// Get update_agent actor's last refresh time.
     fn last_refresh_time(&self) -> fdo::Result<i64> {
-        let msg = LastRefresh {};
-        let refresh_time_fut = self.agent_addr.send(msg).map_err(|e| {
-            let err_msg = format!("failed to get last refresh time from agent actor: {}", e);
-            log::error!("LastRefreshTime D-Bus method call: {}", err_msg);
-            fdo::Error::Failed(err_msg)
-        });
+        self.send_to_agent(LastRefresh {}, "LastRefreshTime")
+    }
 
-        Runtime::new()
-            .map_err(|e| {
-                let err_msg = format!("failed to create runtime to execute future: {}", e);
-                log::error!("{}", err_msg);
-                fdo::Error::Failed(err_msg)
-            })
-            .and_then(|runtime| runtime.block_on(refresh_time_fut))
+    /// Get a consistent snapshot of the whole agent status, in a single call.
+    fn get_full_status(&self) -> fdo::Result<AgentStatus> {
+        self.send_to_agent(GetStatus {}, "GetFullStatus")
+    }
+
+    /// Get the update target found by the last check, with its advisory
+    /// metadata (severity, release notes, errata).
+    fn check_update(&self) -> fdo::Result<UpdateCheck> {
+        self.send_to_agent(GetStatus {}, "CheckUpdate")
+            .map(UpdateCheck::from)
+    }
+
+    /// Get the effective (merged) configuration of the agent, as JSON.
+    fn get_config(&self) -> fdo::Result<String> {
+        self.send_to_agent(GetConfig {}, "GetConfig")
+    }
+
+    /// Get the projected next actions of the agent, with estimated times.
+    fn get_plan(&self) -> fdo::Result<Vec<PlannedAction>> {
+        self.send_to_agent(GetPlan {}, "GetPlan")
+    }
+
+    /// Schedule a one-time finalization at the given UTC timestamp, if an
+    /// update is staged by then.
+    fn schedule_finalize(&self, timestamp: i64) -> fdo::Result<()> {
+        let msg = ScheduleFinalize { timestamp };
+        self.send_to_agent(msg, "ScheduleFinalize")?
+            .map_err(|e| fdo::Error::Failed(format!("{:#}", e)))
+    }
+
+    /// Cancel the one-time scheduled finalization, returning whether one was armed.
+    fn cancel_scheduled_finalize(&self) -> fdo::Result<bool> {
+        self.send_to_agent(CancelScheduledFinalize {}, "CancelScheduledFinalize")?
+            .map_err(|e| fdo::Error::Failed(format!("{:#}", e)))
+    }
+
+    /// Forbid reboots between the given UTC timestamps, replacing any
+    /// previous temporary blackout.
+    fn set_temporary_blackout(&self, start: i64, end: i64, reason: &str) -> fdo::Result<()> {
+        let msg = SetTemporaryBlackout {
+            start,
+            end,
+            reason: reason.to_string(),
+        };
+        self.send_to_agent(msg, "SetTemporaryBlackout")?
+            .map_err(|e| fdo::Error::Failed(format!("{:#}", e)))
+    }
+
+    /// Lift the temporary blackout, returning whether one was set.
+    fn clear_temporary_blackout(&self) -> fdo::Result<bool> {
+        self.send_to_agent(ClearTemporaryBlackout {}, "ClearTemporaryBlackout")?
+            .map_err(|e| fdo::Error::Failed(format!("{:#}", e)))
+    }
+
+    /// Hold the node on its booted release, returning the pinned version.
+    ///
+    /// Updates are still checked for and reported, but not staged.
+    fn hold(&self) -> fdo::Result<String> {
+        self.send_to_agent(HoldUpdates {}, "Hold")?
+            .map_err(|e| fdo::Error::Failed(format!("{:#}", e)))
+    }
+
+    /// Release the hold on the booted release, returning whether one was set.
+    fn unhold(&self) -> fdo::Result<bool> {
+        self.send_to_agent(ReleaseHold {}, "Unhold")?
+            .map_err(|e| fdo::Error::Failed(format!("{:#}", e)))
+    }
+
+    /// Move this node to another update group, returning whether it changed.
+    ///
+    /// The group is persisted across agent restarts, overriding configuration.
+    /// This requires polkit authorization for `org.coreos.zincati.set-update-group`.
+    fn set_update_group(
+        &self,
+        #[zbus(header)] header: MessageHeader<'_>,
+        group: &str,
+    ) -> fdo::Result<bool> {
+        let sender = header.sender().ok().flatten();
+        polkit::ensure_authorized(sender, polkit::SET_UPDATE_GROUP_ACTION)?;
+        let msg = SetUpdateGroup {
+            group: group.to_string(),
+        };
+        self.send_to_agent(msg, "SetUpdateGroup")?
+            .map_err(|e| fdo::Error::Failed(format!("{:#}", e)))
+    }
+
+    /// Switch this node to another update stream, returning the refspec
+    /// being rebased onto (empty if already on that stream).
+    ///
+    /// The rebase completes in the background, and the rebased deployment is
+    /// then finalized like any other update.
+    /// This requires polkit authorization for `org.coreos.zincati.switch-stream`.
+    fn switch_stream(
+        &self,
+        #[zbus(header)] header: MessageHeader<'_>,
+        stream: &str,
+    ) -> fdo::Result<String> {
+        let sender = header.sender().ok().flatten();
+        polkit::ensure_authorized(sender, polkit::SWITCH_STREAM_ACTION)?;
+        let msg = SwitchStream {
+            stream: stream.to_string(),
+        };
+        self.send_to_agent(msg, "SwitchStream")?
+            .map_err(|e| fdo::Error::Failed(format!("{:#}", e)))
+    }
+
+    /// Reload configuration from disk, keeping the current agent state.
+    fn reload(&self) -> fdo::Result<()> {
+        self.send_to_agent(Reload {}, "Reload")?
+            .map_err(|e| fdo::Error::Failed(format!("{:#}", e)))
+    }
+
+    /// Approve a reboot into the staged update, returning its version.
+    fn approve_pending_reboot(&self) -> fdo::Result<String> {
+        self.send_to_agent(ApprovePendingReboot {}, "ApprovePendingReboot")?
+            .map_err(|e| fdo::Error::Failed(format!("{:#}", e)))
+    }
+
+    /// Cancel the pending reboot into the staged update (scheduled via
+    /// logind, or counting down), returning whether one was pending.
+    ///
+    /// The reboot is not scheduled again before another lead time (or
+    /// countdown length).
+    fn cancel_pending_reboot(&self) -> fdo::Result<bool> {
+        self.send_to_agent(CancelPendingReboot {}, "CancelPendingReboot")?
+            .map_err(|e| fdo::Error::Failed(format!("{:#}", e)))
+    }
+
+    /// UTC timestamp of the one-time scheduled finalization (0 if unset).
+    #[dbus_interface(property)]
+    fn scheduled_finalize_time(&self) -> i64 {
+        self.send_to_agent(ScheduledFinalizeTime {}, "ScheduledFinalizeTime")
+            .ok()
+            .flatten()
+            .unwrap_or(0)
+    }
+
+    /// Version of the update target (empty if none).
+    #[dbus_interface(property)]
+    fn target_version(&self) -> String {
+        self.send_to_agent(TargetRelease {}, "TargetVersion")
+            .ok()
+            .flatten()
+            .map(|release| release.version)
+            .unwrap_or_default()
+    }
+
+    /// Checksum of the update target (empty if none).
+    #[dbus_interface(property)]
+    fn target_checksum(&self) -> String {
+        self.send_to_agent(TargetRelease {}, "TargetChecksum")
+            .ok()
+            .flatten()
+            .map(|release| release.checksum)
+            .unwrap_or_default()
+    }
+
+    /// UTC timestamp of the next refresh tick (`0` if not scheduled).
+    #[dbus_interface(property)]
+    fn next_refresh_time(&self) -> i64 {
+        self.send_to_agent(NextRefresh {}, "NextRefreshTime")
+            .map(|(time, _)| time)
+            .unwrap_or(0)
+    }
+
+    /// Why the next refresh tick was scheduled at that time (empty if unknown).
+    #[dbus_interface(property)]
+    fn next_refresh_reason(&self) -> String {
+        self.send_to_agent(NextRefresh {}, "NextRefreshReason")
+            .map(|(_, reason)| reason)
+            .unwrap_or_default()
+    }
+
+    /// Correlation ID of the last request to the Cincinnati server (empty if none).
+    #[dbus_interface(property)]
+    fn last_cincinnati_request_id(&self) -> String {
+        cincinnati::last_request_id().unwrap_or_default()
+    }
+
+    /// Running and queued rpm-ostree operations, in execution order.
+    #[dbus_interface(property)]
+    fn rpm_ostree_operations(&self) -> Vec<String> {
+        rpm_ostree::queued_operations()
     }
 }
@@ -0,0 +1,34 @@
+//! Manager interface.
+
+use crate::logging;
+use std::time::Duration;
+use zbus::{dbus_interface, fdo};
+
+/// Interface for managing the agent process.
+pub(crate) struct Manager {}
+
+#[dbus_interface(name = "org.coreos.zincati.Manager")]
+impl Manager {
+    /// Set the log level (`error`, `warn`, `info`, `debug` or `trace`) at
+    /// runtime, optionally persisting it across agent restarts.
+    fn set_log_level(&self, level: &str, persist: bool) -> fdo::Result<()> {
+        let level =
+            logging::parse_level(level).map_err(|e| fdo::Error::InvalidArgs(format!("{:#}", e)))?;
+        logging::set_level(level, persist).map_err(|e| fdo::Error::Failed(format!("{:#}", e)))
+    }
+
+    /// Set the log level at runtime for `timeout_secs` seconds, after which
+    /// the previous level is restored.
+    fn set_log_level_temporarily(&self, level: &str, timeout_secs: u64) -> fdo::Result<()> {
+        let level =
+            logging::parse_level(level).map_err(|e| fdo::Error::InvalidArgs(format!("{:#}", e)))?;
+        logging::set_level_for(level, Duration::from_secs(timeout_secs))
+            .map_err(|e| fdo::Error::InvalidArgs(format!("{:#}", e)))
+    }
+
+    /// Current log level.
+    #[dbus_interface(property)]
+    fn log_level(&self) -> String {
+        logging::current_level()
+    }
+}
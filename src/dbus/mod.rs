@@ -3,7 +3,12 @@
 mod experimental;
 use experimental::Experimental;
 
-use crate::update_agent::UpdateAgent;
+mod manager;
+use manager::Manager;
+
+mod polkit;
+
+use crate::update_agent::{SubscribeEvents, UpdateAgent};
 use actix::prelude::*;
 use actix::Addr;
 use anyhow::Result;
@@ -37,6 +42,11 @@ impl DBusService {
             fdo::RequestNameFlags::ReplaceExisting.into(),
         )?;
 
+        let signals = connection.clone();
+        self.agent_addr.do_send(SubscribeEvents {
+            listener: Box::new(move |event| experimental::emit_signal(&signals, event)),
+        });
+
         let mut object_server = zbus::ObjectServer::new(&connection);
         let experimental_interface = Experimental {
             agent_addr: self.agent_addr.clone(),
@@ -45,6 +55,7 @@ impl DBusService {
             &ObjectPath::try_from("/org/coreos/zincati")?,
             experimental_interface,
         )?;
+        object_server.at(&ObjectPath::try_from("/org/coreos/zincati")?, Manager {})?;
 
         loop {
             if let Err(err) = object_server.try_handle_next() {
@@ -0,0 +1,63 @@
+//! Authorization checks via polkit.
+
+use std::collections::HashMap;
+use zbus::{dbus_proxy, fdo};
+use zvariant::Value;
+
+/// Polkit action for changing the update group at runtime.
+pub(crate) static SET_UPDATE_GROUP_ACTION: &str = "org.coreos.zincati.set-update-group";
+
+/// Polkit action for switching to another update stream.
+pub(crate) static SWITCH_STREAM_ACTION: &str = "org.coreos.zincati.switch-stream";
+
+#[dbus_proxy(
+    interface = "org.freedesktop.PolicyKit1.Authority",
+    default_service = "org.freedesktop.PolicyKit1",
+    default_path = "/org/freedesktop/PolicyKit1/Authority"
+)]
+trait Authority {
+    /// CheckAuthorization method
+    fn check_authorization(
+        &self,
+        subject: &(&str, HashMap<&str, Value>),
+        action_id: &str,
+        details: HashMap<&str, &str>,
+        flags: u32,
+        cancellation_id: &str,
+    ) -> zbus::Result<(bool, bool, HashMap<String, String>)>;
+}
+
+/// Ensure that the sender of a D-Bus method call is authorized for `action_id`.
+pub(crate) fn ensure_authorized(sender: Option<&str>, action_id: &str) -> fdo::Result<()> {
+    let sender = sender.ok_or_else(|| {
+        fdo::Error::AccessDenied("unable to identify the sender of the request".to_string())
+    })?;
+
+    let mut subject_details = HashMap::new();
+    subject_details.insert("name", Value::from(sender));
+    let subject = ("system-bus-name", subject_details);
+    let (is_authorized, _, _) = zbus::Connection::new_system()
+        .and_then(|connection| {
+            AuthorityProxy::new(&connection)?.check_authorization(
+                &subject,
+                action_id,
+                HashMap::new(),
+                0,
+                "",
+            )
+        })
+        .map_err(|e| {
+            let err_msg = format!("failed to check authorization via polkit: {}", e);
+            log::error!("{}", err_msg);
+            fdo::Error::Failed(err_msg)
+        })?;
+
+    if !is_authorized {
+        log::warn!("{} denied to {} by polkit", action_id, sender);
+        return Err(fdo::Error::AccessDenied(format!(
+            "not authorized for {}",
+            action_id
+        )));
+    }
+    Ok(())
+}
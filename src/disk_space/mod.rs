@@ -0,0 +1,234 @@
+//! Free disk space checks before staging updates.
+//!
+//! Staging an update writes a new commit to the OSTree repository on the
+//! sysroot, and a new kernel and initramfs to `/boot`. When either runs out
+//! of space, rpm-ostree fails halfway through with an opaque error. These
+//! checks catch low free space ahead of time, so that fetching can be
+//! deferred with a clear reason, optionally after running a cleanup command
+//! (e.g. `rpm-ostree cleanup -r`) to reclaim space.
+
+use crate::config::inputs;
+use anyhow::{anyhow, ensure, Context, Result};
+use fn_error_context::context;
+use prometheus::IntCounterVec;
+use serde::Serialize;
+use std::ffi::CString;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+lazy_static::lazy_static! {
+    static ref CLEANUP_RUNS: IntCounterVec = register_int_counter_vec!(
+        "zincati_disk_space_cleanups_total",
+        "Total number of cleanup commands run to reclaim disk space, by result.",
+        &["result"]
+    ).unwrap();
+}
+
+/// Mountpoint of the OSTree sysroot.
+pub static SYSROOT_PATH: &str = "/sysroot";
+
+/// Mountpoint of the boot filesystem.
+pub static BOOT_PATH: &str = "/boot";
+
+/// Bytes in a mebibyte.
+const MIB: u64 = 1024 * 1024;
+
+/// Minimum free space required on filesystems written by staging.
+#[derive(Clone, Debug, Serialize)]
+pub struct DiskSpaceCheck {
+    /// Minimum free space on the sysroot, in bytes (zero if unchecked).
+    sysroot_min_free_bytes: u64,
+    /// Minimum free space on `/boot`, in bytes (zero if unchecked).
+    boot_min_free_bytes: u64,
+    /// Command reclaiming disk space, as program and arguments (empty if unset).
+    cleanup_command: Vec<String>,
+}
+
+impl DiskSpaceCheck {
+    /// Process disk space check configuration.
+    ///
+    /// This returns `None` if no threshold is configured.
+    #[context("failed to validate disk space check configuration")]
+    pub fn with_config(cfg: inputs::DiskSpaceInput) -> Result<Option<Self>> {
+        let cleanup_command: Vec<String> = cfg
+            .cleanup_command
+            .split_whitespace()
+            .map(String::from)
+            .collect();
+        if let Some(program) = cleanup_command.first() {
+            ensure!(
+                program.starts_with('/'),
+                "cleanup program '{}' is not absolute",
+                program
+            );
+        }
+        if cfg.sysroot_min_free_mib == 0 && cfg.boot_min_free_mib == 0 {
+            ensure!(
+                cleanup_command.is_empty(),
+                "cleanup command configured without any free space threshold"
+            );
+            return Ok(None);
+        }
+
+        let check = Self {
+            sysroot_min_free_bytes: cfg.sysroot_min_free_mib.saturating_mul(MIB),
+            boot_min_free_bytes: cfg.boot_min_free_mib.saturating_mul(MIB),
+            cleanup_command,
+        };
+        Ok(Some(check))
+    }
+
+    /// Check free space before fetching or staging an update.
+    ///
+    /// On failure, this returns the reason for deferring. Errors while
+    /// inspecting filesystems are logged, but do not defer staging.
+    pub fn check(&self) -> Result<(), String> {
+        self.check_at(Path::new(SYSROOT_PATH), Path::new(BOOT_PATH))
+    }
+
+    /// Check free space on the given sysroot and boot filesystems.
+    fn check_at(&self, sysroot: &Path, boot: &Path) -> Result<(), String> {
+        let thresholds = [
+            (sysroot, self.sysroot_min_free_bytes),
+            (boot, self.boot_min_free_bytes),
+        ];
+        for (path, min_free) in thresholds.iter() {
+            if *min_free == 0 {
+                continue;
+            }
+            let free = match free_bytes(path) {
+                Ok(free) => free,
+                Err(e) => {
+                    log::warn!("skipping disk space check: {:#}", e);
+                    continue;
+                }
+            };
+            if free < *min_free {
+                return Err(format!(
+                    "not enough space on {} ({} MiB free, {} MiB required)",
+                    path.display(),
+                    to_mib(free),
+                    to_mib(*min_free)
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether a cleanup command is configured.
+    pub fn has_cleanup(&self) -> bool {
+        !self.cleanup_command.is_empty()
+    }
+
+    /// Run the configured cleanup command, to reclaim disk space.
+    #[context("failed to run disk space cleanup command")]
+    pub fn cleanup(&self) -> Result<()> {
+        let (program, args) = self
+            .cleanup_command
+            .split_first()
+            .ok_or_else(|| anyhow!("no cleanup command configured"))?;
+        log::info!(
+            "running cleanup command to reclaim disk space: {}",
+            self.cleanup_command.join(" ")
+        );
+        let status = Command::new(program)
+            .args(args)
+            .stdin(Stdio::null())
+            .status()
+            .context("failed to spawn process");
+        let result = match status {
+            Ok(s) if s.success() => Ok(()),
+            Ok(s) => Err(anyhow!("failed with {}", s)),
+            Err(e) => Err(e),
+        };
+        let label = if result.is_ok() { "success" } else { "failure" };
+        CLEANUP_RUNS.with_label_values(&[label]).inc();
+        result
+    }
+}
+
+/// Return the space available to unprivileged users on the filesystem at `path`.
+pub fn free_bytes(path: &Path) -> Result<u64> {
+    let c_path = CString::new(path.as_os_str().as_bytes())?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    let ret = unsafe { libc::statvfs(c_path.as_ptr(), &mut stat) };
+    if ret != 0 {
+        return Err(std::io::Error::last_os_error())
+            .with_context(|| format!("failed to statvfs '{}'", path.display()));
+    }
+
+    #[allow(clippy::unnecessary_cast)]
+    let free = (stat.f_bavail as u64).saturating_mul(stat.f_frsize as u64);
+    Ok(free)
+}
+
+/// Convert bytes to mebibytes, rounding up.
+pub fn to_mib(bytes: u64) -> u64 {
+    bytes / MIB + u64::from(bytes % MIB != 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn input(sysroot: u64, boot: u64, cleanup: &str) -> inputs::DiskSpaceInput {
+        inputs::DiskSpaceInput {
+            boot_min_free_mib: boot,
+            cleanup_command: cleanup.to_string(),
+            sysroot_min_free_mib: sysroot,
+        }
+    }
+
+    #[test]
+    fn disk_space_with_config() {
+        assert!(DiskSpaceCheck::with_config(input(0, 0, ""))
+            .unwrap()
+            .is_none());
+        DiskSpaceCheck::with_config(input(0, 0, "/usr/bin/rpm-ostree cleanup -r")).unwrap_err();
+        DiskSpaceCheck::with_config(input(1024, 0, "rpm-ostree cleanup -r")).unwrap_err();
+
+        let check = DiskSpaceCheck::with_config(input(1024, 0, "/usr/bin/rpm-ostree cleanup -r"))
+            .unwrap()
+            .unwrap();
+        assert!(check.has_cleanup());
+        assert_eq!(
+            check.cleanup_command,
+            vec!["/usr/bin/rpm-ostree", "cleanup", "-r"]
+        );
+    }
+
+    #[test]
+    fn disk_space_check_at() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let root = tmpdir.path();
+
+        let check = DiskSpaceCheck::with_config(input(1, 0, ""))
+            .unwrap()
+            .unwrap();
+        check.check_at(root, root).unwrap();
+
+        let check = DiskSpaceCheck {
+            boot_min_free_bytes: u64::MAX,
+            ..check
+        };
+        let reason = check.check_at(root, root).unwrap_err();
+        assert!(reason.contains("MiB required"), "{}", reason);
+
+        // Inspection failures do not defer staging.
+        check.check_at(root, &root.join("missing")).unwrap();
+    }
+
+    #[test]
+    fn disk_space_cleanup() {
+        let check = DiskSpaceCheck::with_config(input(1, 0, "/bin/true"))
+            .unwrap()
+            .unwrap();
+        check.cleanup().unwrap();
+
+        let failing = DiskSpaceCheck::with_config(input(1, 0, "/bin/false"))
+            .unwrap()
+            .unwrap();
+        failing.cleanup().unwrap_err();
+    }
+}
@@ -0,0 +1,205 @@
+//! Barriers against downgrading too far back.
+//!
+//! When downgrades are allowed, a misconfigured (or attacker-controlled)
+//! update source could roll nodes back to old releases with known security
+//! vulnerabilities. Barriers bound how far back a downgrade can go: update
+//! targets must not be older than a minimum version, and staged deployments
+//! must not have a commit much older than the booted one.
+
+use crate::config::inputs;
+use crate::rpm_ostree::Release;
+use anyhow::{ensure, Result};
+use fn_error_context::context;
+use prometheus::IntCounterVec;
+use serde::Serialize;
+use std::cmp::Ordering;
+
+lazy_static::lazy_static! {
+    static ref BARRIER_REJECTIONS: IntCounterVec = register_int_counter_vec!(
+        "zincati_downgrade_barrier_rejections_total",
+        "Total number of downgrades rejected by a downgrade barrier.",
+        &["barrier"]
+    ).unwrap();
+}
+
+/// Limits on how far back downgrades can go.
+#[derive(Clone, Debug, Serialize)]
+pub struct DowngradeBarrier {
+    /// Minimum version of update targets, if any.
+    min_version: Option<String>,
+    /// Maximum age of a staged commit relative to the booted one, if any.
+    #[serde(skip)]
+    max_age: Option<chrono::Duration>,
+}
+
+impl DowngradeBarrier {
+    /// Process downgrade barrier configuration.
+    ///
+    /// This returns `None` if no barrier is configured.
+    #[context("failed to validate downgrade barrier configuration")]
+    pub fn with_config(cfg: inputs::DowngradeBarrierInput) -> Result<Option<Self>> {
+        let min_version = match cfg.min_version.trim() {
+            "" => None,
+            v => {
+                ensure!(
+                    !v.contains(char::is_whitespace),
+                    "invalid minimum version '{}'",
+                    v
+                );
+                Some(v.to_string())
+            }
+        };
+        let max_age = match cfg.max_age_days {
+            0 => None,
+            days => Some(chrono::Duration::days(days.min(u64::from(u32::MAX)) as i64)),
+        };
+        if min_version.is_none() && max_age.is_none() {
+            return Ok(None);
+        }
+
+        let barrier = Self {
+            min_version,
+            max_age,
+        };
+        Ok(Some(barrier))
+    }
+
+    /// Check that an update target is not older than the minimum version.
+    pub fn check_version(&self, release: &Release) -> Result<()> {
+        let min_version = match &self.min_version {
+            Some(v) => v,
+            None => return Ok(()),
+        };
+        if compare_versions(&release.version, min_version) == Ordering::Less {
+            BARRIER_REJECTIONS.with_label_values(&["min_version"]).inc();
+            anyhow::bail!(
+                "target release '{}' is older than minimum version '{}'",
+                release.version,
+                min_version
+            );
+        }
+        Ok(())
+    }
+
+    /// Whether staged commits are checked against the booted one.
+    pub fn checks_commit_age(&self) -> bool {
+        self.max_age.is_some()
+    }
+
+    /// Check that a staged commit is not too much older than the booted one.
+    ///
+    /// `age_secs` is the difference between the booted and the staged commit
+    /// timestamps, positive for a downgrade.
+    pub fn check_commit_age(&self, age_secs: i64) -> Result<()> {
+        let max_age = match self.max_age {
+            Some(age) => age,
+            None => return Ok(()),
+        };
+        let age = chrono::Duration::seconds(age_secs);
+        if age > max_age {
+            BARRIER_REJECTIONS.with_label_values(&["max_age"]).inc();
+            anyhow::bail!(
+                "staged commit is {} days older than booted one, beyond the maximum of {} days",
+                age.num_days(),
+                max_age.num_days()
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Compare two release versions, component by component.
+///
+/// Components are dot-separated, and compared numerically when both are
+/// numbers (e.g. `36.20220505.3.2`), lexicographically otherwise.
+pub fn compare_versions(a: &str, b: &str) -> Ordering {
+    let mut a_parts = a.split('.');
+    let mut b_parts = b.split('.');
+    loop {
+        let (a_part, b_part) = match (a_parts.next(), b_parts.next()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) => (x, y),
+        };
+        let order = match (a_part.parse::<u64>(), b_part.parse::<u64>()) {
+            (Ok(x), Ok(y)) => x.cmp(&y),
+            _ => a_part.cmp(b_part),
+        };
+        if order != Ordering::Equal {
+            return order;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn input(min_version: &str, max_age_days: u64) -> inputs::DowngradeBarrierInput {
+        inputs::DowngradeBarrierInput {
+            min_version: min_version.to_string(),
+            max_age_days,
+        }
+    }
+
+    fn release(version: &str) -> Release {
+        Release {
+            version: version.to_string(),
+            checksum: "c1".to_string(),
+            age_index: None,
+            advisory: None,
+            wave: None,
+        }
+    }
+
+    #[test]
+    fn barrier_with_config() {
+        assert!(DowngradeBarrier::with_config(input("", 0))
+            .unwrap()
+            .is_none());
+        DowngradeBarrier::with_config(input("36. 2022", 0)).unwrap_err();
+
+        let barrier = DowngradeBarrier::with_config(input("", 30))
+            .unwrap()
+            .unwrap();
+        assert!(barrier.checks_commit_age());
+        barrier.check_version(&release("1.0.0")).unwrap();
+    }
+
+    #[test]
+    fn barrier_min_version() {
+        let barrier = DowngradeBarrier::with_config(input("36.20220505.3.2", 0))
+            .unwrap()
+            .unwrap();
+        assert!(!barrier.checks_commit_age());
+        barrier.check_version(&release("36.20220505.3.2")).unwrap();
+        barrier.check_version(&release("36.20220522.3.0")).unwrap();
+        barrier.check_version(&release("37.20221101.3.0")).unwrap();
+        barrier
+            .check_version(&release("36.20220410.3.1"))
+            .unwrap_err();
+        barrier
+            .check_version(&release("35.20220424.3.0"))
+            .unwrap_err();
+    }
+
+    #[test]
+    fn barrier_max_age() {
+        let barrier = DowngradeBarrier::with_config(input("", 7))
+            .unwrap()
+            .unwrap();
+        let day = 24 * 60 * 60;
+        barrier.check_commit_age(-30 * day).unwrap();
+        barrier.check_commit_age(7 * day).unwrap();
+        barrier.check_commit_age(7 * day + 1).unwrap_err();
+    }
+
+    #[test]
+    fn versions_ordering() {
+        assert_eq!(compare_versions("36.1", "36.1"), Ordering::Equal);
+        assert_eq!(compare_versions("36.9", "36.10"), Ordering::Less);
+        assert_eq!(compare_versions("36.1", "36.1.1"), Ordering::Less);
+        assert_eq!(compare_versions("36.1.b", "36.1.a"), Ordering::Greater);
+    }
+}
@@ -0,0 +1,87 @@
+//! Scheduling for update downloads.
+//!
+//! On constrained links, fetching an update can saturate the connection for
+//! a long time. Downloads can thus be restricted to weekly windows (e.g.
+//! outside of business hours), separately from finalization.
+
+use crate::config::inputs;
+use crate::strategy::StrategyPeriodic;
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use fn_error_context::context;
+use serde::Serialize;
+
+/// Weekly windows during which updates can be downloaded.
+#[derive(Clone, Debug, Serialize)]
+pub struct DownloadSchedule {
+    /// Allowed download windows.
+    windows: StrategyPeriodic,
+}
+
+impl DownloadSchedule {
+    /// Process download windows configuration.
+    ///
+    /// This returns `None` if no windows are configured, i.e. downloads
+    /// are allowed at any time.
+    #[context("failed to validate download windows configuration")]
+    pub fn with_config(cfg: inputs::PeriodicInput) -> Result<Option<Self>> {
+        if cfg.intervals.is_empty() {
+            return Ok(None);
+        }
+
+        let windows = StrategyPeriodic::with_windows(cfg)?;
+        log::info!(
+            "update downloads restricted to windows: {}",
+            windows.calendar_summary()
+        );
+
+        Ok(Some(Self { windows }))
+    }
+
+    /// Return whether downloads are allowed at `datetime`.
+    pub fn can_download(&self, datetime: &DateTime<Utc>) -> bool {
+        self.windows.contains_datetime(datetime)
+    }
+
+    /// Return the remaining duration from `datetime` to the next download window.
+    ///
+    /// This returns a zero duration if `datetime` is within a window.
+    pub fn remaining_to_window(&self, datetime: &DateTime<Utc>) -> Option<chrono::Duration> {
+        self.windows.remaining_to_window(datetime)
+    }
+
+    /// Return the next download window, in human terms.
+    pub fn human_next_window(&self) -> String {
+        self.windows.human_next_window()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn download_windows() {
+        let unset = DownloadSchedule::with_config(inputs::PeriodicInput::default()).unwrap();
+        assert!(unset.is_none());
+
+        let cfg = inputs::PeriodicInput {
+            intervals: vec![inputs::PeriodicIntervalInput {
+                start_day: "Mon".to_string(),
+                start_time: "22:00".to_string(),
+                length_minutes: 120,
+                time_zone: None,
+            }],
+            time_zone: "UTC".to_string(),
+            window_jitter_minutes: 0,
+        };
+        let schedule = DownloadSchedule::with_config(cfg).unwrap().unwrap();
+
+        // 2021-06-07 is a Monday.
+        let inside = Utc.with_ymd_and_hms(2021, 6, 7, 23, 0, 0).unwrap();
+        assert!(schedule.can_download(&inside));
+        let outside = Utc.with_ymd_and_hms(2021, 6, 7, 12, 0, 0).unwrap();
+        assert!(!schedule.can_download(&outside));
+    }
+}
@@ -0,0 +1,168 @@
+//! Durable on-disk queues for outgoing events.
+//!
+//! Events which cannot be delivered right away (e.g. because their endpoint
+//! is unreachable) are persisted to a JSON file, so that they survive agent
+//! restarts and reboots, and can be delivered in order once connectivity
+//! returns. Queues are bounded both in size and in age: once full, oldest
+//! events are dropped first, and events older than the queue TTL are dropped
+//! without being delivered.
+
+use crate::utils;
+use anyhow::{Context, Result};
+use chrono::Utc;
+use fn_error_context::context;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// An event waiting in a queue.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct Queued<T> {
+    /// UTC timestamp of the event being queued.
+    pub queued_time: i64,
+    /// Queued event.
+    pub event: T,
+}
+
+/// Number of events dropped from a queue, by reason.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Dropped {
+    /// Events older than the queue TTL.
+    pub expired: usize,
+    /// Oldest events, beyond the queue capacity.
+    pub overflow: usize,
+}
+
+/// A bounded queue of events, persisted to a JSON file.
+#[derive(Clone, Debug, Serialize)]
+pub struct EventQueue {
+    /// Path to the persisted queue.
+    path: PathBuf,
+    /// Maximum number of queued events; older ones are dropped first.
+    max_len: usize,
+    /// Maximum age of queued events, in seconds (zero if unbounded).
+    max_age_secs: i64,
+}
+
+impl EventQueue {
+    /// Build a queue persisted at `path`, with the given bounds.
+    pub fn new(path: impl Into<PathBuf>, max_len: usize, max_age_secs: i64) -> Self {
+        Self {
+            path: path.into(),
+            max_len,
+            max_age_secs,
+        }
+    }
+
+    /// Return the path to the persisted queue.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Whether some events are waiting in the queue.
+    pub fn has_queued(&self) -> bool {
+        self.path.exists()
+    }
+
+    /// Load all queued events, oldest first.
+    #[context("failed to read queue '{}'", self.path.display())]
+    pub fn load<T: DeserializeOwned>(&self) -> Result<Vec<Queued<T>>> {
+        let content = match std::fs::read(&self.path) {
+            Ok(c) => c,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e.into()),
+        };
+        let queue = serde_json::from_slice(&content).context("failed to parse JSON content")?;
+        Ok(queue)
+    }
+
+    /// Append an event to the queue.
+    pub fn push<T: Serialize + DeserializeOwned>(&self, event: T) -> Result<(usize, Dropped)> {
+        let mut queue = self.load::<T>()?;
+        queue.push(Queued {
+            queued_time: Utc::now().timestamp(),
+            event,
+        });
+        self.store(&queue)
+    }
+
+    /// Persist the given events, dropping expired ones and keeping only the
+    /// most recent ones.
+    ///
+    /// This returns the number of events left in the queue, and the number
+    /// of dropped ones.
+    #[context("failed to write queue '{}'", self.path.display())]
+    pub fn store<T: Serialize>(&self, queue: &[Queued<T>]) -> Result<(usize, Dropped)> {
+        let now = Utc::now().timestamp();
+        let len_before = queue.len();
+        let queue: Vec<&Queued<T>> = queue
+            .iter()
+            .filter(|q| self.max_age_secs == 0 || now - q.queued_time <= self.max_age_secs)
+            .collect();
+        let expired = len_before - queue.len();
+        let overflow = queue.len().saturating_sub(self.max_len);
+        let queue = &queue[overflow..];
+
+        if queue.is_empty() {
+            utils::remove_if_exists(&self.path)?;
+        } else {
+            let content = serde_json::to_vec(&queue)?;
+            utils::atomic_write(&self.path, 0o644, &content)?;
+        }
+        Ok((queue.len(), Dropped { expired, overflow }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn event_queue_bounds() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let queue = EventQueue::new(tmpdir.path().join("events.json"), 3, 72 * 3600);
+        assert!(!queue.has_queued());
+        assert!(queue.load::<String>().unwrap().is_empty());
+
+        let (len, dropped) = queue.push("first".to_string()).unwrap();
+        assert_eq!(len, 1);
+        assert_eq!(dropped, Dropped::default());
+        assert!(queue.has_queued());
+
+        // Expired events are dropped, then only the most recent ones are kept.
+        let now = Utc::now().timestamp();
+        let event = |name: &str, age_hours: i64| Queued {
+            queued_time: now - age_hours * 3600,
+            event: name.to_string(),
+        };
+        let events = vec![
+            event("e1", 200),
+            event("e2", 48),
+            event("e3", 24),
+            event("e4", 2),
+            event("e5", 0),
+        ];
+        let (len, dropped) = queue.store(&events).unwrap();
+        assert_eq!(len, 3);
+        assert_eq!(
+            dropped,
+            Dropped {
+                expired: 1,
+                overflow: 1
+            }
+        );
+        let stored: Vec<String> = queue
+            .load::<String>()
+            .unwrap()
+            .into_iter()
+            .map(|q| q.event)
+            .collect();
+        assert_eq!(stored, vec!["e3", "e4", "e5"]);
+
+        // Only expired events, queue is removed.
+        let (len, dropped) = queue.store(&[event("e1", 73)]).unwrap();
+        assert_eq!(len, 0);
+        assert_eq!(dropped.expired, 1);
+        assert!(!queue.has_queued());
+    }
+}
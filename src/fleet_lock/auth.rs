@@ -0,0 +1,120 @@
+//! Authentication to the FleetLock server.
+
+use anyhow::{Context, Result};
+use fn_error_context::context;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+/// Bearer token, read from a file.
+///
+/// The file is read again whenever its modification time changes, so that
+/// tokens can be rotated without restarting the agent.
+#[derive(Clone, Debug)]
+pub struct BearerToken {
+    /// Path to the token file.
+    path: PathBuf,
+    /// Last token read, with the modification time of its file.
+    cache: Arc<Mutex<Option<(SystemTime, String)>>>,
+}
+
+impl BearerToken {
+    /// Load a bearer token from `path`.
+    pub fn new(path: PathBuf) -> Result<Self> {
+        let modified = modification_time(&path)?;
+        let token = read_token(&path)?;
+        let bearer = Self {
+            path,
+            cache: Arc::new(Mutex::new(Some((modified, token)))),
+        };
+        Ok(bearer)
+    }
+
+    /// Return the current token, reading it again if its file changed.
+    ///
+    /// If the file cannot be read (e.g. while being replaced), the last
+    /// token read is used.
+    pub fn token(&self) -> Result<String> {
+        let mut cache = self
+            .cache
+            .lock()
+            .map_err(|_| anyhow::anyhow!("poisoned bearer token cache"))?;
+        let refreshed = modification_time(&self.path).and_then(|modified| {
+            if let Some((last, token)) = cache.as_ref() {
+                if *last == modified {
+                    return Ok((modified, token.clone()));
+                }
+            }
+            let token = read_token(&self.path)?;
+            log::debug!("loaded bearer token from '{}'", self.path.display());
+            Ok((modified, token))
+        });
+
+        match refreshed {
+            Ok((modified, token)) => {
+                *cache = Some((modified, token.clone()));
+                Ok(token)
+            }
+            Err(e) => match cache.as_ref() {
+                Some((_, token)) => {
+                    log::warn!("{:#}, using last bearer token", e);
+                    Ok(token.clone())
+                }
+                None => Err(e),
+            },
+        }
+    }
+}
+
+/// Return the modification time of a file.
+fn modification_time(path: &Path) -> Result<SystemTime> {
+    std::fs::metadata(path)
+        .and_then(|meta| meta.modified())
+        .with_context(|| format!("failed to stat '{}'", path.display()))
+}
+
+/// Read a bearer token from a file, ignoring surrounding whitespace.
+#[context("failed to read bearer token from '{}'", path.display())]
+fn read_token(path: &Path) -> Result<String> {
+    let content = std::fs::read_to_string(path)?;
+    let token = content.trim();
+    anyhow::ensure!(!token.is_empty(), "empty token");
+    anyhow::ensure!(
+        !token.contains(char::is_whitespace),
+        "token contains whitespace"
+    );
+    Ok(token.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use filetime::FileTime;
+    use std::time::Duration;
+
+    #[test]
+    fn bearer_token_reload() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let path = tmpdir.path().join("token");
+
+        BearerToken::new(path.clone()).unwrap_err();
+        std::fs::write(&path, "\n").unwrap();
+        BearerToken::new(path.clone()).unwrap_err();
+        std::fs::write(&path, "foo bar\n").unwrap();
+        BearerToken::new(path.clone()).unwrap_err();
+
+        std::fs::write(&path, "token-1\n").unwrap();
+        let bearer = BearerToken::new(path.clone()).unwrap();
+        assert_eq!(bearer.token().unwrap(), "token-1");
+
+        // Rotated token.
+        std::fs::write(&path, "token-2\n").unwrap();
+        let later = SystemTime::now() + Duration::from_secs(10);
+        filetime::set_file_mtime(&path, FileTime::from_system_time(later)).unwrap();
+        assert_eq!(bearer.token().unwrap(), "token-2");
+
+        // Token file being replaced.
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(bearer.token().unwrap(), "token-2");
+    }
+}
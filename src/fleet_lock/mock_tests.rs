@@ -28,7 +28,32 @@ fn test_pre_reboot_lock() {
     m_pre_reboot.assert();
 
     let lock = res.unwrap();
-    assert_eq!(lock, true);
+    assert!(lock);
+}
+
+#[test]
+fn test_pre_reboot_bearer_token() {
+    let tmpdir = tempfile::tempdir().unwrap();
+    let path = tmpdir.path().join("token");
+    std::fs::write(&path, "mock-token\n").unwrap();
+
+    let m_pre_reboot = mockito::mock("POST", Matcher::Exact(format!("/{}", V1_PRE_REBOOT)))
+        .match_header("fleet-lock-protocol", "true")
+        .match_header("authorization", "Bearer mock-token")
+        .with_status(200)
+        .create();
+
+    let runtime = rt::Runtime::new().unwrap();
+    let id = Identity::mock_default();
+    let bearer = BearerToken::new(path).unwrap();
+    let client = ClientBuilder::new(mockito::server_url(), &id)
+        .bearer_token(Some(bearer))
+        .build()
+        .unwrap();
+    let res = runtime.block_on(client.pre_reboot());
+    m_pre_reboot.assert();
+
+    assert!(res.unwrap());
 }
 
 #[test]
@@ -77,11 +102,11 @@ fn test_steady_state_lock() {
     let client = ClientBuilder::new(mockito::server_url(), &id)
         .build()
         .unwrap();
-    let res = runtime.block_on(client.steady_state());
+    let res = runtime.block_on(client.steady_state(None));
     m_steady_state.assert();
 
     let unlock = res.unwrap();
-    assert_eq!(unlock, true);
+    assert!(unlock);
 }
 
 #[test]
@@ -103,8 +128,85 @@ fn test_steady_state_error() {
     let client = ClientBuilder::new(mockito::server_url(), &id)
         .build()
         .unwrap();
-    let res = runtime.block_on(client.steady_state());
+    let res = runtime.block_on(client.steady_state(None));
     m_steady_state.assert();
 
     let _rejection = res.unwrap_err();
 }
+
+#[test]
+fn test_steady_state_health() {
+    let body = r#"
+{
+  "client_params": {
+    "id": "e0f3745b108f471cbd4883c6fbed8cdd",
+    "group": "mock-workers"
+  },
+  "health": {
+    "booted_version": "31.20200201.3.0",
+    "uptime_secs": 300,
+    "last_update_outcome": "succeeded"
+  }
+}
+"#;
+    let m_steady_state = mockito::mock("POST", Matcher::Exact(format!("/{}", V1_STEADY_STATE)))
+        .match_header("fleet-lock-protocol", "true")
+        .match_body(Matcher::PartialJsonString(body.to_string()))
+        .with_status(200)
+        .create();
+
+    let runtime = rt::Runtime::new().unwrap();
+    let id = Identity::mock_default();
+    let client = ClientBuilder::new(mockito::server_url(), &id)
+        .build()
+        .unwrap();
+    let health = NodeHealth {
+        booted_version: "31.20200201.3.0".to_string(),
+        uptime_secs: Some(300),
+        last_update_outcome: UpdateOutcome::Succeeded,
+    };
+    let res = runtime.block_on(client.steady_state(Some(&health)));
+    m_steady_state.assert();
+
+    let unlock = res.unwrap();
+    assert!(unlock);
+}
+
+#[test]
+fn test_update_confirmation() {
+    let body = r#"
+{
+  "client_params": {
+    "id": "e0f3745b108f471cbd4883c6fbed8cdd",
+    "group": "mock-workers"
+  },
+  "update": {
+    "from_version": "31.20200101.3.0",
+    "to_version": "31.20200201.3.0"
+  }
+}
+"#;
+    let m_confirmation = mockito::mock(
+        "POST",
+        Matcher::Exact(format!("/{}", V1_UPDATE_CONFIRMATION)),
+    )
+    .match_header("fleet-lock-protocol", "true")
+    .match_body(Matcher::PartialJsonString(body.to_string()))
+    .with_status(200)
+    .create();
+
+    let runtime = rt::Runtime::new().unwrap();
+    let id = Identity::mock_default();
+    let client = ClientBuilder::new(mockito::server_url(), &id)
+        .build()
+        .unwrap();
+    let update = UpdateConfirmation {
+        from_version: "31.20200101.3.0".to_string(),
+        to_version: "31.20200201.3.0".to_string(),
+    };
+    let res = runtime.block_on(client.update_confirmation(&update));
+    m_confirmation.assert();
+
+    let confirmed = res.unwrap();
+    assert!(confirmed);
+}
@@ -6,6 +6,7 @@
 //! https://coreos.github.io/zincati/development/fleetlock/protocol/ .
 
 use crate::identity::Identity;
+use crate::network::NetworkSettings;
 use anyhow::{Context, Result};
 use futures::prelude::*;
 use reqwest::Method;
@@ -13,6 +14,9 @@ use serde::{Deserialize, Serialize};
 use std::time::Duration;
 use thiserror::Error;
 
+mod auth;
+pub use auth::BearerToken;
+
 #[cfg(test)]
 mod mock_tests;
 
@@ -25,6 +29,9 @@ static V1_PRE_REBOOT: &str = "v1/pre-reboot";
 /// FleetLock steady-state API path endpoint (v1).
 static V1_STEADY_STATE: &str = "v1/steady-state";
 
+/// FleetLock update-confirmation API path endpoint (v1).
+static V1_UPDATE_CONFIRMATION: &str = "v1/update-confirmation";
+
 /// FleetLock JSON protocol: service error.
 #[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
 pub struct RemoteJsonError {
@@ -99,16 +106,27 @@ pub struct Client {
     hclient: reqwest::Client,
     /// Request body.
     body: String,
+    /// Client parameters, for requests with additional body fields.
+    #[serde(skip)]
+    client_params: ClientParameters,
+    /// Bearer token for authentication, if any.
+    #[serde(skip)]
+    bearer_token: Option<BearerToken>,
 }
 
 impl Client {
+    /// Return the reboot group of this client.
+    pub fn group(&self) -> &str {
+        &self.client_params.group
+    }
+
     /// Try to lock a semaphore slot on the remote manager.
     ///
     /// It returns `true` if the operation succeeds, or a `FleetLockError`
     /// with the relevant error explanation.
     pub fn pre_reboot(&self) -> impl Future<Output = Result<bool, FleetLockError>> {
         let req = self
-            .new_request(Method::POST, V1_PRE_REBOOT)
+            .new_request(Method::POST, V1_PRE_REBOOT, self.body.clone())
             .map_err(|e| FleetLockError::FailedClientBuilder(e.to_string()));
 
         futures::future::ready(req)
@@ -119,13 +137,50 @@ impl Client {
             .and_then(Self::map_response)
     }
 
-    /// Try to unlock a semaphore slot on the remote manager.
+    /// Try to unlock a semaphore slot on the remote manager, optionally
+    /// reporting a node health summary.
     ///
     /// It returns `true` if the operation succeeds, or a `FleetLockError`
     /// with the relevant error explanation.
-    pub fn steady_state(&self) -> impl Future<Output = Result<bool, FleetLockError>> {
-        let req = self
-            .new_request(Method::POST, V1_STEADY_STATE)
+    pub fn steady_state(
+        &self,
+        health: Option<&NodeHealth>,
+    ) -> impl Future<Output = Result<bool, FleetLockError>> {
+        let body = match health {
+            None => Ok(self.body.clone()),
+            Some(health) => serde_json::to_string_pretty(&SteadyStateBody {
+                client_params: &self.client_params,
+                health,
+            })
+            .map_err(anyhow::Error::from),
+        };
+        let req = body
+            .and_then(|body| self.new_request(Method::POST, V1_STEADY_STATE, body))
+            .map_err(|e| FleetLockError::FailedClientBuilder(e.to_string()));
+
+        futures::future::ready(req)
+            .and_then(|req| {
+                req.send()
+                    .map_err(|e| FleetLockError::FailedRequest(e.to_string()))
+            })
+            .and_then(Self::map_response)
+    }
+
+    /// Confirm a successful update to the remote manager.
+    ///
+    /// It returns `true` if the operation succeeds, or a `FleetLockError`
+    /// with the relevant error explanation.
+    pub fn update_confirmation(
+        &self,
+        update: &UpdateConfirmation,
+    ) -> impl Future<Output = Result<bool, FleetLockError>> {
+        let body = ConfirmationBody {
+            client_params: &self.client_params,
+            update,
+        };
+        let req = serde_json::to_string_pretty(&body)
+            .map_err(anyhow::Error::from)
+            .and_then(|body| self.new_request(Method::POST, V1_UPDATE_CONFIRMATION, body))
             .map_err(|e| FleetLockError::FailedClientBuilder(e.to_string()));
 
         futures::future::ready(req)
@@ -141,13 +196,17 @@ impl Client {
         &self,
         method: reqwest::Method,
         url_suffix: S,
+        body: String,
     ) -> Result<reqwest::RequestBuilder> {
         let url = self.api_base.clone().join(url_suffix.as_ref())?;
-        let builder = self
+        let mut builder = self
             .hclient
             .request(method, url)
-            .body(self.body.clone())
+            .body(body)
             .header("fleet-lock-protocol", "true");
+        if let Some(bearer) = &self.bearer_token {
+            builder = builder.bearer_auth(bearer.token()?);
+        }
         Ok(builder)
     }
 
@@ -174,6 +233,10 @@ pub struct ClientBuilder {
     api_base: String,
     /// Asynchronous reqwest client (custom).
     hclient: Option<reqwest::Client>,
+    /// Outbound network settings, for the default reqwest client.
+    network: NetworkSettings,
+    /// Bearer token for authentication (optional).
+    bearer_token: Option<BearerToken>,
     /// Client identity.
     client_identity: ClientIdentity,
 }
@@ -193,15 +256,65 @@ pub struct ClientParameters {
     group: String,
 }
 
+/// Update confirmation details.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct UpdateConfirmation {
+    /// Version of the release before the update.
+    pub from_version: String,
+    /// Version of the release after the update.
+    pub to_version: String,
+}
+
+/// Outcome of the last finalized update, as seen after reboot.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum UpdateOutcome {
+    /// No update recorded.
+    #[serde(rename = "none")]
+    NoneRecorded,
+    /// The node booted into the updated release.
+    Succeeded,
+    /// The node did not boot into the updated release.
+    Failed,
+}
+
+/// Node health summary, for steady-state reports.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct NodeHealth {
+    /// Version of the booted release.
+    pub booted_version: String,
+    /// Time since boot, in seconds.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub uptime_secs: Option<u64>,
+    /// Outcome of the last finalized update.
+    pub last_update_outcome: UpdateOutcome,
+}
+
+/// Request body for steady-state reports with a health summary.
+#[derive(Debug, Serialize)]
+struct SteadyStateBody<'a> {
+    client_params: &'a ClientParameters,
+    health: &'a NodeHealth,
+}
+
+/// Request body for update confirmations.
+#[derive(Debug, Serialize)]
+struct ConfirmationBody<'a> {
+    client_params: &'a ClientParameters,
+    update: &'a UpdateConfirmation,
+}
+
 impl ClientBuilder {
     /// Return a new client builder for the given base API endpoint URL.
-    pub(crate) fn new<T>(api_base: T, identity: &Identity) -> Self
+    pub fn new<T>(api_base: T, identity: &Identity) -> Self
     where
         T: Into<String>,
     {
         Self {
             api_base: api_base.into(),
             hclient: None,
+            network: NetworkSettings::default(),
+            bearer_token: None,
             client_identity: ClientIdentity {
                 client_params: ClientParameters {
                     id: identity.node_uuid.lower_hex(),
@@ -219,11 +332,34 @@ impl ClientBuilder {
         builder
     }
 
+    /// Set the outbound network settings to use.
+    pub fn network(self, network: NetworkSettings) -> Self {
+        let mut builder = self;
+        builder.network = network;
+        builder
+    }
+
+    /// Set the reboot group, instead of the node one.
+    pub fn group(self, group: String) -> Self {
+        let mut builder = self;
+        builder.client_identity.client_params.group = group;
+        builder
+    }
+
+    /// Set (or reset) the bearer token for authentication.
+    pub fn bearer_token(self, bearer_token: Option<BearerToken>) -> Self {
+        let mut builder = self;
+        builder.bearer_token = bearer_token;
+        builder
+    }
+
     /// Build a client with specified parameters.
     pub fn build(self) -> Result<Client> {
         let hclient = match self.hclient {
             Some(client) => client,
-            None => reqwest::ClientBuilder::new()
+            None => self
+                .network
+                .configure(reqwest::ClientBuilder::new())?
                 .timeout(DEFAULT_HTTP_COMPLETION_TIMEOUT)
                 .build()?,
         };
@@ -238,6 +374,8 @@ impl ClientBuilder {
             api_base,
             hclient,
             body,
+            client_params: self.client_identity.client_params,
+            bearer_token: self.bearer_token,
         };
         Ok(client)
     }
@@ -0,0 +1,247 @@
+//! Health checks before updates finalization.
+//!
+//! Rebooting a node which is already degraded (e.g. a failed storage mount,
+//! or a cluster member out of quorum) can turn a minor issue into an outage.
+//! Health checks are run right before finalization, and any failure holds
+//! the reboot back until the node is healthy again.
+
+use anyhow::{Context, Result};
+use fn_error_context::context;
+use futures::prelude::*;
+use prometheus::IntCounter;
+use serde::Serialize;
+use std::pin::Pin;
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+/// Timeout for a single health check (in seconds).
+const CHECK_TIMEOUT_SECS: u64 = 30;
+
+/// Polling interval while waiting for a health check to complete.
+const CHECK_POLL_INTERVAL_MILLIS: u64 = 100;
+
+lazy_static::lazy_static! {
+    static ref CHECK_RUNS: IntCounter = register_int_counter!(opts!(
+        "zincati_health_checks_runs_total",
+        "Total number of health check runs before finalization."
+    )).unwrap();
+    static ref CHECK_FAILURES: IntCounter = register_int_counter!(opts!(
+        "zincati_health_checks_failures_total",
+        "Total number of failed health check runs before finalization."
+    )).unwrap();
+}
+
+/// A single health check.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum HealthCheck {
+    /// A systemd unit, which must be active.
+    Unit {
+        /// Unit name.
+        name: String,
+    },
+    /// A command, which must exit successfully.
+    Command {
+        /// Program and arguments.
+        argv: Vec<String>,
+    },
+}
+
+impl HealthCheck {
+    /// Parse a health check, in `unit:<name>` or `command:<program> [args]`
+    /// format.
+    fn parse(input: &str) -> Result<Self> {
+        let (kind, value) = match input.split_once(':') {
+            Some((k, v)) => (k.trim(), v.trim()),
+            None => anyhow::bail!("missing kind in health check '{}'", input),
+        };
+        if value.is_empty() {
+            anyhow::bail!("empty health check '{}'", input);
+        }
+
+        let check = match kind {
+            "unit" => HealthCheck::Unit {
+                name: value.to_string(),
+            },
+            "command" => {
+                let argv: Vec<String> = value.split_whitespace().map(String::from).collect();
+                if !argv[0].starts_with('/') {
+                    anyhow::bail!("health check program '{}' is not absolute", argv[0]);
+                }
+                HealthCheck::Command { argv }
+            }
+            k => anyhow::bail!("unsupported health check kind '{}'", k),
+        };
+        Ok(check)
+    }
+
+    /// Run this check, with the given timeout.
+    fn run(&self, timeout: Duration) -> Result<()> {
+        let mut cmd = match self {
+            HealthCheck::Unit { name } => {
+                let mut cmd = Command::new("systemctl");
+                cmd.arg("is-active").arg("--quiet").arg(name);
+                cmd
+            }
+            HealthCheck::Command { argv } => {
+                let mut cmd = Command::new(&argv[0]);
+                cmd.args(&argv[1..]);
+                cmd
+            }
+        };
+
+        let mut child = cmd
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .context("failed to spawn process")?;
+        let deadline = Instant::now() + timeout;
+        let status = loop {
+            if let Some(status) = child.try_wait()? {
+                break status;
+            }
+            if Instant::now() >= deadline {
+                let _ = child.kill();
+                let _ = child.wait();
+                anyhow::bail!("timed out after {} seconds", timeout.as_secs());
+            }
+            std::thread::sleep(Duration::from_millis(CHECK_POLL_INTERVAL_MILLIS));
+        };
+
+        if !status.success() {
+            match self {
+                HealthCheck::Unit { .. } => anyhow::bail!("unit is not active"),
+                HealthCheck::Command { .. } => anyhow::bail!("command failed, {}", status),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for HealthCheck {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HealthCheck::Unit { name } => write!(f, "unit '{}'", name),
+            HealthCheck::Command { argv } => write!(f, "command '{}'", argv.join(" ")),
+        }
+    }
+}
+
+/// Health checks, run before finalizing an update.
+#[derive(Clone, Debug, Serialize)]
+pub struct HealthChecks {
+    /// Checks to run, in order.
+    pub checks: Vec<HealthCheck>,
+    /// Timeout for a single check.
+    pub timeout: Duration,
+}
+
+impl HealthChecks {
+    /// Process health checks configuration.
+    ///
+    /// This returns `None` if no health checks are configured.
+    #[context("failed to validate finalization health checks configuration")]
+    pub fn with_config(cfg: Vec<String>) -> Result<Option<Self>> {
+        if cfg.is_empty() {
+            return Ok(None);
+        }
+
+        let checks = cfg
+            .iter()
+            .map(|entry| HealthCheck::parse(entry))
+            .collect::<Result<Vec<_>>>()?;
+        log::info!("finalization gated on {} health check(s)", checks.len());
+
+        let health_checks = Self {
+            checks,
+            timeout: Duration::from_secs(CHECK_TIMEOUT_SECS),
+        };
+        Ok(Some(health_checks))
+    }
+
+    /// Run all health checks, stopping at the first failure.
+    ///
+    /// On failure, this returns a description of the failed check.
+    pub fn run(&self) -> Pin<Box<dyn Future<Output = Result<(), String>>>> {
+        CHECK_RUNS.inc();
+        let checks = self.checks.clone();
+        let timeout = self.timeout;
+        // The blocking task is only spawned once polled, within a runtime.
+        let run = async move {
+            let res = tokio::task::spawn_blocking(move || {
+                for check in checks {
+                    if let Err(e) = check.run(timeout) {
+                        return Err(format!("{}: {:#}", check, e));
+                    }
+                }
+                Ok(())
+            })
+            .await;
+            let outcome =
+                res.unwrap_or_else(|e| Err(format!("failed to join health checks: {}", e)));
+            if let Err(failed) = &outcome {
+                CHECK_FAILURES.inc();
+                log::warn!("finalization health check failed: {}", failed);
+            }
+            outcome
+        };
+        Box::pin(run)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::runtime as rt;
+
+    #[test]
+    fn parse_check() {
+        assert_eq!(
+            HealthCheck::parse("unit:etcd.service").unwrap(),
+            HealthCheck::Unit {
+                name: "etcd.service".to_string()
+            }
+        );
+        assert_eq!(
+            HealthCheck::parse("command:/usr/local/bin/check --quick").unwrap(),
+            HealthCheck::Command {
+                argv: vec!["/usr/local/bin/check".to_string(), "--quick".to_string()]
+            }
+        );
+
+        HealthCheck::parse("etcd.service").unwrap_err();
+        HealthCheck::parse("unit:").unwrap_err();
+        HealthCheck::parse("command:check").unwrap_err();
+        HealthCheck::parse("socket:/run/check.sock").unwrap_err();
+    }
+
+    #[test]
+    fn run_checks() {
+        let unset = HealthChecks::with_config(vec![]).unwrap();
+        assert!(unset.is_none());
+
+        let runtime = rt::Runtime::new().unwrap();
+        let healthy = HealthChecks::with_config(vec!["command:/bin/true".to_string()])
+            .unwrap()
+            .unwrap();
+        runtime.block_on(healthy.run()).unwrap();
+
+        let cfg = vec![
+            "command:/bin/true".to_string(),
+            "command:/bin/false".to_string(),
+        ];
+        let unhealthy = HealthChecks::with_config(cfg).unwrap().unwrap();
+        let failed = runtime.block_on(unhealthy.run()).unwrap_err();
+        assert!(failed.contains("/bin/false"), "{}", failed);
+
+        let slow = HealthChecks {
+            checks: vec![HealthCheck::Command {
+                argv: vec!["/bin/sleep".to_string(), "5".to_string()],
+            }],
+            timeout: Duration::from_millis(200),
+        };
+        let failed = runtime.block_on(slow.run()).unwrap_err();
+        assert!(failed.contains("timed out"), "{}", failed);
+    }
+}
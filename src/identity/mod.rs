@@ -1,7 +1,9 @@
 mod platform;
+mod provider;
 
 use crate::config::inputs;
 use crate::rpm_ostree;
+use crate::utils;
 use anyhow::{anyhow, ensure, Context, Result};
 use fn_error_context::context;
 use lazy_static::lazy_static;
@@ -9,12 +11,23 @@ use libsystemd::id128;
 use ordered_float::NotNan;
 use prometheus::{Gauge, IntGaugeVec};
 use regex::Regex;
-use serde::Serialize;
-use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::num::NonZeroU64;
+use std::path::Path;
 
 /// Default group for reboot management.
 static DEFAULT_GROUP: &str = "default";
 
+/// Absolute path to the persisted group set at runtime, overriding configuration.
+pub static GROUP_OVERRIDE_PATH: &str = "/var/lib/zincati/update-group.json";
+
+/// Absolute path to the persisted stream switch, pending until booted into the new stream.
+pub static STREAM_SWITCH_PATH: &str = "/var/lib/zincati/stream-switch.json";
+
+/// Maximum configurable minimum release age (one year, in hours).
+static MAX_RELEASE_AGE_HOURS: u64 = 365 * 24;
+
 /// Application ID (`de35106b6ec24688b63afddaa156679b`)
 static APP_ID: &[u8] = &[
     0xde, 0x35, 0x10, 0x6b, 0x6e, 0xc2, 0x46, 0x88, 0xb6, 0x3a, 0xfd, 0xda, 0xa1, 0x56, 0x67, 0x9b,
@@ -34,47 +47,88 @@ lazy_static::lazy_static! {
 
 /// Agent identity.
 #[derive(Debug, Serialize)]
-pub(crate) struct Identity {
+pub struct Identity {
     /// OS base architecture.
-    pub(crate) basearch: String,
+    pub basearch: String,
     /// Current OS (version and deployment base-checksum).
-    pub(crate) current_os: rpm_ostree::Release,
+    pub current_os: rpm_ostree::Release,
     /// Update group.
-    pub(crate) group: String,
+    pub group: String,
     /// Unique node identifier.
-    pub(crate) node_uuid: id128::Id128,
+    pub node_uuid: id128::Id128,
     /// OS platform.
-    pub(crate) platform: String,
+    pub platform: String,
     /// Client wariness for rollout throttling.
-    pub(crate) rollout_wariness: Option<NotNan<f64>>,
+    pub rollout_wariness: Option<NotNan<f64>>,
+    /// Minimum age of update targets, in hours.
+    pub min_release_age_hours: Option<u64>,
     /// Stream label.
-    pub(crate) stream: String,
+    pub stream: String,
+    /// Custom parameters, from identity providers.
+    pub parameters: BTreeMap<String, String>,
+    /// Extra client parameters, forwarded to Cincinnati.
+    pub extra_params: BTreeMap<String, String>,
 }
 
 impl Identity {
     /// Create from configuration.
     #[context("failed to validate agent identity configuration")]
-    pub(crate) fn with_config(cfg: inputs::IdentityInput) -> Result<Self> {
-        let mut id = Self::try_default().context("failed to build default identity")?;
+    pub fn with_config(cfg: inputs::IdentityInput) -> Result<Self> {
+        let mut id = Self::try_default(cfg.basearch.as_deref())
+            .context("failed to build default identity")?;
+
+        let providers = if cfg.providers.is_empty() {
+            vec![provider::Provider::Config]
+        } else {
+            cfg.providers
+                .iter()
+                .map(|p| provider::Provider::parse(p))
+                .collect::<Result<Vec<_>>>()?
+        };
+        let mut attrs = provider::collect(&providers, &cfg)?;
 
-        if !cfg.group.is_empty() {
-            id.group = cfg.group;
+        if let Some(group) = attrs.remove("group") {
+            id.group = group;
+        };
+        match load_group_override(GROUP_OVERRIDE_PATH) {
+            Ok(Some(group)) => {
+                log::info!(
+                    "update group '{}' set at runtime, overriding configuration",
+                    group
+                );
+                id.group = group;
+            }
+            Ok(None) => {}
+            Err(e) => log::error!("{:#}", e),
         };
         id.validate_group_label()?;
 
-        if !cfg.node_uuid.is_empty() {
-            id.node_uuid = id128::Id128::parse_str(&cfg.node_uuid)
+        if let Some(node_uuid) = attrs.remove("node_uuid") {
+            id.node_uuid = id128::Id128::parse_str(&node_uuid)
                 .map_err(|e| anyhow!("failed to parse node UUID: {}", e))?;
         }
+        id.parameters = attrs;
 
         if let Some(rw) = cfg.rollout_wariness {
             ensure!(*rw >= 0.0, "unexpected negative rollout wariness: {}", rw);
             ensure!(*rw <= 1.0, "unexpected overlarge rollout wariness: {}", rw);
 
-            prometheus::register(Box::new(ROLLOUT_WARINESS.clone()))?;
+            // Configuration can be assembled again on reload.
+            match prometheus::register(Box::new(ROLLOUT_WARINESS.clone())) {
+                Ok(_) | Err(prometheus::Error::AlreadyReg) => {}
+                Err(e) => return Err(e.into()),
+            };
             ROLLOUT_WARINESS.set(*rw);
             id.rollout_wariness = Some(rw);
         }
+        if let Some(age) = cfg.min_release_age_hours.map(NonZeroU64::get) {
+            ensure!(
+                age <= MAX_RELEASE_AGE_HOURS,
+                "unexpected overlarge minimum release age: {} hours",
+                age
+            );
+            id.min_release_age_hours = Some(age);
+        }
 
         // Export info-metrics with details about booted deployment.
         OS_INFO
@@ -86,15 +140,53 @@ impl Identity {
             ])
             .set(1);
 
+        // A stream switch is pending until the node boots into the new
+        // stream; meanwhile, updates are looked up on the new stream.
+        match load_stream_switch(STREAM_SWITCH_PATH) {
+            Ok(Some(stream)) if stream == id.stream => {
+                log::info!("switch to update stream '{}' completed", stream);
+                if let Err(e) = utils::remove_if_exists(STREAM_SWITCH_PATH) {
+                    log::error!("{:#}", e);
+                }
+            }
+            Ok(Some(stream)) => {
+                log::info!(
+                    "switch from update stream '{}' to '{}' pending reboot",
+                    id.stream,
+                    stream
+                );
+                id.stream = stream;
+            }
+            Ok(None) => {}
+            Err(e) => log::error!("{:#}", e),
+        };
+
+        id.extra_params = id
+            .render_extra_params(&cfg.extra_params)
+            .context("invalid extra client parameters")?;
+
         Ok(id)
     }
 
     /// Try to build default agent identity.
-    pub fn try_default() -> Result<Self> {
+    ///
+    /// If `basearch_override` is set, it replaces the base architecture of
+    /// the booted deployment, which is then not required to be valid.
+    pub fn try_default(basearch_override: Option<&str>) -> Result<Self> {
         // Invoke rpm-ostree to get the status of the currently booted deployment.
         let status = rpm_ostree::invoke_cli_status(true)?;
-        let basearch = rpm_ostree::parse_basearch(&status)
-            .context("failed to introspect OS base architecture")?;
+        let basearch = match basearch_override {
+            Some(basearch) => {
+                validate_basearch_override(basearch)?;
+                log::warn!(
+                    "base architecture overridden by configuration: '{}'",
+                    basearch
+                );
+                basearch.to_string()
+            }
+            None => rpm_ostree::parse_basearch(&status)
+                .context("failed to introspect OS base architecture")?,
+        };
         let current_os =
             rpm_ostree::parse_booted(&status).context("failed to introspect booted OS image")?;
         let node_uuid = {
@@ -114,6 +206,9 @@ impl Identity {
             group: DEFAULT_GROUP.to_string(),
             node_uuid,
             rollout_wariness: None,
+            min_release_age_hours: None,
+            parameters: BTreeMap::new(),
+            extra_params: BTreeMap::new(),
         };
         Ok(id)
     }
@@ -122,7 +217,7 @@ impl Identity {
     pub fn url_variables(&self) -> HashMap<String, String> {
         // This explicitly does not include "current_version"
         // and "node_uuid".
-        let mut vars = HashMap::new();
+        let mut vars: HashMap<String, String> = self.parameters.clone().into_iter().collect();
         vars.insert("basearch".to_string(), self.basearch.clone());
         vars.insert("group".to_string(), self.group.clone());
         vars.insert("platform".to_string(), self.platform.clone());
@@ -132,7 +227,7 @@ impl Identity {
 
     /// Return Cincinnati client parameters.
     pub fn cincinnati_params(&self) -> HashMap<String, String> {
-        let mut vars = HashMap::new();
+        let mut vars: HashMap<String, String> = self.extra_params.clone().into_iter().collect();
         vars.insert("basearch".to_string(), self.basearch.clone());
         vars.insert("os_checksum".to_string(), self.current_os.checksum.clone());
         vars.insert("os_version".to_string(), self.current_os.version.clone());
@@ -146,21 +241,74 @@ impl Identity {
         vars
     }
 
-    #[cfg(test)]
-    pub(crate) fn mock_default() -> Self {
+    /// Return the minimum age of update targets, if any.
+    pub fn min_release_age(&self) -> Option<chrono::Duration> {
+        self.min_release_age_hours
+            .map(|hours| chrono::Duration::hours(hours as i64))
+    }
+
+    /// Build a mock identity, for tests.
+    #[cfg(any(test, feature = "e2e-tests"))]
+    pub fn mock_default() -> Self {
         Self {
             basearch: "mock-amd64".to_string(),
             current_os: rpm_ostree::Release {
                 version: "0.0.0-mock".to_string(),
                 checksum: "sha-mock".to_string(),
                 age_index: None,
+                advisory: None,
+                wave: None,
             },
             group: "mock-workers".to_string(),
             node_uuid: id128::Id128::parse_str("e0f3745b108f471cbd4883c6fbed8cdd").unwrap(),
             platform: "mock-azure".to_string(),
             rollout_wariness: Some(NotNan::new(0.5).unwrap()),
+            min_release_age_hours: None,
             stream: "mock-stable".to_string(),
+            parameters: BTreeMap::new(),
+            extra_params: BTreeMap::new(),
+        }
+    }
+
+    /// Render extra client parameters, where values can reference identity
+    /// variables (e.g. `${zone}`).
+    ///
+    /// Keys and values end up in the query string of Cincinnati requests,
+    /// thus they are restricted to URL-safe characters.
+    fn render_extra_params(
+        &self,
+        templates: &BTreeMap<String, String>,
+    ) -> Result<BTreeMap<String, String>> {
+        static VALID_VALUE: &str = "^[a-zA-Z0-9._~-]+$";
+        lazy_static! {
+            static ref VALID_VALUE_REGEX: Regex = Regex::new(VALID_VALUE).unwrap();
+        }
+
+        let vars = self.url_variables();
+        let mut params = BTreeMap::new();
+        for (key, template) in templates {
+            provider::validate_key(key)?;
+            ensure!(
+                key != "node_uuid" && key != "group",
+                "parameter '{}' is reserved",
+                key
+            );
+            let value = if envsubst::is_templated(template) {
+                envsubst::validate_vars(&vars)?;
+                envsubst::substitute(template.clone(), &vars)?
+            } else {
+                template.clone()
+            };
+            ensure!(
+                VALID_VALUE_REGEX.is_match(&value),
+                "invalid value '{}' for parameter '{}': not conforming to expression '{}'",
+                value,
+                key,
+                VALID_VALUE
+            );
+            params.insert(key.clone(), value);
         }
+        Ok(params)
     }
 
     /// Validate the group label value in current identity.
@@ -169,19 +317,115 @@ impl Identity {
     /// This ensures that label value is compliant to specs regex:
     ///  - https://coreos.github.io/zincati/development/fleetlock/protocol/#body
     fn validate_group_label(&self) -> Result<()> {
-        static VALID_GROUP: &str = "^[a-zA-Z0-9.-]+$";
-        lazy_static! {
-            static ref VALID_GROUP_REGEX: Regex = Regex::new(VALID_GROUP).unwrap();
-        }
-        if !VALID_GROUP_REGEX.is_match(&self.group) {
-            anyhow::bail!(
-                "invalid group label '{}': not conforming to expression '{}'",
-                self.group,
-                VALID_GROUP
-            );
-        }
-        Ok(())
+        validate_group_label(&self.group)
+    }
+}
+
+/// Validate a group label value.
+pub fn validate_group_label(group: &str) -> Result<()> {
+    static VALID_GROUP: &str = "^[a-zA-Z0-9.-]+$";
+    lazy_static! {
+        static ref VALID_GROUP_REGEX: Regex = Regex::new(VALID_GROUP).unwrap();
     }
+    if !VALID_GROUP_REGEX.is_match(group) {
+        anyhow::bail!(
+            "invalid group label '{}': not conforming to expression '{}'",
+            group,
+            VALID_GROUP
+        );
+    }
+    Ok(())
+}
+
+/// Validate an update stream label.
+pub fn validate_stream_label(stream: &str) -> Result<()> {
+    static VALID_STREAM: &str = "^[a-z0-9][a-z0-9._-]*$";
+    lazy_static! {
+        static ref VALID_STREAM_REGEX: Regex = Regex::new(VALID_STREAM).unwrap();
+    }
+    if !VALID_STREAM_REGEX.is_match(stream) {
+        anyhow::bail!(
+            "invalid stream label '{}': not conforming to expression '{}'",
+            stream,
+            VALID_STREAM
+        );
+    }
+    Ok(())
+}
+
+/// Validate a base architecture override.
+fn validate_basearch_override(basearch: &str) -> Result<()> {
+    ensure!(
+        rpm_ostree::KNOWN_BASEARCHES.contains(&basearch),
+        "unknown base architecture override '{}', expected one of: {}",
+        basearch,
+        rpm_ostree::KNOWN_BASEARCHES.join(", ")
+    );
+    Ok(())
+}
+
+/// Group set at runtime, persisted across agent restarts.
+#[derive(Debug, Deserialize, Serialize)]
+struct GroupOverride {
+    group: String,
+}
+
+/// Load the group set at runtime from `path`, if any.
+#[context("failed to load update group override")]
+fn load_group_override(path: impl AsRef<Path>) -> Result<Option<String>> {
+    let path = path.as_ref();
+    let content = match std::fs::read(path) {
+        Ok(c) => c,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).with_context(|| format!("failed to read '{}'", path.display())),
+    };
+    let entry: GroupOverride = serde_json::from_slice(&content)
+        .with_context(|| format!("failed to parse '{}'", path.display()))?;
+
+    Ok(Some(entry.group))
+}
+
+/// Persist a group set at runtime to `path`, overriding configuration.
+#[context("failed to persist update group override")]
+pub fn persist_group_override(path: impl AsRef<Path>, group: &str) -> Result<()> {
+    validate_group_label(group)?;
+    let entry = GroupOverride {
+        group: group.to_string(),
+    };
+    let content = serde_json::to_vec(&entry)?;
+    utils::atomic_write(path, 0o644, &content)
+}
+
+/// Stream switched to at runtime, persisted until booted into it.
+#[derive(Debug, Deserialize, Serialize)]
+struct StreamSwitchEntry {
+    stream: String,
+}
+
+/// Load the stream switched to at runtime from `path`, if any.
+#[context("failed to load pending stream switch")]
+fn load_stream_switch(path: impl AsRef<Path>) -> Result<Option<String>> {
+    let path = path.as_ref();
+    let content = match std::fs::read(path) {
+        Ok(c) => c,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).with_context(|| format!("failed to read '{}'", path.display())),
+    };
+    let entry: StreamSwitchEntry = serde_json::from_slice(&content)
+        .with_context(|| format!("failed to parse '{}'", path.display()))?;
+
+    Ok(Some(entry.stream))
+}
+
+/// Persist a stream switched to at runtime to `path`.
+#[context("failed to persist pending stream switch")]
+pub fn persist_stream_switch(path: impl AsRef<Path>, stream: &str) -> Result<()> {
+    validate_stream_label(stream)?;
+    let entry = StreamSwitchEntry {
+        stream: stream.to_string(),
+    };
+    let content = serde_json::to_vec(&entry)?;
+    utils::atomic_write(path, 0o644, &content)
 }
 
 fn compute_node_uuid(app_id: &id128::Id128) -> Result<id128::Id128> {
@@ -237,6 +481,77 @@ mod tests {
         }
     }
 
+    #[test]
+    fn identity_extra_params() {
+        let mut id = Identity::mock_default();
+        id.parameters
+            .insert("zone".to_string(), "eu-west-1b".to_string());
+
+        let templates: BTreeMap<String, String> = vec![
+            ("datacenter", "${zone}"),
+            ("hw_model", "r740"),
+            ("pool", "${group}"),
+        ]
+        .into_iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+        id.extra_params = id.render_extra_params(&templates).unwrap();
+        let params = id.cincinnati_params();
+        assert_eq!(params["datacenter"], "eu-west-1b");
+        assert_eq!(params["hw_model"], "r740");
+        assert_eq!(params["pool"], "mock-workers");
+        assert!(!id.url_variables().contains_key("datacenter"));
+
+        let invalid = vec![
+            ("stream", "next"),
+            ("node_uuid", "abc"),
+            ("Model", "r740"),
+            ("hw_model", "r740 xd"),
+            ("hw_model", "r&d"),
+            ("hw_model", ""),
+            ("datacenter", "${region}"),
+        ];
+        for (key, value) in invalid {
+            let mut templates = BTreeMap::new();
+            templates.insert(key.to_string(), value.to_string());
+            id.render_extra_params(&templates).unwrap_err();
+        }
+    }
+
+    #[test]
+    fn group_override_roundtrip() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let path = tmpdir.path().join("update-group.json");
+
+        assert_eq!(load_group_override(&path).unwrap(), None);
+        persist_group_override(&path, "intránët").unwrap_err();
+        assert_eq!(load_group_override(&path).unwrap(), None);
+
+        persist_group_override(&path, "canary").unwrap();
+        assert_eq!(
+            load_group_override(&path).unwrap(),
+            Some("canary".to_string())
+        );
+    }
+
+    #[test]
+    fn stream_switch_roundtrip() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let path = tmpdir.path().join("stream-switch.json");
+
+        assert_eq!(load_stream_switch(&path).unwrap(), None);
+        for invalid in &["", "Stable", "next/devel", "-testing"] {
+            persist_stream_switch(&path, invalid).unwrap_err();
+        }
+        assert_eq!(load_stream_switch(&path).unwrap(), None);
+
+        persist_stream_switch(&path, "testing").unwrap();
+        assert_eq!(
+            load_stream_switch(&path).unwrap(),
+            Some("testing".to_string())
+        );
+    }
+
     #[test]
     fn identity_validate_group() {
         let id = Identity::mock_default();
@@ -250,4 +565,12 @@ mod tests {
         assert!(vars.contains_key("os_checksum"));
         assert!(vars.contains_key("os_version"));
     }
+
+    #[test]
+    fn basearch_override() {
+        validate_basearch_override("aarch64").unwrap();
+        for invalid in &["", "arm64", "X86_64", "i686"] {
+            validate_basearch_override(invalid).unwrap_err();
+        }
+    }
 }
@@ -4,7 +4,7 @@
 //!  just enough logic to extract the platform ID value. In particular, it does not
 //!  handle separator quoting/escaping, list of values, and merging of repeated
 //!  flags. Logic is taken from Afterburn, please backport any bugfix there too:
-//!  https://github.com/coreos/afterburn/blob/v4.1.0/src/util/cmdline.rs
+//!  <https://github.com/coreos/afterburn/blob/v4.1.0/src/util/cmdline.rs>
 
 use anyhow::{Context, Result};
 use std::io::Read;
@@ -14,7 +14,7 @@ use std::{fs, io};
 static CMDLINE_PLATFORM_FLAG: &str = "ignition.platform.id";
 
 /// Read platform value from cmdline file.
-pub(crate) fn read_id<T>(cmdline_path: T) -> Result<String>
+pub fn read_id<T>(cmdline_path: T) -> Result<String>
 where
     T: AsRef<str>,
 {
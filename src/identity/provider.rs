@@ -0,0 +1,307 @@
+//! Pluggable providers of identity values.
+//!
+//! Providers run in the configured order, each one contributing a set of
+//! attributes: `node_uuid` and `group` set the corresponding identity values,
+//! while all other keys are custom parameters. Values from later providers
+//! override the ones from earlier providers.
+
+use crate::config::inputs;
+use anyhow::{anyhow, ensure, Context, Result};
+use fn_error_context::context;
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// Absolute path to the cloud metadata written by Afterburn.
+static AFTERBURN_METADATA_PATH: &str = "/run/metadata/afterburn";
+
+/// Keys for identity values which cannot be set by providers.
+static RESERVED_KEYS: [&str; 7] = [
+    "basearch",
+    "os_checksum",
+    "os_version",
+    "platform",
+    "rollout_wariness",
+    "rollout_waves",
+    "stream",
+];
+
+/// Identity attributes, by key.
+pub(crate) type Attributes = BTreeMap<String, String>;
+
+/// A provider of identity values.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Provider {
+    /// Values from the `identity` configuration section.
+    Config,
+    /// Cloud metadata (instance ID, region, zone) fetched by Afterburn.
+    Afterburn {
+        /// Path to the metadata file.
+        path: PathBuf,
+    },
+    /// An executable hook, printing `key=value` lines.
+    Command {
+        /// Program and arguments.
+        argv: Vec<String>,
+    },
+}
+
+impl Provider {
+    /// Parse a provider, in `config`, `afterburn` or `command:<program> [args]` format.
+    pub(crate) fn parse(input: &str) -> Result<Self> {
+        let input = input.trim();
+        if let Some(value) = input.strip_prefix("command:") {
+            let argv: Vec<String> = value.split_whitespace().map(String::from).collect();
+            match argv.first() {
+                None => anyhow::bail!("empty identity provider '{}'", input),
+                Some(program) if !program.starts_with('/') => {
+                    anyhow::bail!("identity provider program '{}' is not absolute", program)
+                }
+                Some(_) => {}
+            };
+            return Ok(Provider::Command { argv });
+        }
+
+        match input {
+            "config" => Ok(Provider::Config),
+            "afterburn" => Ok(Provider::Afterburn {
+                path: PathBuf::from(AFTERBURN_METADATA_PATH),
+            }),
+            _ => anyhow::bail!("unknown identity provider '{}'", input),
+        }
+    }
+
+    /// Return attributes from this provider.
+    ///
+    /// `previous` are the attributes from earlier providers, which can be
+    /// referenced by a templated `group` in configuration.
+    fn attributes(&self, cfg: &inputs::IdentityInput, previous: &Attributes) -> Result<Attributes> {
+        match self {
+            Provider::Config => config_attributes(cfg, previous),
+            Provider::Afterburn { path } => afterburn_attributes(path),
+            Provider::Command { argv } => command_attributes(argv),
+        }
+    }
+}
+
+/// Collect attributes from all `providers`, in order.
+#[context("failed to collect identity values from providers")]
+pub(crate) fn collect(providers: &[Provider], cfg: &inputs::IdentityInput) -> Result<Attributes> {
+    let mut attrs = Attributes::new();
+    for provider in providers {
+        let provided = provider.attributes(cfg, &attrs)?;
+        log::trace!("identity values from {:?}: {:?}", provider, provided);
+        attrs.extend(provided);
+    }
+    Ok(attrs)
+}
+
+/// Validate the key of an attribute.
+pub(super) fn validate_key(key: &str) -> Result<()> {
+    static VALID_KEY: &str = "^[a-z][a-z0-9_]*$";
+    lazy_static! {
+        static ref VALID_KEY_REGEX: Regex = Regex::new(VALID_KEY).unwrap();
+    }
+    ensure!(
+        VALID_KEY_REGEX.is_match(key),
+        "invalid identity key '{}': not conforming to expression '{}'",
+        key,
+        VALID_KEY
+    );
+    ensure!(
+        !RESERVED_KEYS.contains(&key),
+        "identity key '{}' is reserved",
+        key
+    );
+    Ok(())
+}
+
+/// Return attributes from configuration.
+fn config_attributes(cfg: &inputs::IdentityInput, previous: &Attributes) -> Result<Attributes> {
+    let mut attrs = Attributes::new();
+    for (key, value) in &cfg.parameters {
+        validate_key(key)?;
+        ensure!(
+            key != "node_uuid" && key != "group",
+            "identity key '{}' is not a custom parameter",
+            key
+        );
+        attrs.insert(key.clone(), value.clone());
+    }
+    if !cfg.node_uuid.is_empty() {
+        attrs.insert("node_uuid".to_string(), cfg.node_uuid.clone());
+    }
+    if !cfg.group.is_empty() {
+        let group = if envsubst::is_templated(&cfg.group) {
+            let vars: HashMap<String, String> = previous.clone().into_iter().collect();
+            envsubst::validate_vars(&vars)?;
+            let group = envsubst::substitute(cfg.group.clone(), &vars)?;
+            ensure!(
+                !envsubst::is_templated(&group),
+                "unknown variables in group template '{}'",
+                cfg.group
+            );
+            group
+        } else {
+            cfg.group.clone()
+        };
+        attrs.insert("group".to_string(), group);
+    }
+    Ok(attrs)
+}
+
+/// Return instance ID, region and zone from Afterburn metadata.
+#[context("failed to read cloud metadata from '{}'", path.display())]
+fn afterburn_attributes(path: &Path) -> Result<Attributes> {
+    let content = std::fs::read_to_string(path)?;
+    let mut attrs = Attributes::new();
+    for line in content.lines() {
+        let (name, value) = match line.split_once('=') {
+            Some((n, v)) if !v.trim().is_empty() => (n.trim(), v.trim()),
+            _ => continue,
+        };
+        if !name.starts_with("AFTERBURN_") {
+            continue;
+        }
+        let key = if name.ends_with("_INSTANCE_ID") {
+            "instance_id"
+        } else if name.ends_with("_REGION") {
+            "region"
+        } else if name.ends_with("_ZONE") {
+            "zone"
+        } else {
+            continue;
+        };
+        attrs.insert(key.to_string(), value.to_string());
+    }
+    Ok(attrs)
+}
+
+/// Return attributes printed by an executable hook.
+#[context("failed to run identity provider '{}'", argv.join(" "))]
+fn command_attributes(argv: &[String]) -> Result<Attributes> {
+    let (program, args) = argv.split_first().ok_or_else(|| anyhow!("empty command"))?;
+    let output = Command::new(program)
+        .args(args)
+        .stdin(Stdio::null())
+        .stderr(Stdio::inherit())
+        .output()?;
+    ensure!(output.status.success(), "failed with {}", output.status);
+    let stdout = String::from_utf8(output.stdout).context("invalid UTF-8 output")?;
+    parse_lines(&stdout)
+}
+
+/// Parse `key=value` lines, ignoring empty lines and comments.
+fn parse_lines(content: &str) -> Result<Attributes> {
+    let mut attrs = Attributes::new();
+    for line in content.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| anyhow!("invalid line '{}', expected 'key=value'", line))?;
+        let key = key.trim();
+        if key != "node_uuid" && key != "group" {
+            validate_key(key)?;
+        }
+        attrs.insert(key.to_string(), value.trim().to_string());
+    }
+    Ok(attrs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn input(group: &str) -> inputs::IdentityInput {
+        inputs::IdentityInput {
+            group: group.to_string(),
+            node_uuid: String::new(),
+            rollout_wariness: None,
+            min_release_age_hours: None,
+            basearch: None,
+            providers: vec![],
+            parameters: BTreeMap::new(),
+            extra_params: BTreeMap::new(),
+        }
+    }
+
+    #[test]
+    fn provider_parse() {
+        assert_eq!(Provider::parse("config").unwrap(), Provider::Config);
+        assert_eq!(
+            Provider::parse(" afterburn ").unwrap(),
+            Provider::Afterburn {
+                path: PathBuf::from(AFTERBURN_METADATA_PATH)
+            }
+        );
+        assert_eq!(
+            Provider::parse("command:/usr/local/bin/identity --json").unwrap(),
+            Provider::Command {
+                argv: vec!["/usr/local/bin/identity".to_string(), "--json".to_string()]
+            }
+        );
+        for invalid in &["", "cloud", "command:", "command:identity"] {
+            Provider::parse(invalid).unwrap_err();
+        }
+    }
+
+    #[test]
+    fn afterburn_zone_group() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let path = tmpdir.path().join("afterburn");
+        std::fs::write(
+            &path,
+            "AFTERBURN_AWS_INSTANCE_ID=i-0123456789\nAFTERBURN_AWS_REGION=eu-west-1\nAFTERBURN_AWS_AVAILABILITY_ZONE=eu-west-1b\nAFTERBURN_AWS_HOSTNAME=ip-10-0-0-1\n",
+        )
+        .unwrap();
+
+        let mut cfg = input("${zone}");
+        cfg.parameters.insert("rack".to_string(), "r12".to_string());
+        let providers = vec![Provider::Afterburn { path }, Provider::Config];
+        let attrs = collect(&providers, &cfg).unwrap();
+
+        let expected: Attributes = vec![
+            ("group", "eu-west-1b"),
+            ("instance_id", "i-0123456789"),
+            ("rack", "r12"),
+            ("region", "eu-west-1"),
+            ("zone", "eu-west-1b"),
+        ]
+        .into_iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+        assert_eq!(attrs, expected);
+
+        // Template variables must come from earlier providers.
+        collect(&[Provider::Config], &cfg).unwrap_err();
+    }
+
+    #[test]
+    fn command_attributes_override() {
+        let providers = vec![
+            Provider::Config,
+            Provider::parse("command:/usr/bin/printf group=canary\\nrack=r7\\n").unwrap(),
+        ];
+        let attrs = collect(&providers, &input("workers")).unwrap();
+        assert_eq!(attrs["group"], "canary");
+        assert_eq!(attrs["rack"], "r7");
+
+        let failing = Provider::parse("command:/bin/false").unwrap();
+        collect(&[failing], &input("")).unwrap_err();
+    }
+
+    #[test]
+    fn parse_lines_keys() {
+        let attrs = parse_lines("# comment\n\nnode_uuid=abc\ncustom_1 = x\n").unwrap();
+        assert_eq!(attrs["node_uuid"], "abc");
+        assert_eq!(attrs["custom_1"], "x");
+
+        for invalid in &["no-separator", "Upper=x", "stream=next", "os_version=1"] {
+            parse_lines(invalid).unwrap_err();
+        }
+    }
+}
@@ -0,0 +1,79 @@
+//! Core update logic for Fedora CoreOS auto-updates.
+//!
+//! This library contains the update-decision logic of Zincati, which can
+//! be reused by other tools (e.g. test harnesses or fleet simulators):
+//!  * a client for the Cincinnati update-graph service, and target selection
+//!    logic on top of its graph;
+//!  * update strategies, which decide when an update can be finalized;
+//!  * configuration parsing and validation for both of the above;
+//!  * Monte-Carlo simulation of fleet-wide rollouts, and replay of update
+//!    strategies over time ranges.
+//!
+//! The `zincati` daemon (agent state machine, D-Bus and metrics services)
+//! is built on top of this library.
+
+#![deny(missing_debug_implementations)]
+#![deny(missing_docs)]
+
+#[macro_use(fail_point)]
+extern crate fail;
+#[macro_use]
+extern crate prometheus;
+
+/// Blackout periods for updates finalization.
+pub mod blackout;
+/// Cincinnati client.
+pub mod cincinnati;
+/// File-based configuration.
+pub mod config;
+/// Connectivity gate for updates finalization.
+pub mod connectivity;
+/// Logic for monthly and date-based maintenance windows.
+pub mod dated;
+/// Free disk space checks before staging updates.
+pub mod disk_space;
+/// Barriers against downgrading too far back.
+pub mod downgrade;
+/// Scheduling for update downloads.
+pub mod download;
+/// Durable on-disk queues for outgoing events.
+pub mod event_queue;
+/// FleetLock client.
+#[cfg(feature = "fleet-lock")]
+pub mod fleet_lock;
+/// Health checks before updates finalization.
+pub mod health_checks;
+/// Agent identity.
+pub mod identity;
+/// Templates for user-facing messages.
+pub mod messages;
+/// Outbound network settings.
+pub mod network;
+/// OSTree remote update source.
+pub mod ostree_remote;
+/// Reporting of update outcomes.
+pub mod outcome_report;
+/// Post-boot confirmation hooks.
+pub mod post_boot;
+/// Quiescing services before updates finalization.
+pub mod quiesce;
+/// rpm-ostree client.
+pub mod rpm_ostree;
+/// Fleet rollout simulation and strategy replay.
+pub mod simulate;
+/// Update strategies.
+pub mod strategy;
+/// Switching to other update streams.
+pub mod stream_switch;
+/// Settings for the metrics exporter over TCP.
+pub mod telemetry;
+/// Sources of update hints.
+pub mod update_source;
+/// Urgency override for security-critical updates.
+pub mod urgency;
+/// Miscellaneous utilities.
+pub mod utils;
+/// Delivery of agent events to a webhook.
+pub mod webhook;
+/// Logic for weekly maintenance windows.
+pub mod weekly;
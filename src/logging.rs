@@ -0,0 +1,246 @@
+//! Logging setup, and runtime verbosity tweaks.
+//!
+//! When running under systemd with stderr connected to the journal, log
+//! records are sent natively to journald, so that they can carry
+//! structured fields.
+
+use crate::utils;
+use anyhow::{ensure, Context, Result};
+use fn_error_context::context;
+use libsystemd::logging::Priority;
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use std::os::unix::fs::MetadataExt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::Duration;
+use structopt::clap::crate_name;
+
+/// Absolute path to the persisted log level.
+static LOG_LEVEL_PATH: &str = "/var/lib/zincati/log-level";
+
+/// Path to the standard error stream of this process.
+static STDERR_PATH: &str = "/proc/self/fd/2";
+
+/// Whether records are sent natively to journald.
+static TO_JOURNAL: AtomicBool = AtomicBool::new(false);
+
+/// Maximum duration of a temporary log level change (in seconds).
+#[cfg_attr(not(feature = "dbus"), allow(dead_code))]
+const MAX_TEMPORARY_LEVEL_SECS: u64 = 24 * 60 * 60; // 1 day.
+
+/// Generation of runtime log level changes, so that stale reverts are skipped.
+#[cfg_attr(not(feature = "dbus"), allow(dead_code))]
+static LEVEL_GENERATION: AtomicU64 = AtomicU64::new(0);
+
+/// Native journald logger.
+static JOURNAL_LOGGER: JournalLogger = JournalLogger;
+
+/// Initialize logging, at the given level.
+///
+/// The logger itself lets all agent messages through, while the effective
+/// level is enforced via the global maximum level, so that it can be
+/// changed at runtime.
+pub(crate) fn init(level: LevelFilter) {
+    if connected_to_journal() && log::set_logger(&JOURNAL_LOGGER).is_ok() {
+        TO_JOURNAL.store(true, Ordering::Relaxed);
+    } else {
+        env_logger::Builder::from_default_env()
+            .format_timestamp(None)
+            .format_module_path(false)
+            .filter(Some(crate_name!()), LevelFilter::Trace)
+            .init();
+    }
+    log::set_max_level(level);
+}
+
+/// Log an informational message, with additional structured fields.
+///
+/// Fields are only recorded when logging natively to journald.
+pub(crate) fn info_with_fields(msg: &str, fields: &[(&str, &str)]) {
+    if !TO_JOURNAL.load(Ordering::Relaxed) || log::max_level() < Level::Info {
+        log::info!("{}", msg);
+        return;
+    }
+
+    if let Err(e) = libsystemd::logging::journal_send(Priority::Info, msg, fields.iter().copied()) {
+        eprintln!("{} (failed to send to journal: {})", msg, e);
+    }
+}
+
+/// Return whether stderr is connected to the journal (per `JOURNAL_STREAM`).
+fn connected_to_journal() -> bool {
+    let journal_stream = match std::env::var("JOURNAL_STREAM") {
+        Ok(s) => s,
+        Err(_) => return false,
+    };
+    let stderr = match std::fs::metadata(STDERR_PATH) {
+        Ok(m) => m,
+        Err(_) => return false,
+    };
+    journal_stream == format!("{}:{}", stderr.dev(), stderr.ino())
+}
+
+/// Logger sending records natively to journald.
+#[derive(Debug)]
+struct JournalLogger;
+
+impl Log for JournalLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        // Same filtering as the default logger: all agent messages, and
+        // only errors from dependencies.
+        metadata.target().starts_with(crate_name!()) || metadata.level() == Level::Error
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let priority = match record.level() {
+            Level::Error => Priority::Error,
+            Level::Warn => Priority::Warning,
+            Level::Info => Priority::Info,
+            Level::Debug | Level::Trace => Priority::Debug,
+        };
+        let msg = record.args().to_string();
+        let mut fields = vec![("ZINCATI_TARGET", record.target().to_string())];
+        if let Some(module) = record.module_path() {
+            fields.push(("CODE_MODULE", module.to_string()));
+        }
+        if let Some(line) = record.line() {
+            fields.push(("CODE_LINE", line.to_string()));
+        }
+        if let Err(e) = libsystemd::logging::journal_send(priority, &msg, fields.into_iter()) {
+            eprintln!("{} (failed to send to journal: {})", msg, e);
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// Parse a log level name (e.g. `debug`).
+pub(crate) fn parse_level(input: &str) -> Result<LevelFilter> {
+    LevelFilter::from_str(input.trim())
+        .map_err(|_| anyhow::anyhow!("invalid log level '{}'", input.trim()))
+}
+
+/// Return the current log level name.
+pub(crate) fn current_level() -> String {
+    log::max_level().to_string().to_lowercase()
+}
+
+/// Change the log level at runtime, optionally persisting it across agent restarts.
+#[context("failed to set log level")]
+#[cfg_attr(not(feature = "dbus"), allow(dead_code))]
+pub(crate) fn set_level(level: LevelFilter, persist: bool) -> Result<()> {
+    let name = level.to_string().to_lowercase();
+    if persist {
+        utils::atomic_write(LOG_LEVEL_PATH, 0o644, name.as_bytes())?;
+    }
+    LEVEL_GENERATION.fetch_add(1, Ordering::SeqCst);
+    log::set_max_level(level);
+    log::warn!(
+        "log level set to '{}'{}",
+        name,
+        if persist { " (persisted)" } else { "" }
+    );
+    Ok(())
+}
+
+/// Change the log level at runtime for `timeout`, after which the previous
+/// level is restored (unless the level was changed again in the meantime).
+#[context("failed to set log level temporarily")]
+#[cfg_attr(not(feature = "dbus"), allow(dead_code))]
+pub(crate) fn set_level_for(level: LevelFilter, timeout: Duration) -> Result<()> {
+    ensure!(
+        timeout.as_secs() > 0 && timeout.as_secs() <= MAX_TEMPORARY_LEVEL_SECS,
+        "timeout must be between 1 and {} seconds",
+        MAX_TEMPORARY_LEVEL_SECS
+    );
+    let previous = log::max_level();
+    let generation = LEVEL_GENERATION.fetch_add(1, Ordering::SeqCst) + 1;
+    std::thread::Builder::new()
+        .name("log-level-revert".to_string())
+        .spawn(move || {
+            std::thread::sleep(timeout);
+            revert_level(generation, previous);
+        })
+        .context("failed to schedule log level revert")?;
+
+    log::set_max_level(level);
+    log::warn!(
+        "log level set to '{}' for {} seconds",
+        current_level(),
+        timeout.as_secs()
+    );
+    Ok(())
+}
+
+/// Restore the `previous` log level, unless it was changed again after the
+/// change identified by `generation`.
+///
+/// This returns whether the level was restored.
+#[cfg_attr(not(feature = "dbus"), allow(dead_code))]
+fn revert_level(generation: u64, previous: LevelFilter) -> bool {
+    let latest = LEVEL_GENERATION.compare_exchange(
+        generation,
+        generation + 1,
+        Ordering::SeqCst,
+        Ordering::SeqCst,
+    );
+    if latest.is_err() {
+        return false;
+    }
+    log::set_max_level(previous);
+    log::warn!("log level reverted to '{}'", current_level());
+    true
+}
+
+/// Restore the persisted log level, if any.
+pub(crate) fn restore_persisted() {
+    let content = match std::fs::read_to_string(LOG_LEVEL_PATH) {
+        Ok(c) => c,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return,
+        Err(e) => {
+            log::warn!("failed to read '{}': {}", LOG_LEVEL_PATH, e);
+            return;
+        }
+    };
+    match parse_level(&content) {
+        Ok(level) => {
+            log::set_max_level(level);
+            log::info!("restored persisted log level '{}'", current_level());
+        }
+        Err(e) => log::warn!("ignoring '{}': {:#}", LOG_LEVEL_PATH, e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_level() {
+        assert_eq!(parse_level("trace").unwrap(), LevelFilter::Trace);
+        assert_eq!(parse_level("DEBUG\n").unwrap(), LevelFilter::Debug);
+        assert_eq!(parse_level("off").unwrap(), LevelFilter::Off);
+        parse_level("verbose").unwrap_err();
+        parse_level("").unwrap_err();
+    }
+
+    #[test]
+    fn test_revert_level() {
+        let generation = LEVEL_GENERATION.fetch_add(1, Ordering::SeqCst) + 1;
+        log::set_max_level(LevelFilter::Trace);
+        assert!(revert_level(generation, LevelFilter::Info));
+        assert_eq!(current_level(), "info");
+
+        // Stale reverts are skipped.
+        log::set_max_level(LevelFilter::Debug);
+        assert!(!revert_level(generation, LevelFilter::Warn));
+        assert_eq!(current_level(), "debug");
+
+        set_level_for(LevelFilter::Trace, Duration::from_secs(0)).unwrap_err();
+        set_level_for(LevelFilter::Trace, Duration::from_secs(2 * 24 * 60 * 60)).unwrap_err();
+    }
+}
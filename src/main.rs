@@ -3,34 +3,39 @@
 #![deny(missing_debug_implementations)]
 #![deny(missing_docs)]
 
-#[macro_use(fail_point)]
-extern crate fail;
 #[macro_use]
 extern crate prometheus;
 
-// Cincinnati client.
-mod cincinnati;
 mod cli;
-/// File-based configuration.
-mod config;
 /// D-Bus service.
+#[cfg(feature = "dbus")]
 mod dbus;
-/// FleetLock client.
-mod fleet_lock;
-/// Agent identity.
-mod identity;
+/// Logging setup.
+mod logging;
 /// Metrics service.
+#[cfg(feature = "metrics")]
 mod metrics;
-/// rpm-ostree client.
-mod rpm_ostree;
-/// Update strategies.
-mod strategy;
+/// Watcher for network connectivity changes.
+mod network_watch;
+/// Self-test of control surfaces.
+mod self_test;
 /// Update agent.
 mod update_agent;
-/// Logic for weekly maintenance windows.
-mod weekly;
+/// Service manager watchdog.
+mod watchdog;
+
+// Core logic lives in the library crate; these imports keep `crate::` paths
+// working for daemon modules.
+#[cfg(all(test, feature = "e2e-tests"))]
+use zincati_core::network;
+#[cfg(feature = "metrics")]
+use zincati_core::telemetry;
+use zincati_core::{
+    blackout, cincinnati, config, connectivity, disk_space, downgrade, download, health_checks,
+    identity, messages, ostree_remote, outcome_report, post_boot, quiesce, rpm_ostree, simulate,
+    strategy, stream_switch, update_source, urgency, utils, webhook,
+};
 
-use structopt::clap::crate_name;
 use structopt::StructOpt;
 
 /// Binary entrypoint, for all CLI subcommands.
@@ -45,15 +50,11 @@ fn run() -> i32 {
     let cli_opts = cli::CliOptions::from_args();
 
     // Setup logging.
-    env_logger::Builder::from_default_env()
-        .format_timestamp(None)
-        .format_module_path(false)
-        .filter(Some(crate_name!()), cli_opts.loglevel())
-        .init();
+    logging::init(cli_opts.loglevel());
 
     // Dispatch CLI subcommand.
     match cli_opts.run() {
-        Ok(_) => libc::EXIT_SUCCESS,
+        Ok(exit_code) => exit_code,
         Err(e) => {
             log_error_chain(e);
             libc::EXIT_FAILURE
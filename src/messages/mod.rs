@@ -0,0 +1,232 @@
+//! Templates for user-facing messages.
+//!
+//! Messages shown to users (reboot warnings and postponement notices broadcast
+//! to terminals, and the dead-end MOTD fragment) are rendered from templates, which can be
+//! customized via configuration (e.g. for branding or translations).
+//! Templates use `${name}` placeholders for runtime values.
+
+use crate::config::inputs;
+use anyhow::{Context, Result};
+use fn_error_context::context;
+use serde::Serialize;
+use std::collections::HashMap;
+
+/// Default template for reboot warnings.
+static DEFAULT_REBOOT_WARNING: &str = "New update ${version} deployed.\nRebooting into this update in around ${delay} (if permitted by update strategy).";
+
+/// Default template for finalization postponement notices.
+static DEFAULT_POSTPONEMENT_NOTICE: &str = "Update ${version} is waiting for a reboot, postponed due to active user sessions.\nRebooting into this update in at most ${delay} (${remaining} postponements left).";
+
+/// Default template for the dead-end MOTD fragment.
+static DEFAULT_DEADEND_MOTD: &str =
+    "This release is a dead-end and will not further auto-update: ${reason}";
+
+/// Placeholders available in reboot warnings.
+const REBOOT_WARNING_VARS: &[&str] = &["version", "delay"];
+
+/// Placeholders available in postponement notices.
+const POSTPONEMENT_NOTICE_VARS: &[&str] = &["version", "delay", "remaining"];
+
+/// Placeholders available in the dead-end MOTD fragment.
+const DEADEND_MOTD_VARS: &[&str] = &["reason"];
+
+/// Templates for user-facing messages.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct MessageTemplates {
+    /// Reboot warning, broadcast to interactive sessions.
+    reboot_warning: String,
+    /// Postponement notice, broadcast to interactive sessions.
+    postponement_notice: String,
+    /// Dead-end release notice, written as a MOTD fragment.
+    deadend_motd: String,
+}
+
+impl Default for MessageTemplates {
+    fn default() -> Self {
+        Self {
+            reboot_warning: DEFAULT_REBOOT_WARNING.to_string(),
+            postponement_notice: DEFAULT_POSTPONEMENT_NOTICE.to_string(),
+            deadend_motd: DEFAULT_DEADEND_MOTD.to_string(),
+        }
+    }
+}
+
+impl MessageTemplates {
+    /// Process message templates configuration.
+    ///
+    /// Empty templates are replaced by defaults.
+    #[context("failed to validate messages configuration")]
+    pub fn with_config(cfg: inputs::MessagesInput) -> Result<Self> {
+        let mut templates = Self::default();
+        if !cfg.reboot_warning.is_empty() {
+            check_placeholders(&cfg.reboot_warning, REBOOT_WARNING_VARS)
+                .context("invalid reboot warning template")?;
+            templates.reboot_warning = cfg.reboot_warning;
+        }
+        if !cfg.postponement_notice.is_empty() {
+            check_placeholders(&cfg.postponement_notice, POSTPONEMENT_NOTICE_VARS)
+                .context("invalid postponement notice template")?;
+            templates.postponement_notice = cfg.postponement_notice;
+        }
+        if !cfg.deadend_motd.is_empty() {
+            check_placeholders(&cfg.deadend_motd, DEADEND_MOTD_VARS)
+                .context("invalid dead-end MOTD template")?;
+            templates.deadend_motd = cfg.deadend_motd;
+        }
+
+        Ok(templates)
+    }
+
+    /// Render a warning about an upcoming reboot into a new release.
+    pub fn reboot_warning(&self, release_ver: &str, delay_secs: u64) -> String {
+        let vars = maplit::hashmap! {
+            "version".to_string() => release_ver.to_string(),
+            "delay".to_string() => format_seconds(delay_secs),
+        };
+        render(&self.reboot_warning, DEFAULT_REBOOT_WARNING, vars)
+    }
+
+    /// Render a notice about a postponed finalization, with the maximum
+    /// delay before reboot and the number of postponements left.
+    pub fn postponement_notice(&self, release_ver: &str, delay_secs: u64, remaining: u8) -> String {
+        let vars = maplit::hashmap! {
+            "version".to_string() => release_ver.to_string(),
+            "delay".to_string() => format_seconds(delay_secs),
+            "remaining".to_string() => remaining.to_string(),
+        };
+        render(&self.postponement_notice, DEFAULT_POSTPONEMENT_NOTICE, vars)
+    }
+
+    /// Render the notice for a dead-end release.
+    pub fn deadend_motd(&self, reason: &str) -> String {
+        let vars = maplit::hashmap! {
+            "reason".to_string() => reason.to_string(),
+        };
+        render(&self.deadend_motd, DEFAULT_DEADEND_MOTD, vars)
+    }
+}
+
+/// Check that a template only uses the given placeholders.
+fn check_placeholders(template: &str, allowed: &[&str]) -> Result<()> {
+    let vars: HashMap<String, String> = allowed
+        .iter()
+        .map(|name| (name.to_string(), String::new()))
+        .collect();
+    let rendered = envsubst::substitute(template, &vars)?;
+    if envsubst::is_templated(&rendered) {
+        anyhow::bail!(
+            "unknown placeholder in '{}', allowed: {}",
+            template,
+            allowed.join(", ")
+        );
+    }
+    Ok(())
+}
+
+/// Render a template, falling back to the default one on errors.
+///
+/// Runtime values may contain template delimiters (e.g. a dead-end reason
+/// coming from a remote service), in that case they are inserted verbatim
+/// into the default template.
+fn render(template: &str, default: &str, vars: HashMap<String, String>) -> String {
+    let rendered =
+        envsubst::validate_vars(&vars).and_then(|_| envsubst::substitute(template, &vars));
+    match rendered {
+        Ok(msg) => msg,
+        Err(e) => {
+            log::warn!("failed to render message template: {}", e);
+            vars.iter().fold(default.to_string(), |msg, (name, value)| {
+                msg.replace(&format!("${{{}}}", name), value)
+            })
+        }
+    }
+}
+
+/// Helper to return a human-friendly version of seconds.
+/// Example: 65 seconds would be converted to 1 minute and 5 seconds.
+pub fn format_seconds(seconds: u64) -> String {
+    let mut time_till_reboot = if seconds / 60 >= 1 {
+        format!(
+            "{} minute{}{}",
+            seconds / 60,
+            if seconds / 60 == 1 { "" } else { "s" },
+            if seconds % 60 > 0 { " and " } else { "" }
+        )
+    } else {
+        String::from("")
+    };
+    if seconds % 60 > 0 {
+        time_till_reboot.push_str(&format!(
+            "{} second{}",
+            seconds % 60,
+            if seconds % 60 == 1 { "" } else { "s" }
+        ))
+    }
+
+    time_till_reboot
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_templates() {
+        let templates = MessageTemplates::default();
+        assert_eq!(
+            templates.reboot_warning("v1", 90),
+            "New update v1 deployed.\nRebooting into this update in around 1 minute and 30 seconds (if permitted by update strategy)."
+        );
+        assert_eq!(
+            templates.postponement_notice("v1", 300, 4),
+            "Update v1 is waiting for a reboot, postponed due to active user sessions.\nRebooting into this update in at most 5 minutes (4 postponements left)."
+        );
+        assert_eq!(
+            templates.deadend_motd("foo"),
+            "This release is a dead-end and will not further auto-update: foo"
+        );
+
+        // Values with template delimiters are inserted verbatim.
+        assert_eq!(
+            templates.deadend_motd("see ${url}"),
+            "This release is a dead-end and will not further auto-update: see ${url}"
+        );
+    }
+
+    #[test]
+    fn custom_templates() {
+        let cfg = inputs::MessagesInput {
+            reboot_warning: "Neustart in ${delay} für Version ${version}.".to_string(),
+            postponement_notice: "Neustart aufgeschoben (${remaining} verbleibend).".to_string(),
+            deadend_motd: String::new(),
+        };
+        let templates = MessageTemplates::with_config(cfg).unwrap();
+        assert_eq!(
+            templates.reboot_warning("v1", 60),
+            "Neustart in 1 minute für Version v1."
+        );
+        assert_eq!(
+            templates.postponement_notice("v1", 60, 2),
+            "Neustart aufgeschoben (2 verbleibend)."
+        );
+        assert_eq!(templates.deadend_motd, DEFAULT_DEADEND_MOTD);
+
+        let unknown = inputs::MessagesInput {
+            reboot_warning: "Rebooting at ${time}".to_string(),
+            postponement_notice: String::new(),
+            deadend_motd: String::new(),
+        };
+        MessageTemplates::with_config(unknown).unwrap_err();
+    }
+
+    #[test]
+    fn test_format_seconds() {
+        assert_eq!("1 second", format_seconds(1));
+        assert_eq!("2 seconds", format_seconds(2));
+        assert_eq!("1 minute", format_seconds(60));
+        assert_eq!("1 minute and 1 second", format_seconds(60 + 1));
+        assert_eq!("1 minute and 30 seconds", format_seconds(60 + 30));
+        assert_eq!("2 minutes", format_seconds(2 * 60));
+        assert_eq!("42 minutes and 23 seconds", format_seconds(42 * 60 + 23));
+    }
+}
@@ -1,4 +1,7 @@
-//! Metrics endpoint over a Unix-domain socket.
+//! Metrics endpoint over a Unix-domain socket, and optionally over TCP.
+
+mod tcp;
+pub use tcp::TcpExporter;
 
 use actix::prelude::*;
 use anyhow::{Context, Result};
@@ -6,7 +9,7 @@ use std::os::unix::net as std_net;
 use tokio::net as tokio_net;
 
 /// Unix socket path.
-static SOCKET_PATH: &str = "/run/zincati/public/metrics.promsock";
+pub(crate) static SOCKET_PATH: &str = "/run/zincati/public/metrics.promsock";
 
 /// Metrics exposition service.
 pub struct MetricsService {
@@ -0,0 +1,184 @@
+//! Metrics endpoint over TCP.
+//!
+//! Scrapes are infrequent and cheap to serve, thus connections are handled
+//! one at a time on a dedicated thread, with blocking I/O.
+
+use super::MetricsService;
+use crate::telemetry::{BasicAuth, TelemetrySettings};
+use anyhow::{Context, Result};
+use openssl::ssl::SslAcceptor;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::time::Duration;
+
+/// Path of the metrics endpoint.
+static METRICS_PATH: &str = "/metrics";
+
+/// Maximum size of a request head (in bytes).
+const MAX_REQUEST_HEAD_BYTES: usize = 8 * 1024;
+
+/// Timeout for reading a request and writing a response (in seconds).
+const IO_TIMEOUT_SECS: u64 = 10;
+
+/// Metrics exporter over TCP.
+pub struct TcpExporter {
+    listener: TcpListener,
+    basic_auth: Option<BasicAuth>,
+    tls: Option<SslAcceptor>,
+}
+
+impl std::fmt::Debug for TcpExporter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TcpExporter")
+            .field("listener", &self.listener)
+            .field("basic_auth", &self.basic_auth)
+            .field("tls", &self.tls.is_some())
+            .finish()
+    }
+}
+
+impl TcpExporter {
+    /// Create metrics exporter and bind to the configured TCP address.
+    pub fn bind(settings: &TelemetrySettings) -> Result<Self> {
+        let listener = TcpListener::bind(settings.listen_address).with_context(|| {
+            format!(
+                "failed to bind metrics exporter to '{}'",
+                settings.listen_address
+            )
+        })?;
+        let tls = match &settings.tls {
+            Some(tls) => Some(tls.acceptor()?),
+            None => None,
+        };
+        let exporter = Self {
+            listener,
+            basic_auth: settings.basic_auth.clone(),
+            tls,
+        };
+        Ok(exporter)
+    }
+
+    /// Serve metrics scrapes on a dedicated thread.
+    pub fn spawn(self) -> Result<()> {
+        let address = self.listener.local_addr()?;
+        std::thread::Builder::new()
+            .name("metrics-tcp".to_string())
+            .spawn(move || self.serve())
+            .context("failed to spawn metrics exporter")?;
+
+        log::debug!("started metrics exporter on TCP address '{}'", address);
+        Ok(())
+    }
+
+    /// Accept and handle connections, one at a time.
+    fn serve(self) {
+        for stream in self.listener.incoming() {
+            let res = stream
+                .context("failed to accept connection")
+                .and_then(|s| self.handle(s));
+            if let Err(e) = res {
+                log::debug!("metrics exporter: {:#}", e);
+            }
+        }
+    }
+
+    /// Handle a single connection.
+    fn handle(&self, stream: TcpStream) -> Result<()> {
+        let timeout = Some(Duration::from_secs(IO_TIMEOUT_SECS));
+        stream.set_read_timeout(timeout)?;
+        stream.set_write_timeout(timeout)?;
+        match &self.tls {
+            Some(acceptor) => {
+                let tls_stream = acceptor.accept(stream).context("TLS handshake failed")?;
+                self.reply(tls_stream)
+            }
+            None => self.reply(stream),
+        }
+    }
+
+    /// Read a request from `stream`, and write back the response.
+    fn reply(&self, mut stream: impl Read + Write) -> Result<()> {
+        let head = read_request_head(&mut stream)?;
+        let response = respond(&head, self.basic_auth.as_ref());
+        stream.write_all(&response)?;
+        stream.flush()?;
+        Ok(())
+    }
+}
+
+/// Read the head (request line and headers) of an HTTP request.
+fn read_request_head(stream: &mut impl Read) -> Result<String> {
+    let mut head = Vec::new();
+    let mut buf = [0u8; 1024];
+    while !head.windows(4).any(|w| w == b"\r\n\r\n") {
+        if head.len() > MAX_REQUEST_HEAD_BYTES {
+            anyhow::bail!("request head too large");
+        }
+        let n = stream.read(&mut buf).context("failed to read request")?;
+        if n == 0 {
+            anyhow::bail!("connection closed before end of request head");
+        }
+        head.extend_from_slice(&buf[..n]);
+    }
+    Ok(String::from_utf8_lossy(&head).into_owned())
+}
+
+/// Build the full HTTP response for the given request head.
+fn respond(head: &str, basic_auth: Option<&BasicAuth>) -> Vec<u8> {
+    let mut lines = head.lines();
+    let request_line = lines.next().unwrap_or_default();
+    let mut parts = request_line.split_whitespace();
+    let (method, path) = (parts.next(), parts.next());
+
+    if let Some(auth) = basic_auth {
+        let authorized = lines.any(|line| match line.split_once(':') {
+            Some((name, value)) => {
+                name.trim().eq_ignore_ascii_case("authorization") && auth.matches(value)
+            }
+            None => false,
+        });
+        if !authorized {
+            return response(
+                "401 Unauthorized",
+                "WWW-Authenticate: Basic realm=\"zincati\"\r\n",
+                b"unauthorized\n",
+            );
+        }
+    }
+
+    match (method, path) {
+        (Some("GET"), Some(p)) if p == METRICS_PATH => {
+            match MetricsService::prometheus_text_encode() {
+                Ok(metrics) => response("200 OK", "", &metrics),
+                Err(_) => response(
+                    "500 Internal Server Error",
+                    "",
+                    b"failed to encode metrics\n",
+                ),
+            }
+        }
+        (Some("GET"), _) => response("404 Not Found", "", b"not found\n"),
+        _ => response(
+            "405 Method Not Allowed",
+            "Allow: GET\r\n",
+            b"method not allowed\n",
+        ),
+    }
+}
+
+/// Assemble an HTTP response, closing the connection afterwards.
+fn response(status: &str, extra_headers: &str, body: &[u8]) -> Vec<u8> {
+    let mut out = format!(
+        "HTTP/1.1 {}\r\n\
+         Content-Type: text/plain; version=0.0.4\r\n\
+         Content-Length: {}\r\n\
+         Connection: close\r\n\
+         {}\r\n",
+        status,
+        body.len(),
+        extra_headers
+    )
+    .into_bytes();
+    out.extend_from_slice(body);
+    out
+}
@@ -0,0 +1,267 @@
+//! TLS settings for outbound HTTPS connections.
+//!
+//! This allows reaching update endpoints backed by an internal PKI, by
+//! trusting additional CA certificates and (optionally) authenticating
+//! with a client certificate.
+
+use crate::config::inputs;
+use crate::utils;
+use anyhow::{Context, Result};
+use fn_error_context::context;
+use openssl::pkcs12::Pkcs12;
+use openssl::pkey::PKey;
+use openssl::stack::Stack;
+use openssl::x509::X509;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+
+/// TLS settings for outbound HTTPS connections.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct TlsSettings {
+    /// Path to additional CA certificates (PEM bundle).
+    ca_bundle: Option<PathBuf>,
+    /// Path to client certificate (PEM).
+    client_cert: Option<PathBuf>,
+    /// Path to client private key (PEM).
+    client_key: Option<PathBuf>,
+    /// Additional trusted CA certificates.
+    #[serde(skip)]
+    ca_certs: Vec<reqwest::Certificate>,
+    /// Client identity, as a password-less PKCS#12 archive (DER).
+    #[serde(skip)]
+    identity_der: Option<Vec<u8>>,
+}
+
+impl TlsSettings {
+    /// Process TLS configuration, loading certificates and keys.
+    #[context("failed to validate TLS configuration")]
+    pub fn with_config(cfg: inputs::TlsInput) -> Result<Self> {
+        let ca_bundle = non_empty_path(cfg.ca_bundle)?;
+        let client_cert = non_empty_path(cfg.client_cert)?;
+        let client_key = non_empty_path(cfg.client_key)?;
+
+        let ca_certs = match &ca_bundle {
+            Some(path) => read_ca_bundle(path)?,
+            None => vec![],
+        };
+        let identity_der = match (&client_cert, &client_key) {
+            (Some(cert), Some(key)) => Some(read_identity(cert, key)?),
+            (None, None) => None,
+            (Some(_), None) => anyhow::bail!("client certificate configured without a key"),
+            (None, Some(_)) => anyhow::bail!("client key configured without a certificate"),
+        };
+
+        let settings = Self {
+            ca_bundle,
+            client_cert,
+            client_key,
+            ca_certs,
+            identity_der,
+        };
+        Ok(settings)
+    }
+
+    /// Return a copy of these settings, authenticating with the given client
+    /// certificate and key instead of the configured ones (if any).
+    pub fn with_client_identity(&self, cert: &str, key: &str) -> Result<Self> {
+        let client_cert = utils::resolve_credential_path(cert.trim())?;
+        let client_key = utils::resolve_credential_path(key.trim())?;
+        let identity_der = read_identity(&client_cert, &client_key)?;
+
+        let mut settings = self.clone();
+        settings.client_cert = Some(client_cert);
+        settings.client_key = Some(client_key);
+        settings.identity_der = Some(identity_der);
+        Ok(settings)
+    }
+
+    /// Apply these settings to an HTTP client builder.
+    ///
+    /// Additional CA certificates are trusted on top of system ones.
+    pub fn configure(&self, builder: reqwest::ClientBuilder) -> Result<reqwest::ClientBuilder> {
+        let mut builder = builder;
+        for cert in &self.ca_certs {
+            builder = builder.add_root_certificate(cert.clone());
+        }
+        if let Some(der) = &self.identity_der {
+            let identity = reqwest::Identity::from_pkcs12_der(der, "")
+                .context("failed to load client identity")?;
+            builder = builder.identity(identity);
+        }
+        Ok(builder)
+    }
+}
+
+/// Turn an optional path input into a `PathBuf`, ignoring empty values.
+///
+/// Paths can refer to systemd credentials (`credential:<name>`).
+fn non_empty_path(input: Option<String>) -> Result<Option<PathBuf>> {
+    match input.as_deref().map(str::trim) {
+        None | Some("") => Ok(None),
+        Some(path) => utils::resolve_credential_path(path).map(Some),
+    }
+}
+
+/// Read file content, with a contextual error.
+fn read_file(path: &Path) -> Result<Vec<u8>> {
+    std::fs::read(path).with_context(|| format!("failed to read '{}'", path.display()))
+}
+
+/// Read all CA certificates from a PEM bundle.
+#[context("failed to load CA bundle '{}'", path.display())]
+fn read_ca_bundle(path: &Path) -> Result<Vec<reqwest::Certificate>> {
+    let content = read_file(path)?;
+    let x509_certs = X509::stack_from_pem(&content).context("failed to parse PEM certificates")?;
+    if x509_certs.is_empty() {
+        anyhow::bail!("no certificates found");
+    }
+
+    let mut certs = Vec::with_capacity(x509_certs.len());
+    for x509 in x509_certs {
+        let der = x509.to_der()?;
+        let cert = reqwest::Certificate::from_der(&der)?;
+        certs.push(cert);
+    }
+    Ok(certs)
+}
+
+/// Build a client identity from a PEM certificate chain and private key.
+///
+/// The first certificate in `cert_path` is the client certificate, any
+/// further ones are treated as intermediate certificates.
+#[context("failed to load client certificate '{}'", cert_path.display())]
+fn read_identity(cert_path: &Path, key_path: &Path) -> Result<Vec<u8>> {
+    let certs_pem = read_file(cert_path)?;
+    let mut chain = X509::stack_from_pem(&certs_pem)
+        .context("failed to parse PEM certificates")?
+        .into_iter();
+    let cert = chain.next().context("no certificates found")?;
+
+    let key_pem = read_file(key_path)?;
+    let key = PKey::private_key_from_pem(&key_pem)
+        .with_context(|| format!("failed to parse private key '{}'", key_path.display()))?;
+    let cert_key = cert.public_key()?;
+    if !key.public_eq(&cert_key) {
+        anyhow::bail!("private key does not match client certificate");
+    }
+
+    let mut intermediates = Stack::new()?;
+    for x509 in chain {
+        intermediates.push(x509)?;
+    }
+    let pkcs12 = Pkcs12::builder()
+        .name("zincati")
+        .pkey(&key)
+        .cert(&cert)
+        .ca(intermediates)
+        .build2("")
+        .context("failed to assemble PKCS#12 identity")?;
+
+    Ok(pkcs12.to_der()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use openssl::asn1::Asn1Time;
+    use openssl::hash::MessageDigest;
+    use openssl::rsa::Rsa;
+    use openssl::x509::X509NameBuilder;
+    use std::io::Write;
+
+    /// Generate a self-signed certificate and its private key, as PEM.
+    fn self_signed(cn: &str) -> (Vec<u8>, Vec<u8>) {
+        let key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_text("CN", cn).unwrap();
+        let name = name.build();
+
+        let mut builder = X509::builder().unwrap();
+        builder.set_version(2).unwrap();
+        builder.set_subject_name(&name).unwrap();
+        builder.set_issuer_name(&name).unwrap();
+        builder.set_pubkey(&key).unwrap();
+        builder
+            .set_not_before(&Asn1Time::days_from_now(0).unwrap())
+            .unwrap();
+        builder
+            .set_not_after(&Asn1Time::days_from_now(1).unwrap())
+            .unwrap();
+        builder.sign(&key, MessageDigest::sha256()).unwrap();
+        let cert = builder.build();
+
+        (
+            cert.to_pem().unwrap(),
+            key.private_key_to_pem_pkcs8().unwrap(),
+        )
+    }
+
+    fn write_tmp(dir: &Path, name: &str, content: &[u8]) -> String {
+        let path = dir.join(name);
+        let mut f = std::fs::File::create(&path).unwrap();
+        f.write_all(content).unwrap();
+        path.to_string_lossy().to_string()
+    }
+
+    #[test]
+    fn test_default_tls() {
+        let settings = TlsSettings::with_config(inputs::TlsInput::default()).unwrap();
+        assert!(settings.ca_certs.is_empty());
+        assert!(settings.identity_der.is_none());
+        settings
+            .configure(reqwest::ClientBuilder::new())
+            .unwrap()
+            .build()
+            .unwrap();
+    }
+
+    #[test]
+    fn test_tls_config() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let (ca1, _) = self_signed("ca1.example.com");
+        let (ca2, _) = self_signed("ca2.example.com");
+        let (client_cert, client_key) = self_signed("node.example.com");
+        let (_, other_key) = self_signed("other.example.com");
+
+        let bundle = write_tmp(tmpdir.path(), "bundle.pem", &[ca1, ca2].concat());
+        let cert = write_tmp(tmpdir.path(), "client.crt", &client_cert);
+        let key = write_tmp(tmpdir.path(), "client.key", &client_key);
+        let wrong_key = write_tmp(tmpdir.path(), "other.key", &other_key);
+        let empty = write_tmp(tmpdir.path(), "empty.pem", b"");
+
+        let input = inputs::TlsInput {
+            ca_bundle: Some(bundle.clone()),
+            client_cert: Some(cert.clone()),
+            client_key: Some(key.clone()),
+        };
+        let settings = TlsSettings::with_config(input).unwrap();
+        assert_eq!(settings.ca_certs.len(), 2);
+        assert!(settings.identity_der.is_some());
+        settings
+            .configure(reqwest::ClientBuilder::new())
+            .unwrap()
+            .build()
+            .unwrap();
+
+        let default = TlsSettings::default();
+        let overridden = default.with_client_identity(&cert, &key).unwrap();
+        assert!(overridden.identity_der.is_some());
+        default.with_client_identity(&cert, &wrong_key).unwrap_err();
+
+        let invalid = [
+            (Some(empty), None, None),
+            (Some("/missing/bundle.pem".to_string()), None, None),
+            (None, Some(cert.clone()), None),
+            (None, None, Some(key)),
+            (None, Some(cert), Some(wrong_key)),
+        ];
+        for (ca_bundle, client_cert, client_key) in invalid {
+            let input = inputs::TlsInput {
+                ca_bundle,
+                client_cert,
+                client_key,
+            };
+            TlsSettings::with_config(input).unwrap_err();
+        }
+    }
+}
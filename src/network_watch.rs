@@ -0,0 +1,171 @@
+//! Watcher for network connectivity changes.
+//!
+//! NetworkManager and systemd-networkd announce connectivity changes as
+//! D-Bus signals. Once the network comes online (e.g. after boot, or after a
+//! long offline period), the update agent is notified so that it can check
+//! for updates right away, instead of waiting for its next refresh tick.
+//! Signals are received on a dedicated thread, as the D-Bus connection is
+//! blocking.
+
+use crate::update_agent::{NetworkUp, UpdateAgent};
+use actix::Addr;
+use anyhow::Result;
+use fn_error_context::context;
+use prometheus::IntCounter;
+use std::collections::HashMap;
+use zbus::fdo;
+use zvariant::{OwnedValue, Value};
+
+lazy_static::lazy_static! {
+    static ref NETWORK_UP_EVENTS: IntCounter = register_int_counter!(opts!(
+        "zincati_network_watch_online_events_total",
+        "Total number of times the network was reported back online."
+    )).unwrap();
+}
+
+/// NetworkManager D-Bus interface.
+static NM_INTERFACE: &str = "org.freedesktop.NetworkManager";
+
+/// systemd-networkd D-Bus object path.
+static NETWORKD_PATH: &str = "/org/freedesktop/network1";
+
+/// NetworkManager state for full (global) connectivity.
+const NM_STATE_CONNECTED_GLOBAL: u32 = 70;
+
+/// Watcher for network connectivity, notifying the update agent.
+#[derive(Debug)]
+pub(crate) struct NetworkWatch {
+    agent_addr: Addr<UpdateAgent>,
+    /// Whether the network was last reported online, if known.
+    online: Option<bool>,
+}
+
+impl NetworkWatch {
+    /// Create a watcher notifying the given agent.
+    pub(crate) fn new(agent_addr: Addr<UpdateAgent>) -> Self {
+        Self {
+            agent_addr,
+            online: None,
+        }
+    }
+
+    /// Watch connectivity changes, on a dedicated thread.
+    pub(crate) fn spawn(mut self) -> Result<()> {
+        std::thread::Builder::new()
+            .name("network-watch".to_string())
+            .spawn(move || {
+                if let Err(e) = self.run() {
+                    log::error!("{:#}", e);
+                }
+            })?;
+        Ok(())
+    }
+
+    /// Receive connectivity signals, until the D-Bus connection fails.
+    #[context("failed to watch network connectivity changes")]
+    fn run(&mut self) -> Result<()> {
+        let connection = zbus::Connection::new_system()?;
+        let dbus = fdo::DBusProxy::new(&connection)?;
+        dbus.add_match(&format!(
+            "type='signal',interface='{}',member='StateChanged'",
+            NM_INTERFACE
+        ))?;
+        dbus.add_match(&format!(
+            "type='signal',interface='org.freedesktop.DBus.Properties',member='PropertiesChanged',path='{}'",
+            NETWORKD_PATH
+        ))?;
+        log::debug!("watching network connectivity changes");
+
+        loop {
+            let msg = connection.receive_message()?;
+            let header = msg.header()?;
+            if header.message_type()? != zbus::MessageType::Signal {
+                continue;
+            }
+            let online = match (header.interface()?, header.member()?) {
+                (Some(i), Some("StateChanged")) if i == NM_INTERFACE => {
+                    msg.body::<u32>().ok().map(nm_state_online)
+                }
+                (_, Some("PropertiesChanged")) => msg
+                    .body::<(String, HashMap<String, OwnedValue>, Vec<String>)>()
+                    .ok()
+                    .and_then(|(_, changed, _)| networkd_online(&changed)),
+                _ => None,
+            };
+            if let Some(online) = online {
+                self.record(online);
+            }
+        }
+    }
+
+    /// Record reported connectivity, notifying the agent when coming online.
+    fn record(&mut self, online: bool) {
+        let came_online = online && self.online == Some(false);
+        self.online = Some(online);
+        if came_online {
+            log::debug!("network came online");
+            NETWORK_UP_EVENTS.inc();
+            self.agent_addr.do_send(NetworkUp {});
+        }
+    }
+}
+
+/// Whether a NetworkManager state means full connectivity.
+fn nm_state_online(state: u32) -> bool {
+    state >= NM_STATE_CONNECTED_GLOBAL
+}
+
+/// Whether properties changed on systemd-networkd mean connectivity, if
+/// they report it at all.
+///
+/// The online state is preferred, falling back to the operational state on
+/// older versions.
+fn networkd_online(changed: &HashMap<String, OwnedValue>) -> Option<bool> {
+    let state = |key: &str| match changed.get(key).map(|v| &**v) {
+        Some(Value::Str(s)) => Some(s.to_string()),
+        _ => None,
+    };
+    if let Some(online) = state("OnlineState") {
+        return Some(online == "online");
+    }
+    state("OperationalState").map(|s| s == "routable")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn properties(entries: &[(&str, &str)]) -> HashMap<String, OwnedValue> {
+        entries
+            .iter()
+            .map(|(k, v)| (k.to_string(), OwnedValue::from(Value::from(*v))))
+            .collect()
+    }
+
+    #[test]
+    fn test_nm_state_online() {
+        assert!(!nm_state_online(20));
+        assert!(!nm_state_online(60));
+        assert!(nm_state_online(NM_STATE_CONNECTED_GLOBAL));
+    }
+
+    #[test]
+    fn test_networkd_online() {
+        assert_eq!(networkd_online(&properties(&[])), None);
+        assert_eq!(
+            networkd_online(&properties(&[("OperationalState", "routable")])),
+            Some(true)
+        );
+        assert_eq!(
+            networkd_online(&properties(&[
+                ("OperationalState", "routable"),
+                ("OnlineState", "partial")
+            ])),
+            Some(false)
+        );
+        assert_eq!(
+            networkd_online(&properties(&[("OnlineState", "online")])),
+            Some(true)
+        );
+    }
+}
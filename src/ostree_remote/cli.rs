@@ -0,0 +1,132 @@
+//! Interface to `ostree pull --commit-metadata-only` and related queries.
+
+use crate::rpm_ostree::Release;
+use anyhow::{bail, Context, Result};
+use fn_error_context::context;
+
+/// Commit metadata key for release version.
+static VERSION_KEY: &str = "version";
+
+/// Query the commit at the head of a remote ref.
+///
+/// This only pulls commit metadata, content is fetched later on by
+/// rpm-ostree when staging the release.
+#[context("failed to query head of remote ref '{}:{}'", remote, branch)]
+pub(crate) fn remote_head(remote: &str, branch: &str) -> Result<Release> {
+    invoke_ostree(&["pull", "--commit-metadata-only", remote, branch])?;
+
+    let refspec = format!("{}:{}", remote, branch);
+    let checksum = invoke_ostree(&["rev-parse", &refspec])?;
+    let metadata = invoke_ostree(&[
+        "show",
+        &format!("--print-metadata-key={}", VERSION_KEY),
+        &checksum,
+    ])?;
+    let version = parse_gvariant_string(&metadata)?;
+
+    let release = Release {
+        version,
+        checksum,
+        age_index: None,
+        advisory: None,
+        wave: None,
+    };
+    Ok(release)
+}
+
+/// Check that a commit can be fetched from a remote.
+///
+/// This only pulls commit metadata, thus it is cheap compared to staging.
+#[context("commit '{}' not available on remote '{}'", checksum, remote)]
+pub(crate) fn commit_available(remote: &str, checksum: &str) -> Result<()> {
+    fail_point!("commit_available_err", |_| bail!("commit_available_err"));
+    fail_point!("commit_available_ok", |_| Ok(()));
+
+    invoke_ostree(&["pull", "--commit-metadata-only", remote, checksum])?;
+    Ok(())
+}
+
+/// Verify the GPG signatures of a commit, using the keys of a remote.
+///
+/// Commit metadata (including signatures) must have already been pulled.
+#[context(
+    "failed to verify signature of commit '{}' with remote '{}'",
+    checksum,
+    remote
+)]
+pub(crate) fn verify_commit_signature(remote: &str, checksum: &str) -> Result<()> {
+    let output = invoke_ostree(&["show", &format!("--gpg-verify-remote={}", remote), checksum])?;
+    check_signature_output(&output)
+}
+
+/// Check for a valid signature (and no bad ones) in the output of
+/// `ostree show --gpg-verify-remote`.
+fn check_signature_output(output: &str) -> Result<()> {
+    if output.lines().any(|l| l.contains("BAD signature")) {
+        bail!("bad signature found");
+    }
+    if !output.lines().any(|l| l.contains("Good signature")) {
+        bail!("no valid signature found");
+    }
+    Ok(())
+}
+
+/// Run an `ostree` command, returning its trimmed standard output.
+fn invoke_ostree(args: &[&str]) -> Result<String> {
+    let out = std::process::Command::new("ostree")
+        .args(args)
+        .output()
+        .context("failed to run 'ostree' binary")?;
+
+    if !out.status.success() {
+        bail!(
+            "ostree {} failed:\n{}",
+            args.first().unwrap_or(&""),
+            String::from_utf8_lossy(&out.stderr)
+        );
+    }
+
+    let stdout = String::from_utf8(out.stdout).context("non UTF-8 ostree output")?;
+    Ok(stdout.trim().to_string())
+}
+
+/// Parse a GVariant string in text format, as printed by `ostree show`.
+fn parse_gvariant_string(input: &str) -> Result<String> {
+    let value = input
+        .strip_prefix('\'')
+        .and_then(|s| s.strip_suffix('\''))
+        .with_context(|| format!("unexpected metadata value: {}", input))?;
+    if value.is_empty() {
+        bail!("empty version metadata");
+    }
+
+    Ok(value.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_gvariant_string() {
+        assert_eq!(
+            parse_gvariant_string("'34.20210626.3.1'").unwrap(),
+            "34.20210626.3.1"
+        );
+        parse_gvariant_string("34.20210626.3.1").unwrap_err();
+        parse_gvariant_string("''").unwrap_err();
+        parse_gvariant_string("").unwrap_err();
+    }
+
+    #[test]
+    fn test_check_signature_output() {
+        let good = "commit 8b3d\nDate:  2021-07-12 14:05:31 +0000\nVersion: 34.20210711.3.0\n\nFound 1 signature:\n\n  Signature made Mon 12 Jul 2021 02:05:40 PM UTC using RSA key ID 1161AE6945719A39\n  Good signature from \"Fedora <fedora-34-primary@fedoraproject.org>\"\n";
+        check_signature_output(good).unwrap();
+
+        let bad = good.replace("Good signature", "BAD signature");
+        check_signature_output(&bad).unwrap_err();
+        let mixed = format!("{}  BAD signature from \"Unknown\"\n", good);
+        check_signature_output(&mixed).unwrap_err();
+        check_signature_output("commit 8b3d\nVersion: 34.20210711.3.0\n").unwrap_err();
+    }
+}
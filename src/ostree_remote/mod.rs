@@ -0,0 +1,197 @@
+//! Update source polling an OSTree remote ref.
+//!
+//! This is an alternative to Cincinnati for air-gapped sites, which mirror
+//! OSTree repositories but do not run a graph server. The configured remote
+//! ref is periodically pulled (commit metadata only), and any new commit on
+//! it is treated as an update target.
+
+mod cli;
+
+use crate::config::inputs;
+use crate::identity::Identity;
+use crate::rpm_ostree::Release;
+use crate::update_source::UpdateSource;
+use anyhow::{Context, Result};
+use fn_error_context::context;
+use futures::prelude::*;
+use prometheus::IntCounter;
+use serde::Serialize;
+use std::collections::BTreeSet;
+use std::pin::Pin;
+
+lazy_static::lazy_static! {
+    static ref UPDATE_CHECKS: IntCounter = register_int_counter!(opts!(
+        "zincati_ostree_remote_update_checks_total",
+        "Total number of checks for updates on the OSTree remote ref."
+    )).unwrap();
+    static ref UPDATE_CHECKS_ERRORS: IntCounter = register_int_counter!(opts!(
+        "zincati_ostree_remote_update_checks_errors_total",
+        "Total number of errors while checking for updates on the OSTree remote ref."
+    )).unwrap();
+}
+
+/// OSTree remote configuration.
+#[derive(Clone, Debug, Serialize)]
+pub struct OstreeRemote {
+    /// Name of the OSTree remote.
+    pub remote: String,
+    /// Ref (branch) on the remote.
+    pub branch: String,
+}
+
+impl OstreeRemote {
+    /// Source label/name.
+    pub const LABEL: &'static str = "ostree-remote";
+
+    /// Process OSTree remote configuration.
+    #[context("failed to validate ostree-remote configuration")]
+    pub fn with_config(cfg: inputs::OstreeRemoteInput) -> Result<Self> {
+        let (remote, branch) = parse_refspec(&cfg.refspec)?;
+        log::info!("OSTree remote ref: {}:{}", remote, branch);
+
+        Ok(Self { remote, branch })
+    }
+}
+
+impl UpdateSource for OstreeRemote {
+    fn label(&self) -> &'static str {
+        Self::LABEL
+    }
+
+    /// Fetch next update-hint from the OSTree remote ref.
+    ///
+    /// Releases on a remote ref are not ordered, thus downgrades can only be
+    /// rejected later on by rpm-ostree, when staging.
+    fn fetch_update_hint(
+        &self,
+        id: &Identity,
+        deployments: BTreeSet<Release>,
+        _allow_downgrade: bool,
+    ) -> Pin<Box<dyn Future<Output = Option<Release>>>> {
+        UPDATE_CHECKS.inc();
+        log::trace!("checking OSTree remote ref for updates");
+
+        let remote = self.remote.clone();
+        let branch = self.branch.clone();
+        let booted = id.current_os.clone();
+        let update = tokio::task::spawn_blocking(move || cli::remote_head(&remote, &branch))
+            .map(|res| {
+                res.context("failed to join remote ref query")
+                    .and_then(|head| head)
+            })
+            .map_ok(move |head| find_update(head, booted, deployments))
+            .unwrap_or_else(|e| {
+                UPDATE_CHECKS_ERRORS.inc();
+                log::error!("failed to check OSTree remote for updates: {:#}", e);
+                None
+            });
+        Box::pin(update)
+    }
+}
+
+/// Check that the commit of `release` can be fetched from `remote`.
+///
+/// Update graphs may be published before the matching commits reach all
+/// mirrors; this allows detecting that case before trying to stage the
+/// release.
+pub fn check_release_on_remote(remote: &str, release: &Release) -> Result<()> {
+    cli::commit_available(remote, &release.checksum)
+}
+
+/// Verify the signature of the commit of `release`, using the keys of `remote`.
+///
+/// This must run after `check_release_on_remote`, which pulls the commit
+/// metadata.
+pub fn verify_release_signature(remote: &str, release: &Release) -> Result<()> {
+    cli::verify_commit_signature(remote, &release.checksum)
+}
+
+/// Split an OSTree refspec (`remote:ref`) into its remote and ref parts.
+fn parse_refspec(refspec: &str) -> Result<(String, String)> {
+    let (remote, branch) = match refspec.split_once(':') {
+        Some(parts) => parts,
+        None => anyhow::bail!("refspec '{}' is not in 'remote:ref' format", refspec),
+    };
+    if remote.is_empty() {
+        anyhow::bail!("empty remote in refspec '{}'", refspec);
+    }
+    if branch.is_empty() {
+        anyhow::bail!("empty ref in refspec '{}'", refspec);
+    }
+
+    Ok((remote.to_string(), branch.to_string()))
+}
+
+/// Decide whether the commit at the head of the remote ref is an update target.
+///
+/// There is no update graph here, thus any commit other than the booted one
+/// is a target, unless it was already deployed locally in the past.
+fn find_update(
+    head: Release,
+    booted_depl: Release,
+    local_depls: BTreeSet<Release>,
+) -> Option<Release> {
+    if head.checksum == booted_depl.checksum {
+        log::trace!("remote ref still points to booted deployment");
+        return None;
+    }
+
+    if local_depls.iter().any(|d| d.checksum == head.checksum) {
+        log::debug!(
+            "remote ref points to release '{}', already deployed locally in the past; ignoring",
+            head.version
+        );
+        return None;
+    }
+
+    Some(head)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn release(version: &str, checksum: &str) -> Release {
+        Release {
+            version: version.to_string(),
+            checksum: checksum.to_string(),
+            age_index: None,
+            advisory: None,
+            wave: None,
+        }
+    }
+
+    #[test]
+    fn test_parse_refspec() {
+        let (remote, branch) = parse_refspec("fedora:fedora/x86_64/coreos/stable").unwrap();
+        assert_eq!(remote, "fedora");
+        assert_eq!(branch, "fedora/x86_64/coreos/stable");
+
+        parse_refspec("").unwrap_err();
+        parse_refspec("fedora/x86_64/coreos/stable").unwrap_err();
+        parse_refspec(":fedora/x86_64/coreos/stable").unwrap_err();
+        parse_refspec("fedora:").unwrap_err();
+    }
+
+    #[test]
+    fn test_find_update() {
+        let booted = release("v1", "sha1");
+        let previous = release("v0", "sha0");
+        let mut local = BTreeSet::new();
+        local.insert(booted.clone());
+        local.insert(previous.clone());
+
+        // Remote ref not moved.
+        assert_eq!(
+            find_update(booted.clone(), booted.clone(), local.clone()),
+            None
+        );
+
+        // Remote ref rolled back to a previous deployment.
+        assert_eq!(find_update(previous, booted.clone(), local.clone()), None);
+
+        // New commit on remote ref.
+        let next = release("v2", "sha2");
+        assert_eq!(find_update(next.clone(), booted, local), Some(next));
+    }
+}
@@ -0,0 +1,499 @@
+//! Reporting of update outcomes to a fleet server.
+//!
+//! Once the agent reaches steady state after rebooting for an update, it
+//! records whether the node booted into the finalized release (or rolled
+//! back), and reports it to a configured HTTP(S) endpoint. Reports are
+//! queued on disk (bounded in size and age) and sent in batches, so that
+//! they are retried on later refresh ticks, or as soon as the network comes
+//! back online, if the endpoint is unreachable.
+
+use crate::config::inputs;
+use crate::event_queue::{Dropped, EventQueue, Queued};
+use crate::identity::Identity;
+use crate::network::NetworkSettings;
+use crate::rpm_ostree::Release;
+use crate::utils;
+use anyhow::{Context, Result};
+use chrono::Utc;
+use fn_error_context::context;
+use futures::prelude::*;
+use prometheus::{IntCounterVec, IntGauge};
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::time::Duration;
+
+/// Absolute path to the persisted update pending an outcome report.
+static PENDING_OUTCOME_PATH: &str = "/var/lib/zincati/outcome-pending.json";
+
+/// Absolute path to the persisted queue of outcome reports.
+static REPORTS_QUEUE_PATH: &str = "/var/lib/zincati/outcome-reports.json";
+
+/// Timeout for a single report request (in seconds).
+const REPORT_TIMEOUT_SECS: u64 = 30;
+
+lazy_static::lazy_static! {
+    static ref REPORTS: IntCounterVec = register_int_counter_vec!(
+        "zincati_outcome_report_reports_total",
+        "Total number of update outcomes recorded for reporting.",
+        &["result"]
+    ).unwrap();
+    static ref REPORT_FAILURES: IntCounterVec = register_int_counter_vec!(
+        "zincati_outcome_report_failures_total",
+        "Total number of failed requests to the outcome reporting endpoint.",
+        &["kind"]
+    ).unwrap();
+    static ref REPORTS_DROPPED: IntCounterVec = register_int_counter_vec!(
+        "zincati_outcome_report_dropped_reports_total",
+        "Total number of queued update outcome reports dropped without being sent.",
+        &["reason"]
+    ).unwrap();
+    static ref REPORTS_QUEUED: IntGauge = register_int_gauge!(opts!(
+        "zincati_outcome_report_queued_reports",
+        "Number of update outcome reports waiting to be sent."
+    )).unwrap();
+}
+
+/// Result of a finalized update, as seen after reboot.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum UpdateResult {
+    /// The node booted into the finalized release.
+    Succeeded,
+    /// The node did not boot into the finalized release.
+    RolledBack,
+}
+
+impl UpdateResult {
+    /// Label for this result, as used in metrics.
+    fn label(self) -> &'static str {
+        match self {
+            UpdateResult::Succeeded => "succeeded",
+            UpdateResult::RolledBack => "rolled-back",
+        }
+    }
+}
+
+/// A finalized update, pending an outcome report after reboot.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+struct PendingOutcome {
+    /// Release booted before the update.
+    from_version: String,
+    /// Finalized release.
+    to_version: String,
+    /// UTC timestamp of the finalization.
+    finalized_time: i64,
+}
+
+/// Outcome of an update, as reported to the fleet server.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct OutcomeReport {
+    /// Agent ID.
+    pub node_uuid: String,
+    /// Update group.
+    pub group: String,
+    /// Release booted before the update.
+    pub from_version: String,
+    /// Finalized release.
+    pub to_version: String,
+    /// Release booted after the update.
+    pub booted_version: String,
+    /// Whether the node booted into the finalized release.
+    pub result: UpdateResult,
+    /// Time from finalization to steady state after reboot, in seconds.
+    pub duration_secs: u64,
+}
+
+/// Request body, for a batch of reports.
+#[derive(Debug, Serialize)]
+struct ReportsBody<'a> {
+    reports: &'a [&'a OutcomeReport],
+}
+
+/// Reporter of update outcomes.
+#[derive(Clone, Debug, Serialize)]
+pub struct OutcomeReporter {
+    /// Reporting endpoint.
+    url: Url,
+    /// Maximum number of reports per request.
+    batch_size: usize,
+    /// Path to the persisted update pending an outcome report.
+    pending_path: PathBuf,
+    /// Queue of reports not yet sent.
+    queue: EventQueue,
+    /// HTTP client.
+    #[serde(skip)]
+    hclient: reqwest::Client,
+}
+
+impl OutcomeReporter {
+    /// Process outcome reporting configuration.
+    ///
+    /// This returns `None` if no reporting endpoint is configured.
+    #[context("failed to validate outcome reporting configuration")]
+    pub fn with_config(
+        cfg: inputs::OutcomeReportInput,
+        network: &NetworkSettings,
+    ) -> Result<Option<Self>> {
+        let input = cfg.url.trim();
+        if input.is_empty() {
+            return Ok(None);
+        }
+        let url = Url::parse(input).with_context(|| format!("failed to parse '{}'", input))?;
+        match url.scheme() {
+            "http" | "https" => {}
+            s => anyhow::bail!("unsupported reporting endpoint scheme '{}'", s),
+        };
+        let batch_size = usize::try_from(cfg.batch_size.get()).unwrap_or(usize::MAX);
+        let max_queued = usize::try_from(cfg.max_queued.get()).unwrap_or(usize::MAX);
+        let max_age_secs = i64::try_from(cfg.max_age_hours)
+            .unwrap_or(i64::MAX)
+            .saturating_mul(3600);
+        let hclient = network
+            .configure(reqwest::ClientBuilder::new())?
+            .timeout(Duration::from_secs(REPORT_TIMEOUT_SECS))
+            .build()?;
+        log::info!("reporting update outcomes to {}", url);
+
+        let reporter = Self {
+            url,
+            batch_size,
+            pending_path: PathBuf::from(PENDING_OUTCOME_PATH),
+            queue: EventQueue::new(REPORTS_QUEUE_PATH, max_queued, max_age_secs),
+            hclient,
+        };
+        Ok(Some(reporter))
+    }
+
+    /// Record a finalized update, to report its outcome after reboot.
+    pub fn record_finalized(&self, identity: &Identity, update: &Release) {
+        let pending = PendingOutcome {
+            from_version: identity.current_os.version.clone(),
+            to_version: update.version.clone(),
+            finalized_time: Utc::now().timestamp(),
+        };
+        if let Err(e) = write_json(&self.pending_path, &pending) {
+            log::error!("{:#}", e);
+        }
+    }
+
+    /// Queue the outcome of a recorded finalized update (if any), then send
+    /// all queued reports.
+    pub fn report_pending(&self, identity: &Identity) -> Pin<Box<dyn Future<Output = ()>>> {
+        if let Err(e) = self.queue_pending(identity) {
+            log::error!("{:#}", e);
+        }
+        self.flush()
+    }
+
+    /// Whether some reports are waiting to be sent.
+    pub fn has_queued(&self) -> bool {
+        self.queue.has_queued()
+    }
+
+    /// Send all queued reports, in batches.
+    ///
+    /// Reports which could not be sent are kept queued, to be retried later.
+    pub fn flush(&self) -> Pin<Box<dyn Future<Output = ()>>> {
+        let queue = match self.load_queue() {
+            Ok(q) if !q.is_empty() => q,
+            Ok(_) => return Box::pin(future::ready(())),
+            Err(e) => {
+                log::error!("{:#}", e);
+                return Box::pin(future::ready(()));
+            }
+        };
+
+        let reporter = self.clone();
+        let flush = async move {
+            let mut sent = 0;
+            for batch in queue.chunks(reporter.batch_size) {
+                let batch: Vec<_> = batch.iter().map(|q| &q.event).collect();
+                if let Err(e) = reporter.send(&batch).await {
+                    log::warn!("failed to report update outcomes: {:#}", e);
+                    break;
+                }
+                sent += batch.len();
+            }
+            if sent > 0 {
+                log::debug!("reported {} update outcome(s)", sent);
+            }
+            match reporter.queue.store(&queue[sent..]) {
+                Ok((len, dropped)) => record_queue(len, dropped),
+                Err(e) => log::error!("{:#}", e),
+            }
+        };
+        Box::pin(flush)
+    }
+
+    /// Turn the recorded finalized update (if any) into a queued report.
+    fn queue_pending(&self, identity: &Identity) -> Result<()> {
+        let pending = match read_json::<PendingOutcome>(&self.pending_path)? {
+            Some(p) => p,
+            None => return Ok(()),
+        };
+
+        let booted_version = identity.current_os.version.clone();
+        let result = if booted_version == pending.to_version {
+            UpdateResult::Succeeded
+        } else {
+            UpdateResult::RolledBack
+        };
+        let elapsed = Utc::now().timestamp() - pending.finalized_time;
+        let report = OutcomeReport {
+            node_uuid: identity.node_uuid.lower_hex(),
+            group: identity.group.clone(),
+            from_version: pending.from_version,
+            to_version: pending.to_version,
+            booted_version,
+            result,
+            duration_secs: u64::try_from(elapsed).unwrap_or(0),
+        };
+        log::info!(
+            "update from {} to {} {}, queueing outcome report",
+            report.from_version,
+            report.to_version,
+            result.label()
+        );
+        REPORTS.with_label_values(&[result.label()]).inc();
+
+        let mut queue = self.load_queue()?;
+        queue.push(Queued {
+            queued_time: Utc::now().timestamp(),
+            event: report,
+        });
+        let (len, dropped) = self.queue.store(&queue)?;
+        record_queue(len, dropped);
+        utils::remove_if_exists(&self.pending_path)
+    }
+
+    /// Load queued reports, oldest first.
+    ///
+    /// Queues persisted by older versions (plain lists of reports) are
+    /// still accepted, with reports considered as queued now.
+    fn load_queue(&self) -> Result<Vec<Queued<OutcomeReport>>> {
+        let err = match self.queue.load::<OutcomeReport>() {
+            Ok(queue) => return Ok(queue),
+            Err(e) => e,
+        };
+        let legacy = match read_json::<Vec<OutcomeReport>>(self.queue.path()) {
+            Ok(Some(reports)) => reports,
+            _ => return Err(err),
+        };
+        let now = Utc::now().timestamp();
+        let queue = legacy
+            .into_iter()
+            .map(|event| Queued {
+                queued_time: now,
+                event,
+            })
+            .collect();
+        Ok(queue)
+    }
+
+    /// Send a batch of reports, expecting a successful HTTP status code.
+    async fn send(&self, batch: &[&OutcomeReport]) -> Result<()> {
+        let resp = self
+            .hclient
+            .post(self.url.clone())
+            .json(&ReportsBody { reports: batch })
+            .send()
+            .await
+            .inspect_err(|_| {
+                REPORT_FAILURES.with_label_values(&["request"]).inc();
+            })
+            .with_context(|| format!("failed to query '{}'", self.url))?;
+        resp.error_for_status()
+            .inspect_err(|_| {
+                REPORT_FAILURES.with_label_values(&["http"]).inc();
+            })
+            .with_context(|| format!("failed to query '{}'", self.url))?;
+        Ok(())
+    }
+}
+
+/// Record queue length and dropped reports.
+fn record_queue(len: usize, dropped: Dropped) {
+    REPORTS_QUEUED.set(i64::try_from(len).unwrap_or(i64::MAX));
+    if dropped.expired > 0 {
+        log::warn!(
+            "dropping {} expired update outcome report(s)",
+            dropped.expired
+        );
+        REPORTS_DROPPED
+            .with_label_values(&["expired"])
+            .inc_by(dropped.expired as u64);
+    }
+    if dropped.overflow > 0 {
+        log::warn!(
+            "dropping {} oldest update outcome report(s)",
+            dropped.overflow
+        );
+        REPORTS_DROPPED
+            .with_label_values(&["overflow"])
+            .inc_by(dropped.overflow as u64);
+    }
+}
+
+/// Read JSON content from `path`, if any.
+#[context("failed to read '{}'", path.as_ref().display())]
+fn read_json<T: serde::de::DeserializeOwned>(path: impl AsRef<Path>) -> Result<Option<T>> {
+    let content = match std::fs::read(path.as_ref()) {
+        Ok(c) => c,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let value = serde_json::from_slice(&content).context("failed to parse JSON content")?;
+    Ok(Some(value))
+}
+
+/// Persist JSON content to `path`.
+#[context("failed to write '{}'", path.as_ref().display())]
+fn write_json<T: Serialize>(path: impl AsRef<Path>, value: &T) -> Result<()> {
+    let content = serde_json::to_vec(value)?;
+    utils::atomic_write(path.as_ref(), 0o644, &content)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockito::Matcher;
+    use std::num::NonZeroU64;
+    use tokio::runtime as rt;
+
+    fn reporter(dir: &Path, batch_size: u64) -> OutcomeReporter {
+        let input = inputs::OutcomeReportInput {
+            url: format!("{}/v1/outcomes", mockito::server_url()),
+            batch_size: NonZeroU64::new(batch_size).unwrap(),
+            max_queued: NonZeroU64::new(3).unwrap(),
+            ..Default::default()
+        };
+        let mut reporter = OutcomeReporter::with_config(input, &NetworkSettings::default())
+            .unwrap()
+            .unwrap();
+        reporter.pending_path = dir.join("outcome-pending.json");
+        reporter.queue = EventQueue::new(dir.join("outcome-reports.json"), 3, 0);
+        reporter
+    }
+
+    #[test]
+    fn outcome_report_config() {
+        let network = NetworkSettings::default();
+        let unset = inputs::OutcomeReportInput::default();
+        assert!(OutcomeReporter::with_config(unset, &network)
+            .unwrap()
+            .is_none());
+
+        let ftp = inputs::OutcomeReportInput {
+            url: "ftp://fleet.example.com/outcomes".to_string(),
+            ..Default::default()
+        };
+        OutcomeReporter::with_config(ftp, &network).unwrap_err();
+    }
+
+    #[test]
+    fn outcome_report_batches() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let reporter = reporter(tmpdir.path(), 2);
+        let runtime = rt::Runtime::new().unwrap();
+        let mut id = Identity::mock_default();
+        let update = Release {
+            version: "v2".to_string(),
+            checksum: "c2".to_string(),
+            age_index: None,
+            advisory: None,
+            wave: None,
+        };
+
+        // Three updates: two succeeded and one rolled back, with the
+        // endpoint unreachable.
+        let m_unavailable = mockito::mock("POST", "/v1/outcomes")
+            .with_status(503)
+            .expect(3)
+            .create();
+        for booted in &["v2", "v2", "v1"] {
+            reporter.record_finalized(&id, &update);
+            id.current_os.version = booted.to_string();
+            runtime.block_on(reporter.report_pending(&id));
+        }
+        m_unavailable.assert();
+        assert!(reporter.has_queued());
+        assert!(!reporter.pending_path.exists());
+        let queue = reporter.queue.load::<OutcomeReport>().unwrap();
+        assert_eq!(queue.len(), 3);
+        assert_eq!(queue[2].event.result, UpdateResult::RolledBack);
+        drop(m_unavailable);
+
+        // All queued reports are sent later, in two batches.
+        let m_reports = mockito::mock("POST", "/v1/outcomes")
+            .match_body(Matcher::Regex(
+                r#"^\{"reports":\[\{"node_uuid":"#.to_string(),
+            ))
+            .with_status(200)
+            .expect(2)
+            .create();
+        runtime.block_on(reporter.flush());
+        m_reports.assert();
+        assert!(!reporter.has_queued());
+    }
+
+    #[test]
+    fn outcome_report_legacy_queue() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let reporter = reporter(tmpdir.path(), 2);
+        let legacy = r#"[{"node_uuid":"uuid","group":"default","from_version":"v1","to_version":"v2","booted_version":"v2","result":"succeeded","duration_secs":60,"queued_time":1634400000}]"#;
+        std::fs::write(reporter.queue.path(), legacy).unwrap();
+
+        // Reports queued by older versions are still sent, without the
+        // queueing timestamp.
+        let m_reports = mockito::mock("POST", "/v1/outcomes")
+            .match_body(Matcher::Regex(r#""duration_secs":60\}\]\}$"#.to_string()))
+            .with_status(200)
+            .expect(1)
+            .create();
+        let runtime = rt::Runtime::new().unwrap();
+        runtime.block_on(reporter.flush());
+        m_reports.assert();
+        assert!(!reporter.has_queued());
+    }
+
+    #[test]
+    fn outcome_report_queue_bounds() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let mut reporter = reporter(tmpdir.path(), 2);
+        reporter.queue = EventQueue::new(reporter.queue.path(), 3, 168 * 3600);
+
+        let now = Utc::now().timestamp();
+        let report = |to_version: &str, age_hours: i64| Queued {
+            queued_time: now - age_hours * 3600,
+            event: OutcomeReport {
+                node_uuid: "uuid".to_string(),
+                group: "default".to_string(),
+                from_version: "v1".to_string(),
+                to_version: to_version.to_string(),
+                booted_version: to_version.to_string(),
+                result: UpdateResult::Succeeded,
+                duration_secs: 60,
+            },
+        };
+
+        // Expired reports are dropped, then only the most recent ones are kept.
+        let queue = vec![
+            report("v2", 200),
+            report("v3", 48),
+            report("v4", 24),
+            report("v5", 2),
+            report("v6", 0),
+        ];
+        reporter.queue.store(&queue).unwrap();
+        let stored = reporter.load_queue().unwrap();
+        let versions: Vec<_> = stored.iter().map(|q| q.event.to_version.as_str()).collect();
+        assert_eq!(versions, vec!["v4", "v5", "v6"]);
+
+        // Only expired reports, queue is removed.
+        reporter.queue.store(&[report("v2", 169)]).unwrap();
+        assert!(!reporter.has_queued());
+    }
+}
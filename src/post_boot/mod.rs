@@ -0,0 +1,362 @@
+//! Post-boot confirmation hooks.
+//!
+//! External orchestrators (e.g. a CMDB, or a load balancer) often take a node
+//! out of rotation before it reboots for an update. Post-boot hooks notify
+//! them once the node has booted into the finalized release and the agent
+//! reached steady state, so that the node can be re-enlisted automatically.
+
+use crate::identity::Identity;
+use crate::network::NetworkSettings;
+use crate::rpm_ostree::Release;
+use crate::utils;
+use anyhow::{Context, Result};
+use fn_error_context::context;
+use futures::prelude::*;
+use prometheus::IntCounterVec;
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+/// Absolute path to the persisted update pending post-boot hooks.
+static PENDING_HOOKS_PATH: &str = "/var/lib/zincati/post-boot-hooks.json";
+
+/// Timeout for a single hook (in seconds).
+const HOOK_TIMEOUT_SECS: u64 = 30;
+
+/// Polling interval while waiting for a command hook to complete.
+const HOOK_POLL_INTERVAL_MILLIS: u64 = 100;
+
+lazy_static::lazy_static! {
+    static ref HOOK_RUNS: IntCounterVec = register_int_counter_vec!(
+        "zincati_post_boot_hooks_runs_total",
+        "Total number of post-boot hook runs.",
+        &["kind"]
+    ).unwrap();
+    static ref HOOK_FAILURES: IntCounterVec = register_int_counter_vec!(
+        "zincati_post_boot_hooks_failures_total",
+        "Total number of failed post-boot hook runs.",
+        &["kind"]
+    ).unwrap();
+}
+
+/// A single post-boot hook.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum Hook {
+    /// A command, which must exit successfully.
+    Command {
+        /// Program and arguments.
+        argv: Vec<String>,
+    },
+    /// An HTTP(S) POST request, which must get a successful status code.
+    Http {
+        /// Target URL.
+        url: Url,
+    },
+}
+
+impl Hook {
+    /// Parse a hook, in `command:<program> [args]` or `http(s)://host/path` format.
+    fn parse(input: &str) -> Result<Self> {
+        let input = input.trim();
+        if let Some(value) = input.strip_prefix("command:") {
+            let argv: Vec<String> = value.split_whitespace().map(String::from).collect();
+            match argv.first() {
+                None => anyhow::bail!("empty post-boot hook '{}'", input),
+                Some(program) if !program.starts_with('/') => {
+                    anyhow::bail!("post-boot hook program '{}' is not absolute", program)
+                }
+                Some(_) => {}
+            };
+            return Ok(Hook::Command { argv });
+        }
+
+        let url = Url::parse(input)
+            .with_context(|| format!("failed to parse post-boot hook '{}'", input))?;
+        match url.scheme() {
+            "http" | "https" => Ok(Hook::Http { url }),
+            s => anyhow::bail!("unsupported post-boot hook scheme '{}'", s),
+        }
+    }
+
+    /// Label for this hook kind, as used in metrics.
+    fn kind(&self) -> &'static str {
+        match self {
+            Hook::Command { .. } => "command",
+            Hook::Http { .. } => "http",
+        }
+    }
+}
+
+impl std::fmt::Display for Hook {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Hook::Command { argv } => write!(f, "command '{}'", argv.join(" ")),
+            Hook::Http { url } => write!(f, "URL '{}'", url),
+        }
+    }
+}
+
+/// Details of a finalized update, passed to hooks.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct UpdateNotice {
+    /// Agent ID.
+    pub node_uuid: String,
+    /// Update group.
+    pub group: String,
+    /// Release booted before the update.
+    pub from_version: String,
+    /// Finalized release.
+    pub to_version: String,
+}
+
+/// Hooks to run after booting into a finalized update.
+#[derive(Clone, Debug, Serialize)]
+pub struct PostBootHooks {
+    /// Hooks to run, in order.
+    pub hooks: Vec<Hook>,
+    /// Timeout for a single hook.
+    pub timeout: Duration,
+    /// Path to the persisted update pending hooks.
+    path: PathBuf,
+    /// HTTP client, for HTTP(S) hooks.
+    #[serde(skip)]
+    hclient: reqwest::Client,
+}
+
+impl PostBootHooks {
+    /// Process post-boot hooks configuration.
+    ///
+    /// This returns `None` if no hooks are configured.
+    #[context("failed to validate post-boot hooks configuration")]
+    pub fn with_config(cfg: Vec<String>, network: &NetworkSettings) -> Result<Option<Self>> {
+        if cfg.is_empty() {
+            return Ok(None);
+        }
+
+        let hooks = cfg
+            .iter()
+            .map(|entry| Hook::parse(entry))
+            .collect::<Result<Vec<_>>>()?;
+        let timeout = Duration::from_secs(HOOK_TIMEOUT_SECS);
+        let hclient = network
+            .configure(reqwest::ClientBuilder::new())?
+            .timeout(timeout)
+            .build()?;
+        log::info!("{} post-boot hook(s) configured", hooks.len());
+
+        let post_boot = Self {
+            hooks,
+            timeout,
+            path: PathBuf::from(PENDING_HOOKS_PATH),
+            hclient,
+        };
+        Ok(Some(post_boot))
+    }
+
+    /// Record a finalized update, for hooks to run after reboot.
+    pub fn record_finalized(&self, identity: &Identity, update: &Release) {
+        let notice = UpdateNotice {
+            node_uuid: identity.node_uuid.lower_hex(),
+            group: identity.group.clone(),
+            from_version: identity.current_os.version.clone(),
+            to_version: update.version.clone(),
+        };
+        if let Err(e) = persist_notice(&self.path, &notice) {
+            log::error!("{:#}", e);
+        }
+    }
+
+    /// Run all hooks if the booted release is a recorded finalized update.
+    ///
+    /// Hooks run once: the recorded update is cleared afterwards, even if
+    /// some hooks failed.
+    pub fn run_pending(&self, booted: &Release) -> Pin<Box<dyn Future<Output = ()>>> {
+        let notice = match load_notice(&self.path) {
+            Ok(Some(n)) => n,
+            Ok(None) => return Box::pin(future::ready(())),
+            Err(e) => {
+                log::error!("{:#}", e);
+                return Box::pin(future::ready(()));
+            }
+        };
+        if let Err(e) = utils::remove_if_exists(&self.path) {
+            log::error!("{:#}", e);
+        }
+        if notice.to_version != booted.version {
+            log::warn!(
+                "booted release {} does not match update to {}, skipping post-boot hooks",
+                booted.version,
+                notice.to_version
+            );
+            return Box::pin(future::ready(()));
+        }
+
+        log::info!(
+            "booted into update from {} to {}, running post-boot hooks",
+            notice.from_version,
+            notice.to_version
+        );
+        let hooks = self.hooks.clone();
+        let timeout = self.timeout;
+        let hclient = self.hclient.clone();
+        let run = async move {
+            for hook in hooks {
+                HOOK_RUNS.with_label_values(&[hook.kind()]).inc();
+                let res = match &hook {
+                    Hook::Command { argv } => {
+                        let (argv, notice) = (argv.clone(), notice.clone());
+                        tokio::task::spawn_blocking(move || run_command(&argv, &notice, timeout))
+                            .await
+                            .unwrap_or_else(|e| Err(e.into()))
+                    }
+                    Hook::Http { url } => post_http(&hclient, url, &notice).await,
+                };
+                match res {
+                    Ok(_) => log::debug!("post-boot hook succeeded: {}", hook),
+                    Err(e) => {
+                        HOOK_FAILURES.with_label_values(&[hook.kind()]).inc();
+                        log::warn!("post-boot hook failed: {}: {:#}", hook, e);
+                    }
+                }
+            }
+        };
+        Box::pin(run)
+    }
+}
+
+/// Run a command hook, with the given timeout.
+fn run_command(argv: &[String], notice: &UpdateNotice, timeout: Duration) -> Result<()> {
+    let mut child = Command::new(&argv[0])
+        .args(&argv[1..])
+        .env("ZINCATI_NODE_UUID", &notice.node_uuid)
+        .env("ZINCATI_GROUP", &notice.group)
+        .env("ZINCATI_FROM_VERSION", &notice.from_version)
+        .env("ZINCATI_TO_VERSION", &notice.to_version)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .context("failed to spawn process")?;
+    let deadline = Instant::now() + timeout;
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if Instant::now() >= deadline {
+            let _ = child.kill();
+            let _ = child.wait();
+            anyhow::bail!("timed out after {} seconds", timeout.as_secs());
+        }
+        std::thread::sleep(Duration::from_millis(HOOK_POLL_INTERVAL_MILLIS));
+    };
+
+    if !status.success() {
+        anyhow::bail!("command failed, {}", status);
+    }
+    Ok(())
+}
+
+/// Post update details to a URL, expecting a successful HTTP status code.
+async fn post_http(hclient: &reqwest::Client, url: &Url, notice: &UpdateNotice) -> Result<()> {
+    let resp = hclient
+        .post(url.clone())
+        .json(notice)
+        .send()
+        .await
+        .with_context(|| format!("failed to query '{}'", url))?;
+    resp.error_for_status()
+        .with_context(|| format!("failed to query '{}'", url))?;
+    Ok(())
+}
+
+/// Load an update pending post-boot hooks from `path`, if any.
+#[context("failed to load update pending post-boot hooks")]
+fn load_notice(path: impl AsRef<Path>) -> Result<Option<UpdateNotice>> {
+    let path = path.as_ref();
+    let content = match std::fs::read(path) {
+        Ok(c) => c,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).with_context(|| format!("failed to read '{}'", path.display())),
+    };
+    let notice = serde_json::from_slice(&content)
+        .with_context(|| format!("failed to parse '{}'", path.display()))?;
+    Ok(Some(notice))
+}
+
+/// Persist an update pending post-boot hooks to `path`.
+#[context("failed to persist update pending post-boot hooks")]
+fn persist_notice(path: impl AsRef<Path>, notice: &UpdateNotice) -> Result<()> {
+    let content = serde_json::to_vec(notice)?;
+    utils::atomic_write(path, 0o644, &content)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::runtime as rt;
+
+    #[test]
+    fn parse_hook() {
+        assert_eq!(
+            Hook::parse("command:/usr/local/bin/enlist --pool web").unwrap(),
+            Hook::Command {
+                argv: vec![
+                    "/usr/local/bin/enlist".to_string(),
+                    "--pool".to_string(),
+                    "web".to_string()
+                ]
+            }
+        );
+        assert_eq!(
+            Hook::parse("https://cmdb.example.com/enlist").unwrap(),
+            Hook::Http {
+                url: Url::parse("https://cmdb.example.com/enlist").unwrap()
+            }
+        );
+
+        Hook::parse("command:").unwrap_err();
+        Hook::parse("command:enlist").unwrap_err();
+        Hook::parse("ftp://cmdb.example.com/enlist").unwrap_err();
+        Hook::parse("enlist").unwrap_err();
+    }
+
+    #[test]
+    fn run_pending_hooks() {
+        let network = NetworkSettings::default();
+        let unset = PostBootHooks::with_config(vec![], &network).unwrap();
+        assert!(unset.is_none());
+
+        let tmpdir = tempfile::tempdir().unwrap();
+        let marker = tmpdir.path().join("hook-ran");
+        let cfg = vec![format!("command:/usr/bin/touch {}", marker.display())];
+        let mut hooks = PostBootHooks::with_config(cfg, &network).unwrap().unwrap();
+        hooks.path = tmpdir.path().join("post-boot-hooks.json");
+
+        let id = Identity::mock_default();
+        let update = Release {
+            version: "1.0".to_string(),
+            checksum: "sha-1".to_string(),
+            age_index: None,
+            advisory: None,
+            wave: None,
+        };
+        let runtime = rt::Runtime::new().unwrap();
+
+        // Rolled back, hooks are skipped and the update is cleared.
+        hooks.record_finalized(&id, &update);
+        runtime.block_on(hooks.run_pending(&id.current_os));
+        assert!(!marker.exists());
+        assert!(load_notice(&hooks.path).unwrap().is_none());
+
+        hooks.record_finalized(&id, &update);
+        let notice = load_notice(&hooks.path).unwrap().unwrap();
+        assert_eq!(notice.from_version, id.current_os.version);
+        runtime.block_on(hooks.run_pending(&update));
+        assert!(marker.exists());
+        assert!(load_notice(&hooks.path).unwrap().is_none());
+    }
+}
@@ -0,0 +1,249 @@
+//! Quiescing services before updates finalization.
+//!
+//! Finalization reboots the node right away, and services are then stopped
+//! within the usual shutdown timeouts. Some services (e.g. databases) need a
+//! longer, orderly shutdown to avoid a recovery on the next boot. Configured
+//! units are stopped in order right before finalization, each with its own
+//! timeout, and filesystems are synced afterwards. If a unit fails to stop,
+//! or finalization fails, units marked for it are started again.
+
+use crate::config::inputs;
+use anyhow::{Context, Result};
+use fn_error_context::context;
+use futures::prelude::*;
+use prometheus::IntCounter;
+use serde::Serialize;
+use std::pin::Pin;
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+/// Default timeout for stopping a single unit (in seconds).
+pub const DEFAULT_STOP_TIMEOUT_SECS: u64 = 90;
+
+/// Polling interval while waiting for a unit to stop or start.
+const POLL_INTERVAL_MILLIS: u64 = 100;
+
+/// Timeout for starting a unit again after an abort (in seconds).
+const RESTART_TIMEOUT_SECS: u64 = 90;
+
+/// Unit of the agent itself, which cannot be quiesced.
+static AGENT_UNIT: &str = "zincati.service";
+
+lazy_static::lazy_static! {
+    static ref QUIESCE_RUNS: IntCounter = register_int_counter!(opts!(
+        "zincati_quiesce_runs_total",
+        "Total number of attempts at stopping services before finalization."
+    )).unwrap();
+    static ref QUIESCE_FAILURES: IntCounter = register_int_counter!(opts!(
+        "zincati_quiesce_failures_total",
+        "Total number of failed attempts at stopping services before finalization."
+    )).unwrap();
+    static ref QUIESCE_RESTARTS: IntCounter = register_int_counter!(opts!(
+        "zincati_quiesce_restarts_total",
+        "Total number of services started again after an aborted finalization."
+    )).unwrap();
+}
+
+/// A unit stopped before finalization.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct QuiesceUnit {
+    /// Unit name.
+    pub name: String,
+    /// Timeout for stopping the unit.
+    pub timeout: Duration,
+    /// Whether to start the unit again if finalization is aborted.
+    pub restart_on_abort: bool,
+}
+
+impl QuiesceUnit {
+    /// Validate a unit entry.
+    fn with_config(cfg: inputs::QuiesceUnitInput) -> Result<Self> {
+        let name = cfg.name.trim();
+        anyhow::ensure!(!name.is_empty(), "empty unit name");
+        anyhow::ensure!(
+            !name.starts_with('-') && !name.contains(char::is_whitespace),
+            "invalid unit name '{}'",
+            name
+        );
+        anyhow::ensure!(name != AGENT_UNIT, "unit '{}' cannot be quiesced", name);
+
+        let unit = Self {
+            name: name.to_string(),
+            timeout: Duration::from_secs(cfg.timeout_secs.get()),
+            restart_on_abort: cfg.restart_on_abort,
+        };
+        Ok(unit)
+    }
+
+    /// Stop this unit, within its timeout.
+    fn stop(&self) -> Result<()> {
+        let mut cmd = Command::new("systemctl");
+        cmd.arg("stop").arg(&self.name);
+        run(&mut cmd, self.timeout)
+    }
+
+    /// Start this unit again.
+    fn start(&self) -> Result<()> {
+        let mut cmd = Command::new("systemctl");
+        cmd.arg("start").arg(&self.name);
+        run(&mut cmd, Duration::from_secs(RESTART_TIMEOUT_SECS))
+    }
+}
+
+/// Services stopped before finalizing an update.
+#[derive(Clone, Debug, Serialize)]
+pub struct ServiceQuiesce {
+    /// Units to stop, in order.
+    pub units: Vec<QuiesceUnit>,
+}
+
+impl ServiceQuiesce {
+    /// Process service quiesce configuration.
+    ///
+    /// This returns `None` if no units are configured.
+    #[context("failed to validate service quiesce configuration")]
+    pub fn with_config(cfg: inputs::QuiesceInput) -> Result<Option<Self>> {
+        if cfg.units.is_empty() {
+            return Ok(None);
+        }
+
+        let units = cfg
+            .units
+            .into_iter()
+            .map(QuiesceUnit::with_config)
+            .collect::<Result<Vec<_>>>()?;
+        log::info!("{} unit(s) to be stopped before finalization", units.len());
+
+        Ok(Some(Self { units }))
+    }
+
+    /// Stop all units in order, then sync filesystems.
+    ///
+    /// On failure, units stopped so far (including the failed one) are
+    /// started again if configured so, and this returns the reason.
+    pub fn stop(&self) -> Pin<Box<dyn Future<Output = Result<(), String>>>> {
+        QUIESCE_RUNS.inc();
+        let units = self.units.clone();
+        let stop = tokio::task::spawn_blocking(move || {
+            for (index, unit) in units.iter().enumerate() {
+                log::info!("stopping unit '{}' before finalization", unit.name);
+                if let Err(e) = unit.stop() {
+                    let reason = format!("failed to stop unit '{}': {:#}", unit.name, e);
+                    restart_units(&units[..=index]);
+                    return Err(reason);
+                }
+            }
+            log::debug!("syncing filesystems before finalization");
+            unsafe { libc::sync() };
+            Ok(())
+        })
+        .map(|res| {
+            let outcome = res.unwrap_or_else(|e| Err(format!("failed to join unit stops: {}", e)));
+            if let Err(failed) = &outcome {
+                QUIESCE_FAILURES.inc();
+                log::warn!("quiescing services before finalization failed: {}", failed);
+            }
+            outcome
+        });
+        Box::pin(stop)
+    }
+
+    /// Start units again after finalization was aborted, in reverse order.
+    pub fn restart(&self) -> Pin<Box<dyn Future<Output = ()>>> {
+        let units = self.units.clone();
+        let restart = tokio::task::spawn_blocking(move || restart_units(&units)).map(|res| {
+            if let Err(e) = res {
+                log::error!("failed to join unit restarts: {}", e);
+            }
+        });
+        Box::pin(restart)
+    }
+}
+
+/// Start again the given units which are marked for it, in reverse order.
+fn restart_units(units: &[QuiesceUnit]) {
+    for unit in units.iter().rev().filter(|u| u.restart_on_abort) {
+        log::info!("finalization aborted, starting unit '{}' again", unit.name);
+        match unit.start() {
+            Ok(_) => QUIESCE_RESTARTS.inc(),
+            Err(e) => log::error!("failed to start unit '{}': {:#}", unit.name, e),
+        }
+    }
+}
+
+/// Run a command to completion, killing it on timeout.
+fn run(cmd: &mut Command, timeout: Duration) -> Result<()> {
+    let mut child = cmd
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .context("failed to spawn process")?;
+    let deadline = Instant::now() + timeout;
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if Instant::now() >= deadline {
+            let _ = child.kill();
+            let _ = child.wait();
+            anyhow::bail!("timed out after {} seconds", timeout.as_secs());
+        }
+        std::thread::sleep(Duration::from_millis(POLL_INTERVAL_MILLIS));
+    };
+
+    if !status.success() {
+        anyhow::bail!("command failed, {}", status);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::num::NonZeroU64;
+
+    fn unit(name: &str) -> inputs::QuiesceUnitInput {
+        inputs::QuiesceUnitInput {
+            name: name.to_string(),
+            restart_on_abort: true,
+            timeout_secs: NonZeroU64::new(DEFAULT_STOP_TIMEOUT_SECS).unwrap(),
+        }
+    }
+
+    #[test]
+    fn quiesce_with_config() {
+        let unset = ServiceQuiesce::with_config(inputs::QuiesceInput::default()).unwrap();
+        assert!(unset.is_none());
+
+        let cfg = inputs::QuiesceInput {
+            units: vec![unit("postgresql.service"), unit(" etcd-member.service ")],
+        };
+        let quiesce = ServiceQuiesce::with_config(cfg).unwrap().unwrap();
+        assert_eq!(quiesce.units.len(), 2);
+        assert_eq!(quiesce.units[1].name, "etcd-member.service");
+        assert_eq!(
+            quiesce.units[0].timeout,
+            Duration::from_secs(DEFAULT_STOP_TIMEOUT_SECS)
+        );
+
+        for name in &["", "--all", "foo bar.service", "zincati.service"] {
+            let cfg = inputs::QuiesceInput {
+                units: vec![unit(name)],
+            };
+            ServiceQuiesce::with_config(cfg).unwrap_err();
+        }
+    }
+
+    #[test]
+    fn run_with_timeout() {
+        run(&mut Command::new("/bin/true"), Duration::from_secs(5)).unwrap();
+        let failed = run(&mut Command::new("/bin/false"), Duration::from_secs(5)).unwrap_err();
+        assert!(failed.to_string().contains("command failed"), "{}", failed);
+
+        let mut slow = Command::new("/bin/sleep");
+        slow.arg("5");
+        let failed = run(&mut slow, Duration::from_millis(200)).unwrap_err();
+        assert!(failed.to_string().contains("timed out"), "{}", failed);
+    }
+}
@@ -1,37 +1,204 @@
 //! rpm-ostree client actor.
 
-use super::cli_status::StatusJson;
-use super::Release;
+use super::cli_status::{Layering, SecurityFixes, StatusJson};
+use super::{Backend, Release, Timeouts};
 use actix::prelude::*;
 use anyhow::Result;
 use filetime::FileTime;
+use futures::prelude::*;
 use log::trace;
+use prometheus::HistogramVec;
 use std::collections::BTreeSet;
-use std::rc::Rc;
+use std::sync::{Arc, RwLock};
+use std::time::Instant;
+
+lazy_static::lazy_static! {
+    static ref OPERATION_DURATION: HistogramVec = register_histogram_vec!(
+        "zincati_rpm_ostree_operation_duration_seconds",
+        "Duration of rpm-ostree operations, by operation and result.",
+        &["operation", "result"],
+        vec![0.1, 0.5, 1.0, 5.0, 15.0, 60.0, 300.0, 900.0, 1800.0, 3600.0]
+    ).unwrap();
+}
+
+/// Record the duration of an rpm-ostree operation, labeled by its result.
+pub(super) async fn timed<T>(
+    operation: &'static str,
+    op: impl Future<Output = Result<T>>,
+) -> Result<T> {
+    let start = Instant::now();
+    let result = op.await;
+    let label = if result.is_ok() { "success" } else { "failure" };
+    OPERATION_DURATION
+        .with_label_values(&[operation, label])
+        .observe(start.elapsed().as_secs_f64());
+    result
+}
 
 /// Cache of local deployments.
 #[derive(Clone, Debug)]
 pub struct StatusCache {
-    pub status: Rc<StatusJson>,
-    pub mtime: FileTime,
+    pub status: Arc<StatusJson>,
+    /// Modification time of local deployments, unless they are watched for changes.
+    pub mtime: Option<FileTime>,
+}
+
+/// Status cache, shared between the client and read-only queries.
+///
+/// Up-to-date entries can be read concurrently, without going through the
+/// client, while only the client refreshes or invalidates the cache.
+#[derive(Clone, Debug, Default)]
+pub struct SharedStatusCache {
+    inner: Arc<RwLock<CacheState>>,
+}
+
+#[derive(Debug, Default)]
+struct CacheState {
+    entry: Option<StatusCache>,
+    /// Number of times the cache was invalidated.
+    generation: u64,
+    /// Whether local deployments are watched for changes.
+    watched: bool,
+}
+
+impl SharedStatusCache {
+    /// Return cached status, if it matches the given deployments mtime.
+    pub(crate) fn get(&self, mtime: Option<FileTime>) -> Option<Arc<StatusJson>> {
+        let state = self.inner.read().ok()?;
+        match &state.entry {
+            Some(cache) if cache.mtime == mtime => Some(Arc::clone(&cache.status)),
+            _ => None,
+        }
+    }
+
+    /// Return the current generation, to be passed back when storing a query result.
+    pub(crate) fn generation(&self) -> u64 {
+        self.inner.read().map(|state| state.generation).unwrap_or(0)
+    }
+
+    /// Store the result of a status query started at `generation`.
+    ///
+    /// Results are discarded if the cache was invalidated meanwhile, as they
+    /// may predate that change.
+    pub(crate) fn store(&self, generation: u64, cache: StatusCache) {
+        if let Ok(mut state) = self.inner.write() {
+            if state.generation == generation {
+                state.entry = Some(cache);
+            }
+        }
+    }
+
+    /// Drop cached status, including the result of any query still in progress.
+    pub(crate) fn invalidate(&self) {
+        if let Ok(mut state) = self.inner.write() {
+            state.entry = None;
+            state.generation += 1;
+        }
+    }
+
+    /// Return whether local deployments are watched for changes.
+    ///
+    /// If so, cached status stays valid until invalidated.
+    pub(crate) fn is_watched(&self) -> bool {
+        self.inner
+            .read()
+            .map(|state| state.watched)
+            .unwrap_or(false)
+    }
+
+    /// Record whether local deployments are watched for changes.
+    fn set_watched(&self, watched: bool) {
+        if let Ok(mut state) = self.inner.write() {
+            state.watched = watched;
+        }
+    }
 }
 
 /// Client actor for rpm-ostree.
+///
+/// Operations run asynchronously, thus the client keeps serving requests
+/// while a long transaction is in progress.
 #[derive(Debug, Default, Clone)]
 pub struct RpmOstreeClient {
-    // NB: This is OK for now because `rpm-ostree` actor is curently spawned on a single thread,
-    // but if we move to a larger threadpool, each actor thread will have its own cache.
-    pub status_cache: Option<StatusCache>,
+    pub(crate) status_cache: SharedStatusCache,
+    /// Backend used to interact with rpm-ostree.
+    pub(crate) backend: Backend,
+    /// Timeouts for rpm-ostree operations.
+    pub(crate) timeouts: Timeouts,
 }
 
 impl Actor for RpmOstreeClient {
-    type Context = SyncContext<Self>;
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        let watching = super::watcher::DeploymentsWatcher::new().and_then(|watcher| {
+            let complete = watcher.is_complete();
+            watcher.spawn(ctx.address()).map(|_| complete)
+        });
+        match watching {
+            Ok(complete) => {
+                trace!("watching local deployments for changes");
+                self.status_cache.set_watched(complete);
+            }
+            Err(e) => log::debug!("not watching local deployments: {:#}", e),
+        }
+    }
 }
 
 impl RpmOstreeClient {
-    /// Start the threadpool for rpm-ostree blocking clients.
-    pub fn start(threads: usize) -> Addr<Self> {
-        SyncArbiter::start(threads, RpmOstreeClient::default)
+    /// Create an rpm-ostree client, to be started by the operation queue.
+    pub fn new(backend: Backend, timeouts: Timeouts) -> Self {
+        Self {
+            backend,
+            timeouts,
+            ..RpmOstreeClient::default()
+        }
+    }
+
+    /// Wrap a transaction, invalidating the status cache once it completes.
+    ///
+    /// Staging does not touch deployments on disk, thus the cache may not notice it.
+    fn invalidating<T: 'static>(
+        &self,
+        transaction: impl Future<Output = Result<T>> + 'static,
+    ) -> ResponseFuture<Result<T>> {
+        let cache = self.status_cache.clone();
+        Box::pin(async move {
+            let result = transaction.await;
+            cache.invalidate();
+            result
+        })
+    }
+}
+
+/// Notification: local deployments changed, or stopped being watched.
+#[derive(Debug, Clone)]
+pub(crate) struct DeploymentsChanged {
+    /// Whether local deployments are still watched for changes.
+    pub(crate) watched: bool,
+}
+
+impl Message for DeploymentsChanged {
+    type Result = ();
+}
+
+impl Handler<DeploymentsChanged> for RpmOstreeClient {
+    type Result = ();
+
+    fn handle(&mut self, msg: DeploymentsChanged, ctx: &mut Self::Context) -> Self::Result {
+        self.status_cache.set_watched(msg.watched);
+        self.status_cache.invalidate();
+        if !msg.watched {
+            return;
+        }
+
+        trace!("local deployments changed, refreshing status cache");
+        let refresh = super::cli_status::status_json(self).map(|res| {
+            if let Err(e) = res {
+                log::debug!("failed to refresh status cache: {:#}", e);
+            }
+        });
+        ctx.spawn(refresh.into_actor(self));
     }
 }
 
@@ -40,6 +207,8 @@ impl RpmOstreeClient {
 pub struct StageDeployment {
     /// Whether to allow downgrades.
     pub allow_downgrade: bool,
+    /// Whether the release was already downloaded (no network access).
+    pub cache_only: bool,
     /// Release to be staged.
     pub release: Release,
 }
@@ -49,17 +218,55 @@ impl Message for StageDeployment {
 }
 
 impl Handler<StageDeployment> for RpmOstreeClient {
-    type Result = Result<Release>;
+    type Result = ResponseFuture<Result<Release>>;
 
     fn handle(&mut self, msg: StageDeployment, _ctx: &mut Self::Context) -> Self::Result {
         trace!("request to stage release: {:?}", msg.release);
-        super::cli_deploy::deploy_locked(msg.release, msg.allow_downgrade)
+        let deploy = super::cli_deploy::deploy_locked(
+            msg.release,
+            msg.allow_downgrade,
+            msg.cache_only,
+            self.backend,
+            self.timeouts.stage,
+        );
+        self.invalidating(timed("deploy", deploy))
+    }
+}
+
+/// Request: download a deployment, without staging it.
+#[derive(Debug, Clone)]
+pub struct DownloadDeployment {
+    /// Whether to allow downgrades.
+    pub allow_downgrade: bool,
+    /// Release to be downloaded.
+    pub release: Release,
+}
+
+impl Message for DownloadDeployment {
+    type Result = Result<Release>;
+}
+
+impl Handler<DownloadDeployment> for RpmOstreeClient {
+    type Result = ResponseFuture<Result<Release>>;
+
+    fn handle(&mut self, msg: DownloadDeployment, _ctx: &mut Self::Context) -> Self::Result {
+        trace!("request to download release: {:?}", msg.release);
+        let download = super::cli_deploy::download_only(
+            msg.release,
+            msg.allow_downgrade,
+            self.backend,
+            self.timeouts.stage,
+        );
+        Box::pin(timed("download", download))
     }
 }
 
 /// Request: finalize a staged deployment (by unlocking it and rebooting).
 #[derive(Debug, Clone)]
 pub struct FinalizeDeployment {
+    /// Whether to also finalize a deployment which is not finalization-locked
+    /// (e.g. staged out-of-band).
+    pub allow_unlocked: bool,
     /// Finalized release to finalize.
     pub release: Release,
 }
@@ -69,11 +276,63 @@ impl Message for FinalizeDeployment {
 }
 
 impl Handler<FinalizeDeployment> for RpmOstreeClient {
-    type Result = Result<Release>;
+    type Result = ResponseFuture<Result<Release>>;
 
     fn handle(&mut self, msg: FinalizeDeployment, _ctx: &mut Self::Context) -> Self::Result {
         trace!("request to finalize release: {:?}", msg.release);
-        super::cli_finalize::finalize_deployment(msg.release)
+        let finalize = super::cli_finalize::finalize_deployment(
+            msg.release,
+            msg.allow_unlocked,
+            self.backend,
+            self.timeouts.finalize,
+        );
+        self.invalidating(timed("finalize", finalize))
+    }
+}
+
+/// Request: remove the rollback deployment, if any.
+#[derive(Debug, Clone)]
+pub struct CleanupRollback {}
+
+impl Message for CleanupRollback {
+    type Result = Result<()>;
+}
+
+impl Handler<CleanupRollback> for RpmOstreeClient {
+    type Result = ResponseFuture<Result<()>>;
+
+    fn handle(&mut self, _msg: CleanupRollback, _ctx: &mut Self::Context) -> Self::Result {
+        trace!("request to clean up rollback deployment");
+        let cleanup = super::cli_cleanup::cleanup_rollback(self.backend, self.timeouts.stage);
+        self.invalidating(timed("cleanup", cleanup))
+    }
+}
+
+/// Request: rebase onto another refspec (in finalization-locked mode).
+#[derive(Debug, Clone)]
+pub struct RebaseDeployment {
+    /// Refspec to rebase onto (`remote:ref`).
+    pub refspec: String,
+}
+
+impl Message for RebaseDeployment {
+    type Result = Result<Release>;
+}
+
+impl Handler<RebaseDeployment> for RpmOstreeClient {
+    type Result = ResponseFuture<Result<Release>>;
+
+    fn handle(&mut self, msg: RebaseDeployment, _ctx: &mut Self::Context) -> Self::Result {
+        trace!("request to rebase to: {}", msg.refspec);
+        let rebase =
+            super::cli_rebase::rebase_locked(msg.refspec, self.backend, self.timeouts.stage);
+        let rebase = self.invalidating(timed("rebase", rebase));
+        // Status is only queried once the rebase completed.
+        let staged = super::cli_status::staged_deployment(self);
+        Box::pin(async move {
+            rebase.await?;
+            staged.await
+        })
     }
 }
 
@@ -81,7 +340,7 @@ impl Handler<FinalizeDeployment> for RpmOstreeClient {
 #[derive(Debug, Clone)]
 pub struct QueryLocalDeployments {
     /// Whether to include staged (i.e. not finalized) deployments in query result.
-    pub(crate) omit_staged: bool,
+    pub omit_staged: bool,
 }
 
 impl Message for QueryLocalDeployments {
@@ -89,7 +348,7 @@ impl Message for QueryLocalDeployments {
 }
 
 impl Handler<QueryLocalDeployments> for RpmOstreeClient {
-    type Result = Result<BTreeSet<Release>>;
+    type Result = ResponseFuture<Result<BTreeSet<Release>>>;
 
     fn handle(
         &mut self,
@@ -101,6 +360,91 @@ impl Handler<QueryLocalDeployments> for RpmOstreeClient {
     }
 }
 
+/// Request: query the staged deployment, if any.
+#[derive(Debug, Clone)]
+pub struct QueryStagedDeployment {}
+
+impl Message for QueryStagedDeployment {
+    type Result = Result<Option<Release>>;
+}
+
+impl Handler<QueryStagedDeployment> for RpmOstreeClient {
+    type Result = ResponseFuture<Result<Option<Release>>>;
+
+    fn handle(&mut self, _msg: QueryStagedDeployment, _ctx: &mut Self::Context) -> Self::Result {
+        trace!("request to query staged deployment");
+        super::cli_status::query_staged(self)
+    }
+}
+
+/// Request: query local changes (layered packages and overrides) to the booted deployment.
+#[derive(Debug, Clone)]
+pub struct QueryBootedLayering {}
+
+impl Message for QueryBootedLayering {
+    type Result = Result<Layering>;
+}
+
+impl Handler<QueryBootedLayering> for RpmOstreeClient {
+    type Result = ResponseFuture<Result<Layering>>;
+
+    fn handle(&mut self, _msg: QueryBootedLayering, _ctx: &mut Self::Context) -> Self::Result {
+        trace!("request to query booted deployment layering");
+        super::cli_status::booted_layering(self)
+    }
+}
+
+/// Request: check whether the staged deployment is a downgrade.
+#[derive(Debug, Clone)]
+pub struct QueryStagedDowngrade {}
+
+impl Message for QueryStagedDowngrade {
+    type Result = Result<bool>;
+}
+
+impl Handler<QueryStagedDowngrade> for RpmOstreeClient {
+    type Result = ResponseFuture<Result<bool>>;
+
+    fn handle(&mut self, _msg: QueryStagedDowngrade, _ctx: &mut Self::Context) -> Self::Result {
+        trace!("request to check staged deployment for downgrade");
+        super::cli_status::staged_is_downgrade(self)
+    }
+}
+
+/// Request: query how much older the staged commit is than the booted one.
+#[derive(Debug, Clone)]
+pub struct QueryStagedCommitAge {}
+
+impl Message for QueryStagedCommitAge {
+    type Result = Result<Option<i64>>;
+}
+
+impl Handler<QueryStagedCommitAge> for RpmOstreeClient {
+    type Result = ResponseFuture<Result<Option<i64>>>;
+
+    fn handle(&mut self, _msg: QueryStagedCommitAge, _ctx: &mut Self::Context) -> Self::Result {
+        trace!("request to query staged commit age");
+        super::cli_status::staged_commit_age(self)
+    }
+}
+
+/// Request: query security fixes shipped by the staged deployment.
+#[derive(Debug, Clone)]
+pub struct QueryStagedSecurityFixes {}
+
+impl Message for QueryStagedSecurityFixes {
+    type Result = Result<Option<SecurityFixes>>;
+}
+
+impl Handler<QueryStagedSecurityFixes> for RpmOstreeClient {
+    type Result = ResponseFuture<Result<Option<SecurityFixes>>>;
+
+    fn handle(&mut self, _msg: QueryStagedSecurityFixes, _ctx: &mut Self::Context) -> Self::Result {
+        trace!("request to query staged security fixes");
+        super::cli_status::staged_security_fixes(self)
+    }
+}
+
 /// Request: Register as the update driver for rpm-ostree.
 #[derive(Debug, Clone)]
 pub struct RegisterAsDriver {}
@@ -110,10 +454,45 @@ impl Message for RegisterAsDriver {
 }
 
 impl Handler<RegisterAsDriver> for RpmOstreeClient {
-    type Result = Result<()>;
+    type Result = ResponseFuture<Result<()>>;
 
     fn handle(&mut self, _msg: RegisterAsDriver, _ctx: &mut Self::Context) -> Self::Result {
         trace!("request to register as rpm-ostree update driver");
-        super::cli_deploy::deploy_register_driver()
+        let register = super::cli_deploy::deploy_register_driver(self.timeouts.status);
+        Box::pin(register)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shared_status_cache() {
+        let content = std::fs::read("tests/fixtures/rpm-ostree-status.json").unwrap();
+        let status: Arc<StatusJson> = Arc::new(serde_json::from_slice(&content).unwrap());
+        let mtime = Some(FileTime::from_unix_time(1_600_000_000, 0));
+        let entry = StatusCache {
+            status: Arc::clone(&status),
+            mtime,
+        };
+
+        let cache = SharedStatusCache::default();
+        assert!(cache.get(mtime).is_none());
+        cache.store(cache.generation(), entry.clone());
+        assert!(cache.get(mtime).is_some());
+        assert!(cache
+            .get(Some(FileTime::from_unix_time(1_600_000_001, 0)))
+            .is_none());
+
+        // Results of queries started before invalidation are discarded.
+        let generation = cache.generation();
+        cache.invalidate();
+        assert!(cache.get(mtime).is_none());
+        cache.store(generation, entry.clone());
+        assert!(cache.get(mtime).is_none());
+        cache.store(cache.generation(), entry);
+        assert!(cache.get(mtime).is_some());
+        assert!(!cache.is_watched());
     }
 }
@@ -0,0 +1,37 @@
+//! Interface to `rpm-ostree cancel`.
+
+use anyhow::Result;
+use prometheus::IntCounter;
+use std::time::Duration;
+
+lazy_static::lazy_static! {
+    static ref CANCEL_ATTEMPTS: IntCounter = register_int_counter!(opts!(
+        "zincati_rpm_ostree_cancel_attempts_total",
+        "Total number of 'rpm-ostree cancel' attempts."
+    )).unwrap();
+}
+
+/// Timeout for cancelling a transaction (in seconds).
+const CANCEL_TIMEOUT_SECS: u64 = 60;
+
+/// Cancel the active rpm-ostree transaction, if any.
+///
+/// The cancelled operation (e.g. a deploy) then fails on its own.
+pub fn cancel_transaction() -> Result<()> {
+    fail_point!("cancel_transaction_ok", |_| Ok(()));
+    CANCEL_ATTEMPTS.inc();
+
+    let mut cmd = std::process::Command::new("rpm-ostree");
+    cmd.arg("cancel").env("RPMOSTREE_CLIENT_ID", "zincati");
+    let timeout = Duration::from_secs(CANCEL_TIMEOUT_SECS);
+    let cmd = super::command::blocking_output(&mut cmd, timeout)?;
+
+    if !cmd.status.success() {
+        anyhow::bail!(
+            "rpm-ostree cancel failed:\n{}",
+            String::from_utf8_lossy(&cmd.stderr)
+        );
+    }
+
+    Ok(())
+}
@@ -0,0 +1,57 @@
+//! Interface to `rpm-ostree cleanup --rollback`.
+
+use super::Backend;
+use anyhow::Result;
+use prometheus::IntCounter;
+use std::time::Duration;
+
+lazy_static::lazy_static! {
+    static ref CLEANUP_ATTEMPTS: IntCounter = register_int_counter!(opts!(
+        "zincati_rpm_ostree_cleanup_attempts_total",
+        "Total number of 'rpm-ostree cleanup' attempts."
+    )).unwrap();
+    static ref CLEANUP_FAILURES: IntCounter = register_int_counter!(opts!(
+        "zincati_rpm_ostree_cleanup_failures_total",
+        "Total number of 'rpm-ostree cleanup' failures."
+    )).unwrap();
+}
+
+/// Remove the rollback deployment, if any.
+pub async fn cleanup_rollback(backend: Backend, timeout: Duration) -> Result<()> {
+    CLEANUP_ATTEMPTS.inc();
+
+    let result = match backend {
+        Backend::Cli => invoke_cli_cleanup(timeout).await,
+        Backend::DBus => {
+            super::command::blocking(move || super::dbus_client::cleanup_rollback(timeout)).await
+        }
+    };
+    if result.is_err() {
+        CLEANUP_FAILURES.inc();
+    }
+
+    result
+}
+
+/// CLI executor for cleaning up the rollback deployment.
+async fn invoke_cli_cleanup(timeout: Duration) -> Result<()> {
+    fail_point!("cleanup_rollback_err", |_| anyhow::bail!(
+        "cleanup_rollback_err"
+    ));
+    fail_point!("cleanup_rollback_ok", |_| Ok(()));
+
+    let mut cmd = tokio::process::Command::new("rpm-ostree");
+    cmd.arg("cleanup")
+        .arg("--rollback")
+        .env("RPMOSTREE_CLIENT_ID", "zincati");
+    let cmd = super::command::transaction_output(&mut cmd, timeout).await?;
+
+    if !cmd.status.success() {
+        anyhow::bail!(
+            "rpm-ostree cleanup failed:\n{}",
+            String::from_utf8_lossy(&cmd.stderr)
+        );
+    }
+
+    Ok(())
+}
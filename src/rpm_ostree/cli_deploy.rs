@@ -1,9 +1,13 @@
-//! Interface to `rpm-ostree deploy --lock-finalization` and
-//! `rpm-ostree deploy --register-driver`.
+//! Interface to `rpm-ostree deploy --lock-finalization`,
+//! `rpm-ostree deploy --download-only` and `rpm-ostree deploy --register-driver`.
 
-use super::Release;
-use anyhow::{bail, Context, Result};
-use prometheus::IntCounter;
+use super::command::blocking;
+use super::dbus_client::{self, Progress};
+use super::{Backend, Release};
+use crate::utils::{update_unit_status, StatusSummary};
+use anyhow::{bail, Result};
+use prometheus::{Gauge, IntCounter};
+use std::time::Duration;
 
 const DRIVER_NAME: &str = "Zincati";
 
@@ -16,35 +20,143 @@ lazy_static::lazy_static! {
         "zincati_rpm_ostree_deploy_failures_total",
         "Total number of 'rpm-ostree deploy' failures."
     )).unwrap();
+    static ref DEPLOY_PROGRESS: Gauge = register_gauge!(opts!(
+        "zincati_deploy_progress_ratio",
+        "Progress ratio (0 to 1) of the current task of the ongoing deployment."
+    )).unwrap();
+    static ref DOWNLOAD_ATTEMPTS: IntCounter = register_int_counter!(opts!(
+        "zincati_rpm_ostree_download_attempts_total",
+        "Total number of 'rpm-ostree deploy --download-only' attempts."
+    )).unwrap();
+    static ref DOWNLOAD_FAILURES: IntCounter = register_int_counter!(opts!(
+        "zincati_rpm_ostree_download_failures_total",
+        "Total number of 'rpm-ostree deploy --download-only' failures."
+    )).unwrap();
 }
 
 /// Deploy an upgrade (by checksum) and leave the new deployment locked.
-pub fn deploy_locked(release: Release, allow_downgrade: bool) -> Result<Release> {
+///
+/// If `cache_only` is set, the upgrade must have already been downloaded
+/// and no network access is performed.
+pub async fn deploy_locked(
+    release: Release,
+    allow_downgrade: bool,
+    cache_only: bool,
+    backend: Backend,
+    timeout: Duration,
+) -> Result<Release> {
     DEPLOY_ATTEMPTS.inc();
+    DEPLOY_PROGRESS.set(0.0);
+
+    let result = match backend {
+        Backend::Cli => {
+            let mode = if cache_only { "--cache-only" } else { "" };
+            let flags = ["--lock-finalization", mode];
+            invoke_cli_deploy(release, allow_downgrade, &flags, timeout).await
+        }
+        Backend::DBus => {
+            let on_progress =
+                progress_reporter("staging", "staging update", release.version.clone());
+            blocking(move || {
+                dbus_client::deploy_locked(
+                    &release,
+                    allow_downgrade,
+                    cache_only,
+                    timeout,
+                    on_progress,
+                )
+                .map(|_| release)
+            })
+            .await
+        }
+    };
+    match result {
+        Ok(_) => DEPLOY_PROGRESS.set(1.0),
+        Err(_) => DEPLOY_FAILURES.inc(),
+    };
 
-    let result = invoke_cli_deploy(release, allow_downgrade);
-    if result.is_err() {
-        DEPLOY_FAILURES.inc();
-    }
+    result
+}
+
+/// Download an upgrade (by checksum) without deploying it.
+pub async fn download_only(
+    release: Release,
+    allow_downgrade: bool,
+    backend: Backend,
+    timeout: Duration,
+) -> Result<Release> {
+    DOWNLOAD_ATTEMPTS.inc();
+    DEPLOY_PROGRESS.set(0.0);
+
+    let result = match backend {
+        Backend::Cli => {
+            invoke_cli_deploy(release, allow_downgrade, &["--download-only"], timeout).await
+        }
+        Backend::DBus => {
+            let on_progress =
+                progress_reporter("downloading", "downloading update", release.version.clone());
+            blocking(move || {
+                dbus_client::download_only(&release, allow_downgrade, timeout, on_progress)
+                    .map(|_| release)
+            })
+            .await
+        }
+    };
+    match result {
+        Ok(_) => DEPLOY_PROGRESS.set(1.0),
+        Err(_) => DOWNLOAD_FAILURES.inc(),
+    };
 
     result
 }
 
+/// Build a callback reporting transaction progress to the service manager
+/// and metrics.
+fn progress_reporter(
+    state: &'static str,
+    phase: &'static str,
+    version: String,
+) -> impl FnMut(Progress) {
+    let mut last_progress = None;
+    move |progress| {
+        // Transactions may repeat the same progress, only report changes.
+        if last_progress.as_ref() == Some(&progress) {
+            return;
+        }
+        DEPLOY_PROGRESS.set(progress_ratio(progress.percentage));
+        update_unit_status(
+            StatusSummary::new(state).target(&version),
+            &format!(
+                "{}: {}; {} ({}%)",
+                phase, version, progress.text, progress.percentage
+            ),
+        );
+        last_progress = Some(progress);
+    }
+}
+
+/// Convert a completion percentage into a ratio.
+fn progress_ratio(percentage: u32) -> f64 {
+    f64::from(percentage.min(100)) / 100.0
+}
+
 /// Register as the update driver.
-pub fn deploy_register_driver() -> Result<()> {
-    invoke_cli_register()?;
+pub async fn deploy_register_driver(timeout: Duration) -> Result<()> {
+    fail_point!("register_driver_ok", |_| Ok(()));
+
+    invoke_cli_register(timeout).await?;
     Ok(())
 }
 
 /// CLI executor for registering driver.
-fn invoke_cli_register() -> Result<()> {
-    let mut cmd = std::process::Command::new("rpm-ostree");
+async fn invoke_cli_register(timeout: Duration) -> Result<()> {
+    let mut cmd = tokio::process::Command::new("rpm-ostree");
     cmd.arg("deploy")
         .arg("")
         .arg(format!("--register-driver={}", DRIVER_NAME))
         .env("RPMOSTREE_CLIENT_ID", "zincati");
 
-    let out = cmd.output().context("failed to run 'rpm-ostree' binary")?;
+    let out = super::command::output(&mut cmd, timeout).await?;
 
     if !out.status.success() {
         bail!(
@@ -56,21 +168,26 @@ fn invoke_cli_register() -> Result<()> {
     Ok(())
 }
 
-/// CLI executor for deploying upgrades.
-fn invoke_cli_deploy(release: Release, allow_downgrade: bool) -> Result<Release> {
+/// CLI executor for deploying upgrades, with additional (possibly empty) flags.
+async fn invoke_cli_deploy(
+    release: Release,
+    allow_downgrade: bool,
+    flags: &[&str],
+    timeout: Duration,
+) -> Result<Release> {
     fail_point!("deploy_locked_err", |_| bail!("deploy_locked_err"));
     fail_point!("deploy_locked_ok", |_| Ok(release.clone()));
 
-    let mut cmd = std::process::Command::new("rpm-ostree");
+    let mut cmd = tokio::process::Command::new("rpm-ostree");
     cmd.arg("deploy")
-        .arg("--lock-finalization")
+        .args(flags.iter().filter(|f| !f.is_empty()))
         .arg(format!("revision={}", release.checksum))
         .env("RPMOSTREE_CLIENT_ID", "zincati");
     if !allow_downgrade {
         cmd.arg("--disallow-downgrade");
     }
 
-    let out = cmd.output().context("failed to run 'rpm-ostree' binary")?;
+    let out = super::command::transaction_output(&mut cmd, timeout).await?;
 
     if !out.status.success() {
         bail!(
@@ -86,6 +203,14 @@ mod tests {
     #[allow(unused_imports)]
     use super::*;
 
+    #[test]
+    fn test_progress_ratio() {
+        assert_eq!(progress_ratio(0), 0.0);
+        assert_eq!(progress_ratio(45), 0.45);
+        assert_eq!(progress_ratio(100), 1.0);
+        assert_eq!(progress_ratio(250), 1.0);
+    }
+
     #[cfg(feature = "failpoints")]
     #[test]
     fn deploy_locked_err() {
@@ -96,8 +221,12 @@ mod tests {
             version: "foo".to_string(),
             checksum: "bar".to_string(),
             age_index: None,
+            advisory: None,
+            wave: None,
         };
-        let result = deploy_locked(release, true);
+        let timeout = Duration::from_secs(60);
+        let deploy = deploy_locked(release, true, false, Backend::Cli, timeout);
+        let result = actix::System::new().block_on(deploy);
         assert!(result.is_err());
         assert!(DEPLOY_ATTEMPTS.get() >= 1);
         assert!(DEPLOY_FAILURES.get() >= 1);
@@ -113,8 +242,12 @@ mod tests {
             version: "foo".to_string(),
             checksum: "bar".to_string(),
             age_index: None,
+            advisory: None,
+            wave: None,
         };
-        let result = deploy_locked(release.clone(), true).unwrap();
+        let timeout = Duration::from_secs(60);
+        let deploy = deploy_locked(release.clone(), true, false, Backend::Cli, timeout);
+        let result = actix::System::new().block_on(deploy).unwrap();
         assert_eq!(result, release);
         assert!(DEPLOY_ATTEMPTS.get() >= 1);
     }
@@ -1,8 +1,9 @@
 //! Interface to `rpm-ostree finalize-deployment`.
 
-use super::Release;
-use anyhow::{Context, Result};
+use super::{Backend, Release};
+use anyhow::Result;
 use prometheus::IntCounter;
+use std::time::Duration;
 
 lazy_static::lazy_static! {
     static ref FINALIZE_ATTEMPTS: IntCounter = register_int_counter!(opts!(
@@ -16,17 +17,52 @@ lazy_static::lazy_static! {
 }
 
 /// Unlock and finalize the new deployment.
-pub fn finalize_deployment(release: Release) -> Result<Release> {
+///
+/// If `allow_unlocked` is set, the deployment is finalized even if it was
+/// not staged in finalization-locked mode.
+pub async fn finalize_deployment(
+    release: Release,
+    allow_unlocked: bool,
+    backend: Backend,
+    timeout: Duration,
+) -> Result<Release> {
     FINALIZE_ATTEMPTS.inc();
-    let cmd = std::process::Command::new("rpm-ostree")
-        .arg("finalize-deployment")
+
+    let result = match backend {
+        Backend::Cli => invoke_cli_finalize(release, allow_unlocked, timeout).await,
+        Backend::DBus => {
+            super::command::blocking(move || {
+                super::dbus_client::finalize_deployment(&release, allow_unlocked, timeout)
+                    .map(|_| release)
+            })
+            .await
+        }
+    };
+    if result.is_err() {
+        FINALIZE_FAILURES.inc();
+    }
+
+    result
+}
+
+/// CLI executor for finalizing deployments.
+async fn invoke_cli_finalize(
+    release: Release,
+    allow_unlocked: bool,
+    timeout: Duration,
+) -> Result<Release> {
+    fail_point!("finalize_deployment_ok", |_| Ok(release.clone()));
+
+    let mut cmd = tokio::process::Command::new("rpm-ostree");
+    cmd.arg("finalize-deployment")
         .arg(&release.checksum)
-        .env("RPMOSTREE_CLIENT_ID", "zincati")
-        .output()
-        .context("failed to run 'rpm-ostree' binary")?;
+        .env("RPMOSTREE_CLIENT_ID", "zincati");
+    if allow_unlocked {
+        cmd.arg("--allow-unlocked");
+    }
+    let cmd = super::command::transaction_output(&mut cmd, timeout).await?;
 
     if !cmd.status.success() {
-        FINALIZE_FAILURES.inc();
         anyhow::bail!(
             "rpm-ostree finalize-deployment failed:\n{}",
             String::from_utf8_lossy(&cmd.stderr)
@@ -0,0 +1,72 @@
+//! Interface to `rpm-ostree rebase --lock-finalization`.
+
+use super::dbus_client::Progress;
+use super::Backend;
+use crate::utils::{update_unit_status, StatusSummary};
+use anyhow::Result;
+use prometheus::IntCounter;
+use std::time::Duration;
+
+lazy_static::lazy_static! {
+    static ref REBASE_ATTEMPTS: IntCounter = register_int_counter!(opts!(
+        "zincati_rpm_ostree_rebase_attempts_total",
+        "Total number of 'rpm-ostree rebase' attempts."
+    )).unwrap();
+    static ref REBASE_FAILURES: IntCounter = register_int_counter!(opts!(
+        "zincati_rpm_ostree_rebase_failures_total",
+        "Total number of 'rpm-ostree rebase' failures."
+    )).unwrap();
+}
+
+/// Rebase onto another refspec and leave the new deployment locked.
+///
+/// Any previously staged deployment is replaced.
+pub async fn rebase_locked(refspec: String, backend: Backend, timeout: Duration) -> Result<()> {
+    REBASE_ATTEMPTS.inc();
+
+    let result = match backend {
+        Backend::Cli => invoke_cli_rebase(&refspec, timeout).await,
+        Backend::DBus => {
+            super::command::blocking(move || {
+                let on_progress = |progress: Progress| {
+                    update_unit_status(
+                        StatusSummary::new("staging").reason("stream-switch"),
+                        &format!(
+                            "rebasing to {}: {} ({}%)",
+                            refspec, progress.text, progress.percentage
+                        ),
+                    )
+                };
+                super::dbus_client::rebase_locked(&refspec, timeout, on_progress)
+            })
+            .await
+        }
+    };
+    if result.is_err() {
+        REBASE_FAILURES.inc();
+    }
+
+    result
+}
+
+/// CLI executor for rebasing.
+async fn invoke_cli_rebase(refspec: &str, timeout: Duration) -> Result<()> {
+    fail_point!("rebase_locked_err", |_| anyhow::bail!("rebase_locked_err"));
+    fail_point!("rebase_locked_ok", |_| Ok(()));
+
+    let mut cmd = tokio::process::Command::new("rpm-ostree");
+    cmd.arg("rebase")
+        .arg("--lock-finalization")
+        .arg(refspec)
+        .env("RPMOSTREE_CLIENT_ID", "zincati");
+    let cmd = super::command::transaction_output(&mut cmd, timeout).await?;
+
+    if !cmd.status.success() {
+        anyhow::bail!(
+            "rpm-ostree rebase failed:\n{}",
+            String::from_utf8_lossy(&cmd.stderr)
+        );
+    }
+
+    Ok(())
+}
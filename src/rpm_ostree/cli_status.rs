@@ -1,20 +1,27 @@
 //! Interface to `rpm-ostree status --json`.
 
-use super::actor::{RpmOstreeClient, StatusCache};
-use super::Release;
+use super::actor::{timed, RpmOstreeClient, SharedStatusCache, StatusCache};
+use super::{Backend, Release, Timeouts};
+use actix::prelude::*;
 use anyhow::{anyhow, ensure, Context, Result};
 use filetime::FileTime;
 use log::trace;
-use prometheus::IntCounter;
+use prometheus::{IntCounter, IntGauge};
 use serde::Deserialize;
 use std::collections::BTreeSet;
 use std::fs;
-use std::rc::Rc;
+use std::future::Future;
+use std::process::Output;
+use std::sync::Arc;
+use std::time::Duration;
 
 /// Path to local OSTree deployments. We use its mtime to check for modifications (e.g. new deployments)
 /// to local deployments that might warrant querying `rpm-ostree status` again to update our knowledge
 /// of the current state of deployments.
-const OSTREE_DEPLS_PATH: &str = "/ostree/deploy";
+pub(super) const OSTREE_DEPLS_PATH: &str = "/ostree/deploy";
+
+/// Base architectures which are known to be shipped as OS images.
+pub static KNOWN_BASEARCHES: &[&str] = &["aarch64", "ppc64le", "s390x", "x86_64"];
 
 lazy_static::lazy_static! {
     static ref STATUS_CACHE_ATTEMPTS: IntCounter = register_int_counter!(opts!(
@@ -35,6 +42,18 @@ lazy_static::lazy_static! {
         "zincati_rpm_ostree_status_failures_total",
         "Total number of 'rpm-ostree status' failures."
     )).unwrap();
+    static ref BOOTED_LAYERED_PACKAGES: IntGauge = register_int_gauge!(opts!(
+        "zincati_rpm_ostree_booted_layered_packages",
+        "Number of packages layered on the booted deployment."
+    )).unwrap();
+    static ref BOOTED_OVERRIDES: IntGauge = register_int_gauge!(opts!(
+        "zincati_rpm_ostree_booted_overrides",
+        "Number of base package overrides (removals and replacements) on the booted deployment."
+    )).unwrap();
+    static ref STAGED_FIXED_CVES: IntGauge = register_int_gauge!(opts!(
+        "zincati_rpm_ostree_staged_fixed_cves",
+        "Number of CVEs fixed by the staged deployment, according to its commit metadata."
+    )).unwrap();
 }
 
 /// JSON output from `rpm-ostree status --json`
@@ -55,7 +74,54 @@ pub struct DeploymentJson {
     // NOTE(lucab): missing field means "not staged".
     #[serde(default)]
     staged: bool,
+    /// Commit timestamp.
+    #[serde(default)]
+    timestamp: i64,
     version: String,
+    /// Layered packages, from repositories.
+    #[serde(default)]
+    requested_packages: Vec<String>,
+    /// Layered packages, from local RPM files.
+    #[serde(default)]
+    requested_local_packages: Vec<String>,
+    /// Base packages removed.
+    #[serde(default)]
+    requested_base_removals: Vec<String>,
+    /// Base packages replaced by local RPM files.
+    #[serde(default)]
+    requested_base_local_replacements: Vec<String>,
+}
+
+/// Local changes to the base image of a deployment.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Layering {
+    /// Layered packages.
+    pub packages: Vec<String>,
+    /// Overridden base packages (removed or replaced).
+    pub overrides: Vec<String>,
+}
+
+impl Layering {
+    /// Whether the base image is used as is.
+    pub fn is_empty(&self) -> bool {
+        self.packages.is_empty() && self.overrides.is_empty()
+    }
+
+    /// Return a human-readable description of local changes.
+    pub fn describe(&self) -> String {
+        let list = |items: &[String]| {
+            if items.is_empty() {
+                "none".to_string()
+            } else {
+                items.join(", ")
+            }
+        };
+        format!(
+            "layered packages: {}; overrides: {}",
+            list(&self.packages),
+            list(&self.overrides)
+        )
+    }
 }
 
 /// Metadata from base commit (only fields relevant to zincati).
@@ -65,6 +131,68 @@ struct BaseCommitMetaJson {
     basearch: String,
     #[serde(rename = "fedora-coreos.stream")]
     stream: String,
+    /// Security advisories, kept raw as they are optional and informational.
+    #[serde(rename = "fedora-coreos.advisories", default)]
+    advisories: Option<serde_json::Value>,
+}
+
+/// Security advisory entry from base commit metadata.
+#[derive(Clone, Debug, Deserialize)]
+struct AdvisoryJson {
+    id: String,
+    #[serde(default)]
+    cves: Vec<String>,
+}
+
+/// Security fixes shipped by a deployment, from its commit metadata.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SecurityFixes {
+    /// Advisory IDs.
+    pub advisories: Vec<String>,
+    /// Fixed CVE IDs, without duplicates.
+    pub cves: Vec<String>,
+}
+
+impl SecurityFixes {
+    /// Parse security fixes from raw advisories metadata.
+    ///
+    /// Invalid entries are logged and ignored instead of failing the whole
+    /// status query.
+    fn from_metadata(version: &str, metadata: Option<&serde_json::Value>) -> Self {
+        let entries = match metadata {
+            Some(serde_json::Value::Array(entries)) => entries.as_slice(),
+            Some(_) => {
+                log::warn!(
+                    "ignoring invalid security advisories for release {}",
+                    version
+                );
+                &[]
+            }
+            None => &[],
+        };
+
+        let mut fixes = Self::default();
+        for entry in entries {
+            let advisory: AdvisoryJson = match serde_json::from_value(entry.clone()) {
+                Ok(a) => a,
+                Err(e) => {
+                    log::warn!(
+                        "ignoring invalid security advisory for release {}: {}",
+                        version,
+                        e
+                    );
+                    continue;
+                }
+            };
+            fixes.advisories.push(advisory.id);
+            for cve in advisory.cves {
+                if !fixes.cves.contains(&cve) {
+                    fixes.cves.push(cve);
+                }
+            }
+        }
+        fixes
+    }
 }
 
 impl DeploymentJson {
@@ -74,6 +202,8 @@ impl DeploymentJson {
             checksum: self.base_revision(),
             version: self.version,
             age_index: None,
+            advisory: None,
+            wave: None,
         }
     }
 
@@ -88,7 +218,29 @@ impl DeploymentJson {
 /// Parse base architecture for booted deployment from status object.
 pub fn parse_basearch(status: &StatusJson) -> Result<String> {
     let json = booted_json(status)?;
-    Ok(json.base_metadata.basearch)
+    let basearch = json.base_metadata.basearch;
+    check_basearch(&basearch)?;
+    Ok(basearch)
+}
+
+/// Sanity-check a base architecture label.
+///
+/// Labels are transmitted to external backends (e.g. Cincinnati) and
+/// substituted in URL templates, thus they must be plain tokens. Unknown
+/// architectures are accepted, but reported.
+fn check_basearch(basearch: &str) -> Result<()> {
+    ensure!(!basearch.is_empty(), "empty basearch");
+    ensure!(
+        basearch
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_'),
+        "invalid basearch '{}'",
+        basearch
+    );
+    if !KNOWN_BASEARCHES.contains(&basearch) {
+        log::warn!("unknown base architecture '{}'", basearch);
+    }
+    Ok(())
 }
 
 /// Parse the booted deployment from status object.
@@ -97,6 +249,40 @@ pub fn parse_booted(status: &StatusJson) -> Result<Release> {
     Ok(json.into_release())
 }
 
+/// Parse local changes to the booted deployment from status object.
+pub fn parse_booted_layering(status: &StatusJson) -> Result<Layering> {
+    let json = booted_json(status)?;
+    let packages = json
+        .requested_packages
+        .into_iter()
+        .chain(json.requested_local_packages)
+        .collect();
+    let overrides = json
+        .requested_base_removals
+        .into_iter()
+        .chain(json.requested_base_local_replacements)
+        .collect();
+    Ok(Layering {
+        packages,
+        overrides,
+    })
+}
+
+/// Return local changes to the booted deployment, and refresh related metrics.
+pub fn booted_layering(client: &RpmOstreeClient) -> ResponseFuture<Result<Layering>> {
+    fail_point!("booted_layering_ok", |_| Box::pin(futures::future::ok(
+        Layering::default()
+    )));
+
+    let status = status_json(client);
+    Box::pin(async move {
+        let layering = parse_booted_layering(&*status.await?)?;
+        BOOTED_LAYERED_PACKAGES.set(layering.packages.len() as i64);
+        BOOTED_OVERRIDES.set(layering.overrides.len() as i64);
+        Ok(layering)
+    })
+}
+
 /// Parse updates stream for booted deployment from status object.
 pub fn parse_updates_stream(status: &StatusJson) -> Result<String> {
     let json = booted_json(status)?;
@@ -105,7 +291,7 @@ pub fn parse_updates_stream(status: &StatusJson) -> Result<String> {
 }
 
 /// Parse local deployments from a status object.
-fn parse_local_deployments(status: &StatusJson, omit_staged: bool) -> BTreeSet<Release> {
+pub fn parse_local_deployments(status: &StatusJson, omit_staged: bool) -> BTreeSet<Release> {
     let mut deployments = BTreeSet::<Release>::new();
     for entry in &status.deployments {
         if omit_staged && entry.staged {
@@ -120,13 +306,122 @@ fn parse_local_deployments(status: &StatusJson, omit_staged: bool) -> BTreeSet<R
 
 /// Return local deployments, using client's cache if possible.
 pub fn local_deployments(
-    client: &mut RpmOstreeClient,
+    client: &RpmOstreeClient,
     omit_staged: bool,
-) -> Result<BTreeSet<Release>> {
-    let status = status_json(client)?;
-    let local_depls = parse_local_deployments(&status, omit_staged);
+) -> ResponseFuture<Result<BTreeSet<Release>>> {
+    fail_point!("local_deployments_ok", |_| Box::pin(futures::future::ok(
+        BTreeSet::new()
+    )));
+
+    let status = status_json(client);
+    Box::pin(async move {
+        let local_depls = parse_local_deployments(&*status.await?, omit_staged);
+        Ok(local_depls)
+    })
+}
 
-    Ok(local_depls)
+/// Return whether the staged deployment (if any) is older than the booted one.
+///
+/// This uses the same commit-timestamp ordering which rpm-ostree applies to
+/// reject downgrades.
+pub fn staged_is_downgrade(client: &RpmOstreeClient) -> ResponseFuture<Result<bool>> {
+    fail_point!("staged_is_downgrade_ok", |_| Box::pin(futures::future::ok(
+        false
+    )));
+
+    let status = status_json(client);
+    Box::pin(async move { parse_staged_downgrade(&*status.await?) })
+}
+
+/// Parse whether the staged deployment is a downgrade, from a status object.
+pub(super) fn parse_staged_downgrade(status: &StatusJson) -> Result<bool> {
+    let age = parse_staged_commit_age(status)?;
+    Ok(age.map(|secs| secs > 0).unwrap_or(false))
+}
+
+/// Return how much older (in seconds) the staged commit is than the booted
+/// one, if a deployment is staged.
+///
+/// This is negative if the staged commit is newer.
+pub fn staged_commit_age(client: &RpmOstreeClient) -> ResponseFuture<Result<Option<i64>>> {
+    let status = status_json(client);
+    Box::pin(async move { parse_staged_commit_age(&*status.await?) })
+}
+
+/// Parse the age of the staged commit relative to the booted one, from a status object.
+pub(super) fn parse_staged_commit_age(status: &StatusJson) -> Result<Option<i64>> {
+    let booted = booted_json(status)?;
+    let staged = match status.deployments.iter().find(|d| d.staged) {
+        Some(depl) => depl,
+        None => return Ok(None),
+    };
+    Ok(Some(booted.timestamp - staged.timestamp))
+}
+
+/// Return security fixes shipped by the staged deployment (if any), and refresh related metrics.
+pub fn staged_security_fixes(
+    client: &RpmOstreeClient,
+) -> ResponseFuture<Result<Option<SecurityFixes>>> {
+    fail_point!("staged_security_fixes_none", |_| Box::pin(
+        futures::future::ok(None)
+    ));
+
+    let status = status_json(client);
+    Box::pin(async move {
+        let fixes = parse_staged_security_fixes(&*status.await?);
+        let count = fixes.as_ref().map(|f| f.cves.len()).unwrap_or(0);
+        STAGED_FIXED_CVES.set(count as i64);
+        Ok(fixes)
+    })
+}
+
+/// Parse security fixes shipped by the staged deployment (if any), from a status object.
+pub(super) fn parse_staged_security_fixes(status: &StatusJson) -> Option<SecurityFixes> {
+    let staged = status.deployments.iter().find(|d| d.staged)?;
+    let fixes =
+        SecurityFixes::from_metadata(&staged.version, staged.base_metadata.advisories.as_ref());
+    Some(fixes)
+}
+
+/// Return the staged deployment.
+pub fn staged_deployment(client: &RpmOstreeClient) -> ResponseFuture<Result<Release>> {
+    let status = status_json(client);
+    Box::pin(async move { parse_staged(&*status.await?) })
+}
+
+/// Return the staged deployment, if any.
+pub fn query_staged(client: &RpmOstreeClient) -> ResponseFuture<Result<Option<Release>>> {
+    fail_point!("staged_deployment_found", |version: Option<String>| {
+        let release = Release {
+            version: version.unwrap_or_default(),
+            checksum: "sha-staged-mock".to_string(),
+            age_index: None,
+            advisory: None,
+            wave: None,
+        };
+        Box::pin(futures::future::ok(Some(release)))
+    });
+    fail_point!("staged_deployment_none", |_| Box::pin(futures::future::ok(
+        None
+    )));
+
+    let status = status_json(client);
+    Box::pin(async move { Ok(find_staged(&*status.await?)) })
+}
+
+/// Parse the staged deployment from a status object.
+fn parse_staged(status: &StatusJson) -> Result<Release> {
+    find_staged(status).ok_or_else(|| anyhow!("no staged deployment found"))
+}
+
+/// Find the staged deployment (if any) in a status object.
+pub(super) fn find_staged(status: &StatusJson) -> Option<Release> {
+    status
+        .deployments
+        .iter()
+        .find(|d| d.staged)
+        .cloned()
+        .map(DeploymentJson::into_release)
 }
 
 /// Return JSON object for booted deployment.
@@ -144,35 +439,94 @@ fn booted_json(status: &StatusJson) -> Result<DeploymentJson> {
     Ok(booted)
 }
 
-/// Ensure our status cache is up to date; if empty or out of date, run `rpm-ostree status` to populate it.
-fn status_json(client: &mut RpmOstreeClient) -> Result<Rc<StatusJson>> {
+/// Return cached status if it is up to date, without querying rpm-ostree.
+///
+/// Misses are not recorded, as the query is then retried through the client.
+pub(super) fn cached_status(cache: &SharedStatusCache) -> Option<Arc<StatusJson>> {
+    let status = cache.get(cache_key(cache).ok()?)?;
     STATUS_CACHE_ATTEMPTS.inc();
-    let ostree_depls_data = fs::metadata(OSTREE_DEPLS_PATH)
-        .with_context(|| format!("failed to query directory {}", OSTREE_DEPLS_PATH))?;
-    let ostree_depls_data_mtime = FileTime::from_last_modification_time(&ostree_depls_data);
+    trace!("serving status from up to date cache");
+    Some(status)
+}
 
-    if let Some(cache) = &client.status_cache {
-        if cache.mtime == ostree_depls_data_mtime {
+/// Ensure our status cache is up to date; if empty or out of date, query rpm-ostree to populate it.
+///
+/// The returned future does nothing until polled.
+pub(super) fn status_json(
+    client: &RpmOstreeClient,
+) -> impl Future<Output = Result<Arc<StatusJson>>> + 'static {
+    let cache = client.status_cache.clone();
+    let backend = client.backend;
+    let timeout = client.timeouts.status;
+    async move {
+        STATUS_CACHE_ATTEMPTS.inc();
+        let ostree_depls_data_mtime = cache_key(&cache)?;
+        if let Some(status) = cache.get(ostree_depls_data_mtime) {
             trace!("status cache is up to date");
-            return Ok(cache.status.clone());
+            return Ok(status);
         }
+
+        STATUS_CACHE_MISSES.inc();
+        trace!("cache stale, invoking rpm-ostree to retrieve local deployments");
+        let generation = cache.generation();
+        let status = Arc::new(timed("status", query_status(backend, timeout)).await?);
+        cache.store(
+            generation,
+            StatusCache {
+                status: Arc::clone(&status),
+                mtime: ostree_depls_data_mtime,
+            },
+        );
+        Ok(status)
     }
+}
 
-    STATUS_CACHE_MISSES.inc();
-    trace!("cache stale, invoking rpm-ostree to retrieve local deployments");
-    let status = Rc::new(invoke_cli_status(false)?);
-    client.status_cache = Some(StatusCache {
-        status: Rc::clone(&status),
-        mtime: ostree_depls_data_mtime,
-    });
+/// Return the modification time of local deployments.
+/// Return the modification time of local deployments, unless they are
+/// watched for changes.
+fn cache_key(cache: &SharedStatusCache) -> Result<Option<FileTime>> {
+    if cache.is_watched() {
+        return Ok(None);
+    }
+    deployments_mtime().map(Some)
+}
 
-    Ok(status)
+/// Return the modification time of local deployments.
+fn deployments_mtime() -> Result<FileTime> {
+    let ostree_depls_data = fs::metadata(OSTREE_DEPLS_PATH)
+        .with_context(|| format!("failed to query directory {}", OSTREE_DEPLS_PATH))?;
+    Ok(FileTime::from_last_modification_time(&ostree_depls_data))
 }
 
-/// CLI executor for `rpm-ostree status --json`.
+/// Query deployments status from rpm-ostree.
+async fn query_status(backend: Backend, timeout: Duration) -> Result<StatusJson> {
+    match backend {
+        Backend::Cli => cli_status(timeout).await,
+        Backend::DBus => invoke_dbus_status().await,
+    }
+}
+
+/// CLI executor for `rpm-ostree status --json`, with the default timeout.
+///
+/// This blocks the calling thread, and it does not go through the client.
 pub fn invoke_cli_status(booted_only: bool) -> Result<StatusJson> {
+    let mut cmd = status_command(booted_only);
+    let timeout = Timeouts::default().status;
+    RPM_OSTREE_STATUS_ATTEMPTS.inc();
+    let cmdrun = super::command::blocking_output(&mut cmd, timeout);
+    parse_status_output(cmdrun)
+}
+
+/// CLI executor for `rpm-ostree status --json`.
+async fn cli_status(timeout: Duration) -> Result<StatusJson> {
+    let mut cmd = tokio::process::Command::from(status_command(false));
     RPM_OSTREE_STATUS_ATTEMPTS.inc();
+    let cmdrun = super::command::output(&mut cmd, timeout).await;
+    parse_status_output(cmdrun)
+}
 
+/// Build the `rpm-ostree status --json` command.
+fn status_command(booted_only: bool) -> std::process::Command {
     let mut cmd = std::process::Command::new("rpm-ostree");
     cmd.arg("status").env("RPMOSTREE_CLIENT_ID", "zincati");
 
@@ -181,10 +535,15 @@ pub fn invoke_cli_status(booted_only: bool) -> Result<StatusJson> {
         cmd.arg("--booted");
     }
 
-    let cmdrun = cmd
-        .arg("--json")
-        .output()
-        .context("failed to run 'rpm-ostree' binary")?;
+    cmd.arg("--json");
+    cmd
+}
+
+/// Parse the result of `rpm-ostree status --json`.
+fn parse_status_output(cmdrun: Result<Output>) -> Result<StatusJson> {
+    let cmdrun = cmdrun.inspect_err(|_| {
+        RPM_OSTREE_STATUS_FAILURES.inc();
+    })?;
 
     if !cmdrun.status.success() {
         RPM_OSTREE_STATUS_FAILURES.inc();
@@ -197,6 +556,17 @@ pub fn invoke_cli_status(booted_only: bool) -> Result<StatusJson> {
     Ok(status)
 }
 
+/// D-Bus executor for deployments status.
+async fn invoke_dbus_status() -> Result<StatusJson> {
+    RPM_OSTREE_STATUS_ATTEMPTS.inc();
+
+    let status = super::command::blocking(super::dbus_client::status).await;
+    if status.is_err() {
+        RPM_OSTREE_STATUS_FAILURES.inc();
+    }
+    status
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -227,6 +597,75 @@ mod tests {
         }
     }
 
+    #[test]
+    fn mock_staged() {
+        let status = mock_status("tests/fixtures/rpm-ostree-status.json").unwrap();
+        parse_staged(&status).unwrap_err();
+        assert!(find_staged(&status).is_none());
+
+        let status = mock_status("tests/fixtures/rpm-ostree-staged.json").unwrap();
+        let staged = parse_staged(&status).unwrap();
+        assert_eq!(staged.version, "31.20200517.3.0");
+    }
+
+    #[test]
+    fn mock_staged_downgrade() {
+        let status = mock_status("tests/fixtures/rpm-ostree-status.json").unwrap();
+        assert!(!parse_staged_downgrade(&status).unwrap());
+        assert_eq!(parse_staged_commit_age(&status).unwrap(), None);
+
+        let mut status = mock_status("tests/fixtures/rpm-ostree-staged.json").unwrap();
+        assert!(!parse_staged_downgrade(&status).unwrap());
+
+        for depl in status.deployments.iter_mut().filter(|d| d.staged) {
+            depl.timestamp = 1_500_000_000;
+        }
+        assert!(parse_staged_downgrade(&status).unwrap());
+        assert!(parse_staged_commit_age(&status).unwrap().unwrap() > 0);
+    }
+
+    #[test]
+    fn mock_booted_layering() {
+        let mut status = mock_status("tests/fixtures/rpm-ostree-status.json").unwrap();
+        let layering = parse_booted_layering(&status).unwrap();
+        assert!(layering.is_empty());
+
+        for depl in status.deployments.iter_mut().filter(|d| d.booted) {
+            depl.requested_packages = vec!["htop".to_string()];
+            depl.requested_base_removals = vec!["nano".to_string()];
+        }
+        let layering = parse_booted_layering(&status).unwrap();
+        assert!(!layering.is_empty());
+        assert_eq!(
+            layering.describe(),
+            "layered packages: htop; overrides: nano"
+        );
+    }
+
+    #[test]
+    fn mock_staged_security_fixes() {
+        let status = mock_status("tests/fixtures/rpm-ostree-status.json").unwrap();
+        assert_eq!(parse_staged_security_fixes(&status), None);
+
+        let mut status = mock_status("tests/fixtures/rpm-ostree-staged.json").unwrap();
+        let fixes = parse_staged_security_fixes(&status).unwrap();
+        assert_eq!(fixes.advisories, vec!["FEDORA-2020-0b4d3c0b1e"]);
+        assert_eq!(fixes.cves, vec!["CVE-2020-10713", "CVE-2020-14308"]);
+
+        // Missing or invalid metadata means no known fixes.
+        for value in &[None, Some(serde_json::json!("CVE-2020-10713"))] {
+            for depl in status.deployments.iter_mut().filter(|d| d.staged) {
+                depl.base_metadata.advisories = value.clone();
+            }
+            let fixes = parse_staged_security_fixes(&status).unwrap();
+            assert_eq!(fixes, SecurityFixes::default());
+        }
+        let value = serde_json::json!([{ "cves": ["CVE-2020-10713"] }, { "id": "FEDORA-X" }]);
+        let fixes = SecurityFixes::from_metadata("31.20200517.3.0", Some(&value));
+        assert_eq!(fixes.advisories, vec!["FEDORA-X"]);
+        assert!(fixes.cves.is_empty());
+    }
+
     #[test]
     fn mock_booted_basearch() {
         let status = mock_status("tests/fixtures/rpm-ostree-status.json").unwrap();
@@ -234,6 +673,28 @@ mod tests {
         assert_eq!(booted.base_metadata.basearch, "x86_64");
     }
 
+    #[test]
+    fn basearch_sanity() {
+        let cases = vec![
+            ("aarch64", true),
+            ("ppc64le", true),
+            ("s390x", true),
+            ("x86_64", true),
+            // Unknown architectures are only reported.
+            ("riscv64", true),
+            ("", false),
+            ("x86_64/foo", false),
+            ("x86 64", false),
+            ("${basearch}", false),
+        ];
+        for (basearch, valid) in cases {
+            assert_eq!(check_basearch(basearch).is_ok(), valid, "{}", basearch);
+        }
+        for basearch in KNOWN_BASEARCHES {
+            check_basearch(basearch).unwrap();
+        }
+    }
+
     #[test]
     fn mock_booted_updates_stream() {
         let status = mock_status("tests/fixtures/rpm-ostree-status.json").unwrap();
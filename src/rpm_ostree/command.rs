@@ -0,0 +1,181 @@
+//! Running `rpm-ostree` commands with a timeout.
+//!
+//! Commands run asynchronously, so that the client keeps serving other
+//! requests (e.g. status queries) during long transactions. A stuck
+//! `rpm-ostree` process would otherwise hold its operation forever: on
+//! timeout the process is killed, and for transactions the daemon-side
+//! transaction is cancelled as well.
+
+use anyhow::{Context, Result};
+use futures::future::{self, Either};
+use prometheus::IntCounter;
+use std::io::Read;
+use std::process::{Command, Output, Stdio};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+
+lazy_static::lazy_static! {
+    static ref TIMEOUTS: IntCounter = register_int_counter!(opts!(
+        "zincati_rpm_ostree_timeouts_total",
+        "Total number of rpm-ostree operations aborted after timing out."
+    )).unwrap();
+}
+
+/// Polling interval while waiting for a command (in milliseconds).
+const POLL_INTERVAL_MILLIS: u64 = 100;
+
+/// Error for rpm-ostree operations which did not complete in time.
+#[derive(Debug, Error)]
+#[error("rpm-ostree operation timed out after {} seconds", .0.as_secs())]
+pub struct TimedOut(pub Duration);
+
+/// Return whether `err` was caused by an operation timing out.
+pub(super) fn is_timeout(err: &anyhow::Error) -> bool {
+    err.chain().any(|cause| cause.is::<TimedOut>())
+}
+
+/// Record an operation timing out, returning the corresponding error.
+pub(super) fn timed_out(timeout: Duration) -> anyhow::Error {
+    TIMEOUTS.inc();
+    TimedOut(timeout).into()
+}
+
+/// Run a command to completion and collect its output, killing it after `timeout`.
+pub(super) async fn output(cmd: &mut tokio::process::Command, timeout: Duration) -> Result<Output> {
+    let run = cmd.stdin(Stdio::null()).kill_on_drop(true).output();
+    let expiry = actix::clock::sleep(timeout);
+    match future::select(Box::pin(run), Box::pin(expiry)).await {
+        Either::Left((res, _)) => res.context("failed to run 'rpm-ostree' binary"),
+        // Dropping the pending command kills it.
+        Either::Right(_) => Err(timed_out(timeout)),
+    }
+}
+
+/// Run a transaction command, like `output`.
+///
+/// On timeout, the daemon-side transaction is cancelled too, as it may
+/// outlive the killed client process.
+pub(super) async fn transaction_output(
+    cmd: &mut tokio::process::Command,
+    timeout: Duration,
+) -> Result<Output> {
+    let result = output(cmd, timeout).await;
+    if let Err(e) = &result {
+        if is_timeout(e) {
+            if let Err(e) = blocking(super::cli_cancel::cancel_transaction).await {
+                log::warn!("{:#}", e);
+            }
+        }
+    }
+    result
+}
+
+/// Run a blocking operation (e.g. a D-Bus transaction) on a dedicated thread.
+pub(super) async fn blocking<T, F>(operation: F) -> Result<T>
+where
+    F: FnOnce() -> Result<T> + Send + 'static,
+    T: Send + 'static,
+{
+    tokio::task::spawn_blocking(operation)
+        .await
+        .unwrap_or_else(|e| Err(e.into()))
+}
+
+/// Run a command synchronously, like `output`.
+///
+/// This blocks the calling thread, thus it is only meant for callers
+/// outside of the client (e.g. one-off CLI commands).
+pub(super) fn blocking_output(cmd: &mut Command, timeout: Duration) -> Result<Output> {
+    let mut child = cmd
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("failed to run 'rpm-ostree' binary")?;
+    // Drain outputs concurrently, so that the child never blocks on a full pipe.
+    let stdout = drain(child.stdout.take());
+    let stderr = drain(child.stderr.take());
+
+    let deadline = Instant::now() + timeout;
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if Instant::now() >= deadline {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(timed_out(timeout));
+        }
+        std::thread::sleep(Duration::from_millis(POLL_INTERVAL_MILLIS));
+    };
+
+    let output = Output {
+        status,
+        stdout: collect(stdout),
+        stderr: collect(stderr),
+    };
+    Ok(output)
+}
+
+/// Read a child pipe to its end, on a dedicated thread.
+fn drain<R: Read + Send + 'static>(pipe: Option<R>) -> Option<JoinHandle<Vec<u8>>> {
+    pipe.map(|mut pipe| {
+        std::thread::spawn(move || {
+            let mut content = vec![];
+            let _ = pipe.read_to_end(&mut content);
+            content
+        })
+    })
+}
+
+/// Collect the content read from a child pipe.
+fn collect(reader: Option<JoinHandle<Vec<u8>>>) -> Vec<u8> {
+    reader.and_then(|r| r.join().ok()).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn output_completed() {
+        let sys = actix::System::new();
+        let out = sys.block_on(async {
+            let mut cmd = tokio::process::Command::new("/bin/sh");
+            cmd.arg("-c").arg("echo out; echo err >&2; exit 3");
+            output(&mut cmd, Duration::from_secs(10)).await.unwrap()
+        });
+        assert_eq!(out.status.code(), Some(3));
+        assert_eq!(out.stdout, b"out\n");
+        assert_eq!(out.stderr, b"err\n");
+    }
+
+    #[test]
+    fn output_timed_out() {
+        let sys = actix::System::new();
+        let start = Instant::now();
+        let err = sys.block_on(async {
+            let mut cmd = tokio::process::Command::new("/bin/sleep");
+            cmd.arg("10");
+            output(&mut cmd, Duration::from_millis(200))
+                .await
+                .unwrap_err()
+        });
+        assert!(is_timeout(&err), "{:#}", err);
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        let wrapped = err.context("failed to stage");
+        assert!(is_timeout(&wrapped));
+    }
+
+    #[test]
+    fn blocking_output_timed_out() {
+        let mut cmd = Command::new("/bin/sleep");
+        cmd.arg("10");
+        let start = Instant::now();
+        let err = blocking_output(&mut cmd, Duration::from_millis(200)).unwrap_err();
+        assert!(is_timeout(&err), "{:#}", err);
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+}
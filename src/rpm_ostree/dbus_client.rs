@@ -0,0 +1,407 @@
+//! Interface to the rpm-ostree D-Bus API (`org.projectatomic.rpmostree1`).
+//!
+//! This is an alternative to shelling out to the `rpm-ostree` CLI. Deployment
+//! details are read as structured D-Bus values, and long-running operations
+//! run as rpm-ostree transactions, which are monitored for progress over a
+//! peer-to-peer connection.
+
+use super::cli_status::StatusJson;
+use super::Release;
+use anyhow::{bail, Context, Result};
+use fn_error_context::context;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::Arc;
+use std::time::Duration;
+use zbus::dbus_proxy;
+use zvariant::{OwnedObjectPath, OwnedValue, Value};
+
+/// Well-known name of the rpm-ostree daemon.
+static RPM_OSTREE_SERVICE: &str = "org.projectatomic.rpmostree1";
+
+/// Interface for rpm-ostree transactions.
+static TRANSACTION_INTERFACE: &str = "org.projectatomic.rpmostree1.Transaction";
+
+/// Client ID, reported to rpm-ostree.
+static CLIENT_ID: &str = "zincati";
+
+#[dbus_proxy(
+    interface = "org.projectatomic.rpmostree1.Sysroot",
+    default_service = "org.projectatomic.rpmostree1",
+    default_path = "/org/projectatomic/rpmostree1/Sysroot"
+)]
+trait Sysroot {
+    /// RegisterClient method
+    fn register_client(&self, options: HashMap<&str, Value>) -> zbus::Result<()>;
+
+    /// UnregisterClient method
+    fn unregister_client(&self, options: HashMap<&str, Value>) -> zbus::Result<()>;
+
+    /// Booted property
+    #[dbus_proxy(property)]
+    fn booted(&self) -> zbus::Result<OwnedObjectPath>;
+
+    /// Deployments property
+    #[dbus_proxy(property)]
+    fn deployments(&self) -> zbus::Result<OwnedValue>;
+}
+
+#[dbus_proxy(
+    interface = "org.projectatomic.rpmostree1.OS",
+    default_service = "org.projectatomic.rpmostree1"
+)]
+trait OS {
+    /// Deploy method
+    fn deploy(&self, revision: &str, options: HashMap<&str, Value>) -> zbus::Result<String>;
+
+    /// Cleanup method
+    fn cleanup(&self, elements: &[&str]) -> zbus::Result<String>;
+
+    /// FinalizeDeployment method
+    fn finalize_deployment(&self, options: HashMap<&str, Value>) -> zbus::Result<String>;
+
+    /// Rebase method
+    fn rebase(
+        &self,
+        options: HashMap<&str, Value>,
+        refspec: &str,
+        packages: &[&str],
+    ) -> zbus::Result<String>;
+}
+
+/// Progress of a running transaction.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Progress {
+    /// Human-friendly description of the current task.
+    pub text: String,
+    /// Completion percentage of the current task.
+    pub percentage: u32,
+}
+
+/// A client session with the rpm-ostree daemon.
+///
+/// The client is registered with the daemon for the whole session, so that
+/// it is properly accounted for (e.g. in `rpm-ostree status`).
+struct Session<'c> {
+    connection: &'c zbus::Connection,
+    sysroot: SysrootProxy<'c>,
+}
+
+impl<'c> Session<'c> {
+    /// Open a session, registering as a client.
+    fn open(connection: &'c zbus::Connection) -> Result<Self> {
+        let sysroot = SysrootProxy::new(connection)?;
+        let mut options = HashMap::new();
+        options.insert("id", Value::from(CLIENT_ID));
+        sysroot
+            .register_client(options)
+            .context("failed to register as rpm-ostree client")?;
+        Ok(Self {
+            connection,
+            sysroot,
+        })
+    }
+
+    /// Return a proxy to the OS object for the booted deployment.
+    fn booted_os(&self) -> Result<OSProxy<'static>> {
+        let path = self.sysroot.booted()?;
+        let os = OSProxy::new_for_owned(
+            self.connection.clone(),
+            RPM_OSTREE_SERVICE.to_string(),
+            path.as_str().to_string(),
+        )?;
+        Ok(os)
+    }
+}
+
+impl Drop for Session<'_> {
+    fn drop(&mut self) {
+        if let Err(e) = self.sysroot.unregister_client(HashMap::new()) {
+            log::warn!("failed to unregister as rpm-ostree client: {}", e);
+        }
+    }
+}
+
+/// Query deployments status, in the same format as `rpm-ostree status --json`.
+#[context("failed to query rpm-ostree status over D-Bus")]
+pub fn status() -> Result<StatusJson> {
+    let connection = zbus::Connection::new_system()?;
+    let session = Session::open(&connection)?;
+    let deployments = session.sysroot.deployments()?;
+
+    let json = serde_json::json!({ "deployments": value_to_json(&deployments)? });
+    let status = serde_json::from_value(json).context("failed to parse deployments")?;
+    Ok(status)
+}
+
+/// Deploy an upgrade (by checksum) and leave the new deployment locked.
+///
+/// Transaction progress is reported to `on_progress`.
+#[context("failed to deploy '{}' over D-Bus", release.version)]
+pub fn deploy_locked(
+    release: &Release,
+    allow_downgrade: bool,
+    cache_only: bool,
+    timeout: Duration,
+    on_progress: impl FnMut(Progress),
+) -> Result<()> {
+    let mut options = HashMap::new();
+    options.insert("lock-finalization", Value::from(true));
+    options.insert("cache-only", Value::from(cache_only));
+    options.insert("allow-downgrade", Value::from(allow_downgrade));
+    run_deploy(release, options, timeout, on_progress)
+}
+
+/// Download an upgrade (by checksum) without deploying it.
+///
+/// Transaction progress is reported to `on_progress`.
+#[context("failed to download '{}' over D-Bus", release.version)]
+pub fn download_only(
+    release: &Release,
+    allow_downgrade: bool,
+    timeout: Duration,
+    on_progress: impl FnMut(Progress),
+) -> Result<()> {
+    let mut options = HashMap::new();
+    options.insert("download-only", Value::from(true));
+    options.insert("allow-downgrade", Value::from(allow_downgrade));
+    run_deploy(release, options, timeout, on_progress)
+}
+
+/// Run a deploy transaction for a release, with the given options.
+fn run_deploy(
+    release: &Release,
+    options: HashMap<&str, Value>,
+    timeout: Duration,
+    on_progress: impl FnMut(Progress),
+) -> Result<()> {
+    let connection = zbus::Connection::new_system()?;
+    let session = Session::open(&connection)?;
+    let os = session.booted_os()?;
+
+    let revision = format!("revision={}", release.checksum);
+    let address = os.deploy(&revision, options)?;
+    run_transaction(&address, false, timeout, on_progress)
+}
+
+/// Rebase onto another refspec and leave the new deployment locked.
+///
+/// Transaction progress is reported to `on_progress`.
+#[context("failed to rebase to '{}' over D-Bus", refspec)]
+pub fn rebase_locked(
+    refspec: &str,
+    timeout: Duration,
+    on_progress: impl FnMut(Progress),
+) -> Result<()> {
+    let connection = zbus::Connection::new_system()?;
+    let session = Session::open(&connection)?;
+    let os = session.booted_os()?;
+
+    let mut options = HashMap::new();
+    options.insert("lock-finalization", Value::from(true));
+    let address = os.rebase(options, refspec, &[])?;
+    run_transaction(&address, false, timeout, on_progress)
+}
+
+/// Unlock and finalize the new deployment.
+#[context("failed to finalize '{}' over D-Bus", release.version)]
+pub fn finalize_deployment(
+    release: &Release,
+    allow_unlocked: bool,
+    timeout: Duration,
+) -> Result<()> {
+    let connection = zbus::Connection::new_system()?;
+    let session = Session::open(&connection)?;
+    let os = session.booted_os()?;
+
+    let mut options = HashMap::new();
+    options.insert("checksum", Value::from(release.checksum.as_str()));
+    options.insert("allow-unlocked", Value::from(allow_unlocked));
+    let address = os.finalize_deployment(options)?;
+    // On success, the machine starts rebooting and the daemon may go away
+    // before the transaction completes.
+    run_transaction(&address, true, timeout, |_| {})
+}
+
+/// Remove the rollback deployment, if any.
+#[context("failed to clean up rollback deployment over D-Bus")]
+pub fn cleanup_rollback(timeout: Duration) -> Result<()> {
+    let connection = zbus::Connection::new_system()?;
+    let session = Session::open(&connection)?;
+    let os = session.booted_os()?;
+
+    let address = os.cleanup(&["rollback-deploy"])?;
+    run_transaction(&address, false, timeout, |_| {})
+}
+
+/// Start a transaction, and monitor it until completion.
+///
+/// If `reboots` is set, the transaction is expected to reboot the machine,
+/// thus losing the connection after start is not considered a failure.
+/// The transaction is cancelled if it does not complete within `timeout`.
+fn run_transaction(
+    address: &str,
+    reboots: bool,
+    timeout: Duration,
+    on_progress: impl FnMut(Progress),
+) -> Result<()> {
+    let connection = zbus::Connection::new_for_address(address, false)
+        .with_context(|| format!("failed to connect to transaction at '{}'", address))?;
+    let reply = connection.call_method(None, "/", Some(TRANSACTION_INTERFACE), "Start", &())?;
+    let started: bool = reply.body()?;
+    if !started {
+        bail!("transaction already started by another client");
+    }
+
+    // Cancelling the transaction past its deadline makes the daemon report
+    // it as finished, which ends monitoring.
+    let (done, finished) = mpsc::channel::<()>();
+    let expired = Arc::new(AtomicBool::new(false));
+    let canceller = {
+        let expired = Arc::clone(&expired);
+        std::thread::spawn(move || {
+            if let Err(RecvTimeoutError::Timeout) = finished.recv_timeout(timeout) {
+                expired.store(true, Ordering::SeqCst);
+                if let Err(e) = super::cli_cancel::cancel_transaction() {
+                    log::warn!("{:#}", e);
+                }
+            }
+        })
+    };
+    let result = monitor_transaction(&connection, reboots, on_progress);
+    drop(done);
+    let _ = canceller.join();
+
+    match result {
+        Err(_) if expired.load(Ordering::SeqCst) => Err(super::command::timed_out(timeout)),
+        res => res,
+    }
+}
+
+/// Monitor a started transaction until completion.
+fn monitor_transaction(
+    connection: &zbus::Connection,
+    reboots: bool,
+    mut on_progress: impl FnMut(Progress),
+) -> Result<()> {
+    loop {
+        let msg = match connection.receive_message() {
+            Ok(m) => m,
+            Err(e) if reboots => {
+                log::debug!("transaction connection closed, assuming reboot: {}", e);
+                return Ok(());
+            }
+            Err(e) => return Err(e).context("transaction connection closed"),
+        };
+        let header = msg.header()?;
+        if header.message_type()? != zbus::MessageType::Signal {
+            continue;
+        }
+        let member = match header.member()? {
+            Some(m) => m.to_string(),
+            None => continue,
+        };
+
+        match member.as_str() {
+            "PercentProgress" => {
+                let (text, percentage): (String, u32) = msg.body()?;
+                log::trace!("transaction progress: {} ({}%)", text, percentage);
+                on_progress(Progress { text, percentage });
+            }
+            "Message" => {
+                let text: String = msg.body()?;
+                log::debug!("transaction: {}", text);
+            }
+            "Finished" => {
+                let (success, error): (bool, String) = msg.body()?;
+                if !success {
+                    bail!("transaction failed: {}", error);
+                }
+                return Ok(());
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Convert a D-Bus value into JSON.
+fn value_to_json(value: &Value) -> Result<serde_json::Value> {
+    use serde_json::Value as Json;
+
+    let json = match value {
+        Value::Bool(b) => Json::from(*b),
+        Value::U8(n) => Json::from(*n),
+        Value::I16(n) => Json::from(*n),
+        Value::U16(n) => Json::from(*n),
+        Value::I32(n) => Json::from(*n),
+        Value::U32(n) => Json::from(*n),
+        Value::I64(n) => Json::from(*n),
+        Value::U64(n) => Json::from(*n),
+        Value::F64(n) => Json::from(*n),
+        Value::Str(s) => Json::from(s.as_str()),
+        Value::Signature(s) => Json::from(s.as_str()),
+        Value::ObjectPath(p) => Json::from(p.as_str()),
+        Value::Value(v) => value_to_json(v)?,
+        Value::Maybe(m) => match m.inner() {
+            Some(v) => value_to_json(v)?,
+            None => Json::Null,
+        },
+        Value::Array(a) => {
+            let entries: Result<Vec<_>> = a.get().iter().map(value_to_json).collect();
+            Json::Array(entries?)
+        }
+        Value::Dict(d) => {
+            let entries = HashMap::<String, Value>::try_from(d.clone())
+                .context("unsupported dictionary keys")?;
+            let mut map = serde_json::Map::new();
+            for (k, v) in entries {
+                map.insert(k, value_to_json(&v)?);
+            }
+            Json::Object(map)
+        }
+        Value::Structure(s) => {
+            let fields: Result<Vec<_>> = s.fields().iter().map(value_to_json).collect();
+            Json::Array(fields?)
+        }
+        Value::Fd(_) => Json::Null,
+    };
+    Ok(json)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_value_to_json() {
+        let mut meta = HashMap::new();
+        meta.insert("coreos-assembler.basearch", Value::from("x86_64"));
+        meta.insert("fedora-coreos.stream", Value::from("stable"));
+
+        let mut depl = HashMap::new();
+        depl.insert("booted", Value::from(true));
+        depl.insert("checksum", Value::from("sha-mock"));
+        depl.insert("version", Value::from("0.0.0-mock"));
+        depl.insert("base-commit-meta", Value::from(meta));
+        let depls = Value::from(vec![Value::from(depl)]);
+
+        let json = value_to_json(&depls).unwrap();
+        let expected = serde_json::json!([{
+            "booted": true,
+            "checksum": "sha-mock",
+            "version": "0.0.0-mock",
+            "base-commit-meta": {
+                "coreos-assembler.basearch": "x86_64",
+                "fedora-coreos.stream": "stable",
+            },
+        }]);
+        assert_eq!(json, expected);
+
+        let status: StatusJson =
+            serde_json::from_value(serde_json::json!({ "deployments": json })).unwrap();
+        let booted = super::super::parse_booted(&status).unwrap();
+        assert_eq!(booted.checksum, "sha-mock");
+    }
+}
@@ -1,5 +1,7 @@
-use crate::cincinnati::Cincinnati;
+use crate::cincinnati::{Cincinnati, TargetPolicy};
 use crate::identity::Identity;
+use crate::network::NetworkSettings;
+use crate::update_source::UpdateSource;
 use mockito::{self, Matcher};
 use std::collections::BTreeSet;
 use tokio::runtime as rt;
@@ -37,7 +39,7 @@ fn test_simple_graph() {
 
     let m_graph = mockito::mock("GET", Matcher::Regex(r"^/v1/graph?.+$".to_string()))
         .match_header("accept", Matcher::Regex("application/json".to_string()))
-        .with_body(&simple_graph)
+        .with_body(simple_graph)
         .with_status(200)
         .create();
 
@@ -45,6 +47,9 @@ fn test_simple_graph() {
     let id = Identity::mock_default();
     let client = Cincinnati {
         base_url: mockito::server_url(),
+        network: NetworkSettings::default(),
+        skip_versions: BTreeSet::new(),
+        policy: TargetPolicy::default(),
     };
     let update = runtime.block_on(client.fetch_update_hint(&id, BTreeSet::new(), false));
     m_graph.assert();
@@ -86,7 +91,7 @@ fn test_downgrade() {
 
     let m_graph = mockito::mock("GET", Matcher::Regex(r"^/v1/graph?.+$".to_string()))
         .match_header("accept", Matcher::Regex("application/json".to_string()))
-        .with_body(&simple_graph)
+        .with_body(simple_graph)
         .with_status(200)
         .expect(2)
         .create();
@@ -95,6 +100,9 @@ fn test_downgrade() {
     let id = Identity::mock_default();
     let client = Cincinnati {
         base_url: mockito::server_url(),
+        network: NetworkSettings::default(),
+        skip_versions: BTreeSet::new(),
+        policy: TargetPolicy::default(),
     };
 
     // Downgrades denied.
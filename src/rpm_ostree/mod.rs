@@ -1,20 +1,88 @@
+mod cli_cancel;
+mod cli_cleanup;
 mod cli_deploy;
 mod cli_finalize;
+mod cli_rebase;
 mod cli_status;
-pub use cli_status::{invoke_cli_status, parse_basearch, parse_booted, parse_updates_stream};
+mod command;
+mod dbus_client;
+mod policy;
+mod watcher;
+pub use cli_status::{
+    invoke_cli_status, parse_basearch, parse_booted, parse_booted_layering,
+    parse_local_deployments, parse_updates_stream, Layering, SecurityFixes, KNOWN_BASEARCHES,
+};
+pub use policy::check_update_policy;
 
 mod actor;
 pub use actor::{
-    FinalizeDeployment, QueryLocalDeployments, RegisterAsDriver, RpmOstreeClient, StageDeployment,
+    CleanupRollback, DownloadDeployment, FinalizeDeployment, QueryBootedLayering,
+    QueryLocalDeployments, QueryStagedCommitAge, QueryStagedDeployment, QueryStagedDowngrade,
+    QueryStagedSecurityFixes, RebaseDeployment, RegisterAsDriver, RpmOstreeClient, StageDeployment,
 };
 
+mod queue;
+pub use queue::{queued_operations, OperationQueue, ShutdownQueue};
+
 #[cfg(test)]
 mod mock_tests;
 
-use crate::cincinnati::{Node, AGE_INDEX_KEY, CHECKSUM_SCHEME, SCHEME_KEY};
+use crate::cincinnati::{
+    Node, AGE_INDEX_KEY, CHECKSUM_SCHEME, ERRATA_KEY, RELEASE_NOTES_KEY, SCHEME_KEY, SEVERITY_KEY,
+    WAVE_KEY, WAVE_START_KEY,
+};
+use crate::config::inputs;
 use anyhow::{anyhow, ensure, Context, Result};
+use chrono::{DateTime, Utc};
 use serde::Serialize;
 use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// Known values for release security severity, from lowest to highest.
+pub static SEVERITIES: [&str; 4] = ["low", "moderate", "important", "critical"];
+
+/// Backend used to interact with rpm-ostree.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub enum Backend {
+    /// Shell out to the `rpm-ostree` CLI.
+    #[default]
+    Cli,
+    /// Call the rpm-ostree D-Bus API directly.
+    DBus,
+}
+
+impl Backend {
+    /// Try to parse a backend from its configuration label.
+    pub fn with_config(label: &str) -> Result<Self> {
+        match label {
+            "" | "cli" => Ok(Backend::Cli),
+            "dbus" => Ok(Backend::DBus),
+            x => anyhow::bail!("unsupported rpm-ostree backend '{}'", x),
+        }
+    }
+}
+
+/// Timeouts for rpm-ostree operations.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub struct Timeouts {
+    /// Staging, downloading and rebasing.
+    pub stage: Duration,
+    /// Finalizing.
+    pub finalize: Duration,
+    /// Status queries and driver registration.
+    pub status: Duration,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self {
+            stage: Duration::from_secs(inputs::DEFAULT_RPM_OSTREE_STAGE_TIMEOUT_SECS),
+            finalize: Duration::from_secs(inputs::DEFAULT_RPM_OSTREE_FINALIZE_TIMEOUT_SECS),
+            status: Duration::from_secs(inputs::DEFAULT_RPM_OSTREE_STATUS_TIMEOUT_SECS),
+        }
+    }
+}
 
 /// An OS release.
 #[derive(Clone, Debug, PartialEq, Eq, Serialize)]
@@ -25,13 +93,147 @@ pub struct Release {
     pub checksum: String,
     /// Release age (Cincinnati `age_index`).
     pub age_index: Option<u64>,
+    /// Advisory metadata from Cincinnati, if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub advisory: Option<Advisory>,
+    /// Rollout wave assigned by Cincinnati, if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub wave: Option<RolloutWave>,
+}
+
+/// Advisory metadata for a release, from Cincinnati node metadata.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct Advisory {
+    /// Security severity (one of `SEVERITIES`).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub severity: Option<String>,
+    /// Release-notes URL.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub release_notes: Option<String>,
+    /// Errata IDs.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub errata: Vec<String>,
+}
+
+impl Advisory {
+    /// Parse advisory metadata, if any, from Cincinnati node metadata.
+    ///
+    /// These keys are optional and informational, thus invalid values are
+    /// logged and ignored instead of rejecting the whole node.
+    fn from_metadata(version: &str, metadata: &HashMap<String, String>) -> Option<Self> {
+        let severity = metadata.get(SEVERITY_KEY).and_then(|val| {
+            let val = val.trim().to_lowercase();
+            if SEVERITIES.contains(&val.as_str()) {
+                Some(val)
+            } else {
+                log::warn!(
+                    "ignoring unknown severity '{}' for release {}",
+                    val,
+                    version
+                );
+                None
+            }
+        });
+        let release_notes =
+            metadata
+                .get(RELEASE_NOTES_KEY)
+                .and_then(|val| match reqwest::Url::parse(val.trim()) {
+                    Ok(url) if url.scheme() == "https" || url.scheme() == "http" => {
+                        Some(url.to_string())
+                    }
+                    _ => {
+                        log::warn!(
+                            "ignoring invalid release-notes URL '{}' for release {}",
+                            val,
+                            version
+                        );
+                        None
+                    }
+                });
+        let errata: Vec<String> = metadata
+            .get(ERRATA_KEY)
+            .map(|val| {
+                val.split(',')
+                    .map(str::trim)
+                    .filter(|id| !id.is_empty())
+                    .map(String::from)
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let advisory = Self {
+            severity,
+            release_notes,
+            errata,
+        };
+        if advisory == Self::default() {
+            None
+        } else {
+            Some(advisory)
+        }
+    }
+}
+
+/// Rollout wave assigned to this node by Cincinnati, for a release.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct RolloutWave {
+    /// Wave label.
+    pub name: String,
+    /// Earliest time this node can start updating.
+    pub start: DateTime<Utc>,
+}
+
+impl RolloutWave {
+    /// Parse a rollout wave assignment, if any, from Cincinnati node metadata.
+    ///
+    /// A wave without a valid start time is logged and ignored, so that
+    /// the release is not held back by malformed metadata.
+    fn from_metadata(version: &str, metadata: &HashMap<String, String>) -> Option<Self> {
+        let name = metadata.get(WAVE_KEY)?.trim();
+        if name.is_empty() {
+            return None;
+        }
+        let start = match metadata.get(WAVE_START_KEY) {
+            Some(val) => match DateTime::parse_from_rfc3339(val.trim()) {
+                Ok(t) => t.with_timezone(&Utc),
+                Err(_) => {
+                    log::warn!(
+                        "ignoring rollout wave '{}' with invalid start time '{}' for release {}",
+                        name,
+                        val,
+                        version
+                    );
+                    return None;
+                }
+            },
+            None => {
+                log::warn!(
+                    "ignoring rollout wave '{}' without start time for release {}",
+                    name,
+                    version
+                );
+                return None;
+            }
+        };
+
+        let wave = Self {
+            name: name.to_string(),
+            start,
+        };
+        Some(wave)
+    }
+
+    /// Whether this wave is open at `now`.
+    pub fn is_open(&self, now: &DateTime<Utc>) -> bool {
+        *now >= self.start
+    }
 }
 
 impl std::cmp::Ord for Release {
     fn cmp(&self, other: &Self) -> Ordering {
         // Order is primarily based on age-index coming from Cincinnati.
-        let self_age = self.age_index.clone().unwrap_or(0);
-        let other_age = other.age_index.clone().unwrap_or(0);
+        let self_age = self.age_index.unwrap_or(0);
+        let other_age = other.age_index.unwrap_or(0);
         if self_age != other_age {
             return self_age.cmp(&other_age);
         }
@@ -82,10 +284,14 @@ impl Release {
                 .context(format!("invalid age_index value: {}", val))?
         };
 
+        let advisory = Advisory::from_metadata(&node.version, &node.metadata);
+        let wave = RolloutWave::from_metadata(&node.version, &node.metadata);
         let rel = Self {
             version: node.version,
             checksum: node.payload,
             age_index: Some(age),
+            advisory,
+            wave,
         };
         Ok(rel)
     }
@@ -94,6 +300,7 @@ impl Release {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use chrono::TimeZone;
     use maplit::hashmap;
 
     #[test]
@@ -109,6 +316,82 @@ mod tests {
         Release::from_cincinnati(input).unwrap();
     }
 
+    #[test]
+    fn release_advisory_from_cincinnati() {
+        let node = |metadata: HashMap<String, String>| Node {
+            version: "mock-version".to_string(),
+            payload: "mock-payload".to_string(),
+            metadata,
+        };
+
+        let plain = node(hashmap! {
+            SCHEME_KEY.to_string() => CHECKSUM_SCHEME.to_string(),
+            AGE_INDEX_KEY.to_string() => "0".to_string(),
+        });
+        assert_eq!(Release::from_cincinnati(plain).unwrap().advisory, None);
+
+        let full = node(hashmap! {
+            SCHEME_KEY.to_string() => CHECKSUM_SCHEME.to_string(),
+            AGE_INDEX_KEY.to_string() => "0".to_string(),
+            SEVERITY_KEY.to_string() => "Critical".to_string(),
+            RELEASE_NOTES_KEY.to_string() => "https://example.com/notes".to_string(),
+            ERRATA_KEY.to_string() => "FEDORA-2026-1, FEDORA-2026-2,".to_string(),
+        });
+        let expected = Advisory {
+            severity: Some("critical".to_string()),
+            release_notes: Some("https://example.com/notes".to_string()),
+            errata: vec!["FEDORA-2026-1".to_string(), "FEDORA-2026-2".to_string()],
+        };
+        assert_eq!(
+            Release::from_cincinnati(full).unwrap().advisory,
+            Some(expected)
+        );
+
+        let invalid = node(hashmap! {
+            SCHEME_KEY.to_string() => CHECKSUM_SCHEME.to_string(),
+            AGE_INDEX_KEY.to_string() => "0".to_string(),
+            SEVERITY_KEY.to_string() => "urgent".to_string(),
+            RELEASE_NOTES_KEY.to_string() => "file:///etc/shadow".to_string(),
+        });
+        assert_eq!(Release::from_cincinnati(invalid).unwrap().advisory, None);
+    }
+
+    #[test]
+    fn release_wave_from_cincinnati() {
+        let node = |metadata: HashMap<String, String>| Node {
+            version: "mock-version".to_string(),
+            payload: "mock-payload".to_string(),
+            metadata,
+        };
+
+        let assigned = node(hashmap! {
+            SCHEME_KEY.to_string() => CHECKSUM_SCHEME.to_string(),
+            AGE_INDEX_KEY.to_string() => "0".to_string(),
+            WAVE_KEY.to_string() => "canary".to_string(),
+            WAVE_START_KEY.to_string() => "2026-10-20T08:00:00+02:00".to_string(),
+        });
+        let wave = Release::from_cincinnati(assigned).unwrap().wave.unwrap();
+        assert_eq!(wave.name, "canary");
+        assert_eq!(
+            wave.start,
+            Utc.with_ymd_and_hms(2026, 10, 20, 6, 0, 0).unwrap()
+        );
+        assert!(!wave.is_open(&Utc.with_ymd_and_hms(2026, 10, 20, 5, 59, 59).unwrap()));
+        assert!(wave.is_open(&Utc.with_ymd_and_hms(2026, 10, 20, 6, 0, 0).unwrap()));
+
+        for start in &[None, Some("next week")] {
+            let mut metadata = hashmap! {
+                SCHEME_KEY.to_string() => CHECKSUM_SCHEME.to_string(),
+                AGE_INDEX_KEY.to_string() => "0".to_string(),
+                WAVE_KEY.to_string() => "canary".to_string(),
+            };
+            if let Some(s) = start {
+                metadata.insert(WAVE_START_KEY.to_string(), s.to_string());
+            }
+            assert_eq!(Release::from_cincinnati(node(metadata)).unwrap().wave, None);
+        }
+    }
+
     #[test]
     fn invalid_node() {
         let node1 = Node {
@@ -146,6 +429,14 @@ mod tests {
         Release::from_cincinnati(node4).unwrap_err();
     }
 
+    #[test]
+    fn backend_with_config() {
+        assert_eq!(Backend::with_config("").unwrap(), Backend::Cli);
+        assert_eq!(Backend::with_config("cli").unwrap(), Backend::Cli);
+        assert_eq!(Backend::with_config("dbus").unwrap(), Backend::DBus);
+        Backend::with_config("foo").unwrap_err();
+    }
+
     #[test]
     fn release_cmp() {
         {
@@ -153,46 +444,58 @@ mod tests {
                 version: "v0".to_string(),
                 checksum: "p0".to_string(),
                 age_index: Some(0),
+                advisory: None,
+                wave: None,
             };
             let n1 = Release {
                 version: "v1".to_string(),
                 checksum: "p1".to_string(),
                 age_index: Some(1),
+                advisory: None,
+                wave: None,
             };
-            assert_eq!(n0 < n1, true);
-            assert_eq!(n0 == n0, true);
-            assert_eq!(n0 < n0, false);
-            assert_eq!(n0 > n0, false);
+            assert!(n0 < n1);
+            assert!(n0 == n0);
+            assert!(n0 >= n0);
+            assert!(n0 <= n0);
         }
         {
             let n0 = Release {
                 version: "v0".to_string(),
                 checksum: "p0".to_string(),
                 age_index: Some(0),
+                advisory: None,
+                wave: None,
             };
             let n1 = Release {
                 version: "v1".to_string(),
                 checksum: "p1".to_string(),
                 age_index: Some(0),
+                advisory: None,
+                wave: None,
             };
-            assert_eq!(n0 < n1, true);
-            assert_eq!(n0 < n0, false);
-            assert_eq!(n0 > n0, false);
+            assert!(n0 < n1);
+            assert!(n0 >= n0);
+            assert!(n0 <= n0);
         }
         {
             let n0 = Release {
                 version: "v0".to_string(),
                 checksum: "p0".to_string(),
                 age_index: Some(0),
+                advisory: None,
+                wave: None,
             };
             let n1 = Release {
                 version: "v0".to_string(),
                 checksum: "p1".to_string(),
                 age_index: Some(0),
+                advisory: None,
+                wave: None,
             };
-            assert_eq!(n0 < n1, true);
-            assert_eq!(n0 < n0, false);
-            assert_eq!(n0 > n0, false);
+            assert!(n0 < n1);
+            assert!(n0 >= n0);
+            assert!(n0 <= n0);
         }
     }
 }
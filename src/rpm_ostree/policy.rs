@@ -0,0 +1,196 @@
+//! Divergence between Zincati and rpm-ostree automatic-update policy.
+//!
+//! rpm-ostree has its own automatic-update logic, driven by the
+//! `AutomaticUpdatePolicy` setting and by `rpm-ostreed-automatic.timer`.
+//! When Zincati is the update driver, that logic must stay disabled,
+//! otherwise both agents compete for the same deployments.
+
+use anyhow::{Context, Result};
+use prometheus::IntGauge;
+use std::process::Command;
+
+/// Path to rpm-ostree daemon configuration.
+static RPM_OSTREED_CONF_PATH: &str = "/etc/rpm-ostreed.conf";
+
+/// systemd timer triggering rpm-ostree automatic updates.
+static AUTOMATIC_TIMER: &str = "rpm-ostreed-automatic.timer";
+
+lazy_static::lazy_static! {
+    static ref POLICY_CONFLICTS: IntGauge = register_int_gauge!(opts!(
+        "zincati_rpm_ostree_policy_conflicts",
+        "Number of rpm-ostree automatic-update settings conflicting with Zincati as update driver."
+    )).unwrap();
+}
+
+/// Automatic-update settings of rpm-ostree.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct UpdatePolicy {
+    /// Value of `AutomaticUpdatePolicy` (`none` if unset).
+    policy: String,
+    /// Whether the automatic-update timer is enabled.
+    timer_enabled: bool,
+}
+
+impl UpdatePolicy {
+    /// Return the settings conflicting with Zincati, in human terms.
+    fn conflicts(&self) -> Vec<String> {
+        let mut conflicts = vec![];
+        if self.policy != "none" {
+            conflicts.push(format!(
+                "rpm-ostree 'AutomaticUpdatePolicy' is set to '{}' in '{}'",
+                self.policy, RPM_OSTREED_CONF_PATH
+            ));
+        }
+        if self.timer_enabled {
+            conflicts.push(format!("'{}' is enabled", AUTOMATIC_TIMER));
+        }
+        conflicts
+    }
+}
+
+/// Check rpm-ostree automatic-update policy for conflicts with Zincati.
+///
+/// Conflicts are logged and exposed as a metric. If `reconcile` is set,
+/// the rpm-ostree automatic-update timer is disabled.
+pub fn check_update_policy(reconcile: bool) {
+    let policy = match read_update_policy() {
+        Ok(p) => p,
+        Err(e) => {
+            log::warn!("failed to check rpm-ostree update policy: {:#}", e);
+            return;
+        }
+    };
+
+    let conflicts = policy.conflicts();
+    POLICY_CONFLICTS.set(conflicts.len() as i64);
+    for conflict in &conflicts {
+        log::warn!(
+            "rpm-ostree automatic updates conflict with Zincati as update driver: {}",
+            conflict
+        );
+    }
+
+    if !policy.timer_enabled {
+        return;
+    }
+    if !reconcile {
+        log::warn!(
+            "set 'agent.reconcile_rpm_ostree_policy' to let Zincati disable '{}'",
+            AUTOMATIC_TIMER
+        );
+        return;
+    }
+    match disable_timer() {
+        Ok(_) => {
+            log::info!("disabled '{}'", AUTOMATIC_TIMER);
+            POLICY_CONFLICTS.set(conflicts.len().saturating_sub(1) as i64);
+        }
+        Err(e) => log::error!("{:#}", e),
+    }
+}
+
+/// Read rpm-ostree automatic-update settings from the host.
+fn read_update_policy() -> Result<UpdatePolicy> {
+    let policy = match std::fs::read_to_string(RPM_OSTREED_CONF_PATH) {
+        Ok(content) => parse_automatic_update_policy(&content),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => "none".to_string(),
+        Err(e) => {
+            return Err(e).with_context(|| format!("failed to read '{}'", RPM_OSTREED_CONF_PATH))
+        }
+    };
+    let timer_enabled = Command::new("systemctl")
+        .arg("is-enabled")
+        .arg("--quiet")
+        .arg(AUTOMATIC_TIMER)
+        .status()
+        .context("failed to run 'systemctl'")?
+        .success();
+
+    Ok(UpdatePolicy {
+        policy,
+        timer_enabled,
+    })
+}
+
+/// Disable and stop the rpm-ostree automatic-update timer.
+fn disable_timer() -> Result<()> {
+    let status = Command::new("systemctl")
+        .arg("disable")
+        .arg("--now")
+        .arg(AUTOMATIC_TIMER)
+        .status()
+        .context("failed to run 'systemctl'")?;
+    anyhow::ensure!(
+        status.success(),
+        "failed to disable '{}': {}",
+        AUTOMATIC_TIMER,
+        status
+    );
+    Ok(())
+}
+
+/// Parse `AutomaticUpdatePolicy` from rpm-ostree daemon configuration.
+///
+/// This returns `none` if the setting is absent, or set to its `off` alias.
+fn parse_automatic_update_policy(content: &str) -> String {
+    let mut in_daemon = false;
+    let mut policy = String::new();
+    for line in content.lines().map(str::trim) {
+        if line.starts_with('[') {
+            in_daemon = line == "[Daemon]";
+            continue;
+        }
+        if !in_daemon {
+            continue;
+        }
+        if let Some((key, value)) = line.split_once('=') {
+            if key.trim() == "AutomaticUpdatePolicy" {
+                policy = value.trim().to_lowercase();
+            }
+        }
+    }
+
+    match policy.as_str() {
+        "" | "off" => "none".to_string(),
+        _ => policy,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_automatic_update_policy() {
+        let cases = vec![
+            ("", "none"),
+            ("[Daemon]\n#AutomaticUpdatePolicy=stage\n", "none"),
+            ("[Daemon]\nAutomaticUpdatePolicy=off\n", "none"),
+            ("[Daemon]\nAutomaticUpdatePolicy = check\n", "check"),
+            (
+                "[Daemon]\nIdleExitTimeout=60\nAutomaticUpdatePolicy=stage\n",
+                "stage",
+            ),
+            ("[Other]\nAutomaticUpdatePolicy=stage\n", "none"),
+        ];
+        for (content, expected) in cases {
+            assert_eq!(
+                parse_automatic_update_policy(content),
+                expected,
+                "{}",
+                content
+            );
+        }
+
+        let policy = UpdatePolicy {
+            policy: "stage".to_string(),
+            timer_enabled: true,
+        };
+        assert_eq!(policy.conflicts().len(), 2);
+        let policy = UpdatePolicy {
+            policy: "none".to_string(),
+            timer_enabled: false,
+        };
+        assert!(policy.conflicts().is_empty());
+    }
+}
@@ -0,0 +1,639 @@
+//! Queue for rpm-ostree operations.
+//!
+//! rpm-ostree only supports a single transaction at a time, thus all requests
+//! go through this queue, which keeps at most one transaction in flight.
+//! Status queries do not need a transaction, thus one of them can run
+//! alongside it (e.g. while a long deploy is in progress). Status queries
+//! are answered straight from the client's status cache whenever it is up
+//! to date, without queueing.
+//! Queued operations are run by priority (finalize, then stage, then status
+//! queries), and queued stage/download operations are cancelled as soon as a
+//! newer target release (or a rebase) is requested.
+//! On agent shutdown, the queue stops accepting operations and aborts any
+//! in-flight transaction which would leave a half-staged deployment behind.
+
+use super::actor::{
+    CleanupRollback, DownloadDeployment, FinalizeDeployment, QueryBootedLayering,
+    QueryLocalDeployments, QueryStagedCommitAge, QueryStagedDeployment, QueryStagedDowngrade,
+    QueryStagedSecurityFixes, RebaseDeployment, RegisterAsDriver, RpmOstreeClient,
+    SharedStatusCache, StageDeployment,
+};
+use super::cli_status::{
+    cached_status, find_staged, parse_local_deployments, parse_staged_commit_age,
+    parse_staged_downgrade,
+};
+use super::{Layering, Release, SecurityFixes};
+use actix::dev::ToEnvelope;
+use actix::prelude::*;
+use anyhow::{anyhow, Result};
+use futures::channel::oneshot;
+use futures::future::LocalBoxFuture;
+use futures::prelude::*;
+use log::trace;
+use prometheus::{IntCounter, IntGauge, IntGaugeVec};
+use std::cmp::Reverse;
+use std::collections::BTreeSet;
+use std::sync::Mutex;
+
+lazy_static::lazy_static! {
+    static ref QUEUED_OPERATIONS: IntGaugeVec = register_int_gauge_vec!(
+        "zincati_rpm_ostree_queued_operations",
+        "Number of rpm-ostree operations waiting in queue, by priority.",
+        &["priority"]
+    ).unwrap();
+    static ref IN_FLIGHT: IntGauge = register_int_gauge!(opts!(
+        "zincati_rpm_ostree_operation_in_flight",
+        "Number of rpm-ostree operations currently running."
+    )).unwrap();
+    static ref SUPERSEDED: IntCounter = register_int_counter!(opts!(
+        "zincati_rpm_ostree_superseded_operations_total",
+        "Total number of queued rpm-ostree operations cancelled by a newer target."
+    )).unwrap();
+    static ref REJECTED: IntCounter = register_int_counter!(opts!(
+        "zincati_rpm_ostree_rejected_operations_total",
+        "Total number of rpm-ostree operations rejected because the queue was full."
+    )).unwrap();
+    static ref QUEUE_SNAPSHOT: Mutex<Vec<String>> = Mutex::new(vec![]);
+}
+
+/// Return the running and queued rpm-ostree operations, in execution order.
+pub fn queued_operations() -> Vec<String> {
+    QUEUE_SNAPSHOT
+        .lock()
+        .map(|ops| ops.clone())
+        .unwrap_or_default()
+}
+
+/// Priority of a queued operation, lowest first.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Priority {
+    Status,
+    Stage,
+    Finalize,
+}
+
+impl Priority {
+    /// Whether operations with this priority only query status, without
+    /// starting a transaction.
+    fn is_read_only(self) -> bool {
+        self == Priority::Status
+    }
+
+    /// Label for this priority, as used in metrics.
+    fn label(self) -> &'static str {
+        match self {
+            Priority::Status => "status",
+            Priority::Stage => "stage",
+            Priority::Finalize => "finalize",
+        }
+    }
+}
+
+/// A request waiting for its turn, type-erased.
+trait Operation {
+    /// Forward the request to the client, replying once it completes.
+    fn run(self: Box<Self>, client: &Addr<RpmOstreeClient>) -> LocalBoxFuture<'static, ()>;
+    /// Reply with an error, without running the request.
+    fn cancel(self: Box<Self>, err: anyhow::Error);
+}
+
+/// A pending request, with its reply channel.
+struct Pending<M, T> {
+    msg: M,
+    reply: oneshot::Sender<Result<T>>,
+}
+
+impl<M, T> Operation for Pending<M, T>
+where
+    M: Message<Result = Result<T>> + Send + 'static,
+    T: Send + 'static,
+    RpmOstreeClient: Handler<M>,
+    <RpmOstreeClient as Actor>::Context: ToEnvelope<RpmOstreeClient, M>,
+{
+    fn run(self: Box<Self>, client: &Addr<RpmOstreeClient>) -> LocalBoxFuture<'static, ()> {
+        let Pending { msg, reply } = *self;
+        let result = client.send(msg).unwrap_or_else(|e| Err(e.into()));
+        Box::pin(result.map(move |res| {
+            // The requester may have gone away meanwhile, that's fine.
+            let _ = reply.send(res);
+        }))
+    }
+
+    fn cancel(self: Box<Self>, err: anyhow::Error) {
+        let _ = self.reply.send(Err(err));
+    }
+}
+
+/// An operation in queue.
+struct QueuedOperation {
+    priority: Priority,
+    /// Arrival order, for FIFO among same-priority operations.
+    seq: u64,
+    /// Human-readable description.
+    label: String,
+    /// Target release, for stage and download operations.
+    target: Option<Release>,
+    operation: Box<dyn Operation>,
+}
+
+/// Queue actor, in front of the rpm-ostree client.
+pub struct OperationQueue {
+    client: Addr<RpmOstreeClient>,
+    /// Status cache of the client, for answering queries without queueing.
+    status_cache: SharedStatusCache,
+    /// Maximum number of queued (not running) operations.
+    max_queued: usize,
+    queued: Vec<QueuedOperation>,
+    next_seq: u64,
+    /// Priority and description of running operations, at most one
+    /// transaction and one status query.
+    in_flight: Vec<(Priority, String)>,
+    /// Whether the queue was shut down.
+    closed: bool,
+}
+
+impl std::fmt::Debug for OperationQueue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let queued: Vec<_> = self.queued.iter().map(|op| &op.label).collect();
+        f.debug_struct("OperationQueue")
+            .field("max_queued", &self.max_queued)
+            .field("queued", &queued)
+            .field("in_flight", &self.in_flight)
+            .field("closed", &self.closed)
+            .finish()
+    }
+}
+
+impl Actor for OperationQueue {
+    type Context = Context<Self>;
+}
+
+impl OperationQueue {
+    /// Start the given rpm-ostree client, and the queue in front of it.
+    pub fn start(client: RpmOstreeClient, max_queued: usize) -> Addr<Self> {
+        Self::new(client, max_queued).start()
+    }
+
+    fn new(client: RpmOstreeClient, max_queued: usize) -> Self {
+        let status_cache = client.status_cache.clone();
+        Self {
+            client: client.start(),
+            status_cache,
+            max_queued,
+            queued: vec![],
+            next_seq: 0,
+            in_flight: vec![],
+            closed: false,
+        }
+    }
+
+    /// Queue a request, returning a future for its result.
+    fn enqueue<M, T>(
+        &mut self,
+        ctx: &mut Context<Self>,
+        priority: Priority,
+        label: String,
+        target: Option<Release>,
+        msg: M,
+    ) -> ResponseFuture<Result<T>>
+    where
+        M: Message<Result = Result<T>> + Send + 'static,
+        T: Send + 'static,
+        RpmOstreeClient: Handler<M>,
+        <RpmOstreeClient as Actor>::Context: ToEnvelope<RpmOstreeClient, M>,
+    {
+        if self.closed {
+            let err = anyhow!("rpm-ostree operation '{}' rejected, shutting down", label);
+            return Box::pin(futures::future::err(err));
+        }
+        if let Some(release) = &target {
+            self.cancel_superseded(release);
+        }
+        if self.queued.len() >= self.max_queued {
+            REJECTED.inc();
+            let err = anyhow!(
+                "rpm-ostree operation '{}' rejected, {} operations already queued",
+                label,
+                self.queued.len()
+            );
+            return Box::pin(futures::future::err(err));
+        }
+
+        let (reply, result) = oneshot::channel();
+        trace!("queueing rpm-ostree operation: {}", label);
+        self.queued.push(QueuedOperation {
+            priority,
+            seq: self.next_seq,
+            label,
+            target,
+            operation: Box::new(Pending { msg, reply }),
+        });
+        self.next_seq += 1;
+        self.dispatch(ctx);
+
+        let result = result.unwrap_or_else(|_| Err(anyhow!("rpm-ostree operation dropped")));
+        Box::pin(result)
+    }
+
+    /// Cancel queued stage/download operations for a target other than `release`.
+    fn cancel_superseded(&mut self, release: &Release) {
+        let cause = format!("release '{}'", release.version);
+        self.cancel_targets(|target| target.checksum != release.checksum, &cause);
+    }
+
+    /// Cancel queued stage/download operations for targets matching `superseded`.
+    fn cancel_targets(&mut self, superseded: impl Fn(&Release) -> bool, cause: &str) {
+        let (superseded, kept): (Vec<_>, Vec<_>) =
+            self.queued.drain(..).partition(|op| match &op.target {
+                Some(target) => superseded(target),
+                None => false,
+            });
+        self.queued = kept;
+
+        for op in superseded {
+            log::info!(
+                "cancelling queued rpm-ostree operation '{}', superseded by {}",
+                op.label,
+                cause
+            );
+            SUPERSEDED.inc();
+            let err = anyhow!("operation '{}' superseded by {}", op.label, cause);
+            op.operation.cancel(err);
+        }
+    }
+
+    /// Run the next operations, as long as they do not conflict with in-flight ones.
+    fn dispatch(&mut self, ctx: &mut Context<Self>) {
+        while let Some(index) = self.next_index() {
+            let next = self.queued.remove(index);
+            trace!("running rpm-ostree operation: {}", next.label);
+            let read_only = next.priority.is_read_only();
+            self.in_flight.push((next.priority, next.label));
+            let done =
+                next.operation
+                    .run(&self.client)
+                    .into_actor(self)
+                    .map(move |_, actor, ctx| {
+                        actor
+                            .in_flight
+                            .retain(|(priority, _)| priority.is_read_only() != read_only);
+                        actor.dispatch(ctx);
+                    });
+            ctx.spawn(done);
+        }
+        self.refresh_status();
+    }
+
+    /// Return the index of the next operation which can run: highest priority,
+    /// then oldest.
+    fn next_index(&self) -> Option<usize> {
+        self.queued
+            .iter()
+            .enumerate()
+            .filter(|(_, op)| self.can_run(op.priority))
+            .max_by_key(|(_, op)| (op.priority, Reverse(op.seq)))
+            .map(|(index, _)| index)
+    }
+
+    /// Return whether an operation with the given priority can run now.
+    ///
+    /// Transactions conflict with each other, and so do status queries.
+    fn can_run(&self, priority: Priority) -> bool {
+        !self
+            .in_flight
+            .iter()
+            .any(|(running, _)| running.is_read_only() == priority.is_read_only())
+    }
+
+    /// Refresh metrics and the snapshot exposed over D-Bus.
+    fn refresh_status(&self) {
+        IN_FLIGHT.set(self.in_flight.len() as i64);
+        for priority in &[Priority::Status, Priority::Stage, Priority::Finalize] {
+            let count = self
+                .queued
+                .iter()
+                .filter(|op| op.priority == *priority)
+                .count();
+            QUEUED_OPERATIONS
+                .with_label_values(&[priority.label()])
+                .set(count as i64);
+        }
+
+        let mut order: Vec<&QueuedOperation> = self.queued.iter().collect();
+        order.sort_by_key(|op| (Reverse(op.priority), op.seq));
+        let running = self
+            .in_flight
+            .iter()
+            .map(|(_, label)| format!("running: {}", label));
+        let queued = order.iter().map(|op| format!("queued: {}", op.label));
+        if let Ok(mut snapshot) = QUEUE_SNAPSHOT.lock() {
+            *snapshot = running.chain(queued).collect();
+        }
+    }
+}
+
+impl Handler<StageDeployment> for OperationQueue {
+    type Result = ResponseFuture<Result<Release>>;
+
+    fn handle(&mut self, msg: StageDeployment, ctx: &mut Self::Context) -> Self::Result {
+        let label = format!("stage {}", msg.release.version);
+        let target = Some(msg.release.clone());
+        self.enqueue(ctx, Priority::Stage, label, target, msg)
+    }
+}
+
+impl Handler<DownloadDeployment> for OperationQueue {
+    type Result = ResponseFuture<Result<Release>>;
+
+    fn handle(&mut self, msg: DownloadDeployment, ctx: &mut Self::Context) -> Self::Result {
+        let label = format!("download {}", msg.release.version);
+        let target = Some(msg.release.clone());
+        self.enqueue(ctx, Priority::Stage, label, target, msg)
+    }
+}
+
+impl Handler<FinalizeDeployment> for OperationQueue {
+    type Result = ResponseFuture<Result<Release>>;
+
+    fn handle(&mut self, msg: FinalizeDeployment, ctx: &mut Self::Context) -> Self::Result {
+        let label = format!("finalize {}", msg.release.version);
+        self.enqueue(ctx, Priority::Finalize, label, None, msg)
+    }
+}
+
+impl Handler<CleanupRollback> for OperationQueue {
+    type Result = ResponseFuture<Result<()>>;
+
+    fn handle(&mut self, msg: CleanupRollback, ctx: &mut Self::Context) -> Self::Result {
+        let label = "clean up rollback deployment".to_string();
+        self.enqueue(ctx, Priority::Stage, label, None, msg)
+    }
+}
+
+impl Handler<RebaseDeployment> for OperationQueue {
+    type Result = ResponseFuture<Result<Release>>;
+
+    fn handle(&mut self, msg: RebaseDeployment, ctx: &mut Self::Context) -> Self::Result {
+        // Pending targets were selected for the previous refspec.
+        let cause = format!("rebase to '{}'", msg.refspec);
+        self.cancel_targets(|_| true, &cause);
+        let label = format!("rebase to {}", msg.refspec);
+        self.enqueue(ctx, Priority::Stage, label, None, msg)
+    }
+}
+
+impl Handler<QueryLocalDeployments> for OperationQueue {
+    type Result = ResponseFuture<Result<BTreeSet<Release>>>;
+
+    fn handle(&mut self, msg: QueryLocalDeployments, ctx: &mut Self::Context) -> Self::Result {
+        if let Some(status) = cached_status(&self.status_cache) {
+            let local_depls = parse_local_deployments(&status, msg.omit_staged);
+            return Box::pin(futures::future::ok(local_depls));
+        }
+        let label = "list local deployments".to_string();
+        self.enqueue(ctx, Priority::Status, label, None, msg)
+    }
+}
+
+impl Handler<QueryStagedDeployment> for OperationQueue {
+    type Result = ResponseFuture<Result<Option<Release>>>;
+
+    fn handle(&mut self, msg: QueryStagedDeployment, ctx: &mut Self::Context) -> Self::Result {
+        if let Some(status) = cached_status(&self.status_cache) {
+            return Box::pin(futures::future::ok(find_staged(&status)));
+        }
+        let label = "query staged deployment".to_string();
+        self.enqueue(ctx, Priority::Status, label, None, msg)
+    }
+}
+
+impl Handler<QueryStagedDowngrade> for OperationQueue {
+    type Result = ResponseFuture<Result<bool>>;
+
+    fn handle(&mut self, msg: QueryStagedDowngrade, ctx: &mut Self::Context) -> Self::Result {
+        if let Some(status) = cached_status(&self.status_cache) {
+            return Box::pin(futures::future::ready(parse_staged_downgrade(&status)));
+        }
+        let label = "check staged downgrade".to_string();
+        self.enqueue(ctx, Priority::Status, label, None, msg)
+    }
+}
+
+impl Handler<QueryBootedLayering> for OperationQueue {
+    type Result = ResponseFuture<Result<Layering>>;
+
+    fn handle(&mut self, msg: QueryBootedLayering, ctx: &mut Self::Context) -> Self::Result {
+        // Not answered from cache here, as the client refreshes related metrics.
+        let label = "query booted layering".to_string();
+        self.enqueue(ctx, Priority::Status, label, None, msg)
+    }
+}
+
+impl Handler<QueryStagedCommitAge> for OperationQueue {
+    type Result = ResponseFuture<Result<Option<i64>>>;
+
+    fn handle(&mut self, msg: QueryStagedCommitAge, ctx: &mut Self::Context) -> Self::Result {
+        if let Some(status) = cached_status(&self.status_cache) {
+            return Box::pin(futures::future::ready(parse_staged_commit_age(&status)));
+        }
+        let label = "query staged commit age".to_string();
+        self.enqueue(ctx, Priority::Status, label, None, msg)
+    }
+}
+
+impl Handler<QueryStagedSecurityFixes> for OperationQueue {
+    type Result = ResponseFuture<Result<Option<SecurityFixes>>>;
+
+    fn handle(&mut self, msg: QueryStagedSecurityFixes, ctx: &mut Self::Context) -> Self::Result {
+        // Not answered from cache here, as the client refreshes related metrics.
+        let label = "query staged security fixes".to_string();
+        self.enqueue(ctx, Priority::Status, label, None, msg)
+    }
+}
+
+impl Handler<RegisterAsDriver> for OperationQueue {
+    type Result = ResponseFuture<Result<()>>;
+
+    fn handle(&mut self, msg: RegisterAsDriver, ctx: &mut Self::Context) -> Self::Result {
+        // Registering is a transaction, although not a staging one.
+        let label = "register as update driver".to_string();
+        self.enqueue(ctx, Priority::Stage, label, None, msg)
+    }
+}
+
+/// Request: stop accepting operations, e.g. because the agent is shutting down.
+///
+/// Queued operations are cancelled, and an in-flight stage, download or rebase
+/// transaction is aborted. A running finalization is left to complete.
+#[derive(Debug, Clone)]
+pub struct ShutdownQueue {}
+
+impl Message for ShutdownQueue {
+    type Result = ();
+}
+
+impl Handler<ShutdownQueue> for OperationQueue {
+    type Result = ResponseFuture<()>;
+
+    fn handle(&mut self, _msg: ShutdownQueue, _ctx: &mut Self::Context) -> Self::Result {
+        trace!("rpm-ostree queue: request to shut down");
+        self.closed = true;
+        for op in std::mem::take(&mut self.queued) {
+            log::info!(
+                "cancelling queued rpm-ostree operation '{}', shutting down",
+                op.label
+            );
+            let err = anyhow!("operation '{}' cancelled, shutting down", op.label);
+            op.operation.cancel(err);
+        }
+        self.refresh_status();
+
+        let label = match self.in_flight.iter().find(|(p, _)| *p == Priority::Stage) {
+            Some((_, label)) => label.clone(),
+            None => return Box::pin(futures::future::ready(())),
+        };
+        log::info!("aborting in-flight rpm-ostree operation '{}'", label);
+        let abort =
+            tokio::task::spawn_blocking(super::cli_cancel::cancel_transaction).map(move |res| {
+                let res = res.unwrap_or_else(|e| Err(e.into()));
+                if let Err(e) = res {
+                    log::error!("failed to abort rpm-ostree operation '{}': {:#}", label, e);
+                }
+            });
+        Box::pin(abort)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Request: drain queued operations, returning them in execution order.
+    struct DrainQueue {}
+
+    impl Message for DrainQueue {
+        type Result = Vec<String>;
+    }
+
+    impl Handler<DrainQueue> for OperationQueue {
+        type Result = MessageResult<DrainQueue>;
+
+        fn handle(&mut self, _msg: DrainQueue, _ctx: &mut Self::Context) -> Self::Result {
+            self.in_flight.clear();
+            let labels = std::iter::from_fn(|| {
+                let index = self.next_index()?;
+                Some(self.queued.remove(index).label)
+            })
+            .collect();
+            MessageResult(labels)
+        }
+    }
+
+    /// Start a queue whose operations never get to run, as both a transaction
+    /// (with the given priority) and a status query are pretended to be in flight.
+    fn busy_queue(max_queued: usize, transaction: Priority) -> Addr<OperationQueue> {
+        let mut queue = OperationQueue::new(RpmOstreeClient::default(), max_queued);
+        queue.in_flight = vec![
+            (Priority::Status, "busy".to_string()),
+            (transaction, "busy".to_string()),
+        ];
+        queue.start()
+    }
+
+    #[test]
+    fn queue_ordering() {
+        let sys = actix::System::new();
+        sys.block_on(async {
+            let queue = busy_queue(8, Priority::Stage);
+            let releases: Vec<Release> = ["1.0", "2.0"]
+                .iter()
+                .map(|version| Release {
+                    version: version.to_string(),
+                    checksum: format!("{}-checksum", version),
+                    age_index: None,
+                    advisory: None,
+                    wave: None,
+                })
+                .collect();
+
+            let _status = queue.send(QueryStagedDowngrade {});
+            let stale = queue.send(StageDeployment {
+                allow_downgrade: false,
+                cache_only: false,
+                release: releases[0].clone(),
+            });
+            let _stage = queue.send(StageDeployment {
+                allow_downgrade: false,
+                cache_only: false,
+                release: releases[1].clone(),
+            });
+            let _finalize = queue.send(FinalizeDeployment {
+                allow_unlocked: false,
+                release: releases[1].clone(),
+            });
+
+            let err = stale.await.unwrap().unwrap_err();
+            assert!(err.to_string().contains("superseded"), "{}", err);
+
+            let labels = queue.send(DrainQueue {}).await.unwrap();
+            assert_eq!(
+                labels,
+                vec!["finalize 2.0", "stage 2.0", "check staged downgrade"]
+            );
+        });
+    }
+
+    #[test]
+    fn queue_concurrency() {
+        let sys = actix::System::new();
+        sys.block_on(async {
+            let mut queue = OperationQueue::new(RpmOstreeClient::default(), 8);
+            assert!(queue.can_run(Priority::Stage));
+
+            // Status queries run alongside a transaction, but not alongside each other.
+            queue.in_flight = vec![(Priority::Stage, "stage 1.0".to_string())];
+            assert!(queue.can_run(Priority::Status));
+            assert!(!queue.can_run(Priority::Finalize));
+            assert!(!queue.can_run(Priority::Stage));
+
+            queue.in_flight = vec![(Priority::Status, "status".to_string())];
+            assert!(queue.can_run(Priority::Finalize));
+            assert!(!queue.can_run(Priority::Status));
+        });
+    }
+
+    #[test]
+    fn queue_full() {
+        let sys = actix::System::new();
+        sys.block_on(async {
+            let queue = busy_queue(1, Priority::Stage);
+
+            let _queued = queue.send(QueryStagedDowngrade {});
+            let rejected = queue.send(QueryStagedDowngrade {}).await.unwrap();
+            let err = rejected.unwrap_err();
+            assert!(err.to_string().contains("already queued"), "{}", err);
+
+            let labels = queue.send(DrainQueue {}).await.unwrap();
+            assert_eq!(labels, vec!["check staged downgrade"]);
+        });
+    }
+
+    #[test]
+    fn queue_shutdown() {
+        let sys = actix::System::new();
+        sys.block_on(async {
+            // A running finalization is never aborted.
+            let queue = busy_queue(8, Priority::Finalize);
+
+            let queued = queue.send(QueryStagedDowngrade {});
+            queue.send(ShutdownQueue {}).await.unwrap();
+            let err = queued.await.unwrap().unwrap_err();
+            assert!(err.to_string().contains("shutting down"), "{}", err);
+
+            let rejected = queue.send(QueryStagedDowngrade {}).await.unwrap();
+            rejected.unwrap_err();
+            let labels = queue.send(DrainQueue {}).await.unwrap();
+            assert!(labels.is_empty());
+        });
+    }
+}
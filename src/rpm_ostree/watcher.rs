@@ -0,0 +1,278 @@
+//! Watcher for changes to local deployments.
+//!
+//! Local deployments (and the staged one) are watched via inotify, so that
+//! the status cache is refreshed as soon as they change, including changes
+//! made out-of-band (e.g. by a manual `rpm-ostree upgrade`). Without a
+//! watcher, the cache is checked against the modification time of local
+//! deployments on each query, which is also the fallback while some
+//! directory cannot be watched (e.g. `/run/ostree` does not exist yet).
+//! Reading events is blocking, thus it runs on a dedicated thread.
+
+use super::actor::{DeploymentsChanged, RpmOstreeClient};
+use actix::dev::SendError;
+use actix::Addr;
+use anyhow::{bail, Context, Result};
+use prometheus::IntCounter;
+use std::ffi::CString;
+use std::fs::File;
+use std::io::Read;
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::path::{Path, PathBuf};
+
+lazy_static::lazy_static! {
+    static ref DEPLOYMENTS_CHANGES: IntCounter = register_int_counter!(opts!(
+        "zincati_rpm_ostree_deployments_changes_total",
+        "Total number of changes to local deployments noticed by the watcher."
+    )).unwrap();
+}
+
+/// Directory holding the staged deployment, if any.
+const STAGED_DEPL_DIR: &str = "/run/ostree";
+
+/// Quiet period for a burst of events to settle (in milliseconds).
+const SETTLE_MILLIS: i32 = 500;
+
+/// Interval between attempts to watch missing directories (in milliseconds).
+const RETRY_WATCH_MILLIS: i32 = 60_000;
+
+/// Events signaling a change to a watched directory.
+const WATCH_MASK: u32 = libc::IN_ATTRIB
+    | libc::IN_CLOSE_WRITE
+    | libc::IN_CREATE
+    | libc::IN_DELETE
+    | libc::IN_DELETE_SELF
+    | libc::IN_MOVED_FROM
+    | libc::IN_MOVED_TO
+    | libc::IN_MOVE_SELF;
+
+/// Inotify watches on deployment paths.
+#[derive(Debug)]
+pub(super) struct DeploymentsWatcher {
+    inotify: File,
+    /// Watched directories, by watch descriptor.
+    watched: Vec<(i32, PathBuf)>,
+    /// Directories which could not be watched yet.
+    missing: Vec<PathBuf>,
+}
+
+impl DeploymentsWatcher {
+    /// Watch local deployments and the staged deployment.
+    pub(super) fn new() -> Result<Self> {
+        let paths = [
+            Path::new(super::cli_status::OSTREE_DEPLS_PATH),
+            Path::new(STAGED_DEPL_DIR),
+        ];
+        Self::with_paths(&paths)
+    }
+
+    /// Watch the given directories.
+    ///
+    /// Missing directories are watched as soon as they show up.
+    fn with_paths(paths: &[&Path]) -> Result<Self> {
+        let fd = unsafe { libc::inotify_init1(libc::IN_CLOEXEC) };
+        if fd < 0 {
+            return Err(std::io::Error::last_os_error()).context("failed to initialize inotify");
+        }
+        // The file owns the descriptor from now on, closing it on drop.
+        let mut watcher = Self {
+            inotify: unsafe { File::from_raw_fd(fd) },
+            watched: vec![],
+            missing: vec![],
+        };
+
+        for path in paths {
+            match watcher.add_watch(path) {
+                Ok(()) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                    log::warn!("'{}' missing, not watching it yet", path.display());
+                    watcher.missing.push(path.to_path_buf());
+                }
+                Err(e) => {
+                    return Err(e).with_context(|| format!("failed to watch '{}'", path.display()))
+                }
+            }
+        }
+        Ok(watcher)
+    }
+
+    /// Whether all directories are being watched.
+    pub(super) fn is_complete(&self) -> bool {
+        self.missing.is_empty()
+    }
+
+    /// Add a watch on a directory.
+    fn add_watch(&mut self, path: &Path) -> std::io::Result<()> {
+        let c_path = CString::new(path.to_string_lossy().as_bytes())?;
+        let wd = unsafe {
+            libc::inotify_add_watch(self.inotify.as_raw_fd(), c_path.as_ptr(), WATCH_MASK)
+        };
+        if wd < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        self.watched.push((wd, path.to_path_buf()));
+        Ok(())
+    }
+
+    /// Retry watching missing directories, returning whether any is now watched.
+    fn watch_missing(&mut self) -> bool {
+        let mut added = false;
+        for path in std::mem::take(&mut self.missing) {
+            match self.add_watch(&path) {
+                Ok(()) => {
+                    log::info!("watching '{}' for deployment changes", path.display());
+                    added = true;
+                }
+                Err(e) => {
+                    log::trace!("failed to watch '{}': {}", path.display(), e);
+                    self.missing.push(path);
+                }
+            }
+        }
+        added
+    }
+
+    /// Notify the client of changes on a dedicated thread, until watching fails.
+    pub(super) fn spawn(self, client: Addr<RpmOstreeClient>) -> Result<()> {
+        std::thread::Builder::new()
+            .name("deployments-watcher".to_string())
+            .spawn(move || {
+                if let Err(e) = self.run(&client) {
+                    log::warn!("stopped watching local deployments: {:#}", e);
+                }
+                client.do_send(DeploymentsChanged { watched: false });
+            })
+            .context("failed to spawn deployments watcher")?;
+        Ok(())
+    }
+
+    /// Wait for changes, notifying the client once each burst settles.
+    ///
+    /// Missing directories are periodically retried in the meantime.
+    fn run(mut self, client: &Addr<RpmOstreeClient>) -> Result<()> {
+        loop {
+            let timeout = if self.is_complete() {
+                -1
+            } else {
+                RETRY_WATCH_MILLIS
+            };
+            if self.poll(timeout)? {
+                self.read_events()?;
+                // Deployment operations touch several paths in a row.
+                while self.poll(SETTLE_MILLIS)? {
+                    self.read_events()?;
+                }
+                DEPLOYMENTS_CHANGES.inc();
+            } else if !self.watch_missing() {
+                continue;
+            }
+
+            let msg = DeploymentsChanged {
+                watched: self.is_complete(),
+            };
+            match client.try_send(msg) {
+                Ok(()) => {}
+                // The pending notification refreshes the cache after this change too.
+                Err(SendError::Full(_)) => log::trace!("deployments change already notified"),
+                Err(SendError::Closed(_)) => bail!("rpm-ostree client went away"),
+            }
+        }
+    }
+
+    /// Read pending events, blocking until at least one is available.
+    ///
+    /// Events themselves are not relevant, as any change invalidates the
+    /// status cache, but watches must still be in place.
+    fn read_events(&mut self) -> Result<()> {
+        let mut buf = [0u8; 4096];
+        let len = self
+            .inotify
+            .read(&mut buf)
+            .context("failed to read inotify events")?;
+
+        let header_len = std::mem::size_of::<libc::inotify_event>();
+        let mut offset = 0;
+        while offset + header_len <= len {
+            let event: libc::inotify_event =
+                unsafe { std::ptr::read_unaligned(buf[offset..].as_ptr() as *const _) };
+            if event.mask & libc::IN_IGNORED != 0 {
+                self.unwatch(event.wd);
+            }
+            if event.mask & libc::IN_Q_OVERFLOW != 0 {
+                log::debug!("inotify queue overflowed, some events were lost");
+            }
+            offset += header_len + event.len as usize;
+        }
+        Ok(())
+    }
+
+    /// Forget a removed watch, retrying its directory later on.
+    fn unwatch(&mut self, wd: i32) {
+        if let Some(index) = self.watched.iter().position(|(w, _)| *w == wd) {
+            let (_, path) = self.watched.remove(index);
+            log::warn!("'{}' went away, not watching it anymore", path.display());
+            self.missing.push(path);
+        }
+    }
+
+    /// Return whether events are available within `timeout_millis`.
+    fn poll(&self, timeout_millis: i32) -> Result<bool> {
+        let mut pollfd = libc::pollfd {
+            fd: self.inotify.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let ret = unsafe { libc::poll(&mut pollfd, 1, timeout_millis) };
+        if ret < 0 {
+            let err = std::io::Error::last_os_error();
+            if err.kind() == std::io::ErrorKind::Interrupted {
+                return Ok(false);
+            }
+            return Err(err).context("failed to poll inotify events");
+        }
+        Ok(ret > 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn watcher_events() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let mut watcher = DeploymentsWatcher::with_paths(&[tmpdir.path()]).unwrap();
+        assert!(watcher.is_complete());
+        assert!(!watcher.poll(0).unwrap());
+
+        std::fs::write(tmpdir.path().join("staged-deployment"), "{}").unwrap();
+        assert!(watcher.poll(1000).unwrap());
+        watcher.read_events().unwrap();
+
+        // Removing a watched directory retries watching it.
+        let path = tmpdir.path().to_path_buf();
+        drop(tmpdir);
+        while watcher.poll(100).unwrap() {
+            watcher.read_events().unwrap();
+        }
+        assert!(!watcher.is_complete());
+        assert_eq!(watcher.missing, vec![path]);
+    }
+
+    #[test]
+    fn watcher_missing_dir() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let missing = tmpdir.path().join("missing");
+        let mut watcher = DeploymentsWatcher::with_paths(&[tmpdir.path(), &missing]).unwrap();
+        assert!(!watcher.is_complete());
+        assert!(!watcher.watch_missing());
+
+        std::fs::create_dir(&missing).unwrap();
+        while watcher.poll(100).unwrap() {
+            watcher.read_events().unwrap();
+        }
+        assert!(watcher.watch_missing());
+        assert!(watcher.is_complete());
+        std::fs::write(missing.join("staged-deployment"), "{}").unwrap();
+        assert!(watcher.poll(1000).unwrap());
+    }
+}
@@ -0,0 +1,252 @@
+//! Periodic self-test of control surfaces.
+//!
+//! The agent periodically exercises its own D-Bus service and metrics
+//! endpoints, the same way a local client would. This catches silent breakage
+//! (e.g. the bus name being lost, or a listener going away) long before an
+//! operator needs those surfaces during an incident.
+//! Probes are blocking, thus they run on a dedicated thread.
+
+use anyhow::{anyhow, Context, Result};
+use prometheus::{IntGauge, IntGaugeVec};
+use std::collections::BTreeSet;
+use std::net::SocketAddr;
+use std::sync::mpsc;
+use std::time::Duration;
+
+lazy_static::lazy_static! {
+    static ref SELF_TEST_FAILING: IntGaugeVec = register_int_gauge_vec!(
+        "zincati_self_test_failing",
+        "Whether the last self-test of a control surface failed.",
+        &["surface"]
+    ).unwrap();
+    static ref SELF_TEST_LAST_RUN: IntGauge = register_int_gauge!(opts!(
+        "zincati_self_test_last_run_timestamp",
+        "UTC timestamp of the last self-test of control surfaces."
+    )).unwrap();
+}
+
+/// Timeout for a single probe (in seconds).
+const PROBE_TIMEOUT_SECS: u64 = 10;
+
+/// A probe of a control surface.
+#[derive(Clone, Copy, Debug)]
+enum Probe {
+    /// D-Bus service, via its well-known name.
+    #[cfg(feature = "dbus")]
+    DBus,
+    /// Metrics endpoint on the Unix-domain socket.
+    #[cfg(feature = "metrics")]
+    MetricsSocket,
+    /// Metrics exporter over TCP.
+    #[cfg(feature = "metrics")]
+    MetricsTcp(SocketAddr),
+}
+
+impl Probe {
+    /// Label for the probed surface, as used in logs and metrics.
+    fn surface(self) -> &'static str {
+        match self {
+            #[cfg(feature = "dbus")]
+            Probe::DBus => "dbus",
+            #[cfg(feature = "metrics")]
+            Probe::MetricsSocket => "metrics-socket",
+            #[cfg(feature = "metrics")]
+            Probe::MetricsTcp(_) => "metrics-tcp",
+        }
+    }
+
+    /// Exercise the surface once.
+    fn run(self) -> Result<()> {
+        match self {
+            #[cfg(feature = "dbus")]
+            Probe::DBus => probe_dbus(),
+            #[cfg(feature = "metrics")]
+            Probe::MetricsSocket => probe_metrics_socket(),
+            #[cfg(feature = "metrics")]
+            Probe::MetricsTcp(address) => probe_metrics_tcp(address),
+        }
+    }
+
+    /// Exercise the surface once, giving up after `PROBE_TIMEOUT_SECS`.
+    ///
+    /// A stuck probe is left behind on its own thread.
+    fn run_with_timeout(self) -> Result<()> {
+        let (tx, rx) = mpsc::channel();
+        std::thread::Builder::new()
+            .name(format!("self-test-{}", self.surface()))
+            .spawn(move || {
+                let _ = tx.send(self.run());
+            })
+            .context("failed to spawn probe")?;
+
+        rx.recv_timeout(Duration::from_secs(PROBE_TIMEOUT_SECS))
+            .unwrap_or_else(|_| Err(anyhow!("timed out after {}s", PROBE_TIMEOUT_SECS)))
+    }
+}
+
+/// Prober for the agent control surfaces.
+#[derive(Debug)]
+pub(crate) struct SelfTest {
+    interval: Duration,
+    probes: Vec<Probe>,
+    /// Surfaces which failed their last probe.
+    failing: BTreeSet<&'static str>,
+}
+
+impl SelfTest {
+    /// Create a prober for all enabled control surfaces.
+    ///
+    /// `metrics_tcp` is the listening address of the metrics exporter over
+    /// TCP, if enabled.
+    pub(crate) fn new(interval: Duration, metrics_tcp: Option<SocketAddr>) -> Self {
+        #[allow(unused_mut)]
+        let mut probes = vec![];
+        #[cfg(feature = "dbus")]
+        probes.push(Probe::DBus);
+        #[cfg(feature = "metrics")]
+        {
+            probes.push(Probe::MetricsSocket);
+            if let Some(address) = metrics_tcp {
+                probes.push(Probe::MetricsTcp(loopback_address(address)));
+            }
+        }
+        #[cfg(not(feature = "metrics"))]
+        let _ = metrics_tcp;
+
+        Self {
+            interval,
+            probes,
+            failing: BTreeSet::new(),
+        }
+    }
+
+    /// Run probes periodically on a dedicated thread.
+    pub(crate) fn spawn(self) -> Result<()> {
+        if self.probes.is_empty() {
+            return Ok(());
+        }
+        for probe in &self.probes {
+            SELF_TEST_FAILING
+                .with_label_values(&[probe.surface()])
+                .set(0);
+        }
+
+        let interval = self.interval;
+        std::thread::Builder::new()
+            .name("self-test".to_string())
+            .spawn(move || self.run())
+            .context("failed to spawn self-test prober")?;
+
+        log::debug!(
+            "started self-test of control surfaces, every {} seconds",
+            interval.as_secs()
+        );
+        Ok(())
+    }
+
+    /// Probe all surfaces after each interval, forever.
+    fn run(mut self) {
+        loop {
+            std::thread::sleep(self.interval);
+            self.probe_all();
+        }
+    }
+
+    /// Probe all surfaces once, recording the outcome.
+    fn probe_all(&mut self) {
+        for probe in &self.probes {
+            let surface = probe.surface();
+            match probe.run_with_timeout() {
+                Ok(()) => {
+                    if self.failing.remove(surface) {
+                        log::info!("self-test of {} recovered", surface);
+                    }
+                    SELF_TEST_FAILING.with_label_values(&[surface]).set(0);
+                }
+                Err(e) => {
+                    log::warn!("self-test of {} failed: {:#}", surface, e);
+                    self.failing.insert(surface);
+                    SELF_TEST_FAILING.with_label_values(&[surface]).set(1);
+                }
+            }
+        }
+        SELF_TEST_LAST_RUN.set(chrono::Utc::now().timestamp());
+    }
+}
+
+/// Call a side-effect free method on our own D-Bus service.
+#[cfg(feature = "dbus")]
+fn probe_dbus() -> Result<()> {
+    let connection = zbus::Connection::new_system().context("failed to connect to system bus")?;
+    let reply = connection.call_method(
+        Some("org.coreos.zincati"),
+        "/org/coreos/zincati",
+        Some("org.coreos.zincati.Experimental"),
+        "Moo",
+        &false,
+    )?;
+    let moo: String = reply.body()?;
+    anyhow::ensure!(moo == "moo.", "unexpected reply '{}'", moo);
+    Ok(())
+}
+
+/// Scrape metrics from the Unix-domain socket.
+#[cfg(feature = "metrics")]
+fn probe_metrics_socket() -> Result<()> {
+    use std::io::Read;
+
+    let path = crate::metrics::SOCKET_PATH;
+    let mut stream = std::os::unix::net::UnixStream::connect(path)
+        .with_context(|| format!("failed to connect to '{}'", path))?;
+    stream.set_read_timeout(Some(Duration::from_secs(PROBE_TIMEOUT_SECS)))?;
+    let mut content = String::new();
+    stream.read_to_string(&mut content)?;
+    anyhow::ensure!(
+        content.contains("zincati_"),
+        "no agent metrics in scrape from '{}'",
+        path
+    );
+    Ok(())
+}
+
+/// Check that the metrics exporter over TCP accepts connections.
+///
+/// This does not perform a full scrape, as it may require credentials.
+#[cfg(feature = "metrics")]
+fn probe_metrics_tcp(address: SocketAddr) -> Result<()> {
+    let timeout = Duration::from_secs(PROBE_TIMEOUT_SECS);
+    std::net::TcpStream::connect_timeout(&address, timeout)
+        .with_context(|| format!("failed to connect to '{}'", address))?;
+    Ok(())
+}
+
+/// Return a loopback address for reaching a listener bound to `address`.
+#[cfg(feature = "metrics")]
+fn loopback_address(address: SocketAddr) -> SocketAddr {
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+    let ip = match address.ip() {
+        IpAddr::V4(ip) if ip.is_unspecified() => IpAddr::V4(Ipv4Addr::LOCALHOST),
+        IpAddr::V6(ip) if ip.is_unspecified() => IpAddr::V6(Ipv6Addr::LOCALHOST),
+        ip => ip,
+    };
+    SocketAddr::new(ip, address.port())
+}
+
+#[cfg(all(test, feature = "metrics"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_loopback_address() {
+        let cases = vec![
+            ("0.0.0.0:9101", "127.0.0.1:9101"),
+            ("[::]:9101", "[::1]:9101"),
+            ("192.0.2.10:9101", "192.0.2.10:9101"),
+        ];
+        for (input, expected) in cases {
+            let address: SocketAddr = input.parse().unwrap();
+            assert_eq!(loopback_address(address).to_string(), expected);
+        }
+    }
+}
@@ -0,0 +1,375 @@
+//! Monte-Carlo simulation of update rollouts across a fleet.
+//!
+//! This models how a single update propagates through a fleet of nodes, in
+//! order to estimate time-to-full-rollout for a given configuration. Each
+//! node goes through the following steps:
+//!  * the update becomes visible once rollout progress (linear over the
+//!    rollout duration) reaches the node wariness;
+//!  * the node notices it at its next steady-state check;
+//!  * the update is downloaded and staged;
+//!  * the node waits for the update strategy to allow finalization;
+//!  * the node reboots into the new release.
+//!
+//! All durations are jittered, and simulation is repeated over several runs.
+//!
+//! A single node configuration can also be replayed over a time range, to
+//! check when reboots would have been allowed.
+
+mod replay;
+pub use replay::{
+    human_replay_summary, ReplaySpan, ReplayVerdict, StrategyReplay, MAX_REPLAY_DAYS,
+};
+
+use crate::strategy::StrategyPeriodic;
+use anyhow::{ensure, Result};
+use chrono::{DateTime, Utc};
+use fn_error_context::context;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::fmt::Write;
+
+/// Finalization policy, as enforced by an update strategy.
+#[derive(Clone, Debug)]
+pub enum FinalizePolicy {
+    /// Finalize as soon as an update is staged.
+    Immediate,
+    /// Finalize only within weekly maintenance windows.
+    Periodic(StrategyPeriodic),
+    /// Finalize only after acquiring one of the available reboot slots.
+    FleetLock {
+        /// Number of concurrent reboot slots.
+        slots: u32,
+    },
+}
+
+/// Parameters for a fleet simulation.
+#[derive(Clone, Debug)]
+pub struct FleetSimulation {
+    /// Number of nodes in the fleet.
+    pub nodes: u32,
+    /// Number of simulation runs.
+    pub runs: u32,
+    /// Finalization policy.
+    pub policy: FinalizePolicy,
+    /// Point in time at which the rollout starts.
+    pub start: DateTime<Utc>,
+    /// Duration of the server-side rollout, in seconds.
+    pub rollout_secs: u64,
+    /// Fixed rollout wariness for all nodes (default: random per node).
+    pub wariness: Option<f64>,
+    /// Pausing interval between update checks in steady state, in seconds.
+    pub steady_interval_secs: u64,
+    /// Refresh period while waiting to finalize, in seconds.
+    pub refresh_period_secs: u64,
+    /// Average time to download and stage an update, in seconds.
+    pub stage_secs: u64,
+    /// Average time to reboot and report steady, in seconds.
+    pub reboot_secs: u64,
+    /// Seed for the random generator (default: random).
+    pub seed: Option<u64>,
+}
+
+impl FleetSimulation {
+    /// Run the simulation.
+    #[context("failed to simulate fleet rollout")]
+    pub fn run(&self) -> Result<SimulationReport> {
+        ensure!(self.nodes > 0, "fleet must contain at least one node");
+        ensure!(self.runs > 0, "at least one simulation run is required");
+        if let Some(w) = self.wariness {
+            ensure!((0.0..=1.0).contains(&w), "invalid rollout wariness: {}", w);
+        }
+        match &self.policy {
+            FinalizePolicy::FleetLock { slots } => {
+                ensure!(*slots > 0, "fleet_lock requires at least one slot")
+            }
+            FinalizePolicy::Periodic(p) => {
+                ensure!(p.schedule_length_minutes() > 0, "empty periodic schedule")
+            }
+            FinalizePolicy::Immediate => {}
+        };
+
+        let mut rng = match self.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+
+        let mut runs = Vec::with_capacity(self.runs as usize);
+        for _ in 0..self.runs {
+            let completions = self.simulate_once(&mut rng)?;
+            runs.push(completions);
+        }
+
+        Ok(SimulationReport { runs })
+    }
+
+    /// Simulate a single rollout, returning sorted completion times (in
+    /// seconds since rollout start) for all nodes.
+    fn simulate_once(&self, rng: &mut StdRng) -> Result<Vec<u64>> {
+        let mut staged = Vec::with_capacity(self.nodes as usize);
+        for _ in 0..self.nodes {
+            let wariness = match self.wariness {
+                Some(w) => w,
+                None => rng.gen_range(0.0..=1.0),
+            };
+            let visible = (self.rollout_secs as f64 * wariness) as u64;
+            let noticed = visible + jitter_upto(rng, self.steady_interval_secs);
+            let staged_at = noticed + jitter_around(rng, self.stage_secs);
+            staged.push(staged_at);
+        }
+
+        let mut completions = match &self.policy {
+            FinalizePolicy::Immediate => staged
+                .into_iter()
+                .map(|t| t + jitter_around(rng, self.reboot_secs))
+                .collect(),
+            FinalizePolicy::Periodic(strategy) => {
+                let mut done = Vec::with_capacity(staged.len());
+                for t in staged {
+                    let finalize_at = self.next_window(strategy, t, rng)?;
+                    done.push(finalize_at + jitter_around(rng, self.reboot_secs));
+                }
+                done
+            }
+            FinalizePolicy::FleetLock { slots } => self.fleet_lock(staged, *slots, rng),
+        };
+
+        completions.sort_unstable();
+        Ok(completions)
+    }
+
+    /// Return when a node staged at `staged_at` can finalize in a periodic window.
+    fn next_window(
+        &self,
+        strategy: &StrategyPeriodic,
+        staged_at: u64,
+        rng: &mut StdRng,
+    ) -> Result<u64> {
+        let datetime = self.start + chrono::Duration::seconds(staged_at as i64);
+        let remaining = match strategy.remaining_to_window(&datetime) {
+            Some(r) => r.num_seconds().max(0) as u64,
+            None => anyhow::bail!("no reachable periodic window"),
+        };
+        if remaining == 0 {
+            return Ok(staged_at);
+        }
+
+        // The window opening is noticed at the next agent refresh.
+        Ok(staged_at + remaining + jitter_upto(rng, self.refresh_period_secs))
+    }
+
+    /// Return completion times for nodes competing for reboot slots.
+    ///
+    /// Slots are acquired in order of staging, and each slot is released
+    /// once the holding node has rebooted and reported steady.
+    fn fleet_lock(&self, staged: Vec<u64>, slots: u32, rng: &mut StdRng) -> Vec<u64> {
+        let mut staged = staged;
+        staged.sort_unstable();
+
+        let mut free_slots: BinaryHeap<Reverse<u64>> = (0..slots).map(|_| Reverse(0)).collect();
+        let mut completions = Vec::with_capacity(staged.len());
+        for t in staged {
+            let Reverse(slot_free_at) = free_slots.pop().expect("no reboot slots");
+            let acquired_at = if slot_free_at <= t {
+                t
+            } else {
+                // The freed slot is noticed at the next agent refresh.
+                slot_free_at + jitter_upto(rng, self.refresh_period_secs)
+            };
+            let done = acquired_at + jitter_around(rng, self.reboot_secs);
+            free_slots.push(Reverse(done));
+            completions.push(done);
+        }
+        completions
+    }
+}
+
+/// Return a random duration in `[0, secs)`.
+fn jitter_upto(rng: &mut StdRng, secs: u64) -> u64 {
+    if secs == 0 {
+        return 0;
+    }
+    rng.gen_range(0..secs)
+}
+
+/// Return a random duration in `[secs/2, secs*3/2]`.
+fn jitter_around(rng: &mut StdRng, secs: u64) -> u64 {
+    if secs == 0 {
+        return 0;
+    }
+    rng.gen_range(secs / 2..=secs.saturating_add(secs / 2))
+}
+
+/// Outcome of a fleet simulation.
+#[derive(Clone, Debug)]
+pub struct SimulationReport {
+    /// Sorted per-node completion times (in seconds since rollout start),
+    /// for each run.
+    runs: Vec<Vec<u64>>,
+}
+
+impl SimulationReport {
+    /// Return completion times (in seconds since rollout start) at which the
+    /// given percentage of the fleet is updated, for each run.
+    pub fn completion_secs(&self, percent: u8) -> Vec<u64> {
+        let percent = u64::from(percent.min(100));
+        self.runs
+            .iter()
+            .map(|nodes| {
+                // Index of the node completing the requested share.
+                let index = (nodes.len() as u64 * percent).saturating_sub(1) / 100;
+                nodes[index as usize]
+            })
+            .collect()
+    }
+
+    /// Return a human-friendly summary of this report.
+    pub fn human_summary(&self) -> String {
+        let mut out = format!(
+            "Time to rollout completion, over {} runs:\n",
+            self.runs.len()
+        );
+        for percent in &[50, 90, 100] {
+            let mut secs = self.completion_secs(*percent);
+            secs.sort_unstable();
+            let mean = secs.iter().sum::<u64>() / secs.len() as u64;
+            let p95 = secs[(secs.len() * 95 - 1) / 100];
+            let _ = writeln!(
+                out,
+                " {:>3}% of nodes: mean {}, p95 {}, worst {}",
+                percent,
+                human_duration(mean),
+                human_duration(p95),
+                human_duration(*secs.last().unwrap_or(&0)),
+            );
+        }
+        out
+    }
+}
+
+/// Format a duration (in seconds) in human terms.
+pub fn human_duration(secs: u64) -> String {
+    let mins = secs / 60;
+    let (days, hours, minutes) = (mins / (24 * 60), (mins / 60) % 24, mins % 60);
+    let mut out = String::new();
+    if days > 0 {
+        let _ = write!(out, "{}d ", days);
+    }
+    if days > 0 || hours > 0 {
+        let _ = write!(out, "{}h ", hours);
+    }
+    let _ = write!(out, "{}m", minutes);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::inputs;
+    use chrono::TimeZone;
+
+    fn simulation(policy: FinalizePolicy) -> FleetSimulation {
+        FleetSimulation {
+            nodes: 100,
+            runs: 10,
+            policy,
+            start: Utc.with_ymd_and_hms(2021, 5, 3, 0, 0, 0).unwrap(),
+            rollout_secs: 24 * 3600,
+            wariness: None,
+            steady_interval_secs: 300,
+            refresh_period_secs: 300,
+            stage_secs: 600,
+            reboot_secs: 300,
+            seed: Some(42),
+        }
+    }
+
+    #[test]
+    fn test_human_duration() {
+        assert_eq!(human_duration(0), "0m");
+        assert_eq!(human_duration(59), "0m");
+        assert_eq!(human_duration(3 * 60), "3m");
+        assert_eq!(human_duration(2 * 3600 + 60), "2h 1m");
+        assert_eq!(human_duration(9 * 86400 + 60), "9d 0h 1m");
+    }
+
+    #[test]
+    fn test_invalid_params() {
+        let mut sim = simulation(FinalizePolicy::Immediate);
+        sim.nodes = 0;
+        sim.run().unwrap_err();
+
+        let sim = simulation(FinalizePolicy::FleetLock { slots: 0 });
+        sim.run().unwrap_err();
+
+        let mut sim = simulation(FinalizePolicy::Immediate);
+        sim.wariness = Some(1.5);
+        sim.run().unwrap_err();
+    }
+
+    #[test]
+    fn test_immediate() {
+        let sim = simulation(FinalizePolicy::Immediate);
+        let report = sim.run().unwrap();
+
+        // Rollout cannot complete before the server-side rollout ends, and
+        // should complete shortly afterwards.
+        let max_extra = sim.steady_interval_secs + 2 * (sim.stage_secs + sim.reboot_secs);
+        for secs in report.completion_secs(100) {
+            assert!(secs <= sim.rollout_secs + max_extra, "{}", secs);
+        }
+
+        // Seeded simulations are reproducible.
+        let other = sim.run().unwrap();
+        assert_eq!(report.completion_secs(90), other.completion_secs(90));
+    }
+
+    #[test]
+    fn test_fleet_lock_slots() {
+        let mut sim = simulation(FinalizePolicy::FleetLock { slots: 1 });
+        sim.wariness = Some(0.0);
+        let single = sim.run().unwrap();
+        // With a single slot, reboots are fully serialized.
+        let min_serial = u64::from(sim.nodes) * sim.reboot_secs / 2;
+        for secs in single.completion_secs(100) {
+            assert!(secs >= min_serial, "{}", secs);
+        }
+
+        sim.policy = FinalizePolicy::FleetLock { slots: 10 };
+        let multi = sim.run().unwrap();
+        let single_mean: u64 = single.completion_secs(100).iter().sum();
+        let multi_mean: u64 = multi.completion_secs(100).iter().sum();
+        assert!(multi_mean < single_mean);
+    }
+
+    #[test]
+    fn test_periodic_windows() {
+        let input = inputs::UpdateInput {
+            strategy: StrategyPeriodic::LABEL.to_string(),
+            periodic: inputs::PeriodicInput {
+                intervals: vec![inputs::PeriodicIntervalInput {
+                    start_day: "Sat".to_string(),
+                    start_time: "22:00".to_string(),
+                    length_minutes: 120,
+                    time_zone: None,
+                }],
+                time_zone: "UTC".to_string(),
+                window_jitter_minutes: 0,
+            },
+            ..Default::default()
+        };
+        let strategy = StrategyPeriodic::new(input).unwrap();
+        let mut sim = simulation(FinalizePolicy::Periodic(strategy));
+        sim.wariness = Some(0.0);
+        let report = sim.run().unwrap();
+
+        // Rollout starts on Monday, reboots happen on the next Saturday night.
+        let window_start = (5 * 24 + 22) * 3600;
+        let window_end = window_start + 2 * 3600;
+        for secs in report.completion_secs(100) {
+            assert!(secs >= window_start, "{}", secs);
+            assert!(secs <= window_end, "{}", secs);
+        }
+    }
+}
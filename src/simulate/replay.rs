@@ -0,0 +1,268 @@
+//! Replay of a finalization policy over a time range.
+//!
+//! This evaluates, minute by minute, whether a node with a staged update
+//! would have been allowed to reboot, according to the update strategy and
+//! blackout periods. FleetLock is stubbed, always granting a reboot slot.
+
+use super::{human_duration, FinalizePolicy};
+use crate::blackout::BlackoutPeriods;
+use anyhow::{ensure, Result};
+use chrono::{DateTime, Utc};
+use fn_error_context::context;
+use std::fmt::Write;
+
+/// Maximum length of a replayed time range, in days.
+pub const MAX_REPLAY_DAYS: i64 = 366;
+
+/// Outcome of a finalization check at some point in time.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReplayVerdict {
+    /// Reboots allowed.
+    Allowed,
+    /// Denied by the update strategy (e.g. outside of periodic windows).
+    Strategy,
+    /// Denied by a blackout period.
+    Blackout,
+}
+
+impl ReplayVerdict {
+    /// Return the label of this verdict.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ReplayVerdict::Allowed => "allowed",
+            ReplayVerdict::Strategy => "strategy",
+            ReplayVerdict::Blackout => "blackout",
+        }
+    }
+}
+
+/// Time span with a constant verdict.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ReplaySpan {
+    /// Start of the span.
+    pub start: DateTime<Utc>,
+    /// End of the span (excluded).
+    pub end: DateTime<Utc>,
+    /// Verdict over the whole span.
+    pub verdict: ReplayVerdict,
+}
+
+/// Parameters for a strategy replay.
+#[derive(Clone, Debug)]
+pub struct StrategyReplay {
+    /// Finalization policy.
+    pub policy: FinalizePolicy,
+    /// Configured blackout periods, if any.
+    pub blackout: Option<BlackoutPeriods>,
+    /// Start of the replayed range.
+    pub from: DateTime<Utc>,
+    /// End of the replayed range (excluded).
+    pub to: DateTime<Utc>,
+}
+
+impl StrategyReplay {
+    /// Run the replay, returning consecutive spans covering the whole range.
+    #[context("failed to replay update strategy")]
+    pub fn run(&self) -> Result<Vec<ReplaySpan>> {
+        ensure!(
+            self.from < self.to,
+            "range end '{}' must be later than its start '{}'",
+            self.to,
+            self.from
+        );
+        ensure!(
+            self.to - self.from <= chrono::Duration::days(MAX_REPLAY_DAYS),
+            "range longer than {} days",
+            MAX_REPLAY_DAYS
+        );
+
+        let step = chrono::Duration::minutes(1);
+        let mut spans: Vec<ReplaySpan> = vec![];
+        let mut datetime = self.from;
+        while datetime < self.to {
+            let end = std::cmp::min(datetime + step, self.to);
+            let verdict = self.verdict(&datetime, &end);
+            match spans.last_mut() {
+                Some(span) if span.verdict == verdict => span.end = end,
+                _ => spans.push(ReplaySpan {
+                    start: datetime,
+                    end,
+                    verdict,
+                }),
+            };
+            datetime = end;
+        }
+        Ok(spans)
+    }
+
+    /// Return the verdict for the span starting at `datetime` and ending at `end`.
+    fn verdict(&self, datetime: &DateTime<Utc>, end: &DateTime<Utc>) -> ReplayVerdict {
+        if let Some(blackout) = &self.blackout {
+            if blackout.active(datetime).is_some() {
+                return ReplayVerdict::Blackout;
+            }
+        }
+        let allowed = match &self.policy {
+            FinalizePolicy::Immediate | FinalizePolicy::FleetLock { .. } => true,
+            // Weekly windows include their final minute as a point in time,
+            // so a span is only allowed if it is entirely within a window.
+            FinalizePolicy::Periodic(p) => {
+                p.contains_datetime(datetime) && p.contains_datetime(end)
+            }
+        };
+        if allowed {
+            ReplayVerdict::Allowed
+        } else {
+            ReplayVerdict::Strategy
+        }
+    }
+}
+
+/// Return a human-friendly summary of replayed spans, listing the ones in
+/// which reboots are allowed.
+pub fn human_replay_summary(spans: &[ReplaySpan]) -> String {
+    let mut out = String::new();
+    let mut allowed_secs = 0;
+    let mut denied = [0u64; 2];
+    for span in spans {
+        let secs = (span.end - span.start).num_seconds().max(0) as u64;
+        match span.verdict {
+            ReplayVerdict::Allowed => {
+                allowed_secs += secs;
+                let _ = writeln!(
+                    out,
+                    "  {} - {}  ({})",
+                    span.start.format("%a %Y-%m-%d %H:%M %Z"),
+                    span.end.format("%a %Y-%m-%d %H:%M %Z"),
+                    human_duration(secs)
+                );
+            }
+            ReplayVerdict::Strategy => denied[0] += secs,
+            ReplayVerdict::Blackout => denied[1] += secs,
+        }
+    }
+    if out.is_empty() {
+        out.push_str("  none\n");
+    }
+
+    let _ = writeln!(out, "Total time allowed: {}", human_duration(allowed_secs));
+    for (verdict, secs) in [ReplayVerdict::Strategy, ReplayVerdict::Blackout]
+        .iter()
+        .zip(denied.iter())
+    {
+        if *secs > 0 {
+            let _ = writeln!(
+                out,
+                "Total time denied by {}: {}",
+                verdict.as_str(),
+                human_duration(*secs)
+            );
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::inputs;
+    use crate::strategy::StrategyPeriodic;
+    use chrono::TimeZone;
+
+    fn replay(policy: FinalizePolicy, days: i64) -> StrategyReplay {
+        let from = Utc.with_ymd_and_hms(2021, 5, 3, 0, 0, 0).unwrap();
+        StrategyReplay {
+            policy,
+            blackout: None,
+            from,
+            to: from + chrono::Duration::days(days),
+        }
+    }
+
+    #[test]
+    fn replay_invalid_range() {
+        replay(FinalizePolicy::Immediate, 0).run().unwrap_err();
+        replay(FinalizePolicy::Immediate, MAX_REPLAY_DAYS + 1)
+            .run()
+            .unwrap_err();
+    }
+
+    #[test]
+    fn replay_immediate_blackout() {
+        let mut replay = replay(FinalizePolicy::FleetLock { slots: 1 }, 7);
+        let spans = replay.run().unwrap();
+        assert_eq!(spans.len(), 1);
+        assert_eq!(spans[0].verdict, ReplayVerdict::Allowed);
+
+        let cfg = inputs::BlackoutInput {
+            periods: vec![inputs::BlackoutPeriodInput {
+                start: "2021-05-05".to_string(),
+                end: "2021-05-05".to_string(),
+                reason: String::new(),
+            }],
+        };
+        replay.blackout = BlackoutPeriods::with_config_since(cfg, replay.from).unwrap();
+        let spans = replay.run().unwrap();
+        let verdicts: Vec<_> = spans.iter().map(|s| s.verdict).collect();
+        assert_eq!(
+            verdicts,
+            vec![
+                ReplayVerdict::Allowed,
+                ReplayVerdict::Blackout,
+                ReplayVerdict::Allowed
+            ]
+        );
+        assert_eq!(
+            spans[1].start,
+            Utc.with_ymd_and_hms(2021, 5, 5, 0, 0, 0).unwrap()
+        );
+        assert_eq!(
+            spans[1].end,
+            Utc.with_ymd_and_hms(2021, 5, 6, 0, 0, 0).unwrap()
+        );
+
+        let summary = human_replay_summary(&spans);
+        assert!(
+            summary.contains("Total time allowed: 6d 0h 0m\n"),
+            "{}",
+            summary
+        );
+        assert!(summary.contains("Total time denied by blackout: 1d 0h 0m\n"));
+    }
+
+    #[test]
+    fn replay_periodic() {
+        let periodic = inputs::PeriodicInput {
+            intervals: vec![inputs::PeriodicIntervalInput {
+                start_day: "Sat".to_string(),
+                start_time: "22:00".to_string(),
+                length_minutes: 120,
+                time_zone: None,
+            }],
+            time_zone: "UTC".to_string(),
+            window_jitter_minutes: 0,
+        };
+        let strategy = StrategyPeriodic::with_windows(periodic).unwrap();
+        let spans = replay(FinalizePolicy::Periodic(strategy), 14)
+            .run()
+            .unwrap();
+
+        let allowed: Vec<_> = spans
+            .iter()
+            .filter(|s| s.verdict == ReplayVerdict::Allowed)
+            .map(|s| (s.start, s.end))
+            .collect();
+        let expected = vec![
+            (
+                Utc.with_ymd_and_hms(2021, 5, 8, 22, 0, 0).unwrap(),
+                Utc.with_ymd_and_hms(2021, 5, 9, 0, 0, 0).unwrap(),
+            ),
+            (
+                Utc.with_ymd_and_hms(2021, 5, 15, 22, 0, 0).unwrap(),
+                Utc.with_ymd_and_hms(2021, 5, 16, 0, 0, 0).unwrap(),
+            ),
+        ];
+        assert_eq!(allowed, expected);
+        assert_eq!(human_replay_summary(&spans).lines().count(), 4);
+    }
+}
@@ -1,15 +1,29 @@
 //! Strategy for fleet-wide coordinated updates (FleetLock protocol).
 
 use crate::config::inputs;
-use crate::fleet_lock::{Client, ClientBuilder};
-use crate::identity::Identity;
-use anyhow::{anyhow, Error, Result};
+use crate::fleet_lock::{
+    BearerToken, Client, ClientBuilder, FleetLockError, NodeHealth, UpdateConfirmation,
+    UpdateOutcome,
+};
+use crate::identity::{self, Identity};
+use crate::network::NetworkSettings;
+use crate::rpm_ostree::Release;
+use crate::utils;
+use anyhow::{anyhow, Context, Error, Result};
+use fn_error_context::context;
 use futures::prelude::*;
 use log::trace;
-use prometheus::IntCounterVec;
+use prometheus::{IntCounterVec, IntGaugeVec};
 use serde::Serialize;
+use std::path::Path;
 use std::pin::Pin;
 
+/// Absolute path to the persisted update pending confirmation.
+static PENDING_CONFIRMATION_PATH: &str = "/var/lib/zincati/fleet-lock-confirmation.json";
+
+/// Path to the system uptime.
+static UPTIME_PATH: &str = "/proc/uptime";
+
 lazy_static::lazy_static! {
     static ref FLEET_LOCK_REQUESTS: IntCounterVec = register_int_counter_vec!(
         "zincati_strategy_fleet_lock_requests_total",
@@ -21,21 +35,36 @@ lazy_static::lazy_static! {
         "Total number of errors while talking to the FleetLock server.",
         &["api", "kind"]
     ).unwrap();
+    static ref FLEET_LOCK_GROUP_LOCKED: IntGaugeVec = register_int_gauge_vec!(
+        "zincati_strategy_fleet_lock_group_locked",
+        "Whether a reboot slot is held in a FleetLock group.",
+        &["group"]
+    ).unwrap();
 }
 
 /// Strategy for remote coordination.
 #[derive(Clone, Debug, Serialize)]
-pub(crate) struct StrategyFleetLock {
-    /// Asynchronous client.
-    pub(crate) client: Client,
+pub struct StrategyFleetLock {
+    /// Asynchronous clients, one per reboot group.
+    pub clients: Vec<Client>,
+    /// Whether to confirm successful updates before unlocking.
+    pub confirm_updates: bool,
+    /// Whether to report a node health summary when unlocking.
+    pub report_health: bool,
+    /// Booted release version.
+    booted_version: String,
 }
 
 impl StrategyFleetLock {
     /// Strategy label/name.
-    pub const LABEL: &'static str = "fleet_lock";
+    pub const LABEL: &'static str = super::FLEET_LOCK_LABEL;
 
     /// Build a new FleetLock strategy.
-    pub fn new(cfg: inputs::UpdateInput, identity: &Identity) -> Result<Self> {
+    pub fn new(
+        cfg: inputs::UpdateInput,
+        identity: &Identity,
+        network: &NetworkSettings,
+    ) -> Result<Self> {
         // Substitute templated key with agent runtime values.
         let base_url = if envsubst::is_templated(&cfg.fleet_lock.base_url) {
             let context = identity.url_variables();
@@ -50,43 +79,326 @@ impl StrategyFleetLock {
         }
         log::info!("remote fleet_lock reboot manager: {}", &base_url);
 
-        let builder = ClientBuilder::new(base_url, identity);
-        let client = builder.build()?;
-        let strategy = Self { client };
+        let network = match (
+            cfg.fleet_lock.client_cert.trim(),
+            cfg.fleet_lock.client_key.trim(),
+        ) {
+            ("", "") => network.clone(),
+            ("", _) | (_, "") => {
+                anyhow::bail!("fleet_lock client certificate and key must be configured together")
+            }
+            (cert, key) => network.with_client_identity(cert, key)?,
+        };
+        let bearer_token = match cfg.fleet_lock.token_path.trim() {
+            "" => None,
+            path => {
+                if base_url.starts_with("http://") {
+                    log::warn!(
+                        "fleet_lock bearer token sent over plain HTTP to {}",
+                        base_url
+                    );
+                }
+                let path = utils::resolve_credential_path(path)?;
+                Some(BearerToken::new(path)?)
+            }
+        };
+
+        let groups = if cfg.fleet_lock.groups.is_empty() {
+            vec![identity.group.clone()]
+        } else {
+            cfg.fleet_lock.groups
+        };
+        let mut clients = Vec::with_capacity(groups.len());
+        for group in groups {
+            identity::validate_group_label(&group)?;
+            if clients.iter().any(|c: &Client| c.group() == group) {
+                anyhow::bail!("duplicate fleet_lock group '{}'", group);
+            }
+            let client = ClientBuilder::new(base_url.clone(), identity)
+                .group(group)
+                .network(network.clone())
+                .bearer_token(bearer_token.clone())
+                .build()?;
+            clients.push(client);
+        }
+        if clients.len() > 1 {
+            let groups: Vec<_> = clients.iter().map(Client::group).collect();
+            log::info!("fleet_lock reboot groups: {}", groups.join(", "));
+        }
+
+        let strategy = Self {
+            clients,
+            confirm_updates: cfg.fleet_lock.confirm_updates,
+            report_health: cfg.fleet_lock.report_health,
+            booted_version: identity.current_os.version.clone(),
+        };
         Ok(strategy)
     }
 
     /// Check if finalization is allowed.
-    pub(crate) fn can_finalize(&self) -> Pin<Box<dyn Future<Output = Result<bool, Error>>>> {
-        let api = "pre-reboot";
-        FLEET_LOCK_REQUESTS.with_label_values(&[api]).inc();
+    ///
+    /// A reboot slot must be granted in all groups. Groups are locked in
+    /// order; if any of them is not granted, slots already granted on this
+    /// attempt are released, so that nodes waiting for each other do not
+    /// hold slots indefinitely.
+    pub fn can_finalize(&self) -> Pin<Box<dyn Future<Output = Result<bool, Error>>>> {
         trace!("fleet_lock strategy, checking whether update can be finalized");
 
-        let res = self.client.pre_reboot().map_err(move |e| {
-            FLEET_LOCK_ERRORS
-                .with_label_values(&[api, &e.error_kind()])
-                .inc();
-            anyhow!("lock-manager {} failure: {}", api, e)
-        });
+        let clients = self.clients.clone();
+        let res = async move {
+            for (index, client) in clients.iter().enumerate() {
+                let outcome = lock(client).await;
+                if let Ok(true) = outcome {
+                    continue;
+                }
+                for acquired in &clients[..index] {
+                    log::info!(
+                        "releasing reboot slot in fleet_lock group '{}', not granted in group '{}'",
+                        acquired.group(),
+                        client.group()
+                    );
+                    if let Err(e) = unlock(acquired, None).await {
+                        log::warn!("{:#}", e);
+                    }
+                }
+                return outcome;
+            }
+            Ok(true)
+        };
         Box::pin(res)
     }
 
-    /// Try to report steady state.
-    pub(crate) fn report_steady(&self) -> Pin<Box<dyn Future<Output = Result<bool, Error>>>> {
-        let api = "steady-state";
-        FLEET_LOCK_REQUESTS.with_label_values(&[api]).inc();
+    /// Record a finalized update, to be confirmed or reported after reboot.
+    pub fn record_finalized(&self, booted: &Release, update: &Release) {
+        if !self.confirm_updates && !self.report_health {
+            return;
+        }
+
+        let confirmation = UpdateConfirmation {
+            from_version: booted.version.clone(),
+            to_version: update.version.clone(),
+        };
+        if let Err(e) = persist_confirmation(PENDING_CONFIRMATION_PATH, &confirmation) {
+            log::error!("{:#}", e);
+        }
+    }
+
+    /// Try to report steady state, confirming a pending update first (if any).
+    pub fn report_steady(&self) -> Pin<Box<dyn Future<Output = Result<bool, Error>>>> {
+        let health = if self.report_health {
+            Some(self.health_summary(PENDING_CONFIRMATION_PATH))
+        } else {
+            None
+        };
+        let update = if self.confirm_updates {
+            self.pending_confirmation(PENDING_CONFIRMATION_PATH)
+        } else {
+            None
+        };
+
+        match update {
+            None => self.unlock(health),
+            Some(update) => {
+                let strategy = self.clone();
+                let res = self.confirm_update(&update).and_then(move |_| {
+                    log::info!(
+                        "confirmed update from {} to {}",
+                        update.from_version,
+                        update.to_version
+                    );
+                    if let Err(e) = utils::remove_if_exists(PENDING_CONFIRMATION_PATH) {
+                        log::error!("{:#}", e);
+                    }
+                    strategy.unlock(health)
+                });
+                Box::pin(res)
+            }
+        }
+    }
+
+    /// Return the update pending confirmation persisted at `path`, if any.
+    ///
+    /// Updates which did not result in the expected booted release are
+    /// discarded without confirmation.
+    fn pending_confirmation(&self, path: impl AsRef<Path>) -> Option<UpdateConfirmation> {
+        let update = match load_confirmation(&path) {
+            Ok(u) => u?,
+            Err(e) => {
+                log::error!("{:#}", e);
+                return None;
+            }
+        };
+
+        if update.to_version != self.booted_version {
+            log::warn!(
+                "booted release {} does not match update to {}, skipping confirmation",
+                self.booted_version,
+                update.to_version
+            );
+            if let Err(e) = utils::remove_if_exists(&path) {
+                log::error!("{:#}", e);
+            }
+            return None;
+        }
+        Some(update)
+    }
+
+    /// Return a health summary for this node, including the outcome of
+    /// the last update recorded at `path` (if any).
+    fn health_summary(&self, path: impl AsRef<Path>) -> NodeHealth {
+        let last_update_outcome = match load_confirmation(path) {
+            Ok(Some(update)) if update.to_version == self.booted_version => {
+                UpdateOutcome::Succeeded
+            }
+            Ok(Some(_)) => UpdateOutcome::Failed,
+            Ok(None) => UpdateOutcome::NoneRecorded,
+            Err(e) => {
+                log::error!("{:#}", e);
+                UpdateOutcome::NoneRecorded
+            }
+        };
+        let uptime_secs = read_uptime_secs(UPTIME_PATH)
+            .map_err(|e| log::warn!("{:#}", e))
+            .ok();
+
+        NodeHealth {
+            booted_version: self.booted_version.clone(),
+            uptime_secs,
+            last_update_outcome,
+        }
+    }
+
+    /// Try to confirm a successful update, in all groups.
+    fn confirm_update(
+        &self,
+        update: &UpdateConfirmation,
+    ) -> Pin<Box<dyn Future<Output = Result<bool, Error>>>> {
+        trace!("fleet_lock strategy, attempting to confirm update");
+
+        let clients = self.clients.clone();
+        let update = update.clone();
+        let res = async move {
+            for client in &clients {
+                let api = "update-confirmation";
+                FLEET_LOCK_REQUESTS.with_label_values(&[api]).inc();
+                client
+                    .update_confirmation(&update)
+                    .await
+                    .map_err(|e| request_error(client, api, e))?;
+            }
+            Ok(true)
+        };
+        Box::pin(res)
+    }
+
+    /// Try to unlock reboot slots in all groups, optionally reporting a
+    /// health summary.
+    ///
+    /// Once reported in all groups, the last recorded update is cleared.
+    fn unlock(
+        &self,
+        health: Option<NodeHealth>,
+    ) -> Pin<Box<dyn Future<Output = Result<bool, Error>>>> {
         trace!("fleet_lock strategy, attempting to report steady");
 
-        let res = self.client.steady_state().map_err(move |e| {
-            FLEET_LOCK_ERRORS
-                .with_label_values(&[api, &e.error_kind()])
-                .inc();
-            anyhow!("lock-manager {} failure: {}", api, e)
-        });
+        let clients = self.clients.clone();
+        let res = async move {
+            for client in &clients {
+                unlock(client, health.as_ref()).await?;
+            }
+            if health.is_some() {
+                if let Err(e) = utils::remove_if_exists(PENDING_CONFIRMATION_PATH) {
+                    log::error!("{:#}", e);
+                }
+            }
+            Ok(true)
+        };
         Box::pin(res)
     }
 }
 
+/// Try to lock a reboot slot in the group of `client`.
+async fn lock(client: &Client) -> Result<bool> {
+    let api = "pre-reboot";
+    FLEET_LOCK_REQUESTS.with_label_values(&[api]).inc();
+    let locked = client
+        .pre_reboot()
+        .await
+        .map_err(|e| request_error(client, api, e))?;
+    if locked {
+        log::debug!(
+            "reboot slot granted in fleet_lock group '{}'",
+            client.group()
+        );
+    }
+    FLEET_LOCK_GROUP_LOCKED
+        .with_label_values(&[client.group()])
+        .set(i64::from(locked));
+    Ok(locked)
+}
+
+/// Try to unlock a reboot slot in the group of `client`, optionally
+/// reporting a health summary.
+async fn unlock(client: &Client, health: Option<&NodeHealth>) -> Result<bool> {
+    let api = "steady-state";
+    FLEET_LOCK_REQUESTS.with_label_values(&[api]).inc();
+    let unlocked = client
+        .steady_state(health)
+        .await
+        .map_err(|e| request_error(client, api, e))?;
+    FLEET_LOCK_GROUP_LOCKED
+        .with_label_values(&[client.group()])
+        .set(0);
+    Ok(unlocked)
+}
+
+/// Record and contextualize a failed request to the FleetLock server.
+fn request_error(client: &Client, api: &str, err: FleetLockError) -> Error {
+    FLEET_LOCK_ERRORS
+        .with_label_values(&[api, &err.error_kind()])
+        .inc();
+    anyhow!(
+        "lock-manager {} failure for group '{}': {}",
+        api,
+        client.group(),
+        err
+    )
+}
+
+/// Load an update pending confirmation from `path`, if any.
+#[context("failed to load update pending confirmation")]
+fn load_confirmation(path: impl AsRef<Path>) -> Result<Option<UpdateConfirmation>> {
+    let path = path.as_ref();
+    let content = match std::fs::read(path) {
+        Ok(c) => c,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).with_context(|| format!("failed to read '{}'", path.display())),
+    };
+    let update = serde_json::from_slice(&content)
+        .with_context(|| format!("failed to parse '{}'", path.display()))?;
+    Ok(Some(update))
+}
+
+/// Read the time since boot from `path` (in `/proc/uptime` format), in seconds.
+#[context("failed to read system uptime")]
+fn read_uptime_secs(path: impl AsRef<Path>) -> Result<u64> {
+    let content = std::fs::read_to_string(path)?;
+    let uptime: f64 = content
+        .split_whitespace()
+        .next()
+        .unwrap_or_default()
+        .parse()
+        .context("failed to parse uptime")?;
+    Ok(uptime as u64)
+}
+
+/// Persist an update pending confirmation to `path`.
+#[context("failed to persist update pending confirmation")]
+fn persist_confirmation(path: impl AsRef<Path>, update: &UpdateConfirmation) -> Result<()> {
+    let content = serde_json::to_vec(update)?;
+    utils::atomic_write(path, 0o644, &content)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -97,19 +409,20 @@ mod tests {
     fn test_url_simple() {
         let id = Identity::mock_default();
         let input = UpdateInput {
-            allow_downgrade: false,
-            enabled: true,
             strategy: "fleet_lock".to_string(),
             fleet_lock: FleetLockInput {
                 base_url: "https://example.com".to_string(),
+                confirm_updates: false,
+                report_health: true,
+                token_path: String::new(),
+                client_cert: String::new(),
+                client_key: String::new(),
+                groups: vec![],
             },
-            periodic: PeriodicInput {
-                intervals: vec![],
-                time_zone: "UTC".to_string(),
-            },
+            ..Default::default()
         };
 
-        let res = StrategyFleetLock::new(input, &id);
+        let res = StrategyFleetLock::new(input, &id, &NetworkSettings::default());
         assert!(res.is_ok());
     }
 
@@ -117,19 +430,146 @@ mod tests {
     fn test_empty_url() {
         let id = Identity::mock_default();
         let input = UpdateInput {
-            allow_downgrade: false,
-            enabled: true,
             strategy: "fleet_lock".to_string(),
             fleet_lock: FleetLockInput {
                 base_url: String::new(),
+                confirm_updates: false,
+                report_health: true,
+                token_path: String::new(),
+                client_cert: String::new(),
+                client_key: String::new(),
+                groups: vec![],
             },
             periodic: PeriodicInput {
                 intervals: vec![],
                 time_zone: "localtime".to_string(),
+                window_jitter_minutes: 0,
             },
+            ..Default::default()
         };
 
-        let res = StrategyFleetLock::new(input, &id);
+        let res = StrategyFleetLock::new(input, &id, &NetworkSettings::default());
         assert!(res.is_err());
     }
+
+    #[test]
+    fn test_pending_confirmation() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let path = tmpdir.path().join("fleet-lock-confirmation.json");
+        let strategy = StrategyFleetLock {
+            clients: vec![
+                ClientBuilder::new("https://example.com", &Identity::mock_default())
+                    .build()
+                    .unwrap(),
+            ],
+            confirm_updates: true,
+            report_health: false,
+            booted_version: "v2".to_string(),
+        };
+        assert_eq!(strategy.pending_confirmation(&path), None);
+
+        let update = UpdateConfirmation {
+            from_version: "v1".to_string(),
+            to_version: "v2".to_string(),
+        };
+        persist_confirmation(&path, &update).unwrap();
+        assert_eq!(strategy.pending_confirmation(&path), Some(update));
+
+        // Updates to a release other than the booted one are discarded.
+        let rolled_back = UpdateConfirmation {
+            from_version: "v1".to_string(),
+            to_version: "v3".to_string(),
+        };
+        persist_confirmation(&path, &rolled_back).unwrap();
+        assert_eq!(strategy.pending_confirmation(&path), None);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_health_summary() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let path = tmpdir.path().join("fleet-lock-confirmation.json");
+        let strategy = StrategyFleetLock {
+            clients: vec![
+                ClientBuilder::new("https://example.com", &Identity::mock_default())
+                    .build()
+                    .unwrap(),
+            ],
+            confirm_updates: false,
+            report_health: true,
+            booted_version: "v2".to_string(),
+        };
+        let health = strategy.health_summary(&path);
+        assert_eq!(health.booted_version, "v2");
+        assert_eq!(health.last_update_outcome, UpdateOutcome::NoneRecorded);
+
+        let update = UpdateConfirmation {
+            from_version: "v1".to_string(),
+            to_version: "v2".to_string(),
+        };
+        persist_confirmation(&path, &update).unwrap();
+        let health = strategy.health_summary(&path);
+        assert_eq!(health.last_update_outcome, UpdateOutcome::Succeeded);
+
+        let rolled_back = UpdateConfirmation {
+            from_version: "v1".to_string(),
+            to_version: "v3".to_string(),
+        };
+        persist_confirmation(&path, &rolled_back).unwrap();
+        let health = strategy.health_summary(&path);
+        assert_eq!(health.last_update_outcome, UpdateOutcome::Failed);
+    }
+
+    #[test]
+    fn test_multiple_groups() {
+        use mockito::Matcher;
+
+        let id = Identity::mock_default();
+        let client = |group: &str| {
+            ClientBuilder::new(mockito::server_url(), &id)
+                .group(group.to_string())
+                .build()
+                .unwrap()
+        };
+        let strategy = StrategyFleetLock {
+            clients: vec![client("storage"), client("rack-12")],
+            confirm_updates: false,
+            report_health: false,
+            booted_version: "v1".to_string(),
+        };
+        let group = |name: &str| {
+            Matcher::PartialJsonString(format!(r#"{{"client_params": {{"group": "{}"}}}}"#, name))
+        };
+
+        let m_lock_storage = mockito::mock("POST", "/v1/pre-reboot")
+            .match_body(group("storage"))
+            .with_status(200)
+            .create();
+        let m_lock_rack = mockito::mock("POST", "/v1/pre-reboot")
+            .match_body(group("rack-12"))
+            .with_status(409)
+            .create();
+        let m_unlock_storage = mockito::mock("POST", "/v1/steady-state")
+            .match_body(group("storage"))
+            .with_status(200)
+            .create();
+
+        // Slot in the first group is released, as the second one is not granted.
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(strategy.can_finalize()).unwrap_err();
+        m_lock_storage.assert();
+        m_lock_rack.assert();
+        m_unlock_storage.assert();
+    }
+
+    #[test]
+    fn test_read_uptime() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let path = tmpdir.path().join("uptime");
+        std::fs::write(&path, "350735.47 234388.90\n").unwrap();
+        assert_eq!(read_uptime_secs(&path).unwrap(), 350735);
+
+        std::fs::write(&path, "").unwrap();
+        read_uptime_secs(&path).unwrap_err();
+    }
 }
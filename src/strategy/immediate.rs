@@ -9,21 +9,22 @@ use std::pin::Pin;
 
 /// Strategy for immediate updates.
 #[derive(Clone, Debug, Default, Serialize)]
-pub(crate) struct StrategyImmediate {}
+pub struct StrategyImmediate {}
 
 impl StrategyImmediate {
     /// Strategy label/name.
     pub const LABEL: &'static str = "immediate";
 
     /// Check if finalization is allowed.
-    pub(crate) fn can_finalize(&self) -> Pin<Box<dyn Future<Output = Result<bool, Error>>>> {
+    pub fn can_finalize(&self) -> Pin<Box<dyn Future<Output = Result<bool, Error>>>> {
         trace!("immediate strategy, can finalize updates: {}", true);
 
         let res = future::ok(true);
         Box::pin(res)
     }
 
-    pub(crate) fn report_steady(&self) -> Pin<Box<dyn Future<Output = Result<bool, Error>>>> {
+    /// Try to report steady state.
+    pub fn report_steady(&self) -> Pin<Box<dyn Future<Output = Result<bool, Error>>>> {
         trace!("immediate strategy, report steady: {}", true);
 
         let immediate = future::ok(true);
@@ -41,7 +42,7 @@ mod tests {
         let default = StrategyImmediate::default();
         let runtime = rt::Runtime::new().unwrap();
         let steady = runtime.block_on(default.report_steady()).unwrap();
-        assert_eq!(steady, true);
+        assert!(steady);
     }
 
     #[test]
@@ -49,6 +50,6 @@ mod tests {
         let default = StrategyImmediate::default();
         let runtime = rt::Runtime::new().unwrap();
         let can_finalize = runtime.block_on(default.can_finalize()).unwrap();
-        assert_eq!(can_finalize, true);
+        assert!(can_finalize);
     }
 }
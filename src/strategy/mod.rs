@@ -2,21 +2,31 @@
 
 use crate::config::inputs;
 use crate::identity::Identity;
+use crate::network::NetworkSettings;
+use crate::rpm_ostree::Release;
 use anyhow::Result;
+use chrono::{DateTime, Utc};
 use fn_error_context::context;
 use futures::prelude::*;
 use log::error;
 use prometheus::{IntGauge, IntGaugeVec};
 use serde::Serialize;
 
+#[cfg(feature = "fleet-lock")]
 mod fleet_lock;
-pub(crate) use fleet_lock::StrategyFleetLock;
+#[cfg(feature = "fleet-lock")]
+pub use fleet_lock::StrategyFleetLock;
 
 mod immediate;
-pub(crate) use immediate::StrategyImmediate;
+pub use immediate::StrategyImmediate;
 
 mod periodic;
-pub(crate) use periodic::StrategyPeriodic;
+pub use periodic::StrategyPeriodic;
+
+/// Configuration label for the "fleet_lock" strategy.
+///
+/// This is available even if FleetLock support is compiled out.
+pub const FLEET_LOCK_LABEL: &str = "fleet_lock";
 
 lazy_static::lazy_static! {
     static ref STRATEGY_MODE: IntGaugeVec = register_int_gauge_vec!(
@@ -31,20 +41,36 @@ lazy_static::lazy_static! {
     ).unwrap();
 }
 
+/// Update strategy, deciding when updates can be finalized.
 #[derive(Clone, Debug, Serialize)]
-pub(crate) enum UpdateStrategy {
+pub enum UpdateStrategy {
+    /// Cluster-wide reboot coordination via FleetLock.
+    #[cfg(feature = "fleet-lock")]
     FleetLock(StrategyFleetLock),
+    /// Finalize updates as soon as they are staged.
     Immediate(StrategyImmediate),
+    /// Finalize updates only within weekly maintenance windows.
     Periodic(StrategyPeriodic),
 }
 
 impl UpdateStrategy {
     /// Try to parse config inputs into a valid strategy.
     #[context("failed to validate update strategy configuration")]
-    pub(crate) fn with_config(cfg: inputs::UpdateInput, identity: &Identity) -> Result<Self> {
+    #[cfg_attr(not(feature = "fleet-lock"), allow(unused_variables))]
+    pub fn with_config(
+        cfg: inputs::UpdateInput,
+        identity: &Identity,
+        network: &NetworkSettings,
+    ) -> Result<Self> {
         let strategy_name = cfg.strategy.clone();
         let strategy = match strategy_name.as_ref() {
-            StrategyFleetLock::LABEL => UpdateStrategy::new_fleet_lock(cfg, identity)?,
+            #[cfg(feature = "fleet-lock")]
+            StrategyFleetLock::LABEL => UpdateStrategy::new_fleet_lock(cfg, identity, network)?,
+            #[cfg(not(feature = "fleet-lock"))]
+            FLEET_LOCK_LABEL => anyhow::bail!(
+                "strategy '{}' not supported, agent built without `fleet-lock` feature",
+                FLEET_LOCK_LABEL
+            ),
             StrategyImmediate::LABEL => UpdateStrategy::new_immediate(),
             StrategyPeriodic::LABEL => UpdateStrategy::new_periodic(cfg)?,
             "" => UpdateStrategy::default(),
@@ -55,14 +81,16 @@ impl UpdateStrategy {
     }
 
     /// Record strategy details to metrics and logs.
-    pub(crate) fn record_details(&self) {
+    pub fn record_details(&self) {
         self.refresh_metrics();
         log::info!("update strategy: {}", self.human_description());
     }
 
     /// Refresh strategy-related metrics values.
-    pub(crate) fn refresh_metrics(&self) {
-        // Export info-metrics with details about current strategy.
+    pub fn refresh_metrics(&self) {
+        // Export info-metrics with details about current strategy, dropping
+        // any previous one (e.g. on configuration reload).
+        STRATEGY_MODE.reset();
         STRATEGY_MODE
             .with_label_values(&[self.configuration_label()])
             .set(1);
@@ -77,8 +105,9 @@ impl UpdateStrategy {
     ///
     /// This can be used to match back an instantiated strategy to the mode label
     /// from configuration.
-    fn configuration_label(&self) -> &'static str {
+    pub fn configuration_label(&self) -> &'static str {
         match self {
+            #[cfg(feature = "fleet-lock")]
             UpdateStrategy::FleetLock(_) => StrategyFleetLock::LABEL,
             UpdateStrategy::Immediate(_) => StrategyImmediate::LABEL,
             UpdateStrategy::Periodic(_) => StrategyPeriodic::LABEL,
@@ -86,8 +115,9 @@ impl UpdateStrategy {
     }
 
     /// Return the human description for this strategy.
-    pub(crate) fn human_description(&self) -> String {
+    pub fn human_description(&self) -> String {
         match self {
+            #[cfg(feature = "fleet-lock")]
             UpdateStrategy::FleetLock(_) => self.configuration_label().to_string(),
             UpdateStrategy::Immediate(_) => self.configuration_label().to_string(),
             UpdateStrategy::Periodic(p) => {
@@ -96,9 +126,24 @@ impl UpdateStrategy {
         }
     }
 
+    /// Return the remaining duration from `datetime` to the next window in
+    /// which finalization may be allowed.
+    ///
+    /// This returns a zero duration for strategies without windows, and
+    /// `None` if no windows are reachable.
+    pub fn remaining_to_window(&self, datetime: &DateTime<Utc>) -> Option<chrono::Duration> {
+        match self {
+            #[cfg(feature = "fleet-lock")]
+            UpdateStrategy::FleetLock(_) => Some(chrono::Duration::zero()),
+            UpdateStrategy::Immediate(_) => Some(chrono::Duration::zero()),
+            UpdateStrategy::Periodic(p) => p.remaining_to_window(datetime),
+        }
+    }
+
     /// Check if finalization is allowed at this time.
-    pub(crate) fn can_finalize(&self) -> impl Future<Output = bool> {
+    pub fn can_finalize(&self) -> impl Future<Output = bool> {
         let lock = match self {
+            #[cfg(feature = "fleet-lock")]
             UpdateStrategy::FleetLock(s) => s.can_finalize(),
             UpdateStrategy::Immediate(s) => s.can_finalize(),
             UpdateStrategy::Periodic(s) => s.can_finalize(),
@@ -113,8 +158,9 @@ impl UpdateStrategy {
     }
 
     /// Try to report and enter steady state.
-    pub(crate) fn report_steady(&self) -> impl Future<Output = bool> {
+    pub fn report_steady(&self) -> impl Future<Output = bool> {
         let unlock = match self {
+            #[cfg(feature = "fleet-lock")]
             UpdateStrategy::FleetLock(s) => s.report_steady(),
             UpdateStrategy::Immediate(s) => s.report_steady(),
             UpdateStrategy::Periodic(s) => s.report_steady(),
@@ -128,6 +174,15 @@ impl UpdateStrategy {
         }
     }
 
+    /// Record a finalized update, moving from the `booted` release.
+    #[cfg_attr(not(feature = "fleet-lock"), allow(unused_variables))]
+    pub fn record_finalized(&self, booted: &Release, update: &Release) {
+        #[cfg(feature = "fleet-lock")]
+        if let UpdateStrategy::FleetLock(s) = self {
+            s.record_finalized(booted, update);
+        }
+    }
+
     /// Build a new "immediate" strategy.
     fn new_immediate() -> Self {
         let immediate = StrategyImmediate::default();
@@ -135,8 +190,13 @@ impl UpdateStrategy {
     }
 
     /// Build a new "fleet_lock" strategy.
-    fn new_fleet_lock(cfg: inputs::UpdateInput, identity: &Identity) -> Result<Self> {
-        let fleet_lock = StrategyFleetLock::new(cfg, identity)?;
+    #[cfg(feature = "fleet-lock")]
+    fn new_fleet_lock(
+        cfg: inputs::UpdateInput,
+        identity: &Identity,
+        network: &NetworkSettings,
+    ) -> Result<Self> {
+        let fleet_lock = StrategyFleetLock::new(cfg, identity, network)?;
         Ok(UpdateStrategy::FleetLock(fleet_lock))
     }
 
@@ -1,28 +1,50 @@
-//! Strategy for periodic (weekly) updates.
+//! Strategy for periodic (weekly or monthly) updates.
 
 use crate::config::inputs;
+use crate::dated::{DatedCalendar, DatedWindow, DayRule};
 use crate::weekly::{utils, WeeklyCalendar, WeeklyWindow};
 use anyhow::{Context, Error, Result};
-use chrono::{TimeZone, Utc};
+use chrono::{DateTime, TimeZone, Utc};
 use fn_error_context::context;
 use futures::future;
 use futures::prelude::*;
 use log::trace;
 use serde::Serialize;
+use std::collections::BTreeMap;
 use std::fs::read_link;
 use std::path::Path;
 use std::pin::Pin;
 use std::time::Duration;
 use tzfile::Tz;
 
-/// Strategy for periodic (weekly) updates.
+/// Strategy for periodic (weekly or monthly) updates.
 #[derive(Clone, Debug, Serialize)]
-pub(crate) struct StrategyPeriodic {
+pub struct StrategyPeriodic {
     /// Whitelisted time windows during which updates are allowed.
     schedule: WeeklyCalendar,
+    /// Whitelisted time windows on specific days (monthly or one-off).
+    dated: DatedCalendar,
     /// Time zone in which time windows are defined in.
     #[serde(skip_serializing)]
-    pub(crate) time_zone: Tz,
+    pub time_zone: Tz,
+    /// Time zone name.
+    tz_name: String,
+    /// Time windows defined in their own time zone.
+    zoned: Vec<ZonedSchedule>,
+    /// Delay after a window opens before considering it open, in minutes.
+    window_offset_minutes: u32,
+}
+
+/// Time windows defined in a specific time zone.
+#[derive(Clone, Debug, Serialize)]
+struct ZonedSchedule {
+    /// Time windows, in wall-clock time of the time zone.
+    schedule: WeeklyCalendar,
+    /// Time windows on specific days, in wall-clock time of the time zone.
+    dated: DatedCalendar,
+    /// Time zone in which time windows are defined in.
+    #[serde(skip_serializing)]
+    time_zone: Tz,
     /// Time zone name.
     tz_name: String,
 }
@@ -32,8 +54,11 @@ impl Default for StrategyPeriodic {
         let utc = "UTC";
         StrategyPeriodic {
             schedule: WeeklyCalendar::default(),
+            dated: DatedCalendar::default(),
             time_zone: Tz::named(utc).unwrap(),
             tz_name: utc.to_string(),
+            zoned: vec![],
+            window_offset_minutes: 0,
         }
     }
 }
@@ -45,45 +70,144 @@ impl StrategyPeriodic {
     /// Build a new periodic strategy.
     #[context("failed to parse periodic strategy")]
     pub fn new(cfg: inputs::UpdateInput) -> Result<Self> {
-        let (time_zone, tz_name) = Self::get_time_zone_info_from_cfg(&cfg.periodic)?;
+        Self::with_windows(cfg.periodic)
+    }
 
-        let mut intervals = Vec::with_capacity(cfg.periodic.intervals.len());
-        for entry in cfg.periodic.intervals {
-            let weekday = utils::weekday_from_string(&entry.start_day)?;
+    /// Build a schedule from a set of windows.
+    ///
+    /// Windows starting on a weekday recur every week, while the ones starting
+    /// on specific days (e.g. `first Sun`, `day 15`, `2021-12-24`) form a
+    /// separate dated calendar.
+    ///
+    /// This is also used for other window-based settings (e.g. download windows).
+    pub fn with_windows(cfg: inputs::PeriodicInput) -> Result<Self> {
+        let (time_zone, tz_name) = Self::get_time_zone_info(&cfg.time_zone)?;
+        let window_offset_minutes = Self::pick_window_offset(&cfg)?;
+
+        // Windows are grouped by time zone, each group forming its own
+        // calendars in wall-clock time.
+        let mut intervals = Vec::with_capacity(cfg.intervals.len());
+        let mut dated = vec![];
+        let mut zoned_intervals: BTreeMap<String, (Vec<WeeklyWindow>, Vec<DatedWindow>)> =
+            BTreeMap::new();
+        for entry in cfg.intervals {
             let start = utils::time_from_string(&entry.start_time)?;
             let length = Duration::from_secs(u64::from(entry.length_minutes).saturating_mul(60));
-            let windows = WeeklyWindow::parse_timespan(weekday, start.0, start.1, length)?;
-            intervals.extend(windows);
+            let (weekly_windows, dated_windows) = match utils::weekday_from_string(&entry.start_day)
+            {
+                Ok(weekday) => (
+                    WeeklyWindow::parse_timespan(weekday, start.0, start.1, length)?,
+                    vec![],
+                ),
+                Err(_) => {
+                    let rule = DayRule::parse(&entry.start_day)?;
+                    (
+                        vec![],
+                        vec![DatedWindow::new(rule, start.0, start.1, length)?],
+                    )
+                }
+            };
+            match entry.time_zone {
+                Some(tz) if tz != cfg.time_zone => {
+                    let group = zoned_intervals.entry(tz).or_default();
+                    group.0.extend(weekly_windows);
+                    group.1.extend(dated_windows);
+                }
+                _ => {
+                    intervals.extend(weekly_windows);
+                    dated.extend(dated_windows);
+                }
+            };
         }
 
-        let calendar = WeeklyCalendar::new(intervals);
-        match calendar.length_minutes() {
-            0 => anyhow::bail!(
-                "invalid or missing periodic updates configuration: weekly calendar length is zero"
-            ),
-            n => log::trace!("periodic updates, weekly calendar length: {} minutes", n),
-        };
+        let mut zoned = Vec::with_capacity(zoned_intervals.len());
+        for (name, (weekly_windows, dated_windows)) in zoned_intervals {
+            let (time_zone, tz_name) = Self::get_time_zone_info(&name)?;
+            zoned.push(ZonedSchedule {
+                schedule: WeeklyCalendar::new(weekly_windows),
+                dated: DatedCalendar::new(dated_windows),
+                time_zone,
+                tz_name,
+            });
+        }
 
         let strategy = Self {
-            schedule: calendar,
+            schedule: WeeklyCalendar::new(intervals),
+            dated: DatedCalendar::new(dated),
             time_zone,
             tz_name,
+            zoned,
+            window_offset_minutes,
+        };
+        let has_dated = strategy.calendars().any(|c| !c.dated.is_empty());
+        match strategy.schedule_length_minutes() {
+            0 if !has_dated => anyhow::bail!(
+                "invalid or missing periodic updates configuration: weekly calendar length is zero"
+            ),
+            n => log::trace!("periodic updates, weekly calendar length: {} minutes", n),
         };
         Ok(strategy)
     }
 
+    /// Return all calendars, along with the time zone they are defined in.
+    fn calendars(&self) -> impl Iterator<Item = ZonedCalendars<'_>> {
+        let default = ZonedCalendars {
+            weekly: &self.schedule,
+            dated: &self.dated,
+            time_zone: &self.time_zone,
+            tz_name: &self.tz_name,
+        };
+        let zoned = self.zoned.iter().map(|z| ZonedCalendars {
+            weekly: &z.schedule,
+            dated: &z.dated,
+            time_zone: &z.time_zone,
+            tz_name: &z.tz_name,
+        });
+        std::iter::once(default).chain(zoned)
+    }
+
+    /// Pick a random delay (below the configured jitter) after a window
+    /// opens before considering it open.
+    ///
+    /// This smears the first finalization attempts of a fleet across the
+    /// opening minutes of a window, instead of all nodes rebooting at once.
+    fn pick_window_offset(cfg: &inputs::PeriodicInput) -> Result<u32> {
+        use rand::Rng;
+
+        let jitter = cfg.window_jitter_minutes;
+        if jitter == 0 {
+            return Ok(0);
+        }
+        if let Some(shortest) = cfg.intervals.iter().map(|e| e.length_minutes).min() {
+            if jitter >= shortest {
+                anyhow::bail!(
+                    "window jitter ({} minutes) must be shorter than all windows (shortest: {} minutes)",
+                    jitter,
+                    shortest
+                );
+            }
+        }
+
+        let offset = rand::thread_rng().gen_range(0..jitter);
+        log::trace!(
+            "periodic updates, windows considered open after {} minutes",
+            offset
+        );
+        Ok(offset)
+    }
+
     /// Getter function for `StrategyPeriodic`'s `tz_name` field.
     pub fn tz_name(&self) -> &str {
         self.tz_name.as_str()
     }
 
-    /// Get the time zone from `periodic` strategy config, returning a `Tz` and its name
+    /// Get a time zone from its configuration name, returning a `Tz` and its name
     /// in a tuple.
     #[context("failed to get time zone info from config")]
-    fn get_time_zone_info_from_cfg(cfg: &inputs::PeriodicInput) -> Result<(Tz, String)> {
+    fn get_time_zone_info(name: &str) -> Result<(Tz, String)> {
         let tz;
         let tz_name;
-        if &cfg.time_zone == "localtime" {
+        if name == "localtime" {
             let local_time_path = Path::new("/etc/localtime");
             // Use `read_link()` instead of `exists()` because we only want to check for
             // the existence of the `/etc/localtime` symlink, not whether it points to
@@ -109,41 +233,91 @@ impl StrategyPeriodic {
                 tz_name = tz_str.to_string();
             }
         } else {
-            tz = Tz::named(&cfg.time_zone)
-                .with_context(|| format!("failed to parse time zone named: {}", &cfg.time_zone));
-            tz_name = cfg.time_zone.to_string();
+            tz = Tz::named(name)
+                .with_context(|| format!("failed to parse time zone named: {}", name));
+            tz_name = name.to_string();
         }
 
         tz.map(|tz| (tz, tz_name))
     }
 
-    /// Return the measured length of the schedule, in minutes.
-    pub(crate) fn schedule_length_minutes(&self) -> u64 {
-        self.schedule.length_minutes()
+    /// Return the measured length of the weekly schedule, in minutes.
+    ///
+    /// Windows overlapping across different time zones are counted once per
+    /// time zone. Windows on specific days are not counted.
+    pub fn schedule_length_minutes(&self) -> u64 {
+        self.calendars().map(|c| c.weekly.length_minutes()).sum()
     }
 
-    /// Return the weekday and time of the next window, in human terms.
-    pub(crate) fn human_next_window(&self) -> String {
-        let naive_utc_dt = Utc::now().naive_utc();
-        let dt = (&self.time_zone).from_utc_datetime(&naive_utc_dt);
-        let next_window_minute_in_week = self.schedule.next_window_minute_in_week(&dt);
-
-        match next_window_minute_in_week {
-            Some(minute_in_week) => {
-                let (weekday, hour, minute) = utils::weekly_minute_as_weekday_time(minute_in_week);
-                format!(
-                    "at {}:{} on {} ({}), subject to time zone caveats.",
-                    hour, minute, weekday, self.tz_name
-                )
-            }
+    /// Return the day and time of the next window, in human terms.
+    pub fn human_next_window(&self) -> String {
+        let now = Utc::now();
+        let next = self
+            .calendars()
+            .flat_map(|c| {
+                let dt = c.time_zone.from_utc_datetime(&now.naive_utc());
+                let weekly = c
+                    .weekly
+                    .remaining_to_datetime(&shift_to_wall_clock(&now, c.time_zone))
+                    .and_then(|remaining| {
+                        let minute_in_week = c.weekly.next_window_minute_in_week(&dt)?;
+                        let (weekday, hour, minute) =
+                            utils::weekly_minute_as_weekday_time(minute_in_week);
+                        let human =
+                            format!("at {}:{} on {} ({})", hour, minute, weekday, c.tz_name);
+                        Some((remaining, human))
+                    });
+                let dated = c.dated.remaining_to_datetime(&dt).and_then(|remaining| {
+                    let start = c.dated.next_window_start(&dt)?;
+                    let human = format!("at {} ({})", start.format("%H:%M on %Y-%m-%d"), c.tz_name);
+                    Some((remaining, human))
+                });
+                weekly.into_iter().chain(dated)
+            })
+            .min_by_key(|(remaining, _)| *remaining);
+
+        match next {
+            Some((_, human)) => format!("{}, subject to time zone caveats.", human),
             None => "not found".to_string(),
         }
     }
 
+    /// Return the remaining duration from `datetime` to the next window.
+    ///
+    /// This returns a zero duration if `datetime` is within a window, and
+    /// `None` if no windows are reachable.
+    pub fn remaining_to_window(&self, datetime: &DateTime<Utc>) -> Option<chrono::Duration> {
+        let remaining = self
+            .calendars()
+            .filter_map(|c| c.remaining_to_datetime(datetime))
+            .min()?;
+
+        let offset = i64::from(self.window_offset_minutes);
+        if remaining > chrono::Duration::zero() {
+            return Some(remaining + chrono::Duration::minutes(offset));
+        }
+        let elapsed = self
+            .minutes_since_window_start(datetime)
+            .map(i64::from)
+            .unwrap_or(offset);
+        Some(chrono::Duration::minutes(
+            offset.saturating_sub(elapsed).max(0),
+        ))
+    }
+
+    /// Return the minutes elapsed since the start of the window containing
+    /// `datetime`, if any.
+    ///
+    /// With windows in multiple time zones, the earliest start is considered.
+    fn minutes_since_window_start(&self, datetime: &DateTime<Utc>) -> Option<u32> {
+        self.calendars()
+            .filter_map(|c| c.minutes_since_window_start(datetime))
+            .max()
+    }
+
     /// Return the remaining duration to next window, in human terms.
-    pub(crate) fn human_remaining(&self) -> String {
-        let datetime = chrono::Utc::now();
-        let remaining = self.schedule.remaining_to_datetime(&datetime);
+    pub fn human_remaining(&self) -> String {
+        let remaining = self.remaining_to_window(&chrono::Utc::now());
         match remaining {
             None => "not found".to_string(),
             Some(ref d) => WeeklyCalendar::human_remaining_duration(d)
@@ -152,7 +326,7 @@ impl StrategyPeriodic {
     }
 
     /// Return some human-friendly information about `PeriodicStrategy`'s calendar.
-    pub(crate) fn calendar_summary(&self) -> String {
+    pub fn calendar_summary(&self) -> String {
         format!(
             "total schedule length {} minutes; next window {}",
             self.schedule_length_minutes(),
@@ -166,11 +340,20 @@ impl StrategyPeriodic {
         )
     }
 
+    /// Return whether `datetime` is within a window, in the schedule time zone.
+    ///
+    /// A window is only considered open once the (jittered) delay after its
+    /// start has elapsed.
+    pub fn contains_datetime(&self, datetime: &DateTime<Utc>) -> bool {
+        match self.minutes_since_window_start(datetime) {
+            Some(elapsed) => elapsed >= self.window_offset_minutes,
+            None => false,
+        }
+    }
+
     /// Check if finalization is allowed.
-    pub(crate) fn can_finalize(&self) -> Pin<Box<dyn Future<Output = Result<bool, Error>>>> {
-        let naive_utc_dt = Utc::now().naive_utc();
-        let dt = (&self.time_zone).from_utc_datetime(&naive_utc_dt);
-        let allowed = self.schedule.contains_datetime(&dt);
+    pub fn can_finalize(&self) -> Pin<Box<dyn Future<Output = Result<bool, Error>>>> {
+        let allowed = self.contains_datetime(&Utc::now());
 
         trace!("periodic strategy, can finalize updates: {}", allowed);
 
@@ -178,7 +361,8 @@ impl StrategyPeriodic {
         Box::pin(res)
     }
 
-    pub(crate) fn report_steady(&self) -> Pin<Box<dyn Future<Output = Result<bool, Error>>>> {
+    /// Try to report steady state.
+    pub fn report_steady(&self) -> Pin<Box<dyn Future<Output = Result<bool, Error>>>> {
         trace!("periodic strategy, report steady: {}", true);
 
         let res = future::ok(true);
@@ -186,6 +370,49 @@ impl StrategyPeriodic {
     }
 }
 
+/// Weekly and dated calendars defined in the same time zone.
+struct ZonedCalendars<'a> {
+    /// Weekly time windows.
+    weekly: &'a WeeklyCalendar,
+    /// Time windows on specific days.
+    dated: &'a DatedCalendar,
+    /// Time zone in which time windows are defined in.
+    time_zone: &'a Tz,
+    /// Time zone name.
+    tz_name: &'a str,
+}
+
+impl ZonedCalendars<'_> {
+    /// Return the remaining duration from `datetime` to the next window.
+    fn remaining_to_datetime(&self, datetime: &DateTime<Utc>) -> Option<chrono::Duration> {
+        let weekly = self
+            .weekly
+            .remaining_to_datetime(&shift_to_wall_clock(datetime, self.time_zone));
+        let dt = self.time_zone.from_utc_datetime(&datetime.naive_utc());
+        let dated = self.dated.remaining_to_datetime(&dt);
+        weekly.into_iter().chain(dated).min()
+    }
+
+    /// Return the minutes elapsed since the start of the window containing
+    /// `datetime`, if any.
+    fn minutes_since_window_start(&self, datetime: &DateTime<Utc>) -> Option<u32> {
+        let dt = self.time_zone.from_utc_datetime(&datetime.naive_utc());
+        let weekly = self.weekly.minutes_since_window_start(&dt);
+        let dated = self.dated.minutes_since_window_start(&dt);
+        weekly.into_iter().chain(dated).max()
+    }
+}
+
+/// Shift `datetime` to the wall-clock time of `tz`, for weekly calendars
+/// which only look at wall-clock time.
+///
+/// Local time is computed from the UTC instant, so DST shifts are accounted
+/// for (see time zone caveats in docs).
+fn shift_to_wall_clock(datetime: &DateTime<Utc>, tz: &Tz) -> DateTime<Utc> {
+    let local = tz.from_utc_datetime(&datetime.naive_utc());
+    Utc.from_utc_datetime(&local.naive_local())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -204,7 +431,7 @@ mod tests {
         let default = StrategyPeriodic::default();
         let runtime = rt::Runtime::new().unwrap();
         let steady = runtime.block_on(default.can_finalize()).unwrap();
-        assert_eq!(steady, false);
+        assert!(!steady);
     }
 
     #[test]
@@ -212,7 +439,7 @@ mod tests {
         let default = StrategyPeriodic::default();
         let runtime = rt::Runtime::new().unwrap();
         let steady = runtime.block_on(default.report_steady()).unwrap();
-        assert_eq!(steady, true);
+        assert!(steady);
     }
 
     #[test]
@@ -222,6 +449,143 @@ mod tests {
         assert_eq!(strategy.schedule.total_length_minutes(), 3145);
     }
 
+    #[test]
+    fn test_window_jitter() {
+        let mut cfg = inputs::PeriodicInput {
+            intervals: vec![inputs::PeriodicIntervalInput {
+                start_day: "Mon".to_string(),
+                start_time: "10:00".to_string(),
+                length_minutes: 30,
+                time_zone: None,
+            }],
+            window_jitter_minutes: 30,
+            ..Default::default()
+        };
+        StrategyPeriodic::with_windows(cfg.clone()).unwrap_err();
+
+        cfg.window_jitter_minutes = 10;
+        let mut strategy = StrategyPeriodic::with_windows(cfg).unwrap();
+        assert!(strategy.window_offset_minutes < 10);
+        strategy.window_offset_minutes = 5;
+
+        // 2021-06-07 is a Monday.
+        let opening = Utc.with_ymd_and_hms(2021, 6, 7, 10, 2, 0).unwrap();
+        assert!(!strategy.contains_datetime(&opening));
+        assert_eq!(
+            strategy.remaining_to_window(&opening),
+            Some(chrono::Duration::minutes(3))
+        );
+        let open = Utc.with_ymd_and_hms(2021, 6, 7, 10, 5, 0).unwrap();
+        assert!(strategy.contains_datetime(&open));
+        assert_eq!(
+            strategy.remaining_to_window(&open),
+            Some(chrono::Duration::zero())
+        );
+        let before = Utc.with_ymd_and_hms(2021, 6, 7, 9, 0, 0).unwrap();
+        assert!(!strategy.contains_datetime(&before));
+        assert_eq!(
+            strategy.remaining_to_window(&before),
+            Some(chrono::Duration::minutes(65))
+        );
+    }
+
+    #[test]
+    fn test_window_time_zone() {
+        let cfg = inputs::PeriodicInput {
+            intervals: vec![
+                inputs::PeriodicIntervalInput {
+                    start_day: "Mon".to_string(),
+                    start_time: "10:00".to_string(),
+                    length_minutes: 60,
+                    time_zone: Some("America/New_York".to_string()),
+                },
+                inputs::PeriodicIntervalInput {
+                    start_day: "Tue".to_string(),
+                    start_time: "10:00".to_string(),
+                    length_minutes: 60,
+                    time_zone: Some("UTC".to_string()),
+                },
+            ],
+            ..Default::default()
+        };
+        let strategy = StrategyPeriodic::with_windows(cfg).unwrap();
+        assert_eq!(strategy.zoned.len(), 1);
+        assert_eq!(strategy.schedule_length_minutes(), 120);
+
+        // 2021-06-07 is a Monday, with daylight saving time (UTC-4).
+        let summer = Utc.with_ymd_and_hms(2021, 6, 7, 14, 30, 0).unwrap();
+        assert!(strategy.contains_datetime(&summer));
+        let before = Utc.with_ymd_and_hms(2021, 6, 7, 13, 0, 0).unwrap();
+        assert!(!strategy.contains_datetime(&before));
+        assert_eq!(
+            strategy.remaining_to_window(&before),
+            Some(chrono::Duration::minutes(60))
+        );
+
+        // 2021-01-04 is a Monday, without daylight saving time (UTC-5).
+        let winter = Utc.with_ymd_and_hms(2021, 1, 4, 14, 30, 0).unwrap();
+        assert!(!strategy.contains_datetime(&winter));
+        let winter = Utc.with_ymd_and_hms(2021, 1, 4, 15, 30, 0).unwrap();
+        assert!(strategy.contains_datetime(&winter));
+
+        // Windows in the strategy time zone.
+        let utc_window = Utc.with_ymd_and_hms(2021, 6, 8, 10, 30, 0).unwrap();
+        assert!(strategy.contains_datetime(&utc_window));
+        let after = Utc.with_ymd_and_hms(2021, 6, 7, 16, 0, 0).unwrap();
+        assert_eq!(
+            strategy.remaining_to_window(&after),
+            Some(chrono::Duration::hours(18))
+        );
+    }
+
+    #[test]
+    fn test_dated_windows() {
+        let cfg = inputs::PeriodicInput {
+            intervals: vec![
+                inputs::PeriodicIntervalInput {
+                    start_day: "first Sun".to_string(),
+                    start_time: "02:00".to_string(),
+                    length_minutes: 120,
+                    time_zone: None,
+                },
+                inputs::PeriodicIntervalInput {
+                    start_day: "2021-08-10".to_string(),
+                    start_time: "22:00".to_string(),
+                    length_minutes: 60,
+                    time_zone: Some("Europe/Rome".to_string()),
+                },
+            ],
+            ..Default::default()
+        };
+        let strategy = StrategyPeriodic::with_windows(cfg).unwrap();
+        assert_eq!(strategy.schedule_length_minutes(), 0);
+
+        // 2021-08-01 is the first Sunday of the month.
+        let first_sunday = Utc.with_ymd_and_hms(2021, 8, 1, 3, 0, 0).unwrap();
+        assert!(strategy.contains_datetime(&first_sunday));
+        let second_sunday = Utc.with_ymd_and_hms(2021, 8, 8, 3, 0, 0).unwrap();
+        assert!(!strategy.contains_datetime(&second_sunday));
+
+        // Rome is at UTC+2 in summer.
+        let date = Utc.with_ymd_and_hms(2021, 8, 10, 20, 30, 0).unwrap();
+        assert!(strategy.contains_datetime(&date));
+        let before = Utc.with_ymd_and_hms(2021, 8, 10, 19, 0, 0).unwrap();
+        assert_eq!(
+            strategy.remaining_to_window(&before),
+            Some(chrono::Duration::minutes(60))
+        );
+
+        // Next window is on 2021-09-05.
+        let after = Utc.with_ymd_and_hms(2021, 8, 10, 22, 0, 0).unwrap();
+        assert_eq!(
+            strategy.remaining_to_window(&after),
+            Some(chrono::Duration::hours(24 * 25 + 4))
+        );
+
+        let empty = inputs::PeriodicInput::default();
+        StrategyPeriodic::with_windows(empty).unwrap_err();
+    }
+
     #[test]
     fn test_non_utc_time() {
         use chrono::{Datelike, Timelike};
@@ -241,6 +605,7 @@ mod tests {
             start_day: weekday.to_string(),
             start_time: time.to_string(),
             length_minutes: 2,
+            time_zone: None,
         }];
 
         // Build a strategy that uses UTC.
@@ -258,14 +623,14 @@ mod tests {
             Tz::named("America/Toronto").unwrap()
         );
         // Check that strategy allows reboot now.
-        assert_eq!(steady, true);
+        assert!(steady);
 
         let utc_strategy = StrategyPeriodic::new(utc_update_input).unwrap();
         let runtime = rt::Runtime::new().unwrap();
         let steady = runtime.block_on(utc_strategy.can_finalize()).unwrap();
         assert_eq!(utc_strategy.time_zone, Tz::named("UTC").unwrap());
         // Check that reboot is NOT allowed for UTC strategy.
-        assert_eq!(steady, false);
+        assert!(!steady);
     }
 
     #[test]
@@ -275,7 +640,7 @@ mod tests {
         let local_time_path = Path::new("/etc/localtime");
         let expected_tz;
         // If symlink `/etc/localtime` doesn't exist, we expect to default to UTC.
-        if let Err(_) = read_link(local_time_path) {
+        if read_link(local_time_path).is_err() {
             expected_tz = Some(Tz::named("UTC").unwrap());
         } else {
             if let Ok(tz_path) = local_time_path.canonicalize() {
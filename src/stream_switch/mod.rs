@@ -0,0 +1,120 @@
+//! Switching to other update streams.
+//!
+//! A node is moved to another stream by rebasing it onto the OSTree ref of
+//! that stream, which is rendered from a configured refspec template. The
+//! rebased deployment is staged like any other update, and finalized
+//! according to the update strategy.
+
+use crate::config::inputs;
+use crate::identity::{self, Identity};
+use anyhow::{ensure, Result};
+use fn_error_context::context;
+use serde::Serialize;
+use std::collections::BTreeSet;
+
+/// Template placeholder for the target stream.
+static STREAM_PLACEHOLDER: &str = "${stream}";
+
+/// Settings for switching to other update streams.
+#[derive(Clone, Debug, Serialize)]
+pub struct StreamSwitch {
+    /// Template for the OSTree refspec of a stream.
+    refspec: String,
+    /// Streams which can be switched to (empty for any).
+    allowed_streams: BTreeSet<String>,
+}
+
+impl StreamSwitch {
+    /// Process stream switching configuration.
+    ///
+    /// This returns `None` if switching streams is not configured.
+    #[context("failed to validate stream switch configuration")]
+    pub fn with_config(cfg: inputs::StreamSwitchInput, id: &Identity) -> Result<Option<Self>> {
+        if cfg.refspec.is_empty() {
+            ensure!(
+                cfg.allowed_streams.is_empty(),
+                "allowed streams configured, but no refspec template"
+            );
+            return Ok(None);
+        }
+        ensure!(
+            cfg.refspec.contains(STREAM_PLACEHOLDER),
+            "refspec template '{}' does not contain '{}'",
+            cfg.refspec,
+            STREAM_PLACEHOLDER
+        );
+        for stream in &cfg.allowed_streams {
+            identity::validate_stream_label(stream)?;
+        }
+
+        let switch = Self {
+            refspec: cfg.refspec,
+            allowed_streams: cfg.allowed_streams.into_iter().collect(),
+        };
+        // Catch unknown placeholders early, instead of at switch time.
+        switch.render(id, &id.stream)?;
+        Ok(Some(switch))
+    }
+
+    /// Return the refspec for `stream`, if the node can switch to it.
+    pub fn refspec_for(&self, id: &Identity, stream: &str) -> Result<String> {
+        identity::validate_stream_label(stream)?;
+        ensure!(
+            self.allowed_streams.is_empty() || self.allowed_streams.contains(stream),
+            "stream '{}' not allowed by configuration",
+            stream
+        );
+        self.render(id, stream)
+    }
+
+    /// Render the refspec template for `stream`.
+    fn render(&self, id: &Identity, stream: &str) -> Result<String> {
+        let mut vars = id.url_variables();
+        vars.insert("stream".to_string(), stream.to_string());
+        envsubst::validate_vars(&vars)?;
+        let refspec = envsubst::substitute(&self.refspec, &vars)?;
+        ensure!(
+            !envsubst::is_templated(&refspec),
+            "unknown placeholder in refspec template '{}'",
+            self.refspec
+        );
+        Ok(refspec)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_refspec_for() {
+        let id = Identity::mock_default();
+
+        let unset = inputs::StreamSwitchInput::default();
+        assert!(StreamSwitch::with_config(unset, &id).unwrap().is_none());
+
+        let static_ref = inputs::StreamSwitchInput {
+            refspec: "fedora:fedora/x86_64/coreos/stable".to_string(),
+            allowed_streams: vec![],
+        };
+        StreamSwitch::with_config(static_ref, &id).unwrap_err();
+
+        let unknown = inputs::StreamSwitchInput {
+            refspec: "fedora:${foo}/${stream}".to_string(),
+            allowed_streams: vec![],
+        };
+        StreamSwitch::with_config(unknown, &id).unwrap_err();
+
+        let input = inputs::StreamSwitchInput {
+            refspec: "fedora:fedora/${basearch}/coreos/${stream}".to_string(),
+            allowed_streams: vec!["stable".to_string(), "testing".to_string()],
+        };
+        let switch = StreamSwitch::with_config(input, &id).unwrap().unwrap();
+        assert_eq!(
+            switch.refspec_for(&id, "testing").unwrap(),
+            "fedora:fedora/mock-amd64/coreos/testing"
+        );
+        switch.refspec_for(&id, "next").unwrap_err();
+        switch.refspec_for(&id, "../stable").unwrap_err();
+    }
+}
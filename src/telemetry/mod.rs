@@ -0,0 +1,206 @@
+//! Settings for the metrics exporter over TCP.
+//!
+//! Besides the local Unix-domain socket, metrics can be exposed on a TCP
+//! address, so that Prometheus can scrape nodes directly. As this endpoint
+//! is reachable from the network, it can be protected with basic auth
+//! and/or TLS (optionally requiring client certificates).
+
+use crate::config::inputs;
+use crate::utils;
+use anyhow::{Context, Result};
+use fn_error_context::context;
+use openssl::ssl::{SslAcceptor, SslFiletype, SslMethod, SslVerifyMode};
+use serde::Serialize;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+
+/// Settings for the metrics exporter over TCP.
+#[derive(Clone, Debug, Serialize)]
+pub struct TelemetrySettings {
+    /// Address to listen on.
+    pub listen_address: SocketAddr,
+    /// Basic auth credentials, if required.
+    pub basic_auth: Option<BasicAuth>,
+    /// TLS settings, if enabled.
+    pub tls: Option<TelemetryTls>,
+}
+
+impl TelemetrySettings {
+    /// Process metrics exporter configuration.
+    ///
+    /// This returns `None` if the TCP exporter is not enabled.
+    #[context("failed to validate telemetry configuration")]
+    pub fn with_config(cfg: inputs::TelemetryInput) -> Result<Option<Self>> {
+        let listen_address = match cfg.listen_address.trim() {
+            "" => return Ok(None),
+            addr => addr
+                .parse()
+                .with_context(|| format!("invalid listen address '{}'", addr))?,
+        };
+        let basic_auth = match non_empty_path(&cfg.basic_auth_file)? {
+            Some(path) => Some(BasicAuth::from_file(&path)?),
+            None => None,
+        };
+        let tls = match (
+            non_empty_path(&cfg.tls_cert)?,
+            non_empty_path(&cfg.tls_key)?,
+            non_empty_path(&cfg.tls_client_ca)?,
+        ) {
+            (Some(cert), Some(key), client_ca) => Some(TelemetryTls {
+                cert,
+                key,
+                client_ca,
+            }),
+            (None, None, None) => None,
+            (None, None, Some(_)) => anyhow::bail!("client CA configured without TLS"),
+            (Some(_), None, _) => anyhow::bail!("server certificate configured without a key"),
+            (None, Some(_), _) => anyhow::bail!("server key configured without a certificate"),
+        };
+        if basic_auth.is_some() && tls.is_none() {
+            log::warn!(
+                "metrics exporter uses basic auth without TLS, credentials are sent in clear"
+            );
+        }
+
+        let settings = Self {
+            listen_address,
+            basic_auth,
+            tls,
+        };
+        Ok(Some(settings))
+    }
+}
+
+/// Basic auth credentials.
+#[derive(Clone, Serialize)]
+pub struct BasicAuth {
+    /// Path to the credentials file.
+    path: PathBuf,
+    /// Expected value for the `Authorization` header.
+    #[serde(skip)]
+    header: String,
+}
+
+impl BasicAuth {
+    /// Load credentials from a file with a single `user:password` line.
+    #[context("failed to load basic auth credentials '{}'", path.display())]
+    fn from_file(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path).context("failed to read file")?;
+        let credentials = content.trim();
+        if !credentials.contains(':') {
+            anyhow::bail!("credentials not in `user:password` format");
+        }
+
+        let header = format!(
+            "Basic {}",
+            openssl::base64::encode_block(credentials.as_bytes())
+        );
+        let auth = Self {
+            path: path.to_path_buf(),
+            header,
+        };
+        Ok(auth)
+    }
+
+    /// Check an `Authorization` header value against these credentials.
+    pub fn matches(&self, header: &str) -> bool {
+        let header = header.trim();
+        header.len() == self.header.len()
+            && openssl::memcmp::eq(header.as_bytes(), self.header.as_bytes())
+    }
+}
+
+impl std::fmt::Debug for BasicAuth {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Credentials are not printed.
+        f.debug_struct("BasicAuth")
+            .field("path", &self.path)
+            .finish()
+    }
+}
+
+/// TLS settings for the metrics exporter.
+#[derive(Clone, Debug, Serialize)]
+pub struct TelemetryTls {
+    /// Path to server certificate chain (PEM).
+    pub cert: PathBuf,
+    /// Path to server private key (PEM).
+    pub key: PathBuf,
+    /// Path to CA certificates for authenticating clients (PEM bundle), if required.
+    pub client_ca: Option<PathBuf>,
+}
+
+impl TelemetryTls {
+    /// Build a TLS acceptor from these settings.
+    #[context("failed to set up TLS for metrics exporter")]
+    pub fn acceptor(&self) -> Result<SslAcceptor> {
+        let mut builder = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls())?;
+        builder
+            .set_certificate_chain_file(&self.cert)
+            .with_context(|| format!("failed to load certificate '{}'", self.cert.display()))?;
+        builder
+            .set_private_key_file(&self.key, SslFiletype::PEM)
+            .with_context(|| format!("failed to load private key '{}'", self.key.display()))?;
+        builder
+            .check_private_key()
+            .context("private key does not match certificate")?;
+        if let Some(ca) = &self.client_ca {
+            builder
+                .set_ca_file(ca)
+                .with_context(|| format!("failed to load client CA '{}'", ca.display()))?;
+            builder.set_verify(SslVerifyMode::PEER | SslVerifyMode::FAIL_IF_NO_PEER_CERT);
+        }
+        Ok(builder.build())
+    }
+}
+
+/// Turn a path input into a `PathBuf`, ignoring empty values.
+///
+/// Paths can refer to systemd credentials (`credential:<name>`).
+fn non_empty_path(input: &str) -> Result<Option<PathBuf>> {
+    match input.trim() {
+        "" => Ok(None),
+        path => utils::resolve_credential_path(path).map(Some),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_with_config() {
+        let unset = TelemetrySettings::with_config(inputs::TelemetryInput::default()).unwrap();
+        assert!(unset.is_none());
+
+        let tmpdir = tempfile::tempdir().unwrap();
+        let auth_path = tmpdir.path().join("metrics-auth");
+        std::fs::write(&auth_path, "prometheus:secret\n").unwrap();
+        let cfg = inputs::TelemetryInput {
+            listen_address: "127.0.0.1:9101".to_string(),
+            basic_auth_file: auth_path.display().to_string(),
+            ..Default::default()
+        };
+        let settings = TelemetrySettings::with_config(cfg).unwrap().unwrap();
+        assert_eq!(settings.listen_address.port(), 9101);
+        assert!(settings.tls.is_none());
+        let auth = settings.basic_auth.unwrap();
+        assert!(auth.matches("Basic cHJvbWV0aGV1czpzZWNyZXQ="));
+        assert!(!auth.matches("Basic cHJvbWV0aGV1czpzZWNyZXU="));
+        assert!(!auth.matches(""));
+        assert!(!format!("{:?}", auth).contains("cHJvbWV0aGV1c"));
+
+        let bad_addr = inputs::TelemetryInput {
+            listen_address: "localhost".to_string(),
+            ..Default::default()
+        };
+        TelemetrySettings::with_config(bad_addr).unwrap_err();
+
+        let missing_key = inputs::TelemetryInput {
+            listen_address: "127.0.0.1:9101".to_string(),
+            tls_cert: "/etc/pki/zincati/metrics.crt".to_string(),
+            ..Default::default()
+        };
+        TelemetrySettings::with_config(missing_key).unwrap_err();
+    }
+}
@@ -1,21 +1,50 @@
 //! Update agent actor.
 
-use super::{UpdateAgent, UpdateAgentState};
+use super::bootfs;
+use super::logind;
+use super::metered;
+use super::{
+    AgentEvent, AgentStatus, EventListener, HistoryEvent, PendingReboot, PlannedAction,
+    ShutdownRecord, UpdateAgent, UpdateAgentState, ADOPTED_DEPLOYMENTS, BLACKOUT_BLOCKED,
+    FETCH_BLOCKED, LOGIND_REBOOTS_CANCELLED, REBOOT_COUNTDOWNS_CANCELLED, ROLLBACK_CLEANUPS,
+    ROLLBACK_CLEANUP_PATH, SHUTDOWN_RECORD_PATH, STAGING_LEAD_TIME_SECS, STEADY_REPORT_FAILURES,
+    STRATEGY_DEGRADED, TARGET_NOT_ON_REMOTE, TARGET_VERIFICATION_FAILURES, URGENCY_OVERRIDES,
+};
+use crate::cincinnati;
+use crate::config::Settings;
+use crate::ostree_remote;
 use crate::rpm_ostree::{self, Release};
+use crate::utils::{update_unit_status, StatusSummary};
+use crate::webhook::Event;
 use actix::prelude::*;
-use anyhow::Error;
+use anyhow::{Context as _, Error};
+use chrono::{DateTime, Utc};
 use futures::prelude::*;
 use libsystemd::daemon::*;
 use log::trace;
 use prometheus::IntGauge;
 use std::collections::BTreeSet;
+use std::convert::TryInto;
+use std::path::Path;
 use std::time::Duration;
 
+/// Minimum time since the last refresh tick before checking for updates on
+/// network connectivity changes (in seconds), against flapping links.
+const NETWORK_UP_MIN_INTERVAL_SECS: i64 = 60;
+
 lazy_static::lazy_static! {
     static ref LAST_REFRESH: IntGauge = register_int_gauge!(opts!(
         "zincati_update_agent_last_refresh_timestamp",
         "UTC timestamp of update-agent last refresh tick."
     )).unwrap();
+    static ref NEXT_REFRESH: IntGauge = register_int_gauge!(opts!(
+        "zincati_update_agent_next_refresh_timestamp",
+        "UTC timestamp of update-agent next scheduled refresh tick."
+    )).unwrap();
+    static ref NEXT_REFRESH_JITTER: IntGauge = register_int_gauge!(opts!(
+        "zincati_update_agent_next_refresh_jitter_seconds",
+        "Random jitter added to the delay before the next refresh tick, in seconds."
+    )).unwrap();
 }
 
 impl Actor for UpdateAgent {
@@ -28,6 +57,12 @@ impl Actor for UpdateAgent {
             log::warn!("client configuration allows (possibly vulnerable) downgrades via auto-updates logic");
         }
 
+        match ShutdownRecord::take(SHUTDOWN_RECORD_PATH) {
+            Ok(Some(record)) => log::info!("agent previously {}", record.describe()),
+            Ok(None) => {}
+            Err(e) => log::error!("{:#}", e),
+        }
+
         // Kick-start the state machine.
         Self::tick_now(ctx);
     }
@@ -48,6 +83,490 @@ impl Handler<LastRefresh> for UpdateAgent {
     }
 }
 
+/// Request: get the UTC timestamp of the next refresh tick (`0` if not
+/// scheduled), and why it was scheduled at that time.
+pub struct NextRefresh {}
+
+impl Message for NextRefresh {
+    type Result = (i64, String);
+}
+
+impl Handler<NextRefresh> for UpdateAgent {
+    type Result = MessageResult<NextRefresh>;
+
+    fn handle(&mut self, _msg: NextRefresh, _ctx: &mut Self::Context) -> Self::Result {
+        trace!("agent: request to get next refresh time");
+        let time = self.next_refresh.map(|t| t.timestamp()).unwrap_or(0);
+        MessageResult((time, self.next_refresh_reason.clone()))
+    }
+}
+
+/// Notification: network connectivity came online.
+///
+/// This triggers an update check right away, instead of waiting for the
+/// next refresh tick.
+pub struct NetworkUp {}
+
+impl Message for NetworkUp {
+    type Result = ();
+}
+
+impl Handler<NetworkUp> for UpdateAgent {
+    type Result = ();
+
+    fn handle(&mut self, _msg: NetworkUp, ctx: &mut Self::Context) -> Self::Result {
+        trace!("agent: network came online");
+
+        // Send outcome reports queued while offline.
+        if let Some(reporter) = &self.outcome_report {
+            if !self.shutting_down && reporter.has_queued() {
+                ctx.spawn(reporter.flush().into_actor(self));
+            }
+        }
+        // Deliver webhook events queued while offline.
+        if !self.shutting_down {
+            self.flush_webhook(ctx);
+        }
+
+        let polling = matches!(
+            self.state,
+            UpdateAgentState::ReportedSteady
+                | UpdateAgentState::NoNewUpdate
+                | UpdateAgentState::WaitingForWave(_)
+        );
+        if self.shutting_down || !polling || self.first_check_delayed() {
+            return;
+        }
+        let since_last = chrono::Utc::now().timestamp() - LAST_REFRESH.get();
+        if since_last < NETWORK_UP_MIN_INTERVAL_SECS {
+            trace!("last update check too recent, not checking again");
+            return;
+        }
+        // No handle means that a refresh tick is already running or imminent.
+        if let Some(handle) = self.refresh_tick.take() {
+            log::info!("network came online, checking for updates");
+            ctx.cancel_future(handle);
+            Self::tick_now(ctx);
+        }
+    }
+}
+
+/// Request: get a snapshot of the whole agent status.
+pub struct GetStatus {}
+
+impl Message for GetStatus {
+    type Result = AgentStatus;
+}
+
+impl Handler<GetStatus> for UpdateAgent {
+    type Result = MessageResult<GetStatus>;
+
+    fn handle(&mut self, _msg: GetStatus, _ctx: &mut Self::Context) -> Self::Result {
+        trace!("agent: request to get full status");
+        MessageResult(self.status(LAST_REFRESH.get()))
+    }
+}
+
+/// Request: get the effective configuration, as JSON.
+pub struct GetConfig {}
+
+impl Message for GetConfig {
+    type Result = String;
+}
+
+impl Handler<GetConfig> for UpdateAgent {
+    type Result = String;
+
+    fn handle(&mut self, _msg: GetConfig, _ctx: &mut Self::Context) -> Self::Result {
+        trace!("agent: request to get effective configuration");
+        self.effective_config.clone()
+    }
+}
+
+/// Request: get the projected next actions of the agent.
+pub struct GetPlan {}
+
+impl Message for GetPlan {
+    type Result = Vec<PlannedAction>;
+}
+
+impl Handler<GetPlan> for UpdateAgent {
+    type Result = MessageResult<GetPlan>;
+
+    fn handle(&mut self, _msg: GetPlan, _ctx: &mut Self::Context) -> Self::Result {
+        trace!("agent: request to get plan");
+        MessageResult(self.plan(chrono::Utc::now()))
+    }
+}
+
+/// Request: arm a one-time finalization at a given UTC timestamp.
+pub struct ScheduleFinalize {
+    /// UTC timestamp (seconds since epoch) at which to finalize.
+    pub timestamp: i64,
+}
+
+impl Message for ScheduleFinalize {
+    type Result = Result<(), Error>;
+}
+
+impl Handler<ScheduleFinalize> for UpdateAgent {
+    type Result = Result<(), Error>;
+
+    fn handle(&mut self, msg: ScheduleFinalize, _ctx: &mut Self::Context) -> Self::Result {
+        trace!(
+            "agent: request to schedule finalization at {}",
+            msg.timestamp
+        );
+        self.schedule_finalize(msg.timestamp)
+    }
+}
+
+/// Request: approve a reboot into the staged update.
+pub struct ApprovePendingReboot {}
+
+impl Message for ApprovePendingReboot {
+    type Result = Result<String, Error>;
+}
+
+impl Handler<ApprovePendingReboot> for UpdateAgent {
+    type Result = Result<String, Error>;
+
+    fn handle(&mut self, _msg: ApprovePendingReboot, _ctx: &mut Self::Context) -> Self::Result {
+        trace!("agent: request to approve pending reboot");
+        self.approve_pending_reboot()
+    }
+}
+
+/// Request: cancel the pending reboot into the staged update (scheduled via
+/// logind, or counting down), if any.
+pub struct CancelPendingReboot {}
+
+impl Message for CancelPendingReboot {
+    type Result = Result<bool, Error>;
+}
+
+impl Handler<CancelPendingReboot> for UpdateAgent {
+    type Result = Result<bool, Error>;
+
+    fn handle(&mut self, _msg: CancelPendingReboot, _ctx: &mut Self::Context) -> Self::Result {
+        trace!("agent: request to cancel pending reboot");
+        self.cancel_pending_reboot()
+    }
+}
+
+/// Request: cancel the one-time scheduled finalization, if any.
+pub struct CancelScheduledFinalize {}
+
+impl Message for CancelScheduledFinalize {
+    type Result = Result<bool, Error>;
+}
+
+impl Handler<CancelScheduledFinalize> for UpdateAgent {
+    type Result = Result<bool, Error>;
+
+    fn handle(&mut self, _msg: CancelScheduledFinalize, _ctx: &mut Self::Context) -> Self::Result {
+        trace!("agent: request to cancel scheduled finalization");
+        let cancelled = self.clear_scheduled_finalize()?;
+        if cancelled {
+            log::info!("one-time scheduled finalization cancelled");
+        }
+        Ok(cancelled)
+    }
+}
+
+/// Request: forbid reboots during a one-off time window.
+pub struct SetTemporaryBlackout {
+    /// UTC timestamp (seconds since epoch) at which the blackout starts.
+    pub start: i64,
+    /// UTC timestamp (seconds since epoch) at which the blackout ends.
+    pub end: i64,
+    /// Reason for the blackout, for humans.
+    pub reason: String,
+}
+
+impl Message for SetTemporaryBlackout {
+    type Result = Result<(), Error>;
+}
+
+impl Handler<SetTemporaryBlackout> for UpdateAgent {
+    type Result = Result<(), Error>;
+
+    fn handle(&mut self, msg: SetTemporaryBlackout, _ctx: &mut Self::Context) -> Self::Result {
+        trace!(
+            "agent: request to set temporary blackout from {} to {}",
+            msg.start,
+            msg.end
+        );
+        self.set_temporary_blackout(msg.start, msg.end, &msg.reason)
+    }
+}
+
+/// Request: lift the temporary blackout, if any.
+pub struct ClearTemporaryBlackout {}
+
+impl Message for ClearTemporaryBlackout {
+    type Result = Result<bool, Error>;
+}
+
+impl Handler<ClearTemporaryBlackout> for UpdateAgent {
+    type Result = Result<bool, Error>;
+
+    fn handle(&mut self, _msg: ClearTemporaryBlackout, _ctx: &mut Self::Context) -> Self::Result {
+        trace!("agent: request to clear temporary blackout");
+        let cleared = self.clear_temporary_blackout()?;
+        if cleared {
+            log::info!("temporary blackout lifted");
+        }
+        Ok(cleared)
+    }
+}
+
+/// Request: hold the node on its booted release.
+pub struct HoldUpdates {}
+
+impl Message for HoldUpdates {
+    type Result = Result<String, Error>;
+}
+
+impl Handler<HoldUpdates> for UpdateAgent {
+    type Result = Result<String, Error>;
+
+    fn handle(&mut self, _msg: HoldUpdates, _ctx: &mut Self::Context) -> Self::Result {
+        trace!("agent: request to hold updates");
+        self.hold_updates()
+    }
+}
+
+/// Request: subscribe to agent events (e.g. to emit them as D-Bus signals).
+pub struct SubscribeEvents {
+    /// Callback, invoked on the agent thread for each event.
+    pub listener: EventListener,
+}
+
+impl Message for SubscribeEvents {
+    type Result = ();
+}
+
+impl Handler<SubscribeEvents> for UpdateAgent {
+    type Result = ();
+
+    fn handle(&mut self, msg: SubscribeEvents, _ctx: &mut Self::Context) -> Self::Result {
+        trace!("agent: request to subscribe to events");
+        self.events.add(msg.listener);
+    }
+}
+
+/// Request: release the hold on the booted release, if any.
+pub struct ReleaseHold {}
+
+impl Message for ReleaseHold {
+    type Result = Result<bool, Error>;
+}
+
+impl Handler<ReleaseHold> for UpdateAgent {
+    type Result = Result<bool, Error>;
+
+    fn handle(&mut self, _msg: ReleaseHold, _ctx: &mut Self::Context) -> Self::Result {
+        trace!("agent: request to release update hold");
+        let released = self.release_hold()?;
+        if released {
+            log::info!("update hold released");
+        }
+        Ok(released)
+    }
+}
+
+/// Request: move this node to another update group.
+pub struct SetUpdateGroup {
+    /// New update group.
+    pub group: String,
+}
+
+impl Message for SetUpdateGroup {
+    type Result = Result<bool, Error>;
+}
+
+impl Handler<SetUpdateGroup> for UpdateAgent {
+    type Result = ResponseActFuture<Self, Result<bool, Error>>;
+
+    fn handle(&mut self, msg: SetUpdateGroup, _ctx: &mut Self::Context) -> Self::Result {
+        trace!("agent: request to set update group to '{}'", msg.group);
+        let previous_strategy = match self.set_update_group(&msg.group) {
+            Ok(Some(s)) => s,
+            Ok(None) => return Box::pin(actix::fut::ok(false)),
+            Err(e) => return Box::pin(actix::fut::err(e)),
+        };
+
+        // Release any reboot slot held under the old group, then re-register
+        // under the new one if steady state was already reported.
+        let reregister = !matches!(
+            self.state,
+            UpdateAgentState::StartState | UpdateAgentState::Initialized
+        );
+        let strategy = self.strategy.clone();
+        let reregistration = async move {
+            if !previous_strategy.report_steady().await {
+                log::warn!("failed to release reboot slot held under previous update group");
+            }
+            if reregister && !strategy.report_steady().await {
+                log::warn!("failed to report steady state under new update group");
+            }
+            Ok(true)
+        };
+
+        Box::pin(reregistration.into_actor(self))
+    }
+}
+
+/// Request: switch the node to another update stream.
+pub struct SwitchStream {
+    /// Update stream to switch to.
+    pub stream: String,
+}
+
+impl Message for SwitchStream {
+    type Result = Result<String, Error>;
+}
+
+impl Handler<SwitchStream> for UpdateAgent {
+    type Result = Result<String, Error>;
+
+    fn handle(&mut self, msg: SwitchStream, ctx: &mut Self::Context) -> Self::Result {
+        trace!("agent: request to switch update stream to '{}'", msg.stream);
+        let refspec = match self.prepare_stream_switch(&msg.stream)? {
+            Some(r) => r,
+            None => return Ok(String::new()),
+        };
+
+        log::info!(
+            "switching to update stream '{}', rebasing to '{}'",
+            msg.stream,
+            refspec
+        );
+        update_unit_status(
+            StatusSummary::new("staging").reason("stream-switch"),
+            &format!("switching to update stream: {}", msg.stream),
+        );
+        let stream = msg.stream;
+        let msg = rpm_ostree::RebaseDeployment {
+            refspec: refspec.clone(),
+        };
+        let rebase = self
+            .rpm_ostree_actor
+            .send(msg)
+            .unwrap_or_else(|e| Err(e.into()))
+            .into_actor(self)
+            .map(move |res, actor, _ctx| {
+                let res = res.and_then(|release| actor.complete_stream_switch(&stream, release));
+                if let Err(e) = res {
+                    actor.record_error("failed to switch update stream", &e);
+                }
+            });
+
+        // Rebasing takes a while, thus it completes in the background. It is
+        // processed sequentially with state machine refresh ticks, so that no
+        // other update gets staged meanwhile.
+        ctx.wait(rebase);
+
+        Ok(refspec)
+    }
+}
+
+/// Request: reload configuration from disk.
+pub struct Reload {}
+
+impl Message for Reload {
+    type Result = Result<(), Error>;
+}
+
+impl Handler<Reload> for UpdateAgent {
+    type Result = Result<(), Error>;
+
+    fn handle(&mut self, _msg: Reload, _ctx: &mut Self::Context) -> Self::Result {
+        trace!("agent: request to reload configuration");
+        self.reload_settings(Settings::assemble())
+    }
+}
+
+/// Request: shut down the agent, e.g. on service stop.
+///
+/// Refresh ticks stop, the reboot slot is released (unless a reboot into
+/// the update may be underway) and the current state is recorded. In-flight
+/// rpm-ostree transactions must be aborted beforehand through the operation
+/// queue, as this request is processed sequentially with state machine
+/// refresh ticks.
+pub struct Shutdown {}
+
+impl Message for Shutdown {
+    type Result = Result<(), Error>;
+}
+
+impl Handler<Shutdown> for UpdateAgent {
+    type Result = ResponseActFuture<Self, Result<(), Error>>;
+
+    fn handle(&mut self, _msg: Shutdown, _ctx: &mut Self::Context) -> Self::Result {
+        trace!("agent: request to shut down");
+        self.shutting_down = true;
+        update_unit_status(StatusSummary::new("stopping"), "shutting down");
+
+        let target = self.state.target().map(|r| r.version.as_str());
+        let record = ShutdownRecord::new(self.state.name(), target, chrono::Utc::now());
+        if let Err(e) = record.persist(SHUTDOWN_RECORD_PATH) {
+            log::error!("{:#}", e);
+        }
+
+        // The reboot slot is kept while the node may be rebooting into the
+        // update, including reboots initiated by others (e.g. via logind).
+        let release_slot = super::release_slot_on_shutdown(
+            &self.state,
+            self.pending_reboot.as_ref(),
+            self.finalize_attempted,
+        ) && !super::system_going_down();
+        let strategy = self.strategy.clone();
+        let release = async move {
+            if release_slot && !strategy.report_steady().await {
+                log::warn!("failed to release reboot slot on shutdown");
+            }
+            log::info!("update agent {}", record.describe());
+            Ok(())
+        };
+
+        Box::pin(release.into_actor(self))
+    }
+}
+
+/// Request: get the UTC timestamp of the one-time scheduled finalization, if any.
+pub struct ScheduledFinalizeTime {}
+
+impl Message for ScheduledFinalizeTime {
+    type Result = Option<i64>;
+}
+
+impl Handler<ScheduledFinalizeTime> for UpdateAgent {
+    type Result = Option<i64>;
+
+    fn handle(&mut self, _msg: ScheduledFinalizeTime, _ctx: &mut Self::Context) -> Self::Result {
+        trace!("agent: request to get scheduled finalization time");
+        self.scheduled_finalize.as_ref().map(|s| s.timestamp())
+    }
+}
+
+/// Request: get the update target (available, downloaded, staged or finalized), if any.
+pub struct TargetRelease {}
+
+impl Message for TargetRelease {
+    type Result = Option<Release>;
+}
+
+impl Handler<TargetRelease> for UpdateAgent {
+    type Result = Option<Release>;
+
+    fn handle(&mut self, _msg: TargetRelease, _ctx: &mut Self::Context) -> Self::Result {
+        trace!("agent: request to get target release");
+        self.state.target().cloned()
+    }
+}
+
 pub(crate) struct RefreshTick {}
 
 impl Message for RefreshTick {
@@ -58,18 +577,86 @@ impl Handler<RefreshTick> for UpdateAgent {
     type Result = ResponseActFuture<Self, Result<(), Error>>;
 
     fn handle(&mut self, _msg: RefreshTick, ctx: &mut Self::Context) -> Self::Result {
+        if self.shutting_down {
+            trace!("update agent shutting down, skipping tick");
+            return Box::pin(actix::fut::ok(()));
+        }
+
         let tick_timestamp = chrono::Utc::now();
         LAST_REFRESH.set(tick_timestamp.timestamp());
+        self.refresh_tick = None;
+        self.state.record_duration();
 
         trace!("update agent tick, current state: {:?}", self.state);
         let prev_state = self.state.clone();
+        let prev_state_name = prev_state.name();
+
+        // A one-time scheduled finalization only applies to updates already
+        // staged by the scheduled time.
+        let is_staged = matches!(self.state, UpdateAgentState::UpdateStaged(_));
+        if self.scheduled_finalize_due() && !is_staged {
+            log::warn!("no update staged at scheduled finalization time, schedule expired");
+            if let Err(e) = self.clear_scheduled_finalize() {
+                log::error!("{:#}", e);
+            }
+        }
+
+        let blackout_expired = self
+            .temporary_blackout
+            .as_ref()
+            .map(|b| b.is_expired(&tick_timestamp))
+            .unwrap_or(false);
+        if blackout_expired {
+            log::info!("temporary blackout expired");
+            if let Err(e) = self.clear_temporary_blackout() {
+                log::error!("{:#}", e);
+            }
+        }
+
+        // Reports are only retried while polling, in order not to race with
+        // the strategy locking for finalization.
+        let polling = matches!(
+            self.state,
+            UpdateAgentState::ReportedSteady
+                | UpdateAgentState::NoNewUpdate
+                | UpdateAgentState::WaitingForWave(_)
+        );
+        if self.strategy_degraded && polling {
+            let retry = self.retry_report_steady();
+            ctx.spawn(retry);
+        }
+        if polling {
+            if let Some(cleanup) = self.cleanup_rollback_if_due() {
+                ctx.spawn(cleanup);
+            }
+        }
 
         let state_action = match &self.state {
             UpdateAgentState::StartState => self.tick_initialize(),
             UpdateAgentState::Initialized => self.tick_report_steady(),
+            UpdateAgentState::ReportedSteady if self.first_check_delayed() => {
+                self.tick_first_check_delayed()
+            }
             UpdateAgentState::ReportedSteady => self.tick_check_updates(),
             UpdateAgentState::NoNewUpdate => self.tick_check_updates(),
+            UpdateAgentState::WaitingForWave(_) => self.tick_check_updates(),
+            UpdateAgentState::UpdateAvailable((release, _)) if self.hold.is_some() => {
+                let update = release.clone();
+                self.tick_held_update(update)
+            }
+            UpdateAgentState::UpdateAvailable((release, attempts)) if *attempts > 0 => {
+                let update = release.clone();
+                self.tick_retry_update(update)
+            }
             UpdateAgentState::UpdateAvailable((release, _)) => {
+                let update = release.clone();
+                self.tick_fetch_update(update)
+            }
+            UpdateAgentState::UpdateDownloaded((release, _)) if self.hold.is_some() => {
+                let update = release.clone();
+                self.tick_held_update(update)
+            }
+            UpdateAgentState::UpdateDownloaded((release, _)) => {
                 let update = release.clone();
                 self.tick_stage_update(update)
             }
@@ -85,17 +672,34 @@ impl Handler<RefreshTick> for UpdateAgent {
         };
 
         let update_machine = state_action.then(move |_r, actor, ctx| {
-            if let Some(pause) = actor.refresh_delay(prev_state) {
+            if actor.state.name() != prev_state.name() {
+                actor.queue_state_change();
+            }
+            actor.flush_webhook(ctx);
+
+            if let Some((pause, reason)) = actor.refresh_delay(prev_state) {
                 log::trace!(
-                    "scheduling next agent refresh in {} seconds",
-                    pause.as_secs()
+                    "scheduling next agent refresh in {} seconds ({})",
+                    pause.as_secs(),
+                    reason
                 );
-                Self::tick_later(ctx, pause);
+                actor.next_refresh = chrono::Duration::from_std(pause)
+                    .ok()
+                    .map(|d| chrono::Utc::now() + d);
+                actor.next_refresh_reason = reason;
+                actor.refresh_tick = Some(Self::tick_later(ctx, pause));
             } else {
                 let update_timestamp = chrono::Utc::now();
+                actor.next_refresh = Some(update_timestamp);
+                actor.next_refresh_reason = format!("state change to {}", actor.state.name());
+                NEXT_REFRESH_JITTER.set(0);
                 actor.state_changed = update_timestamp;
+                if actor.state.name() != prev_state_name {
+                    actor.log_state_change();
+                }
                 Self::tick_now(ctx);
             }
+            NEXT_REFRESH.set(actor.next_refresh.map(|t| t.timestamp()).unwrap_or(0));
             actix::fut::ready(())
         });
 
@@ -117,22 +721,94 @@ impl UpdateAgent {
         ctx.notify_later(RefreshTick {}, after)
     }
 
+    /// Queue a webhook event for the current state, if a webhook is configured.
+    fn queue_state_change(&self) {
+        if let Some(webhook) = &self.webhook {
+            let version = self.state.target().map(|r| r.version.as_str());
+            webhook.queue(Event::new("state-change", self.state.name(), version));
+        }
+    }
+
+    /// Deliver queued webhook events in the background, unless already in progress.
+    fn flush_webhook(&mut self, ctx: &mut Context<Self>) {
+        let webhook = match &self.webhook {
+            Some(w) if !self.webhook_flushing && w.has_queued() => w,
+            _ => return,
+        };
+
+        self.webhook_flushing = true;
+        let flush = actix::fut::wrap_future::<_, Self>(webhook.flush()).map(|_, actor, _ctx| {
+            actor.webhook_flushing = false;
+        });
+        ctx.spawn(flush);
+    }
+
     /// Pausing interval between state-machine refresh cycles.
     ///
     /// This influences the pace of the update-agent refresh loop. Timing of the
     /// state machine is not uniform. Some states benefit from more/less
     /// frequent refreshes, or can be customized by the user.
-    fn refresh_delay(&self, prev_state: UpdateAgentState) -> Option<Duration> {
-        if Self::should_tick_immediately(&self.state, &prev_state) {
+    ///
+    /// This returns the delay along with a human-readable reason for it, or
+    /// `None` if the state machine should be refreshed right away.
+    fn refresh_delay(&self, prev_state: UpdateAgentState) -> Option<(Duration, String)> {
+        if self.state == UpdateAgentState::ReportedSteady {
+            if let Some(remaining) = self.first_check_remaining() {
+                NEXT_REFRESH_JITTER.set(0);
+                return Some((remaining, "first check splay".to_string()));
+            }
+        }
+        if Self::should_tick_immediately(&prev_state, &self.state) {
             return None;
         }
 
-        let (mut refresh_delay, should_jitter) = self.state.get_refresh_delay(self.steady_interval);
+        let (mut refresh_delay, should_jitter) = self.state.get_refresh_delay(
+            self.steady_interval,
+            self.deploy_retry_max_delay,
+            &self.postponements,
+        );
+        let mut reason = format!("{} state", self.state.name());
+        let mut jitter = Duration::default();
         if should_jitter {
-            refresh_delay = Self::add_jitter(refresh_delay);
+            let jittered = Self::add_jitter(refresh_delay);
+            jitter = jittered.saturating_sub(refresh_delay);
+            refresh_delay = jittered;
+            reason.push_str(&format!(", jitter {}s", jitter.as_secs()));
+        };
+        NEXT_REFRESH_JITTER.set(jitter.as_secs().try_into().unwrap_or(i64::MAX));
+
+        // Do not oversleep a one-time scheduled finalization.
+        if let Some(schedule) = &self.scheduled_finalize {
+            if let Some(remaining) = schedule.remaining(&chrono::Utc::now()) {
+                if remaining < refresh_delay {
+                    refresh_delay = remaining;
+                    reason.push_str(", capped by scheduled finalization");
+                }
+            }
+        }
+
+        // Do not oversleep a reboot scheduled via logind, nor the end of a
+        // countdown before reboot.
+        let pending_finalize = match &self.pending_reboot {
+            Some(PendingReboot::Scheduled(reboot_at)) => Some((
+                *reboot_at - chrono::Duration::seconds(logind::FINALIZE_MARGIN_SECS),
+                "logind reboot",
+            )),
+            Some(PendingReboot::Countdown(finalize_at)) => Some((*finalize_at, "reboot countdown")),
+            _ => None,
         };
+        if let Some((finalize_at, label)) = pending_finalize {
+            let remaining = finalize_at
+                .signed_duration_since(chrono::Utc::now())
+                .to_std()
+                .unwrap_or_default();
+            if remaining < refresh_delay {
+                refresh_delay = remaining;
+                reason.push_str(&format!(", capped by {}", label));
+            }
+        }
 
-        Some(refresh_delay)
+        Some((refresh_delay, reason))
     }
 
     /// Return whether a transition from `prev_state` to `cur_state` warrants an immediate
@@ -145,10 +821,12 @@ impl UpdateAgent {
 
         // State changes trigger immediate tick/action.
         if discriminant(prev_state) != discriminant(cur_state) {
-            // Unless we're transitioning from ReportedSteady to NoNewUpdate.
-            if !(*prev_state == UpdateAgentState::ReportedSteady
-                && *cur_state == UpdateAgentState::NoNewUpdate)
-            {
+            // Unless we're transitioning from ReportedSteady to NoNewUpdate,
+            // or waiting for a rollout wave (which would only check again).
+            let steady = *prev_state == UpdateAgentState::ReportedSteady
+                && *cur_state == UpdateAgentState::NoNewUpdate;
+            let waiting = matches!(cur_state, UpdateAgentState::WaitingForWave(_));
+            if !steady && !waiting {
                 return true;
             }
         }
@@ -204,29 +882,47 @@ impl UpdateAgent {
         trace!("update agent in start state");
 
         // Only register as the updates driver for rpm-ostree if auto-updates logic enabled.
-        let initialization = if self.enabled {
+        let initialization = if self.enabled && !self.inhibited {
             self.register_as_driver()
         } else {
             self.nop()
         }
+        .then(|_r, actor, _ctx| actor.refresh_booted_layering())
         .then(|_r, actor, _ctx| actor.local_deployments())
         .map(|res, actor, _ctx| {
             // Report non-future update target deployments.
             if let Ok(depls) = res {
                 Self::log_excluded_depls(&depls, actor);
             }
-            let status;
-            if actor.enabled {
+            let layered = actor
+                .booted_layering
+                .as_ref()
+                .map(|l| !l.is_empty())
+                .unwrap_or(false);
+            let (summary, status);
+            if actor.inhibited {
+                summary = StatusSummary::new("end").reason("kernel-argument");
+                status = "initialization complete, auto-updates logic inhibited for this boot by `zincati.inhibit` kernel argument";
+                log::warn!("{}", status);
+                actor.state.end();
+            } else if actor.enabled && actor.block_on_layered && layered {
+                summary = StatusSummary::new("end").reason("layered-packages");
+                status = "initialization complete, auto-updates logic blocked by local changes to the booted deployment";
+                log::warn!("{}", status);
+                actor.state.end();
+            } else if actor.enabled {
+                summary = StatusSummary::new("initialized");
                 status = "initialization complete, auto-updates logic enabled";
                 log::info!("{}", status);
                 actor.state.initialized();
                 actor.strategy.record_details();
             } else {
+                summary = StatusSummary::new("end").reason("updates-disabled");
                 status = "initialization complete, auto-updates logic disabled by configuration";
                 log::warn!("{}", status);
                 actor.state.end();
             };
-            update_unit_status(status);
+            update_unit_status(summary, status);
             notify_ready();
             Ok(())
         });
@@ -234,17 +930,49 @@ impl UpdateAgent {
         Box::pin(initialization)
     }
 
+    /// Query local changes (layered packages and overrides) to the booted
+    /// deployment, logging them.
+    fn refresh_booted_layering(&mut self) -> ResponseActFuture<Self, ()> {
+        let msg = rpm_ostree::QueryBootedLayering {};
+        let query = self
+            .rpm_ostree_actor
+            .send(msg)
+            .unwrap_or_else(|e| Err(e.into()))
+            .into_actor(self)
+            .map(|res, actor, _ctx| match res {
+                Ok(layering) => {
+                    if layering.is_empty() {
+                        log::debug!("no local changes to the booted deployment");
+                    } else {
+                        log::warn!(
+                            "local changes to the booted deployment may extend staging or make it fail, {}",
+                            layering.describe()
+                        );
+                    }
+                    actor.booted_layering = Some(layering);
+                }
+                Err(e) => log::warn!("failed to query local changes to booted deployment: {:#}", e),
+            });
+        Box::pin(query)
+    }
+
     /// Try to report steady state.
     fn tick_report_steady(&mut self) -> ResponseActFuture<Self, Result<(), ()>> {
         trace!("trying to report steady state");
 
         let report_steady = self.strategy.report_steady();
         let state_change =
-            actix::fut::wrap_future::<_, Self>(report_steady).map(|is_steady, actor, _ctx| {
+            actix::fut::wrap_future::<_, Self>(report_steady).map(|is_steady, actor, ctx| {
                 if is_steady {
                     log::debug!("reached steady state, periodically polling for updates");
-                    update_unit_status("periodically polling for updates");
+                    update_unit_status(
+                        StatusSummary::new("steady"),
+                        "periodically polling for updates",
+                    );
                     actor.state.reported_steady();
+                    actor.steady_state_reached(ctx);
+                } else {
+                    actor.steady_report_failed();
                 }
                 Ok(())
             });
@@ -252,36 +980,280 @@ impl UpdateAgent {
         Box::pin(state_change)
     }
 
-    /// Try to check for updates.
+    /// Retry reporting steady state, while polling for updates in degraded mode.
+    fn retry_report_steady(&mut self) -> impl ActorFuture<Self, Output = ()> {
+        trace!("retrying to report steady state");
+
+        let report_steady = self.strategy.report_steady();
+        actix::fut::wrap_future::<_, Self>(report_steady).map(|is_steady, actor, ctx| {
+            if !actor.strategy_degraded {
+                return;
+            }
+            if is_steady {
+                log::info!("reported steady state, leaving degraded mode");
+                actor.steady_state_reached(ctx);
+            } else {
+                actor.steady_report_failed();
+            }
+        })
+    }
+
+    /// Run actions pending on steady state being reported.
+    fn steady_state_reached(&mut self, ctx: &mut Context<Self>) {
+        self.steady_report_failures = 0;
+        self.strategy_degraded = false;
+        STRATEGY_DEGRADED.set(0);
+        self.record_healthy_release();
+
+        if let Some(hooks) = &self.post_boot_hooks {
+            let run = hooks.run_pending(&self.identity.current_os);
+            ctx.spawn(run.into_actor(self));
+        }
+        if let Some(reporter) = &self.outcome_report {
+            let report = reporter.report_pending(&self.identity);
+            ctx.spawn(report.into_actor(self));
+        }
+    }
+
+    /// Clean up the rollback deployment, if the booted release has been
+    /// healthy for long enough.
+    fn cleanup_rollback_if_due(&mut self) -> Option<impl ActorFuture<Self, Output = ()>> {
+        let after = self.cleanup_rollback_after?;
+        let record = self.rollback_cleanup.as_ref()?;
+        if self.rollback_cleanup_running || !record.is_due(after, &chrono::Utc::now()) {
+            return None;
+        }
+
+        log::info!(
+            "release {} healthy since {}, cleaning up rollback deployment",
+            record.version,
+            record.healthy_since.format("%a %Y-%m-%d %H:%M:%S %Z")
+        );
+        self.rollback_cleanup_running = true;
+        let msg = rpm_ostree::CleanupRollback {};
+        let cleanup = self
+            .rpm_ostree_actor
+            .send(msg)
+            .unwrap_or_else(|e| Err(e.into()))
+            .into_actor(self)
+            .map(|res, actor, _ctx| {
+                actor.rollback_cleanup_running = false;
+                if let Err(e) = res {
+                    actor.record_error("failed to clean up rollback deployment", &e);
+                    return;
+                }
+                ROLLBACK_CLEANUPS.inc();
+                log::info!("rollback deployment cleaned up");
+                if let Some(record) = actor.rollback_cleanup.as_mut() {
+                    record.cleaned = true;
+                    if let Err(e) = record.persist(ROLLBACK_CLEANUP_PATH) {
+                        log::error!("{:#}", e);
+                    }
+                }
+            });
+        Some(cleanup)
+    }
+
+    /// Record a failure at reporting steady state.
+    ///
+    /// Once the configured number of consecutive failures is reached, this
+    /// escalates to polling for updates in degraded mode, retrying the report
+    /// on each refresh.
+    fn steady_report_failed(&mut self) {
+        STEADY_REPORT_FAILURES.inc();
+        self.steady_report_failures = self.steady_report_failures.saturating_add(1);
+        log::warn!(
+            "failed to report steady state to '{}' strategy ({} consecutive failures)",
+            self.strategy.configuration_label(),
+            self.steady_report_failures
+        );
+
+        let max_failures = match self.steady_report_max_failures {
+            Some(max) => max.get(),
+            None => return,
+        };
+        if self.strategy_degraded || self.steady_report_failures < max_failures {
+            return;
+        }
+        if self.state != UpdateAgentState::Initialized {
+            return;
+        }
+
+        log::warn!(
+            "giving up on reporting steady state to '{}' strategy after {} failures, polling for updates in degraded mode",
+            self.strategy.configuration_label(),
+            self.steady_report_failures
+        );
+        self.strategy_degraded = true;
+        STRATEGY_DEGRADED.set(1);
+        update_unit_status(
+            StatusSummary::new("steady").reason("strategy-degraded"),
+            "periodically polling for updates (degraded, steady state not reported)",
+        );
+        self.state.reported_steady();
+    }
+
+    /// Return the time remaining before the first update check, if delayed.
+    fn first_check_remaining(&self) -> Option<Duration> {
+        let first_check_at = self.first_check_at?;
+        (first_check_at - chrono::Utc::now())
+            .to_std()
+            .ok()
+            .filter(|d| *d > Duration::from_secs(0))
+    }
+
+    /// Whether the first update check is still delayed after start.
+    fn first_check_delayed(&self) -> bool {
+        self.first_check_remaining().is_some()
+    }
+
+    /// Wait before the first update check, to smear checks from nodes
+    /// started at the same time.
+    fn tick_first_check_delayed(&mut self) -> ResponseActFuture<Self, Result<(), ()>> {
+        if let Some(first_check_at) = self.first_check_at {
+            trace!("first update check delayed until {}", first_check_at);
+            update_unit_status(
+                StatusSummary::new("steady").reason("first-check-splay"),
+                &format!(
+                    "first update check delayed until {}",
+                    first_check_at.format("%a %Y-%m-%d %H:%M:%S %Z")
+                ),
+            );
+        }
+        self.nop()
+    }
+
+    /// Try to check for updates, unless an update was staged out-of-band.
     fn tick_check_updates(&mut self) -> ResponseActFuture<Self, Result<(), ()>> {
+        let state_change = self
+            .staged_deployment()
+            .then(|staged, actor, _ctx| match staged {
+                Some(release) if release.checksum != actor.identity.current_os.checksum => {
+                    actor.adopt_staged(release)
+                }
+                _ => actor.check_updates(),
+            });
+
+        Box::pin(state_change)
+    }
+
+    /// Adopt a deployment staged out-of-band (e.g. by a manual `rpm-ostree upgrade`),
+    /// instead of staging another update on top of it.
+    ///
+    /// The adopted deployment is then finalized as any other update.
+    fn adopt_staged(&mut self, release: Release) -> ResponseActFuture<Self, Result<(), ()>> {
+        ADOPTED_DEPLOYMENTS.inc();
+        let msg = format!("update staged: {} (found already staged)", release.version);
+        update_unit_status(
+            StatusSummary::new("staged")
+                .target(&release.version)
+                .reason("adopted"),
+            &msg,
+        );
+        log::warn!(
+            "found deployment '{}' already staged, adopting it as update target",
+            release.version
+        );
+        self.staged_downgrade = false;
+        self.staged_fixed_cves.clear();
+        self.adopted_staged = Some(release.checksum.clone());
+        self.record_history(
+            HistoryEvent::Staged,
+            Some(&release),
+            "adopted, staged out-of-band",
+        );
+        self.emit_event(AgentEvent::UpdateStaged {
+            version: release.version.clone(),
+        });
+        let report = self.report_security_fixes(release.clone());
+        self.state.update_staged(release, self.postponements.max);
+        Box::pin(report.map(|_, _actor, _ctx| Ok(())))
+    }
+
+    /// Check for updates.
+    fn check_updates(&mut self) -> ResponseActFuture<Self, Result<(), ()>> {
         trace!("trying to check for updates");
 
         let state_change = self
             .local_deployments()
-            .then(|res, actor, _ctx| {
+            .then(|res, actor, ctx| {
+                // Retry outcome reports which could not be sent earlier.
+                if let Some(reporter) = &actor.outcome_report {
+                    if reporter.has_queued() {
+                        ctx.spawn(reporter.flush().into_actor(actor));
+                    }
+                }
                 let timestamp_now = chrono::Utc::now();
-                update_unit_status(&format!(
-                    "periodically polling for updates (last checked {})",
-                    timestamp_now.format("%a %Y-%m-%d %H:%M:%S %Z")
-                ));
+                update_unit_status(
+                    StatusSummary::new("steady"),
+                    &format!(
+                        "periodically polling for updates (last checked {})",
+                        timestamp_now.format("%a %Y-%m-%d %H:%M:%S %Z")
+                    ),
+                );
                 let allow_downgrade = actor.allow_downgrade;
                 let release = match res {
                     Ok(depls) => {
                         actor
-                            .cincinnati
+                            .source
                             .fetch_update_hint(&actor.identity, depls, allow_downgrade)
                     }
                     _ => Box::pin(futures::future::ready(None)),
                 };
                 release.into_actor(actor)
             })
+            .map(|res, actor, _ctx| res.filter(|rel| actor.passes_downgrade_barrier(rel)))
             .map(|res, actor, _ctx| {
                 match res {
                     Some(release) => {
-                        update_unit_status(&format!("found update on remote: {}", release.version));
-                        actor.state.update_available(release);
+                        let mut status = format!("found update on remote: {}", release.version);
+                        if let Some(advisory) = &release.advisory {
+                            if let Some(severity) = &advisory.severity {
+                                status.push_str(&format!(" (severity: {})", severity));
+                            }
+                            if let Some(notes) = &advisory.release_notes {
+                                log::info!("release notes for {}: {}", release.version, notes);
+                            }
+                        }
+                        if actor.state.target() != Some(&release) {
+                            actor.record_history(HistoryEvent::UpdateFound, Some(&release), "");
+                        }
+                        let now = chrono::Utc::now();
+                        match &release.wave {
+                            Some(wave) if !wave.is_open(&now) => {
+                                let status = format!(
+                                    "{}; waiting for rollout wave '{}', opening at {}",
+                                    status,
+                                    wave.name,
+                                    wave.start.format("%a %Y-%m-%d %H:%M:%S %Z")
+                                );
+                                update_unit_status(
+                                    StatusSummary::new("available")
+                                        .target(&release.version)
+                                        .reason("wave"),
+                                    &status,
+                                );
+                                actor.state.waiting_for_wave(release);
+                            }
+                            _ => {
+                                update_unit_status(
+                                    StatusSummary::new("available").target(&release.version),
+                                    &status,
+                                );
+                                actor.state.update_available(release);
+                            }
+                        };
                     }
                     None => {
+                        if let Some(reason) = cincinnati::deadend_reason() {
+                            update_unit_status(
+                                StatusSummary::new("steady").reason("deadend"),
+                                &format!(
+                                    "current release is a dead-end and will not further auto-update: {}",
+                                    reason
+                                ),
+                            );
+                        }
                         actor.state.no_new_update();
                     }
                 };
@@ -291,19 +1263,314 @@ impl UpdateAgent {
         Box::pin(state_change)
     }
 
+    /// Keep reporting an update, without staging it, while the node is held.
+    fn tick_held_update(&mut self, release: Release) -> ResponseActFuture<Self, Result<(), ()>> {
+        trace!("update held, not staging");
+
+        let phase = match self.state {
+            UpdateAgentState::UpdateDownloaded(_) => "downloaded",
+            _ => "available",
+        };
+        if let Some(hold) = &self.hold {
+            update_unit_status(
+                StatusSummary::new(phase)
+                    .target(&release.version)
+                    .reason("hold"),
+                &format!(
+                    "update {}: {}; staging forbidden by {}",
+                    phase,
+                    release.version,
+                    hold.describe()
+                ),
+            );
+        }
+        self.nop()
+    }
+
+    /// Try to download an update, within download windows.
+    fn tick_download_update(
+        &mut self,
+        release: Release,
+    ) -> ResponseActFuture<Self, Result<(), ()>> {
+        trace!("trying to download an update");
+
+        if let Some(schedule) = &self.download_schedule {
+            if !schedule.can_download(&chrono::Utc::now()) {
+                update_unit_status(
+                    StatusSummary::new("available")
+                        .target(&release.version)
+                        .reason("download-window"),
+                    &format!(
+                        "update available: {}; download delayed until next window {}",
+                        release.version,
+                        schedule.human_next_window()
+                    ),
+                );
+                return self.nop();
+            }
+        }
+        if let Some(deferred) = self.check_disk_space(&release) {
+            return deferred;
+        }
+        if self.fetch_deferred_on_metered(&release) {
+            return self.nop();
+        }
+
+        self.when_on_remote(release, |actor, release| actor.download_update(release))
+    }
+
+    /// Check free disk space before fetching or staging an update.
+    ///
+    /// If space is low, this returns a future deferring the operation to the
+    /// next tick, after running the cleanup command (once, until space is
+    /// back above thresholds) if configured.
+    fn check_disk_space(
+        &mut self,
+        release: &Release,
+    ) -> Option<ResponseActFuture<Self, Result<(), ()>>> {
+        let check = self.disk_space.as_ref()?;
+        let reason = match check.check() {
+            Ok(()) => {
+                self.disk_space_cleaned = false;
+                return None;
+            }
+            Err(reason) => reason,
+        };
+
+        FETCH_BLOCKED.with_label_values(&["disk_space"]).inc();
+        let status = if matches!(self.state, UpdateAgentState::UpdateDownloaded(_)) {
+            "downloaded"
+        } else {
+            "available"
+        };
+        let msg = format!(
+            "update {}: {}; deferred, {}",
+            status, release.version, reason
+        );
+        log::warn!("{}", msg);
+        update_unit_status(
+            StatusSummary::new(status)
+                .target(&release.version)
+                .reason("disk-space"),
+            &msg,
+        );
+        if !check.has_cleanup() || self.disk_space_cleaned {
+            return Some(self.nop());
+        }
+        let check = check.clone();
+
+        self.disk_space_cleaned = true;
+        let cleanup = tokio::task::spawn_blocking(move || check.cleanup())
+            .into_actor(self)
+            .map(|res, _actor, _ctx| {
+                match res {
+                    Ok(Ok(())) => log::info!("disk space cleanup completed"),
+                    Ok(Err(e)) => log::error!("{:#}", e),
+                    Err(e) => log::error!("failed to join disk space cleanup: {}", e),
+                };
+                Ok(())
+            });
+        Some(Box::pin(cleanup))
+    }
+
+    /// Whether fetching an update is deferred because the network connection
+    /// is metered, if configured to respect that.
+    ///
+    /// Failures at querying NetworkManager (e.g. when it is not running) do
+    /// not defer fetching.
+    fn fetch_deferred_on_metered(&self, release: &Release) -> bool {
+        if !self.respect_metered {
+            return false;
+        }
+        match metered::connection_metered() {
+            Ok(false) => false,
+            Ok(true) => {
+                FETCH_BLOCKED.with_label_values(&["metered"]).inc();
+                update_unit_status(
+                    StatusSummary::new("available")
+                        .target(&release.version)
+                        .reason("metered"),
+                    &format!(
+                        "update available: {}; fetching deferred while on a metered connection",
+                        release.version
+                    ),
+                );
+                true
+            }
+            Err(e) => {
+                log::warn!("{:#}", e);
+                false
+            }
+        }
+    }
+
+    /// Download an update.
+    fn download_update(&mut self, release: Release) -> ResponseActFuture<Self, Result<(), ()>> {
+        let target = release.clone();
+        let download_outcome = self.attempt_download(target);
+        let state_change = download_outcome.map(move |res, actor, _ctx| {
+            match res {
+                Ok(_) => {
+                    let msg = format!("update downloaded: {}", release.version);
+                    update_unit_status(
+                        StatusSummary::new("downloaded").target(&release.version),
+                        &msg,
+                    );
+                    log::trace!("{}", msg);
+                    actor.state.update_downloaded();
+                }
+                Err(_) => {
+                    let release_ver = release.version.clone();
+                    let fail_count = actor.deploy_attempt_failed(release);
+                    let msg = format!(
+                        "trying to download {} ({} failed download attempt{})",
+                        release_ver,
+                        fail_count,
+                        if fail_count > 1 { "s" } else { "" }
+                    );
+                    update_unit_status(
+                        StatusSummary::new("available")
+                            .target(&release_ver)
+                            .reason("download-failed"),
+                        &msg,
+                    );
+                    log::trace!("{}", msg);
+                }
+            };
+            Ok(())
+        });
+
+        Box::pin(state_change)
+    }
+
+    /// Try to download or stage an update, depending on configuration.
+    fn tick_fetch_update(&mut self, release: Release) -> ResponseActFuture<Self, Result<(), ()>> {
+        if self.download_schedule.is_some() || self.fetch_only_window {
+            self.tick_download_update(release)
+        } else {
+            self.tick_stage_update(release)
+        }
+    }
+
+    /// Retry fetching an update after failed deploy attempts, unless the
+    /// update target changed in the meantime.
+    ///
+    /// A new target starts over with no failed deploy attempts, so that
+    /// failures for the previous target do not get it quickly abandoned.
+    fn tick_retry_update(&mut self, release: Release) -> ResponseActFuture<Self, Result<(), ()>> {
+        trace!("checking update target before retrying deploy");
+
+        let state_change = self
+            .local_deployments()
+            .then(|res, actor, _ctx| {
+                let allow_downgrade = actor.allow_downgrade;
+                let hint = match res {
+                    Ok(depls) => {
+                        actor
+                            .source
+                            .fetch_update_hint(&actor.identity, depls, allow_downgrade)
+                    }
+                    _ => Box::pin(futures::future::ready(None)),
+                };
+                hint.into_actor(actor)
+            })
+            .map(|res, actor, _ctx| res.filter(|rel| actor.passes_downgrade_barrier(rel)))
+            .then(move |res, actor, _ctx| {
+                let now = chrono::Utc::now();
+                let target = match res {
+                    Some(rel) if rel.checksum != release.checksum => rel,
+                    _ => return actor.tick_fetch_update(release),
+                };
+                if target
+                    .wave
+                    .as_ref()
+                    .map(|w| !w.is_open(&now))
+                    .unwrap_or(false)
+                {
+                    return actor.tick_fetch_update(release);
+                }
+
+                log::info!(
+                    "update target changed from {} to {}, resetting failed deploy attempts",
+                    release.version,
+                    target.version
+                );
+                update_unit_status(
+                    StatusSummary::new("available").target(&target.version),
+                    &format!("found update on remote: {}", target.version),
+                );
+                actor.record_history(HistoryEvent::UpdateFound, Some(&target), "");
+                actor.state.update_retargeted(target.clone());
+                actor.tick_fetch_update(target)
+            });
+
+        Box::pin(state_change)
+    }
+
     /// Try to stage an update.
+    ///
+    /// If the update was already downloaded, staging it does not access the network.
     fn tick_stage_update(&mut self, release: Release) -> ResponseActFuture<Self, Result<(), ()>> {
         trace!("trying to stage an update");
 
+        let cache_only = matches!(self.state, UpdateAgentState::UpdateDownloaded(_));
+        if cache_only && self.fetch_only_window {
+            let now = chrono::Utc::now();
+            let lead_time = chrono::Duration::seconds(STAGING_LEAD_TIME_SECS);
+            let remaining = self.strategy.remaining_to_window(&now);
+            if remaining.map(|r| r > lead_time).unwrap_or(true) {
+                update_unit_status(
+                    StatusSummary::new("downloaded")
+                        .target(&release.version)
+                        .reason("window"),
+                    &format!(
+                        "update downloaded: {}; staging deferred until close to next finalization window",
+                        release.version
+                    ),
+                );
+                return self.nop();
+            }
+        }
+
+        if let Some(deferred) = self.check_disk_space(&release) {
+            return deferred;
+        }
+        if cache_only {
+            return self.stage_update(release, cache_only);
+        }
+        if self.fetch_deferred_on_metered(&release) {
+            return self.nop();
+        }
+        self.when_on_remote(release, move |actor, release| {
+            actor.stage_update(release, cache_only)
+        })
+    }
+
+    /// Fetch (unless `cache_only`) and stage an update.
+    fn stage_update(
+        &mut self,
+        release: Release,
+        cache_only: bool,
+    ) -> ResponseActFuture<Self, Result<(), ()>> {
         let target = release.clone();
-        let deploy_outcome = self.attempt_deploy(target);
-        let state_change = deploy_outcome.map(move |res, actor, _ctx| {
+        let deploy_outcome = self.attempt_deploy(target, cache_only);
+        let state_change = deploy_outcome.map(move |res, actor, ctx| {
             match res {
                 Ok(_) => {
                     let msg = format!("update staged: {}", release.version);
-                    update_unit_status(&msg);
+                    update_unit_status(StatusSummary::new("staged").target(&release.version), &msg);
                     log::trace!("{}", msg);
-                    actor.state.update_staged(release);
+                    actor.staged_downgrade = false;
+                    if actor.allow_downgrade {
+                        ctx.spawn(actor.check_staged_downgrade(release.clone()));
+                    }
+                    actor.staged_fixed_cves.clear();
+                    ctx.spawn(actor.report_security_fixes(release.clone()));
+                    actor.record_history(HistoryEvent::Staged, Some(&release), "");
+                    actor.emit_event(AgentEvent::UpdateStaged {
+                        version: release.version.clone(),
+                    });
+                    actor.state.update_staged(release, actor.postponements.max);
                 }
                 Err(_) => {
                     let release_ver = release.version.clone();
@@ -314,7 +1581,12 @@ impl UpdateAgent {
                         fail_count,
                         if fail_count > 1 { "s" } else { "" }
                     );
-                    update_unit_status(&msg);
+                    update_unit_status(
+                        StatusSummary::new("available")
+                            .target(&release_ver)
+                            .reason("stage-failed"),
+                        &msg,
+                    );
                     log::trace!("{}", msg);
                 }
             };
@@ -331,76 +1603,724 @@ impl UpdateAgent {
     ) -> ResponseActFuture<Self, Result<(), ()>> {
         trace!("trying to finalize an update");
 
-        let strategy_can_finalize = self.strategy.can_finalize();
-        let state_change = actix::fut::wrap_future::<_, Self>(strategy_can_finalize)
-            .then(|strategy_can_finalize, actor, _ctx| {
+        // A hold pins the node to its booted release, thus an update staged
+        // before the hold was placed is not finalized either.
+        if let Some(hold) = &self.hold {
+            update_unit_status(
+                StatusSummary::new("staged")
+                    .target(&release.version)
+                    .reason("hold"),
+                &format!(
+                    "update staged: {}; reboot forbidden by {}",
+                    release.version,
+                    hold.describe()
+                ),
+            );
+            self.abort_pending_reboot("update hold");
+            self.last_finalize_verdict = "hold";
+            self.state.update_staged(release, self.postponements.max);
+            return self.nop();
+        }
+
+        // A blackout forbids any reboot, including scheduled ones, regardless
+        // of update strategy.
+        if let Some((verdict, blackout)) = self.active_blackout() {
+            update_unit_status(
+                StatusSummary::new("staged")
+                    .target(&release.version)
+                    .reason(verdict),
+                &format!(
+                    "update staged: {}; reboot forbidden by {}",
+                    release.version, blackout
+                ),
+            );
+            self.abort_pending_reboot(&blackout);
+            BLACKOUT_BLOCKED.with_label_values(&[verdict]).inc();
+            self.last_finalize_verdict = verdict;
+            self.state.update_staged(release, self.postponements.max);
+            return self.nop();
+        }
+
+        let mut countdown_elapsed = false;
+        match self.pending_reboot.clone() {
+            Some(PendingReboot::Scheduled(at)) => return self.tick_logind_reboot(release, at),
+            Some(PendingReboot::Countdown(at)) if chrono::Utc::now() < at => {
+                trace!("waiting for countdown before reboot");
+                return self.nop();
+            }
+            Some(PendingReboot::Countdown(_)) => {
+                // Countdown over, finalize unless some check fails now.
+                countdown_elapsed = true;
+                self.pending_reboot = None;
+            }
+            Some(PendingReboot::Cancelled(retry_at)) if chrono::Utc::now() < retry_at => {
+                let verdict = self.cancelled_reboot_verdict();
+                update_unit_status(
+                    StatusSummary::new("staged")
+                        .target(&release.version)
+                        .reason(verdict),
+                    &format!(
+                        "update staged: {}; scheduled reboot cancelled, not rescheduling before {}",
+                        release.version,
+                        retry_at.format("%a %Y-%m-%d %H:%M:%S %Z")
+                    ),
+                );
+                return self.nop();
+            }
+            Some(PendingReboot::Cancelled(_)) => self.pending_reboot = None,
+            None => {}
+        }
+
+        let scheduled_due = self.scheduled_finalize_due();
+        let urgency_due = self.urgency_override_due(&release);
+        // A one-time scheduled finalization is an explicit request, thus
+        // it also counts as an approval.
+        let approval_pending = !scheduled_due && !self.reboot_approved(&release);
+        // The reboot lock is taken before anything else, and held until
+        // the reboot unless finalization does not happen on this tick.
+        if !approval_pending && !self.acquire_reboot_lock() {
+            update_unit_status(
+                StatusSummary::new("staged")
+                    .target(&release.version)
+                    .reason("reboot-lock"),
+                &format!(
+                    "update staged: {}; reboot delayed, lock held by another reboot manager",
+                    release.version
+                ),
+            );
+            self.last_finalize_verdict = "reboot-lock";
+            self.state.update_staged(release, self.postponements.max);
+            return self.nop();
+        }
+        let strategy = self.strategy.clone();
+        let gate = self.connectivity_gate.clone();
+        let health_checks = self.health_checks.clone();
+        let version = release.version.clone();
+        // The connectivity gate and health checks are checked first, so that
+        // strategy resources (e.g. a FleetLock reboot slot) are not held while
+        // finalization is blocked. An error result carries the finalization
+        // verdict and the reason for blocking it.
+        let can_finalize = async move {
+            if approval_pending {
+                let reason = "reboot waiting for approval".to_string();
+                return Err(("approval", reason));
+            }
+            if let Err(reason) = bootfs::check(Path::new(bootfs::BOOT_PATH)) {
+                let reason = format!("reboot delayed, {}", reason);
+                return Err(("boot-space", reason));
+            }
+            if let Some(gate) = gate {
+                if !gate.is_open().await {
+                    let reason = "reboot delayed due to connectivity gate".to_string();
+                    return Err(("connectivity-gate", reason));
+                }
+            }
+            if let Some(checks) = health_checks {
+                if let Err(failed) = checks.run().await {
+                    let reason = format!("FINALIZATION_BLOCKED by failed health check {}", failed);
+                    return Err(("health-checks", reason));
+                }
+            }
+            if scheduled_due {
+                log::info!("one-time scheduled finalization reached, overriding update strategy");
+                return Ok(true);
+            }
+            if urgency_due {
+                log::warn!(
+                    "URGENT_UPDATE_OVERRIDE: finalizing {} regardless of update strategy",
+                    version
+                );
+                URGENCY_OVERRIDES.inc();
+                return Ok(true);
+            }
+            Ok(strategy.can_finalize().await)
+        };
+        let state_change = actix::fut::wrap_future::<_, Self>(can_finalize)
+            .then(move |can_finalize, actor, _ctx| {
+                let strategy_can_finalize = match can_finalize {
+                    Ok(can) => can,
+                    Err((verdict, reason)) => {
+                        update_unit_status(
+                            StatusSummary::new("staged")
+                                .target(&release.version)
+                                .reason(verdict),
+                            &format!("update staged: {}; {}", release.version, reason),
+                        );
+                        actor.last_finalize_verdict = verdict;
+                        actor.state.update_staged(release, actor.postponements.max);
+                        let delayed: ResponseActFuture<Self, Result<Release, ()>> =
+                            Box::pin(actix::fut::err(()));
+                        return delayed;
+                    }
+                };
                 if !strategy_can_finalize {
-                    update_unit_status(&format!(
-                        "update staged: {}; reboot pending due to update strategy",
-                        &release.version
-                    ));
-                    // Reset number of postponements to `MAX_FINALIZE_POSTPONEMENTS`
+                    let (verdict, pending_reason) = match &actor.scheduled_finalize {
+                        Some(s) => (
+                            "scheduled",
+                            format!("reboot scheduled at {}", s.human_time()),
+                        ),
+                        None => match actor.urgency_due_time(&release) {
+                            Some(due) => (
+                                "strategy",
+                                format!(
+                                    "reboot pending due to update strategy, urgent update overrides it at {}",
+                                    due.format("%a %Y-%m-%d %H:%M:%S %Z")
+                                ),
+                            ),
+                            None => {
+                                let now = chrono::Utc::now();
+                                let reason = match actor.strategy.remaining_to_window(&now) {
+                                    Some(remaining) => format!(
+                                        "reboot pending due to update strategy, next window at {}",
+                                        (now + remaining).format("%a %Y-%m-%d %H:%M:%S %Z")
+                                    ),
+                                    None => "reboot pending due to update strategy".to_string(),
+                                };
+                                ("strategy", reason)
+                            }
+                        },
+                    };
+                    update_unit_status(
+                        StatusSummary::new("staged")
+                            .target(&release.version)
+                            .reason(verdict),
+                        &format!("update staged: {}; {}", &release.version, pending_reason),
+                    );
+                    // Reset number of postponements to the configured maximum
                     // if strategy does not allow finalization.
-                    actor.state.update_staged(release);
+                    actor.last_finalize_verdict = "strategy";
+                    actor.state.update_staged(release, actor.postponements.max);
+                    Box::pin(actix::fut::err(()))
+                } else if let Some(lead_time) = actor.logind_reboot_lead {
+                    // Logind warns users about scheduled reboots, thus active
+                    // user sessions are not checked here.
+                    actor.last_finalize_verdict = "allowed";
+                    actor.schedule_logind_reboot(&release, lead_time);
                     Box::pin(actix::fut::err(()))
                 } else {
-                    let usersessions_can_finalize = actor.state.usersessions_can_finalize();
+                    let usersessions_can_finalize = actor
+                        .state
+                        .usersessions_can_finalize(&actor.postponements, &actor.messages);
                     if !usersessions_can_finalize {
-                        update_unit_status(&format!(
-                            "update staged: {}; reboot delayed due to active user sessions",
-                            release.version
-                        ));
+                        update_unit_status(
+                            StatusSummary::new("staged")
+                                .target(&release.version)
+                                .reason("user-sessions"),
+                            &format!(
+                                "update staged: {}; reboot delayed due to active user sessions",
+                                release.version
+                            ),
+                        );
                         // Record postponement and postpone finalization.
+                        actor.last_finalize_verdict = "user-sessions";
                         actor.state.record_postponement();
+                        actor.record_history(
+                            HistoryEvent::Postponed,
+                            Some(&release),
+                            "active user sessions",
+                        );
+                        Box::pin(actix::fut::err(()))
+                    } else if let (Some(countdown), false) =
+                        (actor.reboot_countdown, countdown_elapsed)
+                    {
+                        actor.start_reboot_countdown(&release, countdown);
                         Box::pin(actix::fut::err(()))
                     } else {
+                        actor.last_finalize_verdict = "allowed";
                         actor.finalize_deployment(release)
                     }
                 }
             })
             .map(|res, actor, _ctx| {
-                res.map(|release| {
-                    update_unit_status(&format!("update finalized: {}", release.version));
-                    actor.state.update_finalized(release);
-                })
+                if res.is_err() {
+                    actor.release_reboot_lock();
+                }
+                res.map(|release| actor.record_finalized(release))
             });
 
         Box::pin(state_change)
     }
 
+    /// Schedule a reboot via logind, after the given lead time.
+    fn schedule_logind_reboot(&mut self, release: &Release, lead_time: Duration) {
+        let wall_message = self
+            .messages
+            .reboot_warning(&release.version, lead_time.as_secs());
+        let lead_time =
+            chrono::Duration::from_std(lead_time).unwrap_or_else(|_| chrono::Duration::zero());
+        let reboot_at = chrono::Utc::now() + lead_time;
+        if let Err(e) = logind::schedule_reboot(&reboot_at, &wall_message) {
+            self.record_error("failed to schedule reboot", &e);
+            return;
+        }
+
+        log::info!(
+            "reboot into '{}' scheduled via logind at {}",
+            release.version,
+            reboot_at.to_rfc3339()
+        );
+        update_unit_status(
+            StatusSummary::new("staged")
+                .target(&release.version)
+                .reason("logind-scheduled"),
+            &format!(
+                "update staged: {}; reboot scheduled at {}",
+                release.version,
+                reboot_at.format("%a %Y-%m-%d %H:%M:%S %Z")
+            ),
+        );
+        self.emit_event(AgentEvent::RebootScheduled {
+            timestamp: reboot_at.timestamp(),
+        });
+        self.pending_reboot = Some(PendingReboot::Scheduled(reboot_at));
+    }
+
+    /// Track a reboot scheduled via logind, finalizing the update right before it.
+    fn tick_logind_reboot(
+        &mut self,
+        release: Release,
+        reboot_at: DateTime<Utc>,
+    ) -> ResponseActFuture<Self, Result<(), ()>> {
+        let now = chrono::Utc::now();
+        match logind::scheduled_reboot() {
+            Ok(Some(scheduled)) if scheduled == reboot_at => {}
+            Ok(_) => {
+                log::warn!("scheduled reboot was cancelled, postponing finalization");
+                if let Err(e) = logind::reset_wall_message() {
+                    log::warn!("{:#}", e);
+                }
+                self.logind_reboot_cancelled(&release);
+                return self.nop();
+            }
+            Err(e) => {
+                log::error!("{:#}", e);
+                return self.nop();
+            }
+        }
+
+        let margin = chrono::Duration::seconds(logind::FINALIZE_MARGIN_SECS);
+        if now + margin < reboot_at {
+            trace!("waiting for reboot scheduled via logind");
+            return self.nop();
+        }
+
+        // Take over from logind, so that a failed finalization does not
+        // result in a reboot into the current deployment.
+        if let Err(e) = logind::cancel_reboot() {
+            log::warn!("{:#}", e);
+        }
+        self.pending_reboot = None;
+        if !self.acquire_reboot_lock() {
+            update_unit_status(
+                StatusSummary::new("staged")
+                    .target(&release.version)
+                    .reason("reboot-lock"),
+                &format!(
+                    "update staged: {}; reboot delayed, lock held by another reboot manager",
+                    release.version
+                ),
+            );
+            self.last_finalize_verdict = "reboot-lock";
+            return self.nop();
+        }
+        let state_change = self.finalize_deployment(release).map(|res, actor, _ctx| {
+            if res.is_err() {
+                actor.release_reboot_lock();
+            }
+            res.map(|release| actor.record_finalized(release))
+        });
+
+        Box::pin(state_change)
+    }
+
+    /// Start the countdown before finalizing the update, warning logged-in users.
+    ///
+    /// The countdown can be cancelled (e.g. over D-Bus) until it is over.
+    fn start_reboot_countdown(&mut self, release: &Release, countdown: Duration) {
+        let finalize_at = chrono::Utc::now()
+            + chrono::Duration::from_std(countdown).unwrap_or_else(|_| chrono::Duration::zero());
+        let warning = self
+            .messages
+            .reboot_warning(&release.version, countdown.as_secs());
+        super::broadcast_to_user_sessions(&warning);
+
+        log::info!(
+            "rebooting into '{}' at {}, unless cancelled",
+            release.version,
+            finalize_at.to_rfc3339()
+        );
+        update_unit_status(
+            StatusSummary::new("staged")
+                .target(&release.version)
+                .reason("countdown"),
+            &format!(
+                "update staged: {}; reboot at {} unless cancelled",
+                release.version,
+                finalize_at.format("%a %Y-%m-%d %H:%M:%S %Z")
+            ),
+        );
+        self.last_finalize_verdict = "countdown";
+        self.emit_event(AgentEvent::RebootScheduled {
+            timestamp: finalize_at.timestamp(),
+        });
+        self.pending_reboot = Some(PendingReboot::Countdown(finalize_at));
+    }
+
+    /// Back off after a countdown before reboot was cancelled, not starting
+    /// it again before another countdown length.
+    fn reboot_countdown_cancelled(&mut self, release: &Release) {
+        REBOOT_COUNTDOWNS_CANCELLED.inc();
+        let countdown = self.reboot_countdown.unwrap_or_default();
+        let retry_at = chrono::Utc::now()
+            + chrono::Duration::from_std(countdown).unwrap_or_else(|_| chrono::Duration::zero());
+        update_unit_status(
+            StatusSummary::new("staged")
+                .target(&release.version)
+                .reason("countdown-cancelled"),
+            &format!("update staged: {}; reboot cancelled", release.version),
+        );
+        self.last_finalize_verdict = "countdown-cancelled";
+        self.pending_reboot = Some(PendingReboot::Cancelled(retry_at));
+    }
+
+    /// Return the finalization verdict while backing off after a cancelled reboot.
+    fn cancelled_reboot_verdict(&self) -> &'static str {
+        if self.logind_reboot_lead.is_some() {
+            "logind-cancelled"
+        } else {
+            "countdown-cancelled"
+        }
+    }
+
+    /// Back off after a reboot scheduled via logind was cancelled, not
+    /// rescheduling it before another lead time.
+    fn logind_reboot_cancelled(&mut self, release: &Release) {
+        LOGIND_REBOOTS_CANCELLED.inc();
+        let lead_time = self.logind_reboot_lead.unwrap_or_default();
+        let retry_at = chrono::Utc::now()
+            + chrono::Duration::from_std(lead_time).unwrap_or_else(|_| chrono::Duration::zero());
+        update_unit_status(
+            StatusSummary::new("staged")
+                .target(&release.version)
+                .reason("logind-cancelled"),
+            &format!(
+                "update staged: {}; scheduled reboot cancelled",
+                release.version
+            ),
+        );
+        self.last_finalize_verdict = "logind-cancelled";
+        self.pending_reboot = Some(PendingReboot::Cancelled(retry_at));
+    }
+
+    /// Cancel the pending reboot (either scheduled via logind, or counting
+    /// down), returning whether one was pending.
+    fn cancel_pending_reboot(&mut self) -> Result<bool, Error> {
+        let release = match &self.state {
+            UpdateAgentState::UpdateStaged((release, _)) => release.clone(),
+            _ => return Ok(false),
+        };
+        match self.pending_reboot {
+            Some(PendingReboot::Scheduled(_)) => {
+                logind::cancel_reboot()?;
+                log::info!(
+                    "reboot into '{}' scheduled via logind cancelled on request",
+                    release.version
+                );
+                self.logind_reboot_cancelled(&release);
+            }
+            Some(PendingReboot::Countdown(_)) => {
+                log::info!(
+                    "countdown before reboot into '{}' cancelled on request",
+                    release.version
+                );
+                self.reboot_countdown_cancelled(&release);
+            }
+            Some(PendingReboot::Cancelled(_)) | None => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    /// Abort the pending reboot (if any) back to the staged state, e.g.
+    /// because finalization became forbidden.
+    pub(super) fn abort_pending_reboot(&mut self, cause: &str) {
+        match self.pending_reboot {
+            Some(PendingReboot::Scheduled(_)) => {
+                log::info!("cancelling reboot scheduled via logind, due to {}", cause);
+                if let Err(e) = logind::cancel_reboot() {
+                    log::warn!("{:#}", e);
+                }
+            }
+            Some(PendingReboot::Countdown(_)) => {
+                log::info!("cancelling countdown before reboot, due to {}", cause);
+            }
+            Some(PendingReboot::Cancelled(_)) | None => return,
+        }
+        self.pending_reboot = None;
+    }
+
+    /// Record a successful finalization.
+    fn record_finalized(&mut self, release: Release) {
+        if self.scheduled_finalize.is_some() {
+            if let Err(e) = self.clear_scheduled_finalize() {
+                log::error!("{:#}", e);
+            }
+        }
+        if self.staged_downgrade {
+            self.record_downgrade("finalized", &release);
+        }
+        if let Err(e) = self.consume_reboot_approval() {
+            log::error!("{:#}", e);
+        }
+        update_unit_status(
+            StatusSummary::new("finalized").target(&release.version),
+            &format!("update finalized: {}", release.version),
+        );
+        self.strategy
+            .record_finalized(&self.identity.current_os, &release);
+        if let Some(hooks) = &self.post_boot_hooks {
+            hooks.record_finalized(&self.identity, &release);
+        }
+        if let Some(reporter) = &self.outcome_report {
+            reporter.record_finalized(&self.identity, &release);
+        }
+        self.record_history(HistoryEvent::Finalized, Some(&release), "rebooting");
+        self.state.update_finalized(release);
+    }
+
     /// Actor job is done.
     fn tick_end(&mut self, release: Release) -> ResponseActFuture<Self, Result<(), ()>> {
         let status = format!("update applied, waiting for reboot: {}", release.version);
         log::info!("{}", status);
         let state_change = self.nop().map(move |_r, actor, _ctx| {
             actor.state.end();
-            update_unit_status(&status);
+            update_unit_status(
+                StatusSummary::new("end")
+                    .target(&release.version)
+                    .reason("reboot-pending"),
+                &status,
+            );
             Ok(())
         });
 
         Box::pin(state_change)
     }
 
-    /// Fetch and stage an update, in finalization-locked mode.
-    fn attempt_deploy(&mut self, release: Release) -> ResponseActFuture<Self, Result<Release, ()>> {
+    /// Run `fetch` once the target release is known to be available on the
+    /// OSTree remote to check, if any, and its signature verified (if enabled).
+    ///
+    /// Otherwise, fetching is retried on the next tick, without counting
+    /// as a failed deployment attempt. A failed signature verification fails
+    /// closed instead, counting as a failed deployment attempt.
+    fn when_on_remote<F>(
+        &mut self,
+        release: Release,
+        fetch: F,
+    ) -> ResponseActFuture<Self, Result<(), ()>>
+    where
+        F: FnOnce(&mut Self, Release) -> ResponseActFuture<Self, Result<(), ()>> + 'static,
+    {
+        let remote = match &self.verify_remote {
+            Some(remote) => remote.clone(),
+            None => return fetch(self, release),
+        };
+
+        // Outer errors mean that the commit is not (yet) available, inner
+        // ones that its signature failed verification.
+        let target = release.clone();
+        let verify_signature = self.verify_signature;
+        let check = tokio::task::spawn_blocking(move || -> Result<Result<(), Error>, Error> {
+            ostree_remote::check_release_on_remote(&remote, &target)?;
+            if !verify_signature {
+                return Ok(Ok(()));
+            }
+            Ok(ostree_remote::verify_release_signature(&remote, &target))
+        })
+        .map(|res| {
+            res.context("failed to join remote commit query")
+                .and_then(|available| available)
+        });
+        let state_change = check
+            .into_actor(self)
+            .then(move |res, actor, _ctx| match res {
+                Ok(Ok(())) => fetch(actor, release),
+                Ok(Err(e)) => {
+                    TARGET_VERIFICATION_FAILURES.inc();
+                    actor.record_error("failed to verify target release", &e);
+                    let release_ver = release.version.clone();
+                    actor.deploy_attempt_failed(release);
+                    update_unit_status(
+                        StatusSummary::new("available")
+                            .target(&release_ver)
+                            .reason("verification-failed"),
+                        &format!(
+                            "update available: {}; signature verification failed",
+                            release_ver
+                        ),
+                    );
+                    actor.nop()
+                }
+                Err(e) => {
+                    TARGET_NOT_ON_REMOTE.inc();
+                    log::info!("{:#}", e);
+                    update_unit_status(
+                        StatusSummary::new("available")
+                            .target(&release.version)
+                            .reason("not-on-remote"),
+                        &format!(
+                            "update available: {}; not yet available on remote, retrying later",
+                            release.version
+                        ),
+                    );
+                    actor.nop()
+                }
+            });
+
+        Box::pin(state_change)
+    }
+
+    /// Fetch an update, without staging it.
+    fn attempt_download(
+        &mut self,
+        release: Release,
+    ) -> ResponseActFuture<Self, Result<Release, ()>> {
+        log::info!(
+            "target release '{}' selected, proceeding to download it",
+            release.version
+        );
+        update_unit_status(
+            StatusSummary::new("downloading").target(&release.version),
+            &format!("downloading update: {}", release.version),
+        );
+        let msg = rpm_ostree::DownloadDeployment {
+            release,
+            allow_downgrade: self.allow_downgrade,
+        };
+        let download = self
+            .rpm_ostree_actor
+            .send(msg)
+            .unwrap_or_else(|e| Err(e.into()))
+            .into_actor(self)
+            .map(|res, actor, _ctx| {
+                res.map_err(|e| actor.record_error("failed to download deployment", &e))
+            });
+
+        Box::pin(download)
+    }
+
+    /// Fetch (unless `cache_only`) and stage an update, in finalization-locked mode.
+    fn attempt_deploy(
+        &mut self,
+        release: Release,
+        cache_only: bool,
+    ) -> ResponseActFuture<Self, Result<Release, ()>> {
         log::info!(
             "target release '{}' selected, proceeding to stage it",
             release.version
         );
+        update_unit_status(
+            StatusSummary::new("staging").target(&release.version),
+            &format!("staging update: {}", release.version),
+        );
         let msg = rpm_ostree::StageDeployment {
             release,
             allow_downgrade: self.allow_downgrade,
+            cache_only,
         };
         let upgrade = self
             .rpm_ostree_actor
             .send(msg)
             .unwrap_or_else(|e| Err(e.into()))
-            .map_err(|e| log::error!("failed to stage deployment: {}", e))
-            .into_actor(self);
+            .into_actor(self)
+            .then(|res, actor, _ctx| actor.check_staged_commit_age(res))
+            .map(|res, actor, _ctx| {
+                res.map_err(|e| actor.record_error("failed to stage deployment", &e))
+            });
 
         Box::pin(upgrade)
     }
 
+    /// Check a freshly staged update against the downgrade barrier, if any.
+    ///
+    /// The staged deployment is left finalization-locked on failure, thus it
+    /// is never applied.
+    fn check_staged_commit_age(
+        &mut self,
+        staged: Result<Release, Error>,
+    ) -> ResponseActFuture<Self, Result<Release, Error>> {
+        let barrier = match &self.downgrade_barrier {
+            Some(b) if self.allow_downgrade && b.checks_commit_age() && staged.is_ok() => b.clone(),
+            _ => return Box::pin(actix::fut::ready(staged)),
+        };
+        let msg = rpm_ostree::QueryStagedCommitAge {};
+        let check = self
+            .rpm_ostree_actor
+            .send(msg)
+            .unwrap_or_else(|e| Err(e.into()))
+            .into_actor(self)
+            .map(move |res, _actor, _ctx| {
+                match res.context("failed to query staged commit age")? {
+                    Some(age_secs) => barrier
+                        .check_commit_age(age_secs)
+                        .context("staged deployment rejected by downgrade barrier")?,
+                    None => anyhow::bail!("no staged deployment found"),
+                };
+                staged
+            });
+        Box::pin(check)
+    }
+
+    /// Check whether a freshly staged update is a downgrade, and record it.
+    fn check_staged_downgrade(&mut self, release: Release) -> impl ActorFuture<Self, Output = ()> {
+        let msg = rpm_ostree::QueryStagedDowngrade {};
+        self.rpm_ostree_actor
+            .send(msg)
+            .unwrap_or_else(|e| Err(e.into()))
+            .into_actor(self)
+            .map(move |res, actor, _ctx| match res {
+                Ok(true) => actor.record_downgrade("staged", &release),
+                Ok(false) => {}
+                Err(e) => log::warn!("failed to check staged deployment for downgrade: {:#}", e),
+            })
+    }
+
+    /// Report CVEs fixed by a freshly staged update, if its commit metadata lists any.
+    fn report_security_fixes(&mut self, release: Release) -> impl ActorFuture<Self, Output = ()> {
+        let msg = rpm_ostree::QueryStagedSecurityFixes {};
+        self.rpm_ostree_actor
+            .send(msg)
+            .unwrap_or_else(|e| Err(e.into()))
+            .into_actor(self)
+            .map(move |res, actor, _ctx| {
+                let fixes = match res {
+                    Ok(Some(fixes)) if !fixes.cves.is_empty() => fixes,
+                    Ok(_) => return,
+                    Err(e) => {
+                        log::warn!(
+                            "failed to query staged deployment for security fixes: {:#}",
+                            e
+                        );
+                        return;
+                    }
+                };
+                let count = fixes.cves.len();
+                let plural = if count > 1 { "s" } else { "" };
+                let msg = format!(
+                    "update staged: {}; fixes {} CVE{}",
+                    release.version, count, plural
+                );
+                update_unit_status(StatusSummary::new("staged").target(&release.version), &msg);
+                log::info!(
+                    "update {} fixes {} CVE{} (advisories: {}): {}",
+                    release.version,
+                    count,
+                    plural,
+                    fixes.advisories.join(", "),
+                    fixes.cves.join(", ")
+                );
+                actor.staged_fixed_cves = fixes.cves;
+            })
+    }
+
     /// Record a failed deploy attempt and return the total number of
     /// failed deployment attempts.
     fn deploy_attempt_failed(&mut self, release: Release) -> u8 {
@@ -414,6 +2334,26 @@ impl UpdateAgent {
         fail_count
     }
 
+    /// Query the staged deployment, if any.
+    ///
+    /// Failures are logged and treated as no deployment being staged.
+    fn staged_deployment(&mut self) -> ResponseActFuture<Self, Option<Release>> {
+        let msg = rpm_ostree::QueryStagedDeployment {};
+        let staged = self
+            .rpm_ostree_actor
+            .send(msg)
+            .unwrap_or_else(|e| Err(e.into()))
+            .map(|res| {
+                res.unwrap_or_else(|e| {
+                    log::warn!("failed to query staged deployment: {:#}", e);
+                    None
+                })
+            })
+            .into_actor(self);
+
+        Box::pin(staged)
+    }
+
     /// List persistent (i.e. finalized) local deployments.
     ///
     /// This ignores deployments that have been only staged but not finalized in the
@@ -444,13 +2384,52 @@ impl UpdateAgent {
             release.version
         );
 
-        let msg = rpm_ostree::FinalizeDeployment { release };
-        let upgrade = self
-            .rpm_ostree_actor
-            .send(msg)
-            .unwrap_or_else(|e| Err(e.into()))
-            .map_err(|e| log::error!("failed to finalize deployment: {}", e))
-            .into_actor(self);
+        let allow_unlocked = self.adopted_staged.as_ref() == Some(&release.checksum);
+        // Configured services are stopped right before finalization, and
+        // started again if it does not go through.
+        let quiesce = self.quiesce.clone();
+        let quiesced = match &quiesce {
+            Some(quiesce) => quiesce.stop(),
+            None => Box::pin(futures::future::ready(Ok(()))),
+        };
+        let upgrade =
+            actix::fut::wrap_future::<_, Self>(quiesced).then(move |quiesced, actor, _ctx| {
+                if let Err(reason) = quiesced {
+                    update_unit_status(
+                        StatusSummary::new("staged")
+                            .target(&release.version)
+                            .reason("quiesce"),
+                        &format!(
+                            "update staged: {}; reboot delayed, {}",
+                            release.version, reason
+                        ),
+                    );
+                    actor.last_finalize_verdict = "quiesce";
+                    let delayed: ResponseActFuture<Self, Result<Release, ()>> =
+                        Box::pin(actix::fut::err(()));
+                    return delayed;
+                }
+
+                actor.finalize_attempted = true;
+                let msg = rpm_ostree::FinalizeDeployment {
+                    allow_unlocked,
+                    release,
+                };
+                let finalize = actor
+                    .rpm_ostree_actor
+                    .send(msg)
+                    .unwrap_or_else(|e| Err(e.into()))
+                    .into_actor(actor)
+                    .map(move |res, actor, ctx| {
+                        res.map_err(|e| {
+                            actor.record_error("failed to finalize deployment", &e);
+                            if let Some(quiesce) = quiesce {
+                                ctx.spawn(actix::fut::wrap_future::<_, Self>(quiesce.restart()));
+                            }
+                        })
+                    });
+                Box::pin(finalize)
+            });
 
         Box::pin(upgrade)
     }
@@ -477,24 +2456,6 @@ impl UpdateAgent {
     }
 }
 
-/// Helper function to send notification to the service manager about service status changes.
-/// Log errors if unsuccessful.
-fn update_unit_status(status: &str) {
-    match notify(false, &[NotifyState::Status(status.to_string())]) {
-        Err(e) => log::error!(
-            "failed to notify service manager about service status change: {}",
-            e
-        ),
-        Ok(sent) => {
-            if !sent {
-                log::error!(
-                    "update_unit_status: status notifications not supported for this service"
-                );
-            }
-        }
-    }
-}
-
 /// Helper function to tell the service manager that Zincati start up is finished and
 /// configuration is loaded.
 fn notify_ready() {
@@ -515,15 +2476,49 @@ fn notify_ready() {
 mod tests {
     use super::*;
 
+    /// Run `f` on an update agent built from mock settings, without starting it.
+    #[cfg(feature = "e2e-tests")]
+    fn with_mock_agent<T>(
+        settings: Settings,
+        f: impl FnOnce(&mut UpdateAgent, &mut Context<UpdateAgent>) -> T,
+    ) -> T {
+        actix::System::new().block_on(async move {
+            let client = rpm_ostree::RpmOstreeClient::new(
+                settings.rpm_ostree_backend,
+                settings.rpm_ostree_timeouts,
+            );
+            let addr = rpm_ostree::OperationQueue::start(client, settings.rpm_ostree_max_queued);
+            f(
+                &mut UpdateAgent::with_config(settings, addr),
+                &mut Context::with_receiver(actix::dev::channel::channel(16).1),
+            )
+        })
+    }
+
+    /// Dummy `Release`, as the update target.
+    #[cfg(feature = "e2e-tests")]
+    fn mock_release() -> Release {
+        Release {
+            version: "v1".to_string(),
+            checksum: "ostree-checksum".to_string(),
+            age_index: None,
+            advisory: None,
+            wave: None,
+        }
+    }
+
     #[test]
     fn test_should_tick_immediately() {
-        use crate::update_agent::MAX_FINALIZE_POSTPONEMENTS;
+        use crate::update_agent::PostponementBudget;
+        let max_postponements = PostponementBudget::default().max;
 
         // Dummy `Release`.
         let update = Release {
             version: "v1".to_string(),
             checksum: "ostree-checksum".to_string(),
             age_index: None,
+            advisory: None,
+            wave: None,
         };
 
         // Transition between states with different discriminants.
@@ -546,6 +2541,19 @@ mod tests {
             &prev_state,
             &cur_state
         ));
+        // Nor when starting to wait for a rollout wave, but well when it opens.
+        let prev_state = UpdateAgentState::NoNewUpdate;
+        let cur_state = UpdateAgentState::WaitingForWave(update.clone());
+        assert!(!UpdateAgent::should_tick_immediately(
+            &prev_state,
+            &cur_state
+        ));
+        let prev_state = UpdateAgentState::WaitingForWave(update.clone());
+        let cur_state = UpdateAgentState::UpdateAvailable((update.clone(), 0));
+        assert!(UpdateAgent::should_tick_immediately(
+            &prev_state,
+            &cur_state
+        ));
 
         // Transition between states with same discriminants.
         let prev_state = UpdateAgentState::NoNewUpdate;
@@ -560,15 +2568,187 @@ mod tests {
             &prev_state,
             &cur_state
         ));
-        let prev_state =
-            UpdateAgentState::UpdateStaged((update.clone(), MAX_FINALIZE_POSTPONEMENTS));
-        let cur_state = UpdateAgentState::UpdateStaged((
-            update.clone(),
-            MAX_FINALIZE_POSTPONEMENTS.saturating_sub(1),
-        ));
+        let prev_state = UpdateAgentState::UpdateStaged((update.clone(), max_postponements));
+        let cur_state =
+            UpdateAgentState::UpdateStaged((update.clone(), max_postponements.saturating_sub(1)));
         assert!(!UpdateAgent::should_tick_immediately(
             &prev_state,
             &cur_state
         ));
     }
+
+    #[cfg(feature = "e2e-tests")]
+    #[test]
+    fn test_abort_pending_reboot() {
+        with_mock_agent(Settings::mock_default(), |agent, _ctx| {
+            let release = mock_release();
+            agent.state.update_staged(release, agent.postponements.max);
+
+            // Nothing pending, nothing to abort.
+            agent.abort_pending_reboot("test");
+            assert_eq!(agent.pending_reboot, None);
+            assert!(!agent.cancel_pending_reboot().unwrap());
+
+            // A countdown is aborted back to plain `UpdateStaged`.
+            let finalize_at = Utc::now() + chrono::Duration::hours(1);
+            agent.pending_reboot = Some(PendingReboot::Countdown(finalize_at));
+            agent.abort_pending_reboot("test");
+            assert_eq!(agent.pending_reboot, None);
+
+            // A cancelled reboot keeps backing off.
+            let retry_at = Utc::now() + chrono::Duration::hours(1);
+            agent.pending_reboot = Some(PendingReboot::Cancelled(retry_at));
+            agent.abort_pending_reboot("test");
+            assert_eq!(
+                agent.pending_reboot,
+                Some(PendingReboot::Cancelled(retry_at))
+            );
+            assert!(!agent.cancel_pending_reboot().unwrap());
+        });
+    }
+
+    #[cfg(feature = "e2e-tests")]
+    #[test]
+    fn test_cancel_pending_reboot() {
+        let settings = Settings {
+            reboot_countdown: Some(Duration::from_secs(600)),
+            ..Settings::mock_default()
+        };
+        with_mock_agent(settings, |agent, _ctx| {
+            let release = mock_release();
+
+            // Only a staged update has a reboot to cancel.
+            agent.pending_reboot = Some(PendingReboot::Countdown(Utc::now()));
+            assert!(!agent.cancel_pending_reboot().unwrap());
+
+            agent
+                .state
+                .update_staged(release.clone(), agent.postponements.max);
+            agent.start_reboot_countdown(&release, Duration::from_secs(600));
+            assert_eq!(agent.last_finalize_verdict, "countdown");
+            assert!(matches!(
+                agent.pending_reboot,
+                Some(PendingReboot::Countdown(_))
+            ));
+
+            // Cancelling backs off for another countdown length.
+            assert!(agent.cancel_pending_reboot().unwrap());
+            assert_eq!(agent.last_finalize_verdict, "countdown-cancelled");
+            let retry_at = match agent.pending_reboot {
+                Some(PendingReboot::Cancelled(at)) => at,
+                ref other => panic!("unexpected pending reboot: {:?}", other),
+            };
+            assert!(retry_at > Utc::now() + chrono::Duration::seconds(590));
+            assert!(!agent.cancel_pending_reboot().unwrap());
+        });
+    }
+
+    #[cfg(feature = "e2e-tests")]
+    #[test]
+    fn test_steady_report_degraded() {
+        // Without a threshold, failures never escalate.
+        with_mock_agent(Settings::mock_default(), |agent, _ctx| {
+            agent.state.initialized();
+            for _ in 0..10 {
+                agent.steady_report_failed();
+            }
+            assert!(!agent.strategy_degraded);
+            assert_eq!(agent.state, UpdateAgentState::Initialized);
+        });
+
+        let settings = Settings {
+            steady_report_max_failures: std::num::NonZeroU32::new(3),
+            ..Settings::mock_default()
+        };
+        with_mock_agent(settings, |agent, ctx| {
+            agent.state.initialized();
+            agent.steady_report_failed();
+            agent.steady_report_failed();
+            assert!(!agent.strategy_degraded);
+            assert_eq!(agent.state, UpdateAgentState::Initialized);
+
+            // Escalate to polling for updates once the threshold is reached.
+            agent.steady_report_failed();
+            assert!(agent.strategy_degraded);
+            assert_eq!(agent.state, UpdateAgentState::ReportedSteady);
+            assert_eq!(agent.steady_report_failures, 3);
+
+            // Further failures while degraded do not change state.
+            agent.steady_report_failed();
+            assert!(agent.strategy_degraded);
+            assert_eq!(agent.state, UpdateAgentState::ReportedSteady);
+
+            // A successful report returns to normal.
+            agent.steady_state_reached(ctx);
+            assert!(!agent.strategy_degraded);
+            assert_eq!(agent.steady_report_failures, 0);
+            assert_eq!(agent.state, UpdateAgentState::ReportedSteady);
+        });
+    }
+
+    #[cfg(feature = "e2e-tests")]
+    #[test]
+    fn test_first_check_splay() {
+        // Without splay, the first check is never delayed.
+        with_mock_agent(Settings::mock_default(), |agent, _ctx| {
+            assert_eq!(agent.first_check_at, None);
+            agent.state.initialized();
+            agent.state.reported_steady();
+            assert!(!agent.first_check_delayed());
+            let (_, reason) = agent
+                .refresh_delay(UpdateAgentState::ReportedSteady)
+                .unwrap();
+            assert_ne!(reason, "first check splay");
+        });
+
+        let splay = Duration::from_secs(600);
+        for _ in 0..20 {
+            let settings = Settings {
+                first_check_splay: Some(splay),
+                ..Settings::mock_default()
+            };
+            let before = Utc::now();
+            with_mock_agent(settings, |agent, _ctx| {
+                let first_check_at = agent.first_check_at.unwrap();
+                assert!(first_check_at >= before);
+                assert!(first_check_at <= Utc::now() + chrono::Duration::seconds(600));
+                if let Some(remaining) = agent.first_check_remaining() {
+                    assert!(remaining <= splay);
+                    agent.state.initialized();
+                    agent.state.reported_steady();
+                    let (delay, reason) = agent
+                        .refresh_delay(UpdateAgentState::ReportedSteady)
+                        .unwrap();
+                    assert!(delay <= remaining);
+                    assert_eq!(reason, "first check splay");
+                }
+            });
+        }
+    }
+
+    #[cfg(feature = "e2e-tests")]
+    #[test]
+    fn test_reload_settings() {
+        with_mock_agent(Settings::mock_default(), |agent, _ctx| {
+            let countdown = Some(Duration::from_secs(300));
+            let reloaded = Settings {
+                fetch_only_window: true,
+                reboot_countdown: countdown,
+                verify_remote: Some("mock-remote".to_string()),
+                ..Settings::mock_default()
+            };
+            agent.reload_settings(Ok(reloaded)).unwrap();
+            assert!(agent.fetch_only_window);
+            assert_eq!(agent.reboot_countdown, countdown);
+            assert_eq!(agent.verify_remote.as_deref(), Some("mock-remote"));
+
+            // Invalid configuration is rejected, keeping current settings.
+            let mut cfg = crate::config::inputs::ConfigInput::merge_fragments(vec![]);
+            cfg.updates.reboot_lock_path = "relative/lock".to_string();
+            agent.reload_settings(Settings::validate(cfg)).unwrap_err();
+            assert!(agent.fetch_only_window);
+            assert_eq!(agent.reboot_countdown, countdown);
+            assert_eq!(agent.verify_remote.as_deref(), Some("mock-remote"));
+        });
+    }
 }
@@ -0,0 +1,111 @@
+//! Reboot approvals.
+//!
+//! In approval mode, a staged update is only finalized once an administrator
+//! explicitly approves the reboot. The approval is bound to the staged
+//! release, and persisted to disk until consumed by the finalization.
+
+use crate::rpm_ostree::Release;
+use crate::utils;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use fn_error_context::context;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// Absolute path to the persisted reboot approval.
+pub(crate) static REBOOT_APPROVAL_PATH: &str = "/var/lib/zincati/reboot-approval.json";
+
+/// An approval to reboot into a specific release.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub(crate) struct RebootApproval {
+    /// Approved release version.
+    pub(crate) version: String,
+    /// Approved release checksum.
+    pub(crate) checksum: String,
+    /// Point in time at which the reboot was approved.
+    pub(crate) approved_at: DateTime<Utc>,
+}
+
+impl RebootApproval {
+    /// Build a new approval for the given release.
+    pub(crate) fn new(release: &Release, now: DateTime<Utc>) -> Self {
+        Self {
+            version: release.version.clone(),
+            checksum: release.checksum.clone(),
+            approved_at: now,
+        }
+    }
+
+    /// Return whether this approval covers the given release.
+    pub(crate) fn covers(&self, release: &Release) -> bool {
+        self.checksum == release.checksum
+    }
+
+    /// Load a persisted approval from `path`, if any.
+    #[context("failed to load reboot approval")]
+    pub(crate) fn load(path: impl AsRef<Path>) -> Result<Option<Self>> {
+        let path = path.as_ref();
+        let content = match std::fs::read(path) {
+            Ok(c) => c,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => {
+                return Err(e).with_context(|| format!("failed to read '{}'", path.display()))
+            }
+        };
+        let approval = serde_json::from_slice(&content)
+            .with_context(|| format!("failed to parse '{}'", path.display()))?;
+
+        Ok(Some(approval))
+    }
+
+    /// Persist this approval to `path`.
+    #[context("failed to persist reboot approval")]
+    pub(crate) fn persist(&self, path: impl AsRef<Path>) -> Result<()> {
+        let content = serde_json::to_vec(self)?;
+        utils::atomic_write(path, 0o644, &content)
+    }
+
+    /// Remove the persisted approval at `path`, if any.
+    #[context("failed to remove reboot approval")]
+    pub(crate) fn remove(path: impl AsRef<Path>) -> Result<()> {
+        utils::remove_if_exists(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_approval_roundtrip() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let path = tmpdir.path().join("reboot-approval.json");
+        let release = Release {
+            version: "v1".to_string(),
+            checksum: "ostree-checksum".to_string(),
+            age_index: None,
+            advisory: None,
+            wave: None,
+        };
+        let other = Release {
+            version: "v2".to_string(),
+            checksum: "other-checksum".to_string(),
+            age_index: None,
+            advisory: None,
+            wave: None,
+        };
+
+        assert_eq!(RebootApproval::load(&path).unwrap(), None);
+
+        let approval = RebootApproval::new(&release, Utc.timestamp_opt(1_600_000_000, 0).unwrap());
+        assert!(approval.covers(&release));
+        assert!(!approval.covers(&other));
+
+        approval.persist(&path).unwrap();
+        assert_eq!(RebootApproval::load(&path).unwrap(), Some(approval));
+
+        RebootApproval::remove(&path).unwrap();
+        assert_eq!(RebootApproval::load(&path).unwrap(), None);
+    }
+}
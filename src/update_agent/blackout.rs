@@ -0,0 +1,166 @@
+//! Temporary blackout windows.
+//!
+//! An administrator (e.g. an on-call engineer during an incident) can forbid
+//! reboots for a period of time, without touching configuration files.
+//! While a blackout is active, staged updates are not finalized, regardless
+//! of update strategy and one-time scheduled finalizations. The blackout is
+//! persisted to disk until it expires, so that it survives agent restarts.
+
+use crate::utils;
+use anyhow::{Context, Result};
+use chrono::{DateTime, TimeZone, Utc};
+use fn_error_context::context;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// Absolute path to the persisted temporary blackout.
+pub(crate) static TEMPORARY_BLACKOUT_PATH: &str = "/var/lib/zincati/temporary-blackout.json";
+
+/// A one-off window during which reboots are not allowed.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub(crate) struct TemporaryBlackout {
+    /// Start of the blackout.
+    pub(crate) start: DateTime<Utc>,
+    /// End of the blackout.
+    pub(crate) end: DateTime<Utc>,
+    /// Free-form reason, for humans.
+    pub(crate) reason: String,
+}
+
+impl TemporaryBlackout {
+    /// Build a new blackout from UTC timestamps (seconds since epoch).
+    ///
+    /// Empty windows and windows already over are rejected.
+    pub(crate) fn from_timestamps(
+        start: i64,
+        end: i64,
+        reason: &str,
+        now: &DateTime<Utc>,
+    ) -> Result<Self> {
+        let start = parse_timestamp(start)?;
+        let end = parse_timestamp(end)?;
+        if end <= start {
+            anyhow::bail!("blackout end must be later than its start");
+        }
+        if end <= *now {
+            anyhow::bail!("blackout end {} is in the past", format_time(&end));
+        }
+
+        let blackout = Self {
+            start,
+            end,
+            reason: reason.trim().to_string(),
+        };
+        Ok(blackout)
+    }
+
+    /// Return whether reboots are forbidden at `now`.
+    pub(crate) fn is_active(&self, now: &DateTime<Utc>) -> bool {
+        self.start <= *now && *now < self.end
+    }
+
+    /// Return whether the blackout is over at `now`.
+    pub(crate) fn is_expired(&self, now: &DateTime<Utc>) -> bool {
+        self.end <= *now
+    }
+
+    /// Return a human-readable description of this blackout.
+    pub(crate) fn describe(&self) -> String {
+        let mut desc = format!("temporary blackout until {}", format_time(&self.end));
+        if !self.reason.is_empty() {
+            desc.push_str(&format!(" ({})", self.reason));
+        }
+        desc
+    }
+
+    /// Load a persisted blackout from `path`, if any.
+    #[context("failed to load temporary blackout")]
+    pub(crate) fn load(path: impl AsRef<Path>) -> Result<Option<Self>> {
+        let path = path.as_ref();
+        let content = match std::fs::read(path) {
+            Ok(c) => c,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => {
+                return Err(e).with_context(|| format!("failed to read '{}'", path.display()))
+            }
+        };
+        let blackout = serde_json::from_slice(&content)
+            .with_context(|| format!("failed to parse '{}'", path.display()))?;
+
+        Ok(Some(blackout))
+    }
+
+    /// Persist this blackout to `path`.
+    #[context("failed to persist temporary blackout")]
+    pub(crate) fn persist(&self, path: impl AsRef<Path>) -> Result<()> {
+        let content = serde_json::to_vec(self)?;
+        utils::atomic_write(path, 0o644, &content)
+    }
+
+    /// Remove the persisted blackout at `path`, if any.
+    #[context("failed to remove temporary blackout")]
+    pub(crate) fn remove(path: impl AsRef<Path>) -> Result<()> {
+        utils::remove_if_exists(path)
+    }
+}
+
+/// Parse a UTC timestamp (seconds since epoch).
+fn parse_timestamp(timestamp: i64) -> Result<DateTime<Utc>> {
+    match Utc.timestamp_opt(timestamp, 0).single() {
+        Some(dt) => Ok(dt),
+        None => anyhow::bail!("invalid timestamp {}", timestamp),
+    }
+}
+
+/// Format a point in time, in human terms.
+fn format_time(dt: &DateTime<Utc>) -> String {
+    dt.format("%a %Y-%m-%d %H:%M:%S %Z").to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_timestamps() {
+        let now = Utc.timestamp_opt(1_600_000_000, 0).unwrap();
+
+        TemporaryBlackout::from_timestamps(1_600_000_060, 1_600_000_060, "", &now).unwrap_err();
+        TemporaryBlackout::from_timestamps(1_600_000_060, 1_600_000_000, "", &now).unwrap_err();
+        TemporaryBlackout::from_timestamps(1_500_000_000, 1_600_000_000, "", &now).unwrap_err();
+
+        let blackout =
+            TemporaryBlackout::from_timestamps(1_599_999_000, 1_600_043_200, " incident ", &now)
+                .unwrap();
+        assert_eq!(blackout.reason, "incident");
+        assert!(blackout.is_active(&now));
+        assert!(!blackout.is_expired(&now));
+        assert!(blackout.describe().ends_with("(incident)"));
+
+        let before = Utc.timestamp_opt(1_599_998_000, 0).unwrap();
+        assert!(!blackout.is_active(&before));
+        assert!(!blackout.is_expired(&before));
+
+        let after = Utc.timestamp_opt(1_600_043_200, 0).unwrap();
+        assert!(!blackout.is_active(&after));
+        assert!(blackout.is_expired(&after));
+    }
+
+    #[test]
+    fn test_persist_roundtrip() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let path = tmpdir.path().join("temporary-blackout.json");
+
+        assert_eq!(TemporaryBlackout::load(&path).unwrap(), None);
+
+        let now = Utc.timestamp_opt(1_600_000_000, 0).unwrap();
+        let blackout =
+            TemporaryBlackout::from_timestamps(1_600_000_000, 1_600_003_600, "freeze", &now)
+                .unwrap();
+        blackout.persist(&path).unwrap();
+        assert_eq!(TemporaryBlackout::load(&path).unwrap(), Some(blackout));
+
+        TemporaryBlackout::remove(&path).unwrap();
+        assert_eq!(TemporaryBlackout::load(&path).unwrap(), None);
+    }
+}
@@ -0,0 +1,182 @@
+//! Checks on the boot filesystem before finalization.
+//!
+//! Finalizing an update writes a new kernel, initramfs and bootloader entry
+//! to `/boot`. Failures there (e.g. on a full `/boot`) only show up at
+//! shutdown, as a raw error from the finalization service. These checks
+//! catch such cases ahead of time, so that finalization can be postponed
+//! with a precise reason and a remediation hint.
+
+pub(crate) use crate::disk_space::BOOT_PATH;
+use crate::disk_space::{free_bytes, to_mib};
+use anyhow::{Context, Result};
+use fn_error_context::context;
+use std::path::Path;
+
+/// Directory with bootloader (BLS) entries, relative to the boot filesystem.
+static ENTRIES_DIR: &str = "loader/entries";
+
+/// Directory with kernels and initramfs images, relative to the boot filesystem.
+static OSTREE_DIR: &str = "ostree";
+
+/// Maximum number of bootloader entries before refusing to add a new one.
+const MAX_BOOT_ENTRIES: usize = 32;
+
+/// Additional free space required for bootloader entries and configuration (in bytes).
+const SPACE_MARGIN_BYTES: u64 = 1024 * 1024;
+
+/// Usage of the boot filesystem.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct BootUsage {
+    /// Available space, in bytes.
+    free_bytes: u64,
+    /// Estimated space needed for a new kernel and initramfs, in bytes.
+    required_bytes: u64,
+    /// Number of bootloader entries.
+    entries: usize,
+}
+
+impl BootUsage {
+    /// Inspect the boot filesystem mounted at `root`.
+    #[context("failed to inspect boot filesystem")]
+    fn inspect(root: &Path) -> Result<Self> {
+        let usage = Self {
+            free_bytes: free_bytes(root)?,
+            required_bytes: largest_subdir_size(&root.join(OSTREE_DIR))?
+                .saturating_add(SPACE_MARGIN_BYTES),
+            entries: count_entries(&root.join(ENTRIES_DIR))?,
+        };
+        Ok(usage)
+    }
+
+    /// Check whether a new deployment fits, returning the reason if not.
+    fn check(&self) -> Result<(), String> {
+        if self.free_bytes < self.required_bytes {
+            return Err(format!(
+                "not enough space on /boot ({} MiB free, {} MiB needed); \
+                 free up space, e.g. via `rpm-ostree cleanup -r`",
+                to_mib(self.free_bytes),
+                to_mib(self.required_bytes)
+            ));
+        }
+        if self.entries >= MAX_BOOT_ENTRIES {
+            return Err(format!(
+                "too many bootloader entries on /boot ({}, limit {}); \
+                 remove stale deployments, e.g. via `rpm-ostree cleanup -r`",
+                self.entries, MAX_BOOT_ENTRIES
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Check the boot filesystem at `root` before finalization.
+///
+/// On failure, this returns the reason for postponing finalization.
+/// Errors while inspecting the filesystem are logged, but do not block
+/// finalization.
+pub(crate) fn check(root: &Path) -> Result<(), String> {
+    match BootUsage::inspect(root) {
+        Ok(usage) => usage.check(),
+        Err(e) => {
+            log::warn!("skipping boot filesystem checks: {:#}", e);
+            Ok(())
+        }
+    }
+}
+
+/// Return the size of the largest directory directly under `dir`, in bytes.
+fn largest_subdir_size(dir: &Path) -> Result<u64> {
+    let mut largest = 0;
+    for entry in std::fs::read_dir(dir)
+        .with_context(|| format!("failed to read directory '{}'", dir.display()))?
+    {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            largest = largest.max(dir_size(&entry.path())?);
+        }
+    }
+    Ok(largest)
+}
+
+/// Return the total size of files under `dir`, in bytes.
+fn dir_size(dir: &Path) -> Result<u64> {
+    let mut total: u64 = 0;
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            total = total.saturating_add(dir_size(&entry.path())?);
+        } else if file_type.is_file() {
+            total = total.saturating_add(entry.metadata()?.len());
+        }
+    }
+    Ok(total)
+}
+
+/// Return the number of bootloader entries in `dir`.
+fn count_entries(dir: &Path) -> Result<usize> {
+    let mut count = 0;
+    for entry in std::fs::read_dir(dir)
+        .with_context(|| format!("failed to read directory '{}'", dir.display()))?
+    {
+        if entry?.path().extension().is_some_and(|ext| ext == "conf") {
+            count += 1;
+        }
+    }
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_boot_usage_check() {
+        let usage = BootUsage {
+            free_bytes: 200 * 1024 * 1024,
+            required_bytes: 100 * 1024 * 1024,
+            entries: 2,
+        };
+        usage.check().unwrap();
+
+        let full = BootUsage {
+            free_bytes: 50 * 1024 * 1024,
+            ..usage.clone()
+        };
+        let reason = full.check().unwrap_err();
+        assert!(reason.contains("50 MiB free, 100 MiB needed"), "{}", reason);
+
+        let crowded = BootUsage {
+            entries: MAX_BOOT_ENTRIES,
+            ..usage
+        };
+        let reason = crowded.check().unwrap_err();
+        assert!(reason.contains("too many bootloader entries"), "{}", reason);
+    }
+
+    #[test]
+    fn test_boot_usage_inspect() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let root = tmpdir.path();
+        let entries = root.join(ENTRIES_DIR);
+        fs::create_dir_all(&entries).unwrap();
+        fs::write(entries.join("ostree-1-fedora-coreos.conf"), "title 1").unwrap();
+        fs::write(entries.join("ostree-2-fedora-coreos.conf"), "title 2").unwrap();
+        fs::write(entries.join("README"), "not an entry").unwrap();
+        let old = root.join(OSTREE_DIR).join("fedora-coreos-aaaa");
+        let new = root.join(OSTREE_DIR).join("fedora-coreos-bbbb");
+        fs::create_dir_all(&old).unwrap();
+        fs::create_dir_all(&new).unwrap();
+        fs::write(old.join("vmlinuz"), vec![0u8; 10]).unwrap();
+        fs::write(new.join("vmlinuz"), vec![0u8; 20]).unwrap();
+        fs::write(new.join("initramfs.img"), vec![0u8; 30]).unwrap();
+
+        let usage = BootUsage::inspect(root).unwrap();
+        assert_eq!(usage.entries, 2);
+        assert_eq!(usage.required_bytes, 50 + SPACE_MARGIN_BYTES);
+
+        // Inspection failures do not block finalization.
+        check(&root.join("missing")).unwrap();
+    }
+}
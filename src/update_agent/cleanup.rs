@@ -0,0 +1,150 @@
+//! Cleanup of rollback deployments.
+//!
+//! After an update, the previous deployment is kept around as a rollback
+//! target, using space on the sysroot and on `/boot`. Once the node has been
+//! healthy on the new release for long enough, the agent can remove it (as
+//! `rpm-ostree cleanup --rollback` does). The time at which the booted
+//! release was first reported healthy is persisted, so that it survives
+//! agent restarts and reboots into the same release.
+
+use crate::rpm_ostree::Release;
+use crate::utils;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use fn_error_context::context;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::time::Duration;
+
+/// Absolute path to the persisted rollback cleanup record.
+pub(crate) static ROLLBACK_CLEANUP_PATH: &str = "/var/lib/zincati/rollback-cleanup.json";
+
+/// Healthy time of the booted release, for cleaning up its rollback deployment.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub(crate) struct RollbackCleanup {
+    /// Booted release version.
+    pub(crate) version: String,
+    /// Booted release checksum.
+    pub(crate) checksum: String,
+    /// Time at which the booted release was first reported healthy.
+    pub(crate) healthy_since: DateTime<Utc>,
+    /// Whether the rollback deployment has already been cleaned up.
+    pub(crate) cleaned: bool,
+}
+
+impl RollbackCleanup {
+    /// Build a new record for the given (booted) release.
+    pub(crate) fn new(release: &Release, now: DateTime<Utc>) -> Self {
+        Self {
+            version: release.version.clone(),
+            checksum: release.checksum.clone(),
+            healthy_since: now,
+            cleaned: false,
+        }
+    }
+
+    /// Return the record for the booted release, reusing the `persisted`
+    /// one if it is for the same release.
+    ///
+    /// The returned flag is set if the record is new, and needs persisting.
+    pub(crate) fn for_booted(
+        persisted: Option<Self>,
+        booted: &Release,
+        now: DateTime<Utc>,
+    ) -> (Self, bool) {
+        match persisted {
+            Some(record) if record.checksum == booted.checksum => (record, false),
+            _ => (Self::new(booted, now), true),
+        }
+    }
+
+    /// Whether the rollback deployment is due for cleanup, after being
+    /// healthy for `after`.
+    pub(crate) fn is_due(&self, after: Duration, now: &DateTime<Utc>) -> bool {
+        if self.cleaned {
+            return false;
+        }
+        (*now - self.healthy_since)
+            .to_std()
+            .map(|healthy| healthy >= after)
+            .unwrap_or(false)
+    }
+
+    /// Load a persisted record from `path`, if any.
+    #[context("failed to load rollback cleanup record")]
+    pub(crate) fn load(path: impl AsRef<Path>) -> Result<Option<Self>> {
+        let path = path.as_ref();
+        let content = match std::fs::read(path) {
+            Ok(c) => c,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => {
+                return Err(e).with_context(|| format!("failed to read '{}'", path.display()))
+            }
+        };
+        let record = serde_json::from_slice(&content)
+            .with_context(|| format!("failed to parse '{}'", path.display()))?;
+
+        Ok(Some(record))
+    }
+
+    /// Persist this record to `path`.
+    #[context("failed to persist rollback cleanup record")]
+    pub(crate) fn persist(&self, path: impl AsRef<Path>) -> Result<()> {
+        let content = serde_json::to_vec(self)?;
+        utils::atomic_write(path, 0o644, &content)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn release(checksum: &str) -> Release {
+        Release {
+            version: "33.20201201.3.0".to_string(),
+            checksum: checksum.to_string(),
+            age_index: None,
+            advisory: None,
+            wave: None,
+        }
+    }
+
+    #[test]
+    fn test_for_booted_is_due() {
+        let since = Utc.timestamp_opt(1_600_000_000, 0).unwrap();
+        let (record, new) = RollbackCleanup::for_booted(None, &release("c1"), since);
+        assert!(new);
+
+        let later = since + chrono::Duration::days(3);
+        let (mut record, new) = RollbackCleanup::for_booted(Some(record), &release("c1"), later);
+        assert!(!new);
+        assert_eq!(record.healthy_since, since);
+
+        let days = |n: u64| Duration::from_secs(n * 24 * 60 * 60);
+        assert!(record.is_due(days(3), &later));
+        assert!(!record.is_due(days(4), &later));
+        assert!(!record.is_due(days(1), &(since - chrono::Duration::days(1))));
+        record.cleaned = true;
+        assert!(!record.is_due(days(3), &later));
+
+        // A new booted release starts over.
+        let (record, new) = RollbackCleanup::for_booted(Some(record), &release("c2"), later);
+        assert!(new);
+        assert_eq!(record.healthy_since, later);
+        assert!(!record.cleaned);
+    }
+
+    #[test]
+    fn test_persist_load() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let path = tmpdir.path().join("rollback-cleanup.json");
+
+        assert_eq!(RollbackCleanup::load(&path).unwrap(), None);
+
+        let now = Utc.timestamp_opt(1_600_000_000, 0).unwrap();
+        let record = RollbackCleanup::new(&release("c1"), now);
+        record.persist(&path).unwrap();
+        assert_eq!(RollbackCleanup::load(&path).unwrap(), Some(record));
+    }
+}
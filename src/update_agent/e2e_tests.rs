@@ -0,0 +1,453 @@
+//! End-to-end tests for the update agent.
+//!
+//! These drive the real `UpdateAgent` actor through complete upgrade
+//! scenarios, against in-process mock Cincinnati and FleetLock servers
+//! (via `mockito`) and a mock rpm-ostree (via failpoints).
+
+use super::actor::CancelPendingReboot;
+use super::{AgentStatus, GetStatus, UpdateAgent};
+use crate::config::{fragments, inputs, Settings};
+use crate::identity::Identity;
+use crate::network::NetworkSettings;
+use crate::rpm_ostree::{OperationQueue, RpmOstreeClient};
+use crate::strategy::UpdateStrategy;
+use crate::update_source;
+use actix::{Actor, Addr};
+use fail::FailScenario;
+use mockito::{Matcher, Mock};
+use std::cell::Cell;
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+/// Maximum time for a scenario to reach its expected state, in seconds.
+const SCENARIO_TIMEOUT_SECS: u64 = 10;
+
+/// Update graph, from the mock booted release to a newer one.
+static UPDATE_GRAPH: &str = r#"
+{
+  "nodes": [
+    {
+      "version": "0.0.0-mock",
+      "metadata": {
+        "org.fedoraproject.coreos.scheme": "checksum",
+        "org.fedoraproject.coreos.releases.age_index": "0"
+      },
+      "payload": "sha-mock"
+    },
+    {
+      "version": "30.20190725.0",
+      "metadata": {
+        "org.fedoraproject.coreos.scheme": "checksum",
+        "org.fedoraproject.coreos.releases.age_index": "1"
+      },
+      "payload": "8b79877efa7ac06becd8637d95f8ca83aa385f89f383288bf3c2c31ca53216c7"
+    }
+  ],
+  "edges": [
+    [
+      0,
+      1
+    ]
+  ]
+}
+"#;
+
+/// Update graph, with only the mock booted release.
+static EMPTY_GRAPH: &str = r#"
+{
+  "nodes": [
+    {
+      "version": "0.0.0-mock",
+      "metadata": {
+        "org.fedoraproject.coreos.scheme": "checksum",
+        "org.fedoraproject.coreos.releases.age_index": "0"
+      },
+      "payload": "sha-mock"
+    }
+  ],
+  "edges": []
+}
+"#;
+
+/// Mock rpm-ostree, with all operations succeeding.
+///
+/// Failpoints are process-wide, thus scenarios are serialized for as long
+/// as the returned guard is held.
+fn mock_rpm_ostree() -> FailScenario<'static> {
+    let scenario = FailScenario::setup();
+    let failpoints = [
+        "register_driver_ok",
+        "local_deployments_ok",
+        "booted_layering_ok",
+        "staged_deployment_none",
+        "deploy_locked_ok",
+        "staged_is_downgrade_ok",
+        "staged_security_fixes_none",
+        "finalize_deployment_ok",
+    ];
+    for name in &failpoints {
+        fail::cfg(*name, "return").unwrap();
+    }
+    scenario
+}
+
+/// Mock a Cincinnati server, serving the given update graph.
+fn mock_cincinnati(graph: &str) -> Mock {
+    mockito::mock("GET", Matcher::Regex(r"^/v1/graph?.+$".to_string()))
+        .match_header("accept", Matcher::Regex("application/json".to_string()))
+        .with_body(graph)
+        .with_status(200)
+        .create()
+}
+
+/// Mock a FleetLock server endpoint, granting or denying requests.
+fn mock_fleet_lock(endpoint: &str, granted: bool) -> Mock {
+    let mock = mockito::mock("POST", Matcher::Exact(format!("/v1/{}", endpoint)))
+        .match_header("fleet-lock-protocol", "true");
+    if granted {
+        return mock.with_status(200).create();
+    }
+    mock.with_status(409)
+        .with_body(r#"{ "kind": "failed_lock", "value": "all semaphore slots taken" }"#)
+        .create()
+}
+
+/// Build agent settings for the given strategy, using mock servers.
+fn mock_settings(strategy: &str) -> Settings {
+    mock_settings_with(strategy, "")
+}
+
+/// Build agent settings for the given strategy, using mock servers and
+/// additional configuration.
+fn mock_settings_with(strategy: &str, extra: &str) -> Settings {
+    let content = format!(
+        r#"
+[cincinnati]
+base_url = "{url}"
+
+[updates]
+strategy = "{strategy}"
+
+[updates.fleet_lock]
+base_url = "{url}"
+
+{extra}
+"#,
+        url = mockito::server_url(),
+        strategy = strategy,
+        extra = extra
+    );
+    let frag: fragments::ConfigFragment = toml::from_str(&content).unwrap();
+    let cfg = inputs::ConfigInput::merge_fragments(vec![frag]);
+
+    let identity = Identity::mock_default();
+    let network = NetworkSettings::default();
+    let source =
+        update_source::with_config(cfg.cincinnati, &cfg.updates, &identity, &network).unwrap();
+    let max_postponements = cfg.updates.max_postponements;
+    let strategy = UpdateStrategy::with_config(cfg.updates, &identity, &network).unwrap();
+
+    Settings {
+        max_postponements,
+        source,
+        identity,
+        network,
+        strategy,
+        ..Settings::mock_default()
+    }
+}
+
+/// Run the update agent, driving it through `script`.
+fn drive_agent<F, Fut, T>(settings: Settings, script: F) -> T
+where
+    F: FnOnce(Addr<UpdateAgent>) -> Fut,
+    Fut: Future<Output = T>,
+{
+    let sys = actix::System::new();
+    sys.block_on(async move {
+        let rpm_ostree_client =
+            RpmOstreeClient::new(settings.rpm_ostree_backend, settings.rpm_ostree_timeouts);
+        let rpm_ostree_addr =
+            OperationQueue::start(rpm_ostree_client, settings.rpm_ostree_max_queued);
+        let agent_addr = UpdateAgent::with_config(settings, rpm_ostree_addr).start();
+
+        let res = script(agent_addr).await;
+        actix::System::current().stop();
+        res
+    })
+}
+
+/// Poll the agent status until it satisfies `done`.
+///
+/// This returns the last observed status, also on timeout.
+async fn wait_status(
+    agent_addr: &Addr<UpdateAgent>,
+    done: impl Fn(&AgentStatus) -> bool,
+) -> AgentStatus {
+    let deadline = Instant::now() + Duration::from_secs(SCENARIO_TIMEOUT_SECS);
+    loop {
+        let status = agent_addr.send(GetStatus {}).await.unwrap();
+        if done(&status) || Instant::now() >= deadline {
+            return status;
+        }
+        actix::clock::sleep(Duration::from_millis(50)).await;
+    }
+}
+
+/// Run the update agent until its status satisfies `done`.
+///
+/// This returns the last observed status, also on timeout.
+fn run_agent(settings: Settings, done: impl Fn(&AgentStatus) -> bool) -> AgentStatus {
+    drive_agent(settings, |agent_addr| async move {
+        wait_status(&agent_addr, done).await
+    })
+}
+
+#[test]
+fn immediate_update_finalized() {
+    let _scenario = mock_rpm_ostree();
+    let m_graph = mock_cincinnati(UPDATE_GRAPH);
+
+    let status = run_agent(mock_settings("immediate"), |s| s.state == "EndState");
+    m_graph.assert();
+    assert_eq!(status.state, "EndState");
+    assert_eq!(status.last_finalize_verdict, "allowed");
+}
+
+#[test]
+fn no_update_available() {
+    let _scenario = mock_rpm_ostree();
+    let m_graph = mock_cincinnati(EMPTY_GRAPH);
+
+    let status = run_agent(mock_settings("immediate"), |s| s.state == "NoNewUpdate");
+    m_graph.assert();
+    assert_eq!(status.state, "NoNewUpdate");
+    assert_eq!(status.target_version, "");
+}
+
+#[test]
+fn fleet_lock_update_finalized() {
+    let _scenario = mock_rpm_ostree();
+    let m_graph = mock_cincinnati(UPDATE_GRAPH);
+    let m_steady_state = mock_fleet_lock("steady-state", true);
+    let m_pre_reboot = mock_fleet_lock("pre-reboot", true);
+
+    let status = run_agent(mock_settings("fleet_lock"), |s| s.state == "EndState");
+    m_graph.assert();
+    m_steady_state.assert();
+    m_pre_reboot.assert();
+    assert_eq!(status.state, "EndState");
+    assert_eq!(status.strategy, "fleet_lock");
+    assert_eq!(status.last_finalize_verdict, "allowed");
+}
+
+#[test]
+fn fleet_lock_reboot_denied() {
+    let _scenario = mock_rpm_ostree();
+    let m_graph = mock_cincinnati(UPDATE_GRAPH);
+    let m_steady_state = mock_fleet_lock("steady-state", true);
+    let m_pre_reboot = mock_fleet_lock("pre-reboot", false);
+
+    let status = run_agent(mock_settings("fleet_lock"), |s| {
+        s.state == "UpdateStaged" && !s.last_finalize_verdict.is_empty()
+    });
+    m_graph.assert();
+    m_steady_state.assert();
+    m_pre_reboot.assert();
+    assert_eq!(status.state, "UpdateStaged");
+    assert_eq!(status.target_version, "30.20190725.0");
+    assert_eq!(status.last_finalize_verdict, "strategy");
+}
+
+#[test]
+fn stage_failure_retried() {
+    let _scenario = mock_rpm_ostree();
+    fail::cfg("deploy_locked_err", "return").unwrap();
+    let m_graph = mock_cincinnati(UPDATE_GRAPH);
+
+    let status = run_agent(mock_settings("immediate"), |s| !s.last_error.is_empty());
+    m_graph.assert();
+    assert_eq!(status.state, "UpdateAvailable");
+    assert_eq!(status.target_version, "30.20190725.0");
+    assert!(
+        status.last_error.contains("deploy_locked_err"),
+        "{}",
+        status.last_error
+    );
+}
+
+#[test]
+fn reboot_countdown_finalized() {
+    let _scenario = mock_rpm_ostree();
+    let m_graph = mock_cincinnati(UPDATE_GRAPH);
+    let settings = Settings {
+        reboot_countdown: Some(Duration::from_secs(1)),
+        ..mock_settings("immediate")
+    };
+
+    let counted_down = Cell::new(false);
+    let status = run_agent(settings, |s| {
+        if s.last_finalize_verdict == "countdown" {
+            counted_down.set(true);
+        }
+        s.state == "EndState"
+    });
+    m_graph.assert();
+    assert!(counted_down.get());
+    assert_eq!(status.state, "EndState");
+    assert_eq!(status.last_finalize_verdict, "allowed");
+}
+
+#[test]
+fn reboot_countdown_cancelled() {
+    let _scenario = mock_rpm_ostree();
+    let m_graph = mock_cincinnati(UPDATE_GRAPH);
+    let settings = Settings {
+        reboot_countdown: Some(Duration::from_secs(3600)),
+        ..mock_settings("immediate")
+    };
+
+    let (cancelled, status, cancelled_again) = drive_agent(settings, |agent_addr| async move {
+        let counting_down = wait_status(&agent_addr, |s| s.last_finalize_verdict == "countdown");
+        assert_eq!(counting_down.await.state, "UpdateStaged");
+        let cancelled = agent_addr.send(CancelPendingReboot {}).await.unwrap();
+        let status = agent_addr.send(GetStatus {}).await.unwrap();
+        let cancelled_again = agent_addr.send(CancelPendingReboot {}).await.unwrap();
+        (cancelled.unwrap(), status, cancelled_again.unwrap())
+    });
+    m_graph.assert();
+    assert!(cancelled);
+    assert_eq!(status.state, "UpdateStaged");
+    assert_eq!(status.target_version, "30.20190725.0");
+    assert_eq!(status.last_finalize_verdict, "countdown-cancelled");
+    assert!(!cancelled_again);
+}
+
+#[test]
+fn staged_deployment_adopted() {
+    let _scenario = mock_rpm_ostree();
+    fail::cfg("staged_deployment_found", "return(30.20190725.0)").unwrap();
+    let m_steady_state = mock_fleet_lock("steady-state", true);
+    let m_pre_reboot = mock_fleet_lock("pre-reboot", false);
+
+    let status = run_agent(mock_settings("fleet_lock"), |s| {
+        s.state == "UpdateStaged" && !s.last_finalize_verdict.is_empty()
+    });
+    m_steady_state.assert();
+    m_pre_reboot.assert();
+    assert_eq!(status.state, "UpdateStaged");
+    assert_eq!(status.target_version, "30.20190725.0");
+    assert_eq!(status.target_checksum, "sha-staged-mock");
+    assert_eq!(status.last_finalize_verdict, "strategy");
+}
+
+/// Build agent settings for the `periodic` strategy, with a daily window
+/// starting at the given offset from now.
+fn mock_periodic_settings(start_offset: chrono::Duration) -> Settings {
+    let start = chrono::Utc::now() + start_offset;
+    let window = format!(
+        r#"
+[[updates.periodic.window]]
+days = [ "Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun" ]
+start_time = "{}"
+length_minutes = 120
+"#,
+        start.format("%H:%M")
+    );
+    mock_settings_with("periodic", &window)
+}
+
+/// Current value of a counter metric, accumulated across scenarios.
+fn counter(name: &str) -> u64 {
+    prometheus::gather()
+        .iter()
+        .find(|m| m.get_name() == name)
+        .map(|m| m.get_metric()[0].get_counter().get_value() as u64)
+        .unwrap_or(0)
+}
+
+#[test]
+fn fetch_only_window_deferred() {
+    let _scenario = mock_rpm_ostree();
+    let m_graph = mock_cincinnati(UPDATE_GRAPH);
+    let settings = Settings {
+        fetch_only_window: true,
+        ..mock_periodic_settings(chrono::Duration::hours(6))
+    };
+
+    // Downloaded right away, but neither staged nor finalized outside the window.
+    let status = drive_agent(settings, |agent_addr| async move {
+        wait_status(&agent_addr, |s| s.state == "UpdateDownloaded").await;
+        actix::clock::sleep(Duration::from_millis(500)).await;
+        agent_addr.send(GetStatus {}).await.unwrap()
+    });
+    m_graph.assert();
+    assert_eq!(status.state, "UpdateDownloaded");
+    assert_eq!(status.target_version, "30.20190725.0");
+    assert_eq!(status.last_finalize_verdict, "");
+}
+
+#[test]
+fn fetch_only_window_finalized() {
+    let _scenario = mock_rpm_ostree();
+    let m_graph = mock_cincinnati(UPDATE_GRAPH);
+    let settings = Settings {
+        fetch_only_window: true,
+        ..mock_periodic_settings(-chrono::Duration::hours(1))
+    };
+
+    // Downloaded first, then staged and finalized within the window.
+    let downloads = counter("zincati_rpm_ostree_download_attempts_total");
+    let status = run_agent(settings, |s| s.state == "EndState");
+    m_graph.assert();
+    assert_eq!(
+        counter("zincati_rpm_ostree_download_attempts_total"),
+        downloads + 1
+    );
+    assert_eq!(status.state, "EndState");
+    assert_eq!(status.strategy, "periodic");
+    assert_eq!(status.last_finalize_verdict, "allowed");
+}
+
+#[test]
+fn remote_check_refused() {
+    let _scenario = mock_rpm_ostree();
+    fail::cfg("commit_available_err", "return").unwrap();
+    let m_graph = mock_cincinnati(UPDATE_GRAPH);
+    let settings = Settings {
+        verify_remote: Some("mock-remote".to_string()),
+        ..mock_settings("immediate")
+    };
+
+    // Fetching is delayed, without counting as a failed deploy attempt.
+    let delayed = counter("zincati_update_agent_target_not_on_remote_total");
+    let status = drive_agent(settings, |agent_addr| async move {
+        wait_status(&agent_addr, |s| s.state == "UpdateAvailable").await;
+        actix::clock::sleep(Duration::from_millis(500)).await;
+        agent_addr.send(GetStatus {}).await.unwrap()
+    });
+    m_graph.assert();
+    assert_eq!(status.state, "UpdateAvailable");
+    assert_eq!(status.target_version, "30.20190725.0");
+    assert_eq!(status.last_error, "");
+    assert_eq!(
+        counter("zincati_update_agent_target_not_on_remote_total"),
+        delayed + 1
+    );
+}
+
+#[test]
+fn remote_check_passed() {
+    let _scenario = mock_rpm_ostree();
+    fail::cfg("commit_available_ok", "return").unwrap();
+    let m_graph = mock_cincinnati(UPDATE_GRAPH);
+    let settings = Settings {
+        verify_remote: Some("mock-remote".to_string()),
+        ..mock_settings("immediate")
+    };
+
+    let status = run_agent(settings, |s| s.state == "EndState");
+    m_graph.assert();
+    assert_eq!(status.state, "EndState");
+    assert_eq!(status.last_finalize_verdict, "allowed");
+}
@@ -0,0 +1,73 @@
+//! Notable agent events, for external listeners (e.g. D-Bus signals).
+
+use std::fmt;
+
+/// Notable agent event.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AgentEvent {
+    /// An update was staged (or adopted, if staged out-of-band).
+    UpdateStaged {
+        /// Version of the staged release.
+        version: String,
+    },
+    /// A reboot into the staged update was scheduled.
+    RebootScheduled {
+        /// UTC timestamp (seconds since epoch) of the reboot.
+        timestamp: i64,
+    },
+}
+
+/// Callback for agent events.
+pub type EventListener = Box<dyn Fn(&AgentEvent) + Send>;
+
+/// Listeners subscribed to agent events.
+#[derive(Default)]
+pub(crate) struct EventListeners {
+    listeners: Vec<EventListener>,
+}
+
+impl fmt::Debug for EventListeners {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EventListeners")
+            .field("listeners", &self.listeners.len())
+            .finish()
+    }
+}
+
+impl EventListeners {
+    /// Subscribe a listener to all future events.
+    pub(crate) fn add(&mut self, listener: EventListener) {
+        self.listeners.push(listener);
+    }
+
+    /// Notify all listeners of an event.
+    pub(crate) fn emit(&self, event: AgentEvent) {
+        log::trace!("agent event: {:?}", event);
+        for listener in &self.listeners {
+            listener(&event);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn test_emit() {
+        let received = Arc::new(Mutex::new(vec![]));
+        let mut listeners = EventListeners::default();
+        listeners.emit(AgentEvent::RebootScheduled { timestamp: 1 });
+
+        let sink = Arc::clone(&received);
+        listeners.add(Box::new(move |event| {
+            sink.lock().unwrap().push(event.clone())
+        }));
+        let staged = AgentEvent::UpdateStaged {
+            version: "34.20210711.3.0".to_string(),
+        };
+        listeners.emit(staged.clone());
+        assert_eq!(*received.lock().unwrap(), vec![staged]);
+    }
+}
@@ -0,0 +1,180 @@
+//! Update history.
+//!
+//! The agent appends an entry for each step in the lifecycle of an update
+//! (found, staged, postponed, finalized) and for each failure, as JSON lines
+//! to an append-only log. This provides an audit trail of when and why a node
+//! rebooted. Checks not finding any new update are not recorded, as they
+//! happen on every refresh.
+
+use crate::rpm_ostree::Release;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use fn_error_context::context;
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+
+/// Absolute path to the update history log.
+pub(crate) static HISTORY_PATH: &str = "/var/lib/zincati/history.log";
+
+/// Step in the lifecycle of an update.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum HistoryEvent {
+    /// Update found by a check.
+    UpdateFound,
+    /// Update staged (or adopted, if staged out-of-band).
+    Staged,
+    /// Finalization postponed.
+    Postponed,
+    /// Update finalized, rebooting.
+    Finalized,
+    /// Failure of an update operation.
+    Failure,
+}
+
+impl HistoryEvent {
+    /// Return the label of this event.
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            HistoryEvent::UpdateFound => "update-found",
+            HistoryEvent::Staged => "staged",
+            HistoryEvent::Postponed => "postponed",
+            HistoryEvent::Finalized => "finalized",
+            HistoryEvent::Failure => "failure",
+        }
+    }
+}
+
+/// Entry of the update history.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub(crate) struct HistoryEntry {
+    /// Point in time of the event.
+    pub(crate) timestamp: DateTime<Utc>,
+    /// Lifecycle event.
+    pub(crate) event: HistoryEvent,
+    /// Version of the release involved, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) version: Option<String>,
+    /// Checksum of the release involved, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) checksum: Option<String>,
+    /// Additional details (e.g. a failure message).
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub(crate) detail: String,
+}
+
+impl HistoryEntry {
+    /// Build a new entry for an event involving `release`, happening now.
+    pub(crate) fn new(event: HistoryEvent, release: Option<&Release>, detail: &str) -> Self {
+        Self {
+            timestamp: Utc::now(),
+            event,
+            version: release.map(|r| r.version.clone()),
+            checksum: release.map(|r| r.checksum.clone()),
+            detail: detail.to_string(),
+        }
+    }
+
+    /// Return a human-readable description of this entry.
+    pub(crate) fn describe(&self) -> String {
+        let subject = match &self.version {
+            Some(version) if self.detail.is_empty() => version.clone(),
+            Some(version) => format!("{}: {}", version, self.detail),
+            None => self.detail.clone(),
+        };
+        let line = format!(
+            "{}  {:<12} {}",
+            self.timestamp.format("%a %Y-%m-%d %H:%M:%S %Z"),
+            self.event.as_str(),
+            subject
+        );
+        line.trim_end().to_string()
+    }
+
+    /// Append this entry to the history log at `path`.
+    #[context("failed to record update history")]
+    pub(crate) fn append(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let mut line = serde_json::to_vec(self)?;
+        line.push(b'\n');
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .mode(0o644)
+            .open(path)
+            .with_context(|| format!("failed to open '{}'", path.display()))?;
+        // A single write, so that concurrent entries are never interleaved.
+        file.write_all(&line)
+            .with_context(|| format!("failed to write '{}'", path.display()))
+    }
+}
+
+/// Load all entries from the history log at `path`, oldest first.
+///
+/// Invalid lines (e.g. truncated by a crash) are logged and skipped.
+#[context("failed to load update history")]
+pub(crate) fn load(path: impl AsRef<Path>) -> Result<Vec<HistoryEntry>> {
+    let path = path.as_ref();
+    let content = match std::fs::read_to_string(path) {
+        Ok(c) => c,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e).with_context(|| format!("failed to read '{}'", path.display())),
+    };
+
+    let mut entries = vec![];
+    for (index, line) in content.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str(line) {
+            Ok(entry) => entries.push(entry),
+            Err(e) => log::warn!(
+                "skipping invalid line {} of '{}': {}",
+                index + 1,
+                path.display(),
+                e
+            ),
+        }
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_append_load() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let path = tmpdir.path().join("history.log");
+        assert_eq!(load(&path).unwrap(), vec![]);
+
+        let release = Release {
+            version: "34.20210711.3.0".to_string(),
+            checksum: "bbbb".to_string(),
+            age_index: None,
+            advisory: None,
+            wave: None,
+        };
+        let staged = HistoryEntry::new(HistoryEvent::Staged, Some(&release), "");
+        let failure = HistoryEntry::new(HistoryEvent::Failure, None, "failed to stage");
+        staged.append(&path).unwrap();
+        failure.append(&path).unwrap();
+        assert!(staged
+            .describe()
+            .ends_with(&format!("{:<12} 34.20210711.3.0", "staged")));
+        assert!(failure
+            .describe()
+            .ends_with(&format!("{:<12} failed to stage", "failure")));
+
+        // Truncated lines are skipped.
+        let mut file = std::fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap();
+        file.write_all(b"{\"timestamp\":").unwrap();
+        assert_eq!(load(&path).unwrap(), vec![staged, failure]);
+    }
+}
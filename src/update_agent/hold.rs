@@ -0,0 +1,100 @@
+//! Update holds.
+//!
+//! An administrator can pin a node to its booted release, e.g. while
+//! validating a workload against it. Unlike disabling auto-updates, the
+//! agent keeps checking for updates and reporting them, but never stages
+//! nor finalizes them while the hold is in place. The hold is persisted to
+//! disk until released, so that it survives agent restarts.
+
+use crate::utils;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use fn_error_context::context;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// Absolute path to the persisted update hold.
+pub(crate) static UPDATE_HOLD_PATH: &str = "/var/lib/zincati/update-hold.json";
+
+/// A hold pinning the node to a release.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub(crate) struct UpdateHold {
+    /// Pinned (booted) release version.
+    pub(crate) version: String,
+    /// Time at which the hold was placed.
+    pub(crate) since: DateTime<Utc>,
+}
+
+impl UpdateHold {
+    /// Build a new hold on the given release version.
+    pub(crate) fn new(version: &str, now: DateTime<Utc>) -> Self {
+        Self {
+            version: version.to_string(),
+            since: now,
+        }
+    }
+
+    /// Return a human-readable description of this hold.
+    pub(crate) fn describe(&self) -> String {
+        format!(
+            "hold on {} since {}",
+            self.version,
+            self.since.format("%a %Y-%m-%d %H:%M:%S %Z")
+        )
+    }
+
+    /// Load a persisted hold from `path`, if any.
+    #[context("failed to load update hold")]
+    pub(crate) fn load(path: impl AsRef<Path>) -> Result<Option<Self>> {
+        let path = path.as_ref();
+        let content = match std::fs::read(path) {
+            Ok(c) => c,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => {
+                return Err(e).with_context(|| format!("failed to read '{}'", path.display()))
+            }
+        };
+        let hold = serde_json::from_slice(&content)
+            .with_context(|| format!("failed to parse '{}'", path.display()))?;
+
+        Ok(Some(hold))
+    }
+
+    /// Persist this hold to `path`.
+    #[context("failed to persist update hold")]
+    pub(crate) fn persist(&self, path: impl AsRef<Path>) -> Result<()> {
+        let content = serde_json::to_vec(self)?;
+        utils::atomic_write(path, 0o644, &content)
+    }
+
+    /// Remove the persisted hold at `path`, if any.
+    #[context("failed to remove update hold")]
+    pub(crate) fn remove(path: impl AsRef<Path>) -> Result<()> {
+        utils::remove_if_exists(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_persist_roundtrip() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let path = tmpdir.path().join("update-hold.json");
+
+        assert_eq!(UpdateHold::load(&path).unwrap(), None);
+
+        let now = Utc.timestamp_opt(1_600_000_000, 0).unwrap();
+        let hold = UpdateHold::new("33.20201201.3.0", now);
+        assert!(hold
+            .describe()
+            .starts_with("hold on 33.20201201.3.0 since "));
+        hold.persist(&path).unwrap();
+        assert_eq!(UpdateHold::load(&path).unwrap(), Some(hold));
+
+        UpdateHold::remove(&path).unwrap();
+        assert_eq!(UpdateHold::load(&path).unwrap(), None);
+    }
+}
@@ -0,0 +1,191 @@
+//! Reboots scheduled via systemd-logind.
+//!
+//! Instead of rebooting right away on finalization, the agent can ask logind
+//! to schedule a reboot (`ScheduledShutdown`) some time in advance. Logind
+//! then warns logged-in users natively, and the reboot can be cancelled with
+//! the usual tools (e.g. `shutdown -c`). The agent finalizes the update right
+//! before the scheduled time, and backs off if the schedule is cancelled.
+//! The warning shown by logind is set from the configured reboot warning
+//! template, and reset once the schedule is gone.
+//!
+//! Logind is also the source of truth for interactive sessions, which are
+//! warned about finalization postponements.
+
+use anyhow::Result;
+use chrono::{DateTime, TimeZone, Utc};
+use fn_error_context::context;
+use std::convert::TryFrom;
+use zbus::dbus_proxy;
+use zvariant::{OwnedObjectPath, OwnedValue, Value};
+
+/// Shutdown type for reboots.
+static REBOOT_TYPE: &str = "reboot";
+
+/// Margin before the scheduled time at which the update is finalized (in seconds).
+///
+/// Finalization happens slightly ahead of logind, otherwise logind would
+/// reboot into the current deployment (the staged one being still locked).
+pub(crate) const FINALIZE_MARGIN_SECS: i64 = 30;
+
+/// Session entry, as `(id, uid, user, seat, path)` returned by `ListSessions`.
+type SessionEntry = (String, u32, String, String, OwnedObjectPath);
+
+#[dbus_proxy(
+    interface = "org.freedesktop.login1.Manager",
+    default_service = "org.freedesktop.login1",
+    default_path = "/org/freedesktop/login1"
+)]
+trait Manager {
+    /// ScheduleShutdown method
+    fn schedule_shutdown(&self, type_: &str, usec: u64) -> zbus::Result<()>;
+
+    /// CancelScheduledShutdown method
+    fn cancel_scheduled_shutdown(&self) -> zbus::Result<bool>;
+
+    /// ListSessions method
+    fn list_sessions(&self) -> zbus::Result<Vec<SessionEntry>>;
+
+    /// SetWallMessage method
+    fn set_wall_message(&self, wall_message: &str, enable: bool) -> zbus::Result<()>;
+
+    /// ScheduledShutdown property
+    #[dbus_proxy(property)]
+    fn scheduled_shutdown(&self) -> zbus::Result<OwnedValue>;
+
+    /// PreparingForShutdown property
+    #[dbus_proxy(property)]
+    fn preparing_for_shutdown(&self) -> zbus::Result<bool>;
+}
+
+#[dbus_proxy(
+    interface = "org.freedesktop.login1.Session",
+    default_service = "org.freedesktop.login1"
+)]
+trait Session {
+    /// TTY property
+    #[dbus_proxy(property, name = "TTY")]
+    fn tty(&self) -> zbus::Result<String>;
+}
+
+/// Schedule a reboot at the given time, warning users with the given message.
+#[context("failed to schedule reboot via logind")]
+pub(crate) fn schedule_reboot(at: &DateTime<Utc>, wall_message: &str) -> Result<()> {
+    let connection = zbus::Connection::new_system()?;
+    let manager = ManagerProxy::new(&connection)?;
+    manager.set_wall_message(wall_message, true)?;
+    manager.schedule_shutdown(REBOOT_TYPE, timestamp_usec(at))?;
+    Ok(())
+}
+
+/// Cancel a scheduled reboot, if any.
+#[context("failed to cancel scheduled reboot via logind")]
+pub(crate) fn cancel_reboot() -> Result<()> {
+    let connection = zbus::Connection::new_system()?;
+    let manager = ManagerProxy::new(&connection)?;
+    manager.cancel_scheduled_shutdown()?;
+    manager.set_wall_message("", true)?;
+    Ok(())
+}
+
+/// Reset the warning message to logind default.
+#[context("failed to reset wall message via logind")]
+pub(crate) fn reset_wall_message() -> Result<()> {
+    let connection = zbus::Connection::new_system()?;
+    let manager = ManagerProxy::new(&connection)?;
+    manager.set_wall_message("", true)?;
+    Ok(())
+}
+
+/// Return user sessions attached to a tty, as `(user, tty)` tuples.
+#[context("failed to list sessions via logind")]
+pub(crate) fn tty_sessions() -> Result<Vec<(String, String)>> {
+    let connection = zbus::Connection::new_system()?;
+    let manager = ManagerProxy::new(&connection)?;
+    let mut sessions = vec![];
+    for (_id, _uid, user, _seat, path) in manager.list_sessions()? {
+        let session = SessionProxy::new_for_path(&connection, path.as_str())?;
+        let tty = session.tty()?;
+        if tty.is_empty() {
+            log::debug!(
+                "found user {} with no tty, user considered non-interactive",
+                user
+            );
+            continue;
+        }
+        sessions.push((user, tty));
+    }
+    Ok(sessions)
+}
+
+/// Return the time of the reboot currently scheduled via logind, if any.
+#[context("failed to query scheduled reboot via logind")]
+pub(crate) fn scheduled_reboot() -> Result<Option<DateTime<Utc>>> {
+    let connection = zbus::Connection::new_system()?;
+    let manager = ManagerProxy::new(&connection)?;
+    let value = manager.scheduled_shutdown()?;
+    let (kind, usec) = parse_scheduled_shutdown(&value)?;
+
+    if kind != REBOOT_TYPE || usec == 0 {
+        return Ok(None);
+    }
+    Ok(Some(from_timestamp_usec(usec)))
+}
+
+/// Return whether the system is about to shut down or reboot.
+#[context("failed to query shutdown status via logind")]
+pub(crate) fn preparing_for_shutdown() -> Result<bool> {
+    let connection = zbus::Connection::new_system()?;
+    let manager = ManagerProxy::new(&connection)?;
+    let preparing = manager.preparing_for_shutdown()?;
+    Ok(preparing)
+}
+
+/// Parse the `ScheduledShutdown` property, as a `(type, usec)` tuple.
+fn parse_scheduled_shutdown(value: &Value) -> Result<(String, u64)> {
+    let fields = match value {
+        Value::Structure(s) => s.fields(),
+        Value::Value(v) => return parse_scheduled_shutdown(v),
+        _ => anyhow::bail!("unexpected property type"),
+    };
+    match fields {
+        [Value::Str(kind), Value::U64(usec)] => Ok((kind.to_string(), *usec)),
+        _ => anyhow::bail!("unexpected property fields"),
+    }
+}
+
+/// Convert a datetime to a timestamp in microseconds, as used by logind.
+fn timestamp_usec(at: &DateTime<Utc>) -> u64 {
+    let usec = at.timestamp().saturating_mul(1_000_000);
+    u64::try_from(usec).unwrap_or(0)
+}
+
+/// Convert a timestamp in microseconds to a datetime.
+fn from_timestamp_usec(usec: u64) -> DateTime<Utc> {
+    let secs = i64::try_from(usec / 1_000_000).unwrap_or(i64::MAX);
+    Utc.timestamp_opt(secs, 0)
+        .single()
+        .unwrap_or_else(|| DateTime::from(std::time::UNIX_EPOCH))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use zvariant::Structure;
+
+    #[test]
+    fn test_parse_scheduled_shutdown() {
+        let value = Value::from(Structure::from(("reboot", 1_600_000_000_000_000u64)));
+        let (kind, usec) = parse_scheduled_shutdown(&value).unwrap();
+        assert_eq!(kind, "reboot");
+        assert_eq!(
+            from_timestamp_usec(usec),
+            Utc.timestamp_opt(1_600_000_000, 0).unwrap()
+        );
+        assert_eq!(
+            timestamp_usec(&Utc.timestamp_opt(1_600_000_000, 0).unwrap()),
+            1_600_000_000_000_000
+        );
+
+        parse_scheduled_shutdown(&Value::from(42u64)).unwrap_err();
+    }
+}
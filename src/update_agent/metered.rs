@@ -0,0 +1,56 @@
+//! Metered connections, as reported by NetworkManager.
+//!
+//! On metered links (e.g. cellular backhaul) traffic can be expensive, so the
+//! agent can defer fetching updates until the node is back on an unmetered
+//! connection. NetworkManager reports whether the primary connection is
+//! metered, either as configured or as guessed from the device type.
+
+use anyhow::Result;
+use fn_error_context::context;
+use zbus::dbus_proxy;
+
+/// NetworkManager metered value for metered connections (`NM_METERED_YES`).
+const NM_METERED_YES: u32 = 1;
+
+/// NetworkManager metered value for connections guessed to be metered
+/// (`NM_METERED_GUESS_YES`).
+const NM_METERED_GUESS_YES: u32 = 3;
+
+#[dbus_proxy(
+    interface = "org.freedesktop.NetworkManager",
+    default_service = "org.freedesktop.NetworkManager",
+    default_path = "/org/freedesktop/NetworkManager"
+)]
+trait NetworkManager {
+    /// Metered property
+    #[dbus_proxy(property)]
+    fn metered(&self) -> zbus::Result<u32>;
+}
+
+/// Return whether the primary network connection is metered.
+#[context("failed to query metered connection via NetworkManager")]
+pub(crate) fn connection_metered() -> Result<bool> {
+    let connection = zbus::Connection::new_system()?;
+    let manager = NetworkManagerProxy::new(&connection)?;
+    let metered = manager.metered()?;
+    Ok(is_metered(metered))
+}
+
+/// Whether a NetworkManager metered value means a metered connection.
+fn is_metered(value: u32) -> bool {
+    value == NM_METERED_YES || value == NM_METERED_GUESS_YES
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_metered() {
+        // Unknown, yes, no, guess-yes, guess-no.
+        let cases = vec![(0, false), (1, true), (2, false), (3, true), (4, false)];
+        for (value, metered) in cases {
+            assert_eq!(is_metered(value), metered, "{}", value);
+        }
+    }
+}
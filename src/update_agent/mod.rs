@@ -1,61 +1,264 @@
 //! Update agent.
 
 mod actor;
-pub use actor::LastRefresh;
+#[cfg(feature = "dbus")]
+pub use actor::{
+    ApprovePendingReboot, CancelPendingReboot, CancelScheduledFinalize, ClearTemporaryBlackout,
+    GetConfig, GetPlan, GetStatus, HoldUpdates, NextRefresh, ReleaseHold, ScheduleFinalize,
+    ScheduledFinalizeTime, SetTemporaryBlackout, SetUpdateGroup, SubscribeEvents, SwitchStream,
+    TargetRelease,
+};
+pub use actor::{LastRefresh, NetworkUp, Reload, Shutdown};
 
-use crate::cincinnati::Cincinnati;
+mod approval;
+use approval::{RebootApproval, REBOOT_APPROVAL_PATH};
+
+mod blackout;
+pub(crate) use blackout::{TemporaryBlackout, TEMPORARY_BLACKOUT_PATH};
+
+mod bootfs;
+
+mod cleanup;
+use cleanup::{RollbackCleanup, ROLLBACK_CLEANUP_PATH};
+
+#[cfg(all(test, feature = "e2e-tests"))]
+mod e2e_tests;
+
+pub(crate) mod history;
+use history::{HistoryEntry, HistoryEvent, HISTORY_PATH};
+
+mod events;
+use events::EventListeners;
+pub use events::{AgentEvent, EventListener};
+
+mod hold;
+pub(crate) use hold::{UpdateHold, UPDATE_HOLD_PATH};
+
+mod logind;
+
+mod metered;
+
+mod plan;
+pub use plan::PlannedAction;
+
+mod reboot_lock;
+use reboot_lock::RebootLock;
+
+mod schedule;
+use schedule::{ScheduledFinalize, SCHEDULED_FINALIZE_PATH};
+
+mod shutdown;
+use shutdown::{ShutdownRecord, SHUTDOWN_RECORD_PATH};
+
+use crate::blackout::BlackoutPeriods;
+use crate::config::inputs::{DEFAULT_MAX_POSTPONEMENTS, DEFAULT_POSTPONEMENT_DELAY_MINUTES};
 use crate::config::Settings;
-use crate::identity::Identity;
-use crate::rpm_ostree::{Release, RpmOstreeClient};
+use crate::connectivity::ConnectivityGate;
+use crate::disk_space::DiskSpaceCheck;
+use crate::downgrade::DowngradeBarrier;
+use crate::download::DownloadSchedule;
+use crate::health_checks::HealthChecks;
+use crate::identity::{self, Identity, GROUP_OVERRIDE_PATH, STREAM_SWITCH_PATH};
+use crate::logging;
+use crate::messages::MessageTemplates;
+use crate::outcome_report::OutcomeReporter;
+use crate::post_boot::PostBootHooks;
+use crate::quiesce::ServiceQuiesce;
+use crate::rpm_ostree::{self, Layering, OperationQueue, Release};
 use crate::strategy::UpdateStrategy;
+use crate::stream_switch::StreamSwitch;
+use crate::update_source::UpdateSource;
+use crate::urgency::UrgencyOverride;
+use crate::utils;
+use crate::webhook::{Event, Webhook};
 use actix::Addr;
 use anyhow::{Context, Result};
 use chrono::prelude::*;
-use prometheus::{IntCounter, IntGauge};
-use serde::{Deserialize, Deserializer};
+use prometheus::{HistogramVec, IntCounter, IntCounterVec, IntGauge, IntGaugeVec};
+use serde::{Deserialize, Deserializer, Serialize};
 use std::convert::TryInto;
 use std::fs;
+use std::io::Write;
+use std::num::NonZeroU32;
 use std::time::Duration;
-
-/// Default refresh interval for steady state (in seconds).
-pub(crate) const DEFAULT_STEADY_INTERVAL_SECS: u64 = 300; // 5 minutes.
+use zvariant::derive::Type;
 
 /// Default tick/refresh period for the state machine (in seconds).
 const DEFAULT_REFRESH_PERIOD_SECS: u64 = 300; // 5 minutes.
 
-/// Default amount of time to postpone finalizing an update if active
-/// interactive user sessions detected.
-const DEFAULT_POSTPONEMENT_TIME_SECS: u64 = 60; // 1 minute.
+/// Lead time before the next finalization window at which a downloaded
+/// update is staged, in fetch-only window mode (in seconds).
+const STAGING_LEAD_TIME_SECS: i64 = 3600; // 1 hour.
 
 /// Maximum failed deploy attempts in a row in `UpdateAvailable` state
 /// before abandoning a target update.
 const MAX_DEPLOY_ATTEMPTS: u8 = 12;
 
-/// Maximum number of postponements to finalizing an update in the
-/// `UpdateStaged` state before forcing an update finalization and reboot.
-pub(crate) const MAX_FINALIZE_POSTPONEMENTS: u8 = 10;
+/// Kernel command-line argument inhibiting auto-updates for the current boot.
+const INHIBIT_KARG: &str = "zincati.inhibit";
+
+/// Journal message ID for downgrades performed by the agent.
+static DOWNGRADE_MESSAGE_ID: &str = "4c0c1a4f3e5b4d7e9a2b6f8d1e3c5a79";
+
+/// Path to the kernel command-line of the current boot.
+pub(crate) static KERNEL_CMDLINE_PATH: &str = "/proc/cmdline";
+
+/// Pending reboot into the staged update, tracked by the agent as a sub-state
+/// of `UpdateStaged`.
+///
+/// A pending reboot can be aborted (e.g. over D-Bus) back to plain `UpdateStaged`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum PendingReboot {
+    /// Reboot scheduled via logind at the given time.
+    Scheduled(DateTime<Utc>),
+    /// Countdown running, finalizing the update at the given time.
+    Countdown(DateTime<Utc>),
+    /// Pending reboot cancelled, not to be re-scheduled before the given time.
+    Cancelled(DateTime<Utc>),
+}
+
+/// Budget for postponing finalization, if active interactive user sessions
+/// are detected.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct PostponementBudget {
+    /// Maximum number of postponements to finalizing an update in the
+    /// `UpdateStaged` state before forcing an update finalization and reboot.
+    pub(crate) max: u8,
+    /// Amount of time to postpone finalizing an update by.
+    pub(crate) delay: Duration,
+}
+
+impl Default for PostponementBudget {
+    fn default() -> Self {
+        Self {
+            max: DEFAULT_MAX_POSTPONEMENTS,
+            delay: Duration::from_secs(DEFAULT_POSTPONEMENT_DELAY_MINUTES.saturating_mul(60)),
+        }
+    }
+}
 
 lazy_static::lazy_static! {
-    pub(crate) static ref ALLOW_DOWNGRADE: IntGauge = register_int_gauge!(opts!(
-        "zincati_update_agent_updates_allow_downgrade",
-        "Whether downgrades via auto-updates logic are allowed."
-    )).unwrap();
     static ref LATEST_STATE_CHANGE: IntGauge = register_int_gauge!(opts!(
         "zincati_update_agent_latest_state_change_timestamp",
         "UTC timestamp of update-agent last state change."
     )).unwrap();
-    pub(crate) static ref UPDATES_ENABLED: IntGauge = register_int_gauge!(opts!(
-        "zincati_update_agent_updates_enabled",
-        "Whether auto-updates logic is enabled."
-    )).unwrap();
+    static ref STATE_DURATION: HistogramVec = register_histogram_vec!(
+        "zincati_update_agent_state_duration_seconds",
+        "Time spent by the update-agent in a state, observed when leaving it.",
+        &["state"],
+        vec![60.0, 300.0, 900.0, 3600.0, 14400.0, 43200.0, 86400.0, 259200.0, 604800.0]
+    ).unwrap();
+    static ref CURRENT_STATE_DURATION: IntGaugeVec = register_int_gauge_vec!(
+        "zincati_update_agent_current_state_duration_seconds",
+        "Time spent so far by the update-agent in its current state, as of the last refresh tick.",
+        &["state"]
+    ).unwrap();
     static ref POSTPONED_FINALIZATIONS: IntCounter = register_int_counter!(opts!(
         "zincati_update_agent_postponed_finalizations_total",
         "Total number of update finalization postponements due to active users."
     )).unwrap();
+    static ref POSTPONEMENTS_REMAINING: IntGauge = register_int_gauge!(opts!(
+        "zincati_update_agent_finalization_postponements_remaining",
+        "Number of finalization postponements remaining for the staged update."
+    )).unwrap();
     static ref DETECTED_ACTIVE_USERS: IntGauge = register_int_gauge!(opts!(
         "zincati_update_agent_finalization_detected_active_users",
         "Number of active users detected by the update-agent."
     )).unwrap();
+    static ref UPDATES_INHIBITED: IntGauge = register_int_gauge!(opts!(
+        "zincati_update_agent_updates_inhibited",
+        "Whether auto-updates logic is inhibited for the current boot via kernel argument."
+    )).unwrap();
+    static ref LOGIND_REBOOTS_CANCELLED: IntCounter = register_int_counter!(opts!(
+        "zincati_update_agent_logind_reboots_cancelled_total",
+        "Total number of reboots scheduled via logind and cancelled by users."
+    )).unwrap();
+    static ref REBOOT_COUNTDOWNS_CANCELLED: IntCounter = register_int_counter!(opts!(
+        "zincati_update_agent_reboot_countdowns_cancelled_total",
+        "Total number of countdowns before reboot cancelled on request."
+    )).unwrap();
+    static ref DOWNGRADES: IntCounterVec = register_int_counter_vec!(
+        "zincati_update_agent_downgrades_total",
+        "Total number of downgrades performed by the update-agent.",
+        &["phase"]
+    ).unwrap();
+    static ref REBOOT_LOCK_BLOCKED: IntCounter = register_int_counter!(opts!(
+        "zincati_update_agent_reboot_lock_blocked_total",
+        "Total number of finalizations blocked by another reboot manager holding the reboot lock."
+    )).unwrap();
+    static ref TARGET_NOT_ON_REMOTE: IntCounter = register_int_counter!(opts!(
+        "zincati_update_agent_target_not_on_remote_total",
+        "Total number of fetches delayed because the target release was not yet available on the OSTree remote."
+    )).unwrap();
+    static ref FETCH_BLOCKED: IntCounterVec = register_int_counter_vec!(
+        "zincati_update_agent_fetch_blocked_total",
+        "Total number of update fetches (downloads or stagings) deferred, by reason.",
+        &["reason"]
+    ).unwrap();
+    static ref TARGET_VERIFICATION_FAILURES: IntCounter = register_int_counter!(opts!(
+        "zincati_update_agent_target_verification_failures_total",
+        "Total number of fetches refused because the signature of the target release failed verification."
+    )).unwrap();
+    static ref SCHEDULED_FINALIZATION: IntGauge = register_int_gauge!(opts!(
+        "zincati_update_agent_scheduled_finalization_timestamp",
+        "UTC timestamp of the one-time scheduled finalization (0 if unset)."
+    )).unwrap();
+    static ref TEMPORARY_BLACKOUT_END: IntGauge = register_int_gauge!(opts!(
+        "zincati_update_agent_temporary_blackout_end_timestamp",
+        "UTC timestamp of the end of the temporary blackout (0 if unset)."
+    )).unwrap();
+    static ref BLACKOUT_BLOCKED: IntCounterVec = register_int_counter_vec!(
+        "zincati_update_agent_blackout_blocked_total",
+        "Total number of finalizations blocked by a blackout.",
+        &["reason"]
+    ).unwrap();
+    static ref UPDATE_HELD: IntGauge = register_int_gauge!(opts!(
+        "zincati_update_agent_update_held",
+        "Whether the node is held on its booted release."
+    )).unwrap();
+    static ref ADOPTED_DEPLOYMENTS: IntCounter = register_int_counter!(opts!(
+        "zincati_update_agent_adopted_deployments_total",
+        "Total number of deployments found already staged (e.g. out-of-band), and adopted by the agent."
+    )).unwrap();
+    static ref ROLLBACK_CLEANUPS: IntCounter = register_int_counter!(opts!(
+        "zincati_update_agent_rollback_cleanups_total",
+        "Total number of rollback deployments cleaned up after a healthy period on a new release."
+    )).unwrap();
+    static ref URGENCY_OVERRIDES: IntCounter = register_int_counter!(opts!(
+        "zincati_update_agent_urgency_overrides_total",
+        "Total number of finalizations overriding the update strategy for urgent releases."
+    )).unwrap();
+    static ref TARGET_INFO: IntGaugeVec = register_int_gauge_vec!(
+        "zincati_update_agent_target_info",
+        "Update target release, with its advisory severity.",
+        &["version", "severity"]
+    ).unwrap();
+    static ref PENDING_UPDATE_INFO: IntGaugeVec = register_int_gauge_vec!(
+        "zincati_pending_update_info",
+        "Release the node is moving to (available, downloaded, staged or finalized).",
+        &["version", "checksum"]
+    ).unwrap();
+    static ref ROLLOUT_WAVE_INFO: IntGaugeVec = register_int_gauge_vec!(
+        "zincati_update_agent_rollout_wave_info",
+        "Rollout wave the agent is waiting for, as assigned by Cincinnati.",
+        &["wave"]
+    ).unwrap();
+    static ref ROLLOUT_WAVE_START: IntGauge = register_int_gauge!(opts!(
+        "zincati_update_agent_rollout_wave_start_timestamp",
+        "UTC timestamp of the start of the rollout wave the agent is waiting for (0 if none)."
+    )).unwrap();
+    static ref STEADY_REPORT_FAILURES: IntCounter = register_int_counter!(opts!(
+        "zincati_update_agent_steady_report_failures_total",
+        "Total number of failed attempts at reporting steady state to the update strategy."
+    )).unwrap();
+    static ref STRATEGY_DEGRADED: IntGauge = register_int_gauge!(opts!(
+        "zincati_strategy_degraded",
+        "Whether the agent is polling for updates without having reported steady state to the update strategy."
+    )).unwrap();
+    static ref ROLLOUT_WAVE_REMAINING: IntGauge = register_int_gauge!(opts!(
+        "zincati_update_agent_rollout_wave_remaining_seconds",
+        "Seconds remaining until the rollout wave the agent is waiting for opens (0 if none)."
+    )).unwrap();
 }
 
 /// JSON output from `loginctl list-sessions --output=json`.
@@ -98,18 +301,27 @@ enum UpdateAgentState {
     ReportedSteady,
     /// No further updates available yet.
     NoNewUpdate,
-    /// Update available from Cincinnati.
+    /// Update available from the update source, but the rollout wave
+    /// assigned to this node has not opened yet.
+    WaitingForWave(Release),
+    /// Update available from the update source.
     ///
     /// The integer counter keeps track of how many times in a row this
     /// update was attempted, but deploying failed. At `MAX_DEPLOY_ATTEMPTS`
     /// a state transition is triggered to abandon the target update.
     UpdateAvailable((Release, u8)),
+    /// Update downloaded by rpm-ostree, but not staged yet.
+    ///
+    /// This state is only used if download windows or fetch-only window mode
+    /// are configured. The integer counter carries over failed attempts from
+    /// `UpdateAvailable`.
+    UpdateDownloaded((Release, u8)),
     /// Update staged by rpm-ostree.
     ///
     /// The integer counter keeps track of how many more finalization
     /// postponements are permitted. If the counter reaches zero, the
     /// finalization will proceed, disregarding any users logged in.
-    /// The counter is reset to `MAX_FINALIZE_POSTPONEMENTS` if a
+    /// The counter is reset to the maximum number of postponements if a
     /// finalization attempt failed due to update strategy constraints.
     UpdateStaged((Release, u8)),
     /// Update finalized by rpm-ostree.
@@ -122,19 +334,99 @@ impl Default for UpdateAgentState {
     fn default() -> Self {
         let start_state = UpdateAgentState::StartState;
         LATEST_STATE_CHANGE.set(chrono::Utc::now().timestamp());
+        start_state.record_duration();
         start_state
     }
 }
 
 impl UpdateAgentState {
+    /// Return the name of this state.
+    fn name(&self) -> &'static str {
+        match self {
+            UpdateAgentState::StartState => "StartState",
+            UpdateAgentState::Initialized => "Initialized",
+            UpdateAgentState::ReportedSteady => "ReportedSteady",
+            UpdateAgentState::NoNewUpdate => "NoNewUpdate",
+            UpdateAgentState::WaitingForWave(_) => "WaitingForWave",
+            UpdateAgentState::UpdateAvailable(_) => "UpdateAvailable",
+            UpdateAgentState::UpdateDownloaded(_) => "UpdateDownloaded",
+            UpdateAgentState::UpdateStaged(_) => "UpdateStaged",
+            UpdateAgentState::UpdateFinalized(_) => "UpdateFinalized",
+            UpdateAgentState::EndState => "EndState",
+        }
+    }
+
+    /// Return the target release, if any.
+    fn target(&self) -> Option<&Release> {
+        match self {
+            UpdateAgentState::WaitingForWave(r)
+            | UpdateAgentState::UpdateAvailable((r, _))
+            | UpdateAgentState::UpdateDownloaded((r, _))
+            | UpdateAgentState::UpdateStaged((r, _))
+            | UpdateAgentState::UpdateFinalized(r) => Some(r),
+            _ => None,
+        }
+    }
+
+    /// Return the time spent in this state so far (in seconds).
+    fn elapsed_secs(&self) -> i64 {
+        let now = chrono::Utc::now().timestamp();
+        now.saturating_sub(LATEST_STATE_CHANGE.get()).max(0)
+    }
+
+    /// Update the metric tracking time spent in the current state.
+    fn record_duration(&self) {
+        CURRENT_STATE_DURATION.reset();
+        CURRENT_STATE_DURATION
+            .with_label_values(&[self.name()])
+            .set(self.elapsed_secs());
+    }
+
     /// Progress the machine to a new state.
     fn transition_to(&mut self, state: Self) {
         use std::mem::discriminant;
-        if discriminant(self) != discriminant(&state) {
+        let state_changed = discriminant(self) != discriminant(&state);
+        if state_changed {
+            STATE_DURATION
+                .with_label_values(&[self.name()])
+                .observe(self.elapsed_secs() as f64);
             LATEST_STATE_CHANGE.set(chrono::Utc::now().timestamp());
         }
+        let postponements_remaining = match &state {
+            UpdateAgentState::UpdateStaged((_, p)) => i64::from(*p),
+            _ => 0,
+        };
+        POSTPONEMENTS_REMAINING.set(postponements_remaining);
+        TARGET_INFO.reset();
+        PENDING_UPDATE_INFO.reset();
+        if let Some(release) = state.target() {
+            TARGET_INFO
+                .with_label_values(&[&release.version, severity(release).unwrap_or("none")])
+                .set(1);
+            PENDING_UPDATE_INFO
+                .with_label_values(&[&release.version, &release.checksum])
+                .set(1);
+        }
+        ROLLOUT_WAVE_INFO.reset();
+        match &state {
+            UpdateAgentState::WaitingForWave(Release {
+                wave: Some(wave), ..
+            }) => {
+                let remaining = wave.start - chrono::Utc::now();
+                ROLLOUT_WAVE_INFO.with_label_values(&[&wave.name]).set(1);
+                ROLLOUT_WAVE_START.set(wave.start.timestamp());
+                ROLLOUT_WAVE_REMAINING.set(remaining.num_seconds().max(0));
+            }
+            _ => {
+                ROLLOUT_WAVE_START.set(0);
+                ROLLOUT_WAVE_REMAINING.set(0);
+            }
+        };
 
         *self = state;
+        if state_changed {
+            self.record_duration();
+        }
     }
 
     /// Transition to the Initialized state.
@@ -170,7 +462,12 @@ impl UpdateAgentState {
         let target = UpdateAgentState::NoNewUpdate;
         // Allowed starting states.
         assert!(
-            *self == UpdateAgentState::ReportedSteady || *self == UpdateAgentState::NoNewUpdate,
+            matches!(
+                self,
+                UpdateAgentState::ReportedSteady
+                    | UpdateAgentState::NoNewUpdate
+                    | UpdateAgentState::WaitingForWave(_)
+            ),
             "transition not allowed: {:?} to {:?}",
             self,
             target
@@ -179,12 +476,36 @@ impl UpdateAgentState {
         self.transition_to(UpdateAgentState::NoNewUpdate);
     }
 
+    /// Transition to the WaitingForWave state with a new release.
+    fn waiting_for_wave(&mut self, update: Release) {
+        let target = UpdateAgentState::WaitingForWave(update);
+        // Allowed starting states.
+        assert!(
+            matches!(
+                self,
+                UpdateAgentState::ReportedSteady
+                    | UpdateAgentState::NoNewUpdate
+                    | UpdateAgentState::WaitingForWave(_)
+            ),
+            "transition not allowed: {:?} to {:?}",
+            self,
+            target
+        );
+
+        self.transition_to(target);
+    }
+
     /// Transition to the UpdateAvailable state with a new release.
     fn update_available(&mut self, update: Release) {
         let target = UpdateAgentState::UpdateAvailable((update, 0));
         // Allowed starting states.
         assert!(
-            *self == UpdateAgentState::ReportedSteady || *self == UpdateAgentState::NoNewUpdate,
+            matches!(
+                self,
+                UpdateAgentState::ReportedSteady
+                    | UpdateAgentState::NoNewUpdate
+                    | UpdateAgentState::WaitingForWave(_)
+            ),
             "transition not allowed: {:?} to {:?}",
             self,
             target
@@ -193,7 +514,35 @@ impl UpdateAgentState {
         self.transition_to(target);
     }
 
-    /// Record a failed deploy attempt in UpdateAvailable state.
+    /// Transition to the UpdateAvailable state with a new target, replacing
+    /// the one which failed to deploy.
+    ///
+    /// The new target starts over with no failed deploy attempts.
+    fn update_retargeted(&mut self, update: Release) {
+        let target = UpdateAgentState::UpdateAvailable((update, 0));
+        // Allowed starting states.
+        assert!(
+            matches!(self, UpdateAgentState::UpdateAvailable(_)),
+            "transition not allowed: {:?} to {:?}",
+            self,
+            target
+        );
+
+        self.transition_to(target);
+    }
+
+    /// Transition to the UpdateDownloaded state.
+    fn update_downloaded(&mut self) {
+        let target = match self.clone() {
+            UpdateAgentState::UpdateAvailable((r, a)) => UpdateAgentState::UpdateDownloaded((r, a)),
+            _ => unreachable!("transition not allowed: update_downloaded on {:?}", self),
+        };
+
+        self.transition_to(target);
+    }
+
+    /// Record a failed download or deploy attempt in UpdateAvailable or
+    /// UpdateDownloaded state.
     ///
     /// This returns a tuple containing a bool representing whether the target
     /// update was abandoned and the total number of failed deployment attempts
@@ -201,6 +550,7 @@ impl UpdateAgentState {
     fn record_failed_deploy(&mut self) -> (bool, u8) {
         let (release, attempts) = match self.clone() {
             UpdateAgentState::UpdateAvailable((r, a)) => (r, a),
+            UpdateAgentState::UpdateDownloaded((r, a)) => (r, a),
             _ => unreachable!("transition not allowed: record_failed_deploy on {:?}", self,),
         };
         let fail_count = attempts.saturating_add(1);
@@ -230,20 +580,24 @@ impl UpdateAgentState {
     }
 
     /// Transition to the UpdateStaged state, setting the number of postponements
-    /// remaining to `MAX_FINALIZE_POSTPONEMENTS`.
-    fn update_staged(&mut self, update: Release) {
-        let target = UpdateAgentState::UpdateStaged((update, MAX_FINALIZE_POSTPONEMENTS));
+    /// remaining to `max_postponements`.
+    fn update_staged(&mut self, update: Release, max_postponements: u8) {
+        let target = UpdateAgentState::UpdateStaged((update, max_postponements));
 
         self.transition_to(target);
     }
 
     /// Determine whether to allow finalization based off of current state.
     /// Returns a boolean indicating whether a finalization is permitted.
-    fn usersessions_can_finalize(&mut self) -> bool {
+    fn usersessions_can_finalize(
+        &mut self,
+        budget: &PostponementBudget,
+        messages: &MessageTemplates,
+    ) -> bool {
         match get_interactive_user_sessions() {
             Ok(interactive_sessions) => {
                 DETECTED_ACTIVE_USERS.set(interactive_sessions.len().try_into().unwrap());
-                self.handle_interactive_sessions(&interactive_sessions)
+                self.handle_interactive_sessions(&interactive_sessions, budget, messages)
             }
             Err(e) => {
                 // If we failed to check for interactive sessions, just allow
@@ -259,7 +613,12 @@ impl UpdateAgentState {
     /// state's remaining postponements (possibly broadcasting warning messages to active sessions).
     ///
     /// Returns a boolean indicating whether finalization is permitted.
-    fn handle_interactive_sessions(&mut self, interactive_sessions: &[InteractiveSession]) -> bool {
+    fn handle_interactive_sessions(
+        &mut self,
+        interactive_sessions: &[InteractiveSession],
+        budget: &PostponementBudget,
+        messages: &MessageTemplates,
+    ) -> bool {
         if interactive_sessions.is_empty() {
             return true;
         }
@@ -276,15 +635,24 @@ impl UpdateAgentState {
             return true;
         }
 
-        if postponements_remaining == MAX_FINALIZE_POSTPONEMENTS {
-            let max_reboot_delay_secs =
-                DEFAULT_POSTPONEMENT_TIME_SECS.saturating_mul(MAX_FINALIZE_POSTPONEMENTS as u64);
-            let warning_msg = format_reboot_warning(max_reboot_delay_secs, &release.version);
+        if postponements_remaining == budget.max {
+            let max_reboot_delay_secs = budget.delay.as_secs().saturating_mul(budget.max as u64);
+            let warning_msg = messages.reboot_warning(&release.version, max_reboot_delay_secs);
             broadcast(&warning_msg, interactive_sessions);
         } else if postponements_remaining == 1 {
-            let warning_msg =
-                format_reboot_warning(DEFAULT_POSTPONEMENT_TIME_SECS, &release.version);
+            let warning_msg = messages.reboot_warning(&release.version, budget.delay.as_secs());
             broadcast(&warning_msg, interactive_sessions);
+        } else {
+            let reboot_delay_secs = budget
+                .delay
+                .as_secs()
+                .saturating_mul(postponements_remaining as u64);
+            let notice_msg = messages.postponement_notice(
+                &release.version,
+                reboot_delay_secs,
+                postponements_remaining.saturating_sub(1),
+            );
+            broadcast(&notice_msg, interactive_sessions);
         }
 
         false
@@ -329,16 +697,38 @@ impl UpdateAgentState {
 
     /// Return the amount of delay between refreshes for this state, and whether
     /// jitter should be added.
-    fn get_refresh_delay(&self, steady_interval: Duration) -> (Duration, bool) {
+    fn get_refresh_delay(
+        &self,
+        steady_interval: Duration,
+        deploy_retry_max_delay: Duration,
+        budget: &PostponementBudget,
+    ) -> (Duration, bool) {
         match self {
             UpdateAgentState::ReportedSteady | UpdateAgentState::NoNewUpdate => {
                 (steady_interval, true)
             }
+            UpdateAgentState::WaitingForWave(release) => {
+                // Check again once the wave opens, if sooner than usual.
+                let remaining = release
+                    .wave
+                    .as_ref()
+                    .and_then(|w| (w.start - chrono::Utc::now()).to_std().ok());
+                match remaining {
+                    Some(r) if r < steady_interval => (r, true),
+                    _ => (steady_interval, true),
+                }
+            }
+            UpdateAgentState::UpdateAvailable((_, attempts))
+            | UpdateAgentState::UpdateDownloaded((_, attempts))
+                if *attempts > 0 =>
+            {
+                (deploy_retry_delay(*attempts, deploy_retry_max_delay), true)
+            }
             UpdateAgentState::UpdateStaged((_, postponements)) => {
-                // If postponements is less than `MAX_FINALIZE_POSTPONEMENTS`, that means the current tick
-                // led to a postponment, and so we should add a delay of `DEFAULT_POSTPONEMENT_TIME_SECS`.
-                if *postponements < MAX_FINALIZE_POSTPONEMENTS {
-                    (Duration::from_secs(DEFAULT_POSTPONEMENT_TIME_SECS), false)
+                // If postponements is less than the maximum, that means the current tick
+                // led to a postponment, and so we should add a delay of `budget.delay`.
+                if *postponements < budget.max {
+                    (budget.delay, false)
                 } else {
                     (Duration::from_secs(DEFAULT_REFRESH_PERIOD_SECS), true)
                 }
@@ -348,44 +738,1007 @@ impl UpdateAgentState {
     }
 }
 
+/// Return the delay before retrying after `attempts` failed deploy attempts.
+///
+/// The delay doubles after each failure, starting from the default refresh
+/// period and capped at `max_delay`, so that a persistently failing (e.g.
+/// timing out) rpm-ostree does not get hammered.
+fn deploy_retry_delay(attempts: u8, max_delay: Duration) -> Duration {
+    let exponent = u32::from(attempts.saturating_sub(1)).min(16);
+    let secs = DEFAULT_REFRESH_PERIOD_SECS.saturating_mul(1 << exponent);
+    Duration::from_secs(secs).min(max_delay)
+}
+
+/// Snapshot of the whole agent status.
+///
+/// This is gathered at once, so that clients cannot observe torn state.
+/// Unset values are reported as empty strings or zero timestamps.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, Type)]
+pub struct AgentStatus {
+    /// Name of the current state-machine state.
+    pub state: String,
+    /// Booted release version.
+    pub booted_version: String,
+    /// Booted release checksum.
+    pub booted_checksum: String,
+    /// Target (available, downloaded, staged or finalized) release version.
+    pub target_version: String,
+    /// Target release checksum.
+    pub target_checksum: String,
+    /// Update source label.
+    pub update_source: String,
+    /// Update strategy label.
+    pub strategy: String,
+    /// Outcome of the last finalization check (e.g. `allowed`, `strategy`).
+    pub last_finalize_verdict: String,
+    /// Reasons for auto-updates not running (e.g. `updates-disabled`).
+    pub inhibitors: Vec<String>,
+    /// UTC timestamp of the last refresh tick.
+    pub last_refresh_time: i64,
+    /// UTC timestamp of the last state change.
+    pub state_change_time: i64,
+    /// UTC timestamp of the one-time scheduled finalization.
+    pub scheduled_finalize_time: i64,
+    /// Estimated UTC timestamp of the reboot into the staged update.
+    pub estimated_reboot_time: i64,
+    /// UTC timestamp of the end of the temporary blackout.
+    pub blackout_end_time: i64,
+    /// Reason for the temporary blackout.
+    pub blackout_reason: String,
+    /// Refresh interval in steady state, in seconds.
+    pub steady_interval_secs: u64,
+    /// Finalization postponements remaining for the staged update.
+    pub postponements_remaining: u8,
+    /// Whether the staged (or finalized) update is a downgrade.
+    pub downgrade: bool,
+    /// Last error from rpm-ostree operations.
+    pub last_error: String,
+    /// UTC timestamp of the last error.
+    pub last_error_time: i64,
+    /// Advisory severity of the target release.
+    pub target_severity: String,
+    /// Release-notes URL of the target release.
+    pub target_release_notes: String,
+    /// Errata IDs of the target release.
+    pub target_errata: Vec<String>,
+    /// CVEs fixed by the staged update, from its commit metadata.
+    pub fixed_cves: Vec<String>,
+    /// UTC timestamp of the next refresh tick.
+    pub next_refresh_time: i64,
+    /// Why the next refresh tick was scheduled at that time.
+    pub next_refresh_reason: String,
+}
+
+/// Update target found by the last check, with its advisory metadata.
+///
+/// Unset values are reported as empty strings, an empty version meaning
+/// that no update is available.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, Type)]
+pub struct UpdateCheck {
+    /// Name of the current state-machine state.
+    pub state: String,
+    /// Target release version.
+    pub version: String,
+    /// Target release checksum.
+    pub checksum: String,
+    /// Advisory severity (e.g. `critical`).
+    pub severity: String,
+    /// Release-notes URL.
+    pub release_notes: String,
+    /// Errata IDs.
+    pub errata: Vec<String>,
+    /// UTC timestamp of the last refresh tick.
+    pub last_refresh_time: i64,
+}
+
+impl From<AgentStatus> for UpdateCheck {
+    fn from(status: AgentStatus) -> Self {
+        Self {
+            state: status.state,
+            version: status.target_version,
+            checksum: status.target_checksum,
+            severity: status.target_severity,
+            release_notes: status.target_release_notes,
+            errata: status.target_errata,
+            last_refresh_time: status.last_refresh_time,
+        }
+    }
+}
+
+/// Return the advisory severity of a release, if any.
+fn severity(release: &Release) -> Option<&str> {
+    release
+        .advisory
+        .as_ref()
+        .and_then(|a| a.severity.as_deref())
+}
+
 /// Update agent.
 #[derive(Debug)]
 pub(crate) struct UpdateAgent {
     /// Whether to allow automatic downgrades.
     allow_downgrade: bool,
-    /// Cincinnati service.
-    cincinnati: Cincinnati,
+    /// Barriers against downgrading too far back, if any.
+    downgrade_barrier: Option<DowngradeBarrier>,
+    /// Update source (e.g. Cincinnati service).
+    source: Box<dyn UpdateSource>,
     /// Whether to enable auto-updates logic.
     enabled: bool,
+    /// Whether to block updates when packages are layered on the booted deployment.
+    block_on_layered: bool,
+    /// Local changes to the booted deployment, if known.
+    booted_layering: Option<Layering>,
+    /// Healthy time on a new release before cleaning up the rollback
+    /// deployment, if enabled.
+    cleanup_rollback_after: Option<Duration>,
+    /// Healthy time of the booted release, if recorded.
+    rollback_cleanup: Option<RollbackCleanup>,
+    /// Whether a cleanup of the rollback deployment is in progress.
+    rollback_cleanup_running: bool,
+    /// Connectivity gate for finalization, if any.
+    connectivity_gate: Option<ConnectivityGate>,
+    /// Maximum delay before retrying a failed deploy.
+    deploy_retry_max_delay: Duration,
+    /// Free disk space checks before staging, if any.
+    disk_space: Option<DiskSpaceCheck>,
+    /// Whether the cleanup command already ran since space went low.
+    disk_space_cleaned: bool,
+    /// Windows for downloading updates, if any.
+    download_schedule: Option<DownloadSchedule>,
+    /// Whether to defer staging until close to a finalization window.
+    fetch_only_window: bool,
+    /// Whether to defer fetching updates on metered connections.
+    respect_metered: bool,
+    /// Health checks before finalization, if any.
+    health_checks: Option<HealthChecks>,
+    /// Services stopped before finalization, if any.
+    quiesce: Option<ServiceQuiesce>,
+    /// Lead time for reboots scheduled via logind, if enabled.
+    logind_reboot_lead: Option<Duration>,
+    /// Countdown before rebooting into a finalized update, if enabled.
+    reboot_countdown: Option<Duration>,
+    /// Pending reboot into the staged update, if any.
+    pending_reboot: Option<PendingReboot>,
+    /// Whether auto-updates logic is inhibited for the current boot.
+    inhibited: bool,
     /// Agent identity.
     identity: Identity,
+    /// Templates for user-facing messages.
+    messages: MessageTemplates,
+    /// Budget for postponing finalization due to active user sessions.
+    postponements: PostponementBudget,
+    /// Reporting of update outcomes, if configured.
+    outcome_report: Option<OutcomeReporter>,
+    /// Hooks to run after booting into a finalized update, if any.
+    post_boot_hooks: Option<PostBootHooks>,
     /// Refresh interval in steady state.
     steady_interval: Duration,
-    /// rpm-ostree client actor.
-    rpm_ostree_actor: Addr<RpmOstreeClient>,
+    /// Consecutive failures at reporting steady state, before polling in
+    /// degraded mode (if any limit).
+    steady_report_max_failures: Option<NonZeroU32>,
+    /// Consecutive failures at reporting steady state so far.
+    steady_report_failures: u32,
+    /// Whether polling for updates without having reported steady state.
+    strategy_degraded: bool,
+    /// Queue for rpm-ostree client operations.
+    rpm_ostree_actor: Addr<OperationQueue>,
     /// Update strategy.
     strategy: UpdateStrategy,
+    /// Switching to other update streams, if configured.
+    stream_switch: Option<StreamSwitch>,
+    /// Override of the update strategy for urgent releases, if enabled.
+    urgency: Option<UrgencyOverride>,
+    /// Urgent release (checksum) ready to be finalized, and since when.
+    urgent_since: Option<(String, DateTime<Utc>)>,
     /// Current status for agent state machine.
     state: UpdateAgentState,
     /// Timestamp of last state transition.
     state_changed: DateTime<Utc>,
+    /// Expected time of the next refresh tick, if scheduled.
+    next_refresh: Option<DateTime<Utc>>,
+    /// Why the next refresh tick was scheduled at that time.
+    next_refresh_reason: String,
+    /// Earliest time of the first update check, if delayed after start.
+    first_check_at: Option<DateTime<Utc>>,
+    /// Handle of the next refresh tick, if scheduled later.
+    refresh_tick: Option<actix::SpawnHandle>,
+    /// One-time scheduled finalization, if any.
+    scheduled_finalize: Option<ScheduledFinalize>,
+    /// Temporary blackout for reboots, if any.
+    temporary_blackout: Option<TemporaryBlackout>,
+    /// Hold on the booted release, if any.
+    hold: Option<UpdateHold>,
+    /// Configured blackout periods, if any.
+    blackout_periods: Option<BlackoutPeriods>,
+    /// Outcome of the last finalization check.
+    last_finalize_verdict: &'static str,
+    /// Last error from rpm-ostree operations, with its timestamp.
+    last_error: Option<(DateTime<Utc>, String)>,
+    /// Whether the staged update is a downgrade.
+    staged_downgrade: bool,
+    /// CVEs fixed by the staged update, from its commit metadata.
+    staged_fixed_cves: Vec<String>,
+    /// Deployment (checksum) staged outside of the agent and adopted by it, if any.
+    adopted_staged: Option<String>,
+    /// Listeners subscribed to agent events.
+    events: EventListeners,
+    /// Whether to require an explicit approval before finalization.
+    require_reboot_approval: bool,
+    /// Reboot approval, if any.
+    reboot_approval: Option<RebootApproval>,
+    /// Lock shared with other reboot managers, if any.
+    reboot_lock: Option<RebootLock>,
+    /// Webhook for agent events, if any.
+    webhook: Option<Webhook>,
+    /// Whether queued webhook events are being delivered.
+    webhook_flushing: bool,
+    /// OSTree remote to check for the target release before fetching it, if any.
+    verify_remote: Option<String>,
+    /// Whether to verify the signature of the target release on `verify_remote`.
+    verify_signature: bool,
+    /// Effective configuration, as JSON.
+    effective_config: String,
+    /// Whether finalization of the staged update was attempted.
+    finalize_attempted: bool,
+    /// Whether the agent is shutting down.
+    shutting_down: bool,
 }
 
 impl UpdateAgent {
     /// Build an update agent with the given config.
-    pub(crate) fn with_config(cfg: Settings, rpm_ostree_addr: Addr<RpmOstreeClient>) -> Self {
+    pub(crate) fn with_config(cfg: Settings, rpm_ostree_addr: Addr<OperationQueue>) -> Self {
         let steady_secs = cfg.steady_interval_secs.get();
+        let scheduled_finalize =
+            ScheduledFinalize::load(SCHEDULED_FINALIZE_PATH).unwrap_or_else(|e| {
+                log::error!("{:#}", e);
+                None
+            });
+        SCHEDULED_FINALIZATION.set(
+            scheduled_finalize
+                .as_ref()
+                .map(ScheduledFinalize::timestamp)
+                .unwrap_or(0),
+        );
+        let temporary_blackout = TemporaryBlackout::load(TEMPORARY_BLACKOUT_PATH)
+            .unwrap_or_else(|e| {
+                log::error!("{:#}", e);
+                None
+            })
+            .filter(|b| !b.is_expired(&chrono::Utc::now()));
+        TEMPORARY_BLACKOUT_END.set(
+            temporary_blackout
+                .as_ref()
+                .map(|b| b.end.timestamp())
+                .unwrap_or(0),
+        );
+        let hold = UpdateHold::load(UPDATE_HOLD_PATH).unwrap_or_else(|e| {
+            log::error!("{:#}", e);
+            None
+        });
+        UPDATE_HELD.set(i64::from(hold.is_some()));
+        let inhibited = match fs::read_to_string(KERNEL_CMDLINE_PATH) {
+            Ok(cmdline) => cmdline_inhibits_updates(&cmdline),
+            Err(e) => {
+                log::error!("failed to read '{}': {}", KERNEL_CMDLINE_PATH, e);
+                false
+            }
+        };
+        UPDATES_INHIBITED.set(i64::from(inhibited));
+        let first_check_at = cfg.first_check_splay.map(|splay| {
+            use rand::Rng;
+            let secs = rand::thread_rng().gen_range(0..=splay.as_secs());
+            chrono::Utc::now() + chrono::Duration::seconds(secs.try_into().unwrap_or(i64::MAX))
+        });
+        let reboot_approval = if cfg.require_reboot_approval {
+            RebootApproval::load(REBOOT_APPROVAL_PATH).unwrap_or_else(|e| {
+                log::error!("{:#}", e);
+                None
+            })
+        } else {
+            None
+        };
         Self {
             allow_downgrade: cfg.allow_downgrade,
-            cincinnati: cfg.cincinnati,
+            downgrade_barrier: cfg.downgrade_barrier,
             enabled: cfg.enabled,
+            block_on_layered: cfg.block_on_layered,
+            booted_layering: None,
+            cleanup_rollback_after: cfg.cleanup_rollback_after,
+            rollback_cleanup: None,
+            rollback_cleanup_running: false,
+            connectivity_gate: cfg.connectivity_gate,
+            deploy_retry_max_delay: cfg.deploy_retry_max_delay,
+            disk_space: cfg.disk_space,
+            disk_space_cleaned: false,
+            download_schedule: cfg.download_schedule,
+            fetch_only_window: cfg.fetch_only_window,
+            respect_metered: cfg.network.respect_metered(),
+            health_checks: cfg.health_checks,
+            quiesce: cfg.quiesce,
+            logind_reboot_lead: cfg.logind_reboot_lead,
+            reboot_countdown: cfg.reboot_countdown,
+            pending_reboot: None,
+            inhibited,
             identity: cfg.identity,
+            messages: cfg.messages,
+            postponements: PostponementBudget {
+                max: cfg.max_postponements,
+                delay: cfg.postponement_delay,
+            },
+            outcome_report: cfg.outcome_report,
+            post_boot_hooks: cfg.post_boot_hooks,
             rpm_ostree_actor: rpm_ostree_addr,
+            source: cfg.source,
             steady_interval: Duration::from_secs(steady_secs),
+            steady_report_max_failures: cfg.steady_report_max_failures,
+            steady_report_failures: 0,
+            strategy_degraded: false,
             state: UpdateAgentState::default(),
             strategy: cfg.strategy,
+            stream_switch: cfg.stream_switch,
+            urgency: cfg.urgency,
+            urgent_since: None,
             state_changed: chrono::Utc::now(),
+            next_refresh: None,
+            next_refresh_reason: String::new(),
+            first_check_at,
+            refresh_tick: None,
+            scheduled_finalize,
+            temporary_blackout,
+            hold,
+            blackout_periods: cfg.blackout,
+            last_finalize_verdict: "",
+            last_error: None,
+            staged_downgrade: false,
+            staged_fixed_cves: vec![],
+            adopted_staged: None,
+            events: EventListeners::default(),
+            require_reboot_approval: cfg.require_reboot_approval,
+            reboot_approval,
+            reboot_lock: cfg.reboot_lock_path.map(RebootLock::new),
+            webhook: cfg.webhook,
+            webhook_flushing: false,
+            verify_remote: cfg.verify_remote,
+            verify_signature: cfg.verify_signature,
+            effective_config: cfg.effective_config,
+            finalize_attempted: false,
+            shutting_down: false,
+        }
+    }
+
+    /// Log and record an error from rpm-ostree operations.
+    fn record_error(&mut self, context: &str, err: &anyhow::Error) {
+        let msg = format!("{}: {:#}", context, err);
+        log::error!("{}", msg);
+        let target = self.state.target().cloned();
+        self.record_history(HistoryEvent::Failure, target.as_ref(), &msg);
+        self.last_error = Some((chrono::Utc::now(), msg));
+    }
+
+    /// Append an entry to the update history, logging failures.
+    ///
+    /// The entry is also queued for the webhook, if any.
+    fn record_history(&self, event: HistoryEvent, release: Option<&Release>, detail: &str) {
+        if let Some(webhook) = &self.webhook {
+            let version = release.map(|r| r.version.as_str());
+            webhook.queue(Event::new(event.as_str(), self.state.name(), version).detail(detail));
+        }
+        let entry = HistoryEntry::new(event, release, detail);
+        if let Err(e) = entry.append(HISTORY_PATH) {
+            log::warn!("{:#}", e);
+        }
+    }
+
+    /// Notify listeners of an agent event.
+    ///
+    /// The event is also queued for the webhook, if any.
+    fn emit_event(&self, event: AgentEvent) {
+        if let Some(webhook) = &self.webhook {
+            let hook_event = match &event {
+                AgentEvent::UpdateStaged { version } => {
+                    Event::new("update-staged", self.state.name(), Some(version))
+                }
+                AgentEvent::RebootScheduled { timestamp } => {
+                    let version = self.state.target().map(|r| r.version.as_str());
+                    Event::new("reboot-scheduled", self.state.name(), version)
+                        .detail(&timestamp.to_string())
+                }
+            };
+            webhook.queue(hook_event);
+        }
+        self.events.emit(event);
+    }
+
+    /// Record the booted release as healthy, for cleaning up its rollback
+    /// deployment later on (if enabled).
+    ///
+    /// The persisted record is kept as long as the same release is booted.
+    fn record_healthy_release(&mut self) {
+        if self.cleanup_rollback_after.is_none() {
+            return;
+        }
+        let persisted = RollbackCleanup::load(ROLLBACK_CLEANUP_PATH).unwrap_or_else(|e| {
+            log::error!("{:#}", e);
+            None
+        });
+        let (record, new) =
+            RollbackCleanup::for_booted(persisted, &self.identity.current_os, chrono::Utc::now());
+        if new {
+            if let Err(e) = record.persist(ROLLBACK_CLEANUP_PATH) {
+                log::error!("{:#}", e);
+            }
         }
+        self.rollback_cleanup = Some(record);
+    }
+
+    /// Return whether an update target passes the downgrade barrier, if any.
+    ///
+    /// Barriers only apply when downgrades are allowed, as update targets
+    /// are otherwise never older than the booted release.
+    fn passes_downgrade_barrier(&self, release: &Release) -> bool {
+        let barrier = match &self.downgrade_barrier {
+            Some(b) if self.allow_downgrade => b,
+            _ => return true,
+        };
+        match barrier.check_version(release) {
+            Ok(_) => true,
+            Err(e) => {
+                log::error!("update target rejected by downgrade barrier: {:#}", e);
+                false
+            }
+        }
+    }
+
+    /// Log and record a downgrade, as an auditable journal entry.
+    fn record_downgrade(&mut self, phase: &'static str, release: &Release) {
+        DOWNGRADES.with_label_values(&[phase]).inc();
+        self.staged_downgrade = true;
+
+        let msg = format!(
+            "downgrade {} from '{}' to '{}'",
+            phase, self.identity.current_os.version, release.version
+        );
+        log::warn!("{}", msg);
+        let fields = vec![
+            ("MESSAGE_ID", DOWNGRADE_MESSAGE_ID.to_string()),
+            ("ZINCATI_DOWNGRADE_PHASE", phase.to_string()),
+            (
+                "ZINCATI_FROM_VERSION",
+                self.identity.current_os.version.clone(),
+            ),
+            ("ZINCATI_TO_VERSION", release.version.clone()),
+            ("ZINCATI_TO_CHECKSUM", release.checksum.clone()),
+        ];
+        let res = libsystemd::logging::journal_send(
+            libsystemd::logging::Priority::Notice,
+            &msg,
+            fields.into_iter(),
+        );
+        if let Err(e) = res {
+            log::warn!("failed to record downgrade to journal: {}", e);
+        }
+    }
+
+    /// Log a state transition, with structured journal fields.
+    fn log_state_change(&self) {
+        let state = self.state.name();
+        let release = self
+            .state
+            .target()
+            .map(|r| r.version.as_str())
+            .unwrap_or_default();
+        let msg = match release {
+            "" => format!("agent state changed to {}", state),
+            version => format!("agent state changed to {} ({})", state, version),
+        };
+        let fields = [
+            ("ZINCATI_STATE", state),
+            ("ZINCATI_RELEASE", release),
+            ("ZINCATI_STRATEGY", self.strategy.configuration_label()),
+        ];
+        logging::info_with_fields(&msg, &fields);
+    }
+
+    /// Return a snapshot of the whole agent status.
+    fn status(&self, last_refresh_time: i64) -> AgentStatus {
+        let mut inhibitors = vec![];
+        if !self.enabled {
+            inhibitors.push("updates-disabled".to_string());
+        }
+        if self.inhibited {
+            inhibitors.push("kernel-argument".to_string());
+        }
+        if self.hold.is_some() {
+            inhibitors.push("hold".to_string());
+        }
+        let target = self.state.target();
+        let advisory = target.and_then(|r| r.advisory.clone()).unwrap_or_default();
+        let (last_error_time, last_error) = match &self.last_error {
+            Some((time, msg)) => (time.timestamp(), msg.clone()),
+            None => (0, String::new()),
+        };
+        let (blackout_end_time, blackout_reason) = match &self.temporary_blackout {
+            Some(b) => (b.end.timestamp(), b.reason.clone()),
+            None => (0, String::new()),
+        };
+
+        AgentStatus {
+            state: self.state.name().to_string(),
+            booted_version: self.identity.current_os.version.clone(),
+            booted_checksum: self.identity.current_os.checksum.clone(),
+            target_version: target.map(|r| r.version.clone()).unwrap_or_default(),
+            target_checksum: target.map(|r| r.checksum.clone()).unwrap_or_default(),
+            update_source: self.source.label().to_string(),
+            strategy: self.strategy.configuration_label().to_string(),
+            last_finalize_verdict: self.last_finalize_verdict.to_string(),
+            inhibitors,
+            last_refresh_time,
+            state_change_time: self.state_changed.timestamp(),
+            scheduled_finalize_time: self
+                .scheduled_finalize
+                .as_ref()
+                .map(ScheduledFinalize::timestamp)
+                .unwrap_or(0),
+            estimated_reboot_time: self
+                .estimated_reboot(chrono::Utc::now())
+                .map(|t| t.timestamp())
+                .unwrap_or(0),
+            blackout_end_time,
+            blackout_reason,
+            steady_interval_secs: self.steady_interval.as_secs(),
+            postponements_remaining: match &self.state {
+                UpdateAgentState::UpdateStaged((_, p)) => *p,
+                _ => 0,
+            },
+            downgrade: self.staged_downgrade && target.is_some(),
+            last_error,
+            last_error_time,
+            target_severity: advisory.severity.unwrap_or_default(),
+            target_release_notes: advisory.release_notes.unwrap_or_default(),
+            target_errata: advisory.errata,
+            fixed_cves: match target {
+                Some(_) => self.staged_fixed_cves.clone(),
+                None => vec![],
+            },
+            next_refresh_time: self.next_refresh.map(|t| t.timestamp()).unwrap_or(0),
+            next_refresh_reason: self.next_refresh_reason.clone(),
+        }
+    }
+
+    /// Reload configuration from freshly assembled settings.
+    ///
+    /// Invalid settings are rejected as a whole, keeping the current ones.
+    fn reload_settings(&mut self, assembled: Result<Settings>) -> Result<()> {
+        let cfg = assembled.context("failed to reload configuration")?;
+        self.apply_settings(cfg);
+        Ok(())
+    }
+
+    /// Apply settings from a configuration reload.
+    ///
+    /// Only settings which can change without restarting the state machine
+    /// are applied (e.g. a staged update is kept). Settings tied to the node
+    /// identity or to long-lived clients require an agent restart.
+    fn apply_settings(&mut self, cfg: Settings) {
+        cfg.refresh_metrics();
+        rpm_ostree::check_update_policy(cfg.reconcile_rpm_ostree_policy);
+        if cfg.allow_downgrade && !self.allow_downgrade {
+            log::warn!("client configuration allows (possibly vulnerable) downgrades via auto-updates logic");
+        }
+        self.allow_downgrade = cfg.allow_downgrade;
+        self.downgrade_barrier = cfg.downgrade_barrier;
+        self.blackout_periods = cfg.blackout;
+        self.cleanup_rollback_after = cfg.cleanup_rollback_after;
+        let steady = !matches!(
+            self.state,
+            UpdateAgentState::StartState | UpdateAgentState::Initialized
+        );
+        if steady && !self.strategy_degraded && self.rollback_cleanup.is_none() {
+            self.record_healthy_release();
+        }
+        self.connectivity_gate = cfg.connectivity_gate;
+        self.deploy_retry_max_delay = cfg.deploy_retry_max_delay;
+        self.disk_space = cfg.disk_space;
+        self.download_schedule = cfg.download_schedule;
+        self.fetch_only_window = cfg.fetch_only_window;
+        self.respect_metered = cfg.network.respect_metered();
+        self.health_checks = cfg.health_checks;
+        self.quiesce = cfg.quiesce;
+        self.reboot_countdown = cfg.reboot_countdown;
+        self.messages = cfg.messages;
+        self.postponements = PostponementBudget {
+            max: cfg.max_postponements,
+            delay: cfg.postponement_delay,
+        };
+        self.outcome_report = cfg.outcome_report;
+        self.post_boot_hooks = cfg.post_boot_hooks;
+        self.steady_interval = Duration::from_secs(cfg.steady_interval_secs.get());
+        self.steady_report_max_failures = cfg.steady_report_max_failures;
+        self.strategy = cfg.strategy;
+        self.stream_switch = cfg.stream_switch;
+        self.urgency = cfg.urgency;
+        self.verify_remote = cfg.verify_remote;
+        self.verify_signature = cfg.verify_signature;
+        self.effective_config = cfg.effective_config;
+
+        log::info!(
+            "configuration reloaded, update strategy: {}",
+            self.strategy.human_description()
+        );
+    }
+
+    /// Move this node to another update group, persisting it across agent
+    /// restarts.
+    ///
+    /// Identity-dependent settings (update source and strategy) are rebuilt
+    /// for the new group. This returns the strategy previously in use, so
+    /// that resources held under the old group can be released, or `None`
+    /// if the node is already in the given group.
+    fn set_update_group(&mut self, group: &str) -> Result<Option<UpdateStrategy>> {
+        if group == self.identity.group {
+            return Ok(None);
+        }
+        if matches!(
+            self.state,
+            UpdateAgentState::UpdateFinalized(_) | UpdateAgentState::EndState
+        ) {
+            anyhow::bail!("cannot change update group while an update is pending reboot");
+        }
+
+        let previous = fs::read(GROUP_OVERRIDE_PATH).ok();
+        identity::persist_group_override(GROUP_OVERRIDE_PATH, group)?;
+        let cfg = match Settings::assemble() {
+            Ok(cfg) => cfg,
+            Err(e) => {
+                // Restore the previous group, to stay consistent across restarts.
+                let restored = match previous {
+                    Some(content) => utils::atomic_write(GROUP_OVERRIDE_PATH, 0o644, &content),
+                    None => utils::remove_if_exists(GROUP_OVERRIDE_PATH),
+                };
+                if let Err(err) = restored {
+                    log::error!("{:#}", err);
+                }
+                return Err(e.context("failed to rebuild settings for the new update group"));
+            }
+        };
+
+        log::info!(
+            "update group changed from '{}' to '{}'",
+            self.identity.group,
+            cfg.identity.group
+        );
+        self.identity = cfg.identity;
+        self.source = cfg.source;
+        self.effective_config = cfg.effective_config;
+        let previous_strategy = std::mem::replace(&mut self.strategy, cfg.strategy);
+        Ok(Some(previous_strategy))
+    }
+
+    /// Check whether this node can switch to another update stream.
+    ///
+    /// This returns the refspec to rebase onto, or `None` if the node is
+    /// already on the given stream (or switching to it).
+    fn prepare_stream_switch(&self, stream: &str) -> Result<Option<String>> {
+        if stream == self.identity.stream {
+            return Ok(None);
+        }
+        let switch = match &self.stream_switch {
+            Some(s) => s,
+            None => anyhow::bail!("switching update streams is not configured"),
+        };
+        if matches!(
+            self.state,
+            UpdateAgentState::UpdateFinalized(_) | UpdateAgentState::EndState
+        ) {
+            anyhow::bail!("cannot switch update stream while an update is pending reboot");
+        }
+        if let Some(hold) = &self.hold {
+            anyhow::bail!("cannot switch update stream, {}", hold.describe());
+        }
+
+        let refspec = switch.refspec_for(&self.identity, stream)?;
+        Ok(Some(refspec))
+    }
+
+    /// Complete a switch to another update stream, after rebasing onto it.
+    ///
+    /// The rebased deployment replaces any previously staged update, and is
+    /// finalized as such. The new stream is persisted until the node boots
+    /// into it, and identity-dependent settings are rebuilt for it.
+    fn complete_stream_switch(&mut self, stream: &str, release: Release) -> Result<()> {
+        log::info!(
+            "rebased to update stream '{}', staged release '{}'",
+            stream,
+            release.version
+        );
+        utils::update_unit_status(
+            utils::StatusSummary::new("staged")
+                .target(&release.version)
+                .reason("stream-switch"),
+            &format!("update staged: {} (stream {})", release.version, stream),
+        );
+        self.abort_pending_reboot("stream switch");
+        self.staged_downgrade = false;
+        self.staged_fixed_cves.clear();
+        self.state.update_staged(release, self.postponements.max);
+
+        identity::persist_stream_switch(STREAM_SWITCH_PATH, stream)?;
+        let cfg =
+            Settings::assemble().context("failed to rebuild settings for the new update stream")?;
+        self.identity = cfg.identity;
+        self.source = cfg.source;
+        self.effective_config = cfg.effective_config;
+        Ok(())
+    }
+
+    /// Arm a one-time finalization at the given UTC timestamp, replacing
+    /// any previous schedule.
+    fn schedule_finalize(&mut self, timestamp: i64) -> Result<()> {
+        let schedule = ScheduledFinalize::from_timestamp(timestamp, &chrono::Utc::now())?;
+        schedule.persist(SCHEDULED_FINALIZE_PATH)?;
+        log::info!(
+            "one-time finalization scheduled at {}",
+            schedule.human_time()
+        );
+        SCHEDULED_FINALIZATION.set(schedule.timestamp());
+        self.emit_event(AgentEvent::RebootScheduled {
+            timestamp: schedule.timestamp(),
+        });
+        self.scheduled_finalize = Some(schedule);
+        Ok(())
+    }
+
+    /// Forbid reboots between the given UTC timestamps, replacing any
+    /// previous temporary blackout.
+    fn set_temporary_blackout(&mut self, start: i64, end: i64, reason: &str) -> Result<()> {
+        let blackout = TemporaryBlackout::from_timestamps(start, end, reason, &chrono::Utc::now())?;
+        blackout.persist(TEMPORARY_BLACKOUT_PATH)?;
+        log::info!("reboots forbidden by {}", blackout.describe());
+        TEMPORARY_BLACKOUT_END.set(blackout.end.timestamp());
+        self.temporary_blackout = Some(blackout);
+        Ok(())
+    }
+
+    /// Lift the temporary blackout, if any.
+    ///
+    /// This returns whether a blackout was actually removed.
+    fn clear_temporary_blackout(&mut self) -> Result<bool> {
+        TemporaryBlackout::remove(TEMPORARY_BLACKOUT_PATH)?;
+        TEMPORARY_BLACKOUT_END.set(0);
+        Ok(self.temporary_blackout.take().is_some())
+    }
+
+    /// Hold the node on its booted release, replacing any previous hold.
+    ///
+    /// This returns the pinned release version.
+    fn hold_updates(&mut self) -> Result<String> {
+        let version = self.identity.current_os.version.clone();
+        let hold = UpdateHold::new(&version, chrono::Utc::now());
+        hold.persist(UPDATE_HOLD_PATH)?;
+        log::info!("updates held, {}", hold.describe());
+        UPDATE_HELD.set(1);
+        self.hold = Some(hold);
+        Ok(version)
+    }
+
+    /// Release the hold on the booted release, if any.
+    ///
+    /// This returns whether a hold was actually removed.
+    fn release_hold(&mut self) -> Result<bool> {
+        UpdateHold::remove(UPDATE_HOLD_PATH)?;
+        UPDATE_HELD.set(0);
+        Ok(self.hold.take().is_some())
+    }
+
+    /// Return the temporary blackout, if currently active.
+    fn active_temporary_blackout(&self) -> Option<&TemporaryBlackout> {
+        self.temporary_blackout
+            .as_ref()
+            .filter(|b| b.is_active(&chrono::Utc::now()))
+    }
+
+    /// Return the active blackout (temporary or configured), if any.
+    ///
+    /// This returns the finalization verdict along with a description of
+    /// the blackout.
+    fn active_blackout(&self) -> Option<(&'static str, String)> {
+        if let Some(blackout) = self.active_temporary_blackout() {
+            return Some(("blackout", blackout.describe()));
+        }
+        let now = chrono::Utc::now();
+        let period = self.blackout_periods.as_ref()?.active(&now)?;
+        Some(("blackout-period", period.describe()))
+    }
+
+    /// Approve a reboot into the staged update, returning its version.
+    fn approve_pending_reboot(&mut self) -> Result<String> {
+        if !self.require_reboot_approval {
+            anyhow::bail!("reboot approval is not required by configuration");
+        }
+        let release = match &self.state {
+            UpdateAgentState::UpdateStaged((release, _)) => release.clone(),
+            _ => anyhow::bail!("no staged update pending reboot"),
+        };
+
+        let approval = RebootApproval::new(&release, chrono::Utc::now());
+        approval.persist(REBOOT_APPROVAL_PATH)?;
+        log::info!("reboot into '{}' approved", release.version);
+        self.reboot_approval = Some(approval);
+        Ok(release.version)
+    }
+
+    /// Return whether a reboot into `release` is approved (or no approval is required).
+    fn reboot_approved(&self, release: &Release) -> bool {
+        if !self.require_reboot_approval {
+            return true;
+        }
+        self.reboot_approval
+            .as_ref()
+            .map(|a| a.covers(release))
+            .unwrap_or(false)
+    }
+
+    /// Consume the reboot approval, if any.
+    fn consume_reboot_approval(&mut self) -> Result<()> {
+        if self.reboot_approval.take().is_some() {
+            RebootApproval::remove(REBOOT_APPROVAL_PATH)?;
+        }
+        Ok(())
+    }
+
+    /// Try to take the lock shared with other reboot managers, if any.
+    ///
+    /// This returns whether finalization can proceed.
+    fn acquire_reboot_lock(&mut self) -> bool {
+        let lock = match self.reboot_lock.as_mut() {
+            Some(lock) => lock,
+            None => return true,
+        };
+        match lock.try_acquire() {
+            Ok(true) => true,
+            Ok(false) => {
+                REBOOT_LOCK_BLOCKED.inc();
+                false
+            }
+            Err(e) => {
+                self.record_error("failed to acquire reboot lock", &e);
+                false
+            }
+        }
+    }
+
+    /// Release the lock shared with other reboot managers, if held.
+    fn release_reboot_lock(&mut self) {
+        if let Some(lock) = self.reboot_lock.as_mut() {
+            lock.release();
+        }
+    }
+
+    /// Disarm the one-time scheduled finalization, if any.
+    ///
+    /// This returns whether a schedule was actually removed.
+    fn clear_scheduled_finalize(&mut self) -> Result<bool> {
+        ScheduledFinalize::remove(SCHEDULED_FINALIZE_PATH)?;
+        SCHEDULED_FINALIZATION.set(0);
+        Ok(self.scheduled_finalize.take().is_some())
+    }
+
+    /// Return whether a one-time scheduled finalization is due.
+    fn scheduled_finalize_due(&self) -> bool {
+        self.scheduled_finalize
+            .as_ref()
+            .map(|s| s.is_due(&chrono::Utc::now()))
+            .unwrap_or(false)
+    }
+
+    /// Return whether the update strategy is to be overridden for `release`,
+    /// as an urgent release past its grace period.
+    ///
+    /// The grace period starts the first time an urgent release is ready
+    /// to be finalized.
+    fn urgency_override_due(&mut self, release: &Release) -> bool {
+        let (urgency, severity) = match &self.urgency {
+            Some(u) => match u.urgent_severity(release) {
+                Some(s) => (u, s),
+                None => return false,
+            },
+            None => return false,
+        };
+        let now = chrono::Utc::now();
+        let since = match &self.urgent_since {
+            Some((checksum, since)) if *checksum == release.checksum => *since,
+            _ => {
+                let due = urgency.due_time(&now);
+                log::warn!(
+                    "update {} has {} severity, overriding update strategy at {}",
+                    release.version,
+                    severity,
+                    due.format("%a %Y-%m-%d %H:%M:%S %Z")
+                );
+                self.urgent_since = Some((release.checksum.clone(), now));
+                now
+            }
+        };
+        now >= urgency.due_time(&since)
+    }
+
+    /// Return the time at which the update strategy is overridden for
+    /// `release`, if it is urgent.
+    fn urgency_due_time(&self, release: &Release) -> Option<DateTime<Utc>> {
+        match (&self.urgency, &self.urgent_since) {
+            (Some(urgency), Some((checksum, since)))
+                if *checksum == release.checksum && urgency.urgent_severity(release).is_some() =>
+            {
+                Some(urgency.due_time(since))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Return whether the reboot slot can be released on shutdown.
+///
+/// A finalized update is about to reboot the node, thus it keeps holding
+/// its reboot slot until the next steady state report. The same goes for a
+/// reboot which may already be underway, i.e. after finalization was
+/// attempted or while a reboot is pending.
+fn release_slot_on_shutdown(
+    state: &UpdateAgentState,
+    pending_reboot: Option<&PendingReboot>,
+    finalize_attempted: bool,
+) -> bool {
+    let holding = matches!(
+        state,
+        UpdateAgentState::StartState
+            | UpdateAgentState::Initialized
+            | UpdateAgentState::UpdateFinalized(_)
+            | UpdateAgentState::EndState
+    );
+    let reboot_pending = matches!(
+        pending_reboot,
+        Some(PendingReboot::Scheduled(_)) | Some(PendingReboot::Countdown(_))
+    );
+    !holding && !reboot_pending && !finalize_attempted
+}
+
+/// Return whether the system is going down, according to logind.
+///
+/// Errors are logged, and the system considered as not going down.
+fn system_going_down() -> bool {
+    logind::preparing_for_shutdown().unwrap_or_else(|e| {
+        log::warn!("{:#}", e);
+        false
+    })
+}
+
+/// Return whether the kernel command-line inhibits auto-updates.
+///
+/// The `zincati.inhibit` argument can be added from the bootloader for a
+/// single boot (e.g. a rescue entry). A bare argument or a truthy value
+/// inhibits updates; if repeated, the last occurrence wins.
+pub(crate) fn cmdline_inhibits_updates(cmdline: &str) -> bool {
+    let mut inhibited = false;
+    for arg in cmdline.split_whitespace() {
+        let (key, value) = match arg.split_once('=') {
+            Some((k, v)) => (k, Some(v)),
+            None => (arg, None),
+        };
+        if key != INHIBIT_KARG {
+            continue;
+        }
+        inhibited = match value {
+            None => true,
+            Some(v) => matches!(v, "1" | "true" | "yes" | "on"),
+        };
+    }
+    inhibited
+}
+
+/// Attempt to broadcast msg to all interactive user sessions, if any.
+fn broadcast_to_user_sessions(msg: &str) {
+    match get_interactive_user_sessions() {
+        Ok(sessions) => broadcast(msg, &sessions),
+        Err(e) => log::error!("failed to check for interactive sessions: {}", e),
     }
 }
 
@@ -406,7 +1759,12 @@ fn broadcast(msg: &str, sessions: &[InteractiveSession]) {
             &session.user,
             &session.tty_dev
         );
-        if let Err(e) = fs::write(&session.tty_dev, &broadcast_msg) {
+        // Messages are appended, like `wall` does, so that earlier ones stay visible.
+        let written = fs::OpenOptions::new()
+            .append(true)
+            .open(&session.tty_dev)
+            .and_then(|mut tty| tty.write_all(broadcast_msg.as_bytes()));
+        if let Err(e) = written {
             log::error!("failed to write to {}: {}", &session.tty_dev, e);
             continue;
         };
@@ -423,9 +1781,30 @@ fn broadcast(msg: &str, sessions: &[InteractiveSession]) {
     }
 }
 
+/// Get sessions with logged in interactive users from logind, falling back
+/// to `loginctl` if its D-Bus API is not reachable.
+fn get_interactive_user_sessions() -> Result<Vec<InteractiveSession>> {
+    match logind::tty_sessions() {
+        Ok(sessions) => {
+            let interactive_sessions = sessions
+                .into_iter()
+                .map(|(user, tty)| InteractiveSession {
+                    user,
+                    tty_dev: format!("/dev/{}", tty),
+                })
+                .collect();
+            Ok(interactive_sessions)
+        }
+        Err(e) => {
+            log::debug!("{:#}", e);
+            loginctl_interactive_sessions()
+        }
+    }
+}
+
 /// Get sessions with logged in interactive users using `loginctl`.
 /// Returns a Result with vector of `SessionsJson` if no error.
-fn get_interactive_user_sessions() -> Result<Vec<InteractiveSession>> {
+fn loginctl_interactive_sessions() -> Result<Vec<InteractiveSession>> {
     let cmdrun = std::process::Command::new("loginctl")
         .arg("list-sessions")
         .arg("--output=json")
@@ -467,45 +1846,13 @@ fn get_interactive_user_sessions() -> Result<Vec<InteractiveSession>> {
     Ok(interactive_session)
 }
 
-/// Returns a warning string about the time until reboot and the release
-/// that is staged.
-fn format_reboot_warning(seconds: u64, release_ver: &str) -> String {
-    let time_till_reboot = format_seconds(seconds);
-
-    format!(
-        "New update {} deployed.\nRebooting into this update in around {} (if permitted by update strategy).",
-        release_ver, time_till_reboot
-    )
-}
-
-/// Helper to return a human-friendly version of seconds.
-/// Example: 65 seconds would be converted to 1 minute and 5 seconds.
-fn format_seconds(seconds: u64) -> String {
-    let mut time_till_reboot = if seconds / 60 >= 1 {
-        format!(
-            "{} minute{}{}",
-            seconds / 60,
-            if seconds / 60 == 1 { "" } else { "s" },
-            if seconds % 60 > 0 { " and " } else { "" }
-        )
-    } else {
-        String::from("")
-    };
-    if seconds % 60 > 0 {
-        time_till_reboot.push_str(&format!(
-            "{} second{}",
-            seconds % 60,
-            if seconds % 60 == 1 { "" } else { "s" }
-        ))
-    }
-
-    time_till_reboot
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::rpm_ostree::Release;
+    use crate::config::inputs::{
+        DEFAULT_DEPLOY_RETRY_MAX_DELAY_SECS, DEFAULT_STEADY_INTERVAL_SECS,
+    };
+    use crate::rpm_ostree::{Release, RolloutWave};
     use std::{thread, time};
 
     #[test]
@@ -513,10 +1860,27 @@ mod tests {
         assert_eq!(UpdateAgentState::default(), UpdateAgentState::StartState);
     }
 
+    #[test]
+    fn state_duration_metrics() {
+        let observed = |state: &str| {
+            STATE_DURATION
+                .with_label_values(&[state])
+                .get_sample_count()
+        };
+        let mut machine = UpdateAgentState::default();
+        machine.initialized();
+        let before = observed("Initialized");
+        machine.reported_steady();
+        // Other tests may run transitions concurrently.
+        assert!(observed("Initialized") > before);
+    }
+
     #[test]
     fn state_machine_happy_path() {
         let steady_interval = Duration::from_secs(DEFAULT_STEADY_INTERVAL_SECS);
         let default_interval = Duration::from_secs(DEFAULT_REFRESH_PERIOD_SECS);
+        let budget = PostponementBudget::default();
+        let retry_max = Duration::from_secs(DEFAULT_DEPLOY_RETRY_MAX_DELAY_SECS);
 
         let mut machine = UpdateAgentState::default();
         assert_eq!(machine, UpdateAgentState::StartState);
@@ -533,7 +1897,7 @@ mod tests {
         let state_change_time_after = LATEST_STATE_CHANGE.get();
         assert_eq!(machine, UpdateAgentState::NoNewUpdate);
         assert_ne!(state_change_time_before, state_change_time_after);
-        let (delay, should_jitter) = machine.get_refresh_delay(steady_interval);
+        let (delay, should_jitter) = machine.get_refresh_delay(steady_interval, retry_max, &budget);
         assert_eq!(delay, steady_interval);
         assert!(should_jitter);
 
@@ -549,6 +1913,8 @@ mod tests {
             version: "v1".to_string(),
             checksum: "ostree-checksum".to_string(),
             age_index: None,
+            advisory: None,
+            wave: None,
         };
         machine.update_available(update.clone());
         assert_eq!(
@@ -557,19 +1923,24 @@ mod tests {
         );
 
         let (persistent_err, _) = machine.record_failed_deploy();
-        assert_eq!(persistent_err, false);
+        assert!(!persistent_err);
         assert_eq!(
             machine,
             UpdateAgentState::UpdateAvailable((update.clone(), 1))
         );
-        let (delay, should_jitter) = machine.get_refresh_delay(steady_interval);
+        let (delay, should_jitter) = machine.get_refresh_delay(steady_interval, retry_max, &budget);
         assert_eq!(delay, default_interval);
         assert!(should_jitter);
 
-        machine.update_staged(update.clone());
+        // Further failures back off.
+        machine.record_failed_deploy();
+        let (delay, _) = machine.get_refresh_delay(steady_interval, retry_max, &budget);
+        assert_eq!(delay, default_interval * 2);
+
+        machine.update_staged(update.clone(), budget.max);
         assert_eq!(
             machine,
-            UpdateAgentState::UpdateStaged((update.clone(), MAX_FINALIZE_POSTPONEMENTS))
+            UpdateAgentState::UpdateStaged((update.clone(), budget.max))
         );
 
         machine.update_finalized(update.clone());
@@ -579,12 +1950,62 @@ mod tests {
         assert_eq!(machine, UpdateAgentState::EndState);
     }
 
+    #[test]
+    fn test_deploy_retry_delay() {
+        let default_interval = Duration::from_secs(DEFAULT_REFRESH_PERIOD_SECS);
+        let max = Duration::from_secs(DEFAULT_DEPLOY_RETRY_MAX_DELAY_SECS);
+        assert_eq!(deploy_retry_delay(1, max), default_interval);
+        assert_eq!(deploy_retry_delay(3, max), default_interval * 4);
+        assert_eq!(deploy_retry_delay(MAX_DEPLOY_ATTEMPTS, max), max);
+        assert_eq!(deploy_retry_delay(u8::MAX, max), max);
+
+        // A higher cap keeps backing off for longer.
+        let max = Duration::from_secs(6 * 3600);
+        assert_eq!(deploy_retry_delay(7, max), default_interval * 64);
+        assert_eq!(deploy_retry_delay(MAX_DEPLOY_ATTEMPTS, max), max);
+    }
+
+    #[test]
+    fn test_fsm_rollout_wave() {
+        let steady_interval = Duration::from_secs(3600);
+        let budget = PostponementBudget::default();
+        let retry_max = Duration::from_secs(DEFAULT_DEPLOY_RETRY_MAX_DELAY_SECS);
+        let wave = RolloutWave {
+            name: "canary".to_string(),
+            start: chrono::Utc::now() + chrono::Duration::minutes(10),
+        };
+        let update = Release {
+            version: "v1".to_string(),
+            checksum: "ostree-checksum".to_string(),
+            age_index: None,
+            advisory: None,
+            wave: Some(wave),
+        };
+        let mut machine = UpdateAgentState::NoNewUpdate;
+
+        machine.waiting_for_wave(update.clone());
+        assert_eq!(machine, UpdateAgentState::WaitingForWave(update.clone()));
+        assert_eq!(machine.target(), Some(&update));
+        let (delay, _) = machine.get_refresh_delay(steady_interval, retry_max, &budget);
+        assert!(delay <= Duration::from_secs(600));
+
+        // Release pulled while waiting.
+        machine.no_new_update();
+        assert_eq!(machine, UpdateAgentState::NoNewUpdate);
+
+        machine.waiting_for_wave(update.clone());
+        machine.update_available(update.clone());
+        assert_eq!(machine, UpdateAgentState::UpdateAvailable((update, 0)));
+    }
+
     #[test]
     fn test_fsm_abandon_update() {
         let update = Release {
             version: "v1".to_string(),
             checksum: "ostree-checksum".to_string(),
             age_index: None,
+            advisory: None,
+            wave: None,
         };
         let mut machine = UpdateAgentState::NoNewUpdate;
 
@@ -597,49 +2018,130 @@ mod tests {
         // MAX-1 temporary failures.
         for attempt in 1..MAX_DEPLOY_ATTEMPTS {
             let (persistent_err, _) = machine.record_failed_deploy();
-            assert_eq!(persistent_err, false);
+            assert!(!persistent_err);
             assert_eq!(
                 machine,
-                UpdateAgentState::UpdateAvailable((update.clone(), attempt as u8))
+                UpdateAgentState::UpdateAvailable((update.clone(), attempt))
             )
         }
 
         // Persistent error threshold reached.
         let (persistent_err, _) = machine.record_failed_deploy();
-        assert_eq!(persistent_err, true);
+        assert!(persistent_err);
         assert_eq!(machine, UpdateAgentState::NoNewUpdate);
     }
 
+    #[test]
+    fn test_fsm_retarget_update() {
+        let update = Release {
+            version: "v1".to_string(),
+            checksum: "ostree-checksum".to_string(),
+            age_index: None,
+            advisory: None,
+            wave: None,
+        };
+        let mut machine = UpdateAgentState::NoNewUpdate;
+        machine.update_available(update.clone());
+        for _ in 1..MAX_DEPLOY_ATTEMPTS {
+            machine.record_failed_deploy();
+        }
+
+        // A new target starts over, instead of being abandoned on next failure.
+        let next = Release {
+            version: "v2".to_string(),
+            checksum: "ostree-checksum-2".to_string(),
+            ..update
+        };
+        machine.update_retargeted(next.clone());
+        assert_eq!(
+            machine,
+            UpdateAgentState::UpdateAvailable((next.clone(), 0))
+        );
+        let (persistent_err, fail_count) = machine.record_failed_deploy();
+        assert!(!persistent_err);
+        assert_eq!(fail_count, 1);
+    }
+
+    #[test]
+    fn test_fsm_download_update() {
+        let budget = PostponementBudget::default();
+        let update = Release {
+            version: "v1".to_string(),
+            checksum: "ostree-checksum".to_string(),
+            age_index: None,
+            advisory: None,
+            wave: None,
+        };
+        let mut machine = UpdateAgentState::NoNewUpdate;
+
+        assert_eq!(machine.target(), None);
+
+        machine.update_available(update.clone());
+        machine.update_downloaded();
+        assert_eq!(
+            machine,
+            UpdateAgentState::UpdateDownloaded((update.clone(), 0))
+        );
+        assert_eq!(machine.name(), "UpdateDownloaded");
+        assert_eq!(machine.target(), Some(&update));
+
+        // Failing to stage a downloaded update goes back to fetching it.
+        let (persistent_err, fail_count) = machine.record_failed_deploy();
+        assert!(!persistent_err);
+        assert_eq!(fail_count, 1);
+        assert_eq!(
+            machine,
+            UpdateAgentState::UpdateAvailable((update.clone(), 1))
+        );
+
+        machine.update_downloaded();
+        assert_eq!(
+            machine,
+            UpdateAgentState::UpdateDownloaded((update.clone(), 1))
+        );
+        machine.update_staged(update.clone(), budget.max);
+        assert_eq!(
+            machine,
+            UpdateAgentState::UpdateStaged((update, budget.max))
+        );
+    }
+
     #[test]
     fn test_fsm_postpone_finalize() {
         let steady_interval = Duration::from_secs(DEFAULT_STEADY_INTERVAL_SECS);
         let default_interval = Duration::from_secs(DEFAULT_REFRESH_PERIOD_SECS);
-        let postponement_interval = Duration::from_secs(DEFAULT_POSTPONEMENT_TIME_SECS);
+        let budget = PostponementBudget::default();
+        let retry_max = Duration::from_secs(DEFAULT_DEPLOY_RETRY_MAX_DELAY_SECS);
+        let postponement_interval = budget.delay;
         let update = Release {
             version: "v1".to_string(),
             checksum: "ostree-checksum".to_string(),
             age_index: None,
+            advisory: None,
+            wave: None,
         };
         let mut machine = UpdateAgentState::UpdateAvailable((update.clone(), 0));
-        let (delay, should_jitter) = machine.get_refresh_delay(steady_interval);
+        let (delay, should_jitter) = machine.get_refresh_delay(steady_interval, retry_max, &budget);
         assert_eq!(delay, default_interval);
         assert!(should_jitter);
 
-        machine.update_staged(update.clone());
+        machine.update_staged(update.clone(), budget.max);
         assert_eq!(
             machine,
-            UpdateAgentState::UpdateStaged((update.clone(), MAX_FINALIZE_POSTPONEMENTS))
+            UpdateAgentState::UpdateStaged((update.clone(), budget.max))
         );
 
         // Set up empty interactive sessions.
+        let messages = MessageTemplates::default();
         let no_interactive_sessions: Vec<InteractiveSession> = vec![];
-        let can_finalize = machine.handle_interactive_sessions(&no_interactive_sessions);
+        let can_finalize =
+            machine.handle_interactive_sessions(&no_interactive_sessions, &budget, &messages);
         assert!(can_finalize);
         assert_eq!(
             machine,
-            UpdateAgentState::UpdateStaged((update.clone(), MAX_FINALIZE_POSTPONEMENTS))
+            UpdateAgentState::UpdateStaged((update.clone(), budget.max))
         );
-        let (delay, should_jitter) = machine.get_refresh_delay(steady_interval);
+        let (delay, should_jitter) = machine.get_refresh_delay(steady_interval, retry_max, &budget);
         assert_eq!(delay, default_interval);
         assert!(should_jitter);
 
@@ -647,48 +2149,104 @@ mod tests {
         let fake_tty_path = tempfile::tempdir_in("/tmp").unwrap();
         let fake_tty_path_str = fake_tty_path.path().to_str().unwrap();
         let fake_tty = format!("{}/tty1", fake_tty_path_str);
+        fs::File::create(&fake_tty).unwrap();
         let fake_session = InteractiveSession {
             user: String::from("fakeuser"),
             tty_dev: String::from(&fake_tty),
         };
         let interactive_sessions_present: Vec<InteractiveSession> = vec![fake_session];
 
-        // Postpone MAX_FINALIZE_POSTPONEMENTS times (counting from 1).
-        for finalization_attempt in 1..MAX_FINALIZE_POSTPONEMENTS + 1 {
-            let can_finalize = machine.handle_interactive_sessions(&interactive_sessions_present);
+        // Postpone budget.max times (counting from 1).
+        for finalization_attempt in 1..budget.max + 1 {
+            let can_finalize = machine.handle_interactive_sessions(
+                &interactive_sessions_present,
+                &budget,
+                &messages,
+            );
             assert!(!can_finalize);
             machine.record_postponement(); // as we cannot finalize.
-            let postponement_remaining =
-                MAX_FINALIZE_POSTPONEMENTS.saturating_sub(finalization_attempt);
+            let postponement_remaining = budget.max.saturating_sub(finalization_attempt);
             assert_eq!(
                 machine,
                 UpdateAgentState::UpdateStaged((update.clone(), postponement_remaining))
             );
-            let (delay, should_jitter) = machine.get_refresh_delay(steady_interval);
+            let (delay, should_jitter) =
+                machine.get_refresh_delay(steady_interval, retry_max, &budget);
             assert_eq!(delay, postponement_interval);
             assert!(!should_jitter);
         }
 
-        // Sanity check final broadcasted message.
+        // Sanity check broadcasted messages: a notice on every postponement,
+        // and a final reboot warning.
         let tty_contents = fs::read_to_string(&fake_tty).unwrap();
-        assert!(tty_contents.contains("Broadcast message from Zincati"));
+        assert_eq!(
+            tty_contents
+                .matches("Broadcast message from Zincati")
+                .count(),
+            budget.max as usize
+        );
         assert!(tty_contents.contains(&update.version));
-        assert!(tty_contents.contains(&format_seconds(DEFAULT_POSTPONEMENT_TIME_SECS)));
+        assert!(tty_contents.contains("postponed due to active user sessions"));
+        let (_, final_msg) = tty_contents.rsplit_once("Broadcast message").unwrap();
+        assert!(final_msg.contains(&crate::messages::format_seconds(budget.delay.as_secs())));
+        assert!(!final_msg.contains("postponed due to active user sessions"));
 
         // Reached 0 remaining postponements.
-        let can_finalize = machine.handle_interactive_sessions(&interactive_sessions_present);
+        let can_finalize =
+            machine.handle_interactive_sessions(&interactive_sessions_present, &budget, &messages);
         assert!(can_finalize);
         assert_eq!(machine, UpdateAgentState::UpdateStaged((update.clone(), 0)));
     }
 
     #[test]
-    fn test_format_seconds() {
-        assert_eq!("1 second", format_seconds(1));
-        assert_eq!("2 seconds", format_seconds(2));
-        assert_eq!("1 minute", format_seconds(60));
-        assert_eq!("1 minute and 1 second", format_seconds(1 * 60 + 1));
-        assert_eq!("1 minute and 30 seconds", format_seconds(1 * 60 + 30));
-        assert_eq!("2 minutes", format_seconds(2 * 60));
-        assert_eq!("42 minutes and 23 seconds", format_seconds(42 * 60 + 23));
+    fn test_cmdline_inhibits_updates() {
+        let base = "BOOT_IMAGE=(hd0,gpt3)/ostree/vmlinuz root=UUID=abcd rw";
+        assert!(!cmdline_inhibits_updates(base));
+        assert!(!cmdline_inhibits_updates(""));
+
+        let cases = vec![
+            ("zincati.inhibit", true),
+            ("zincati.inhibit=1", true),
+            ("zincati.inhibit=true", true),
+            ("zincati.inhibit=0", false),
+            ("zincati.inhibit=no", false),
+            ("zincati.inhibitx=1", false),
+            ("zincati.inhibit=1 zincati.inhibit=0", false),
+            ("zincati.inhibit=0 zincati.inhibit", true),
+        ];
+        for (args, expected) in cases {
+            let cmdline = format!("{} {}\n", base, args);
+            assert_eq!(cmdline_inhibits_updates(&cmdline), expected, "{}", args);
+        }
+    }
+
+    #[test]
+    fn test_release_slot_on_shutdown() {
+        let update = Release {
+            version: "v1".to_string(),
+            checksum: "ostree-checksum".to_string(),
+            age_index: None,
+            advisory: None,
+            wave: None,
+        };
+        let staged = UpdateAgentState::UpdateStaged((update.clone(), 0));
+        let at = chrono::Utc::now() + chrono::Duration::minutes(10);
+
+        assert!(release_slot_on_shutdown(&staged, None, false));
+        let cancelled = PendingReboot::Cancelled(at);
+        assert!(release_slot_on_shutdown(&staged, Some(&cancelled), false));
+
+        // Reboot pending, or finalization attempted: the slot is kept.
+        for pending in &[PendingReboot::Countdown(at), PendingReboot::Scheduled(at)] {
+            assert!(!release_slot_on_shutdown(&staged, Some(pending), false));
+        }
+        assert!(!release_slot_on_shutdown(&staged, None, true));
+        let finalized = UpdateAgentState::UpdateFinalized(update);
+        assert!(!release_slot_on_shutdown(&finalized, None, false));
+        assert!(release_slot_on_shutdown(
+            &UpdateAgentState::ReportedSteady,
+            None,
+            false
+        ));
     }
 }
@@ -0,0 +1,283 @@
+//! Projected next actions of the agent.
+//!
+//! The plan is a forecast, computed from the current state, the update
+//! strategy and the configuration (windows, schedules, blackouts). It lets
+//! dashboards show what the agent is expected to do next and when, rather
+//! than only its current state. It is not a promise: dynamic checks (e.g.
+//! fleet-wide locks, health checks, user sessions) can still delay actions.
+
+use super::blackout::TemporaryBlackout;
+use super::schedule::ScheduledFinalize;
+use super::{PendingReboot, UpdateAgent, UpdateAgentState, STAGING_LEAD_TIME_SECS};
+use crate::blackout::BlackoutPeriods;
+use crate::strategy::UpdateStrategy;
+use chrono::{DateTime, TimeZone, Timelike, Utc};
+use serde::{Deserialize, Serialize};
+use zvariant::derive::Type;
+
+/// A projected action of the agent.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, Type)]
+pub struct PlannedAction {
+    /// Action name (`check`, `download`, `stage`, `finalize` or `reboot`).
+    pub action: String,
+    /// Estimated UTC timestamp of the action (0 if it cannot be estimated).
+    pub estimated_time: i64,
+    /// Conditions the action depends on, in human terms (empty if none).
+    pub conditions: String,
+}
+
+impl PlannedAction {
+    fn new(action: &str, at: Option<DateTime<Utc>>, conditions: Vec<String>) -> Self {
+        Self {
+            action: action.to_string(),
+            estimated_time: at.map(|t| t.timestamp()).unwrap_or(0),
+            conditions: conditions.join("; "),
+        }
+    }
+}
+
+/// Steps of the update pipeline, in order.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Step {
+    Check,
+    Download,
+    Stage,
+    Finalize,
+}
+
+impl UpdateAgent {
+    /// Return the projected next actions, as seen at `now`.
+    ///
+    /// The plan is empty if auto-updates are disabled, inhibited or held,
+    /// or if the agent has nothing left to do.
+    pub(super) fn plan(&self, now: DateTime<Utc>) -> Vec<PlannedAction> {
+        if !self.enabled || self.inhibited || self.hold.is_some() {
+            return vec![];
+        }
+
+        let next_tick = self.next_refresh.filter(|t| *t > now).unwrap_or(now);
+        let deferred_download = self.download_schedule.is_some() || self.fetch_only_window;
+        let first = match &self.state {
+            UpdateAgentState::StartState
+            | UpdateAgentState::Initialized
+            | UpdateAgentState::ReportedSteady
+            | UpdateAgentState::NoNewUpdate => Step::Check,
+            UpdateAgentState::WaitingForWave(_) | UpdateAgentState::UpdateAvailable(_)
+                if deferred_download =>
+            {
+                Step::Download
+            }
+            UpdateAgentState::WaitingForWave(_)
+            | UpdateAgentState::UpdateAvailable(_)
+            | UpdateAgentState::UpdateDownloaded(_) => Step::Stage,
+            UpdateAgentState::UpdateStaged(_) => Step::Finalize,
+            UpdateAgentState::UpdateFinalized(_) => {
+                let reboot = PlannedAction::new("reboot", Some(next_tick), vec![]);
+                return vec![reboot];
+            }
+            UpdateAgentState::EndState => return vec![],
+        };
+
+        let mut plan = vec![];
+        let mut at = Some(next_tick);
+        let mut conditions = vec![];
+        if let UpdateAgentState::WaitingForWave(release) = &self.state {
+            if let Some(wave) = &release.wave {
+                at = at.map(|t| t.max(wave.start));
+                conditions.push(format!("once rollout wave '{}' opens", wave.name));
+            }
+        }
+        if first == Step::Check {
+            plan.push(PlannedAction::new("check", at, vec![]));
+            conditions.push("if an update is found".to_string());
+        }
+
+        if first <= Step::Download && deferred_download {
+            let mut download_conditions = conditions.clone();
+            if let Some(schedule) = &self.download_schedule {
+                at = at.and_then(|t| Some(t + schedule.remaining_to_window(&t)?));
+                download_conditions.push("within download windows".to_string());
+            }
+            plan.push(PlannedAction::new("download", at, download_conditions));
+        }
+
+        if first <= Step::Stage {
+            let mut stage_conditions = conditions.clone();
+            if self.fetch_only_window {
+                let lead_time = chrono::Duration::seconds(STAGING_LEAD_TIME_SECS);
+                at = at.and_then(|t| {
+                    let window = t + self.strategy.remaining_to_window(&t)?;
+                    Some(t.max(window - lead_time))
+                });
+                stage_conditions.push("close to the next finalization window".to_string());
+            }
+            if self.verify_remote.is_some() {
+                stage_conditions.push("once available on the OSTree remote".to_string());
+            }
+            if self.verify_signature {
+                stage_conditions.push("with a valid signature".to_string());
+            }
+            plan.push(PlannedAction::new("stage", at, stage_conditions));
+        }
+
+        let finalize_at = at.and_then(|t| {
+            estimate_finalize(
+                t,
+                &self.strategy,
+                self.scheduled_finalize.as_ref(),
+                self.temporary_blackout.as_ref(),
+                self.blackout_periods.as_ref(),
+            )
+        });
+        conditions.extend(self.finalize_conditions());
+        plan.push(PlannedAction::new("finalize", finalize_at, conditions));
+
+        plan
+    }
+
+    /// Return the estimated time of the reboot into the staged update, as
+    /// seen at `now`.
+    ///
+    /// A reboot already scheduled through logind (or counting down) takes
+    /// precedence over the plan.
+    pub(super) fn estimated_reboot(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        if !matches!(
+            self.state,
+            UpdateAgentState::UpdateStaged(_) | UpdateAgentState::UpdateFinalized(_)
+        ) {
+            return None;
+        }
+        match self.pending_reboot {
+            Some(PendingReboot::Scheduled(at)) | Some(PendingReboot::Countdown(at)) => {
+                return Some(at)
+            }
+            _ => {}
+        }
+        self.plan(now)
+            .iter()
+            .find(|a| a.action == "finalize" || a.action == "reboot")
+            .filter(|a| a.estimated_time != 0)
+            .and_then(|a| Utc.timestamp_opt(a.estimated_time, 0).single())
+    }
+
+    /// Return the dynamic conditions finalization depends on.
+    fn finalize_conditions(&self) -> Vec<String> {
+        let mut conditions = vec![format!("{} strategy", self.strategy.configuration_label())];
+        if self.require_reboot_approval {
+            conditions.push("reboot approval".to_string());
+        }
+        if self.reboot_lock.is_some() {
+            conditions.push("reboot lock".to_string());
+        }
+        if self.connectivity_gate.is_some() {
+            conditions.push("connectivity gate".to_string());
+        }
+        if self.health_checks.is_some() {
+            conditions.push("health checks".to_string());
+        }
+        conditions.push("user sessions".to_string());
+        conditions
+    }
+}
+
+/// Estimate when an update staged at `staged_at` would be finalized.
+///
+/// This returns `None` if the strategy has no reachable windows.
+fn estimate_finalize(
+    staged_at: DateTime<Utc>,
+    strategy: &UpdateStrategy,
+    scheduled: Option<&ScheduledFinalize>,
+    blackout: Option<&TemporaryBlackout>,
+    periods: Option<&BlackoutPeriods>,
+) -> Option<DateTime<Utc>> {
+    let next_window = |t: DateTime<Utc>| {
+        let remaining = strategy.remaining_to_window(&t)?;
+        if remaining <= chrono::Duration::zero() {
+            return Some(t);
+        }
+        // Remaining time is computed with minute granularity, while windows
+        // start on whole minutes.
+        (t + remaining).with_second(0)?.with_nanosecond(0)
+    };
+
+    let mut estimate = next_window(staged_at);
+    // A one-time scheduled finalization overrides the strategy, if an
+    // update is staged by then.
+    if let Some(schedule) = scheduled {
+        if schedule.finalize_at >= staged_at {
+            estimate = Some(match estimate {
+                Some(t) => t.min(schedule.finalize_at),
+                None => schedule.finalize_at,
+            });
+        }
+    }
+    if let (Some(t), Some(b)) = (estimate, blackout) {
+        if b.is_active(&t) {
+            estimate = next_window(b.end);
+        }
+    }
+    // Blackout periods can be back to back, thus skip all of them.
+    if let Some(periods) = periods {
+        while let Some(period) = estimate.and_then(|t| periods.active(&t)) {
+            estimate = next_window(period.end());
+        }
+    }
+    estimate
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::inputs;
+    use crate::strategy::StrategyPeriodic;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_estimate_finalize() {
+        let now = Utc.timestamp_opt(1_600_000_000, 0).unwrap();
+        let later = Utc.timestamp_opt(1_600_010_000, 0).unwrap();
+        let immediate = UpdateStrategy::default();
+        assert_eq!(
+            estimate_finalize(now, &immediate, None, None, None),
+            Some(now)
+        );
+
+        let blackout =
+            TemporaryBlackout::from_timestamps(1_599_999_000, 1_600_003_600, "", &now).unwrap();
+        assert_eq!(
+            estimate_finalize(now, &immediate, None, Some(&blackout), None),
+            Some(blackout.end)
+        );
+        assert_eq!(
+            estimate_finalize(later, &immediate, None, Some(&blackout), None),
+            Some(later)
+        );
+
+        // 2020-09-13 is a Sunday, next window is on Wednesday.
+        let cfg = inputs::PeriodicInput {
+            intervals: vec![inputs::PeriodicIntervalInput {
+                start_day: "Wed".to_string(),
+                start_time: "10:00".to_string(),
+                length_minutes: 60,
+                time_zone: None,
+            }],
+            ..Default::default()
+        };
+        let periodic = UpdateStrategy::Periodic(StrategyPeriodic::with_windows(cfg).unwrap());
+        let window = Utc.with_ymd_and_hms(2020, 9, 16, 10, 0, 0).unwrap();
+        assert_eq!(
+            estimate_finalize(now, &periodic, None, None, None),
+            Some(window)
+        );
+
+        let schedule = ScheduledFinalize::from_timestamp(1_600_007_200, &now).unwrap();
+        assert_eq!(
+            estimate_finalize(now, &periodic, Some(&schedule), None, None),
+            Some(schedule.finalize_at)
+        );
+        assert_eq!(
+            estimate_finalize(later, &periodic, Some(&schedule), None, None),
+            Some(window)
+        );
+    }
+}
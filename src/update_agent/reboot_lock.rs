@@ -0,0 +1,91 @@
+//! Reboot coordination with other reboot managers.
+//!
+//! Other tools on the host may also reboot it (e.g. to apply package updates
+//! or after `needs-restarting`). To avoid racing with them, the agent can take
+//! an exclusive advisory lock (`flock`) on a shared lock file right before
+//! finalizing an update, and back off while another manager holds it.
+//! Once taken, the lock is held until the reboot (or released if the
+//! finalization does not happen).
+
+use anyhow::{Context, Result};
+use fn_error_context::context;
+use std::fs::{File, OpenOptions};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+use std::path::PathBuf;
+
+/// Exclusive lock shared with other reboot managers.
+#[derive(Debug)]
+pub(crate) struct RebootLock {
+    /// Path to the lock file.
+    path: PathBuf,
+    /// Open lock file, while the lock is held.
+    held: Option<File>,
+}
+
+impl RebootLock {
+    /// Build a new (not yet held) lock on the given file.
+    pub(crate) fn new(path: PathBuf) -> Self {
+        Self { path, held: None }
+    }
+
+    /// Try to take the lock, without blocking.
+    ///
+    /// This returns `false` if the lock is currently held by another process.
+    #[context("failed to take reboot lock")]
+    pub(crate) fn try_acquire(&mut self) -> Result<bool> {
+        if self.held.is_some() {
+            return Ok(true);
+        }
+
+        // The file is shared with other reboot managers, which may store
+        // details about the current holder in it: never clobber its content,
+        // as it is opened before the lock is taken.
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .mode(0o644)
+            .open(&self.path)
+            .with_context(|| format!("failed to open '{}'", self.path.display()))?;
+        let ret = unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) };
+        if ret != 0 {
+            let err = std::io::Error::last_os_error();
+            if err.kind() == std::io::ErrorKind::WouldBlock {
+                return Ok(false);
+            }
+            return Err(err).context("flock failed");
+        }
+
+        self.held = Some(file);
+        Ok(true)
+    }
+
+    /// Release the lock, if held.
+    pub(crate) fn release(&mut self) {
+        // Closing the file releases the lock.
+        self.held = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reboot_lock() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let path = tmpdir.path().join("reboot.lock");
+        let mut ours = RebootLock::new(path.clone());
+        let mut other = RebootLock::new(path);
+
+        assert!(other.try_acquire().unwrap());
+        assert!(!ours.try_acquire().unwrap());
+
+        other.release();
+        assert!(ours.try_acquire().unwrap());
+        assert!(ours.try_acquire().unwrap());
+        assert!(!other.try_acquire().unwrap());
+    }
+}
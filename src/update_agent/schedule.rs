@@ -0,0 +1,153 @@
+//! One-time scheduled finalization.
+//!
+//! An administrator can request a single finalization (i.e. reboot) at a
+//! specific wall-clock time. If an update is staged by then, it is finalized
+//! regardless of update strategy constraints. The schedule is persisted to
+//! disk, so that it survives agent restarts.
+
+use crate::utils;
+use anyhow::{Context, Result};
+use chrono::{DateTime, TimeZone, Utc};
+use fn_error_context::context;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::time::Duration;
+
+/// Absolute path to the persisted scheduled finalization.
+pub(crate) static SCHEDULED_FINALIZE_PATH: &str = "/var/lib/zincati/scheduled-finalize.json";
+
+/// How far in the future a finalization can be scheduled, in days.
+const MAX_SCHEDULE_DAYS: i64 = 30;
+
+/// A one-time finalization, scheduled at a specific time.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub(crate) struct ScheduledFinalize {
+    /// Point in time at which a staged update should be finalized.
+    pub(crate) finalize_at: DateTime<Utc>,
+}
+
+impl ScheduledFinalize {
+    /// Build a new schedule from a UTC timestamp (seconds since epoch).
+    ///
+    /// Timestamps in the past, or more than `MAX_SCHEDULE_DAYS` in the
+    /// future (likely a mistake, e.g. milliseconds instead of seconds),
+    /// are rejected.
+    pub(crate) fn from_timestamp(timestamp: i64, now: &DateTime<Utc>) -> Result<Self> {
+        let finalize_at = match Utc.timestamp_opt(timestamp, 0).single() {
+            Some(dt) => dt,
+            None => anyhow::bail!("invalid timestamp {}", timestamp),
+        };
+        if finalize_at <= *now {
+            anyhow::bail!(
+                "scheduled time {} is in the past",
+                finalize_at.format("%a %Y-%m-%d %H:%M:%S %Z")
+            );
+        }
+        if finalize_at > *now + chrono::Duration::days(MAX_SCHEDULE_DAYS) {
+            anyhow::bail!(
+                "scheduled time {} is more than {} days in the future",
+                finalize_at.format("%a %Y-%m-%d %H:%M:%S %Z"),
+                MAX_SCHEDULE_DAYS
+            );
+        }
+
+        Ok(Self { finalize_at })
+    }
+
+    /// Return the UTC timestamp (seconds since epoch) of this schedule.
+    pub(crate) fn timestamp(&self) -> i64 {
+        self.finalize_at.timestamp()
+    }
+
+    /// Return whether the scheduled time has been reached.
+    pub(crate) fn is_due(&self, now: &DateTime<Utc>) -> bool {
+        self.finalize_at <= *now
+    }
+
+    /// Return the time remaining until the scheduled time, if in the future.
+    pub(crate) fn remaining(&self, now: &DateTime<Utc>) -> Option<Duration> {
+        self.finalize_at.signed_duration_since(*now).to_std().ok()
+    }
+
+    /// Return the scheduled time, in human terms.
+    pub(crate) fn human_time(&self) -> String {
+        self.finalize_at
+            .format("%a %Y-%m-%d %H:%M:%S %Z")
+            .to_string()
+    }
+
+    /// Load a persisted schedule from `path`, if any.
+    #[context("failed to load scheduled finalization")]
+    pub(crate) fn load(path: impl AsRef<Path>) -> Result<Option<Self>> {
+        let path = path.as_ref();
+        let content = match std::fs::read(path) {
+            Ok(c) => c,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => {
+                return Err(e).with_context(|| format!("failed to read '{}'", path.display()))
+            }
+        };
+        let schedule = serde_json::from_slice(&content)
+            .with_context(|| format!("failed to parse '{}'", path.display()))?;
+
+        Ok(Some(schedule))
+    }
+
+    /// Persist this schedule to `path`.
+    #[context("failed to persist scheduled finalization")]
+    pub(crate) fn persist(&self, path: impl AsRef<Path>) -> Result<()> {
+        let content = serde_json::to_vec(self)?;
+        utils::atomic_write(path, 0o644, &content)
+    }
+
+    /// Remove the persisted schedule at `path`, if any.
+    #[context("failed to remove scheduled finalization")]
+    pub(crate) fn remove(path: impl AsRef<Path>) -> Result<()> {
+        utils::remove_if_exists(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_timestamp() {
+        let now = Utc.timestamp_opt(1_600_000_000, 0).unwrap();
+
+        ScheduledFinalize::from_timestamp(1_500_000_000, &now).unwrap_err();
+        ScheduledFinalize::from_timestamp(1_600_000_000, &now).unwrap_err();
+        // Milliseconds instead of seconds.
+        ScheduledFinalize::from_timestamp(1_600_000_060_000, &now).unwrap_err();
+
+        let schedule = ScheduledFinalize::from_timestamp(1_600_000_060, &now).unwrap();
+        assert_eq!(schedule.timestamp(), 1_600_000_060);
+        assert!(!schedule.is_due(&now));
+        assert_eq!(schedule.remaining(&now), Some(Duration::from_secs(60)));
+
+        let later = Utc.timestamp_opt(1_600_000_060, 0).unwrap();
+        assert!(schedule.is_due(&later));
+        assert_eq!(
+            schedule.remaining(&Utc.timestamp_opt(1_600_000_061, 0).unwrap()),
+            None
+        );
+    }
+
+    #[test]
+    fn test_persist_roundtrip() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let path = tmpdir.path().join("scheduled-finalize.json");
+
+        assert_eq!(ScheduledFinalize::load(&path).unwrap(), None);
+
+        let now = Utc.timestamp_opt(1_600_000_000, 0).unwrap();
+        let schedule = ScheduledFinalize::from_timestamp(1_600_003_600, &now).unwrap();
+        schedule.persist(&path).unwrap();
+        assert_eq!(ScheduledFinalize::load(&path).unwrap(), Some(schedule));
+
+        ScheduledFinalize::remove(&path).unwrap();
+        assert_eq!(ScheduledFinalize::load(&path).unwrap(), None);
+        // Removing a missing schedule is not an error.
+        ScheduledFinalize::remove(&path).unwrap();
+    }
+}
@@ -0,0 +1,102 @@
+//! Shutdown records.
+//!
+//! When the agent is stopped (e.g. on service stop), it records the state it
+//! was in. On the next start the record is logged and cleared: the state
+//! machine itself is re-derived from rpm-ostree deployments, thus a
+//! half-finished update flow is simply resumed.
+
+use crate::utils;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use fn_error_context::context;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// Absolute path to the persisted shutdown record.
+pub(crate) static SHUTDOWN_RECORD_PATH: &str = "/var/lib/zincati/shutdown.json";
+
+/// State of the agent when it was last stopped.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub(crate) struct ShutdownRecord {
+    /// Name of the agent state.
+    pub(crate) state: String,
+    /// Version of the target release, if any.
+    pub(crate) target_version: Option<String>,
+    /// Point in time at which the agent was stopped.
+    pub(crate) stopped_at: DateTime<Utc>,
+}
+
+impl ShutdownRecord {
+    /// Build a new record for the given state.
+    pub(crate) fn new(state: &str, target_version: Option<&str>, now: DateTime<Utc>) -> Self {
+        Self {
+            state: state.to_string(),
+            target_version: target_version.map(String::from),
+            stopped_at: now,
+        }
+    }
+
+    /// Return a human-readable description of this record.
+    pub(crate) fn describe(&self) -> String {
+        let target = match &self.target_version {
+            Some(version) => format!(" (target {})", version),
+            None => String::new(),
+        };
+        format!(
+            "stopped in state {}{} at {}",
+            self.state,
+            target,
+            self.stopped_at.format("%a %Y-%m-%d %H:%M:%S %Z")
+        )
+    }
+
+    /// Load and remove a persisted record from `path`, if any.
+    #[context("failed to load shutdown record")]
+    pub(crate) fn take(path: impl AsRef<Path>) -> Result<Option<Self>> {
+        let path = path.as_ref();
+        let content = match std::fs::read(path) {
+            Ok(c) => c,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => {
+                return Err(e).with_context(|| format!("failed to read '{}'", path.display()))
+            }
+        };
+        utils::remove_if_exists(path)?;
+        let record = serde_json::from_slice(&content)
+            .with_context(|| format!("failed to parse '{}'", path.display()))?;
+
+        Ok(Some(record))
+    }
+
+    /// Persist this record to `path`.
+    #[context("failed to persist shutdown record")]
+    pub(crate) fn persist(&self, path: impl AsRef<Path>) -> Result<()> {
+        let content = serde_json::to_vec(self)?;
+        utils::atomic_write(path, 0o644, &content)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_persist_take() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let path = tmpdir.path().join("shutdown.json");
+
+        assert_eq!(ShutdownRecord::take(&path).unwrap(), None);
+
+        let now = Utc.timestamp_opt(1_600_000_000, 0).unwrap();
+        let record = ShutdownRecord::new("UpdateAvailable", Some("33.20201201.3.0"), now);
+        assert!(record
+            .describe()
+            .starts_with("stopped in state UpdateAvailable (target 33.20201201.3.0) at "));
+        record.persist(&path).unwrap();
+        assert_eq!(ShutdownRecord::take(&path).unwrap(), Some(record));
+
+        // Records are consumed on load.
+        assert_eq!(ShutdownRecord::take(&path).unwrap(), None);
+    }
+}
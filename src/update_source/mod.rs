@@ -0,0 +1,135 @@
+//! Sources of update hints.
+//!
+//! The update agent discovers update targets through an `UpdateSource`
+//! backend, selected via configuration:
+//!  * `cincinnati`: update graph served by a Cincinnati service (default);
+//!  * `ostree-remote`: head of a ref on an OSTree remote;
+//!  * `static-graph`: update graph read from a local JSON file.
+
+mod static_graph;
+pub use static_graph::StaticGraph;
+
+use crate::cincinnati::Cincinnati;
+use crate::config::inputs;
+use crate::identity::Identity;
+use crate::network::NetworkSettings;
+use crate::ostree_remote::OstreeRemote;
+use crate::rpm_ostree::Release;
+use anyhow::Result;
+use fn_error_context::context;
+use futures::prelude::*;
+use prometheus::IntGaugeVec;
+use serde::{Serialize, Serializer};
+use std::collections::BTreeSet;
+use std::fmt::Debug;
+use std::pin::Pin;
+
+lazy_static::lazy_static! {
+    static ref SOURCE_MODE: IntGaugeVec = register_int_gauge_vec!(
+        "zincati_updates_source_mode",
+        "Update source in use",
+        &["source"]
+    ).unwrap();
+}
+
+/// Backend discovering update targets.
+pub trait UpdateSource: Debug {
+    /// Return the configuration label/name for this update source.
+    fn label(&self) -> &'static str;
+
+    /// Fetch next update-hint from this source.
+    ///
+    /// Local deployments (booted one excluded) are not valid update targets.
+    /// Errors are handled (logged and recorded) by the source itself.
+    fn fetch_update_hint(
+        &self,
+        id: &Identity,
+        deployments: BTreeSet<Release>,
+        allow_downgrade: bool,
+    ) -> Pin<Box<dyn Future<Output = Option<Release>>>>;
+}
+
+impl Serialize for dyn UpdateSource {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.label())
+    }
+}
+
+/// Try to parse config inputs into a valid update source.
+#[context("failed to validate update source configuration")]
+pub fn with_config(
+    cincinnati: inputs::CincinnatiInput,
+    updates: &inputs::UpdateInput,
+    identity: &Identity,
+    network: &NetworkSettings,
+) -> Result<Box<dyn UpdateSource>> {
+    let source: Box<dyn UpdateSource> = match updates.source.as_ref() {
+        Cincinnati::LABEL | "" => {
+            let c = Cincinnati::with_config(cincinnati, identity, network, &updates.skip_versions)?;
+            Box::new(c)
+        }
+        OstreeRemote::LABEL => {
+            let o = OstreeRemote::with_config(updates.ostree_remote.clone())?;
+            Box::new(o)
+        }
+        StaticGraph::LABEL => {
+            let s = StaticGraph::with_config(updates.static_graph.clone(), &updates.skip_versions)?;
+            Box::new(s)
+        }
+        x => anyhow::bail!("unsupported update source '{}'", x),
+    };
+
+    Ok(source)
+}
+
+/// Refresh source-related metrics values.
+pub fn refresh_metrics(source: &dyn UpdateSource) {
+    SOURCE_MODE.with_label_values(&[source.label()]).set(1);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::runtime as rt;
+
+    /// Test stub, always offering the same update target.
+    #[derive(Debug)]
+    struct StubSource {
+        next: Option<Release>,
+    }
+
+    impl UpdateSource for StubSource {
+        fn label(&self) -> &'static str {
+            "stub"
+        }
+
+        fn fetch_update_hint(
+            &self,
+            _id: &Identity,
+            _deployments: BTreeSet<Release>,
+            _allow_downgrade: bool,
+        ) -> Pin<Box<dyn Future<Output = Option<Release>>>> {
+            Box::pin(futures::future::ready(self.next.clone()))
+        }
+    }
+
+    #[test]
+    fn stub_source() {
+        let release = Release {
+            version: "v1".to_string(),
+            checksum: "sha1".to_string(),
+            age_index: None,
+            advisory: None,
+            wave: None,
+        };
+        let source: Box<dyn UpdateSource> = Box::new(StubSource {
+            next: Some(release.clone()),
+        });
+        assert_eq!(serde_json::to_string(&source).unwrap(), r#""stub""#);
+
+        let runtime = rt::Runtime::new().unwrap();
+        let id = Identity::mock_default();
+        let update = runtime.block_on(source.fetch_update_hint(&id, BTreeSet::new(), false));
+        assert_eq!(update, Some(release));
+    }
+}
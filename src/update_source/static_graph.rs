@@ -0,0 +1,164 @@
+//! Update source reading a static update graph from a local file.
+//!
+//! The file contains an update graph in Cincinnati JSON format, and is
+//! re-read on every check. This is mostly useful for testing and for
+//! environments where the graph is distributed out-of-band.
+
+use super::UpdateSource;
+use crate::cincinnati::{self, Graph};
+use crate::config::inputs;
+use crate::identity::Identity;
+use crate::rpm_ostree::Release;
+use anyhow::{Context, Result};
+use fn_error_context::context;
+use futures::prelude::*;
+use serde::Serialize;
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+use std::pin::Pin;
+
+/// Static update graph configuration.
+#[derive(Clone, Debug, Serialize)]
+pub struct StaticGraph {
+    /// Path to the update graph (JSON).
+    pub path: PathBuf,
+    /// Release versions never to be selected as update targets.
+    pub skip_versions: BTreeSet<String>,
+}
+
+impl StaticGraph {
+    /// Source label/name.
+    pub const LABEL: &'static str = "static-graph";
+
+    /// Process static graph configuration.
+    #[context("failed to validate static-graph configuration")]
+    pub fn with_config(cfg: inputs::StaticGraphInput, skip_versions: &[String]) -> Result<Self> {
+        if cfg.path.is_empty() {
+            anyhow::bail!("empty static graph path");
+        }
+        let path = PathBuf::from(cfg.path);
+        if !path.is_absolute() {
+            anyhow::bail!("static graph path '{}' is not absolute", path.display());
+        }
+        log::info!("static update graph: {}", path.display());
+
+        Ok(Self {
+            path,
+            skip_versions: skip_versions.iter().cloned().collect(),
+        })
+    }
+
+    /// Read and parse the update graph.
+    #[context("failed to read update graph '{}'", self.path.display())]
+    fn read_graph(&self) -> Result<Graph> {
+        let content = std::fs::read(&self.path)?;
+        let graph = serde_json::from_slice(&content).context("failed to parse JSON")?;
+        Ok(graph)
+    }
+}
+
+impl UpdateSource for StaticGraph {
+    fn label(&self) -> &'static str {
+        Self::LABEL
+    }
+
+    /// Fetch next update-hint from the static graph.
+    fn fetch_update_hint(
+        &self,
+        id: &Identity,
+        deployments: BTreeSet<Release>,
+        allow_downgrade: bool,
+    ) -> Pin<Box<dyn Future<Output = Option<Release>>>> {
+        log::trace!("checking static update graph for updates");
+
+        let booted = id.current_os.clone();
+        let min_age = id.min_release_age();
+        let update = self
+            .read_graph()
+            .and_then(|graph| {
+                cincinnati::find_update(
+                    graph,
+                    booted,
+                    deployments,
+                    allow_downgrade,
+                    min_age,
+                    &self.skip_versions,
+                    cincinnati::TargetPolicy::default(),
+                )
+                .map_err(|e| anyhow::anyhow!("{}", e))
+            })
+            .unwrap_or_else(|e| {
+                log::error!("failed to check static graph for updates: {:#}", e);
+                None
+            });
+        Box::pin(futures::future::ready(update))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tokio::runtime as rt;
+
+    #[test]
+    fn static_graph_update() {
+        let graph = r#"
+{
+  "nodes": [
+    {
+      "version": "0.0.0-mock",
+      "metadata": {
+        "org.fedoraproject.coreos.scheme": "checksum",
+        "org.fedoraproject.coreos.releases.age_index": "0"
+      },
+      "payload": "sha-mock"
+    },
+    {
+      "version": "30.20190725.0",
+      "metadata": {
+        "org.fedoraproject.coreos.scheme": "checksum",
+        "org.fedoraproject.coreos.releases.age_index": "1"
+      },
+      "payload": "8b79877efa7ac06becd8637d95f8ca83aa385f89f383288bf3c2c31ca53216c7"
+    }
+  ],
+  "edges": [ [ 0, 1 ] ]
+}
+"#;
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(graph.as_bytes()).unwrap();
+
+        let cfg = inputs::StaticGraphInput {
+            path: file.path().display().to_string(),
+        };
+        let source = StaticGraph::with_config(cfg, &[]).unwrap();
+        let id = Identity::mock_default();
+        let runtime = rt::Runtime::new().unwrap();
+        let update = runtime
+            .block_on(source.fetch_update_hint(&id, BTreeSet::new(), false))
+            .unwrap();
+        assert_eq!(update.version, "30.20190725.0");
+
+        // Unreadable graphs are not fatal.
+        let missing = StaticGraph {
+            path: PathBuf::from("/missing/graph.json"),
+            skip_versions: BTreeSet::new(),
+        };
+        let update = runtime.block_on(missing.fetch_update_hint(&id, BTreeSet::new(), false));
+        assert_eq!(update, None);
+    }
+
+    #[test]
+    fn invalid_config() {
+        let relative = inputs::StaticGraphInput {
+            path: "graph.json".to_string(),
+        };
+        StaticGraph::with_config(relative, &[]).unwrap_err();
+
+        let empty = inputs::StaticGraphInput {
+            path: String::new(),
+        };
+        StaticGraph::with_config(empty, &[]).unwrap_err();
+    }
+}
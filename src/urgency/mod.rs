@@ -0,0 +1,133 @@
+//! Urgency override for security-critical updates.
+//!
+//! Releases flagged with a high enough severity in update graph metadata
+//! can be finalized regardless of the update strategy (e.g. outside of
+//! `periodic` windows, or without a `fleet_lock` slot), once a grace period
+//! has elapsed since they were first ready to be finalized. All other
+//! finalization checks (blackouts, approvals, health checks) still apply.
+
+use crate::config::inputs;
+use crate::rpm_ostree::{Release, SEVERITIES};
+use anyhow::{ensure, Result};
+use chrono::{DateTime, Utc};
+use fn_error_context::context;
+use serde::Serialize;
+
+/// Settings for overriding the update strategy on urgent releases.
+#[derive(Clone, Debug, Serialize)]
+pub struct UrgencyOverride {
+    /// Minimum severity for a release to be urgent.
+    min_severity: String,
+    /// Delay before overriding the update strategy.
+    #[serde(skip)]
+    grace_period: chrono::Duration,
+}
+
+impl UrgencyOverride {
+    /// Process urgency override configuration.
+    ///
+    /// This returns `None` if the override is not enabled.
+    #[context("failed to validate urgency override configuration")]
+    pub fn with_config(cfg: inputs::UrgencyInput) -> Result<Option<Self>> {
+        if !cfg.enabled {
+            return Ok(None);
+        }
+        let min_severity = cfg.min_severity.trim().to_lowercase();
+        ensure!(
+            SEVERITIES.contains(&min_severity.as_str()),
+            "unknown minimum severity '{}', expected one of: {}",
+            cfg.min_severity,
+            SEVERITIES.join(", ")
+        );
+        let minutes = cfg.grace_period_minutes.min(u64::from(u32::MAX));
+        let grace_period = chrono::Duration::minutes(minutes as i64);
+
+        let urgency = Self {
+            min_severity,
+            grace_period,
+        };
+        Ok(Some(urgency))
+    }
+
+    /// Return the severity of `release`, if it is urgent.
+    pub fn urgent_severity<'r>(&self, release: &'r Release) -> Option<&'r str> {
+        let severity = release.advisory.as_ref()?.severity.as_deref()?;
+        if rank(severity) >= rank(&self.min_severity) {
+            Some(severity)
+        } else {
+            None
+        }
+    }
+
+    /// Return the time at which the update strategy is overridden, for an
+    /// urgent release ready to be finalized since `since`.
+    pub fn due_time(&self, since: &DateTime<Utc>) -> DateTime<Utc> {
+        *since + self.grace_period
+    }
+}
+
+/// Return the rank of a severity, higher being more severe.
+fn rank(severity: &str) -> Option<usize> {
+    SEVERITIES.iter().position(|s| *s == severity)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rpm_ostree::Advisory;
+
+    fn input(min_severity: &str, grace_period_minutes: u64) -> inputs::UrgencyInput {
+        inputs::UrgencyInput {
+            enabled: true,
+            min_severity: min_severity.to_string(),
+            grace_period_minutes,
+        }
+    }
+
+    fn release(severity: Option<&str>) -> Release {
+        Release {
+            version: "v1".to_string(),
+            checksum: "c1".to_string(),
+            age_index: Some(1),
+            advisory: severity.map(|s| Advisory {
+                severity: Some(s.to_string()),
+                ..Advisory::default()
+            }),
+            wave: None,
+        }
+    }
+
+    #[test]
+    fn urgency_with_config() {
+        let disabled = inputs::UrgencyInput::default();
+        assert!(UrgencyOverride::with_config(disabled).unwrap().is_none());
+
+        UrgencyOverride::with_config(input("urgent", 60)).unwrap_err();
+
+        let urgency = UrgencyOverride::with_config(input("Important", 90))
+            .unwrap()
+            .unwrap();
+        let since = Utc::now();
+        assert_eq!(
+            urgency.due_time(&since),
+            since + chrono::Duration::minutes(90)
+        );
+    }
+
+    #[test]
+    fn urgency_severity() {
+        let urgency = UrgencyOverride::with_config(input("important", 0))
+            .unwrap()
+            .unwrap();
+        assert_eq!(urgency.urgent_severity(&release(None)), None);
+        assert_eq!(urgency.urgent_severity(&release(Some("moderate"))), None);
+        assert_eq!(
+            urgency.urgent_severity(&release(Some("important"))),
+            Some("important")
+        );
+        assert_eq!(
+            urgency.urgent_severity(&release(Some("critical"))),
+            Some("critical")
+        );
+    }
+}
@@ -0,0 +1,265 @@
+//! Miscellaneous utilities.
+
+use anyhow::{Context, Result};
+use fn_error_context::context;
+use libsystemd::daemon::{notify, watchdog_enabled, NotifyState};
+use std::ffi::OsStr;
+use std::fs::Permissions;
+use std::io::Write;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Prefix for path settings referring to systemd credentials.
+pub static CREDENTIAL_PREFIX: &str = "credential:";
+
+/// Atomically write `content` to the file at `path`, with the given mode.
+///
+/// This avoids exposing partially-written files, by writing content to a
+/// tempfile and then persisting (renaming) it to its final destination.
+#[context("failed to write file '{}'", path.as_ref().display())]
+pub fn atomic_write(path: impl AsRef<Path>, mode: u32, content: &[u8]) -> Result<()> {
+    let path = path.as_ref();
+    let dir = path.parent().context("missing parent directory")?;
+    let prefix = match path.file_name() {
+        Some(name) => format!(".{}.", name.to_string_lossy()),
+        None => anyhow::bail!("missing file name"),
+    };
+
+    let mut f = tempfile::Builder::new()
+        .prefix(&prefix)
+        .suffix(".partial")
+        // Create the tempfile in the same directory as the final file, to
+        // ensure proper SELinux labels are applied to the tempfile before
+        // renaming.
+        .tempfile_in(dir)
+        .with_context(|| format!("failed to create temporary file under '{}'", dir.display()))?;
+    // Set correct permissions of the temporary file, before moving to
+    // the destination (`tempfile` creates files with mode 0600).
+    std::fs::set_permissions(f.path(), Permissions::from_mode(mode)).with_context(|| {
+        format!(
+            "failed to set permissions of temporary file at '{}'",
+            f.path().display()
+        )
+    })?;
+
+    f.write_all(content)
+        .and_then(|_| f.flush())
+        .with_context(|| format!("failed to write content to '{}'", f.path().display()))?;
+
+    f.persist(path)
+        .with_context(|| format!("failed to persist temporary file to '{}'", path.display()))?;
+    Ok(())
+}
+
+/// Remove the file at `path`, if any.
+#[context("failed to remove file '{}'", path.as_ref().display())]
+pub fn remove_if_exists(path: impl AsRef<Path>) -> Result<()> {
+    if let Err(e) = std::fs::remove_file(path.as_ref()) {
+        if e.kind() != std::io::ErrorKind::NotFound {
+            return Err(e.into());
+        }
+    }
+    Ok(())
+}
+
+/// Resolve a path setting, which may refer to a systemd credential.
+///
+/// Values in the form `credential:<name>` refer to credentials passed to the
+/// service by systemd (e.g. via `LoadCredential=` or `ImportCredential=`),
+/// and resolve to files under `$CREDENTIALS_DIRECTORY`. Other values are
+/// plain paths.
+pub fn resolve_credential_path(input: &str) -> Result<PathBuf> {
+    let creds_dir = std::env::var_os("CREDENTIALS_DIRECTORY");
+    resolve_credential_path_in(input, creds_dir.as_deref())
+}
+
+/// Resolve a path setting, with credentials under `creds_dir` (if any).
+fn resolve_credential_path_in(input: &str, creds_dir: Option<&OsStr>) -> Result<PathBuf> {
+    let name = match input.strip_prefix(CREDENTIAL_PREFIX) {
+        Some(name) => name.trim(),
+        None => return Ok(PathBuf::from(input)),
+    };
+    if name.is_empty() || name.contains('/') || name == "." || name == ".." {
+        anyhow::bail!("invalid credential name '{}'", name);
+    }
+    let dir = match creds_dir {
+        Some(dir) if !dir.is_empty() => dir,
+        _ => anyhow::bail!(
+            "credential '{}' requested, but no systemd credentials are available",
+            name
+        ),
+    };
+    Ok(Path::new(dir).join(name))
+}
+
+/// Machine-stable summary of the agent state, prefixed to unit status messages.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct StatusSummary<'a> {
+    /// Compact state label (e.g. `staged`).
+    pub state: &'a str,
+    /// Target release version, if any.
+    pub target: Option<&'a str>,
+    /// Compact reason label (e.g. `window`), if any.
+    pub reason: Option<&'a str>,
+}
+
+impl<'a> StatusSummary<'a> {
+    /// Build a summary for the given state label.
+    pub fn new(state: &'a str) -> Self {
+        Self {
+            state,
+            ..Self::default()
+        }
+    }
+
+    /// Set the target release version.
+    pub fn target(mut self, version: &'a str) -> Self {
+        self.target = Some(version);
+        self
+    }
+
+    /// Set the reason label.
+    pub fn reason(mut self, reason: &'a str) -> Self {
+        self.reason = Some(reason);
+        self
+    }
+}
+
+/// Format a unit status message, prefixed by its machine-stable summary.
+///
+/// The prefix always has the form `state=<state> target=<target> reason=<reason>`,
+/// followed by a space and the human-readable text. Unset values are
+/// rendered as `-`, and characters outside of `[A-Za-z0-9._:+-]` are
+/// replaced by `_`, so that fields never contain whitespace.
+pub fn format_unit_status(summary: &StatusSummary, text: &str) -> String {
+    fn field(value: Option<&str>) -> String {
+        match value {
+            None | Some("") => "-".to_string(),
+            Some(v) => v
+                .chars()
+                .map(|c| match c {
+                    'A'..='Z' | 'a'..='z' | '0'..='9' | '.' | '_' | ':' | '+' | '-' => c,
+                    _ => '_',
+                })
+                .collect(),
+        }
+    }
+
+    format!(
+        "state={} target={} reason={} {}",
+        field(Some(summary.state)),
+        field(summary.target),
+        field(summary.reason),
+        text
+    )
+}
+
+/// Helper function to send notification to the service manager about service status changes.
+/// Log errors if unsuccessful.
+///
+/// The status is prefixed by a machine-stable summary, see `format_unit_status`.
+pub fn update_unit_status(summary: StatusSummary, text: &str) {
+    let status = format_unit_status(&summary, text);
+    match notify(false, &[NotifyState::Status(status)]) {
+        Err(e) => log::error!(
+            "failed to notify service manager about service status change: {}",
+            e
+        ),
+        Ok(sent) => {
+            if !sent {
+                log::error!(
+                    "update_unit_status: status notifications not supported for this service"
+                );
+            }
+        }
+    }
+}
+
+/// Return the interval for sending keep-alives to the service manager, if
+/// the watchdog is enabled for this service.
+///
+/// This is half of the configured watchdog timeout, as recommended by
+/// `sd_watchdog_enabled(3)`.
+pub fn watchdog_interval() -> Option<Duration> {
+    watchdog_enabled(false)
+        .filter(|timeout| *timeout > Duration::from_secs(0))
+        .map(|timeout| timeout / 2)
+}
+
+/// Send a keep-alive to the service manager watchdog.
+/// Log errors if unsuccessful.
+pub fn notify_watchdog() {
+    if let Err(e) = notify(false, &[NotifyState::Watchdog]) {
+        log::error!("failed to send keep-alive to service manager: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_atomic_write() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let path = tmpdir.path().join("sample.txt");
+
+        atomic_write(&path, 0o644, b"foo\n").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "foo\n");
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o644);
+
+        // Overwrite existing content.
+        atomic_write(&path, 0o600, b"bar\n").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "bar\n");
+
+        // No leftover temporary files.
+        assert_eq!(std::fs::read_dir(tmpdir.path()).unwrap().count(), 1);
+
+        remove_if_exists(&path).unwrap();
+        assert!(!path.exists());
+        remove_if_exists(&path).unwrap();
+    }
+
+    #[test]
+    fn test_resolve_credential_path() {
+        let creds_dir = OsStr::new("/run/credentials/zincati.service");
+
+        let plain = resolve_credential_path_in("/etc/pki/client.key", Some(creds_dir)).unwrap();
+        assert_eq!(plain, PathBuf::from("/etc/pki/client.key"));
+
+        let cred = resolve_credential_path_in("credential:client.key", Some(creds_dir)).unwrap();
+        assert_eq!(
+            cred,
+            PathBuf::from("/run/credentials/zincati.service/client.key")
+        );
+
+        resolve_credential_path_in("credential:client.key", None).unwrap_err();
+        resolve_credential_path_in("credential:", Some(creds_dir)).unwrap_err();
+        resolve_credential_path_in("credential:../shadow", Some(creds_dir)).unwrap_err();
+    }
+
+    #[test]
+    fn test_format_unit_status() {
+        let steady = StatusSummary::new("steady");
+        assert_eq!(
+            format_unit_status(&steady, "periodically polling for updates"),
+            "state=steady target=- reason=- periodically polling for updates"
+        );
+
+        let staged = StatusSummary::new("staged")
+            .target("39.20240101.3.0")
+            .reason("strategy");
+        assert_eq!(
+            format_unit_status(&staged, "update staged: 39.20240101.3.0; reboot pending"),
+            "state=staged target=39.20240101.3.0 reason=strategy update staged: 39.20240101.3.0; reboot pending"
+        );
+
+        // Fields never contain whitespace.
+        let odd = StatusSummary::new("staged").target("v 1=2").reason("");
+        assert_eq!(
+            format_unit_status(&odd, ""),
+            "state=staged target=v_1_2 reason=- "
+        );
+    }
+}
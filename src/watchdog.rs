@@ -0,0 +1,100 @@
+//! Service manager watchdog.
+//!
+//! When the systemd watchdog is enabled (`WatchdogSec=`), keep-alives are sent
+//! from a task on the actor system, so that they stop if the event loop gets
+//! wedged. Each keep-alive is gated on the update agent answering a probe.
+//! A long-running operation (e.g. staging an update via rpm-ostree) keeps the
+//! agent busy without starving the watchdog: the agent is only considered
+//! wedged once it has been unresponsive for longer than `STALL_LIMIT_SECS`,
+//! at which point keep-alives stop and the service manager restarts the agent.
+
+use crate::update_agent::{LastRefresh, UpdateAgent};
+use crate::utils;
+use actix::Addr;
+use futures::future::{self, Either};
+use prometheus::{IntCounter, IntGauge};
+use std::time::{Duration, Instant};
+
+lazy_static::lazy_static! {
+    static ref KEEPALIVES: IntCounter = register_int_counter!(opts!(
+        "zincati_watchdog_keepalives_total",
+        "Total number of keep-alives sent to the service manager watchdog."
+    )).unwrap();
+    static ref AGENT_UNRESPONSIVE: IntGauge = register_int_gauge!(opts!(
+        "zincati_watchdog_agent_unresponsive_seconds",
+        "Time since the update agent stopped answering watchdog probes."
+    )).unwrap();
+}
+
+/// Maximum time the agent can stay unresponsive before keep-alives stop (in seconds).
+const STALL_LIMIT_SECS: u64 = 2 * 60 * 60;
+
+/// Keep-alive sender for the service manager watchdog.
+#[derive(Debug)]
+pub(crate) struct Watchdog {
+    interval: Duration,
+    agent_addr: Addr<UpdateAgent>,
+    /// Since when the agent has not answered probes, if it is unresponsive.
+    unresponsive_since: Option<Instant>,
+}
+
+impl Watchdog {
+    /// Create a keep-alive sender, if the watchdog is enabled for this service.
+    pub(crate) fn new(agent_addr: Addr<UpdateAgent>) -> Option<Self> {
+        let interval = utils::watchdog_interval()?;
+        let watchdog = Self {
+            interval,
+            agent_addr,
+            unresponsive_since: None,
+        };
+        Some(watchdog)
+    }
+
+    /// Send keep-alives periodically, as a task on the actor system.
+    pub(crate) fn spawn(mut self) {
+        log::debug!(
+            "sending watchdog keep-alives every {} milliseconds",
+            self.interval.as_millis()
+        );
+        actix::spawn(async move {
+            // Half of the interval is for probing, half for sleeping.
+            let pause = self.interval / 2;
+            loop {
+                if self.probe(pause).await {
+                    utils::notify_watchdog();
+                    KEEPALIVES.inc();
+                }
+                actix::clock::sleep(pause).await;
+            }
+        });
+    }
+
+    /// Probe the agent, returning whether it is considered healthy.
+    async fn probe(&mut self, timeout: Duration) -> bool {
+        let probe = Box::pin(self.agent_addr.send(LastRefresh {}));
+        let timeout = actix::clock::sleep(timeout);
+        let responsive = match future::select(probe, Box::pin(timeout)).await {
+            Either::Left((res, _)) => res.is_ok(),
+            Either::Right(_) => false,
+        };
+        if responsive {
+            if self.unresponsive_since.take().is_some() {
+                log::debug!("update agent answering watchdog probes again");
+            }
+            AGENT_UNRESPONSIVE.set(0);
+            return true;
+        }
+
+        let since = *self.unresponsive_since.get_or_insert_with(Instant::now);
+        let elapsed = since.elapsed();
+        AGENT_UNRESPONSIVE.set(elapsed.as_secs() as i64);
+        if elapsed < Duration::from_secs(STALL_LIMIT_SECS) {
+            return true;
+        }
+        log::error!(
+            "update agent unresponsive for {} seconds, stopping watchdog keep-alives",
+            elapsed.as_secs()
+        );
+        false
+    }
+}
@@ -0,0 +1,304 @@
+//! Delivery of agent events to a webhook.
+//!
+//! Fleet dashboards often want to be told about update progress instead of
+//! scraping every node. When a webhook is configured, the agent POSTs its
+//! events (e.g. state changes) to it as JSON. Events are first appended to
+//! a durable on-disk queue, so that nodes which are temporarily offline
+//! deliver them in order once connectivity returns.
+
+use crate::config::inputs;
+use crate::event_queue::{Dropped, EventQueue};
+use crate::identity::Identity;
+use crate::network::NetworkSettings;
+use anyhow::{Context, Result};
+use fn_error_context::context;
+use futures::prelude::*;
+use prometheus::{IntCounterVec, IntGauge};
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::pin::Pin;
+use std::time::Duration;
+
+/// Path to the queue of events not yet delivered.
+pub static WEBHOOK_QUEUE_PATH: &str = "/var/lib/zincati/webhook-events.json";
+
+/// Timeout for a single webhook request (in seconds).
+const REQUEST_TIMEOUT_SECS: u64 = 30;
+
+/// Maximum number of events sent in a single request.
+const MAX_BATCH_LEN: usize = 50;
+
+lazy_static::lazy_static! {
+    static ref QUEUED_EVENTS: IntGauge = register_int_gauge!(opts!(
+        "zincati_webhook_queued_events",
+        "Number of agent events queued for the webhook."
+    )).unwrap();
+    static ref DROPPED_EVENTS: IntCounterVec = register_int_counter_vec!(
+        "zincati_webhook_dropped_events_total",
+        "Total number of queued agent events dropped before delivery.",
+        &["reason"]
+    ).unwrap();
+    static ref FAILURES: IntCounterVec = register_int_counter_vec!(
+        "zincati_webhook_failures_total",
+        "Total number of failed webhook deliveries.",
+        &["kind"]
+    ).unwrap();
+}
+
+/// An agent event.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct Event {
+    /// Event kind (e.g. `state-change`).
+    pub kind: String,
+    /// UTC timestamp of the event.
+    pub time: i64,
+    /// Agent state at the time of the event.
+    pub state: String,
+    /// Target release version, if any.
+    pub version: Option<String>,
+    /// Additional details (e.g. a failure message), if any.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub detail: String,
+}
+
+impl Event {
+    /// Build an event of the given kind, timestamped now.
+    pub fn new(kind: &str, state: &str, version: Option<&str>) -> Self {
+        Self {
+            kind: kind.to_string(),
+            time: chrono::Utc::now().timestamp(),
+            state: state.to_string(),
+            version: version.map(String::from),
+            detail: String::new(),
+        }
+    }
+
+    /// Attach additional details to this event.
+    pub fn detail(mut self, detail: &str) -> Self {
+        self.detail = detail.to_string();
+        self
+    }
+}
+
+/// Body of a webhook request.
+#[derive(Debug, Serialize)]
+struct Delivery<'a> {
+    node_uuid: &'a str,
+    group: &'a str,
+    events: Vec<&'a Event>,
+}
+
+/// Webhook for agent events.
+#[derive(Clone, Debug, Serialize)]
+pub struct Webhook {
+    /// Endpoint receiving agent events.
+    pub url: Url,
+    /// Queue of events not yet delivered.
+    queue: EventQueue,
+    /// Node UUID, as reported to the endpoint.
+    node_uuid: String,
+    /// Update group, as reported to the endpoint.
+    group: String,
+    /// HTTP client.
+    #[serde(skip)]
+    hclient: reqwest::Client,
+}
+
+impl Webhook {
+    /// Process webhook configuration.
+    ///
+    /// This returns `None` if no endpoint is configured.
+    #[context("failed to validate webhook configuration")]
+    pub fn with_config(
+        cfg: inputs::WebhookInput,
+        identity: &Identity,
+        network: &NetworkSettings,
+    ) -> Result<Option<Self>> {
+        if cfg.url.trim().is_empty() {
+            return Ok(None);
+        }
+
+        let url = Url::parse(cfg.url.trim()).context("failed to parse webhook URL")?;
+        if !matches!(url.scheme(), "http" | "https") {
+            anyhow::bail!("unsupported webhook scheme '{}'", url.scheme());
+        }
+        let max_len = usize::try_from(cfg.max_queued.get()).unwrap_or(usize::MAX);
+        let max_age_secs = i64::try_from(cfg.max_age_hours.saturating_mul(3600))
+            .context("webhook events max age out of range")?;
+        let hclient = network
+            .configure(reqwest::ClientBuilder::new())?
+            .timeout(Duration::from_secs(REQUEST_TIMEOUT_SECS))
+            .build()?;
+        log::info!("agent events sent to webhook '{}'", url);
+
+        let webhook = Self {
+            url,
+            queue: EventQueue::new(WEBHOOK_QUEUE_PATH, max_len, max_age_secs),
+            node_uuid: identity.node_uuid.lower_hex(),
+            group: identity.group.clone(),
+            hclient,
+        };
+        Ok(Some(webhook))
+    }
+
+    /// Queue an event for delivery.
+    ///
+    /// Errors are logged, and result in the event being lost.
+    pub fn queue(&self, event: Event) {
+        match self.queue.push(event) {
+            Ok((len, dropped)) => record_queue(len, dropped),
+            Err(e) => log::error!("failed to queue webhook event: {:#}", e),
+        }
+    }
+
+    /// Whether some events are waiting for delivery.
+    pub fn has_queued(&self) -> bool {
+        self.queue.has_queued()
+    }
+
+    /// Deliver queued events, oldest first.
+    ///
+    /// Delivery stops at the first failure; undelivered events are kept
+    /// in the queue for a later attempt. Errors are logged and recorded.
+    pub fn flush(&self) -> Pin<Box<dyn Future<Output = ()>>> {
+        let webhook = self.clone();
+        let fut = async move {
+            let mut events = match webhook.queue.load::<Event>() {
+                Ok(events) => events,
+                Err(e) => {
+                    log::error!("{:#}", e);
+                    return;
+                }
+            };
+
+            while !events.is_empty() {
+                let batch_len = events.len().min(MAX_BATCH_LEN);
+                let batch = events[..batch_len].iter().map(|q| &q.event).collect();
+                if let Err(e) = webhook.send(batch).await {
+                    log::warn!("{:#}", e);
+                    break;
+                }
+                events.drain(..batch_len);
+            }
+
+            match webhook.queue.store(&events) {
+                Ok((len, dropped)) => record_queue(len, dropped),
+                Err(e) => log::error!("{:#}", e),
+            }
+        };
+        Box::pin(fut)
+    }
+
+    /// Send a batch of events to the endpoint.
+    async fn send(&self, events: Vec<&Event>) -> Result<()> {
+        let body = Delivery {
+            node_uuid: &self.node_uuid,
+            group: &self.group,
+            events,
+        };
+        let resp = self
+            .hclient
+            .post(self.url.clone())
+            .json(&body)
+            .send()
+            .await
+            .inspect_err(|_| {
+                FAILURES.with_label_values(&["request"]).inc();
+            })
+            .with_context(|| format!("failed to send events to webhook '{}'", self.url))?;
+        resp.error_for_status()
+            .inspect_err(|_| {
+                FAILURES.with_label_values(&["http"]).inc();
+            })
+            .with_context(|| format!("webhook '{}' rejected events", self.url))?;
+        Ok(())
+    }
+}
+
+/// Record queue length and dropped events.
+fn record_queue(len: usize, dropped: Dropped) {
+    QUEUED_EVENTS.set(i64::try_from(len).unwrap_or(i64::MAX));
+    if dropped.expired > 0 {
+        log::warn!("dropped {} expired webhook events", dropped.expired);
+        DROPPED_EVENTS
+            .with_label_values(&["expired"])
+            .inc_by(dropped.expired as u64);
+    }
+    if dropped.overflow > 0 {
+        log::warn!("dropped {} webhook events, queue full", dropped.overflow);
+        DROPPED_EVENTS
+            .with_label_values(&["overflow"])
+            .inc_by(dropped.overflow as u64);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::identity::Identity;
+    use std::num::NonZeroU64;
+    use tokio::runtime as rt;
+
+    fn webhook(url: &str, queue_path: &std::path::Path) -> Webhook {
+        let cfg = inputs::WebhookInput {
+            url: url.to_string(),
+            max_queued: NonZeroU64::new(10).unwrap(),
+            ..Default::default()
+        };
+        let mut webhook = Webhook::with_config(cfg, &Identity::mock_default(), &Default::default())
+            .unwrap()
+            .unwrap();
+        webhook.queue = EventQueue::new(queue_path, 10, 0);
+        webhook
+    }
+
+    #[test]
+    fn webhook_config() {
+        let identity = Identity::mock_default();
+        let network = NetworkSettings::default();
+        let unset = inputs::WebhookInput::default();
+        let webhook = Webhook::with_config(unset, &identity, &network).unwrap();
+        assert!(webhook.is_none());
+
+        let invalid = inputs::WebhookInput {
+            url: "ftp://example.com/events".to_string(),
+            ..Default::default()
+        };
+        Webhook::with_config(invalid, &identity, &network).unwrap_err();
+    }
+
+    #[test]
+    fn webhook_flush() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let queue_path = tmpdir.path().join("events.json");
+        let failing = mockito::mock("POST", "/events")
+            .with_status(503)
+            .expect(1)
+            .create();
+        let webhook = webhook(&format!("{}/events", mockito::server_url()), &queue_path);
+        webhook.queue(Event::new("state-change", "UpdateStaged", Some("1.0")));
+        webhook.queue(Event::new("state-change", "UpdateFinalized", Some("1.0")));
+        assert!(webhook.has_queued());
+
+        // Undelivered events are kept.
+        let runtime = rt::Runtime::new().unwrap();
+        runtime.block_on(webhook.flush());
+        failing.assert();
+        drop(failing);
+        assert_eq!(webhook.queue.load::<Event>().unwrap().len(), 2);
+
+        // Delivered events are removed, in a single batch.
+        let ok = mockito::mock("POST", "/events")
+            .match_body(mockito::Matcher::PartialJsonString(format!(
+                r#"{{"node_uuid":"{}","group":"{}"}}"#,
+                webhook.node_uuid, webhook.group
+            )))
+            .with_status(200)
+            .expect(1)
+            .create();
+        runtime.block_on(webhook.flush());
+        ok.assert();
+        assert!(!webhook.has_queued());
+    }
+}
@@ -6,7 +6,7 @@
 
 // TODO(lucab): stabilize and split this to its own `weekly` crate.
 
-pub(crate) mod utils;
+pub mod utils;
 
 use anyhow::{ensure, Result};
 use chrono::{DateTime, TimeZone, Utc};
@@ -20,13 +20,13 @@ use std::ops::Range;
 use std::time::Duration;
 
 /// Whole week duration, in minutes.
-pub(crate) const MAX_WEEKLY_MINS: u32 = 7 * 24 * 60;
+pub const MAX_WEEKLY_MINS: u32 = 7 * 24 * 60;
 
 /// Whole week duration, in seconds.
-pub(crate) const MAX_WEEKLY_SECS: u64 = (MAX_WEEKLY_MINS as u64) * 60;
+pub const MAX_WEEKLY_SECS: u64 = (MAX_WEEKLY_MINS as u64) * 60;
 
 /// A weekly point in time, as minutes since beginning of week (Monday 00:00).
-pub(crate) type MinuteInWeek = u32;
+pub type MinuteInWeek = u32;
 
 /// Calendar for periodic time-windows, recurring on weekly basis.
 #[derive(Clone, Debug)]
@@ -66,8 +66,8 @@ impl WeeklyCalendar {
         }
 
         // Already in a window, return now.
-        if self.contains_datetime(&datetime) {
-            return Some(utils::datetime_as_weekly_minute(&datetime));
+        if self.contains_datetime(datetime) {
+            return Some(utils::datetime_as_weekly_minute(datetime));
         }
 
         let timepoint = utils::datetime_as_weekly_minute(datetime);
@@ -101,7 +101,7 @@ impl WeeklyCalendar {
         }
 
         // Already in a window, zero minutes.
-        if self.contains_datetime(&datetime) {
+        if self.contains_datetime(datetime) {
             return Some(chrono::Duration::zero());
         }
 
@@ -130,6 +130,36 @@ impl WeeklyCalendar {
         Some(chrono::Duration::minutes(remaining_mins))
     }
 
+    /// Return the minutes elapsed since the start of the window containing
+    /// the given datetime.
+    ///
+    /// In case of overlapping windows, the earliest start is considered.
+    /// A window continuing from the end of the previous week (e.g. after
+    /// being chopped at week boundary) is considered as started on the
+    /// previous week.
+    /// This returns `None` if the datetime is not in any window.
+    pub fn minutes_since_window_start(&self, datetime: &DateTime<impl TimeZone>) -> Option<u32> {
+        let timepoint = utils::datetime_as_weekly_minute(datetime);
+        let start = self.earliest_start_containing(timepoint)?;
+        let mut elapsed = timepoint.saturating_sub(start);
+
+        if start == 0 {
+            if let Some(prev_start) = self.earliest_start_containing(MAX_WEEKLY_MINS - 1) {
+                let prev_elapsed = MAX_WEEKLY_MINS.saturating_sub(prev_start);
+                elapsed = elapsed.saturating_add(prev_elapsed);
+            }
+        }
+        Some(elapsed)
+    }
+
+    /// Return the earliest start of all windows containing a weekly minute.
+    fn earliest_start_containing(&self, timepoint: MinuteInWeek) -> Option<MinuteInWeek> {
+        self.windows
+            .query_point(timepoint)
+            .map(|elem| elem.range.start)
+            .min()
+    }
+
     /// Format remaining duration till the next window in human terms.
     pub fn human_remaining_duration(remaining: &chrono::Duration) -> Result<String> {
         if remaining.is_zero() {
@@ -452,6 +482,36 @@ mod tests {
         assert_eq!(chopped[1].length_minutes(), 1);
     }
 
+    #[test]
+    fn calendar_minutes_since_window_start() {
+        let length = utils::check_minutes(60).unwrap();
+        let windows = WeeklyWindow::parse_timespan(chrono::Weekday::Mon, 10, 0, length).unwrap();
+        let calendar = WeeklyCalendar::new(windows);
+
+        let before = DateTime::parse_from_rfc3339("2019-06-24T09:59:00+00:00").unwrap();
+        assert_eq!(calendar.minutes_since_window_start(&before), None);
+        let start = DateTime::parse_from_rfc3339("2019-06-24T10:00:00+00:00").unwrap();
+        assert_eq!(calendar.minutes_since_window_start(&start), Some(0));
+        let inside = DateTime::parse_from_rfc3339("2019-06-24T10:05:30+00:00").unwrap();
+        assert_eq!(calendar.minutes_since_window_start(&inside), Some(5));
+
+        let length = utils::check_minutes(30).unwrap();
+        let windows = WeeklyWindow::parse_timespan(chrono::Weekday::Sun, 23, 50, length).unwrap();
+        assert_eq!(windows.len(), 2);
+        let calendar = WeeklyCalendar::new(windows);
+
+        let before_boundary = DateTime::parse_from_rfc3339("2019-06-30T23:55:00+00:00").unwrap();
+        assert_eq!(
+            calendar.minutes_since_window_start(&before_boundary),
+            Some(5)
+        );
+        let after_boundary = DateTime::parse_from_rfc3339("2019-07-01T00:10:00+00:00").unwrap();
+        assert_eq!(
+            calendar.minutes_since_window_start(&after_boundary),
+            Some(20)
+        );
+    }
+
     #[test]
     fn calendar_contains_datetime() {
         let length = utils::check_minutes(75).unwrap();
@@ -462,10 +522,10 @@ mod tests {
         let calendar = WeeklyCalendar::new(windows);
         assert_eq!(calendar.windows.iter().count(), 1);
 
-        let datetime = Utc.ymd(2019, 6, 25).and_hms(21, 10, 0);
+        let datetime = Utc.with_ymd_and_hms(2019, 6, 25, 21, 10, 0).unwrap();
         assert!(calendar.contains_datetime(&datetime));
         // Sanity check that `WeeklyCalendar` is `TimeZone`-agnostic.
-        let datetime = Local.ymd(2019, 6, 25).and_hms(21, 10, 0);
+        let datetime = Local.with_ymd_and_hms(2019, 6, 25, 21, 10, 0).unwrap();
         assert!(calendar.contains_datetime(&datetime));
     }
 
@@ -578,7 +638,7 @@ mod tests {
 
     #[test]
     fn test_next_window_minute_in_week() {
-        use chrono::{NaiveDate, TimeZone};
+        use chrono::TimeZone;
         use tzfile::Tz;
 
         let l1 = utils::check_minutes(45).unwrap();
@@ -592,11 +652,11 @@ mod tests {
         let calendar = WeeklyCalendar::new(w1.clone());
 
         let tz = Tz::named("UTC").unwrap();
-        let dt0 = (&tz).from_utc_datetime(&NaiveDate::from_ymd(2021, 4, 12).and_hms(0, 0, 0));
-        let dt1 = (&tz).from_utc_datetime(&NaiveDate::from_ymd(2021, 4, 12).and_hms(1, 5, 0));
-        let dt2 = (&tz).from_utc_datetime(&NaiveDate::from_ymd(2021, 4, 12).and_hms(2, 16, 0));
-        let dt3 = (&tz).from_utc_datetime(&NaiveDate::from_ymd(2021, 4, 16).and_hms(15, 14, 56));
-        let dt4 = (&tz).from_utc_datetime(&NaiveDate::from_ymd(2021, 4, 18).and_hms(23, 35, 00));
+        let dt0 = (&tz).with_ymd_and_hms(2021, 4, 12, 0, 0, 0).unwrap();
+        let dt1 = (&tz).with_ymd_and_hms(2021, 4, 12, 1, 5, 0).unwrap();
+        let dt2 = (&tz).with_ymd_and_hms(2021, 4, 12, 2, 16, 0).unwrap();
+        let dt3 = (&tz).with_ymd_and_hms(2021, 4, 16, 15, 14, 56).unwrap();
+        let dt4 = (&tz).with_ymd_and_hms(2021, 4, 18, 23, 35, 00).unwrap();
 
         let cases = vec![
             (
@@ -8,7 +8,7 @@ use std::convert::TryInto;
 use std::time::Duration;
 
 /// Convert `MinuteInWeek` to a week day and time.
-pub(crate) fn weekly_minute_as_weekday_time(weekly_minute: MinuteInWeek) -> (Weekday, u8, u8) {
+pub fn weekly_minute_as_weekday_time(weekly_minute: MinuteInWeek) -> (Weekday, u8, u8) {
     assert!(weekly_minute < MAX_WEEKLY_MINS);
     let days_from_monday = weekly_minute / (60_u32).saturating_mul(24);
     let weekday = match days_from_monday {
@@ -29,7 +29,7 @@ pub(crate) fn weekly_minute_as_weekday_time(weekly_minute: MinuteInWeek) -> (Wee
 }
 
 /// Convert datetime to minutes since beginning of week.
-pub(crate) fn datetime_as_weekly_minute(datetime: &DateTime<impl TimeZone>) -> MinuteInWeek {
+pub fn datetime_as_weekly_minute(datetime: &DateTime<impl TimeZone>) -> MinuteInWeek {
     use chrono::{Datelike, Timelike};
 
     let weekday = datetime.weekday();
@@ -42,7 +42,7 @@ pub(crate) fn datetime_as_weekly_minute(datetime: &DateTime<impl TimeZone>) -> M
 }
 
 /// Convert a point in weekly-time to minutes since beginning of week.
-pub(crate) fn time_as_weekly_minute(day: chrono::Weekday, hour: u8, minute: u8) -> MinuteInWeek {
+pub fn time_as_weekly_minute(day: chrono::Weekday, hour: u8, minute: u8) -> MinuteInWeek {
     let hour_minutes = u32::from(hour.min(23)).saturating_mul(60);
     let day_minutes = day
         .num_days_from_monday()
@@ -57,7 +57,7 @@ pub(crate) fn time_as_weekly_minute(day: chrono::Weekday, hour: u8, minute: u8)
 }
 
 /// Check duration for a sane lower and upper bound (whole week).
-pub(crate) fn check_duration(length: &Duration) -> Result<()> {
+pub fn check_duration(length: &Duration) -> Result<()> {
     if length.as_secs() > MAX_WEEKLY_SECS {
         bail!("length longer than a week")
     };
@@ -69,7 +69,7 @@ pub(crate) fn check_duration(length: &Duration) -> Result<()> {
 }
 
 /// Parse a week day string (English names).
-pub(crate) fn weekday_from_string(input: &str) -> Result<Weekday> {
+pub fn weekday_from_string(input: &str) -> Result<Weekday> {
     let day = match input.to_lowercase().as_str() {
         "mon" | "monday" => Weekday::Mon,
         "tue" | "tuesady" => Weekday::Tue,
@@ -89,16 +89,17 @@ pub(crate) fn weekday_from_string(input: &str) -> Result<Weekday> {
 /// ## Example
 ///
 /// ```rust
+/// # use zincati_core::weekly::utils::time_from_string;
 /// let morning = time_from_string("6:20").unwrap();
 /// assert_eq!(morning.0, 6);
-/// assert_eq!(morning.0, 20);
+/// assert_eq!(morning.1, 20);
 ///
 /// let afternoon = time_from_string("14:05").unwrap();
-/// assert_eq!(morning.0, 14);
-/// assert_eq!(morning.0, 5);
+/// assert_eq!(afternoon.0, 14);
+/// assert_eq!(afternoon.1, 5);
 /// ```
 #[context("failed to parse time string")]
-pub(crate) fn time_from_string(input: &str) -> Result<(u8, u8)> {
+pub fn time_from_string(input: &str) -> Result<(u8, u8)> {
     let fields: Vec<_> = input.split(':').collect();
     if fields.len() != 2 {
         bail!("unrecognized time value: {}", input);
@@ -118,7 +119,7 @@ pub(crate) fn time_from_string(input: &str) -> Result<(u8, u8)> {
 
 /// Validate a timespan (in minutes) and return its duration.
 #[cfg(test)]
-pub(crate) fn check_minutes(minutes: u32) -> Result<Duration> {
+pub fn check_minutes(minutes: u32) -> Result<Duration> {
     let secs = u64::from(minutes).saturating_mul(60);
     let length = Duration::from_secs(secs);
     check_duration(&length)?;
@@ -132,8 +133,8 @@ mod tests {
 
     #[test]
     fn test_check_duration() {
-        check_duration(&Duration::from_secs(std::u64::MIN)).unwrap_err();
-        check_duration(&Duration::from_secs(std::u64::MAX)).unwrap_err();
+        check_duration(&Duration::from_secs(u64::MIN)).unwrap_err();
+        check_duration(&Duration::from_secs(u64::MAX)).unwrap_err();
 
         let length = Duration::from_secs(42 * 60);
         check_duration(&length).unwrap();
@@ -148,8 +149,8 @@ mod tests {
 
     #[test]
     fn test_check_minutes() {
-        check_minutes(std::u32::MIN).unwrap_err();
-        check_minutes(std::u32::MAX).unwrap_err();
+        check_minutes(u32::MIN).unwrap_err();
+        check_minutes(u32::MAX).unwrap_err();
 
         let length = check_minutes(42).unwrap();
         assert_eq!(length.as_secs(), 42 * 60);
@@ -30,8 +30,57 @@ last_refresh_time=$(echo "${response}" | sed 's/[^0-9]*//g')
 test "${last_refresh_time}" -gt 1616414400 # 1616414400 is Monday, March 22, 2021 12:00:00 PM UTC.
 ok "LastRefreshTime method"
 
+# Check ApprovePendingReboot method, with reboot approval not required by configuration.
+if busctl call org.coreos.zincati /org/coreos/zincati org.coreos.zincati.Experimental ApprovePendingReboot 2> err.txt; then
+  fatal "ApprovePendingReboot unexpectedly succeeded"
+fi
+assert_file_has_content err.txt "reboot approval is not required"
+ok "ApprovePendingReboot method"
+
+# Check SetTemporaryBlackout and ClearTemporaryBlackout methods.
+if busctl call org.coreos.zincati /org/coreos/zincati org.coreos.zincati.Experimental SetTemporaryBlackout xxs 1616414400 1616418000 "past" 2> err.txt; then
+  fatal "SetTemporaryBlackout in the past unexpectedly succeeded"
+fi
+assert_file_has_content err.txt "is in the past"
+now=$(date +%s)
+busctl call org.coreos.zincati /org/coreos/zincati org.coreos.zincati.Experimental SetTemporaryBlackout xxs "${now}" $((now + 43200)) "kola test"
+test -f /var/lib/zincati/temporary-blackout.json
+busctl call org.coreos.zincati /org/coreos/zincati org.coreos.zincati.Experimental ClearTemporaryBlackout > output.txt
+assert_file_has_content output.txt "true"
+test ! -e /var/lib/zincati/temporary-blackout.json
+ok "SetTemporaryBlackout and ClearTemporaryBlackout methods"
+
+# Check GetPlan method.
+busctl call org.coreos.zincati /org/coreos/zincati org.coreos.zincati.Experimental GetPlan > output.txt
+assert_file_has_content output.txt "^a(sxs) "
+/usr/libexec/zincati ex get-plan
+ok "GetPlan method"
+
+# Check GetConfig method.
+busctl --json=short call org.coreos.zincati /org/coreos/zincati org.coreos.zincati.Experimental GetConfig | jq -r '.data[0]' > config.json
+jq -e '.updates.strategy' config.json
+ok "GetConfig method"
+
+# Check Reload method.
+busctl call org.coreos.zincati /org/coreos/zincati org.coreos.zincati.Experimental Reload
+systemctl reload zincati.service
+systemctl is-active zincati.service
+ok "Reload method"
+
 # Check that CLI commands work.
 /usr/libexec/zincati ex moo --talkative > output.txt
 assert_file_has_content output.txt "Moooo mooo moooo!"
 test $(/usr/libexec/zincati ex last-refresh-time) -gt 1616414400
 ok "last-refresh-time CLI command"
+
+/usr/libexec/zincati status > output.txt
+assert_file_has_content output.txt "^State:"
+/usr/libexec/zincati status --json > output.json
+jq -e '.last_refresh_time > 1616414400' output.json
+ok "status CLI command"
+
+rc=0
+/usr/libexec/zincati check-update > output.txt || rc=$?
+test "${rc}" -eq 0 -o "${rc}" -eq 100
+assert_file_has_content output.txt "update available"
+ok "check-update CLI command"
@@ -0,0 +1,37 @@
+#!/bin/bash
+
+# Tests for the `org.coreos.zincati.Manager` interface.
+
+set -xeuo pipefail
+
+. ${KOLA_EXT_DATA}/libtest.sh
+
+cd $(mktemp -d)
+
+# Ensure that methods in this interface can only be called by root.
+if sudo -u core busctl call org.coreos.zincati /org/coreos/zincati org.coreos.zincati.Manager SetLogLevel sb trace false 2> err.txt; then
+  fatal "Non-root user calling Manager interface unexpectedly succeeded"
+fi
+assert_file_has_content err.txt "Access denied"
+ok "only allow root to call Manager interface"
+
+# Check SetLogLevel method and LogLevel property.
+busctl call org.coreos.zincati /org/coreos/zincati org.coreos.zincati.Manager SetLogLevel sb debug false
+busctl get-property org.coreos.zincati /org/coreos/zincati org.coreos.zincati.Manager LogLevel > output.txt
+assert_file_has_content output.txt "debug"
+if busctl call org.coreos.zincati /org/coreos/zincati org.coreos.zincati.Manager SetLogLevel sb verbose false 2> err.txt; then
+  fatal "setting an invalid log level unexpectedly succeeded"
+fi
+assert_file_has_content err.txt "invalid log level"
+ok "SetLogLevel method"
+
+# Check that persisted levels survive agent restarts.
+busctl call org.coreos.zincati /org/coreos/zincati org.coreos.zincati.Manager SetLogLevel sb trace true
+assert_file_has_content /var/lib/zincati/log-level "trace"
+systemctl restart zincati.service
+sleep 5
+busctl get-property org.coreos.zincati /org/coreos/zincati org.coreos.zincati.Manager LogLevel > output.txt
+assert_file_has_content output.txt "trace"
+rm /var/lib/zincati/log-level
+systemctl restart zincati.service
+ok "persisted log level"